@@ -0,0 +1,142 @@
+//! `--expand-arg-env`: `$VAR` and `${VAR}` expansion over the trailing child args, for
+//! callers that build the command line without a shell (YAML lists, job templates).
+
+use clap::ValueEnum;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ExpandMode {
+    /// An unset variable is an error
+    Strict,
+    /// An unset variable expands to the empty string
+    Lossy,
+}
+
+fn is_name_start(c: char) -> bool {
+    c == '_' || c.is_ascii_alphabetic()
+}
+
+fn is_name_char(c: char) -> bool {
+    c == '_' || c.is_ascii_alphanumeric()
+}
+
+/// Expand one argument. `$$` is a literal `$`, and a `$` not followed by a name or `{` is
+/// kept as is.
+pub fn expand(
+    arg: &str,
+    mode: ExpandMode,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Result<String, String> {
+    let mut out = String::with_capacity(arg.len());
+    let mut rest = arg;
+    while let Some(pos) = rest.find('$') {
+        out.push_str(&rest[..pos]);
+        let after = &rest[pos + 1..];
+        let (name, consumed) = if let Some(tail) = after.strip_prefix('$') {
+            out.push('$');
+            rest = tail;
+            continue;
+        } else if let Some(braced) = after.strip_prefix('{') {
+            let end = braced
+                .find('}')
+                .ok_or_else(|| format!("unterminated `${{` in `{arg}`"))?;
+            let name = &braced[..end];
+            if !name.starts_with(is_name_start) || !name.chars().all(is_name_char) {
+                return Err(format!("invalid variable name `${{{name}}}` in `{arg}`"));
+            }
+            (name, end + 2)
+        } else if after.starts_with(is_name_start) {
+            let end = after.find(|c| !is_name_char(c)).unwrap_or(after.len());
+            (&after[..end], end)
+        } else {
+            out.push('$');
+            rest = after;
+            continue;
+        };
+        match lookup(name) {
+            Some(value) => out.push_str(&value),
+            None if mode == ExpandMode::Lossy => {}
+            None => return Err(format!("`${name}` in `{arg}` is not set")),
+        }
+        rest = &after[consumed..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+pub fn expand_all(
+    args: &[String],
+    mode: ExpandMode,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Result<Vec<String>, String> {
+    args.iter().map(|a| expand(a, mode, lookup)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "DIR" => Some("/etc/mcp".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        }
+    }
+
+    fn strict(arg: &str) -> Result<String, String> {
+        expand(arg, ExpandMode::Strict, &lookup)
+    }
+
+    #[test]
+    fn names_and_braces() {
+        assert_eq!(strict("$DIR/mcp.json").as_deref(), Ok("/etc/mcp/mcp.json"));
+        assert_eq!(strict("${DIR}x").as_deref(), Ok("/etc/mcpx"));
+        assert_eq!(strict("$DIR-a").as_deref(), Ok("/etc/mcp-a"));
+        assert_eq!(strict("a${EMPTY}b$EMPTY").as_deref(), Ok("ab"));
+        assert_eq!(strict("no dollars").as_deref(), Ok("no dollars"));
+    }
+
+    #[test]
+    fn dollars_that_are_not_references() {
+        assert_eq!(strict("$$DIR").as_deref(), Ok("$DIR"));
+        assert_eq!(strict("$$$DIR").as_deref(), Ok("$/etc/mcp"));
+        assert_eq!(strict("cost: $5, or $").as_deref(), Ok("cost: $5, or $"));
+        assert_eq!(strict("a $ b").as_deref(), Ok("a $ b"));
+    }
+
+    #[test]
+    fn unset_variables() {
+        assert_eq!(
+            strict("--config=$NOPE"),
+            Err("`$NOPE` in `--config=$NOPE` is not set".to_string())
+        );
+        let lossy = expand("--config=${NOPE}.json", ExpandMode::Lossy, &lookup);
+        assert_eq!(lossy.as_deref(), Ok("--config=.json"));
+    }
+
+    #[test]
+    fn malformed_braces() {
+        assert_eq!(
+            strict("${DIR"),
+            Err("unterminated `${` in `${DIR`".to_string())
+        );
+        assert_eq!(
+            strict("${1X}"),
+            Err("invalid variable name `${1X}` in `${1X}`".to_string())
+        );
+        assert!(strict("${}").is_err());
+        // Malformed even when lossy: a typo shouldn't vanish silently
+        assert!(expand("${A-B}", ExpandMode::Lossy, &lookup).is_err());
+    }
+
+    #[test]
+    fn every_arg_or_none() {
+        let args = ["-p".to_string(), "$DIR".to_string()];
+        assert_eq!(
+            expand_all(&args, ExpandMode::Strict, &lookup),
+            Ok(vec!["-p".to_string(), "/etc/mcp".to_string()])
+        );
+        let args = ["$DIR".to_string(), "$NOPE".to_string()];
+        assert!(expand_all(&args, ExpandMode::Strict, &lookup).is_err());
+    }
+}
@@ -0,0 +1,175 @@
+//! `--attempt-artifacts DIR`: one set of files per attempt, easier to diff than a combined
+//! log. `attempt-NN.stdout` and `.stderr` are written as the output is forwarded, so a killed
+//! run still leaves them behind; `attempt-NN.meta.json` is written at the start of the attempt
+//! and completed once its outcome is known.
+//!
+//! With `--compress-artifacts` the output files are gzip streams (`.stdout.gz`) sync-flushed
+//! after every chunk of output, so a partially written file still decompresses up to there.
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use clap::ValueEnum;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde_json::{Map, Value};
+
+use crate::schema;
+
+/// Which attempts' files survive the run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Keep {
+    /// Only the most recent attempt
+    Last,
+    /// Only attempts that failed
+    Failed,
+    /// Every attempt
+    All,
+}
+
+/// One output file of an attempt, written as the output is forwarded.
+pub enum Stream {
+    Plain(File),
+    Gzip(GzEncoder<File>),
+}
+
+impl Stream {
+    pub fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        match self {
+            Stream::Plain(file) => file.write_all(data),
+            Stream::Gzip(encoder) => {
+                encoder.write_all(data)?;
+                encoder.flush()
+            }
+        }
+    }
+
+    /// Finish the stream; for gzip this writes the trailer that makes the file complete.
+    pub fn close(self) -> io::Result<()> {
+        match self {
+            Stream::Plain(_) => Ok(()),
+            Stream::Gzip(encoder) => encoder.finish().map(drop),
+        }
+    }
+}
+
+pub struct Artifacts {
+    dir: PathBuf,
+    keep: Keep,
+    compress: bool,
+    /// Attempt number and start metadata of the attempt in progress.
+    current: Option<(u32, Map<String, Value>)>,
+}
+
+pub fn unix_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+impl Artifacts {
+    /// Create `dir` if it doesn't exist yet.
+    /// Create `dir` if needed and make sure files can be written there, so an unwritable
+    /// directory fails the run before the first attempt rather than during a later one.
+    pub fn open(dir: &Path, keep: Keep, compress: bool) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let probe = dir.join(format!(".rusty-claude-write-test-{}", std::process::id()));
+        File::create(&probe)?;
+        let _ = fs::remove_file(&probe);
+        Ok(Artifacts {
+            dir: dir.to_path_buf(),
+            keep,
+            compress,
+            current: None,
+        })
+    }
+
+    fn path(&self, attempt: u32, ext: &str) -> PathBuf {
+        self.dir.join(format!("attempt-{attempt:02}.{ext}"))
+    }
+
+    fn output_ext(&self, stream: &str) -> String {
+        if self.compress {
+            format!("{stream}.gz")
+        } else {
+            stream.to_string()
+        }
+    }
+
+    fn remove(&self, attempt: u32) {
+        for ext in [
+            self.output_ext("stdout"),
+            self.output_ext("stderr"),
+            "meta.json".into(),
+        ] {
+            let _ = fs::remove_file(self.path(attempt, &ext));
+        }
+    }
+
+    fn create(&self, attempt: u32, stream: &str) -> io::Result<Stream> {
+        let file = File::create(self.path(attempt, &self.output_ext(stream)))?;
+        Ok(if self.compress {
+            Stream::Gzip(GzEncoder::new(file, Compression::default()))
+        } else {
+            Stream::Plain(file)
+        })
+    }
+
+    /// Write `meta` via a temporary file so readers never see half of it.
+    fn write_meta(&self, attempt: u32, meta: &Map<String, Value>) -> io::Result<()> {
+        let path = self.path(attempt, "meta.json");
+        let tmp = path.with_extension("json.tmp");
+        let mut text = serde_json::to_string_pretty(meta).map_err(io::Error::other)?;
+        text.push('\n');
+        fs::write(&tmp, text)?;
+        fs::rename(&tmp, &path)
+    }
+
+    /// Start `attempt` (1-based): returns the stdout and stderr files to stream into.
+    pub fn begin(
+        &mut self,
+        attempt: u32,
+        start: Map<String, Value>,
+    ) -> io::Result<(Stream, Stream)> {
+        if self.keep == Keep::Last && attempt > 1 {
+            self.remove(attempt - 1);
+        }
+        let files = (
+            self.create(attempt, "stdout")?,
+            self.create(attempt, "stderr")?,
+        );
+        let mut meta = Map::new();
+        meta.insert("schema".into(), schema::ATTEMPT_META.into());
+        meta.extend(start);
+        self.write_meta(attempt, &meta)?;
+        self.current = Some((attempt, meta));
+        Ok(files)
+    }
+
+    /// Complete the metadata of the attempt in progress and apply the retention policy.
+    /// Compressed output files get their on-disk sizes recorded next to the raw byte counts.
+    pub fn finish(&mut self, success: bool, outcome: Value) -> io::Result<()> {
+        let Some((attempt, mut meta)) = self.current.take() else {
+            return Ok(());
+        };
+        if success && self.keep == Keep::Failed {
+            self.remove(attempt);
+            return Ok(());
+        }
+        meta.insert("finished_at_ms".into(), unix_ms().into());
+        if let Value::Object(fields) = outcome {
+            meta.extend(fields);
+        }
+        if self.compress {
+            for stream in ["stdout", "stderr"] {
+                let path = self.path(attempt, &self.output_ext(stream));
+                if let Ok(m) = fs::metadata(path) {
+                    meta.insert(format!("{stream}_compressed_bytes"), m.len().into());
+                }
+            }
+        }
+        self.write_meta(attempt, &meta)
+    }
+}
@@ -0,0 +1,313 @@
+//! Delays between attempts (`--backoff-strategy`). Every strategy is capped at
+//! `--max-delay-ms`; growth is `base * multiplier^attempt` for attempt 0, 1, ...
+//!
+//! | strategy | delay for attempt n |
+//! |----------|---------------------|
+//! | `exponential` | between half of the growth and all of it, so the floor rises too |
+//! | `full-jitter` | anywhere from 0 to the growth |
+//! | `decorrelated-jitter` | from `base` to three times the previous delay |
+//! | `linear` | `base * (n + 1)` |
+//! | `constant` | `base` |
+
+use clap::ValueEnum;
+use rand::Rng;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Strategy {
+    /// Growing delays with the upper half jittered
+    Exponential,
+    /// Growing delays jittered all the way down to 0
+    FullJitter,
+    /// Random between the base and 3x the previous delay
+    DecorrelatedJitter,
+    /// base, 2*base, 3*base, ...
+    Linear,
+    /// Always the base delay
+    Constant,
+}
+
+/// `--backoff-multiplier`: a finite factor of at least 1.
+pub fn parse_multiplier(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(m) if m.is_finite() && m >= 1.0 => Ok(m),
+        _ => Err(format!(
+            "invalid multiplier `{s}` (expected a number >= 1, e.g. 1.5)"
+        )),
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Backoff {
+    pub strategy: Strategy,
+    pub base_ms: u64,
+    pub cap_ms: u64,
+    pub multiplier: f64,
+}
+
+impl Backoff {
+    /// `base * multiplier^attempt`, capped.
+    fn growth(&self, attempt: u32) -> u64 {
+        let grown = self.base_ms as f64 * self.multiplier.powi(attempt.min(i32::MAX as u32) as i32);
+        (grown.min(self.cap_ms as f64) as u64).max(self.base_ms.min(self.cap_ms))
+    }
+
+    /// The smallest and largest delay `delay_ms` can return for `attempt` (0-based).
+    pub fn bounds(&self, attempt: u32, previous: Option<u64>) -> (u64, u64) {
+        let cap = self.cap_ms;
+        match self.strategy {
+            Strategy::Exponential => {
+                let g = self.growth(attempt);
+                (g / 2, g)
+            }
+            Strategy::FullJitter => (0, self.growth(attempt)),
+            Strategy::DecorrelatedJitter => {
+                let prev = previous.unwrap_or(self.base_ms);
+                let low = self.base_ms.min(cap);
+                (low, prev.saturating_mul(3).clamp(low, cap))
+            }
+            Strategy::Linear => {
+                let d = self.base_ms.saturating_mul(u64::from(attempt) + 1).min(cap);
+                (d, d)
+            }
+            Strategy::Constant => {
+                let d = self.base_ms.min(cap);
+                (d, d)
+            }
+        }
+    }
+
+    /// What `delay_ms` draws from, for `-v`: e.g. `exponential backoff for attempt 2 (base
+    /// 1000ms, x2, cap 60000ms): 1000-2000ms`.
+    pub fn describe(&self, attempt: u32, previous: Option<u64>) -> String {
+        let (low, high) = self.bounds(attempt, previous);
+        let strategy = self
+            .strategy
+            .to_possible_value()
+            .map_or_else(String::new, |v| v.get_name().to_string());
+        let mut inputs = format!("base {}ms", self.base_ms);
+        if matches!(self.strategy, Strategy::Exponential | Strategy::FullJitter) {
+            inputs += &format!(", x{}", self.multiplier);
+        }
+        if let (Strategy::DecorrelatedJitter, Some(previous)) = (self.strategy, previous) {
+            inputs += &format!(", previous {previous}ms");
+        }
+        let range = if low == high {
+            format!("{low}ms")
+        } else {
+            format!("{low}-{high}ms")
+        };
+        format!(
+            "{strategy} backoff for attempt {} ({inputs}, cap {}ms): {range}",
+            attempt + 1,
+            self.cap_ms
+        )
+    }
+
+    /// The delay before retrying after `attempt` failed; `previous` is the delay that came
+    /// before it, if any.
+    pub fn delay_ms(&self, attempt: u32, previous: Option<u64>, rng: &mut impl Rng) -> u64 {
+        let (low, high) = self.bounds(attempt, previous);
+        rng.random_range(low..=high)
+    }
+
+    /// `--print-backoff`: a line for the delay before each of `retries` retries, then their
+    /// total. With `rng` these are the exact delays it draws; without, each line gives the
+    /// least, expected, and most delay (decorrelated jitter following the least, expected,
+    /// or most previous delay in turn, so its expected values are approximate).
+    pub fn schedule(&self, retries: u32, rng: Option<impl Rng>) -> String {
+        let mut out = String::new();
+        if let Some(mut rng) = rng {
+            let (mut previous, mut total) = (None, 0u64);
+            for n in 0..retries {
+                let ms = self.delay_ms(n, previous, &mut rng);
+                out += &format!("retry {} (after attempt {}): {ms}ms\n", n + 1, n + 1);
+                previous = Some(ms);
+                total = total.saturating_add(ms);
+            }
+            out += &format!("total: {total}ms over {retries} retries\n");
+            return out;
+        }
+        let (mut least, mut expected, mut most) = (None, None, None);
+        let mut totals = (0u64, 0u64, 0u64);
+        for n in 0..retries {
+            let min = self.bounds(n, least).0;
+            let (low, high) = self.bounds(n, expected);
+            let mid = low + (high - low) / 2;
+            let max = self.bounds(n, most).1;
+            out += &format!(
+                "retry {} (after attempt {}): min {min}ms, expected {mid}ms, max {max}ms\n",
+                n + 1,
+                n + 1
+            );
+            (least, expected, most) = (Some(min), Some(mid), Some(max));
+            totals = (
+                totals.0.saturating_add(min),
+                totals.1.saturating_add(mid),
+                totals.2.saturating_add(max),
+            );
+        }
+        out += &format!(
+            "total: min {}ms, expected {}ms, max {}ms over {retries} retries\n",
+            totals.0, totals.1, totals.2
+        );
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    fn backoff(strategy: Strategy) -> Backoff {
+        Backoff {
+            strategy,
+            base_ms: 100,
+            cap_ms: 1_000,
+            multiplier: 2.0,
+        }
+    }
+
+    #[test]
+    fn bounds_per_strategy() {
+        let exponential = backoff(Strategy::Exponential);
+        assert_eq!(exponential.bounds(0, None), (50, 100));
+        assert_eq!(exponential.bounds(2, None), (200, 400));
+        // Capped, however many attempts came before
+        assert_eq!(exponential.bounds(40, None), (500, 1_000));
+        assert_eq!(exponential.bounds(u32::MAX, None), (500, 1_000));
+        assert_eq!(backoff(Strategy::FullJitter).bounds(3, None), (0, 800));
+        let decorrelated = backoff(Strategy::DecorrelatedJitter);
+        assert_eq!(decorrelated.bounds(0, None), (100, 300));
+        assert_eq!(decorrelated.bounds(5, Some(250)), (100, 750));
+        assert_eq!(decorrelated.bounds(5, Some(900)), (100, 1_000));
+        assert_eq!(backoff(Strategy::Linear).bounds(2, None), (300, 300));
+        assert_eq!(backoff(Strategy::Linear).bounds(20, None), (1_000, 1_000));
+        assert_eq!(backoff(Strategy::Constant).bounds(7, None), (100, 100));
+    }
+
+    #[test]
+    fn bounds_with_the_binary_defaults() {
+        // strategy, multiplier, attempt, previous delay, expected bounds (base 500ms, cap 20s)
+        type Row = (Strategy, f64, u32, Option<u64>, (u64, u64));
+        let table: &[Row] = &[
+            (Strategy::Exponential, 2.0, 0, None, (250, 500)),
+            (Strategy::Exponential, 2.0, 1, None, (500, 1000)),
+            (Strategy::Exponential, 2.0, 3, None, (2000, 4000)),
+            (Strategy::Exponential, 2.0, 10, None, (10_000, 20_000)),
+            (Strategy::Exponential, 1.5, 2, None, (562, 1125)),
+            (Strategy::FullJitter, 2.0, 0, None, (0, 500)),
+            (Strategy::FullJitter, 2.0, 2, None, (0, 2000)),
+            (Strategy::DecorrelatedJitter, 2.0, 0, None, (500, 1500)),
+            (
+                Strategy::DecorrelatedJitter,
+                2.0,
+                4,
+                Some(4000),
+                (500, 12_000),
+            ),
+            (
+                Strategy::DecorrelatedJitter,
+                2.0,
+                5,
+                Some(10_000),
+                (500, 20_000),
+            ),
+            (Strategy::Linear, 2.0, 0, None, (500, 500)),
+            (Strategy::Linear, 2.0, 2, None, (1500, 1500)),
+            (Strategy::Linear, 2.0, 100, None, (20_000, 20_000)),
+            (Strategy::Constant, 2.0, 5, None, (500, 500)),
+        ];
+        for &(strategy, multiplier, attempt, previous, want) in table {
+            let b = Backoff {
+                strategy,
+                base_ms: 500,
+                cap_ms: 20_000,
+                multiplier,
+            };
+            assert_eq!(
+                b.bounds(attempt, previous),
+                want,
+                "{strategy:?} x{multiplier} attempt {attempt} after {previous:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn the_exponential_floor_never_falls() {
+        let b = backoff(Strategy::Exponential);
+        for attempt in 1..40 {
+            assert!(
+                b.bounds(attempt, None).0 >= b.bounds(attempt - 1, None).0,
+                "attempt {attempt}"
+            );
+        }
+    }
+
+    #[test]
+    fn a_base_over_the_cap_is_capped() {
+        let b = Backoff {
+            base_ms: 5_000,
+            ..backoff(Strategy::Constant)
+        };
+        assert_eq!(b.bounds(0, None), (1_000, 1_000));
+        let b = Backoff {
+            strategy: Strategy::Exponential,
+            ..b
+        };
+        assert_eq!(b.bounds(0, None), (500, 1_000));
+    }
+
+    #[test]
+    fn delays_stay_in_bounds_and_repeat_with_a_seed() {
+        for strategy in Strategy::value_variants() {
+            let b = backoff(*strategy);
+            let draw = |seed| {
+                let mut rng = StdRng::seed_from_u64(seed);
+                let mut previous = None;
+                (0..8)
+                    .map(|n| {
+                        let ms = b.delay_ms(n, previous, &mut rng);
+                        let (low, high) = b.bounds(n, previous);
+                        assert!((low..=high).contains(&ms), "{strategy:?} attempt {n}: {ms}");
+                        previous = Some(ms);
+                        ms
+                    })
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(draw(7), draw(7), "{strategy:?}");
+        }
+    }
+
+    #[test]
+    fn describe_and_schedule() {
+        assert_eq!(
+            backoff(Strategy::Exponential).describe(1, None),
+            "exponential backoff for attempt 2 (base 100ms, x2, cap 1000ms): 100-200ms"
+        );
+        assert_eq!(
+            backoff(Strategy::DecorrelatedJitter).describe(1, Some(120)),
+            "decorrelated-jitter backoff for attempt 2 (base 100ms, previous 120ms, cap \
+            1000ms): 100-360ms"
+        );
+        assert_eq!(
+            backoff(Strategy::Linear).schedule(2, None::<StdRng>),
+            "retry 1 (after attempt 1): min 100ms, expected 100ms, max 100ms\n\
+            retry 2 (after attempt 2): min 200ms, expected 200ms, max 200ms\n\
+            total: min 300ms, expected 300ms, max 300ms over 2 retries\n"
+        );
+        let seeded = backoff(Strategy::FullJitter).schedule(3, Some(StdRng::seed_from_u64(1)));
+        assert!(seeded.ends_with("over 3 retries\n"), "{seeded}");
+    }
+
+    #[test]
+    fn multipliers() {
+        assert_eq!(parse_multiplier("1.5"), Ok(1.5));
+        assert_eq!(parse_multiplier("1"), Ok(1.0));
+        for bad in ["0.5", "inf", "NaN", "x"] {
+            assert!(parse_multiplier(bad).is_err(), "{bad}");
+        }
+    }
+}
@@ -0,0 +1,289 @@
+//! `--batch FILE`: run the child once per prompt, each entry with its own retry cycle under
+//! the shared settings, up to `--batch-concurrency` entries at a time.
+//!
+//! Entries are the file's non-blank lines, each a plain prompt or an NDJSON object with a
+//! `prompt` field, and are numbered by line. The prompt replaces `{prompt}` in the child
+//! args, or goes to the child's stdin when no arg has it. Every entry runs on one
+//! [`Supervisor`], so a wait one entry's output asks for holds the others too.
+//!
+//! Only the retry settings apply to entries (retries, backoff, patterns, exit-code lists,
+//! `--retry-on-any-error`, `--max-retry-after-ms`); the per-run features (timeouts, budgets,
+//! hooks, artifacts, events) do not. A failed entry is recorded and the batch goes on.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::thread;
+use std::time::Instant;
+
+use rusty_claude::exec::{Attempt, RunReport, Supervisor};
+use rusty_claude::patterns::Patterns;
+use serde_json::{json, Value};
+
+use crate::child_env::ChildEnv;
+use crate::duration::format_duration;
+use crate::exit_codes::{self, Outcome, Reason};
+use crate::{locale, resolve, runid, schema, Cli};
+
+/// The child arg text a prompt replaces.
+pub const PLACEHOLDER: &str = "{prompt}";
+
+/// One prompt, numbered by its line in the batch file.
+struct Entry {
+    line: usize,
+    prompt: String,
+}
+
+/// The entries of a batch file.
+fn read(path: &Path) -> Result<Vec<Entry>, String> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("cannot read --batch {}: {e}", path.display()))?;
+    let mut entries = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line_no = n + 1;
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() {
+            continue;
+        }
+        let prompt = if line.trim_start().starts_with('{') {
+            let at = || format!("{}:{line_no}", path.display());
+            let doc: Value = serde_json::from_str(line)
+                .map_err(|e| format!("--batch entry at {} is not valid JSON: {e}", at()))?;
+            doc["prompt"]
+                .as_str()
+                .ok_or_else(|| format!("--batch entry at {} has no string `prompt`", at()))?
+                .to_string()
+        } else {
+            line.to_string()
+        };
+        entries.push(Entry {
+            line: line_no,
+            prompt,
+        });
+    }
+    if entries.is_empty() {
+        return Err(format!("--batch {} holds no prompts", path.display()));
+    }
+    Ok(entries)
+}
+
+/// Where finished entries go: NDJSON lines on stdout, or one file each in a directory.
+enum Sink {
+    Stdout(Mutex<io::Stdout>),
+    Dir(PathBuf),
+}
+
+impl Sink {
+    fn write(&self, line: usize, record: &Value) -> io::Result<()> {
+        match self {
+            Sink::Stdout(stdout) => {
+                let mut stdout = stdout.lock().unwrap_or_else(PoisonError::into_inner);
+                writeln!(stdout, "{record}")?;
+                stdout.flush()
+            }
+            Sink::Dir(dir) => fs::write(
+                dir.join(format!("entry-{line}.json")),
+                format!("{record:#}\n"),
+            ),
+        }
+    }
+}
+
+/// The record of a finished entry.
+fn record(entry: &Entry, result: &io::Result<RunReport>, duration_ms: u64) -> Value {
+    let mut record = json!({
+        "schema": schema::BATCH,
+        "index": entry.line,
+        "prompt": entry.prompt,
+    });
+    let fields = match result {
+        Ok(report) => {
+            let last = report.last();
+            json!({
+                "ok": report.success(),
+                "exit_code": exit_codes::of_status(last.status),
+                "attempts": report.attempts.len(),
+                "duration_ms": duration_ms,
+                "stdout": String::from_utf8_lossy(&last.stdout),
+                "stderr": String::from_utf8_lossy(&last.stderr),
+                "error": null,
+            })
+        }
+        Err(e) => json!({
+            "ok": false,
+            "exit_code": null,
+            "attempts": 0,
+            "duration_ms": duration_ms,
+            "stdout": "",
+            "stderr": "",
+            "error": e.to_string(),
+        }),
+    };
+    if let (Some(record), Value::Object(fields)) = (record.as_object_mut(), fields) {
+        record.extend(fields);
+    }
+    record
+}
+
+/// The child for `entry`: `{prompt}` replaced in the args, and the stdin to feed it if no
+/// arg took the prompt.
+fn command(
+    cli: &Cli,
+    real_cmd: &str,
+    child_env: &ChildEnv,
+    run_id: &str,
+    entry: &Entry,
+) -> (Command, Option<Vec<u8>>) {
+    let substituted = cli.args.iter().any(|a| a.contains(PLACEHOLDER));
+    let args: Vec<String> = cli
+        .args
+        .iter()
+        .map(|a| a.replace(PLACEHOLDER, &entry.prompt))
+        .collect();
+    let mut cmd = resolve::command(real_cmd, &args);
+    child_env.apply(&mut cmd, &child_env.vars);
+    cmd.env(runid::ENV_VAR, run_id);
+    let input = (!substituted).then(|| format!("{}\n", entry.prompt).into_bytes());
+    (cmd, input)
+}
+
+/// Why an attempt is being retried, for the progress line.
+fn retried(line: usize, attempt: &Attempt, number: usize) -> String {
+    let mut why = format!("exit {}", exit_codes::of_status(attempt.status));
+    if let Some(pattern) = attempt.decision.as_ref().and_then(|d| d.matched.as_deref()) {
+        why += &format!(", matched `{pattern}`");
+    }
+    format!(
+        "[rusty-claude] batch entry {line}: attempt {number} failed ({why}); retrying in {}",
+        format_duration(attempt.delay.unwrap_or_default())
+    )
+}
+
+/// Run the batch in `cli.batch` under `patterns` and the other retry settings.
+pub fn run(cli: &Cli, real_cmd: &str, patterns: Patterns, run_id: &str) -> io::Result<Outcome> {
+    let path = cli.batch.as_deref().expect("--batch is set");
+    let entries = match read(path) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("[rusty-claude] error: {e}");
+            return Ok(Outcome::wrapper(
+                Reason::ConfigError,
+                exit_codes::CONFIG_ERROR,
+                0,
+            ));
+        }
+    };
+    let sink = if cli.batch_output == Path::new("-") {
+        Sink::Stdout(Mutex::new(io::stdout()))
+    } else {
+        if let Err(e) = fs::create_dir_all(&cli.batch_output) {
+            eprintln!(
+                "[rusty-claude] error: cannot create --batch-output {}: {e}",
+                cli.batch_output.display()
+            );
+            return Ok(Outcome::wrapper(
+                Reason::ConfigError,
+                exit_codes::CONFIG_ERROR,
+                0,
+            ));
+        }
+        Sink::Dir(cli.batch_output.clone())
+    };
+    let supervisor = Supervisor::new(crate::policy::retry_policy(cli, patterns));
+    let mut child_env = ChildEnv::new(cli);
+    if cli.stable_locale {
+        child_env.vars.extend(locale::stable_env().0);
+    }
+    child_env.set_explicit(cli);
+    if cli.verbose > 0 {
+        eprintln!("[rusty-claude] {}", child_env.describe(&[runid::ENV_VAR]));
+    }
+    let workers = (cli.batch_concurrency as usize).min(entries.len());
+    if !cli.quiet {
+        eprintln!(
+            "[rusty-claude] batch: {} entries from {}, {workers} at a time",
+            entries.len(),
+            path.display()
+        );
+    }
+
+    let next = AtomicUsize::new(0);
+    let failed = Mutex::new(Vec::new());
+    let attempts = AtomicUsize::new(0);
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                while let Some(entry) = entries.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let started = Instant::now();
+                    let (cmd, input) = command(cli, real_cmd, &child_env, run_id, entry);
+                    let mut number = 0;
+                    let result = supervisor.run_with(cmd, input.as_deref(), |attempt| {
+                        number += 1;
+                        if !cli.quiet {
+                            eprintln!("{}", retried(entry.line, attempt, number));
+                        }
+                    });
+                    let duration_ms = started.elapsed().as_millis() as u64;
+                    let record = record(entry, &result, duration_ms);
+                    attempts.fetch_add(
+                        record["attempts"].as_u64().unwrap_or(0) as usize,
+                        Ordering::Relaxed,
+                    );
+                    if record["ok"] != true {
+                        failed
+                            .lock()
+                            .unwrap_or_else(PoisonError::into_inner)
+                            .push(entry.line);
+                    }
+                    if !cli.quiet {
+                        let how = match (&result, record["ok"] == true) {
+                            (Err(e), _) => format!("could not run: {e}"),
+                            (Ok(_), true) => "ok".to_string(),
+                            (Ok(_), false) => format!("failed with exit {}", record["exit_code"]),
+                        };
+                        eprintln!(
+                            "[rusty-claude] batch entry {}: {how} after {} attempt(s) in {}",
+                            entry.line,
+                            record["attempts"],
+                            format_duration(started.elapsed())
+                        );
+                    }
+                    if let Err(e) = sink.write(entry.line, &record) {
+                        eprintln!(
+                            "[rusty-claude] warning: cannot write the result of batch entry {}: {e}",
+                            entry.line
+                        );
+                    }
+                }
+            });
+        }
+    });
+
+    let mut failed = failed.into_inner().unwrap_or_else(PoisonError::into_inner);
+    failed.sort_unstable();
+    let attempts = u32::try_from(attempts.into_inner()).unwrap_or(u32::MAX);
+    if failed.is_empty() {
+        if !cli.quiet {
+            eprintln!(
+                "[rusty-claude] batch: all {} entries succeeded",
+                entries.len()
+            );
+        }
+        return Ok(Outcome::wrapper(Reason::Success, 0, attempts));
+    }
+    let list: Vec<String> = failed.iter().map(usize::to_string).collect();
+    eprintln!(
+        "[rusty-claude] batch: {} of {} entries failed; failed entries (by line): {}",
+        failed.len(),
+        entries.len(),
+        list.join(",")
+    );
+    Ok(Outcome::wrapper(
+        Reason::BatchFailed,
+        exit_codes::BATCH_FAILED,
+        attempts,
+    ))
+}
@@ -0,0 +1,409 @@
+//! `rusty-claude bench`: measure what the wrapper adds on top of running the child directly.
+//!
+//! Each workload runs the built-in fake child with pinned arguments, once straight and once
+//! through the full wrapper pipeline (capture, tee, pattern matching), so numbers from
+//! different versions of rusty-claude stay comparable. The scan workloads time the pattern
+//! scan of a failed attempt in-process, whole-buffer against line-wise.
+
+use std::env;
+use std::hint::black_box;
+use std::io::{self, Read};
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::Args;
+use rusty_claude::patterns::{should_retry, Output, Patterns};
+use serde_json::json;
+
+use crate::exit_codes;
+use crate::schema;
+
+#[derive(Args, Debug)]
+pub struct BenchArgs {
+    /// Timed runs per workload and mode, after one untimed warm-up run
+    #[arg(long, default_value_t = 20, value_parser = clap::value_parser!(u32).range(1..))]
+    iterations: u32,
+
+    /// Print the results as JSON instead of a table
+    #[arg(long)]
+    json: bool,
+}
+
+/// A fake-child invocation whose behavior is fixed so results compare across versions.
+struct Workload {
+    name: &'static str,
+    child_args: &'static [&'static str],
+}
+
+const WORKLOADS: &[Workload] = &[
+    Workload {
+        name: "fast-exit",
+        child_args: &["succeed"],
+    },
+    Workload {
+        name: "streaming",
+        child_args: &["huge-output", "--bytes", "33554432"],
+    },
+];
+
+/// A synthetic transcript for the post-exit pattern scan, `SCAN_BYTES` of answer lines.
+struct ScanWorkload {
+    name: &'static str,
+    /// End the transcript with an overload error, as a failed attempt's output does.
+    error_at_tail: bool,
+}
+
+const SCAN_BYTES: usize = 16 * 1024 * 1024;
+
+const SCAN_WORKLOADS: &[ScanWorkload] = &[
+    ScanWorkload {
+        name: "scan-tail",
+        error_at_tail: true,
+    },
+    ScanWorkload {
+        name: "scan-miss",
+        error_at_tail: false,
+    },
+];
+
+/// Stream-json answer lines that no built-in pattern matches, then the error if asked.
+fn transcript(error_at_tail: bool) -> String {
+    const WORDS: &[&str] = &[
+        "the", "quick", "brown", "fox", "jumps", "over", "lazy", "dog",
+    ];
+    let mut text = String::with_capacity(SCAN_BYTES + 256);
+    let mut n = 0;
+    while text.len() < SCAN_BYTES {
+        text.push_str(r#"{"type":"assistant","message":{"content":[{"type":"text","text":""#);
+        for i in 0..12 {
+            text.push_str(WORDS[(n + i * 3) % WORDS.len()]);
+            text.push(' ');
+        }
+        text.push_str("\"}]}}\n");
+        n += 1;
+    }
+    if error_at_tail {
+        text.push_str(
+            r#"API Error: 529 {"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#,
+        );
+        text.push('\n');
+    }
+    text
+}
+
+/// Median wall times of the whole-buffer scan (each retry pattern over all of `text` in
+/// list order, as rusty-claude once matched) and of the decision it makes now (the line-wise
+/// scan and the `Retry-After` search), after checking that both report the same pattern.
+fn measure_scan(workload: &ScanWorkload, iterations: u32) -> io::Result<(Duration, Duration)> {
+    let patterns = Patterns::defaults(true, false);
+    let text = transcript(workload.error_at_tail);
+    let whole = || {
+        patterns
+            .regexes
+            .iter()
+            .find(|re| re.is_match(black_box(&text)))
+            .map(|re| re.as_str().to_string())
+    };
+    let by_line = || {
+        should_retry(
+            Output::Merged(black_box(&text)),
+            Some(1),
+            false,
+            &patterns,
+            None,
+        )
+        .matched
+    };
+    if whole() != by_line() {
+        return Err(io::Error::other(format!(
+            "{}: the scans disagree: {:?} against {:?}",
+            workload.name,
+            whole(),
+            by_line()
+        )));
+    }
+    let median = |scan: &dyn Fn() -> Option<String>| {
+        let mut walls: Vec<Duration> = (0..iterations)
+            .map(|_| {
+                let started = Instant::now();
+                black_box(scan());
+                started.elapsed()
+            })
+            .collect();
+        walls.sort_unstable();
+        walls[walls.len() / 2]
+    };
+    Ok((median(&whole), median(&by_line)))
+}
+
+/// One run of either mode.
+struct Sample {
+    wall: Duration,
+    first_byte: Option<Duration>,
+    /// Peak resident set of the process tree, in KiB, where the platform reports it.
+    max_rss_kib: Option<u64>,
+}
+
+/// Medians over the timed runs of one workload in one mode.
+struct Summary {
+    wall: Duration,
+    first_byte: Option<Duration>,
+    max_rss_kib: Option<u64>,
+}
+
+fn summarize(mut samples: Vec<Sample>) -> Summary {
+    fn median<T: Ord + Copy>(mut v: Vec<T>) -> Option<T> {
+        v.sort_unstable();
+        v.get(v.len() / 2).copied()
+    }
+    samples.sort_by_key(|s| s.wall);
+    Summary {
+        wall: samples[samples.len() / 2].wall,
+        first_byte: median(samples.iter().filter_map(|s| s.first_byte).collect()),
+        max_rss_kib: samples.iter().filter_map(|s| s.max_rss_kib).max(),
+    }
+}
+
+fn run_once(exe: &Path, wrapped: bool, workload: &Workload) -> io::Result<Sample> {
+    let mut cmd = Command::new(exe);
+    if wrapped {
+        cmd.arg("--cmd")
+            .arg(exe)
+            .args(["--allow-self-wrap", "--quiet", "--no-config", "--"]);
+    }
+    cmd.arg("__fake-child")
+        .args(workload.child_args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null());
+    crate::selftest::scrub_env(&mut cmd);
+
+    let started = Instant::now();
+    let mut child = cmd.spawn()?;
+    let mut out = child.stdout.take().expect("piped stdout");
+    let reader = thread::spawn(move || {
+        let mut first = None;
+        let mut buf = [0u8; 64 * 1024];
+        while let Ok(n) = out.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            first.get_or_insert_with(|| started.elapsed());
+        }
+        first
+    });
+    let (status, max_rss_kib) = wait_with_rss(&mut child)?;
+    let wall = started.elapsed();
+    let first_byte = reader.join().unwrap_or(None);
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "{} run of {} exited with {status}",
+            if wrapped { "wrapped" } else { "direct" },
+            workload.name
+        )));
+    }
+    Ok(Sample {
+        wall,
+        first_byte,
+        max_rss_kib,
+    })
+}
+
+/// Reap the child with `wait4` to get its peak RSS (which covers the wrapper's own child).
+#[cfg(unix)]
+fn wait_with_rss(child: &mut std::process::Child) -> io::Result<(ExitStatus, Option<u64>)> {
+    use std::os::unix::process::ExitStatusExt;
+
+    let mut status: libc::c_int = 0;
+    // SAFETY: rusage is plain data filled by wait4.
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    loop {
+        // SAFETY: waiting on our own, not yet reaped child with valid out-pointers.
+        let rc = unsafe { libc::wait4(child.id() as libc::pid_t, &mut status, 0, &mut usage) };
+        if rc != -1 {
+            break;
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+    // Linux and the BSDs report KiB, macOS bytes
+    let rss = usage.ru_maxrss as u64;
+    let kib = if cfg!(target_os = "macos") {
+        rss / 1024
+    } else {
+        rss
+    };
+    Ok((ExitStatus::from_raw(status), Some(kib)))
+}
+
+#[cfg(not(unix))]
+fn wait_with_rss(child: &mut std::process::Child) -> io::Result<(ExitStatus, Option<u64>)> {
+    child.wait().map(|status| (status, None))
+}
+
+fn measure(exe: &Path, wrapped: bool, workload: &Workload, iterations: u32) -> io::Result<Summary> {
+    run_once(exe, wrapped, workload)?;
+    let samples = (0..iterations)
+        .map(|_| run_once(exe, wrapped, workload))
+        .collect::<io::Result<Vec<_>>>()?;
+    Ok(summarize(samples))
+}
+
+/// Milliseconds with microsecond precision.
+fn ms(d: Duration) -> f64 {
+    d.as_micros() as f64 / 1000.0
+}
+
+fn delta_ms(wrapped: Option<Duration>, direct: Option<Duration>) -> Option<f64> {
+    Some(ms(wrapped?) - ms(direct?))
+}
+
+fn cell(v: Option<f64>, unit: &str) -> String {
+    v.map_or_else(|| "-".to_string(), |v| format!("{v:.2}{unit}"))
+}
+
+/// Run every workload and print the comparison; returns the process exit code.
+pub fn run(args: &BenchArgs) -> i32 {
+    let exe = match env::current_exe() {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("[rusty-claude] bench: cannot locate own executable: {e}");
+            return exit_codes::INTERNAL_ERROR;
+        }
+    };
+    let mut results = Vec::new();
+    for workload in WORKLOADS {
+        let pair = measure(&exe, false, workload, args.iterations).and_then(|direct| {
+            measure(&exe, true, workload, args.iterations).map(|wrapped| (direct, wrapped))
+        });
+        match pair {
+            Ok((direct, wrapped)) => results.push((workload, direct, wrapped)),
+            Err(e) => {
+                eprintln!("[rusty-claude] bench: {e}");
+                return exit_codes::INTERNAL_ERROR;
+            }
+        }
+    }
+    let mut scans = Vec::new();
+    for workload in SCAN_WORKLOADS {
+        match measure_scan(workload, args.iterations) {
+            Ok((whole, by_line)) => scans.push((workload, whole, by_line)),
+            Err(e) => {
+                eprintln!("[rusty-claude] bench: {e}");
+                return exit_codes::INTERNAL_ERROR;
+            }
+        }
+    }
+
+    if args.json {
+        let workloads: Vec<_> = results
+            .iter()
+            .map(|(w, direct, wrapped)| {
+                let mode = |s: &Summary| {
+                    json!({
+                        "wall_ms": ms(s.wall),
+                        "first_byte_ms": s.first_byte.map(ms),
+                        "max_rss_kib": s.max_rss_kib,
+                    })
+                };
+                json!({
+                    "name": w.name,
+                    "child_args": w.child_args,
+                    "direct": mode(direct),
+                    "wrapped": mode(wrapped),
+                    "overhead": {
+                        "wall_ms": ms(wrapped.wall) - ms(direct.wall),
+                        "first_byte_ms": delta_ms(wrapped.first_byte, direct.first_byte),
+                        "rss_kib": wrapped.max_rss_kib.zip(direct.max_rss_kib)
+                            .map(|(w, d)| w as i64 - d as i64),
+                    },
+                })
+            })
+            .collect();
+        let report = json!({
+            "schema": schema::BENCH,
+            "version": env!("CARGO_PKG_VERSION"),
+            "os": env::consts::OS,
+            "arch": env::consts::ARCH,
+            "iterations": args.iterations,
+            "workloads": workloads,
+            "scans": scans
+                .iter()
+                .map(|(w, whole, by_line)| json!({
+                    "name": w.name,
+                    "bytes": SCAN_BYTES,
+                    "whole_buffer_ms": ms(*whole),
+                    "line_wise_ms": ms(*by_line),
+                }))
+                .collect::<Vec<_>>(),
+        });
+        println!("{report:#}");
+        return 0;
+    }
+
+    println!(
+        "rusty-claude {} bench ({}/{}), median of {} runs",
+        env!("CARGO_PKG_VERSION"),
+        env::consts::OS,
+        env::consts::ARCH,
+        args.iterations
+    );
+    println!(
+        "  {:10}  {:8}  {:>11}  {:>11}  {:>12}",
+        "workload", "mode", "wall", "first byte", "peak rss"
+    );
+    for (w, direct, wrapped) in &results {
+        let rss = |s: &Summary| {
+            s.max_rss_kib
+                .map_or_else(|| "-".to_string(), |k| format!("{k} KiB"))
+        };
+        for (mode, s) in [("direct", direct), ("wrapped", wrapped)] {
+            println!(
+                "  {:10}  {:8}  {:>11}  {:>11}  {:>12}",
+                w.name,
+                mode,
+                cell(Some(ms(s.wall)), "ms"),
+                cell(s.first_byte.map(ms), "ms"),
+                rss(s)
+            );
+        }
+        let rss_delta = wrapped.max_rss_kib.zip(direct.max_rss_kib).map_or_else(
+            || "-".to_string(),
+            |(w, d)| format!("{:+} KiB", w as i64 - d as i64),
+        );
+        println!(
+            "  {:10}  {:8}  {:>11}  {:>11}  {:>12}",
+            w.name,
+            "overhead",
+            format!("{:+.2}ms", ms(wrapped.wall) - ms(direct.wall)),
+            delta_ms(wrapped.first_byte, direct.first_byte)
+                .map_or_else(|| "-".to_string(), |d| format!("{d:+.2}ms")),
+            rss_delta
+        );
+    }
+    for (w, whole, by_line) in &scans {
+        for (mode, wall) in [("whole", whole), ("by-line", by_line)] {
+            println!(
+                "  {:10}  {:8}  {:>11}  {:>11}  {:>12}",
+                w.name,
+                mode,
+                cell(Some(ms(*wall)), "ms"),
+                "-",
+                "-"
+            );
+        }
+        println!(
+            "  {:10}  {:8}  {:>11}  {:>11}  {:>12}",
+            w.name,
+            "delta",
+            format!("{:+.2}ms", ms(*by_line) - ms(*whole)),
+            "-",
+            "-"
+        );
+    }
+    0
+}
@@ -0,0 +1,173 @@
+//! The run's total-time budget (`--max-total-ms`), for retry decisions that depend on how
+//! much of it is left. It runs from before the first attempt, so the child's own running
+//! time counts as much as the waits between attempts.
+//!
+//! A backoff or `Retry-After` wait that cannot end inside the budget would only be slept
+//! through to fail anyway, so the retry loop gives up before it instead.
+
+use std::time::{Duration, Instant};
+
+use crate::duration::format_duration;
+
+/// The point by which the run must be over.
+#[derive(Clone, Copy, Debug)]
+pub struct Budget {
+    deadline: Instant,
+    total: Duration,
+}
+
+impl Budget {
+    /// `total` from `start`.
+    pub fn new(start: Instant, total: Duration) -> Self {
+        Budget {
+            deadline: start + total,
+            total,
+        }
+    }
+
+    pub fn total(&self) -> Duration {
+        self.total
+    }
+
+    pub fn remaining(&self, now: Instant) -> Duration {
+        self.deadline.saturating_duration_since(now)
+    }
+}
+
+/// A retry abandoned because its wait outlasts the budget.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GiveUp {
+    pub wait: Duration,
+    pub remaining: Duration,
+}
+
+impl GiveUp {
+    /// e.g. `server asked for 2m 00s but only 45s of budget remain`.
+    pub fn describe(&self, retry_after: bool) -> String {
+        let asked = if retry_after {
+            "server asked for"
+        } else {
+            "backoff is"
+        };
+        format!(
+            "{asked} {} but only {} of budget remain",
+            format_duration(self.wait),
+            format_duration(self.remaining)
+        )
+    }
+
+    /// `key=value` lines for `--reason-file`.
+    pub fn render(&self) -> String {
+        format!(
+            "gave_up_early=true\ngave_up_wait_ms={}\ngave_up_remaining_ms={}\n",
+            self.wait.as_millis(),
+            self.remaining.as_millis()
+        )
+    }
+}
+
+/// Whether sleeping `wait` at `now` leaves any budget for the attempt after it. Without a
+/// budget every wait fits; one that uses up exactly what's left does not.
+pub fn check_wait(budget: Option<&Budget>, wait: Duration, now: Instant) -> Result<(), GiveUp> {
+    let Some(budget) = budget else {
+        return Ok(());
+    };
+    let remaining = budget.remaining(now);
+    if wait < remaining {
+        Ok(())
+    } else {
+        Err(GiveUp { wait, remaining })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A clock that only moves when told to, from an arbitrary start.
+    struct Clock {
+        start: Instant,
+        elapsed: Duration,
+    }
+
+    impl Clock {
+        fn new() -> Self {
+            Clock {
+                start: Instant::now(),
+                elapsed: Duration::ZERO,
+            }
+        }
+
+        fn now(&self) -> Instant {
+            self.start + self.elapsed
+        }
+
+        fn advance(&mut self, by: Duration) {
+            self.elapsed += by;
+        }
+    }
+
+    const SEC: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn waits_against_the_budget() {
+        let mut clock = Clock::new();
+        let budget = Budget::new(clock.now(), 45 * SEC);
+        let give_up = |wait, remaining| Err(GiveUp { wait, remaining });
+        assert_eq!(
+            check_wait(Some(&budget), 120 * SEC, clock.now()),
+            give_up(120 * SEC, 45 * SEC)
+        );
+        // Sleeping exactly what's left leaves nothing for the attempt
+        assert_eq!(
+            check_wait(Some(&budget), 45 * SEC, clock.now()),
+            give_up(45 * SEC, 45 * SEC)
+        );
+        assert_eq!(check_wait(Some(&budget), 44 * SEC, clock.now()), Ok(()));
+        clock.advance(30 * SEC);
+        assert_eq!(budget.remaining(clock.now()), 15 * SEC);
+        assert_eq!(
+            check_wait(Some(&budget), 20 * SEC, clock.now()),
+            give_up(20 * SEC, 15 * SEC)
+        );
+        clock.advance(15 * SEC);
+        assert_eq!(
+            check_wait(Some(&budget), Duration::ZERO, clock.now()),
+            give_up(Duration::ZERO, Duration::ZERO)
+        );
+        // Past the deadline nothing is left, rather than a negative remainder
+        clock.advance(15 * SEC);
+        assert_eq!(budget.remaining(clock.now()), Duration::ZERO);
+        assert_eq!(
+            check_wait(Some(&budget), SEC, clock.now()),
+            give_up(SEC, Duration::ZERO)
+        );
+        assert_eq!(budget.total(), 45 * SEC);
+    }
+
+    #[test]
+    fn without_a_budget_every_wait_fits() {
+        let clock = Clock::new();
+        assert_eq!(check_wait(None, 86_400 * SEC, clock.now()), Ok(()));
+    }
+
+    #[test]
+    fn give_up_text() {
+        let give_up = GiveUp {
+            wait: 120 * SEC,
+            remaining: 45 * SEC,
+        };
+        assert_eq!(
+            give_up.describe(true),
+            "server asked for 2m 00s but only 45s of budget remain"
+        );
+        assert_eq!(
+            give_up.describe(false),
+            "backoff is 2m 00s but only 45s of budget remain"
+        );
+        assert_eq!(
+            give_up.render(),
+            "gave_up_early=true\ngave_up_wait_ms=120000\ngave_up_remaining_ms=45000\n"
+        );
+    }
+}
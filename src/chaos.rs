@@ -0,0 +1,203 @@
+//! Fault injection for testing rusty-claude itself: the hidden `--chaos SPEC` flag, built
+//! only with the `chaos` cargo feature. Each spec names a checkpoint and how it misbehaves:
+//!
+//! - `tee-write-error:after=SIZE`: forwarding child output fails once SIZE bytes have been
+//!   forwarded in the run
+//! - `slow-consumer:delay=DURATION`: every forwarded chunk is held up first, like a reader
+//!   that can't keep up
+//! - `spawn-fail:attempt=N:kind=notfound|permission`: spawning attempt N fails
+//! - `sleep-skew:+DURATION` or `-DURATION`: backoff waits run that much longer or shorter,
+//!   as if the clock jumped during the wait
+//! - `panic:attempt=N`: the wrapper panics before spawning attempt N
+//! - `release-log:path=FILE`: every cleanup the shutdown registry runs appends its name to
+//!   FILE, so a test can see each resource released exactly once
+//!
+//! Without the feature every checkpoint is an empty inline function.
+
+use std::io;
+use std::time::Duration;
+
+#[cfg(feature = "chaos")]
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "chaos")]
+use std::sync::OnceLock;
+
+#[cfg(feature = "chaos")]
+use crate::duration::parse_duration;
+#[cfg(feature = "chaos")]
+use crate::size::parse_size;
+
+#[cfg(feature = "chaos")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Fault {
+    TeeWriteError { after: u64 },
+    SlowConsumer { delay: Duration },
+    SpawnFail { attempt: u32, kind: io::ErrorKind },
+    SleepSkew { longer: bool, by: Duration },
+    Panic { attempt: u32 },
+    ReleaseLog { path: std::path::PathBuf },
+}
+
+#[cfg(feature = "chaos")]
+static FAULTS: OnceLock<Vec<Fault>> = OnceLock::new();
+/// Child output forwarded so far, for `tee-write-error`.
+#[cfg(feature = "chaos")]
+static FORWARDED: AtomicU64 = AtomicU64::new(0);
+
+/// Parse one `--chaos` spec.
+#[cfg(feature = "chaos")]
+pub fn parse(spec: &str) -> Result<Fault, String> {
+    if let Some(skew) = spec.strip_prefix("sleep-skew:") {
+        let (longer, by) = match skew.split_at_checked(1) {
+            Some(("+", by)) => (true, by),
+            Some(("-", by)) => (false, by),
+            _ => return Err(format!("`{skew}`: expected +DURATION or -DURATION")),
+        };
+        return Ok(Fault::SleepSkew {
+            longer,
+            by: parse_duration(by)?,
+        });
+    }
+    let mut parts = spec.split(':');
+    let name = parts.next().unwrap_or_default();
+    let mut options = Vec::new();
+    for part in parts {
+        options.push(
+            part.split_once('=')
+                .ok_or_else(|| format!("`{part}` in `{spec}`: expected KEY=VALUE"))?,
+        );
+    }
+    let option = |key: &str| {
+        options
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| *v)
+            .ok_or_else(|| format!("`{name}` needs {key}=..."))
+    };
+    match name {
+        "tee-write-error" => Ok(Fault::TeeWriteError {
+            after: parse_size(option("after")?)?,
+        }),
+        "slow-consumer" => Ok(Fault::SlowConsumer {
+            delay: parse_duration(option("delay")?)?,
+        }),
+        "panic" => Ok(Fault::Panic {
+            attempt: option("attempt")?
+                .parse()
+                .map_err(|_| format!("invalid attempt in `{spec}`"))?,
+        }),
+        "release-log" => Ok(Fault::ReleaseLog {
+            path: option("path")?.into(),
+        }),
+        "spawn-fail" => Ok(Fault::SpawnFail {
+            attempt: option("attempt")?
+                .parse()
+                .map_err(|_| format!("invalid attempt in `{spec}`"))?,
+            kind: match option("kind").unwrap_or("notfound") {
+                "notfound" => io::ErrorKind::NotFound,
+                "permission" => io::ErrorKind::PermissionDenied,
+                other => return Err(format!("unknown spawn-fail kind `{other}`")),
+            },
+        }),
+        other => Err(format!(
+            "unknown fault `{other}` (expected tee-write-error, slow-consumer, spawn-fail, \
+            sleep-skew, panic, or release-log)"
+        )),
+    }
+}
+
+/// Arm the faults for this run.
+#[cfg(feature = "chaos")]
+pub fn install(faults: Vec<Fault>) {
+    let _ = FAULTS.set(faults);
+}
+
+#[cfg(feature = "chaos")]
+fn faults() -> &'static [Fault] {
+    FAULTS.get().map_or(&[], Vec::as_slice)
+}
+
+/// Checkpoint before `n` bytes of child output are forwarded.
+#[cfg(feature = "chaos")]
+pub fn tee_write(n: usize) -> io::Result<()> {
+    for fault in faults() {
+        match fault {
+            Fault::SlowConsumer { delay } => std::thread::sleep(*delay),
+            Fault::TeeWriteError { after }
+                if FORWARDED.fetch_add(n as u64, Ordering::SeqCst) >= *after =>
+            {
+                return Err(io::Error::other("chaos: injected tee write error"));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "chaos"))]
+#[inline(always)]
+pub fn tee_write(_: usize) -> io::Result<()> {
+    Ok(())
+}
+
+/// Checkpoint before the child of `attempt` (1-based) is spawned.
+#[cfg(feature = "chaos")]
+pub fn spawn(attempt: u32) -> io::Result<()> {
+    if faults()
+        .iter()
+        .any(|f| matches!(f, Fault::Panic { attempt: n } if *n == attempt))
+    {
+        panic!("chaos: injected panic before attempt {attempt}");
+    }
+    match faults().iter().find_map(|f| match f {
+        Fault::SpawnFail { attempt: n, kind } if *n == attempt => Some(*kind),
+        _ => None,
+    }) {
+        Some(kind) => Err(io::Error::new(kind, "chaos: injected spawn failure")),
+        None => Ok(()),
+    }
+}
+
+#[cfg(not(feature = "chaos"))]
+#[inline(always)]
+pub fn spawn(_: u32) -> io::Result<()> {
+    Ok(())
+}
+
+/// Checkpoint before a backoff wait; returns the wait to actually sleep.
+#[cfg(feature = "chaos")]
+pub fn sleep(wait: Duration) -> Duration {
+    faults().iter().fold(wait, |wait, fault| match fault {
+        Fault::SleepSkew { longer: true, by } => wait + *by,
+        Fault::SleepSkew { longer: false, by } => wait.saturating_sub(*by),
+        _ => wait,
+    })
+}
+
+#[cfg(not(feature = "chaos"))]
+#[inline(always)]
+pub fn sleep(wait: Duration) -> Duration {
+    wait
+}
+
+/// Checkpoint as the shutdown registry runs the cleanup called `name`.
+#[cfg(feature = "chaos")]
+pub fn released(name: &str) {
+    use std::io::Write;
+
+    for fault in faults() {
+        if let Fault::ReleaseLog { path } = fault {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path);
+            if let Ok(mut file) = file {
+                let _ = writeln!(file, "{name}");
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "chaos"))]
+#[inline(always)]
+pub fn released(_: &str) {}
@@ -0,0 +1,156 @@
+//! `--env`, `--unset-env`, and `--no-inherit-env`: the environment every child starts with.
+//!
+//! The inherited environment comes first (only a few basics of it under
+//! `--no-inherit-env`), minus the variables `--unset-env` names; then the wrapper's own
+//! variables and `--env`, which wins. Every spawn of the child, interactive or piped and on
+//! every attempt, clears what it would inherit and sets exactly this.
+
+use std::env;
+use std::ffi::OsString;
+use std::process::Command;
+
+use crate::Cli;
+
+/// What `--no-inherit-env` keeps; Windows programs need a few more to start at all.
+const BASICS: &[&str] = if cfg!(windows) {
+    &[
+        "PATH",
+        "HOME",
+        "TERM",
+        "SystemRoot",
+        "ComSpec",
+        "PATHEXT",
+        "USERPROFILE",
+        "APPDATA",
+        "LOCALAPPDATA",
+        "TEMP",
+        "TMP",
+    ]
+} else {
+    &["PATH", "HOME", "TERM"]
+};
+
+/// Parse a `--env KEY=VALUE`.
+pub fn parse_assignment(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err("expected KEY=VALUE".to_string()),
+    }
+}
+
+/// Variable names are case-insensitive on Windows.
+fn same_name(a: &str, b: &str) -> bool {
+    if cfg!(windows) {
+        a.eq_ignore_ascii_case(b)
+    } else {
+        a == b
+    }
+}
+
+/// Whether `name` matches `pattern`, where `*` is any run of characters and `?` any one.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let (p, n): (Vec<char>, Vec<char>) = if cfg!(windows) {
+        (
+            pattern.to_ascii_uppercase().chars().collect(),
+            name.to_ascii_uppercase().chars().collect(),
+        )
+    } else {
+        (pattern.chars().collect(), name.chars().collect())
+    };
+    // The last `*` seen and where in `name` it was taken to start
+    let (mut pi, mut ni) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while ni < n.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == n[ni]) {
+            pi += 1;
+            ni += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ni));
+            pi += 1;
+        } else if let Some((sp, sn)) = star {
+            pi = sp + 1;
+            ni = sn + 1;
+            star = Some((sp, sn + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+/// The environment of every child: what it inherits of ours, and the variables set over
+/// that.
+pub struct ChildEnv {
+    pub inherited: Vec<(OsString, OsString)>,
+    /// How many of our variables were left out.
+    pub dropped: usize,
+    /// The wrapper's own variables, then `--env`.
+    pub vars: Vec<(OsString, OsString)>,
+}
+
+impl ChildEnv {
+    /// Everything we have, or only the basics under `--no-inherit-env`, less the variables
+    /// an `--unset-env` pattern matches; nothing set over it yet.
+    pub fn new(cli: &Cli) -> Self {
+        let mut inherited = Vec::new();
+        let mut dropped = 0;
+        for (key, value) in env::vars_os() {
+            let name = key.to_string_lossy();
+            let basic = BASICS.iter().any(|b| same_name(b, &name));
+            if (!cli.no_inherit_env || basic) && !cli.unset_env.iter().any(|p| glob_match(p, &name))
+            {
+                inherited.push((key, value));
+            } else {
+                dropped += 1;
+            }
+        }
+        ChildEnv {
+            inherited,
+            dropped,
+            vars: Vec::new(),
+        }
+    }
+
+    /// Set `--env` over everything so far; call it after the wrapper's own variables.
+    pub fn set_explicit(&mut self, cli: &Cli) {
+        self.vars.extend(
+            cli.child_vars
+                .iter()
+                .map(|(k, v)| (OsString::from(k), OsString::from(v))),
+        );
+    }
+
+    /// Give `cmd` exactly the inherited variables with `vars` over them (`self.vars`, or a
+    /// copy with an attempt's own additions).
+    pub fn apply(&self, cmd: &mut Command, vars: &[(OsString, OsString)]) {
+        cmd.env_clear()
+            .envs(self.inherited.iter().map(|(k, v)| (k, v)))
+            .envs(vars.iter().map(|(k, v)| (k, v)));
+    }
+
+    /// The `-v` line naming the child's variables, never their values; `extra` are the
+    /// names set at each spawn.
+    pub fn describe(&self, extra: &[&str]) -> String {
+        let mut names: Vec<String> = Vec::new();
+        let all = self
+            .inherited
+            .iter()
+            .chain(&self.vars)
+            .map(|(k, _)| k.to_string_lossy());
+        for name in all.chain(extra.iter().map(|&n| n.into())) {
+            if !names.iter().any(|n| same_name(n, &name)) {
+                names.push(name.into_owned());
+            }
+        }
+        names.sort();
+        let mut line = format!(
+            "child environment: {} variable(s): {}",
+            names.len(),
+            names.join(" ")
+        );
+        if self.dropped > 0 {
+            line += &format!(" ({} of ours not inherited)", self.dropped);
+        }
+        line
+    }
+}
@@ -0,0 +1,207 @@
+//! Workflow annotations and collapsible log groups for CI systems (`--ci-annotations`).
+
+use clap::ValueEnum;
+use std::env;
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum CiMode {
+    /// Detect from GITHUB_ACTIONS / GITLAB_CI
+    Auto,
+    Github,
+    Gitlab,
+    Off,
+}
+
+impl CiMode {
+    /// Resolve `auto` against the environment; other modes are returned unchanged.
+    pub fn resolve(self) -> CiMode {
+        match self {
+            CiMode::Auto if env::var_os("GITHUB_ACTIONS").is_some() => CiMode::Github,
+            CiMode::Auto if env::var_os("GITLAB_CI").is_some() => CiMode::Gitlab,
+            CiMode::Auto => CiMode::Off,
+            other => other,
+        }
+    }
+}
+
+/// Emits control lines for the selected CI platform. Lines go to stdout per the platforms'
+/// conventions unless `to_stderr` is set because stdout carries machine-readable data.
+pub struct Annotator {
+    mode: CiMode,
+    to_stderr: bool,
+}
+
+impl Annotator {
+    pub fn new(mode: CiMode, to_stderr: bool) -> Self {
+        Annotator { mode, to_stderr }
+    }
+
+    pub fn group_start(&self, attempt: u32) {
+        if let Some(line) = group_start_line(self.mode, attempt, unix_now()) {
+            self.emit(&line);
+        }
+    }
+
+    pub fn group_end(&self, attempt: u32) {
+        if let Some(line) = group_end_line(self.mode, attempt, unix_now()) {
+            self.emit(&line);
+        }
+    }
+
+    pub fn warning(&self, msg: &str) {
+        if let Some(line) = message_line(self.mode, Level::Warning, msg) {
+            self.emit(&line);
+        }
+    }
+
+    pub fn error(&self, msg: &str) {
+        if let Some(line) = message_line(self.mode, Level::Error, msg) {
+            self.emit(&line);
+        }
+    }
+
+    fn emit(&self, line: &str) {
+        // Annotation output is best-effort; it must never fail the run.
+        if self.to_stderr {
+            let mut err = io::stderr().lock();
+            let _ = writeln!(err, "{line}");
+        } else {
+            let mut out = io::stdout().lock();
+            let _ = writeln!(out, "{line}");
+            let _ = out.flush();
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Level {
+    Warning,
+    Error,
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// GitHub workflow commands treat `%`, CR and LF in the message specially.
+fn escape_github(msg: &str) -> String {
+    msg.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+pub fn group_start_line(mode: CiMode, attempt: u32, ts: u64) -> Option<String> {
+    match mode {
+        CiMode::Github => Some(format!("::group::attempt {attempt}")),
+        CiMode::Gitlab => Some(format!(
+            "\x1b[0Ksection_start:{ts}:attempt_{attempt}[collapsed=true]\r\x1b[0Kattempt {attempt}"
+        )),
+        CiMode::Auto | CiMode::Off => None,
+    }
+}
+
+pub fn group_end_line(mode: CiMode, attempt: u32, ts: u64) -> Option<String> {
+    match mode {
+        CiMode::Github => Some("::endgroup::".to_string()),
+        CiMode::Gitlab => Some(format!(
+            "\x1b[0Ksection_end:{ts}:attempt_{attempt}\r\x1b[0K"
+        )),
+        CiMode::Auto | CiMode::Off => None,
+    }
+}
+
+pub fn message_line(mode: CiMode, level: Level, msg: &str) -> Option<String> {
+    match (mode, level) {
+        (CiMode::Github, Level::Warning) => Some(format!("::warning::{}", escape_github(msg))),
+        (CiMode::Github, Level::Error) => Some(format!("::error::{}", escape_github(msg))),
+        // GitLab has no annotation syntax; colored lines stand out in the job log.
+        (CiMode::Gitlab, Level::Warning) => Some(format!("\x1b[33;1mWARNING: {msg}\x1b[0m")),
+        (CiMode::Gitlab, Level::Error) => Some(format!("\x1b[31;1mERROR: {msg}\x1b[0m")),
+        (CiMode::Auto | CiMode::Off, _) => None,
+    }
+}
+
+/// Whether the child args ask for machine-readable stdout that control lines would corrupt.
+pub fn stdout_is_structured(args: &[String]) -> bool {
+    args.iter()
+        .enumerate()
+        .any(|(i, a)| match a.strip_prefix("--output-format=") {
+            Some(format) => format != "text",
+            None => {
+                a == "--json"
+                    || (a == "--output-format" && args.get(i + 1).is_some_and(|f| f != "text"))
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn github_lines() {
+        assert_eq!(
+            group_start_line(CiMode::Github, 2, 0).as_deref(),
+            Some("::group::attempt 2")
+        );
+        assert_eq!(
+            group_end_line(CiMode::Github, 2, 0).as_deref(),
+            Some("::endgroup::")
+        );
+        assert_eq!(
+            message_line(CiMode::Github, Level::Warning, "50% done\r\nnext").as_deref(),
+            Some("::warning::50%25 done%0D%0Anext")
+        );
+        assert_eq!(
+            message_line(CiMode::Github, Level::Error, "failed").as_deref(),
+            Some("::error::failed")
+        );
+    }
+
+    #[test]
+    fn gitlab_lines() {
+        assert_eq!(
+            group_start_line(CiMode::Gitlab, 1, 1700000000).as_deref(),
+            Some("\x1b[0Ksection_start:1700000000:attempt_1[collapsed=true]\r\x1b[0Kattempt 1")
+        );
+        assert_eq!(
+            group_end_line(CiMode::Gitlab, 1, 1700000001).as_deref(),
+            Some("\x1b[0Ksection_end:1700000001:attempt_1\r\x1b[0K")
+        );
+        assert_eq!(
+            message_line(CiMode::Gitlab, Level::Warning, "retrying").as_deref(),
+            Some("\x1b[33;1mWARNING: retrying\x1b[0m")
+        );
+        assert_eq!(
+            message_line(CiMode::Gitlab, Level::Error, "failed").as_deref(),
+            Some("\x1b[31;1mERROR: failed\x1b[0m")
+        );
+    }
+
+    #[test]
+    fn off_emits_nothing() {
+        for mode in [CiMode::Off, CiMode::Auto] {
+            assert_eq!(group_start_line(mode, 1, 0), None);
+            assert_eq!(group_end_line(mode, 1, 0), None);
+            assert_eq!(message_line(mode, Level::Error, "x"), None);
+        }
+    }
+
+    #[test]
+    fn structured_stdout() {
+        let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert!(stdout_is_structured(&args(&["--json"])));
+        assert!(stdout_is_structured(&args(&[
+            "--output-format",
+            "stream-json"
+        ])));
+        assert!(stdout_is_structured(&args(&["--output-format=json"])));
+        assert!(!stdout_is_structured(&args(&["--output-format", "text"])));
+        assert!(!stdout_is_structured(&args(&["-p", "hi"])));
+    }
+}
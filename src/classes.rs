@@ -0,0 +1,163 @@
+//! Error classes for retryable matches, and `--class-budget` accounting on top of the
+//! global `--max-retries`.
+
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ErrorClass {
+    /// 429 / Too Many Requests; cheap to wait out.
+    RateLimit,
+    /// 5xx responses and overload errors.
+    Server,
+    /// Connection resets, timeouts, and DNS or fetch failures.
+    Network,
+}
+
+impl ErrorClass {
+    pub const ALL: [ErrorClass; 3] = [
+        ErrorClass::RateLimit,
+        ErrorClass::Server,
+        ErrorClass::Network,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorClass::RateLimit => "ratelimit",
+            ErrorClass::Server => "server",
+            ErrorClass::Network => "network",
+        }
+    }
+}
+
+impl fmt::Display for ErrorClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ErrorClass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ErrorClass::ALL
+            .into_iter()
+            .find(|c| c.as_str() == s)
+            .ok_or_else(|| {
+                format!("unknown error class `{s}` (expected ratelimit, server, or network)")
+            })
+    }
+}
+
+/// Parse `ratelimit=10,server=2,network=3`.
+pub fn parse_class_budget(s: &str) -> Result<ClassBudget, String> {
+    let mut budget = ClassBudget::default();
+    for item in s.split(',').map(str::trim).filter(|i| !i.is_empty()) {
+        let (class, limit) = item
+            .split_once('=')
+            .ok_or_else(|| format!("expected CLASS=N, got `{item}`"))?;
+        let class: ErrorClass = class.trim().parse()?;
+        let limit: u32 = limit
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid retry count in `{item}`"))?;
+        budget.limits[class as usize] = Some(limit);
+    }
+    Ok(budget)
+}
+
+/// Retries allowed and taken per class. Classes without a limit, and matches that belong
+/// to no class, are bounded by the global budget only.
+#[derive(Clone, Debug, Default)]
+pub struct ClassBudget {
+    limits: [Option<u32>; 3],
+    used: [u32; 3],
+}
+
+impl ClassBudget {
+    /// Account for one more retry of `class`; returns the exhausted limit if there is none left.
+    pub fn take(&mut self, class: Option<ErrorClass>) -> Result<(), u32> {
+        let Some(class) = class else {
+            return Ok(());
+        };
+        let i = class as usize;
+        match self.limits[i] {
+            Some(limit) if self.used[i] >= limit => Err(limit),
+            _ => {
+                self.used[i] += 1;
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ErrorClass::{Network, RateLimit, Server};
+
+    /// Drive a budget over the classes of a scripted run of failures under `--max-retries`,
+    /// as the supervisor does: returns the retries taken and, if a class budget ended the
+    /// run, the limit it hit.
+    fn run(budget: &str, max_retries: u32, failures: &[Option<ErrorClass>]) -> (u32, Option<u32>) {
+        let mut budget = parse_class_budget(budget).unwrap();
+        let mut retries = 0;
+        for &class in failures {
+            if retries == max_retries {
+                break;
+            }
+            if let Err(limit) = budget.take(class) {
+                return (retries, Some(limit));
+            }
+            retries += 1;
+        }
+        (retries, None)
+    }
+
+    #[test]
+    fn parses_budgets() {
+        let budget = parse_class_budget(" server=2, network = 0 ,").unwrap();
+        assert_eq!(budget.limits, [None, Some(2), Some(0)]);
+        assert!(parse_class_budget("")
+            .unwrap()
+            .limits
+            .iter()
+            .all(Option::is_none));
+        for (bad, error) in [
+            ("server", "expected CLASS=N, got `server`"),
+            ("disk=1", "unknown error class `disk`"),
+            ("server=-1", "invalid retry count in `server=-1`"),
+        ] {
+            let got = parse_class_budget(bad).unwrap_err();
+            assert!(got.starts_with(error), "{bad}: {got}");
+        }
+    }
+
+    #[test]
+    fn a_class_stops_at_its_limit() {
+        assert_eq!(run("server=2", 5, &[Some(Server); 9]), (2, Some(2)));
+        assert_eq!(run("server=0", 5, &[Some(Server); 9]), (0, Some(0)));
+    }
+
+    #[test]
+    fn the_global_budget_still_applies() {
+        assert_eq!(run("ratelimit=10", 2, &[Some(RateLimit); 9]), (2, None));
+        assert_eq!(run("server=1", 3, &[None; 9]), (3, None));
+    }
+
+    #[test]
+    fn classes_are_counted_apart() {
+        let failures = [
+            Some(Server),
+            Some(RateLimit),
+            Some(Network),
+            None,
+            Some(RateLimit),
+            Some(Server),
+        ];
+        assert_eq!(run("server=1,network=1", 9, &failures), (5, Some(1)));
+        assert_eq!(run("server=2,network=1", 9, &failures), (6, None));
+        assert_eq!(run("network=0", 9, &failures), (2, Some(0)));
+    }
+}
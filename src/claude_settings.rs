@@ -0,0 +1,136 @@
+//! `--claude-settings`: defaults from the `rustyClaude` block of a Claude Code settings
+//! file, so a project configures the CLI and the supervisor in one place.
+//!
+//! ```json
+//! { "permissions": { ... }, "rustyClaude": { "maxRetries": 4, "patterns": ["busy"] } }
+//! ```
+//!
+//! The block takes the config file's keys in camelCase and sits below it in precedence.
+//! The rest of the file belongs to the CLI: when it doesn't parse, the block is read on its
+//! own, and only a problem inside the block stops the run.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json::Value as Json;
+
+use crate::config::{self, Config, Entry, Value};
+use crate::settings::Source;
+
+/// The key of our block in the settings file.
+const BLOCK: &str = "rustyClaude";
+
+/// The settings file for `--claude-settings`: `auto` is the first `.claude/settings.json`
+/// in the working directory or above it, if any.
+pub fn locate(requested: &str) -> Option<PathBuf> {
+    if requested != "auto" {
+        return Some(PathBuf::from(requested));
+    }
+    let cwd = env::current_dir().ok()?;
+    cwd.ancestors()
+        .map(|dir| dir.join(".claude").join("settings.json"))
+        .find(|path| path.is_file())
+}
+
+/// Read the block from `path`; `None` when the file has none.
+pub fn load(path: &Path) -> Result<Option<Config>, String> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("cannot read Claude settings {}: {e}", path.display()))?;
+    let Some(block) = block(path, &text)? else {
+        return Ok(None);
+    };
+    let Json::Object(fields) = block else {
+        return Err(format!("{}: `{BLOCK}` must be an object", path.display()));
+    };
+    let at = path.display().to_string();
+    let mut entries = Vec::new();
+    let mut unknown = Vec::new();
+    for (field, json) in fields {
+        let name = format!("{BLOCK}.{field}");
+        let key = snake_case(&field);
+        if !config::known(&key) {
+            unknown.push(config::unknown_key(&at, &name, "Claude settings block"));
+            continue;
+        }
+        let value = convert(&json).ok_or_else(|| {
+            let why = match &json {
+                Json::Number(n) => format!("must be an integer, not {n}"),
+                Json::Array(_) => "must hold only strings, integers, and booleans".to_string(),
+                _ => "must not be null or an object".to_string(),
+            };
+            format!("{at}: `{name}` {why}")
+        })?;
+        entries.push(Entry {
+            key,
+            source: Source::ClaudeSettings(format!("{at} {name}")),
+            name,
+            value,
+            at: at.clone(),
+        });
+    }
+    let mut config = Config::checked(path, entries, "Claude settings block")?;
+    config.unknown.splice(0..0, unknown);
+    Ok(Some(config))
+}
+
+/// The value of our block: from the whole file when it parses, else from the text after
+/// the block's key.
+fn block(path: &Path, text: &str) -> Result<Option<Json>, String> {
+    if let Ok(whole) = serde_json::from_str::<Json>(text) {
+        return Ok(whole.get(BLOCK).cloned());
+    }
+    let key = format!("\"{BLOCK}\"");
+    let start = text.match_indices(&key).find_map(|(i, _)| {
+        let after = &text[i + key.len()..];
+        let value = after.trim_start().strip_prefix(':')?;
+        Some(text.len() - value.len())
+    });
+    let Some(start) = start else {
+        return Ok(None);
+    };
+    match serde_json::Deserializer::from_str(&text[start..])
+        .into_iter::<Json>()
+        .next()
+    {
+        Some(Ok(value)) => Ok(Some(value)),
+        Some(Err(e)) => {
+            let line = text[..start].matches('\n').count() + e.line();
+            // The position serde_json appends is within the block, not the file
+            let e = e.to_string();
+            let message = e.rsplit_once(" at line ").map_or(e.as_str(), |(m, _)| m);
+            Err(format!(
+                "{}:{line}: cannot parse the `{BLOCK}` block: {message}",
+                path.display()
+            ))
+        }
+        None => Err(format!(
+            "{}: the `{BLOCK}` block has no value",
+            path.display()
+        )),
+    }
+}
+
+/// `maxRetries` -> `max_retries`.
+fn snake_case(field: &str) -> String {
+    let mut out = String::new();
+    for c in field.chars() {
+        if c.is_ascii_uppercase() {
+            out.push('_');
+            out.push(c.to_ascii_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+fn convert(json: &Json) -> Option<Value> {
+    Some(match json {
+        Json::Bool(on) => Value::Bool(*on),
+        Json::Number(n) => Value::Integer(n.as_i64()?),
+        Json::String(s) => Value::String(s.clone()),
+        Json::Array(items) => Value::Array(items.iter().map(convert).collect::<Option<_>>()?),
+        Json::Null | Json::Object(_) => return None,
+    })
+}
@@ -0,0 +1,178 @@
+//! Exit-code lists for `--retry-exit-codes` and `--no-retry-exit-codes`: comma-separated
+//! codes (`1`), inclusive ranges (`70-78`), and `signal` for a child killed by a signal,
+//! which has no exit code.
+
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Entry {
+    Range(i32, i32),
+    Signal,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CodeList {
+    entries: Vec<Entry>,
+}
+
+impl CodeList {
+    /// Whether an attempt that exited with `code` (`None` for a signal) is listed.
+    pub fn contains(&self, code: Option<i32>) -> bool {
+        self.entries.iter().any(|&e| match (e, code) {
+            (Entry::Range(low, high), Some(c)) => (low..=high).contains(&c),
+            (Entry::Signal, None) => true,
+            _ => false,
+        })
+    }
+}
+
+fn code(s: &str) -> Result<i32, String> {
+    s.trim()
+        .parse::<u32>()
+        .ok()
+        .and_then(|c| i32::try_from(c).ok())
+        .ok_or_else(|| format!("invalid exit code `{}`", s.trim()))
+}
+
+impl FromStr for CodeList {
+    type Err = String;
+
+    /// e.g. `1,75,130`, `70-78,signal`.
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut entries = Vec::new();
+        for item in s.split(',').map(str::trim) {
+            let entry = if item.eq_ignore_ascii_case("signal") {
+                Entry::Signal
+            } else if let Some((low, high)) = item.split_once('-') {
+                let (low, high) = (code(low)?, code(high)?);
+                if low > high {
+                    return Err(format!("range `{item}` runs backwards"));
+                }
+                Entry::Range(low, high)
+            } else if item.is_empty() {
+                return Err(format!(
+                    "empty entry in `{s}` (expected codes like 1,75, ranges like 70-78, or \
+                    signal)"
+                ));
+            } else {
+                let c = code(item)?;
+                Entry::Range(c, c)
+            };
+            entries.push(entry);
+        }
+        Ok(CodeList { entries })
+    }
+}
+
+impl fmt::Display for CodeList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, entry) in self.entries.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            match *entry {
+                Entry::Range(low, high) if low == high => write!(f, "{low}")?,
+                Entry::Range(low, high) => write!(f, "{low}-{high}")?,
+                Entry::Signal => f.write_str("signal")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_ranges_and_signals() {
+        let list: CodeList = "1, 70-78,signal".parse().unwrap();
+        for code in [Some(1), Some(70), Some(75), Some(78), None] {
+            assert!(list.contains(code), "{code:?}");
+        }
+        for code in [Some(0), Some(2), Some(69), Some(79)] {
+            assert!(!list.contains(code), "{code:?}");
+        }
+        assert!(!"1".parse::<CodeList>().unwrap().contains(None));
+        assert!(!CodeList::default().contains(Some(1)));
+    }
+
+    #[test]
+    fn displays_as_parsed() {
+        for text in ["1", "1,75,130", "70-78,signal", "3-3"] {
+            let list: CodeList = text.parse().unwrap();
+            let shown = list.to_string();
+            assert_eq!(shown.parse::<CodeList>().unwrap(), list, "{text}");
+        }
+        assert_eq!(
+            " 3-3 , SIGNAL".parse::<CodeList>().unwrap().to_string(),
+            "3,signal"
+        );
+    }
+
+    #[test]
+    fn list_table() {
+        // input, how it prints back (None: rejected), codes it holds, codes it doesn't
+        type Row = (
+            &'static str,
+            Option<&'static str>,
+            &'static [Option<i32>],
+            &'static [Option<i32>],
+        );
+        let table: &[Row] = &[
+            ("1", Some("1"), &[Some(1)], &[Some(0), Some(2), None]),
+            (
+                "1, 75 ,130",
+                Some("1,75,130"),
+                &[Some(1), Some(75), Some(130)],
+                &[Some(74)],
+            ),
+            (
+                "70-78",
+                Some("70-78"),
+                &[Some(70), Some(74), Some(78)],
+                &[Some(69), Some(79), None],
+            ),
+            ("SIGNAL,2", Some("signal,2"), &[None, Some(2)], &[Some(1)]),
+            ("5-5", Some("5"), &[Some(5)], &[Some(4)]),
+            ("78-70", None, &[], &[]),
+            ("1,,2", None, &[], &[]),
+            ("", None, &[], &[]),
+            ("-1", None, &[], &[]),
+            ("x", None, &[], &[]),
+            ("1-", None, &[], &[]),
+            ("99999999999", None, &[], &[]),
+        ];
+        for &(input, shown, has, lacks) in table {
+            let parsed = input.parse::<CodeList>();
+            assert_eq!(
+                parsed.as_ref().ok().map(ToString::to_string).as_deref(),
+                shown,
+                "{input:?}: {parsed:?}"
+            );
+            let Ok(list) = parsed else { continue };
+            for &code in has {
+                assert!(list.contains(code), "{input:?} should hold {code:?}");
+            }
+            for &code in lacks {
+                assert!(!list.contains(code), "{input:?} should not hold {code:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn refuses_bad_lists() {
+        for (text, error) in [
+            ("", "empty entry in ``"),
+            ("1,,2", "empty entry in `1,,2`"),
+            ("78-70", "range `78-70` runs backwards"),
+            ("-1", "invalid exit code ``"),
+            ("x", "invalid exit code `x`"),
+            ("4294967295", "invalid exit code `4294967295`"),
+        ] {
+            let got = text.parse::<CodeList>().unwrap_err();
+            assert!(got.starts_with(error), "{text}: {got}");
+        }
+    }
+}
@@ -0,0 +1,455 @@
+//! The config file: defaults for the supervisor's own settings, below the environment
+//! knobs and the flags.
+//!
+//! It is read from `--config PATH`, else `RUSTY_CLAUDE_CONFIG`, else
+//! `$XDG_CONFIG_HOME/rusty-claude/config.toml` (`~/.config/...` without it, and
+//! `%APPDATA%\rusty-claude\config.toml` on Windows), unless `--no-config`. Only the default
+//! location may be missing; an empty `RUSTY_CLAUDE_CONFIG` means no file at all.
+//!
+//! The file is TOML. The settings hold strings, integers, booleans, and arrays of those;
+//! any syntax error, and a setting of another type, fails the run with the line it is on.
+//! Unknown keys only warn, whatever they hold, so a file can be shared with newer versions.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use toml::Spanned;
+
+use crate::duration::parse_duration;
+use crate::settings::Source;
+
+/// A value as written in the file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+    Integer(i64),
+    Bool(bool),
+    String(String),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Integer(_) => "an integer",
+            Value::Bool(_) => "a boolean",
+            Value::String(_) => "a string",
+            Value::Array(_) => "an array",
+        }
+    }
+}
+
+/// What a known key must hold.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    Text,
+    /// A non-negative integer that fits in `u32`.
+    Count,
+    /// A non-negative integer.
+    Number,
+    /// A positive integer.
+    Positive,
+    /// A duration string such as `90s`, or an integer number of seconds.
+    Duration,
+    Flag,
+    /// An array of strings.
+    List,
+}
+
+/// Keys the file may set, each named like the flag (and `--print-config` row) it defaults.
+const KEYS: &[(&str, Kind)] = &[
+    ("cmd", Kind::Text),
+    ("args", Kind::List),
+    ("max_retries", Kind::Count),
+    ("base_delay_ms", Kind::Number),
+    ("max_delay_ms", Kind::Number),
+    ("max_total_ms", Kind::Number),
+    ("initial_delay", Kind::Duration),
+    ("stable_locale", Kind::Flag),
+    ("attempt_timeout_secs", Kind::Positive),
+    ("retry_on_any_error", Kind::Flag),
+    ("force_tee", Kind::Flag),
+    ("require_stdin", Kind::Number),
+    ("patterns", Kind::List),
+    ("fatal_patterns", Kind::List),
+    ("no_default_patterns", Kind::Flag),
+    ("no_default_fatal_patterns", Kind::Flag),
+];
+
+/// One loaded source of defaults whose known keys all hold the right kind of value.
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub path: PathBuf,
+    values: BTreeMap<String, (Value, Source)>,
+    /// One message per key that isn't a setting, each naming where it is.
+    pub unknown: Vec<String>,
+}
+
+impl Config {
+    /// Parse `text`, read from `path`, noting the keys that aren't settings.
+    pub fn parse(path: &Path, text: &str) -> Result<Config, String> {
+        let line = |offset: usize| text[..offset.min(text.len())].matches('\n').count() + 1;
+        let at = |offset: usize| format!("{}:{}", path.display(), line(offset));
+        let document: BTreeMap<Spanned<String>, Spanned<toml::Value>> = toml::from_str(text)
+            .map_err(|e| {
+                let offset = e.span().map_or(0, |span| span.start);
+                format!("{}: {}", at(offset), e.message())
+            })?;
+        // In file order, so the messages about unknown keys are too
+        let mut keys: Vec<(String, usize, toml::Value)> = Vec::new();
+        for (key, value) in document {
+            flatten(
+                key.get_ref(),
+                key.span().start,
+                value.into_inner(),
+                &mut keys,
+            );
+        }
+        keys.sort_by_key(|&(_, offset, _)| offset);
+        let mut entries = Vec::new();
+        let mut unknown = Vec::new();
+        for (key, offset, value) in keys {
+            if !known(&key) {
+                unknown.push(unknown_key(&at(offset), &key, "config file"));
+                continue;
+            }
+            let value = convert(&value).ok_or_else(|| {
+                format!(
+                    "{}: `{key}` holds {}, which is not supported here (only strings, \
+                    integers, booleans, and arrays are)",
+                    at(offset),
+                    toml_type(&value)
+                )
+            })?;
+            entries.push(Entry {
+                name: key.clone(),
+                key,
+                value,
+                source: Source::File(at(offset)),
+                at: at(offset),
+            });
+        }
+        let mut config = Config::checked(path, entries, "config file")?;
+        config.unknown.splice(0..0, unknown);
+        Ok(config)
+    }
+
+    /// Keep the settings among `entries`, failing on the first that holds the wrong kind of
+    /// value. `what` names the file in the messages about the others.
+    pub fn checked(
+        path: &Path,
+        entries: impl IntoIterator<Item = Entry>,
+        what: &str,
+    ) -> Result<Config, String> {
+        let mut values = BTreeMap::new();
+        let mut unknown = Vec::new();
+        for entry in entries {
+            let Entry {
+                key,
+                name,
+                value,
+                source,
+                at,
+            } = entry;
+            match KEYS.iter().find(|(k, _)| *k == key) {
+                Some(&(_, kind)) => {
+                    check(&name, kind, &value).map_err(|e| format!("{at}: {e}"))?;
+                    values.insert(key, (value, source));
+                }
+                None => unknown.push(unknown_key(&at, &name, what)),
+            }
+        }
+        Ok(Config {
+            path: path.to_path_buf(),
+            values,
+            unknown,
+        })
+    }
+}
+
+/// Whether `key` names a setting a source of defaults may hold.
+pub fn known(key: &str) -> bool {
+    KEYS.iter().any(|(k, _)| *k == key)
+}
+
+/// The message about a key that isn't a setting, warned about (or refused under
+/// `--strict-config`) once the sources are loaded.
+pub fn unknown_key(at: &str, name: &str, what: &str) -> String {
+    format!("{at}: unknown key `{name}` in the {what}")
+}
+
+/// One value read from a source of defaults.
+pub struct Entry {
+    /// The setting it would be, named as in `KEYS`.
+    pub key: String,
+    /// The key as written, for messages.
+    pub name: String,
+    pub value: Value,
+    pub source: Source,
+    /// Where to point a message about it.
+    pub at: String,
+}
+
+/// The sources of defaults below the environment; each setting comes from the config
+/// file if it has it, else from the Claude settings block.
+#[derive(Clone, Debug, Default)]
+pub struct Layers {
+    pub file: Option<Config>,
+    pub claude_settings: Option<Config>,
+}
+
+impl Layers {
+    /// The messages about keys that aren't settings, from every source.
+    pub fn unknown(&self) -> impl Iterator<Item = &String> {
+        [&self.file, &self.claude_settings]
+            .into_iter()
+            .flatten()
+            .flat_map(|c| &c.unknown)
+    }
+
+    fn get(&self, key: &str) -> Option<(&Value, Source)> {
+        [&self.file, &self.claude_settings]
+            .into_iter()
+            .flatten()
+            .find_map(|c| {
+                c.values
+                    .get(key)
+                    .map(|(value, source)| (value, source.clone()))
+            })
+    }
+
+    pub fn text(&self, key: &str) -> Option<(String, Source)> {
+        match self.get(key)? {
+            (Value::String(s), src) => Some((s.clone(), src)),
+            _ => None,
+        }
+    }
+
+    pub fn number(&self, key: &str) -> Option<(u64, Source)> {
+        match self.get(key)? {
+            (&Value::Integer(n), src) => Some((u64::try_from(n).ok()?, src)),
+            _ => None,
+        }
+    }
+
+    pub fn flag(&self, key: &str) -> Option<(bool, Source)> {
+        match self.get(key)? {
+            (&Value::Bool(on), src) => Some((on, src)),
+            _ => None,
+        }
+    }
+
+    pub fn duration(&self, key: &str) -> Option<(Duration, Source)> {
+        match self.get(key)? {
+            (Value::String(s), src) => Some((parse_duration(s).ok()?, src)),
+            (&Value::Integer(n), src) => Some((Duration::from_secs(u64::try_from(n).ok()?), src)),
+            _ => None,
+        }
+    }
+
+    pub fn list(&self, key: &str) -> Option<(Vec<String>, Source)> {
+        match self.get(key)? {
+            (Value::Array(items), src) => Some((
+                items
+                    .iter()
+                    .filter_map(|v| match v {
+                        Value::String(s) => Some(s.clone()),
+                        _ => None,
+                    })
+                    .collect(),
+                src,
+            )),
+            _ => None,
+        }
+    }
+}
+
+fn check(key: &str, kind: Kind, value: &Value) -> Result<(), String> {
+    let want = match kind {
+        Kind::Text => "a string",
+        Kind::Count => "a non-negative integer up to 4294967295",
+        Kind::Number => "a non-negative integer",
+        Kind::Positive => "a positive integer",
+        Kind::Duration => "a duration such as \"90s\"",
+        Kind::Flag => "true or false",
+        Kind::List => "an array of strings",
+    };
+    let ok = match (kind, value) {
+        (Kind::Text, Value::String(_)) => true,
+        (Kind::Count, &Value::Integer(n)) => u32::try_from(n).is_ok(),
+        (Kind::Number | Kind::Duration, &Value::Integer(n)) => n >= 0,
+        (Kind::Positive, &Value::Integer(n)) => n > 0,
+        (Kind::Duration, Value::String(s)) => {
+            if let Err(e) = parse_duration(s) {
+                return Err(format!("`{key}`: {e}"));
+            }
+            true
+        }
+        (Kind::Flag, Value::Bool(_)) => true,
+        (Kind::List, Value::Array(items)) => {
+            if let Some(item) = items.iter().find(|v| !matches!(v, Value::String(_))) {
+                return Err(format!(
+                    "`{key}` must be an array of strings, but holds {}",
+                    item.type_name()
+                ));
+            }
+            true
+        }
+        _ => false,
+    };
+    if ok {
+        Ok(())
+    } else {
+        Err(format!("`{key}` must be {want}, not {}", describe(value)))
+    }
+}
+
+fn describe(value: &Value) -> String {
+    match value {
+        Value::Integer(n) => n.to_string(),
+        Value::Bool(on) => on.to_string(),
+        Value::String(_) | Value::Array(_) => value.type_name().to_string(),
+    }
+}
+
+/// Where the config file is, from `--config` or the environment or the default location,
+/// and whether it was asked for (and so must exist). `None` when there isn't one to read.
+pub fn locate(flag: Option<&Path>) -> Option<(PathBuf, bool)> {
+    let explicit = flag
+        .map(Path::to_path_buf)
+        .or_else(|| crate::envvars::var("CONFIG").map(|v| PathBuf::from(v.value)));
+    match explicit {
+        Some(path) if path.as_os_str().is_empty() => None,
+        Some(path) => Some((path, true)),
+        None => default_path().map(|path| (path, false)),
+    }
+}
+
+fn default_path() -> Option<PathBuf> {
+    let dir = if cfg!(windows) {
+        PathBuf::from(env::var_os("APPDATA")?)
+    } else {
+        match env::var_os("XDG_CONFIG_HOME").map(PathBuf::from) {
+            Some(dir) if dir.is_absolute() => dir,
+            _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        }
+    };
+    Some(dir.join("rusty-claude").join("config.toml"))
+}
+
+/// Read the config file `locate` found; a missing file is only an error if it was asked for.
+pub fn load(path: &Path, explicit: bool) -> Result<Option<Config>, String> {
+    match fs::read_to_string(path) {
+        Ok(text) => Config::parse(path, &text).map(Some),
+        Err(e) if !explicit && e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("cannot read config file {}: {e}", path.display())),
+    }
+}
+
+/// `value` under `key`, with the keys of a table that isn't a setting joined to its own by
+/// dots, all placed at `offset` (the table's key, since the parsed values carry no positions of their own).
+fn flatten(
+    key: &str,
+    offset: usize,
+    value: toml::Value,
+    out: &mut Vec<(String, usize, toml::Value)>,
+) {
+    match value {
+        toml::Value::Table(table) if !known(key) => {
+            for (name, value) in table {
+                flatten(&format!("{key}.{name}"), offset, value, out);
+            }
+        }
+        value => out.push((key.to_string(), offset, value)),
+    }
+}
+
+/// A TOML value as a setting's, if it is of a type the settings use.
+fn convert(value: &toml::Value) -> Option<Value> {
+    Some(match value {
+        toml::Value::Integer(n) => Value::Integer(*n),
+        toml::Value::Boolean(on) => Value::Bool(*on),
+        toml::Value::String(s) => Value::String(s.clone()),
+        toml::Value::Array(items) => {
+            Value::Array(items.iter().map(convert).collect::<Option<_>>()?)
+        }
+        toml::Value::Float(_) | toml::Value::Datetime(_) | toml::Value::Table(_) => return None,
+    })
+}
+
+fn toml_type(value: &toml::Value) -> &'static str {
+    match value {
+        toml::Value::Float(_) => "a float",
+        toml::Value::Datetime(_) => "a date-time",
+        toml::Value::Table(_) => "a table",
+        toml::Value::Array(_) => "an array with a float, date-time, or table in it",
+        _ => "a value",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Result<Config, String> {
+        Config::parse(Path::new("c.toml"), text)
+    }
+
+    #[test]
+    fn settings_carry_their_line() {
+        let config = parse(
+            "# defaults\n\
+            max_retries = 2\n\
+            patterns = [\n  'a\\d',\n  \"b\",\n]\n\
+            stable_locale = true\n",
+        )
+        .unwrap();
+        let layers = Layers {
+            file: Some(config),
+            claude_settings: None,
+        };
+        let (n, src) = layers.number("max_retries").unwrap();
+        assert_eq!((n, src.to_string()), (2, "config c.toml:2".to_string()));
+        let (list, src) = layers.list("patterns").unwrap();
+        assert_eq!(list, ["a\\d", "b"]);
+        assert_eq!(src.to_string(), "config c.toml:3");
+        assert_eq!(layers.flag("stable_locale").map(|(on, _)| on), Some(true));
+    }
+
+    #[test]
+    fn unknown_keys_warn_in_file_order() {
+        let config = parse("zeta = 1.5\nmax_retries = 1\n[extra]\nalpha = 1\n").unwrap();
+        assert_eq!(
+            config.unknown,
+            [
+                "c.toml:1: unknown key `zeta` in the config file",
+                "c.toml:3: unknown key `extra.alpha` in the config file",
+            ]
+        );
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        for (text, want) in [
+            ("max_retries = 1\n\nbase_delay_ms = = 1\n", "c.toml:3: "),
+            ("max_retries = 1\nmax_retries = 2\n", "c.toml:2: "),
+            (
+                "max_retries = -1\n",
+                "c.toml:1: `max_retries` must be a non-negative integer up to 4294967295, not -1",
+            ),
+            (
+                "max_retries = 1.5\n",
+                "c.toml:1: `max_retries` holds a float",
+            ),
+            (
+                "stable_locale = {}\n",
+                "c.toml:1: `stable_locale` holds a table",
+            ),
+        ] {
+            let got = parse(text).unwrap_err();
+            assert!(got.starts_with(want), "{text:?}: {got}");
+        }
+    }
+}
@@ -0,0 +1,165 @@
+//! Cooperative cancellation, for preStop hooks and dashboards that must stop one run without
+//! signals that kill the child mid-stream:
+//!
+//! - `drain`: let the attempt in flight finish, then stop instead of retrying
+//! - `abort`: kill the child now and exit 130
+//!
+//! A command arrives as a `{"cmd":"drain"}` line sent to `--observe-socket`, or as the whole
+//! content of `--control-file`, which is read whenever the wrapper checks (while an attempt
+//! runs and during backoff waits). Abort supersedes drain, and neither can be taken back.
+
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::json;
+
+use crate::events::Events;
+
+/// How often a backoff wait checks for a command.
+const POLL: Duration = Duration::from_millis(50);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Command {
+    Drain,
+    Abort,
+}
+
+impl Command {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Command::Drain => "drain",
+            Command::Abort => "abort",
+        }
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// `drain` or `abort`, ignoring case and surrounding whitespace.
+pub fn parse(s: &str) -> Option<Command> {
+    match s.trim().to_ascii_lowercase().as_str() {
+        "drain" => Some(Command::Drain),
+        "abort" => Some(Command::Abort),
+        _ => None,
+    }
+}
+
+/// A `{"cmd":"..."}` frame from an observer.
+#[cfg_attr(not(unix), allow(dead_code))]
+pub fn parse_frame(line: &str) -> Option<Command> {
+    let frame: serde_json::Value = serde_json::from_str(line).ok()?;
+    parse(frame["cmd"].as_str()?)
+}
+
+#[derive(Default)]
+struct State {
+    requested: Option<Command>,
+    /// Where `requested` came from: `socket` or `file`.
+    via: &'static str,
+    /// `requested` has been logged and evented.
+    announced: bool,
+    /// The control file held something other than a command, and we said so.
+    warned: bool,
+}
+
+#[derive(Default)]
+struct Control {
+    file: Option<PathBuf>,
+    quiet: bool,
+    state: Mutex<State>,
+}
+
+static CONTROL: OnceLock<Control> = OnceLock::new();
+
+fn control() -> &'static Control {
+    CONTROL.get_or_init(Control::default)
+}
+
+/// Watch `file` (`--control-file`) for the rest of the run.
+pub fn install(file: Option<PathBuf>, quiet: bool) {
+    let _ = CONTROL.set(Control {
+        file,
+        quiet,
+        ..Control::default()
+    });
+}
+
+/// Record `cmd`, unless an abort was already requested.
+#[cfg_attr(not(unix), allow(dead_code))]
+pub fn request(cmd: Command, via: &'static str) {
+    control().request(cmd, via);
+}
+
+/// Read the control file, announce a command not seen before, and return the one in effect.
+pub fn check(events: &Events) -> Option<Command> {
+    control().check(events)
+}
+
+/// Sleep for `total`, returning early with the command if one arrives, or with none on an
+/// interrupt (see [`crate::interrupt`]).
+pub fn sleep(total: Duration, events: &Events) -> Option<Command> {
+    let deadline = Instant::now() + total;
+    loop {
+        if let Some(cmd) = check(events) {
+            return Some(cmd);
+        }
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() || crate::interrupt::received().is_some() {
+            return None;
+        }
+        thread::sleep(POLL.min(left));
+    }
+}
+
+impl Control {
+    fn request(&self, cmd: Command, via: &'static str) {
+        if let Ok(mut state) = self.state.lock() {
+            if state.requested.is_none_or(|r| r < cmd) {
+                state.requested = Some(cmd);
+                state.via = via;
+                state.announced = false;
+            }
+        }
+    }
+
+    fn check(&self, events: &Events) -> Option<Command> {
+        if let Some(text) = self
+            .file
+            .as_ref()
+            .and_then(|f| std::fs::read_to_string(f).ok())
+        {
+            match parse(&text) {
+                Some(cmd) => self.request(cmd, "file"),
+                None if !text.trim().is_empty() => {
+                    let mut state = self.state.lock().ok()?;
+                    if !state.warned {
+                        state.warned = true;
+                        eprintln!(
+                            "[rusty-claude] warning: --control-file holds `{}`, not drain or \
+                            abort; ignoring it",
+                            text.trim()
+                        );
+                    }
+                }
+                None => {}
+            }
+        }
+        let mut state = self.state.lock().ok()?;
+        let cmd = state.requested?;
+        if !state.announced {
+            state.announced = true;
+            if !self.quiet {
+                eprintln!("[rusty-claude] {cmd} requested via {}", state.via);
+            }
+            events.emit("control", json!({ "cmd": cmd.as_str(), "via": state.via }));
+        }
+        Some(cmd)
+    }
+}
@@ -0,0 +1,101 @@
+//! `--delay-cmd`: ask an external command how long to wait before a retry, so pacing can be
+//! tuned centrally (say, from fleet-wide 429 rates) without new wrapper configs.
+//!
+//! The command gets the decision context in the environment:
+//!
+//! - `RUSTY_CLAUDE_ATTEMPT`: the attempt that just failed (1-based)
+//! - `RUSTY_CLAUDE_RUN_ID`
+//! - `RUSTY_CLAUDE_EXIT_CODE`: its exit code, empty when it was killed by a signal
+//! - `RUSTY_CLAUDE_CLASS`, `RUSTY_CLAUDE_MATCHED`: the error class and retry pattern, empty
+//!   when none matched
+//! - `RUSTY_CLAUDE_ELAPSED_MS`: time since the run began
+//! - `RUSTY_CLAUDE_BUDGET_REMAINING_MS`: what is left of `--max-total-ms`, empty without one
+//! - `RUSTY_CLAUDE_DEFAULT_DELAY_MS`: the built-in backoff it replaces
+//!
+//! Its stdout must be a duration: `750` (milliseconds), `750ms`, `2.5s`, `1m30s`. A non-zero
+//! exit, anything else on stdout, or running past `--delay-cmd-timeout` (after which it is
+//! killed) leaves the built-in backoff in place.
+
+use std::io::{self, Read};
+use std::process::Stdio;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::classes::ErrorClass;
+use crate::duration::{format_duration, parse_duration};
+use crate::hook::{failure, shell, wait_until};
+
+/// What the failed attempt and the run look like when the command is asked.
+pub struct Context<'a> {
+    pub attempt: u32,
+    pub run_id: &'a str,
+    pub code: Option<i32>,
+    pub class: Option<ErrorClass>,
+    pub matched: Option<&'a str>,
+    pub elapsed: Duration,
+    pub budget_left: Option<Duration>,
+    pub default_ms: u64,
+}
+
+/// A bare number is milliseconds; anything else is a duration like `2.5s`.
+pub fn parse_output(text: &str) -> Option<u64> {
+    let text = text.trim();
+    if let Ok(ms) = text.parse::<u64>() {
+        return Some(ms);
+    }
+    parse_duration(text).ok().map(|d| d.as_millis() as u64)
+}
+
+/// Run `cmd` and return the delay it printed, or why it could not be used.
+pub fn delay_ms(cmd: &str, ctx: &Context, timeout: Duration) -> Result<u64, String> {
+    let ms = |d: Duration| (d.as_millis() as u64).to_string();
+    let mut command = shell(cmd);
+    // Its own group, so a timeout also kills whatever the shell started
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    let mut child = command
+        .env("RUSTY_CLAUDE_ATTEMPT", ctx.attempt.to_string())
+        .env(crate::runid::ENV_VAR, ctx.run_id)
+        .env(
+            "RUSTY_CLAUDE_EXIT_CODE",
+            ctx.code.map(|c| c.to_string()).unwrap_or_default(),
+        )
+        .env(
+            "RUSTY_CLAUDE_CLASS",
+            ctx.class.map(ErrorClass::as_str).unwrap_or_default(),
+        )
+        .env("RUSTY_CLAUDE_MATCHED", ctx.matched.unwrap_or_default())
+        .env("RUSTY_CLAUDE_ELAPSED_MS", ms(ctx.elapsed))
+        .env(
+            "RUSTY_CLAUDE_BUDGET_REMAINING_MS",
+            ctx.budget_left.map(ms).unwrap_or_default(),
+        )
+        .env("RUSTY_CLAUDE_DEFAULT_DELAY_MS", ctx.default_ms.to_string())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("could not start: {e}"))?;
+    // Read on a thread so a grandchild holding stdout open can't block us past the deadline
+    let (tx, rx) = mpsc::channel();
+    if let Some(mut stdout) = child.stdout.take() {
+        thread::spawn(move || {
+            let mut out = Vec::new();
+            let _ = tx.send(stdout.read_to_end(&mut out).map(|_| out));
+        });
+    }
+    let deadline = Instant::now() + timeout;
+    let timed_out = || format!("timed out after {}", format_duration(timeout));
+    let status = wait_until(&mut child, deadline)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(timed_out)?;
+    if !status.success() {
+        return Err(failure(status));
+    }
+    let out = rx
+        .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+        .map_err(|_| timed_out())?
+        .map_err(|e: io::Error| e.to_string())?;
+    let text = String::from_utf8_lossy(&out);
+    parse_output(&text).ok_or_else(|| format!("printed `{}`, not a duration", text.trim()))
+}
@@ -0,0 +1,316 @@
+//! `rusty-claude [FLAGS] doctor`: check the environment a run would start in, without
+//! starting one. The child command is resolved exactly as a run would resolve it, located
+//! and asked for `--version`; every retry and fatal pattern is compiled; and the effective
+//! settings are listed with where each came from.
+//!
+//! A missing or non-executable command, a user pattern that doesn't compile, or a config
+//! that doesn't load makes it exit non-zero, so it can gate a CI job before the real run.
+
+use std::env;
+use std::path::Path;
+use std::time::Duration;
+
+use clap::{ArgMatches, Args};
+use serde_json::json;
+
+use rusty_claude::patterns::{
+    build_user_pattern, regex_error, DEFAULT_FATAL_PATTERNS, DEFAULT_RETRY_PATTERNS,
+};
+
+use crate::{config, exit_codes, integrity, pattern_file, resolve, schema, version};
+use crate::{Cli, Commands};
+
+/// How long `<cmd> --version` may take; shorter than a run's probe, since nothing waits on
+/// the answer.
+const VERSION_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Args, Debug)]
+pub struct DoctorArgs {
+    /// Print the report as one JSON document
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Ok,
+    Warn,
+    Fail,
+}
+
+impl Status {
+    fn as_str(self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Warn => "warn",
+            Status::Fail => "fail",
+        }
+    }
+}
+
+/// One finding, with the exit code it stands for if it fails.
+struct Check {
+    status: Status,
+    message: String,
+    code: i32,
+}
+
+#[derive(Default)]
+struct Report {
+    checks: Vec<Check>,
+}
+
+impl Report {
+    fn ok(&mut self, message: String) {
+        self.push(Status::Ok, message, 0);
+    }
+
+    fn warn(&mut self, message: String) {
+        self.push(Status::Warn, message, 0);
+    }
+
+    fn fail(&mut self, message: String, code: i32) {
+        self.push(Status::Fail, message, code);
+    }
+
+    fn push(&mut self, status: Status, message: String, code: i32) {
+        self.checks.push(Check {
+            status,
+            message,
+            code,
+        });
+    }
+
+    /// The code of the first failure, or 0.
+    fn exit_code(&self) -> i32 {
+        self.checks
+            .iter()
+            .find(|c| c.status == Status::Fail)
+            .map_or(0, |c| c.code)
+    }
+}
+
+/// Whether the file at `path` can be run.
+fn executable(path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        path.metadata()
+            .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+    }
+    #[cfg(not(unix))]
+    {
+        path.is_file()
+    }
+}
+
+/// Why a user pattern doesn't compile, as a run would put it.
+fn pattern_error(e: &regex::Error) -> String {
+    match e {
+        regex::Error::CompiledTooBig(limit) => {
+            format!("too expensive to compile (exceeds the {limit}-byte limit for user patterns)")
+        }
+        e => regex_error(e),
+    }
+}
+
+/// `rusty-claude doctor`; returns the process exit code.
+pub fn run(mut cli: Cli, matches: &ArgMatches) -> i32 {
+    let Some(Commands::Doctor(args)) = cli.command.take() else {
+        unreachable!("dispatched on the doctor subcommand");
+    };
+    let mut report = Report::default();
+    crate::set_env_policy(&cli);
+    let layers = match crate::load_layers(&cli) {
+        Ok(layers) => layers,
+        Err(e) => {
+            report.fail(format!("config: {e}"), exit_codes::CONFIG_ERROR);
+            config::Layers::default()
+        }
+    };
+    let settings = crate::resolve_settings(&mut cli, matches, &layers);
+
+    // The command, as a run resolves it
+    let cmd = crate::run::resolve_cmd(&cli);
+    let path = resolve::locate(&cmd).filter(|p| p.is_file());
+    let target = path.as_ref().and_then(|p| p.canonicalize().ok());
+    let runnable = path.as_deref().is_some_and(executable);
+    match &path {
+        None => report.fail(
+            format!("command: `{cmd}` was not found"),
+            exit_codes::NOT_FOUND,
+        ),
+        Some(p) if !runnable => report.fail(
+            format!(
+                "command: `{cmd}` is {}, which is not executable",
+                p.display()
+            ),
+            exit_codes::CANNOT_EXECUTE,
+        ),
+        Some(p) => {
+            let absolute = target.clone().unwrap_or_else(|| {
+                env::current_dir()
+                    .map(|dir| dir.join(p))
+                    .unwrap_or_else(|_| p.clone())
+            });
+            let via = if *p == absolute {
+                String::new()
+            } else {
+                format!(" (found as {})", p.display())
+            };
+            report.ok(format!(
+                "command: `{cmd}` is {}{via}, executable",
+                absolute.display()
+            ));
+        }
+    }
+    if let Some(found) = resolve::self_wrap(&cmd).filter(|_| !cli.allow_self_wrap) {
+        report.fail(
+            format!(
+                "command: `{cmd}` is rusty-claude itself ({}); point --cmd at the real CLI, or \
+                pass --allow-self-wrap if the nesting is intended",
+                found.display()
+            ),
+            exit_codes::CONFIG_ERROR,
+        );
+    }
+    if !cli.expect_cmd_sha256.is_empty() && path.is_some() {
+        match integrity::verify(&cmd, &cli.expect_cmd_sha256) {
+            Ok(pin) => report.ok(format!(
+                "command: {} matches --expect-cmd-sha256",
+                pin.path.display()
+            )),
+            Err(e) => report.fail(
+                format!("command: --expect-cmd-sha256: {e}"),
+                exit_codes::INTEGRITY_MISMATCH,
+            ),
+        }
+    }
+
+    // Its version
+    let banner = runnable.then(|| version::banner(&cmd, VERSION_TIMEOUT));
+    let found_version = match &banner {
+        Some(Ok(output)) => version::parse_banner(output),
+        _ => None,
+    };
+    match (&banner, found_version) {
+        (None, _) => {}
+        (Some(Err(e)), _) => report.warn(format!("version: {e}")),
+        (Some(Ok(output)), None) => report.warn(format!(
+            "version: no version in `{cmd} --version` output: {:?}",
+            output.trim()
+        )),
+        (Some(Ok(output)), Some(found)) => {
+            report.ok(format!(
+                "version: `{cmd} --version` printed {:?}",
+                output.trim()
+            ));
+            if let Some(min) = cli.min_child_version.filter(|&min| found < min) {
+                let message = format!("version: {found} is older than --min-child-version {min}");
+                if cli.enforce_min_child_version {
+                    report.fail(message, exit_codes::CHILD_TOO_OLD);
+                } else {
+                    report.warn(message);
+                }
+            }
+        }
+    }
+
+    // Every pattern a run would compile, built in or not
+    let mut patterns: Vec<(&str, String, String, Option<String>)> = Vec::new();
+    for &(p, _) in DEFAULT_RETRY_PATTERNS
+        .iter()
+        .filter(|_| !cli.no_default_patterns)
+    {
+        patterns.push(("retry", p.to_string(), "built-in".to_string(), None));
+    }
+    for &p in DEFAULT_FATAL_PATTERNS
+        .iter()
+        .filter(|_| !cli.no_default_fatal_patterns)
+    {
+        patterns.push(("fatal", p.to_string(), "built-in".to_string(), None));
+    }
+    let from_file = match cli.patterns_file.as_deref().map(pattern_file::read) {
+        Some(Ok(list)) => list,
+        Some(Err(e)) => {
+            report.fail(format!("patterns: {e}"), exit_codes::CONFIG_ERROR);
+            Vec::new()
+        }
+        None => Vec::new(),
+    };
+    let (retry, fatal) = crate::policy::user_patterns(&cli, from_file);
+    let user: Vec<_> = retry
+        .into_iter()
+        .map(|(p, source)| ("retry", p, source))
+        .chain(fatal.into_iter().map(|(p, source)| ("fatal", p, source)))
+        .collect();
+    let user_count = user.len();
+    for (kind, p, source) in user {
+        let error = build_user_pattern(&p).err().map(|e| pattern_error(&e));
+        if let Some(why) = &error {
+            report.fail(
+                format!("patterns: {kind} pattern `{p}` from {source} does not compile: {why}"),
+                exit_codes::CONFIG_ERROR,
+            );
+        }
+        patterns.push((kind, p, source, error));
+    }
+    let broken = patterns.iter().filter(|p| p.3.is_some()).count();
+    if broken == 0 {
+        report.ok(format!(
+            "patterns: {} built in and {user_count} of your own all compile",
+            patterns.len() - user_count
+        ));
+    }
+
+    let code = report.exit_code();
+    if args.json {
+        let doc = json!({
+            "schema": schema::DOCTOR,
+            "ok": code == 0,
+            "exit_code": code,
+            "cmd": cmd,
+            "path": path.as_ref().map(|p| p.display().to_string()),
+            "resolved_path": target.as_ref().map(|p| p.display().to_string()),
+            "executable": runnable,
+            "version_output": banner.and_then(Result::ok).map(|o| o.trim().to_string()),
+            "version": found_version.map(|v| v.to_string()),
+            "patterns": patterns.iter().map(|(kind, p, source, error)| json!({
+                "kind": kind,
+                "pattern": p,
+                "source": source,
+                "error": error,
+            })).collect::<Vec<_>>(),
+            "settings": settings.iter().map(|s| json!({
+                "key": s.key,
+                "value": s.value,
+                "source": s.source.to_string(),
+            })).collect::<Vec<_>>(),
+            "checks": report.checks.iter().map(|c| json!({
+                "status": c.status.as_str(),
+                "message": c.message,
+            })).collect::<Vec<_>>(),
+        });
+        println!("{doc:#}");
+        return code;
+    }
+
+    for check in &report.checks {
+        println!("  {:<4}  {}", check.status.as_str(), check.message);
+    }
+    println!("effective settings:");
+    for line in crate::settings::render(&settings).lines() {
+        println!("  {line}");
+    }
+    let failed = report
+        .checks
+        .iter()
+        .filter(|c| c.status == Status::Fail)
+        .count();
+    if failed == 0 {
+        println!("no problems found");
+    } else {
+        println!("{failed} problem(s) found");
+    }
+    code
+}
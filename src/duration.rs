@@ -0,0 +1,127 @@
+//! Parsing and display of human-friendly durations used by the time-based flags.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// Parse a duration such as `500ms`, `30s`, `5m`, `1h30m`, or a bare number of seconds.
+pub fn parse_duration(input: &str) -> Result<Duration, String> {
+    let s = input.trim();
+    if s.is_empty() {
+        return Err("empty duration".to_string());
+    }
+    if let Ok(secs) = s.parse::<u64>() {
+        return Ok(Duration::from_secs(secs));
+    }
+
+    let mut total = Duration::ZERO;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        if digits == 0 {
+            return Err(format!("invalid duration `{input}`: expected a number"));
+        }
+        let value: f64 = rest[..digits]
+            .parse()
+            .map_err(|_| format!("invalid duration `{input}`"))?;
+        rest = &rest[digits..];
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let unit_ms = match &rest[..unit_len] {
+            "ms" => 1.0,
+            "s" | "sec" | "secs" => 1_000.0,
+            "m" | "min" | "mins" => 60_000.0,
+            "h" | "hr" | "hrs" => 3_600_000.0,
+            "" => return Err(format!("invalid duration `{input}`: missing unit")),
+            unit => return Err(format!("invalid duration `{input}`: unknown unit `{unit}`")),
+        };
+        rest = &rest[unit_len..];
+        total += Duration::from_millis((value * unit_ms).round() as u64);
+    }
+    Ok(total)
+}
+
+/// Set by `--raw-durations`: messages show whole milliseconds, as they used to.
+static RAW: AtomicBool = AtomicBool::new(false);
+
+pub fn set_raw(raw: bool) {
+    RAW.store(raw, Ordering::Relaxed);
+}
+
+/// Display used in every supervisor message, e.g. `850ms`, `18.3s`, `2m 05s`, `1h 12m`
+/// (under `--raw-durations` always milliseconds, `18273ms`). Each magnitude rounds to its
+/// last digit, moving up a unit when that carries over, so `59.97s` reads `1m 00s`.
+/// Machine-readable output carries exact `_ms` numbers instead.
+pub fn format_duration(d: Duration) -> String {
+    let ms = d.as_millis() as u64;
+    if ms < 1000 || RAW.load(Ordering::Relaxed) {
+        return format!("{ms}ms");
+    }
+    let tenths = (ms + 50) / 100;
+    if tenths < 600 {
+        return if tenths.is_multiple_of(10) {
+            format!("{}s", tenths / 10)
+        } else {
+            format!("{}.{}s", tenths / 10, tenths % 10)
+        };
+    }
+    let secs = (ms + 500) / 1000;
+    if secs < 3600 {
+        return format!("{}m {:02}s", secs / 60, secs % 60);
+    }
+    let mins = (ms + 30_000) / 60_000;
+    format!("{}h {:02}m", mins / 60, mins % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `--raw-durations` is process-wide, so only the self-test exercises it
+
+    #[test]
+    fn display() {
+        for (ms, want) in [
+            (0, "0ms"),
+            (999, "999ms"),
+            (1_000, "1s"),
+            (1_049, "1s"),
+            (1_050, "1.1s"),
+            (18_273, "18.3s"),
+            (59_949, "59.9s"),
+            (59_950, "1m 00s"),
+            (60_000, "1m 00s"),
+            (125_000, "2m 05s"),
+            (3_599_499, "59m 59s"),
+            (3_599_500, "1h 00m"),
+            (4_320_000, "1h 12m"),
+            (90_000_000, "25h 00m"),
+        ] {
+            assert_eq!(format_duration(Duration::from_millis(ms)), want, "{ms}ms");
+        }
+    }
+
+    #[test]
+    fn parsing() {
+        for (input, want) in [
+            ("30", 30_000),
+            ("500ms", 500),
+            (" 30s ", 30_000),
+            ("1.5s", 1_500),
+            ("5m", 300_000),
+            ("1h30m", 5_400_000),
+            ("2hrs15mins", 8_100_000),
+        ] {
+            assert_eq!(
+                parse_duration(input),
+                Ok(Duration::from_millis(want)),
+                "{input}"
+            );
+        }
+        for input in ["", "s", "5x", "1.2.3s", "10 s"] {
+            assert!(parse_duration(input).is_err(), "{input}");
+        }
+    }
+}
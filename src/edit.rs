@@ -0,0 +1,71 @@
+//! `--edit-on-retry`: before a retry, open the captured stdin in `$VISUAL` or `$EDITOR` on
+//! the controlling terminal so the prompt can be fixed, and replay the edited input on every
+//! later attempt.
+//!
+//! stdin is a pipe and stdout may be one too, so the editor is attached to the controlling
+//! terminal directly. Saving the file unchanged or quitting the editor with an error keeps
+//! the original input; without a terminal `--edit-no-tty` decides.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use regex::Regex;
+
+use crate::shutdown::{self, Priority};
+use crate::tty;
+
+/// Whether a retry should stop for editing: always without a pattern, otherwise only when
+/// the failed attempt's output matches it.
+pub fn wanted(pattern: Option<&Regex>, output: &str) -> bool {
+    pattern.is_none_or(|re| re.is_match(output))
+}
+
+/// `$VISUAL`, then `$EDITOR`, then the platform's stock editor. The value is split on
+/// whitespace, so `code --wait` works.
+fn editor() -> Vec<String> {
+    let configured = ["VISUAL", "EDITOR"]
+        .iter()
+        .filter_map(|k| std::env::var(k).ok())
+        .find(|v| !v.trim().is_empty());
+    match configured {
+        Some(v) => v.split_whitespace().map(str::to_string).collect(),
+        None if cfg!(windows) => vec!["notepad".to_string()],
+        None => vec!["vi".to_string()],
+    }
+}
+
+/// Let the user edit `input` in a temporary file; `Ok(None)` means keep the original.
+pub fn edit(input: &[u8]) -> io::Result<Option<Vec<u8>>> {
+    let path = std::env::temp_dir().join(format!(
+        "rusty-claude-prompt-{}-{:08x}.txt",
+        std::process::id(),
+        rand::random::<u32>()
+    ));
+    fs::write(&path, input)?;
+    let removed = path.clone();
+    let removal = shutdown::register("prompt file", Priority::Normal, move || {
+        let _ = fs::remove_file(removed);
+    });
+    let result = run_editor(&path).and_then(|ok| {
+        let edited = fs::read(&path)?;
+        Ok((ok && edited != input).then_some(edited))
+    });
+    drop(removal);
+    result
+}
+
+/// Run the editor on the terminal; `Ok(false)` when it exited unsuccessfully.
+fn run_editor(path: &Path) -> io::Result<bool> {
+    let (tty_in, tty_out) = tty::terminal().map_err(io::Error::other)?.handles()?;
+    let argv = editor();
+    let status = Command::new(&argv[0])
+        .args(&argv[1..])
+        .arg(path)
+        .stdin(Stdio::from(tty_in))
+        .stdout(Stdio::from(tty_out.try_clone()?))
+        .stderr(Stdio::from(tty_out))
+        .status()?;
+    Ok(status.success())
+}
@@ -0,0 +1,174 @@
+//! Lookup of the supervisor's own environment knobs.
+//!
+//! Every knob is read as `RUSTY_CLAUDE_<NAME>`, falling back to the deprecated
+//! `CLAUDE_SUPERVISOR_<NAME>` spelling. The new name wins when both are set, and using only
+//! the old one prints a deprecation warning once per variable.
+//!
+//! `--no-env` ignores every knob and `--env-only-prefix` honors a single spelling; both only
+//! affect the supervisor's own settings, never the environment passed to the child.
+
+use std::collections::HashSet;
+use std::env;
+use std::sync::{Mutex, OnceLock};
+
+pub const PREFIX: &str = "RUSTY_CLAUDE_";
+pub const LEGACY_PREFIX: &str = "CLAUDE_SUPERVISOR_";
+
+static WARNED: Mutex<Option<HashSet<String>>> = Mutex::new(None);
+static POLICY: OnceLock<Policy> = OnceLock::new();
+
+/// Which knob variables are honored, chosen once per invocation.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Policy {
+    #[default]
+    All,
+    /// `--no-env`
+    None,
+    /// `--env-only-prefix`: just one of `PREFIX` and `LEGACY_PREFIX`.
+    OnlyPrefix(String),
+}
+
+impl Policy {
+    fn honors(&self, prefix: &str) -> bool {
+        match self {
+            Policy::All => true,
+            Policy::None => false,
+            Policy::OnlyPrefix(only) => only == prefix,
+        }
+    }
+
+    /// The flag responsible for ignoring variables, for `--print-config`.
+    pub fn flag(&self) -> &'static str {
+        match self {
+            Policy::All => "",
+            Policy::None => "--no-env",
+            Policy::OnlyPrefix(_) => "--env-only-prefix",
+        }
+    }
+}
+
+/// Set the policy for the rest of the process; the first call wins.
+pub fn set_policy(policy: Policy) {
+    let _ = POLICY.set(policy);
+}
+
+pub fn policy() -> &'static Policy {
+    POLICY.get_or_init(Policy::default)
+}
+
+/// A knob value together with the variable name that supplied it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EnvValue {
+    pub name: String,
+    pub value: String,
+}
+
+impl EnvValue {
+    pub fn is_legacy(&self) -> bool {
+        self.name.starts_with(LEGACY_PREFIX)
+    }
+}
+
+/// Resolve the knob `suffix` (e.g. `MAX_RETRIES`) from the process environment.
+pub fn var(suffix: &str) -> Option<EnvValue> {
+    let found = lookup(suffix, policy(), |name| env::var(name).ok());
+    if let Some(v) = found.as_ref().filter(|v| v.is_legacy()) {
+        warn_deprecated(&v.name, suffix);
+    }
+    found
+}
+
+/// A set variable for `suffix` that the policy made `var` skip.
+pub fn ignored(suffix: &str) -> Option<EnvValue> {
+    if var(suffix).is_some() {
+        return None;
+    }
+    lookup(suffix, &Policy::All, |name| env::var(name).ok())
+}
+
+/// Precedence rule shared by `var`, with the environment access injected.
+pub fn lookup(
+    suffix: &str,
+    policy: &Policy,
+    get: impl Fn(&str) -> Option<String>,
+) -> Option<EnvValue> {
+    [PREFIX, LEGACY_PREFIX]
+        .iter()
+        .filter(|prefix| policy.honors(prefix))
+        .find_map(|prefix| {
+            let name = format!("{prefix}{suffix}");
+            get(&name).map(|value| EnvValue { name, value })
+        })
+}
+
+fn warn_deprecated(legacy_name: &str, suffix: &str) {
+    if first_use(legacy_name) {
+        eprintln!(
+            "[rusty-claude] warning: {legacy_name} is deprecated; use {PREFIX}{suffix} instead"
+        );
+    }
+}
+
+/// Whether `legacy_name` has not been warned about yet, marking it warned.
+fn first_use(legacy_name: &str) -> bool {
+    let mut warned = WARNED.lock().unwrap_or_else(|e| e.into_inner());
+    warned
+        .get_or_insert_with(HashSet::new)
+        .insert(legacy_name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `lookup` over a fixed environment.
+    fn resolve(suffix: &str, policy: &Policy, vars: &[(&str, &str)]) -> Option<EnvValue> {
+        lookup(suffix, policy, |name| {
+            vars.iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, v)| v.to_string())
+        })
+    }
+
+    #[test]
+    fn the_new_name_wins() {
+        let both = [
+            ("CLAUDE_SUPERVISOR_MAX_RETRIES", "1"),
+            ("RUSTY_CLAUDE_MAX_RETRIES", "2"),
+        ];
+        let v = resolve("MAX_RETRIES", &Policy::All, &both).unwrap();
+        assert_eq!(
+            (v.name.as_str(), v.value.as_str()),
+            ("RUSTY_CLAUDE_MAX_RETRIES", "2")
+        );
+        assert!(!v.is_legacy());
+        let v = resolve("MAX_RETRIES", &Policy::All, &both[..1]).unwrap();
+        assert_eq!(v.value, "1");
+        assert!(v.is_legacy());
+        assert_eq!(resolve("BASE_MS", &Policy::All, &both), None);
+    }
+
+    #[test]
+    fn policies_pick_the_spellings_read() {
+        let both = [
+            ("CLAUDE_SUPERVISOR_BASE_MS", "10"),
+            ("RUSTY_CLAUDE_BASE_MS", "20"),
+        ];
+        assert_eq!(resolve("BASE_MS", &Policy::None, &both), None);
+        let legacy = Policy::OnlyPrefix(LEGACY_PREFIX.to_string());
+        assert_eq!(resolve("BASE_MS", &legacy, &both).unwrap().value, "10");
+        let new = Policy::OnlyPrefix(PREFIX.to_string());
+        assert_eq!(resolve("BASE_MS", &new, &both[..1]), None);
+        assert_eq!(
+            [Policy::All, Policy::None, new].map(|p| p.flag()),
+            ["", "--no-env", "--env-only-prefix"]
+        );
+    }
+
+    #[test]
+    fn each_legacy_name_is_warned_about_once() {
+        assert!(first_use("CLAUDE_SUPERVISOR_TEST_ONCE"));
+        assert!(!first_use("CLAUDE_SUPERVISOR_TEST_ONCE"));
+        assert!(first_use("CLAUDE_SUPERVISOR_TEST_OTHER"));
+    }
+}
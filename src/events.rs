@@ -0,0 +1,80 @@
+//! `--json-events`: supervisor lifecycle events as JSON lines, so orchestration above us can
+//! gate on them instead of scraping stderr.
+
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::{Map, Value};
+
+use crate::observe::Hub;
+use crate::schema;
+
+/// Where events go: the `--json-events` sink and/or `--observe-socket` clients. With
+/// neither, `emit` is a no-op.
+pub struct Events {
+    sink: Option<Mutex<Box<dyn Write + Send>>>,
+    observers: Option<Arc<Hub>>,
+    /// Stamped on every event so they can be joined with the run's other outputs.
+    run_id: String,
+}
+
+impl Events {
+    pub fn disabled(run_id: &str) -> Self {
+        Events {
+            sink: None,
+            observers: None,
+            run_id: run_id.to_string(),
+        }
+    }
+
+    /// Also publish every event to observer-socket clients.
+    pub fn observe(&mut self, hub: Arc<Hub>) {
+        self.observers = Some(hub);
+    }
+
+    /// Append to `path`, or write to stderr when it is `-`.
+    pub fn open(path: &Path, run_id: &str) -> io::Result<Self> {
+        let sink: Box<dyn Write + Send> = if path == Path::new("-") {
+            Box::new(io::stderr())
+        } else {
+            Box::new(OpenOptions::new().create(true).append(true).open(path)?)
+        };
+        Ok(Events {
+            sink: Some(Mutex::new(sink)),
+            observers: None,
+            run_id: run_id.to_string(),
+        })
+    }
+
+    /// Write one event line: `event`, a unix-millisecond `ts` and the `run_id`, followed by
+    /// `fields` (a JSON object). Write errors are ignored so observers can't break supervision.
+    pub fn emit(&self, event: &str, fields: Value) {
+        if self.sink.is_none() && self.observers.is_none() {
+            return;
+        }
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        let mut line = Map::new();
+        line.insert("schema".into(), schema::EVENTS.into());
+        line.insert("event".into(), event.into());
+        line.insert("ts".into(), ts.into());
+        line.insert("run_id".into(), self.run_id.clone().into());
+        if let Value::Object(fields) = fields {
+            line.extend(fields);
+        }
+        if let Some(Ok(mut w)) = self.sink.as_ref().map(|s| s.lock()) {
+            let _ = writeln!(w, "{}", Value::Object(line.clone()));
+            let _ = w.flush();
+        }
+        if let Some(hub) = &self.observers {
+            let mut frame = Map::new();
+            frame.insert("type".into(), "event".into());
+            frame.extend(line);
+            hub.publish(Value::Object(frame).to_string());
+        }
+    }
+}
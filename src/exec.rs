@@ -0,0 +1,562 @@
+//! [`Supervisor`]: the retry loop for Rust tooling that runs the CLI itself, without the
+//! `rusty-claude` binary in between. Each attempt's output is captured (and optionally
+//! forwarded as it arrives), judged by [`should_retry`], and retried after the backoff or
+//! the wait the output asks for.
+//!
+//! It covers the core of a piped run: patterns, exit codes, backoff, and `Retry-After`. The
+//! binary's other features (timeouts, budgets, hooks, artifacts, events) stay in the binary,
+//! which runs its attempts through [`Supervisor::drive`], the loop under [`Supervisor::run`].
+//!
+//! One `Supervisor` may run several commands at once from different threads. They share a
+//! pause: when one attempt's output asks for a wait, the others hold their next attempt
+//! until it is over instead of each running into the same limit.
+
+use std::io::{self, Read, Write};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::{Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use crate::backoff::{Backoff, Strategy};
+use crate::patterns::{should_retry, Output, Patterns, RetryDecision};
+
+/// When to retry and how long to wait in between.
+pub struct RetryPolicy {
+    /// Retries after the first attempt.
+    pub max_retries: u32,
+    pub backoff: Backoff,
+    pub patterns: Patterns,
+    /// Retry every failure, not only those the patterns or exit-code lists pick out.
+    pub retry_on_any: bool,
+    /// A longer wait asked for by the output is ignored in favor of the backoff.
+    pub max_retry_after: Duration,
+    /// Seed the backoff jitter, so the same runs wait the same delays.
+    pub jitter_seed: Option<u64>,
+}
+
+impl Default for RetryPolicy {
+    /// The binary's defaults: 6 retries, exponential backoff from 500ms capped at 20s, the
+    /// built-in patterns, and `Retry-After` waits of up to 10 minutes.
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 6,
+            backoff: Backoff {
+                strategy: Strategy::Exponential,
+                base_ms: 500,
+                cap_ms: 20_000,
+                multiplier: 2.0,
+            },
+            patterns: Patterns::defaults(true, true),
+            retry_on_any: false,
+            max_retry_after: Duration::from_secs(600),
+            jitter_seed: None,
+        }
+    }
+}
+
+/// One finished attempt.
+pub struct Attempt {
+    pub status: ExitStatus,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub duration: Duration,
+    /// How the failure was judged; `None` for the attempt that succeeded.
+    pub decision: Option<RetryDecision>,
+    /// The wait before the next attempt, if it was retried.
+    pub delay: Option<Duration>,
+}
+
+/// What one step of [`Supervisor::drive`] decided.
+pub enum Step<T> {
+    /// Run another attempt.
+    Retry,
+    /// End the run with this.
+    Stop(T),
+}
+
+/// Every attempt of a run, the last one deciding how it went.
+pub struct RunReport {
+    pub attempts: Vec<Attempt>,
+}
+
+impl RunReport {
+    /// The attempt the run ended with.
+    pub fn last(&self) -> &Attempt {
+        self.attempts
+            .last()
+            .expect("a run has at least one attempt")
+    }
+
+    pub fn status(&self) -> ExitStatus {
+        self.last().status
+    }
+
+    pub fn success(&self) -> bool {
+        self.status().success()
+    }
+}
+
+/// Runs a command under a [`RetryPolicy`].
+///
+/// ```no_run
+/// use std::process::Command;
+/// use rusty_claude::{RetryPolicy, Supervisor};
+///
+/// let mut cmd = Command::new("claude");
+/// cmd.args(["-p", "--output-format", "json"]);
+/// let report = Supervisor::new(RetryPolicy::default())
+///     .run(cmd, Some(b"Summarize README.md"))
+///     .unwrap();
+/// println!("{} attempt(s)", report.attempts.len());
+/// ```
+pub struct Supervisor {
+    policy: RetryPolicy,
+    tee: bool,
+    /// Until when every run holds its next attempt, after an output asked for a wait.
+    paused_until: Mutex<Option<Instant>>,
+    /// Every run's backoff draws, in the order they are made.
+    rng: Mutex<StdRng>,
+}
+
+impl Supervisor {
+    pub fn new(policy: RetryPolicy) -> Self {
+        let rng = match policy.jitter_seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_rng(&mut rand::rng()),
+        };
+        Supervisor {
+            policy,
+            tee: false,
+            paused_until: Mutex::new(None),
+            rng: Mutex::new(rng),
+        }
+    }
+
+    pub fn policy(&self) -> &RetryPolicy {
+        &self.policy
+    }
+
+    /// The policy's backoff after `attempt` failed, following `previous` if any, drawn from
+    /// the jitter every run of this supervisor shares.
+    pub fn backoff_ms(&self, attempt: u32, previous: Option<u64>) -> u64 {
+        let mut rng = self.rng.lock().unwrap_or_else(PoisonError::into_inner);
+        self.policy.backoff.delay_ms(attempt, previous, &mut *rng)
+    }
+
+    /// The retry loop, for callers that spawn and judge each attempt themselves: calls
+    /// `attempt` with 0, 1, 2, ... until it returns [`Step::Stop`], holding each call while
+    /// another run's pause lasts. The waits between attempts are up to `attempt`.
+    pub fn drive<T>(&self, mut attempt: impl FnMut(u32) -> io::Result<Step<T>>) -> io::Result<T> {
+        let mut n = 0;
+        loop {
+            self.hold();
+            if let Step::Stop(done) = attempt(n)? {
+                return Ok(done);
+            }
+            n += 1;
+        }
+    }
+
+    /// Also forward each attempt's output to our own stdout and stderr as it arrives.
+    pub fn tee(mut self, tee: bool) -> Self {
+        self.tee = tee;
+        self
+    }
+
+    /// Run `cmd` until an attempt succeeds, fails in a way the policy doesn't retry, or the
+    /// retries run out, feeding `input` to every attempt's stdin. Its stdio is replaced.
+    pub fn run(&self, cmd: Command, input: Option<&[u8]>) -> io::Result<RunReport> {
+        self.run_with(cmd, input, |_| {})
+    }
+
+    /// [`run`](Self::run), calling `on_retry` with each attempt that is about to be retried,
+    /// before the wait.
+    pub fn run_with(
+        &self,
+        mut cmd: Command,
+        input: Option<&[u8]>,
+        mut on_retry: impl FnMut(&Attempt),
+    ) -> io::Result<RunReport> {
+        let policy = &self.policy;
+        let mut attempts = Vec::new();
+        let mut previous = None;
+        self.drive(|n| {
+            let mut attempt = self.attempt(&mut cmd, input)?;
+            if attempt.status.success() {
+                attempts.push(attempt);
+                return Ok(Step::Stop(()));
+            }
+            let (stdout, stderr) = (
+                String::from_utf8_lossy(&attempt.stdout),
+                String::from_utf8_lossy(&attempt.stderr),
+            );
+            let output = Output::Streams {
+                stdout: &stdout,
+                stderr: &stderr,
+            };
+            let mut decision = should_retry(
+                output,
+                attempt.status.code(),
+                policy.retry_on_any,
+                &policy.patterns,
+                None,
+            );
+            let max_retry_after = policy.max_retry_after.as_millis() as u64;
+            decision.retry_after_ms = decision.retry_after_ms.filter(|&ms| ms <= max_retry_after);
+            let retry = decision.retry && n < policy.max_retries;
+            let wait = decision.floored(
+                decision
+                    .retry_after_ms
+                    .unwrap_or_else(|| self.backoff_ms(n, previous)),
+            );
+            let asked = decision.retry_after_ms.is_some();
+            attempt.delay = retry.then_some(Duration::from_millis(wait));
+            attempt.decision = Some(decision);
+            if retry {
+                on_retry(&attempt);
+            }
+            attempts.push(attempt);
+            if !retry {
+                return Ok(Step::Stop(()));
+            }
+            if asked {
+                self.pause(Duration::from_millis(wait));
+            }
+            previous = Some(wait);
+            thread::sleep(Duration::from_millis(wait));
+            Ok(Step::Retry)
+        })?;
+        Ok(RunReport { attempts })
+    }
+
+    /// Hold every run's next attempt for `wait` from now, unless a pause already lasts longer.
+    fn pause(&self, wait: Duration) {
+        let until = Instant::now() + wait;
+        let mut paused = self
+            .paused_until
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if paused.is_none_or(|t| t < until) {
+            *paused = Some(until);
+        }
+    }
+
+    /// Wait out the pause, if there is one.
+    fn hold(&self) {
+        loop {
+            let until = *self
+                .paused_until
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            match until.map(|t| t.saturating_duration_since(Instant::now())) {
+                Some(left) if !left.is_zero() => thread::sleep(left),
+                _ => return,
+            }
+        }
+    }
+
+    fn attempt(&self, cmd: &mut Command, input: Option<&[u8]>) -> io::Result<Attempt> {
+        let started = Instant::now();
+        let mut child = cmd
+            .stdin(if input.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take();
+        let stdout = child.stdout.take().expect("piped stdout");
+        let stderr = child.stderr.take().expect("piped stderr");
+        let forward = self.tee;
+        let (status, stdout, stderr) = thread::scope(|scope| {
+            if let (Some(mut stdin), Some(input)) = (stdin, input) {
+                // A child that exits without reading its input is judged by its own exit
+                scope.spawn(move || stdin.write_all(input));
+            }
+            let out = scope.spawn(move || {
+                if forward {
+                    tee(stdout, io::stdout(), Vec::new())
+                } else {
+                    tee(stdout, io::sink(), Vec::new())
+                }
+            });
+            let err = scope.spawn(move || {
+                if forward {
+                    tee(stderr, io::stderr(), Vec::new())
+                } else {
+                    tee(stderr, io::sink(), Vec::new())
+                }
+            });
+            let status = child.wait();
+            let out = out.join().expect("stdout reader");
+            let err = err.join().expect("stderr reader");
+            (status, out, err)
+        });
+        Ok(Attempt {
+            status: status?,
+            stdout: stdout?,
+            stderr: stderr?,
+            duration: started.elapsed(),
+            decision: None,
+            delay: None,
+        })
+    }
+}
+
+/// What [`tee`] does with each chunk besides copying it to its destination.
+pub trait Sink {
+    /// Take one chunk read from the source, and say whether it goes on to the destination.
+    /// An error is kept as the result of the whole [`tee`], which still drains the source.
+    fn chunk(&mut self, chunk: &[u8]) -> io::Result<bool>;
+
+    /// Called once the source is done with, however it ended.
+    fn finish(&mut self) {}
+}
+
+impl<S: Sink + ?Sized> Sink for &mut S {
+    fn chunk(&mut self, chunk: &[u8]) -> io::Result<bool> {
+        (**self).chunk(chunk)
+    }
+
+    fn finish(&mut self) {
+        (**self).finish()
+    }
+}
+
+/// Captures everything and forwards everything.
+impl Sink for Vec<u8> {
+    fn chunk(&mut self, chunk: &[u8]) -> io::Result<bool> {
+        self.extend_from_slice(chunk);
+        Ok(true)
+    }
+}
+
+/// Read `src` to the end, handing each chunk to `sink` and copying it to `dst` as it
+/// arrives, unless the sink holds it back. After the first failed write (or sink error)
+/// nothing more goes to `dst`, but the rest of `src` is still read, or a child blocked on
+/// the full pipe never exits; that first error is the result once `src` ends. A read error
+/// ends the copy at once.
+pub fn tee<S: Sink>(mut src: impl Read, mut dst: impl Write, mut sink: S) -> io::Result<S> {
+    let mut buf = [0u8; 8192];
+    let mut error = None;
+    loop {
+        let n = match src.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                sink.finish();
+                return Err(e);
+            }
+        };
+        let forward = sink
+            .chunk(&buf[..n])
+            .map_err(|e| error.get_or_insert(e))
+            .unwrap_or(false);
+        if forward && error.is_none() {
+            error = dst.write_all(&buf[..n]).and_then(|()| dst.flush()).err();
+        }
+    }
+    sink.finish();
+    match error {
+        Some(e) => Err(e),
+        None => Ok(sink),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seeded(seed: u64) -> Supervisor {
+        Supervisor::new(RetryPolicy {
+            jitter_seed: Some(seed),
+            ..RetryPolicy::default()
+        })
+    }
+
+    #[test]
+    fn drive_steps_until_stopped() {
+        let mut seen = Vec::new();
+        let done = seeded(1)
+            .drive(|n| {
+                seen.push(n);
+                Ok(if n == 3 {
+                    Step::Stop("done")
+                } else {
+                    Step::Retry
+                })
+            })
+            .unwrap();
+        assert_eq!((done, seen), ("done", vec![0, 1, 2, 3]));
+        let failed =
+            seeded(1).drive(|_| -> io::Result<Step<()>> { Err(io::Error::other("spawn")) });
+        assert_eq!(failed.unwrap_err().to_string(), "spawn");
+    }
+
+    #[test]
+    fn backoff_draws_repeat_with_a_seed() {
+        let draws = |s: &Supervisor| {
+            let mut previous = None;
+            (0..6)
+                .map(|n| {
+                    let ms = s.backoff_ms(n, previous);
+                    previous = Some(ms);
+                    ms
+                })
+                .collect::<Vec<_>>()
+        };
+        let (a, b) = (seeded(42), seeded(42));
+        assert_eq!(draws(&a), draws(&b));
+        for (n, ms) in draws(&a).into_iter().enumerate() {
+            let (low, high) = a.policy().backoff.bounds(n as u32, None);
+            assert!((low..=high).contains(&ms), "attempt {n}: {ms}");
+        }
+    }
+
+    #[test]
+    fn a_pause_holds_the_next_attempt() {
+        let supervisor = seeded(1);
+        supervisor.pause(Duration::from_millis(50));
+        let started = Instant::now();
+        supervisor.drive(|_| Ok(Step::Stop(()))).unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(50));
+    }
+
+    /// Holds back every other chunk, and fails on the one at `fail`.
+    #[derive(Debug)]
+    struct Alternate {
+        seen: usize,
+        fail: Option<usize>,
+        finished: bool,
+    }
+
+    impl Sink for Alternate {
+        fn chunk(&mut self, _: &[u8]) -> io::Result<bool> {
+            self.seen += 1;
+            if self.fail == Some(self.seen) {
+                return Err(io::Error::other("sink"));
+            }
+            Ok(self.seen % 2 == 1)
+        }
+
+        fn finish(&mut self) {
+            self.finished = true;
+        }
+    }
+
+    /// Hands out `data` a few bytes at a time, then fails if `then_fail`.
+    struct Chunks<'a> {
+        data: &'a [u8],
+        then_fail: bool,
+    }
+
+    impl Read for Chunks<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.data.is_empty() && self.then_fail {
+                return Err(io::Error::other("read"));
+            }
+            let n = self.data.len().min(buf.len()).min(3);
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    /// Accepts `room` bytes, then fails every write.
+    struct Full {
+        written: Vec<u8>,
+        room: usize,
+    }
+
+    impl Write for Full {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.written.len() + buf.len() > self.room {
+                return Err(io::Error::other("full"));
+            }
+            self.written.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn chunks(data: &[u8]) -> Chunks<'_> {
+        Chunks {
+            data,
+            then_fail: false,
+        }
+    }
+
+    fn alternate(fail: Option<usize>) -> Alternate {
+        Alternate {
+            seen: 0,
+            fail,
+            finished: false,
+        }
+    }
+
+    #[test]
+    fn tee_captures_and_forwards() {
+        let mut out = Vec::new();
+        let captured = tee(chunks(b"hello world"), &mut out, Vec::new()).unwrap();
+        assert_eq!(
+            (captured.as_slice(), out.as_slice()),
+            (&b"hello world"[..], &b"hello world"[..])
+        );
+    }
+
+    #[test]
+    fn tee_forwards_only_what_the_sink_lets_through() {
+        let mut out = Vec::new();
+        let sink = tee(chunks(b"aaabbbcccd"), &mut out, alternate(None)).unwrap();
+        assert_eq!(
+            (out.as_slice(), sink.seen, sink.finished),
+            (&b"aaaccc"[..], 4, true)
+        );
+    }
+
+    #[test]
+    fn tee_drains_past_the_first_error() {
+        // A failed write stops the forwarding, not the reading
+        let mut full = Full {
+            written: Vec::new(),
+            room: 4,
+        };
+        let err = tee(chunks(b"aaabbbccc"), &mut full, alternate(None)).unwrap_err();
+        assert_eq!(
+            (err.to_string(), full.written.as_slice()),
+            ("full".into(), &b"aaa"[..])
+        );
+
+        let mut sink = alternate(Some(2));
+        let mut out = Vec::new();
+        let err = tee(chunks(b"aaabbbcccddd"), &mut out, &mut sink).unwrap_err();
+        assert_eq!(err.to_string(), "sink");
+        assert_eq!(
+            (out.as_slice(), sink.seen, sink.finished),
+            (&b"aaa"[..], 4, true)
+        );
+    }
+
+    #[test]
+    fn tee_stops_at_a_read_error() {
+        let mut sink = alternate(None);
+        let src = Chunks {
+            data: b"aaab",
+            then_fail: true,
+        };
+        let err = tee(src, io::sink(), &mut sink).unwrap_err();
+        assert_eq!(
+            (err.to_string(), sink.seen, sink.finished),
+            ("read".into(), 2, true)
+        );
+    }
+}
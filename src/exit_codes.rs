@@ -0,0 +1,240 @@
+//! Exit codes reserved for outcomes that originate in the wrapper rather than the child.
+//!
+//! | code | meaning |
+//! |------|---------|
+//! | 2    | configuration rejected (`--strict-config`) |
+//! | 114  | stdin was not piped, or held less than `--require-stdin` |
+//! | 115  | at least one `--batch` entry failed |
+//! | 116  | child exited 0 but its JSON output reported an error (`--json-errors`) |
+//! | 117  | child exited 0 but `--success-pattern` never matched |
+//! | 118  | child binary failed `--expect-cmd-sha256` verification |
+//! | 119  | child CLI below `--min-child-version` (with `--enforce-min-child-version`) |
+//! | 120  | cumulative output exceeded `--max-total-output` |
+//! | 121  | an attempt's output exceeded `--buffer-limit` under `--buffer-output` |
+//! | 122  | `--max-total-ms` ran out before the retries did |
+//! | 123  | the last attempt was silent for `--idle-timeout-secs`, or wrote nothing within `--first-output-timeout` |
+//! | 124  | the last attempt ran into `--attempt-timeout-secs` |
+//! | 125  | wrapper internal error |
+//! | 126  | command found but not executable |
+//! | 127  | command not found |
+//! | 130  | stopped by an `abort` control command |
+//!
+//! `--timeout-exit-code`, `--stall-exit-code` and `--total-timeout-exit-code` replace 124,
+//! 123 and 122. `--exhausted-exit-code` optionally reports retry exhaustion with its own code, and a run
+//! that ends on an attempt that exited 0 but was failed by `--retry-on-success-match` exits
+//! with `--success-match-exit-code` (1 by default). Child exit codes are passed through
+//! untouched everywhere else. A child may itself exit with one of the reserved values;
+//! `--reason-file` records the origin so callers can tell them apart.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::ExitStatus;
+
+use crate::budget::GiveUp;
+use crate::classes::ErrorClass;
+use crate::waste::Waste;
+
+/// A settings combination was rejected (`--strict-config`); matches clap's usage-error code.
+pub const CONFIG_ERROR: i32 = 2;
+/// Stdin was a terminal, or held fewer bytes than `--require-stdin` asks for.
+pub const NO_STDIN: i32 = 114;
+/// At least one `--batch` entry still failed once its retries were over.
+pub const BATCH_FAILED: i32 = 115;
+/// The last attempt exited 0 but its JSON output reported an error (`--json-errors`).
+pub const JSON_ERROR: i32 = 116;
+/// The last attempt exited 0 without its output matching `--success-pattern`.
+pub const NO_SUCCESS_MATCH: i32 = 117;
+/// The child binary's SHA-256 is not one of the `--expect-cmd-sha256` digests.
+pub const INTEGRITY_MISMATCH: i32 = 118;
+/// The child CLI is older than `--min-child-version` and enforcement is on.
+pub const CHILD_TOO_OLD: i32 = 119;
+/// Retrying stopped because the child's cumulative output exceeded `--max-total-output`.
+pub const OUTPUT_LIMIT: i32 = 120;
+/// An attempt wrote more than `--buffer-limit` under `--buffer-output`.
+pub const BUFFER_LIMIT: i32 = 121;
+/// Retrying stopped because the next wait would end past `--max-total-ms`.
+pub const TOTAL_TIMEOUT: i32 = 122;
+/// The last attempt stalled: no output for `--idle-timeout-secs`, or none at all within
+/// `--first-output-timeout`.
+pub const STALLED: i32 = 123;
+/// The last attempt was killed by `--attempt-timeout-secs`.
+pub const ATTEMPT_TIMEOUT: i32 = 124;
+/// The wrapper itself failed (I/O error while supervising, broken invariant).
+pub const INTERNAL_ERROR: i32 = 125;
+/// The command was found but could not be executed (e.g. permission denied).
+pub const CANNOT_EXECUTE: i32 = 126;
+/// The command was not found.
+pub const NOT_FOUND: i32 = 127;
+/// An `abort` control command killed the child; 128 + SIGINT, as for an interrupt.
+pub const INTERRUPTED: i32 = 130;
+
+/// The exit code that passes on a child's `status`: its own code, or on Unix 128 + the
+/// signal that killed it, the way a shell reports one.
+pub fn of_status(status: ExitStatus) -> i32 {
+    if let Some(code) = status.code() {
+        return code;
+    }
+    #[cfg(unix)]
+    if let Some(sig) = std::os::unix::process::ExitStatusExt::signal(&status) {
+        return 128 + sig;
+    }
+    1
+}
+
+/// Why the run ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reason {
+    Success,
+    /// The child failed and the failure was not retryable.
+    NotRetryable,
+    /// The child's output matched a fatal pattern (`--fatal-patterns`).
+    Fatal,
+    /// The child kept failing with retryable errors until the retry budget ran out.
+    Exhausted,
+    /// Retrying stopped because of `--max-total-output`.
+    OutputLimit,
+    /// An attempt was killed for exceeding `--buffer-limit`.
+    BufferLimit,
+    /// The last attempt was killed by `--attempt-timeout-secs`.
+    AttemptTimeout,
+    /// The last attempt was killed by `--idle-timeout-secs`.
+    Stalled,
+    /// The last attempt was killed by `--first-output-timeout`.
+    NoOutput,
+    /// Retrying stopped because the next wait would end past `--max-total-ms`.
+    TotalTimeout,
+    /// `--server-mode` supervision was stopped by SIGTERM/SIGINT.
+    Stopped,
+    /// A `drain` control command ended the run instead of a retry or restart.
+    Drained,
+    /// An `abort` control command killed the child.
+    Aborted,
+    /// SIGINT or SIGTERM (Ctrl-C on Windows) stopped the run; it exits 128 + the signal.
+    Interrupted,
+    /// The child binary failed `--expect-cmd-sha256` verification.
+    IntegrityMismatch,
+    /// The child CLI failed the `--min-child-version` check.
+    ChildTooOld,
+    /// At least one `--batch` entry failed.
+    BatchFailed,
+    /// Stdin failed the `--require-stdin` check.
+    NoStdin,
+    SpawnNotFound,
+    SpawnCannotExecute,
+    ConfigError,
+    InternalError,
+}
+
+impl Reason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Reason::Success => "success",
+            Reason::NotRetryable => "not-retryable",
+            Reason::Fatal => "fatal",
+            Reason::Exhausted => "exhausted",
+            Reason::OutputLimit => "output-limit",
+            Reason::BufferLimit => "buffer-limit",
+            Reason::AttemptTimeout => "attempt-timeout",
+            Reason::Stalled => "stalled",
+            Reason::NoOutput => "no-output",
+            Reason::TotalTimeout => "total-timeout",
+            Reason::Stopped => "stopped",
+            Reason::Drained => "drained",
+            Reason::Aborted => "aborted",
+            Reason::Interrupted => "interrupted",
+            Reason::IntegrityMismatch => "integrity-mismatch",
+            Reason::ChildTooOld => "child-too-old",
+            Reason::BatchFailed => "batch-failed",
+            Reason::NoStdin => "no-stdin",
+            Reason::SpawnNotFound => "spawn-not-found",
+            Reason::SpawnCannotExecute => "spawn-cannot-execute",
+            Reason::ConfigError => "config-error",
+            Reason::InternalError => "internal-error",
+        }
+    }
+
+    /// Which timeout ended the run, if one did: `attempt`, `idle`, `first-output` or `total`.
+    pub fn timeout(self) -> Option<&'static str> {
+        match self {
+            Reason::AttemptTimeout => Some("attempt"),
+            Reason::Stalled => Some("idle"),
+            Reason::NoOutput => Some("first-output"),
+            Reason::TotalTimeout => Some("total"),
+            _ => None,
+        }
+    }
+}
+
+/// Final disposition of a run.
+#[derive(Debug)]
+pub struct Outcome {
+    pub exit_code: i32,
+    pub reason: Reason,
+    /// Whether `exit_code` was chosen by the wrapper rather than passed through from the child.
+    pub from_wrapper: bool,
+    /// The last child exit code, if a child ran and exited normally.
+    pub child_code: Option<i32>,
+    pub attempts: u32,
+    /// The `--class-budget` class whose limit ended the retries, if one did.
+    pub exhausted_class: Option<ErrorClass>,
+    /// What the retried attempts cost, when any were retried.
+    pub waste: Option<Waste>,
+    /// The pattern (and its class, if built in) each failed attempt matched, in order.
+    pub matched: Vec<(String, Option<ErrorClass>)>,
+    /// Set when the last retry was abandoned because its wait outlasted the time budget.
+    pub gave_up: Option<GiveUp>,
+}
+
+impl Outcome {
+    pub fn wrapper(reason: Reason, exit_code: i32, attempts: u32) -> Self {
+        Outcome {
+            exit_code,
+            reason,
+            from_wrapper: true,
+            child_code: None,
+            attempts,
+            exhausted_class: None,
+            waste: None,
+            matched: Vec::new(),
+            gave_up: None,
+        }
+    }
+
+    /// Render the `key=value` lines written to `--reason-file`.
+    pub fn render(&self) -> String {
+        let origin = if self.from_wrapper {
+            "wrapper"
+        } else {
+            "child"
+        };
+        let child_code = self
+            .child_code
+            .map_or_else(|| "none".to_string(), |c| c.to_string());
+        let mut text = format!(
+            "exit_code={}\norigin={}\nreason={}\nchild_exit_code={}\nattempts={}\n",
+            self.exit_code,
+            origin,
+            self.reason.as_str(),
+            child_code,
+            self.attempts
+        );
+        if let Some(timeout) = self.reason.timeout() {
+            text.push_str(&format!("timeout={timeout}\n"));
+        }
+        if let Some(class) = self.exhausted_class {
+            text.push_str(&format!("exhausted_class={class}\n"));
+        }
+        if let Some(give_up) = &self.gave_up {
+            text.push_str(&give_up.render());
+        }
+        if let Some(waste) = &self.waste {
+            text.push_str(&waste.render());
+        }
+        text
+    }
+
+    pub fn write_reason_file(&self, path: &Path, run_id: &str) -> io::Result<()> {
+        fs::write(path, format!("{}run_id={run_id}\n", self.render()))
+    }
+}
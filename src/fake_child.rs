@@ -0,0 +1,465 @@
+//! A stand-in for the Claude CLI with scripted behavior, run as the hidden `__fake-child`
+//! subcommand. `self-test` and `bench` point `--cmd` at our own executable and use it to
+//! exercise the retry machinery without a real CLI or network access.
+
+use clap::{Args, ValueEnum};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Scenario {
+    /// Print `ok` and exit 0
+    Succeed,
+    /// Print an overload error and exit 1 for the first `--failures` runs, then succeed
+    FailsThenSucceeds,
+    /// Print an overload error but still exit 0 for the first `--failures` runs, as older
+    /// CLI builds do; then print `ok`
+    OverloadedExitsZero,
+    /// Print a usage-style error that matches no retry pattern and exit `--exit-code`
+    AlwaysFatal,
+    /// Fail once with a 529 overload and `Retry-After: 1`, then succeed (a 429 would wait
+    /// out its pattern's minimum delay instead)
+    EmitsRetryAfter,
+    /// Write `--bytes` bytes to stdout and exit 0
+    HugeOutput,
+    /// Kill itself with SIGTERM (Unix only)
+    SignalDeath,
+    /// Print `start`, stay silent for `--secs`, then print `end` and exit `--status` (or,
+    /// with `--close-output`, close both pipes after `start` and exit without `end`)
+    Stalls,
+    /// Fail `--failures` times without reading stdin, then copy stdin to stdout
+    EchoStdin,
+    /// Write the `--payload` byte streams to stdout and stderr (in `--chunk`-byte writes when
+    /// given) and exit `--status`
+    RawBytes,
+    /// Print `NAME=value` for each `--var` (empty if unset) and exit `--status`
+    PrintEnv,
+    /// For the first `--failures` runs print an overload error and then hang for `--secs`
+    /// without exiting, as a CLI stuck in its own retries does; then succeed
+    OverloadedThenHangs,
+    /// Print stream-json whose result embeds a 529 overload and exit 0 for the first
+    /// `--failures` runs, then a clean result that mentions 429 in its text and exit
+    /// `--status`
+    JsonError,
+    /// Print `tick N` every 10ms for `--secs`, then `chatty done`; with `--orphan`, leave the
+    /// printing to a process of its own that shares our output, and wait for a kill
+    Chatty,
+    /// Print a stream-json init line with the session id `5e55-000N` (N the run) and
+    /// `--filler` bytes of other lines, then for the first `--failures` runs an overload error
+    /// and exit 1; then print `ok`
+    Session,
+    /// Stay silent for `--startup` seconds, print `listening` on stderr, run for `--secs`,
+    /// then exit `--exit-code` like a crashing server
+    Server,
+    /// Print `ready> `, read one line from stdin, print `got: LINE` and exit `--status`, as
+    /// a terminal UI taking its first message does
+    Prompt,
+    /// On run N, act out the Nth of `--steps` (the last one once they run out): `ratelimit`,
+    /// `server`, or `network` print an error of that class and exit 1, `ok` succeeds
+    Sequence,
+}
+
+/// Byte streams that a text-minded wrapper would be tempted to alter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Payload {
+    /// NUL bytes, invalid UTF-8, CRLF and lone CR, and no trailing newline
+    Adversarial,
+    /// Nothing at all
+    Empty,
+    /// `--bytes` bytes of a non-repeating-at-chunk-size sequence, to catch reordering
+    Large,
+    /// An overload error on stderr with a 4-byte character, invalid bytes, and a truncated
+    /// sequence at the end of the line
+    Malformed,
+    /// A valid overload error line longer than an embedded excerpt, with a 3-byte character
+    /// straddling the excerpt limit
+    LongLine,
+    /// `--bytes` bytes of noise lines on stderr, then an overload error on a line of a few
+    /// KB, where a capture cut from the front falls
+    NoisyOverload,
+    /// An answer on stdout that quotes `HTTP 500` and `429 Too Many Requests`, and nothing on
+    /// stderr
+    QuotesErrors,
+}
+
+/// The exact stdout and stderr bytes `raw-bytes` writes for `payload`.
+pub fn payload_bytes(payload: Payload, bytes: u64) -> (Vec<u8>, Vec<u8>) {
+    match payload {
+        Payload::Adversarial => (
+            b"line one\r\nnul:\0\0 bad:\xff\xfe\xc3( lone\rcr\n\n  no newline".to_vec(),
+            b"\xe2\x82 err\0\r".to_vec(),
+        ),
+        Payload::Empty => (Vec::new(), Vec::new()),
+        Payload::Large => (
+            (0..bytes).map(|i| (i % 251) as u8).collect(),
+            (0..bytes / 16).map(|i| (i % 241) as u8).collect(),
+        ),
+        Payload::Malformed => (
+            b"some output\n".to_vec(),
+            b"API Error: 529 overloaded \xf0\x9f\x98\x80 \xff\xfe and cut \xe2\x82\n".to_vec(),
+        ),
+        Payload::LongLine => {
+            let mut line = b"API Error: 529 overloaded ".to_vec();
+            line.resize(crate::utf8::EXCERPT_BYTES - 2, b'x');
+            line.extend_from_slice("\u{20ac} tail\n".as_bytes());
+            (Vec::new(), line)
+        }
+        Payload::NoisyOverload => {
+            let mut err: Vec<u8> = b"noise\n"
+                .iter()
+                .copied()
+                .cycle()
+                .take(bytes as usize)
+                .collect();
+            err.extend_from_slice(b"API Error: 529 overloaded ");
+            err.resize(err.len() + 3000, b'x');
+            err.push(b'\n');
+            (Vec::new(), err)
+        }
+        Payload::QuotesErrors => (
+            b"Retry when the API answers HTTP 500 or 429 Too Many Requests.\n".to_vec(),
+            Vec::new(),
+        ),
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct FakeChildArgs {
+    #[arg(value_enum)]
+    scenario: Scenario,
+
+    /// Counter file that persists the run count across attempts
+    #[arg(long)]
+    state: Option<PathBuf>,
+
+    #[arg(long, default_value_t = 1)]
+    failures: u32,
+
+    #[arg(long, default_value_t = 2)]
+    exit_code: i32,
+
+    #[arg(long, default_value_t = 8 * 1024 * 1024)]
+    bytes: u64,
+
+    #[arg(long, default_value_t = 1.0)]
+    secs: f64,
+
+    #[arg(long, default_value_t = 0.0)]
+    startup: f64,
+
+    #[arg(long, value_enum, default_value_t = Payload::Adversarial)]
+    payload: Payload,
+
+    #[arg(long = "var")]
+    vars: Vec<String>,
+
+    #[arg(long, default_value_t = 0)]
+    status: i32,
+
+    /// Write `raw-bytes` payloads in writes of this many bytes, flushing between them
+    #[arg(long)]
+    chunk: Option<usize>,
+
+    /// With `stalls`, close stdout and stderr before going silent (Unix only)
+    #[arg(long)]
+    close_output: bool,
+
+    /// With `stalls`, write TEXT to PATH (`PATH=TEXT`) after `start`, e.g. a control command
+    #[arg(long, value_name = "PATH=TEXT")]
+    write_file: Option<String>,
+
+    /// With `stalls`, connect to the Unix socket PATH and send LINE (`PATH=LINE`) after
+    /// `start` (Unix only)
+    #[arg(long, value_name = "PATH=LINE")]
+    send_socket: Option<String>,
+
+    /// With `stalls`, send SIGTERM to our parent (the wrapper) after `start`, N times 200ms
+    /// apart (Unix only)
+    #[arg(long, value_name = "N", num_args = 0..=1, default_missing_value = "1", default_value_t = 0)]
+    signal_parent: u32,
+
+    /// With `stalls`, ignore SIGTERM (Unix only)
+    #[arg(long)]
+    ignore_sigterm: bool,
+
+    /// With `chatty`, print from a separate process that outlives us
+    #[arg(long)]
+    orphan: bool,
+
+    /// Print this file's contents first, as passed by --feed-previous-error
+    #[arg(long)]
+    previous_error: Option<PathBuf>,
+
+    /// With `sequence`, what each run does, e.g. `server,ratelimit,ok`
+    #[arg(long, value_delimiter = ',', default_value = "ok")]
+    steps: Vec<String>,
+
+    /// With `session`, this many bytes of filler lines after the init line
+    #[arg(long, default_value_t = 0)]
+    filler: usize,
+
+    /// Print `resuming SESSION` first, as passed by --auto-resume
+    #[arg(long)]
+    resume: Option<String>,
+
+    /// First start a process that sleeps for a minute, as the CLI's helpers would, and write
+    /// its pid to PATH
+    #[arg(long, value_name = "PATH")]
+    grandchild: Option<PathBuf>,
+}
+
+/// Increment and return the run counter (1 for the first run).
+fn bump_counter(state: Option<&PathBuf>) -> io::Result<u32> {
+    let Some(path) = state else {
+        return Ok(1);
+    };
+    let runs = fs::read_to_string(path)
+        .ok()
+        .and_then(|s| s.trim().parse::<u32>().ok())
+        .unwrap_or(0)
+        + 1;
+    fs::write(path, runs.to_string())?;
+    Ok(runs)
+}
+
+pub fn run(args: &FakeChildArgs) -> io::Result<i32> {
+    let runs = bump_counter(args.state.as_ref())?;
+    if let Some(path) = &args.grandchild {
+        let helper = std::process::Command::new(std::env::current_exe()?)
+            .args(["__fake-child", "stalls", "--secs", "60"])
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .spawn()?;
+        fs::write(path, helper.id().to_string())?;
+    }
+    let mut stdout = io::stdout().lock();
+    if let Some(path) = &args.previous_error {
+        writeln!(stdout, "previous error from {}:", path.display())?;
+        stdout.write_all(&fs::read(path)?)?;
+    }
+    if let Some(session) = &args.resume {
+        writeln!(stdout, "resuming {session}")?;
+    }
+    match args.scenario {
+        Scenario::Succeed => writeln!(stdout, "ok")?,
+        Scenario::FailsThenSucceeds => {
+            if runs <= args.failures {
+                eprintln!(
+                    r#"API Error: 529 {{"type":"error","error":{{"type":"overloaded_error","message":"Overloaded"}}}}"#
+                );
+                return Ok(1);
+            }
+            writeln!(stdout, "ok")?;
+        }
+        Scenario::Session => {
+            writeln!(
+                stdout,
+                r#"{{"type":"system","subtype":"init","session_id":"5e55-{runs:04}"}}"#
+            )?;
+            for _ in 0..args.filler / 64 {
+                writeln!(stdout, "{}", "-".repeat(63))?;
+            }
+            if runs <= args.failures {
+                eprintln!("API Error: 529 Overloaded");
+                return Ok(1);
+            }
+            writeln!(stdout, "ok")?;
+        }
+        Scenario::OverloadedExitsZero => {
+            if runs <= args.failures {
+                eprintln!("API Error: 529 Overloaded");
+                return Ok(0);
+            }
+            writeln!(stdout, "ok")?;
+        }
+        Scenario::AlwaysFatal => {
+            eprintln!("error: unknown option '--bogus'");
+            return Ok(args.exit_code);
+        }
+        Scenario::EmitsRetryAfter => {
+            if runs == 1 {
+                eprintln!("API Error: 529 Overloaded");
+                eprintln!("Retry-After: 1");
+                return Ok(1);
+            }
+            writeln!(stdout, "ok")?;
+        }
+        Scenario::HugeOutput => {
+            let chunk = [b'x'; 64 * 1024];
+            let mut left = args.bytes;
+            while left > 0 {
+                let n = left.min(chunk.len() as u64) as usize;
+                stdout.write_all(&chunk[..n])?;
+                left -= n as u64;
+            }
+        }
+        Scenario::SignalDeath => {
+            #[cfg(unix)]
+            // SAFETY: raising a signal on ourselves has no memory-safety preconditions.
+            unsafe {
+                libc::raise(libc::SIGTERM);
+            }
+            return Ok(1);
+        }
+        Scenario::Stalls => {
+            writeln!(stdout, "start")?;
+            stdout.flush()?;
+            let split = |spec: &str| {
+                spec.split_once('=')
+                    .map(|(path, text)| (path.to_string(), text.to_string()))
+                    .ok_or_else(|| io::Error::other(format!("`{spec}`: expected PATH=TEXT")))
+            };
+            if let Some(spec) = &args.write_file {
+                let (path, text) = split(spec)?;
+                fs::write(path, text)?;
+            }
+            #[cfg(unix)]
+            if let Some(spec) = &args.send_socket {
+                let (path, line) = split(spec)?;
+                let mut conn = std::os::unix::net::UnixStream::connect(path)?;
+                writeln!(conn, "{line}")?;
+            }
+            #[cfg(unix)]
+            if args.ignore_sigterm {
+                // SAFETY: ignoring a signal has no memory-safety preconditions.
+                unsafe {
+                    libc::signal(libc::SIGTERM, libc::SIG_IGN);
+                }
+            }
+            #[cfg(unix)]
+            for n in 0..args.signal_parent {
+                if n > 0 {
+                    thread::sleep(Duration::from_millis(200));
+                }
+                // SAFETY: signalling our parent by pid.
+                unsafe {
+                    libc::kill(libc::getppid(), libc::SIGTERM);
+                }
+            }
+            #[cfg(unix)]
+            if args.close_output {
+                // SAFETY: nothing writes to these descriptors afterwards.
+                unsafe {
+                    libc::close(1);
+                    libc::close(2);
+                }
+                thread::sleep(Duration::from_secs_f64(args.secs));
+                return Ok(0);
+            }
+            thread::sleep(Duration::from_secs_f64(args.secs));
+            writeln!(stdout, "end")?;
+            stdout.flush()?;
+            return Ok(args.status);
+        }
+        Scenario::Sequence => {
+            let step = args.steps.get(runs as usize - 1).or(args.steps.last());
+            let error = match step.map(String::as_str) {
+                Some("ok") | None => None,
+                Some("ratelimit") => Some("API Error: Too Many Requests"),
+                Some("server") => Some("API Error: 529 Overloaded"),
+                Some("network") => Some("API Error: read ECONNRESET"),
+                Some(other) => return Err(io::Error::other(format!("unknown step `{other}`"))),
+            };
+            if let Some(error) = error {
+                eprintln!("{error}");
+                return Ok(1);
+            }
+            writeln!(stdout, "ok")?;
+        }
+        Scenario::Prompt => {
+            write!(stdout, "ready> ")?;
+            stdout.flush()?;
+            let mut line = String::new();
+            io::stdin().read_line(&mut line)?;
+            writeln!(stdout, "got: {}", line.trim_end())?;
+            return Ok(args.status);
+        }
+        Scenario::EchoStdin => {
+            if runs <= args.failures {
+                eprintln!("API Error: 503 Server Error");
+                return Ok(1);
+            }
+            let mut input = Vec::new();
+            io::stdin().read_to_end(&mut input)?;
+            stdout.write_all(&input)?;
+        }
+        Scenario::RawBytes => {
+            let (out, err) = payload_bytes(args.payload, args.bytes);
+            let chunk = args.chunk.unwrap_or(usize::MAX).max(1);
+            for part in out.chunks(chunk) {
+                stdout.write_all(part)?;
+                stdout.flush()?;
+            }
+            let mut stderr = io::stderr();
+            for part in err.chunks(chunk) {
+                stderr.write_all(part)?;
+                stderr.flush()?;
+                if args.chunk.is_some() {
+                    thread::sleep(Duration::from_millis(10));
+                }
+            }
+            return Ok(args.status);
+        }
+        Scenario::PrintEnv => {
+            for name in &args.vars {
+                writeln!(stdout, "{name}={}", std::env::var(name).unwrap_or_default())?;
+            }
+            stdout.flush()?;
+            return Ok(args.status);
+        }
+        Scenario::OverloadedThenHangs => {
+            if runs <= args.failures {
+                eprintln!("API Error: 529 Overloaded");
+                thread::sleep(Duration::from_secs_f64(args.secs));
+                return Ok(1);
+            }
+            writeln!(stdout, "ok")?;
+        }
+        Scenario::JsonError => {
+            writeln!(stdout, r#"{{"type":"system","subtype":"init"}}"#)?;
+            if runs <= args.failures {
+                writeln!(
+                    stdout,
+                    r#"{{"type":"result","is_error":true,"result":"API Error: 529 {{\"type\":\"error\",\"error\":{{\"type\":\"overloaded_error\",\"message\":\"Overloaded\"}}}}"}}"#
+                )?;
+            } else {
+                writeln!(
+                    stdout,
+                    r#"{{"type":"result","is_error":false,"result":"HTTP 429 means Too Many Requests"}}"#
+                )?;
+                stdout.flush()?;
+                return Ok(args.status);
+            }
+        }
+        Scenario::Chatty => {
+            if args.orphan {
+                std::process::Command::new(std::env::current_exe()?)
+                    .args(["__fake-child", "chatty", "--secs", &args.secs.to_string()])
+                    .stdin(std::process::Stdio::null())
+                    .spawn()?;
+                thread::sleep(Duration::from_secs_f64(args.secs * 2.0));
+                return Ok(args.status);
+            }
+            let ticks = (args.secs * 100.0) as u64;
+            for n in 0..ticks {
+                writeln!(stdout, "tick {n}")?;
+                stdout.flush()?;
+                thread::sleep(Duration::from_millis(10));
+            }
+            writeln!(stdout, "chatty done")?;
+        }
+        Scenario::Server => {
+            thread::sleep(Duration::from_secs_f64(args.startup));
+            eprintln!("listening");
+            thread::sleep(Duration::from_secs_f64(args.secs));
+            eprintln!("fatal: connection reset");
+            return Ok(args.exit_code);
+        }
+    }
+    stdout.flush()?;
+    Ok(0)
+}
@@ -0,0 +1,40 @@
+//! `--fallback-args`: argument sets to move on to when the retries of the one before have run
+//! out on retryable errors, such as a smaller model while the big one is overloaded. Each set
+//! gets a fresh retry cycle of its own (`--max-retries`, backoff, `--class-budget`), and the
+//! run ends with the last attempt actually run.
+
+use clap::ValueEnum;
+
+/// How a fallback set is combined with the child args.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum FallbackMode {
+    /// Use the fallback args in place of the child args
+    Replace,
+    /// Append the fallback args to the child args
+    Append,
+}
+
+/// The child args for argument set `set`: 0 is the primary, `n` the `n`th fallback.
+pub fn args(
+    primary: &[String],
+    fallbacks: &[String],
+    set: usize,
+    mode: FallbackMode,
+) -> Vec<String> {
+    let Some(fallback) = set.checked_sub(1).and_then(|i| fallbacks.get(i)) else {
+        return primary.to_vec();
+    };
+    let fallback = fallback.split_whitespace().map(str::to_string);
+    match mode {
+        FallbackMode::Replace => fallback.collect(),
+        FallbackMode::Append => primary.iter().cloned().chain(fallback).collect(),
+    }
+}
+
+/// e.g. `primary args` or `fallback 1 of 2 (--model claude-sonnet-4)`.
+pub fn describe(fallbacks: &[String], set: usize) -> String {
+    match set.checked_sub(1).and_then(|i| fallbacks.get(i)) {
+        None => "primary args".to_string(),
+        Some(args) => format!("fallback {set} of {} ({})", fallbacks.len(), args.trim()),
+    }
+}
@@ -0,0 +1,78 @@
+//! `--feed-previous-error ARG`: hand the previous attempt's stderr to the retry as
+//! `ARG <file>` (e.g. `--append-system-prompt-file`), so an agentic child can adjust instead
+//! of repeating the same mistake.
+//!
+//! The file holds the redacted tail of that stderr and only lives for the attempt it was
+//! written for.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::shellquote::redact_text;
+use crate::shutdown::{self, Priority, Registration};
+
+/// Bytes of the previous stderr passed on; the end is where the error usually is.
+pub const TAIL_BYTES: usize = 16 * 1024;
+
+/// `--feed-previous-error` takes the child flag the file is passed with.
+pub fn parse_arg(s: &str) -> Result<String, String> {
+    if s.starts_with('-') && s.len() > 1 && !s.contains(char::is_whitespace) {
+        Ok(s.to_string())
+    } else {
+        Err("expected a child flag such as --append-system-prompt-file".to_string())
+    }
+}
+
+/// Refuse setups where the child would get `arg` from two places and the model could be
+/// handed the wrong file.
+pub fn check(arg: &str, args: &[String], retry_extra_args: Option<&str>) -> Result<(), String> {
+    let clashes = |a: &str| a == arg || a.strip_prefix(arg).is_some_and(|r| r.starts_with('='));
+    if args.iter().any(|a| clashes(a)) {
+        return Err(format!("the child args already pass `{arg}`"));
+    }
+    if retry_extra_args.is_some_and(|extra| extra.split_whitespace().any(clashes)) {
+        return Err(format!("--retry-extra-args already passes `{arg}`"));
+    }
+    Ok(())
+}
+
+/// The last `TAIL_BYTES` of `stderr`, starting at a line boundary, with secrets redacted.
+pub fn tail(stderr: &[u8]) -> String {
+    if stderr.len() <= TAIL_BYTES {
+        return redact_text(&String::from_utf8_lossy(stderr));
+    }
+    let cut = &stderr[stderr.len() - TAIL_BYTES..];
+    let start = memchr::memchr(b'\n', cut).map_or(0, |i| i + 1);
+    let omitted = stderr.len() - cut.len() + start;
+    format!(
+        "[{omitted} earlier bytes omitted]\n{}",
+        redact_text(&String::from_utf8_lossy(&cut[start..]))
+    )
+}
+
+/// The previous error written out for one attempt; removed when the attempt is over.
+pub struct ErrorFile {
+    pub path: PathBuf,
+    pub bytes: usize,
+    _removal: Registration,
+}
+
+impl ErrorFile {
+    pub fn write(stderr: &[u8], attempt: u32) -> io::Result<Self> {
+        let path = std::env::temp_dir().join(format!(
+            "rusty-claude-previous-error-{}-{attempt}.txt",
+            std::process::id()
+        ));
+        let text = tail(stderr);
+        fs::write(&path, &text)?;
+        let removed = path.clone();
+        Ok(ErrorFile {
+            path,
+            bytes: text.len(),
+            _removal: shutdown::register("previous-error file", Priority::Normal, move || {
+                let _ = fs::remove_file(removed);
+            }),
+        })
+    }
+}
@@ -0,0 +1,169 @@
+//! Guard rails for `--retry-on-any-error`. A child that fails in a few milliseconds, exits
+//! with a usage-error code, or prints a usage complaint will fail the same way on every
+//! retry, so such a failure is not retried. The guards only ever veto a retry that exists
+//! because of `--retry-on-any-error`; a retry pattern match is never overridden.
+
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use clap::ValueEnum;
+use regex::RegexSet;
+
+use crate::duration::format_duration;
+
+/// Exit codes conventionally meaning "bad invocation": clap/getopt's 2 and sysexits'
+/// `EX_USAGE`.
+pub const USAGE_CODES: &[i32] = &[2, 64];
+
+/// Lines a CLI prints when it rejects its arguments.
+const USAGE_SIGNATURES: &[&str] = &[
+    r"(?im)^\s*usage:\s",
+    r"(?im)\bunknown (option|flag|argument|command|subcommand)\b",
+    r"(?im)\bunrecognized (option|argument|arguments)\b",
+    r"(?im)\bunexpected argument\b",
+    r"(?im)\binvalid (option|flag)\b",
+    r"(?im)\b(missing|required) (argument|option)\b",
+];
+
+fn signatures() -> &'static RegexSet {
+    static SET: OnceLock<RegexSet> = OnceLock::new();
+    SET.get_or_init(|| RegexSet::new(USAGE_SIGNATURES).expect("usage signatures compile"))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Guard {
+    /// The child exited sooner than --min-runtime-for-retry
+    Runtime,
+    /// The child exited with a usage-error code (2 or 64)
+    ExitCode,
+    /// The output looks like a usage or unknown-option error
+    UsageText,
+}
+
+impl Guard {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Guard::Runtime => "runtime",
+            Guard::ExitCode => "exit-code",
+            Guard::UsageText => "usage-text",
+        }
+    }
+}
+
+/// The active guards.
+#[derive(Clone, Debug)]
+pub struct Guards {
+    pub min_runtime: Duration,
+    /// Turned off with `--no-retry-guard`.
+    pub disabled: Vec<Guard>,
+}
+
+impl Guards {
+    fn on(&self, guard: Guard) -> bool {
+        !self.disabled.contains(&guard)
+    }
+
+    /// The first guard that vetoes retrying this failure, and why.
+    pub fn check(
+        &self,
+        code: Option<i32>,
+        runtime: Duration,
+        output: &str,
+    ) -> Option<(Guard, String)> {
+        if let Some(c) = code.filter(|c| self.on(Guard::ExitCode) && USAGE_CODES.contains(c)) {
+            return Some((
+                Guard::ExitCode,
+                format!("exit code {c} conventionally means a usage error"),
+            ));
+        }
+        if self.on(Guard::Runtime) && runtime < self.min_runtime {
+            return Some((
+                Guard::Runtime,
+                format!(
+                    "the child exited after {}, under --min-runtime-for-retry {}",
+                    format_duration(runtime),
+                    format_duration(self.min_runtime)
+                ),
+            ));
+        }
+        if self.on(Guard::UsageText) && signatures().is_match(output) {
+            return Some((
+                Guard::UsageText,
+                "its output looks like a usage error".to_string(),
+            ));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guards_in_order() {
+        let ms = Duration::from_millis;
+        let usage = "error: unknown option '--bogus'";
+        // code, runtime ms, stderr, min runtime ms, disabled guards, expected guard
+        type Row = (
+            Option<i32>,
+            u64,
+            &'static str,
+            u64,
+            &'static [Guard],
+            Option<Guard>,
+        );
+        let table: &[Row] = &[
+            (Some(2), 1000, "", 250, &[], Some(Guard::ExitCode)),
+            (Some(64), 1000, "", 250, &[], Some(Guard::ExitCode)),
+            (Some(1), 40, "", 250, &[], Some(Guard::Runtime)),
+            (None, 40, "", 250, &[], Some(Guard::Runtime)),
+            (Some(1), 0, "", 0, &[], None),
+            (Some(1), 1000, usage, 250, &[], Some(Guard::UsageText)),
+            (
+                Some(1),
+                1000,
+                "Usage: claude [options]",
+                250,
+                &[],
+                Some(Guard::UsageText),
+            ),
+            (Some(1), 1000, "API Error: connection reset", 250, &[], None),
+            (
+                Some(2),
+                40,
+                usage,
+                250,
+                &[Guard::ExitCode],
+                Some(Guard::Runtime),
+            ),
+            (
+                Some(2),
+                40,
+                usage,
+                250,
+                &[Guard::ExitCode, Guard::Runtime],
+                Some(Guard::UsageText),
+            ),
+            (
+                Some(2),
+                40,
+                usage,
+                250,
+                &[Guard::ExitCode, Guard::Runtime, Guard::UsageText],
+                None,
+            ),
+        ];
+        for &(code, runtime, stderr, min_runtime, disabled, want) in table {
+            let guards = Guards {
+                min_runtime: ms(min_runtime),
+                disabled: disabled.to_vec(),
+            };
+            let got = guards.check(code, ms(runtime), stderr).map(|(g, _)| g);
+            assert_eq!(
+                got, want,
+                "code={code:?} runtime={runtime}ms stderr={stderr:?} disabled={disabled:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//! `--isolated-home`: give the child a throwaway HOME so cached credentials, config, and
+//! state on the machine can't leak into or out of the run.
+
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::shutdown::{self, Priority, Registration};
+
+/// The child's home directory, removed on drop when this run created it.
+pub struct IsolatedHome {
+    pub path: PathBuf,
+    _removal: Option<Registration>,
+}
+
+/// Create (or reuse) the home directory, copying `template` into it when it was just
+/// created. Without `requested`, a fresh directory under the system temp dir is used.
+/// Only a directory created here is ever removed, and not with `keep`.
+pub fn prepare(
+    requested: Option<&Path>,
+    template: Option<&Path>,
+    keep: bool,
+) -> io::Result<IsolatedHome> {
+    let path = match requested {
+        Some(p) => p.to_path_buf(),
+        None => std::env::temp_dir().join(format!(
+            "rusty-claude-home-{}-{:08x}",
+            std::process::id(),
+            rand::random::<u32>()
+        )),
+    };
+    let created = !path.exists();
+    if created {
+        fs::create_dir_all(&path)?;
+        if let Some(template) = template {
+            copy_dir(template, &path)?;
+        }
+    } else if !path.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "exists and is not a directory",
+        ));
+    }
+    // Children resolve relative paths against these, so hand them an absolute one
+    let path = path.canonicalize()?;
+    let removed = path.clone();
+    Ok(IsolatedHome {
+        path,
+        _removal: (created && !keep).then(|| {
+            shutdown::register("isolated home", Priority::Normal, move || {
+                let _ = fs::remove_dir_all(removed);
+            })
+        }),
+    })
+}
+
+fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            fs::create_dir_all(&target)?;
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+impl IsolatedHome {
+    /// Variables pointing the child's home, XDG, and Windows profile dirs inside `path`.
+    pub fn env(&self) -> Vec<(OsString, OsString)> {
+        let under = |rel: &str| self.path.join(rel).into_os_string();
+        vec![
+            ("HOME".into(), self.path.clone().into_os_string()),
+            ("USERPROFILE".into(), self.path.clone().into_os_string()),
+            ("XDG_CONFIG_HOME".into(), under(".config")),
+            ("XDG_DATA_HOME".into(), under(".local/share")),
+            ("XDG_STATE_HOME".into(), under(".local/state")),
+            ("XDG_CACHE_HOME".into(), under(".cache")),
+            ("APPDATA".into(), under("AppData/Roaming")),
+            ("LOCALAPPDATA".into(), under("AppData/Local")),
+        ]
+    }
+}
@@ -0,0 +1,216 @@
+//! `--on-retry-cmd` and `--on-exhausted-cmd`: shell commands run before every retry's
+//! backoff, and when the retries have run out, to rotate whatever external state the next
+//! attempt depends on or to tell someone.
+//!
+//! Both get the context of the attempt that failed:
+//!
+//! - `RUSTY_CLAUDE_ATTEMPT`: the attempt about to start, or for `--on-exhausted-cmd` the last
+//!   one
+//! - `RUSTY_CLAUDE_RUN_ID`
+//! - `RUSTY_CLAUDE_EXIT_CODE`: the failed attempt's exit code, empty when it was killed by a
+//!   signal
+//! - `RUSTY_CLAUDE_CLASS`, `RUSTY_CLAUDE_MATCHED`: its error class and retry pattern, empty
+//!   when none matched
+//! - `RUSTY_CLAUDE_NEXT_DELAY_MS`: the backoff about to be waited, empty when giving up
+//! - `RUSTY_CLAUDE_CHILD_CMD`: the failed attempt's command line, quoted and redacted as in
+//!   `reproduce with:`
+//!
+//! `--on-retry-cmd` also gets `RUSTY_CLAUDE_ENV_FILE`, an empty file it may fill with
+//! `KEY=VALUE` lines. Those are layered over the next attempt's environment, so a hook can
+//! hand over a freshly fetched short-lived token without rusty-claude knowing anything about
+//! the auth system. The file is removed once it has been read.
+//!
+//! A hook past `--hook-timeout` is killed, with whatever it started. Neither hook can change
+//! what the run does: a failure is only a warning.
+
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::classes::ErrorClass;
+use crate::duration::format_duration;
+use crate::shutdown::{self, Priority};
+
+const POLL: Duration = Duration::from_millis(10);
+
+/// What the failed attempt and the run look like when a hook runs.
+pub struct Context<'a> {
+    pub attempt: u32,
+    pub run_id: &'a str,
+    pub code: Option<i32>,
+    pub class: Option<ErrorClass>,
+    pub matched: Option<&'a str>,
+    pub next_delay_ms: Option<u64>,
+    pub child_cmd: &'a str,
+}
+
+/// What a hook run produced for the next attempt.
+pub struct HookResult {
+    /// How the hook failed, if it exited unsuccessfully.
+    pub failure: Option<String>,
+    pub env: Vec<(OsString, OsString)>,
+    /// One message per env file line that was not a `KEY=VALUE` assignment.
+    pub warnings: Vec<String>,
+}
+
+fn valid_key(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Parse env file `text`: `KEY=VALUE` per line, blank lines and `#` comments skipped. The
+/// value is taken verbatim (no quotes or escapes), so tokens never need escaping.
+pub fn parse_env_file(text: &str) -> (Vec<(String, String)>, Vec<String>) {
+    let mut vars = Vec::new();
+    let mut warnings = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        match line.split_once('=') {
+            Some((key, value)) if valid_key(key) => vars.push((key.to_string(), value.to_string())),
+            Some((key, _)) => {
+                warnings.push(format!("line {}: invalid variable name `{key}`", n + 1))
+            }
+            None => warnings.push(format!("line {}: expected KEY=VALUE", n + 1)),
+        }
+    }
+    (vars, warnings)
+}
+
+/// `sh -c cmd`, or `cmd /C cmd` on Windows.
+pub fn shell(cmd: &str) -> Command {
+    if cfg!(windows) {
+        let mut c = Command::new("cmd");
+        c.arg("/C").arg(cmd);
+        c
+    } else {
+        let mut c = Command::new("sh");
+        c.arg("-c").arg(cmd);
+        c
+    }
+}
+
+/// Kill `child` and, on Unix, the process group it was started in.
+pub fn kill(child: &mut Child) {
+    #[cfg(unix)]
+    // SAFETY: signalling the process group we created for our own child.
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    let _ = child.kill();
+}
+
+/// Wait for `child` until `deadline`, then kill it; `Ok(None)` means it was killed.
+pub fn wait_until(child: &mut Child, deadline: Instant) -> io::Result<Option<ExitStatus>> {
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            kill(child);
+            let _ = child.wait();
+            return Ok(None);
+        }
+        thread::sleep(POLL);
+    }
+}
+
+/// Run hook `cmd` with `ctx` in its environment (plus `extra`), giving up on it after
+/// `timeout`. Its stdout goes to our stderr so it can't end up in the child's output stream.
+fn spawn(
+    cmd: &str,
+    ctx: &Context,
+    extra: &[(&str, &std::path::Path)],
+    timeout: Duration,
+) -> Result<ExitStatus, String> {
+    let mut command = shell(cmd);
+    // Its own group, so a timeout also kills whatever the shell started
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    let mut child = command
+        .env("RUSTY_CLAUDE_ATTEMPT", ctx.attempt.to_string())
+        .env(crate::runid::ENV_VAR, ctx.run_id)
+        .env(
+            "RUSTY_CLAUDE_EXIT_CODE",
+            ctx.code.map(|c| c.to_string()).unwrap_or_default(),
+        )
+        .env(
+            "RUSTY_CLAUDE_CLASS",
+            ctx.class.map(ErrorClass::as_str).unwrap_or_default(),
+        )
+        .env("RUSTY_CLAUDE_MATCHED", ctx.matched.unwrap_or_default())
+        .env(
+            "RUSTY_CLAUDE_NEXT_DELAY_MS",
+            ctx.next_delay_ms
+                .map(|ms| ms.to_string())
+                .unwrap_or_default(),
+        )
+        .env("RUSTY_CLAUDE_CHILD_CMD", ctx.child_cmd)
+        .envs(extra.iter().copied())
+        .stdin(Stdio::null())
+        .stdout(io::stderr())
+        .spawn()
+        .map_err(|e| format!("could not start: {e}"))?;
+    match wait_until(&mut child, Instant::now() + timeout) {
+        Ok(Some(status)) => Ok(status),
+        Ok(None) => Err(format!("timed out after {}", format_duration(timeout))),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Why a command that ran did not succeed.
+pub fn failure(status: ExitStatus) -> String {
+    match status.code() {
+        Some(code) => format!("exited with code {code}"),
+        None => "was killed by a signal".to_string(),
+    }
+}
+
+/// Run `--on-retry-cmd` ahead of `ctx.attempt`, collecting the variables it set for it.
+pub fn run(cmd: &str, ctx: &Context, timeout: Duration) -> Result<HookResult, String> {
+    let env_file = std::env::temp_dir().join(format!(
+        "rusty-claude-hook-env-{}-{}",
+        std::process::id(),
+        ctx.attempt
+    ));
+    fs::write(&env_file, "").map_err(|e| format!("{}: {e}", env_file.display()))?;
+    let removed = env_file.clone();
+    let removal = shutdown::register("hook env file", Priority::Normal, move || {
+        let _ = fs::remove_file(removed);
+    });
+    let status = spawn(
+        cmd,
+        ctx,
+        &[("RUSTY_CLAUDE_ENV_FILE", env_file.as_path())],
+        timeout,
+    );
+    let text = fs::read_to_string(&env_file);
+    drop(removal);
+    let status = status?;
+    let (vars, warnings) = parse_env_file(&text.map_err(|e| e.to_string())?);
+    Ok(HookResult {
+        failure: (!status.success()).then(|| failure(status)),
+        env: vars
+            .into_iter()
+            .map(|(k, v)| (OsString::from(k), OsString::from(v)))
+            .collect(),
+        warnings,
+    })
+}
+
+/// Run `--on-exhausted-cmd`.
+pub fn notify(cmd: &str, ctx: &Context, timeout: Duration) -> Result<(), String> {
+    let status = spawn(cmd, ctx, &[], timeout)?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(failure(status))
+    }
+}
@@ -0,0 +1,90 @@
+//! `--expect-cmd-sha256`: refuse to exec a child binary whose contents aren't a known
+//! artifact.
+//!
+//! The command is resolved to an absolute path with symlinks followed, and that final
+//! target is both hashed and what gets spawned, so `PATH` can't be swapped in between.
+
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use sha2::{Digest, Sha256};
+
+/// Lower-case hex SHA-256 of a file's contents.
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect())
+}
+
+/// Validate a `--expect-cmd-sha256` value.
+pub fn parse_digest(s: &str) -> Result<String, String> {
+    let s = s.trim().to_ascii_lowercase();
+    if s.len() == 64 && s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        Ok(s)
+    } else {
+        Err("expected 64 hex digits".to_string())
+    }
+}
+
+/// The verified child binary; `recheck` hashes it again if it changed on disk.
+pub struct Pinned {
+    pub path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn check(path: &Path, expected: &[String]) -> Result<(), String> {
+    let actual = sha256_file(path).map_err(|e| format!("cannot hash {}: {e}", path.display()))?;
+    if expected.contains(&actual) {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} has sha256 {actual}, which is not an expected digest",
+            path.display()
+        ))
+    }
+}
+
+/// Resolve `cmd` (through `PATH` if it has no directory part) to its final target and
+/// verify it against the accepted digests.
+pub fn verify(cmd: &str, expected: &[String]) -> Result<Pinned, String> {
+    let path = crate::resolve::locate(cmd)
+        .ok_or_else(|| format!("`{cmd}` is not on PATH"))?
+        .canonicalize()
+        .map_err(|e| format!("cannot resolve `{cmd}`: {e}"))?;
+    check(&path, expected)?;
+    Ok(Pinned {
+        modified: modified(&path),
+        path,
+    })
+}
+
+impl Pinned {
+    /// Re-verify before another spawn when the file's mtime has changed since the last check.
+    pub fn recheck(&mut self, expected: &[String]) -> Result<(), String> {
+        let now = modified(&self.path);
+        if now == self.modified {
+            return Ok(());
+        }
+        check(&self.path, expected)?;
+        self.modified = now;
+        Ok(())
+    }
+}
@@ -0,0 +1,155 @@
+//! Ctrl-C and SIGTERM during a supervised run. Once the retry loop [`claim`]s them, such a
+//! signal no longer ends the wrapper: it is passed on to the running attempt, and the run
+//! stops retrying, ending with 128 + the signal once that attempt is gone. A second one
+//! kills the attempt outright, for a child that ignores the first. The wait between
+//! attempts notices through [`received`] and ends early.
+//!
+//! On Unix the signal arrives through the [`shutdown`](crate::shutdown) watcher thread,
+//! which leaves any it isn't claimed for (SIGHUP, or anything before the loop starts) to
+//! end the process. On Windows the console already delivers Ctrl-C to the child, so the
+//! handler only records it; a second one terminates the child.
+
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU32, Ordering};
+
+static CLAIMED: AtomicBool = AtomicBool::new(false);
+/// The first signal received, or 0.
+static SIGNAL: AtomicI32 = AtomicI32::new(0);
+static COUNT: AtomicU32 = AtomicU32::new(0);
+/// Pid of the running attempt, or 0 between attempts.
+static CHILD: AtomicU32 = AtomicU32::new(0);
+
+/// SIGINT, which a Windows Ctrl-C counts as.
+#[cfg(not(unix))]
+const SIGINT: i32 = 2;
+
+/// Take over SIGINT and SIGTERM for the rest of the run.
+pub fn claim() {
+    CLAIMED.store(true, Ordering::Relaxed);
+    #[cfg(windows)]
+    windows::install();
+}
+
+/// Pass signals on to `pid` until [`unwatch`]; one that came before it existed goes to it now.
+pub fn watch(pid: u32) {
+    CHILD.store(pid, Ordering::Relaxed);
+    if let Some(sig) = received() {
+        forward(pid, sig, COUNT.load(Ordering::Relaxed));
+    }
+}
+
+/// The attempt has been waited for; its pid may belong to someone else from now on.
+pub fn unwatch() {
+    CHILD.store(0, Ordering::Relaxed);
+}
+
+/// The first SIGINT or SIGTERM the run received, if any.
+pub fn received() -> Option<i32> {
+    Some(SIGNAL.load(Ordering::Relaxed)).filter(|&sig| sig != 0)
+}
+
+/// The conventional exit code for dying of `sig`.
+pub fn exit_code(sig: i32) -> i32 {
+    128 + sig
+}
+
+pub fn name(sig: i32) -> &'static str {
+    #[cfg(unix)]
+    match sig {
+        libc::SIGINT => "SIGINT",
+        libc::SIGTERM => "SIGTERM",
+        _ => "a signal",
+    }
+    #[cfg(not(unix))]
+    match sig {
+        SIGINT => "Ctrl-C",
+        _ => "a signal",
+    }
+}
+
+/// Handle `sig` if it is claimed, returning whether it was.
+pub fn handle(sig: i32) -> bool {
+    #[cfg(unix)]
+    let ours = sig == libc::SIGINT || sig == libc::SIGTERM;
+    #[cfg(not(unix))]
+    let ours = sig == SIGINT;
+    if !ours || !CLAIMED.load(Ordering::Relaxed) {
+        return false;
+    }
+    let _ = SIGNAL.compare_exchange(0, sig, Ordering::Relaxed, Ordering::Relaxed);
+    let count = COUNT.fetch_add(1, Ordering::Relaxed) + 1;
+    match CHILD.load(Ordering::Relaxed) {
+        0 => eprintln!("[rusty-claude] {} received; not retrying", name(sig)),
+        pid => forward(pid, sig, count),
+    }
+    true
+}
+
+/// Pass the `count`th signal on to the attempt: the signal itself the first time, a kill
+/// after that.
+fn forward(pid: u32, sig: i32, count: u32) {
+    if count <= 1 {
+        #[cfg(unix)]
+        crate::tree::signal(pid, sig);
+        eprintln!(
+            "[rusty-claude] {} received; passed it on to the attempt and not retrying \
+            (again to kill the attempt)",
+            name(sig)
+        );
+    } else {
+        #[cfg(unix)]
+        crate::tree::signal(pid, libc::SIGKILL);
+        #[cfg(windows)]
+        if !crate::tree::terminate(pid) {
+            windows::terminate(pid);
+        }
+        eprintln!(
+            "[rusty-claude] {} received again; killed the attempt",
+            name(sig)
+        );
+    }
+}
+
+#[cfg(windows)]
+mod windows {
+    use std::ffi::c_void;
+
+    const CTRL_C_EVENT: u32 = 0;
+    const CTRL_BREAK_EVENT: u32 = 1;
+    const PROCESS_TERMINATE: u32 = 0x0001;
+
+    extern "system" {
+        fn SetConsoleCtrlHandler(
+            handler: Option<unsafe extern "system" fn(u32) -> i32>,
+            add: i32,
+        ) -> i32;
+        fn OpenProcess(access: u32, inherit: i32, pid: u32) -> *mut c_void;
+        fn TerminateProcess(process: *mut c_void, code: u32) -> i32;
+        fn CloseHandle(handle: *mut c_void) -> i32;
+    }
+
+    /// Runs on a thread of its own, so it can do everything [`super::handle`] does.
+    unsafe extern "system" fn on_ctrl(event: u32) -> i32 {
+        match event {
+            CTRL_C_EVENT | CTRL_BREAK_EVENT => i32::from(super::handle(super::SIGINT)),
+            _ => 0,
+        }
+    }
+
+    pub fn install() {
+        // SAFETY: registering a handler that lives as long as the process.
+        unsafe {
+            SetConsoleCtrlHandler(Some(on_ctrl), 1);
+        }
+    }
+
+    pub fn terminate(pid: u32) {
+        // SAFETY: the handle is checked, used once, and closed.
+        unsafe {
+            let process = OpenProcess(PROCESS_TERMINATE, 0, pid);
+            if !process.is_null() {
+                TerminateProcess(process, 1);
+                CloseHandle(process);
+            }
+        }
+    }
+}
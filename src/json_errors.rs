@@ -0,0 +1,216 @@
+//! `--json-errors`: take the retry decision from the structured error in
+//! `--output-format json` or `stream-json` output instead of pattern matches, which can fire
+//! on a prompt that happens to mention "429" and miss an error reported with exit code 0.
+//!
+//! stdout is read as one JSON document, or failing that as its last non-empty line (the
+//! end of a stream); an array stands for its last element. An error is then:
+//!
+//! - `{"type":"error","error":{"type":"overloaded_error",...}}`, the API's error body
+//! - any object with an `error.type`
+//! - `{"type":"result","is_error":true,"result":"API Error: 529 {...}"}`, the CLI's result
+//!   with the API response embedded in its text (the status code stands in for the type
+//!   when no body follows)
+//!
+//! A `retry_after` (seconds) or `retry_after_ms` field on the object, its `error`, or the
+//! embedded body is the wait the server asked for.
+
+use serde_json::Value;
+
+use crate::classes::ErrorClass;
+
+/// What stdout says about the attempt.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Found {
+    /// It holds an error of this type.
+    Error(JsonError),
+    /// It is JSON without an error.
+    Clean,
+    /// It isn't JSON, or names an error without saying which.
+    Unparsed,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct JsonError {
+    /// `error.type`, e.g. `overloaded_error`.
+    pub error_type: String,
+    pub retry_after_ms: Option<u64>,
+}
+
+impl JsonError {
+    /// The class of a retryable error type; `None` for the rest (bad requests, auth).
+    pub fn class(&self) -> Option<ErrorClass> {
+        match self.error_type.as_str() {
+            "overloaded_error" | "api_error" => Some(ErrorClass::Server),
+            "rate_limit_error" => Some(ErrorClass::RateLimit),
+            _ => None,
+        }
+    }
+}
+
+/// The error type the API uses for an HTTP status.
+fn type_for_status(status: u16) -> Option<&'static str> {
+    Some(match status {
+        400 => "invalid_request_error",
+        401 => "authentication_error",
+        403 => "permission_error",
+        404 => "not_found_error",
+        413 => "request_too_large",
+        429 => "rate_limit_error",
+        529 => "overloaded_error",
+        500..=599 => "api_error",
+        _ => return None,
+    })
+}
+
+fn retry_after_ms(v: &Value) -> Option<u64> {
+    let secs = |v: &Value| match v {
+        Value::String(s) => s.trim().parse::<f64>().ok(),
+        v => v.as_f64(),
+    };
+    let ms = v["retry_after_ms"]
+        .as_f64()
+        .or_else(|| secs(&v["retry_after"]).map(|s| s * 1000.0))?;
+    (ms.is_finite() && ms >= 0.0).then(|| ms.round() as u64)
+}
+
+/// The error an API error body describes.
+fn api_error(v: &Value) -> Option<JsonError> {
+    let error_type = v["error"]["type"].as_str()?;
+    Some(JsonError {
+        error_type: error_type.to_string(),
+        retry_after_ms: retry_after_ms(&v["error"]).or_else(|| retry_after_ms(v)),
+    })
+}
+
+/// The error in a CLI result's `API Error: 529 {...}` text.
+fn embedded_error(v: &Value) -> Option<JsonError> {
+    let text = v["result"].as_str()?;
+    let rest = &text[text.find("API Error:")? + "API Error:".len()..];
+    let rest = rest.trim_start();
+    let digits = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    let status: u16 = rest[..digits].parse().ok()?;
+    let body: Option<Value> = serde_json::from_str(rest[digits..].trim()).ok();
+    if let Some(error) = body.as_ref().and_then(api_error) {
+        return Some(error);
+    }
+    Some(JsonError {
+        error_type: type_for_status(status)?.to_string(),
+        retry_after_ms: retry_after_ms(v),
+    })
+}
+
+/// Read the error, if any, out of an attempt's stdout.
+pub fn inspect(stdout: &str) -> Found {
+    let value = match serde_json::from_str::<Value>(stdout.trim()) {
+        Ok(v) => v,
+        Err(_) => {
+            let last = stdout.lines().map(str::trim).rfind(|l| !l.is_empty());
+            match last.and_then(|l| serde_json::from_str(l).ok()) {
+                Some(v) => v,
+                None => return Found::Unparsed,
+            }
+        }
+    };
+    let value = match value {
+        Value::Array(mut items) => match items.pop() {
+            Some(last) => last,
+            None => return Found::Clean,
+        },
+        v => v,
+    };
+    if !value.is_object() {
+        return Found::Unparsed;
+    }
+    if let Some(error) = api_error(&value) {
+        return Found::Error(error);
+    }
+    if value["is_error"] == true {
+        return embedded_error(&value).map_or(Found::Unparsed, Found::Error);
+    }
+    if value["type"] == "error" {
+        return Found::Unparsed;
+    }
+    Found::Clean
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payloads() {
+        let error = |t: &str, retry_after_ms| {
+            Found::Error(JsonError {
+                error_type: t.to_string(),
+                retry_after_ms,
+            })
+        };
+        let table: &[(&str, Found)] = &[
+            (
+                r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#,
+                error("overloaded_error", None),
+            ),
+            (
+                r#"{"type":"error","error":{"type":"rate_limit_error","retry_after":30}}"#,
+                error("rate_limit_error", Some(30_000)),
+            ),
+            (
+                r#"{"type":"error","retry_after_ms":1500,"error":{"type":"api_error"}}"#,
+                error("api_error", Some(1_500)),
+            ),
+            (
+                r#"{"type":"result","subtype":"success","is_error":true,"duration_ms":812,"result":"API Error: 529 {\"type\":\"error\",\"error\":{\"type\":\"overloaded_error\",\"message\":\"Overloaded\"}}","session_id":"0b4c"}"#,
+                error("overloaded_error", None),
+            ),
+            (
+                r#"{"type":"result","is_error":true,"result":"API Error: 429 rate limited","retry_after":"2.5"}"#,
+                error("rate_limit_error", Some(2_500)),
+            ),
+            (
+                r#"{"type":"result","is_error":true,"result":"API Error: 401 {\"type\":\"error\",\"error\":{\"type\":\"authentication_error\",\"message\":\"invalid x-api-key\"}}"}"#,
+                error("authentication_error", None),
+            ),
+            (
+                "{\"type\":\"system\",\"subtype\":\"init\"}\n{\"type\":\"assistant\"}\n\
+                {\"type\":\"error\",\"error\":{\"type\":\"overloaded_error\"}}\n",
+                error("overloaded_error", None),
+            ),
+            (
+                r#"[{"type":"system"},{"type":"result","is_error":true,"result":"API Error: 529 Overloaded"}]"#,
+                error("overloaded_error", None),
+            ),
+            (
+                r#"{"type":"result","is_error":false,"result":"HTTP 429 is Too Many Requests"}"#,
+                Found::Clean,
+            ),
+            (
+                r#"[{"type":"error","error":{"type":"api_error"}},{"type":"result"}]"#,
+                Found::Clean,
+            ),
+            ("[]", Found::Clean),
+            (
+                r#"{"type":"result","is_error":true,"result":"Something broke"}"#,
+                Found::Unparsed,
+            ),
+            (r#"{"type":"error","message":"?"}"#, Found::Unparsed),
+            ("API Error: 529 Overloaded", Found::Unparsed),
+            ("{\"type\":\"result\",\"resu", Found::Unparsed),
+            ("", Found::Unparsed),
+        ];
+        for (text, want) in table {
+            let got = inspect(text);
+            assert_eq!(&got, want, "{text:?}");
+            if let Found::Error(e) = &got {
+                // Only the authentication error is not retried
+                assert_eq!(
+                    e.class().is_some(),
+                    e.error_type != "authentication_error",
+                    "{}",
+                    e.error_type
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,17 @@
+//! The retry engine behind `rusty-claude`, for Rust tooling that runs the Claude CLI itself.
+//!
+//! [`Supervisor`] runs a command under a [`RetryPolicy`] and returns a [`RunReport`] of every
+//! attempt. The pieces it is built from are public too, and the binary uses the same ones:
+//! [`patterns`] (the retry and fatal patterns and [`should_retry`]), [`backoff`] (delays
+//! between attempts), and [`retry_after`] (waits the output asks for).
+
+pub mod backoff;
+pub mod classes;
+pub mod code_list;
+pub mod exec;
+pub mod patterns;
+pub mod retry_after;
+pub mod tz;
+
+pub use exec::{Attempt, RetryPolicy, RunReport, Step, Supervisor};
+pub use patterns::{should_retry, Output, Patterns, RetryDecision};
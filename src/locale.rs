@@ -0,0 +1,57 @@
+//! `--stable-locale`: run the child under a fixed locale so its error messages stay in the
+//! English the retry patterns are written for.
+
+use std::env;
+use std::ffi::OsString;
+use std::process::{Command, Stdio};
+
+/// Variables that select the language or encoding of the child's messages.
+const INHERITED: &[&str] = &["LC_ALL", "LC_MESSAGES", "LANG", "LANGUAGE"];
+
+/// `C.UTF-8` where the system provides it, plain `C` otherwise.
+fn pick() -> &'static str {
+    let listed = Command::new("locale")
+        .arg("-a")
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output();
+    match listed {
+        Ok(out) if out.status.success() => {
+            let has_utf8 = String::from_utf8_lossy(&out.stdout)
+                .lines()
+                .any(|l| l.trim().replace('-', "").eq_ignore_ascii_case("c.utf8"));
+            if has_utf8 {
+                "C.UTF-8"
+            } else {
+                "C"
+            }
+        }
+        // No `locale` tool (musl, Windows): C.UTF-8 is built in everywhere but macOS
+        _ if cfg!(target_os = "macos") => "C",
+        _ => "C.UTF-8",
+    }
+}
+
+/// Overrides for the child environment, and a description of what they replace.
+pub fn stable_env() -> (Vec<(OsString, OsString)>, String) {
+    let locale = pick();
+    let replaced: Vec<String> = INHERITED
+        .iter()
+        .filter_map(|name| {
+            let value = env::var(name).ok().filter(|v| !v.is_empty())?;
+            Some(format!("{name}={value}"))
+        })
+        .collect();
+    let note = if replaced.is_empty() {
+        format!("LC_ALL={locale}")
+    } else {
+        format!("LC_ALL={locale}, overriding {}", replaced.join(" "))
+    };
+    let vars = vec![
+        ("LC_ALL".into(), locale.into()),
+        ("LANG".into(), locale.into()),
+        // gettext consults LANGUAGE before LC_ALL unless the locale is C
+        ("LANGUAGE".into(), OsString::new()),
+    ];
+    (vars, note)
+}
@@ -0,0 +1,115 @@
+//! `--log-file PATH`: one JSON line per attempt and a summary line at the end, so a run that
+//! failed overnight leaves more behind than what scrolled past on stderr.
+//!
+//! The file is opened for appending and every line goes out in a single `write`, so runs
+//! from several shells sharing one file interleave whole lines. Nothing that goes wrong with
+//! the log stops the run: a failed open or write is a warning, given once.
+
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+use serde_json::{json, Map, Value};
+
+use crate::artifacts::unix_ms;
+use crate::exit_codes::Outcome;
+use crate::memory;
+use crate::schema;
+use crate::shellquote;
+
+/// The attempt's outcome fields the log carries over from its metadata.
+const ATTEMPT_FIELDS: &[&str] = &[
+    "code",
+    "retry",
+    "matched",
+    "class",
+    "delay_ms",
+    "duration_ms",
+    "stdout_bytes",
+    "stderr_bytes",
+];
+
+struct Log {
+    sink: Mutex<Box<dyn Write + Send>>,
+    path: String,
+    run_id: String,
+}
+
+static LOG: OnceLock<Log> = OnceLock::new();
+static WARNED: AtomicBool = AtomicBool::new(false);
+
+fn warn(path: &str, e: &io::Error) {
+    if !WARNED.swap(true, Ordering::Relaxed) {
+        eprintln!("[rusty-claude] warning: --log-file {path}: {e}; the run goes on without it");
+    }
+}
+
+/// Append to `path`, or write to stderr when it is `-`, for the rest of the run.
+pub fn open(path: &Path, run_id: &str) {
+    let sink: Box<dyn Write + Send> = if path == Path::new("-") {
+        Box::new(io::stderr())
+    } else {
+        match OpenOptions::new().create(true).append(true).open(path) {
+            Ok(file) => Box::new(file),
+            Err(e) => return warn(&path.display().to_string(), &e),
+        }
+    };
+    let _ = LOG.set(Log {
+        sink: Mutex::new(sink),
+        path: path.display().to_string(),
+        run_id: run_id.to_string(),
+    });
+}
+
+/// Write the `fields` of a JSON object after the common `record`, `ts`, and `run_id`.
+fn write(kind: &str, fields: Value) {
+    let Some(log) = LOG.get() else {
+        return;
+    };
+    let mut line = Map::new();
+    line.insert("schema".into(), schema::LOG.into());
+    line.insert("record".into(), kind.into());
+    line.insert("ts".into(), unix_ms().into());
+    line.insert("run_id".into(), log.run_id.clone().into());
+    if let Value::Object(fields) = fields {
+        line.extend(fields);
+    }
+    let mut text = Value::Object(line).to_string();
+    text.push('\n');
+    let mut sink = log.sink.lock().unwrap_or_else(|e| e.into_inner());
+    if let Err(e) = sink.write_all(text.as_bytes()).and_then(|()| sink.flush()) {
+        warn(&log.path, &e);
+    }
+}
+
+/// Log a finished attempt (1-based) of `cmd args`, from the `outcome` its metadata records.
+pub fn attempt(attempt: u32, cmd: &str, args: &[String], outcome: &Value) {
+    let mut record = Map::new();
+    record.insert("attempt".into(), attempt.into());
+    record.insert("cmd".into(), cmd.into());
+    record.insert("args".into(), shellquote::redacted(args).into());
+    for &field in ATTEMPT_FIELDS {
+        record.insert(
+            field.into(),
+            outcome.get(field).cloned().unwrap_or(Value::Null),
+        );
+    }
+    write("attempt", Value::Object(record));
+}
+
+/// Log how the whole run ended.
+pub fn summary(outcome: &Outcome, duration_ms: u64) {
+    let record = json!({
+        "attempts": outcome.attempts,
+        "reason": outcome.reason.as_str(),
+        "exit_code": outcome.exit_code,
+        "child_exit_code": outcome.child_code,
+        "duration_ms": duration_ms,
+        "pre_delay_ms": crate::summary::pre_delay_ms(),
+        "matched": outcome.matched.iter().map(|(pattern, _)| pattern).collect::<Vec<_>>(),
+        "peak_buffer_bytes": memory::peak(),
+    });
+    write("summary", record);
+}
@@ -1,11 +1,80 @@
-use clap::{ArgAction, Parser};
-use rand::Rng;
-use regex::Regex;
+mod argenv;
+mod artifacts;
+mod batch;
+mod bench;
+mod budget;
+mod chaos;
+mod child_env;
+mod ci;
+mod claude_settings;
+mod config;
+mod control;
+mod delay_cmd;
+mod doctor;
+mod duration;
+mod edit;
+mod envvars;
+mod events;
+mod exit_codes;
+mod fake_child;
+mod fallback;
+mod feed;
+mod guard;
+mod home;
+mod hook;
+mod integrity;
+mod interrupt;
+mod json_errors;
+mod locale;
+mod log_file;
+mod memory;
+mod mode;
+mod observe;
+mod pattern_file;
+mod piped;
+mod policy;
+#[cfg(unix)]
+mod pty;
+mod resolve;
+mod resume;
+mod run;
+mod runid;
+mod schema;
+mod selftest;
+mod server;
+mod settings;
+mod shellquote;
+mod shutdown;
+mod signals;
+mod simulate;
+mod size;
+mod spool;
+mod stats;
+mod summary;
+mod tag;
+mod title;
+mod trace;
+mod tree;
+mod tty;
+mod utf8;
+mod verdict;
+mod version;
+mod waste;
+
+use ci::CiMode;
+use clap::parser::ValueSource;
+use clap::{ArgAction, ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
+use classes::ClassBudget;
+use code_list::CodeList;
+use duration::{format_duration, parse_duration};
+use exit_codes::{Outcome, Reason};
+use rusty_claude::patterns::MatchStreams;
+use rusty_claude::{backoff, classes, code_list, tz};
+use settings::{Setting, Source};
+use size::{format_size, parse_size};
 use std::env;
-use std::io::{self, Read, Write};
-use std::process::{Command, Stdio};
-use std::thread;
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 /// Retry wrapper for the official Claude CLI/EXE.
 ///
@@ -13,10 +82,14 @@ use std::time::Duration;
 ///   rusty-claude                          # interactive (TTY, no args)
 ///   echo '{"...": "..."}' | rusty-claude -- --json
 ///   rusty-claude -- --help               # pass args after `--` to the child CLI
+///   rusty-claude self-test               # verify this binary against built-in scenarios
 #[derive(Parser, Debug)]
-#[command(name="rusty-claude", about="A retry wrapper for the official Claude CLI/EXE", version=env!("CARGO_PKG_VERSION"))]
+#[command(name="rusty-claude", about="A retry wrapper for the official Claude CLI/EXE", version=env!("CARGO_PKG_VERSION"), disable_help_subcommand = true)]
 struct Cli {
-    /// Command to run (default: `claude` on Linux, `claude.exe` on Windows)
+    #[command(subcommand)]
+    command: Option<Commands>,
+
+    /// Command to run (default: `claude`, found on Windows through PATH and PATHEXT)
     #[arg(long)]
     cmd: Option<String>,
 
@@ -36,277 +109,1339 @@ struct Cli {
     #[arg(long, default_value_t = 20_000)]
     max_delay_ms: u64,
 
+    /// Longest server-requested wait (Retry-After, rate-limit reset) honored, in ms; a
+    /// longer one falls back to the normal backoff
+    #[arg(long, value_name = "MS", default_value_t = 600_000)]
+    max_retry_after_ms: u64,
+
+    /// Time zone of usage-limit reset times that name none ("resets at 7pm"): an IANA name
+    /// like America/Los_Angeles, an abbreviation, or UTC+2; defaults to the host's zone
+    #[arg(long, value_name = "TZ")]
+    assume_tz: Option<String>,
+
+    /// How the delay grows between attempts
+    #[arg(
+        long,
+        value_enum,
+        default_value = "exponential",
+        value_name = "STRATEGY"
+    )]
+    backoff_strategy: backoff::Strategy,
+
+    /// Growth factor per attempt for exponential and full-jitter backoff, e.g. 1.5
+    #[arg(long, value_parser = backoff::parse_multiplier, default_value = "2", value_name = "FACTOR")]
+    backoff_multiplier: f64,
+
+    /// Seed the backoff jitter, so a given seed gives the same delays on every run.
+    /// ENV: RUSTY_CLAUDE_SEED
+    #[arg(long, value_name = "U64")]
+    jitter_seed: Option<u64>,
+
+    /// Print the delay before each retry under the current settings, then exit without
+    /// running anything: its range when unseeded, the exact values under --jitter-seed
+    #[arg(long, action = ArgAction::SetTrue)]
+    print_backoff: bool,
+
+    /// Wait this long before the first attempt, e.g. after a known rate-limit event.
+    /// ENV: RUSTY_CLAUDE_INITIAL_DELAY
+    #[arg(long, value_parser = parse_duration)]
+    initial_delay: Option<Duration>,
+
+    /// Stop retrying when the next wait would end past this many milliseconds since the
+    /// run began (the child's running time included); exits with --total-timeout-exit-code.
+    /// ENV: RUSTY_CLAUDE_MAX_TOTAL_MS
+    #[arg(long, value_name = "MS")]
+    max_total_ms: Option<u64>,
+
+    /// Per-class retry limits within --max-retries, e.g. `ratelimit=10,server=2,network=3`;
+    /// matches of user patterns count against the global limit only
+    #[arg(long, value_parser = classes::parse_class_budget, value_name = "CLASS=N,...")]
+    class_budget: Option<ClassBudget>,
+
     /// Retry even when no overload pattern matches (any non-zero exit)
     #[arg(long, action = ArgAction::SetTrue)]
     retry_on_any_error: bool,
 
-    /// Force tee piping even if TTY interactive (debug capture); default off
+    /// Exit codes to retry even when no pattern matches, e.g. `1,75,70-78` (`signal` for a
+    /// child killed by one). Unlike --retry-on-any-error, the guards don't veto these; a
+    /// fatal pattern or --no-retry-exit-codes still does
+    #[arg(long, value_name = "CODES")]
+    retry_exit_codes: Option<CodeList>,
+
+    /// Exit codes never to retry, even when a retry pattern matches or they are also in
+    /// --retry-exit-codes, e.g. `2,64`
+    #[arg(long, value_name = "CODES")]
+    no_retry_exit_codes: Option<CodeList>,
+
+    /// With --retry-on-any-error, don't retry a child that failed sooner than this (0 to
+    /// always retry)
+    #[arg(long, value_parser = parse_duration, default_value = "250ms", value_name = "DURATION")]
+    min_runtime_for_retry: Duration,
+
+    /// Turn off a --retry-on-any-error guard (runtime, exit-code, usage-text; repeatable or
+    /// comma-separated)
+    #[arg(long, value_enum, value_delimiter = ',', value_name = "GUARD")]
+    no_retry_guard: Vec<guard::Guard>,
+
+    /// Capture the child's output even in an interactive terminal session, so retry patterns
+    /// apply to it: the session runs on a PTY relayed by rusty-claude (Unix only; an error
+    /// elsewhere). Piped runs are always teed
     #[arg(long, action = ArgAction::SetTrue)]
     force_tee: bool,
 
-    /// Extra retry regex patterns (pipe-separated). ENV override: CLAUDE_SUPERVISOR_PATTERNS
+    /// Expand $VAR and ${VAR} in the child args once per run ($$ for a literal $); unset
+    /// variables are an error, or empty with `=lossy`
+    #[arg(
+        long,
+        value_enum,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "strict",
+        value_name = "MODE"
+    )]
+    expand_arg_env: Option<argenv::ExpandMode>,
+
+    /// Print the resolved command line and exit without running anything
+    #[arg(long, action = ArgAction::SetTrue)]
+    dry_run: bool,
+
+    /// Log more supervisor detail to stderr (the child's argv, ...)
+    #[arg(short, long, action = ArgAction::Count)]
+    verbose: u8,
+
+    /// Show durations in messages as whole milliseconds (`18273ms`) instead of `18.3s`, for
+    /// log parsers that expect the old format; JSON output is exact milliseconds either way
+    #[arg(long, action = ArgAction::SetTrue)]
+    raw_durations: bool,
+
+    /// Extra retry regex patterns (pipe-separated). ENV: RUSTY_CLAUDE_PATTERNS
     #[arg(long)]
     patterns: Option<String>,
-}
 
-fn default_cmd() -> String {
-    #[cfg(windows)]
-    {
-        "claude.exe".to_string()
-    }
-    #[cfg(not(windows))]
-    {
-        "claude".to_string()
-    }
+    /// Read more retry patterns from this file, one regex per line (`|` is alternation, not a
+    /// separator); blank lines and `#` comments are skipped
+    #[arg(long, value_name = "PATH")]
+    patterns_file: Option<PathBuf>,
+
+    /// Drop the built-in retry patterns, leaving only your own
+    #[arg(long, action = ArgAction::SetTrue)]
+    no_default_patterns: bool,
+
+    /// Regex patterns (pipe-separated) that mark a failure no retry can fix, checked before
+    /// the retry patterns. ENV: RUSTY_CLAUDE_FATAL_PATTERNS
+    #[arg(long)]
+    fatal_patterns: Option<String>,
+
+    /// Wait at least MS after retry pattern REGEX matches, whatever the backoff or the
+    /// output's Retry-After says; REGEX is added as a retry pattern if it isn't one. When
+    /// several patterns match the same line, the largest minimum wins (repeatable; `=0`
+    /// drops the minimum of the built-in pattern with the identical text only)
+    #[arg(long, value_parser = policy::parse_pattern_delay, value_name = "REGEX=MS")]
+    pattern_delay: Vec<(String, u64)>,
+
+    /// Drop the built-in fatal patterns (401, 403, invalid API key, credit balance)
+    #[arg(long, action = ArgAction::SetTrue)]
+    no_default_fatal_patterns: bool,
+
+    /// If the command is not on PATH, use the first installation found in well-known locations
+    #[arg(long, action = ArgAction::SetTrue)]
+    auto_discover_cmd: bool,
+
+    /// Time budget for scanning output against retry patterns (e.g. 2s); when exceeded the
+    /// decision falls back to the exit code alone
+    #[arg(long, value_parser = parse_duration)]
+    match_timeout: Option<Duration>,
+
+    /// Write attempt-NN.stdout, .stderr, and .meta.json for every attempt into DIR
+    /// (non-interactive only)
+    #[arg(long, value_name = "DIR", visible_alias = "capture-dir")]
+    attempt_artifacts: Option<PathBuf>,
+
+    /// Which attempts' files --attempt-artifacts keeps
+    #[arg(
+        long,
+        visible_alias = "capture-keep",
+        value_enum,
+        default_value_t = artifacts::Keep::All,
+        requires = "attempt_artifacts"
+    )]
+    attempt_artifacts_keep: artifacts::Keep,
+
+    /// Gzip the --attempt-artifacts output files (`attempt-NN.stdout.gz`)
+    #[arg(long, action = ArgAction::SetTrue, requires = "attempt_artifacts")]
+    compress_artifacts: bool,
+
+    /// Give the child a throwaway HOME (and XDG/profile dirs) at PATH, or in a fresh temp
+    /// directory; a directory created here is removed afterwards
+    #[arg(
+        long,
+        num_args = 0..=1,
+        require_equals = true,
+        value_name = "PATH"
+    )]
+    isolated_home: Option<Option<PathBuf>>,
+
+    /// Copy this directory into a newly created --isolated-home
+    #[arg(long, value_name = "DIR", requires = "isolated_home")]
+    home_template: Option<PathBuf>,
+
+    /// Leave the --isolated-home directory in place after the run
+    #[arg(long, action = ArgAction::SetTrue, requires = "isolated_home")]
+    keep_isolated_home: bool,
+
+    /// Run the child with LC_ALL=C.UTF-8 (or C) so its error messages match the English
+    /// retry patterns. ENV: RUSTY_CLAUDE_STABLE_LOCALE
+    #[arg(long, action = ArgAction::SetTrue)]
+    stable_locale: bool,
+
+    /// Set a variable in the child's environment, overriding an inherited one (repeatable)
+    #[arg(long = "env", value_parser = child_env::parse_assignment, value_name = "KEY=VALUE")]
+    child_vars: Vec<(String, String)>,
+
+    /// Keep an inherited variable out of the child's environment; `*` and `?` globs such as
+    /// `AWS_*` are accepted (repeatable)
+    #[arg(long, value_name = "KEY")]
+    unset_env: Vec<String>,
+
+    /// Start the child's environment from PATH, HOME, and TERM only, plus whatever --env sets
+    #[arg(long, action = ArgAction::SetTrue)]
+    no_inherit_env: bool,
+
+    /// Refuse to run unless the resolved child binary has this SHA-256 (repeatable for
+    /// several accepted digests); symlinks are followed and the final target is hashed
+    #[arg(long, value_parser = integrity::parse_digest, value_name = "HEX")]
+    expect_cmd_sha256: Vec<String>,
+
+    /// Warn when `<cmd> --version` reports a version below this known-good minimum
+    #[arg(long)]
+    min_child_version: Option<version::Version>,
+
+    /// Refuse to run (instead of warning) when the child is below --min-child-version
+    #[arg(long, action = ArgAction::SetTrue, requires = "min_child_version")]
+    enforce_min_child_version: bool,
+
+    /// Run even when the child command is rusty-claude itself (directly or via a symlink),
+    /// which would otherwise be refused as an accidental recursion
+    #[arg(long, action = ArgAction::SetTrue)]
+    allow_self_wrap: bool,
+
+    /// Treat configurations that can never retry, and unknown keys in the config file or the
+    /// Claude settings block, as errors instead of warnings
+    #[arg(long, action = ArgAction::SetTrue)]
+    strict_config: bool,
+
+    /// Suppress informational supervisor messages (warnings and errors are still printed)
+    #[arg(short, long, action = ArgAction::SetTrue)]
+    quiet: bool,
+
+    /// Print a keepalive line to stderr when the child has been silent this long (e.g. 5m)
+    #[arg(long, value_parser = parse_duration)]
+    heartbeat: Option<Duration>,
+
+    /// Forward the child's stdout/stderr byte-for-byte: implies --quiet (only fatal errors
+    /// are printed) and refuses output-modifying features such as CI markers and heartbeats
+    #[arg(
+        long,
+        action = ArgAction::SetTrue,
+        conflicts_with_all = [
+            "ci_annotations",
+            "heartbeat",
+            "heartbeat_even_when_quiet",
+            "pty",
+            "set_title",
+            "stats"
+        ]
+    )]
+    raw_passthrough: bool,
+
+    /// Keep printing heartbeat lines under --quiet (CI usually wants both)
+    #[arg(long, action = ArgAction::SetTrue)]
+    heartbeat_even_when_quiet: bool,
+
+    /// Kill an attempt still running after this many seconds and retry it (non-interactive
+    /// only). ENV: RUSTY_CLAUDE_ATTEMPT_TIMEOUT
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..), value_name = "SECS")]
+    attempt_timeout_secs: Option<u64>,
+
+    /// Kill an attempt whose stdout and stderr have both been silent this many seconds and
+    /// retry it (non-interactive only; not once both have closed)
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..), value_name = "SECS")]
+    idle_timeout_secs: Option<u64>,
+
+    /// Kill an attempt that has written nothing on stdout or stderr this long after its stdin
+    /// was replayed (e.g. 30s; 0 disables) and retry it after --first-output-retry-delay
+    /// (non-interactive only)
+    #[arg(long, value_parser = parse_duration, value_name = "DURATION")]
+    first_output_timeout: Option<Duration>,
+
+    /// Delay before retrying an attempt killed by --first-output-timeout, instead of the
+    /// backoff
+    #[arg(long, value_parser = parse_duration, default_value = "1s", value_name = "DURATION")]
+    first_output_retry_delay: Duration,
+
+    /// Keep forwarding what a killed attempt writes while it is torn down (e.g. from a
+    /// process it started), instead of holding it back after the kill
+    #[arg(long, action = ArgAction::SetTrue)]
+    forward_late_output: bool,
+
+    /// Leave the child in our process group instead of a group (job object on Windows) of
+    /// its own, for where that breaks job control; kills then reach only the direct child,
+    /// not what it started
+    #[arg(long, action = ArgAction::SetTrue)]
+    no_process_group: bool,
+
+    /// Check retry patterns against output as it arrives and, on a match, kill the attempt and
+    /// retry at once instead of waiting for the child to exit (never on the last attempt)
+    #[arg(long, action = ArgAction::SetTrue)]
+    stream_match: bool,
+
+    /// Decide retries from the `error.type` in the child's JSON stdout (--output-format
+    /// json or stream-json) instead of the patterns, which then only see stderr; falls back to
+    /// the patterns when stdout isn't JSON. A JSON error fails even an attempt that exited 0
+    #[arg(long, action = ArgAction::SetTrue)]
+    json_errors: bool,
+
+    /// This long before the attempt timeout, warn that it approaches and signal the child so
+    /// it can checkpoint (e.g. 30s; once per attempt)
+    #[arg(long, value_parser = parse_duration, value_name = "DURATION")]
+    timeout_warning: Option<Duration>,
+
+    /// Signal sent with --timeout-warning, or `none` (nothing is sent on Windows)
+    #[arg(
+        long,
+        value_parser = signals::parse,
+        default_value = "USR1",
+        value_name = "SIGNAL",
+        requires = "timeout_warning"
+    )]
+    timeout_warning_signal: signals::Signal,
+
+    /// Relaunch a failed interactive session (after a banner and a short grace period);
+    /// by default an interactive session is never restarted
+    #[arg(long, action = ArgAction::SetTrue)]
+    interactive_retry: bool,
+
+    /// Extra child args (whitespace-separated) appended on every attempt after the first,
+    /// e.g. "--continue"
+    #[arg(long, value_name = "ARGS", allow_hyphen_values = true)]
+    retry_extra_args: Option<String>,
+
+    /// Child args (whitespace-separated) to fall back to once the retries have run out on
+    /// retryable errors, e.g. "--model claude-sonnet-4"; each set gets a fresh retry cycle.
+    /// Repeatable, tried in order
+    #[arg(long, value_name = "ARGS", allow_hyphen_values = true)]
+    fallback_args: Vec<String>,
+
+    /// Whether --fallback-args are appended to the child args or replace them
+    #[arg(long, value_enum, value_name = "MODE", default_value = "append")]
+    fallback_mode: fallback::FallbackMode,
+
+    /// Hand each attempt a `<run_id>-<attempt>` tag for server-side correlation: exported as
+    /// RUSTY_CLAUDE_ATTEMPT_TAG (`env`) or passed as the --tag-arg args (`header`)
+    #[arg(long, value_enum, value_name = "MODE")]
+    tag_attempts: Option<tag::TagMode>,
+
+    /// Child args carrying the tag in `header` mode, whitespace-separated with {tag} replaced;
+    /// `--flag {tag}` and `--flag={tag}` both work
+    #[arg(
+        long,
+        default_value = tag::DEFAULT_TEMPLATE,
+        value_name = "TEMPLATE",
+        allow_hyphen_values = true,
+        requires = "tag_attempts"
+    )]
+    tag_arg: String,
+
+    /// On a retry, write the previous attempt's stderr tail (redacted) to a temp file and pass
+    /// it to the child as `ARG <file>`, e.g. --append-system-prompt-file
+    #[arg(long, value_name = "ARG", value_parser = feed::parse_arg, allow_hyphen_values = true)]
+    feed_previous_error: Option<String>,
+
+    /// On a retry, resume the failed attempt's session (`--resume <id>`, the `session_id` in
+    /// its stdout) instead of starting over; a plain retry when its output names none
+    #[arg(long, action = ArgAction::SetTrue)]
+    auto_resume: bool,
+
+    /// Shell command run before every retry; KEY=VALUE lines it writes to the file named by
+    /// $RUSTY_CLAUDE_ENV_FILE are set in the next attempt's environment
+    #[arg(long, value_name = "CMD")]
+    on_retry_cmd: Option<String>,
+
+    /// Shell command run when the retries have run out, e.g. to page someone; it gets the
+    /// last attempt's context in RUSTY_CLAUDE_* variables
+    #[arg(long, value_name = "CMD")]
+    on_exhausted_cmd: Option<String>,
+
+    /// Kill --on-retry-cmd or --on-exhausted-cmd after this long; the run goes on either way
+    #[arg(long, value_parser = duration::parse_duration, default_value = "10s")]
+    hook_timeout: Duration,
+
+    /// Shell command asked for the delay before each retry: it gets the attempt, error class,
+    /// exit code and remaining budget in RUSTY_CLAUDE_* variables and prints a duration
+    /// (`750`, `2.5s`); the built-in backoff is used if it fails
+    #[arg(long, value_name = "CMD")]
+    delay_cmd: Option<String>,
+
+    /// Kill --delay-cmd and fall back to the built-in backoff after this long
+    #[arg(
+        long,
+        value_parser = duration::parse_duration,
+        default_value = "5s",
+        requires = "delay_cmd"
+    )]
+    delay_cmd_timeout: Duration,
+
+    /// Supervise a long-running child (e.g. `claude mcp serve`): every exit is restarted,
+    /// stdin/stdout are passed through untouched, and --max-retries does not apply
+    #[arg(long, action = ArgAction::SetTrue)]
+    server_mode: bool,
+
+    /// Exit with code 114 before spawning anything unless stdin is piped and holds at least
+    /// MIN_BYTES (1 if omitted), to catch an upstream stage that produced nothing; with
+    /// --stdin-file the file is checked instead
+    #[arg(
+        long,
+        value_parser = parse_size,
+        num_args = 0..=1,
+        default_missing_value = "1",
+        value_name = "MIN_BYTES",
+        conflicts_with_all = ["batch", "server_mode"]
+    )]
+    require_stdin: Option<u64>,
+
+    /// In server mode, reset the backoff once the child has stayed up this long
+    #[arg(long, value_parser = duration::parse_duration, default_value = "60s")]
+    stable_after: Duration,
+
+    /// In server mode, give up when the child has been restarted this many times within an hour
+    #[arg(long, default_value_t = 30)]
+    max_restarts_per_hour: u32,
+
+    /// Run an interactive session on a pseudo-terminal with rusty-claude relaying the
+    /// terminal (Unix only)
+    #[arg(long, action = ArgAction::SetTrue)]
+    pty: bool,
+
+    /// Type this text plus Enter into the interactive session once the child looks ready
+    /// (requires --pty)
+    #[arg(long, value_name = "TEXT", requires = "pty")]
+    initial_input: Option<String>,
+
+    /// Regex on the child's output that marks it ready: for --initial-input (default: any
+    /// output), or on stderr in server mode
+    #[arg(long, value_name = "REGEX")]
+    ready_pattern: Option<String>,
+
+    /// Before a retry, open the captured stdin in $VISUAL/$EDITOR on the terminal and replay
+    /// the edited input from then on; with =REGEX only when the failed output matches it
+    #[arg(
+        long,
+        num_args = 0..=1,
+        require_equals = true,
+        value_name = "REGEX"
+    )]
+    edit_on_retry: Option<Option<String>>,
+
+    /// What --edit-on-retry does without a controlling terminal: refuse to run, or retry
+    /// without editing
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = tty::Fallback::Abort,
+        requires = "edit_on_retry"
+    )]
+    edit_no_tty: tty::Fallback,
+
+    /// Behave as if there were no controlling terminal, so features that would talk to the
+    /// user take their --*-no-tty fallback
+    #[arg(long, action = ArgAction::SetTrue)]
+    no_tty: bool,
+
+    /// Judge success by this regex matching the attempt's output instead of by a zero exit
+    /// (the exit code is still recorded); an attempt without a match is a failure
+    #[arg(long, value_name = "REGEX")]
+    success_pattern: Option<String>,
+
+    /// Which of the attempt's streams the retry and fatal patterns are matched against;
+    /// `stderr` (recommended) keeps the model's answer on stdout from triggering a retry
+    #[arg(long, value_enum, value_name = "STREAMS", default_value = "both")]
+    match_streams: MatchStreams,
+
+    /// Also run the output of an attempt that exited 0 through the retry and fatal patterns,
+    /// and on a match treat it as failed
+    #[arg(long, action = ArgAction::SetTrue)]
+    retry_on_success_match: bool,
+
+    /// Exit with this code when the run ends on an attempt that exited 0 but was failed by
+    /// --retry-on-success-match
+    #[arg(long, value_name = "CODE", default_value_t = 1)]
+    success_match_exit_code: i32,
+
+    /// In server mode, the child is ready once this address accepts TCP connections
+    #[arg(long, value_name = "HOST:PORT", requires = "server_mode")]
+    ready_tcp: Option<String>,
+
+    /// How long the child may take to look ready; a server that isn't ready in time counts
+    /// as a failed start, and --initial-input is dropped
+    #[arg(long, value_parser = duration::parse_duration, default_value = "10s")]
+    ready_timeout: Duration,
+
+    /// Serve live output and supervisor events to observers on this Unix domain socket
+    #[arg(long, value_name = "PATH")]
+    observe_socket: Option<PathBuf>,
+
+    /// Poll this file for a control command: `drain` (no retry after the current attempt)
+    /// or `abort` (kill the child now and exit 130). Observers can send the same as
+    /// {"cmd":"drain"} lines on --observe-socket
+    #[arg(long, value_name = "PATH")]
+    control_file: Option<PathBuf>,
+
+    /// Write supervisor events as JSON lines to this file (`-` for stderr)
+    #[arg(long, value_name = "PATH")]
+    json_events: Option<PathBuf>,
+
+    /// Keep invalid UTF-8 out of the output excerpts embedded in JSON events and attempt
+    /// metadata: `encode` (the default) moves such an excerpt base64-encoded to a
+    /// `<field>_base64` field, `omit` replaces it with a marker and the invalid byte ranges
+    #[arg(
+        long,
+        value_enum,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "encode",
+        value_name = "MODE"
+    )]
+    strict_utf8: Option<utf8::StrictUtf8>,
+
+    /// Inject a fault at a named checkpoint, for testing rusty-claude itself (repeatable)
+    #[cfg(feature = "chaos")]
+    #[arg(long, hide = true, value_parser = chaos::parse, value_name = "SPEC")]
+    chaos: Vec<chaos::Fault>,
+
+    /// Emit CI log groups and annotations for each attempt (non-interactive only)
+    #[arg(long, value_enum, default_value_t = CiMode::Off)]
+    ci_annotations: CiMode,
+
+    /// Exit with this code when retries are exhausted (default: the child's code)
+    #[arg(long)]
+    exhausted_exit_code: Option<i32>,
+
+    /// Exit with this code when the last attempt ran into --attempt-timeout-secs
+    #[arg(long, value_name = "CODE", default_value_t = exit_codes::ATTEMPT_TIMEOUT)]
+    timeout_exit_code: i32,
+
+    /// Exit with this code when the last attempt ran into --idle-timeout-secs or
+    /// --first-output-timeout
+    #[arg(long, value_name = "CODE", default_value_t = exit_codes::STALLED)]
+    stall_exit_code: i32,
+
+    /// Exit with this code when --max-total-ms ends the retries
+    #[arg(long, value_name = "CODE", default_value_t = exit_codes::TOTAL_TIMEOUT)]
+    total_timeout_exit_code: i32,
+
+    /// Stop retrying once the child's output across all attempts exceeds this size (e.g. 500MB)
+    #[arg(long, value_parser = parse_size)]
+    max_total_output: Option<u64>,
+
+    /// Hold each attempt's stdout in memory and write it only once that attempt is the
+    /// final one, so a pipe (`| jq .`) never sees a failed attempt's output
+    #[arg(long, action = ArgAction::SetTrue)]
+    buffer_output: bool,
+
+    /// With --buffer-output, hold back stderr the same way instead of streaming it
+    #[arg(long, action = ArgAction::SetTrue, requires = "buffer_output")]
+    buffer_stderr: bool,
+
+    /// Most an attempt may buffer under --buffer-output; past it the attempt is killed and
+    /// the run fails with code 121
+    #[arg(
+        long,
+        value_parser = parse_size,
+        default_value = "256MiB",
+        requires = "buffer_output"
+    )]
+    buffer_limit: u64,
+
+    /// Most the wrapper's own buffers may hold at once (e.g. 512MiB). Output held back by
+    /// --buffer-output moves to a temp file past it, and other captured output keeps only its
+    /// most recent part for retry matching
+    #[arg(long, value_parser = parse_size, value_name = "SIZE")]
+    self_mem_limit: Option<u64>,
+
+    /// Most of each attempt's stdout and stderr kept for retry matching; past it only the
+    /// most recent output is kept, where the errors are. Output held back by --buffer-output
+    /// is kept whole, and 0 keeps everything
+    #[arg(long, value_parser = parse_size, value_name = "SIZE", default_value_t = memory::DEFAULT_CAPTURE_LIMIT)]
+    max_capture_bytes: u64,
+
+    /// Feed this file to the child's stdin on every attempt, as piped stdin is replayed, and
+    /// run non-interactively even from a terminal; `-` reads our stdin once and replays it
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["batch", "server_mode"])]
+    stdin_file: Option<PathBuf>,
+
+    /// Piped stdin up to this size is read whole and kept in memory; past it the child is
+    /// started at once and stdin streamed to it through a temp file that retries replay
+    #[arg(long, value_parser = parse_size, value_name = "SIZE", default_value_t = spool::DEFAULT_THRESHOLD)]
+    stdin_spool_threshold_bytes: u64,
+
+    /// Ignore every RUSTY_CLAUDE_* and CLAUDE_SUPERVISOR_* setting for this run (the child's
+    /// environment is unaffected)
+    #[arg(long, action = ArgAction::SetTrue)]
+    no_env: bool,
+
+    /// Honor only setting variables with this prefix
+    #[arg(
+        long,
+        value_name = "PREFIX",
+        value_parser = [envvars::PREFIX, envvars::LEGACY_PREFIX],
+        conflicts_with = "no_env"
+    )]
+    env_only_prefix: Option<String>,
+
+    /// Read default settings from this TOML file instead of
+    /// ~/.config/rusty-claude/config.toml; the environment and flags override it.
+    /// ENV: RUSTY_CLAUDE_CONFIG
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// Read no config file
+    #[arg(long, action = ArgAction::SetTrue, conflicts_with = "config")]
+    no_config: bool,
+
+    /// Also read defaults from the `rustyClaude` block of this Claude Code settings file, or
+    /// with `auto` of the nearest .claude/settings.json at or above the working directory;
+    /// the config file overrides it
+    #[arg(long, value_name = "PATH|auto")]
+    claude_settings: Option<String>,
+
+    /// `patterns` and `fatal_patterns` from the config file with their source, kept apart
+    /// from the flags since their entries may contain `|`.
+    #[arg(skip)]
+    file_patterns: Vec<(String, String)>,
+    #[arg(skip)]
+    file_fatal_patterns: Vec<(String, String)>,
+
+    /// Show the wrapper's state (running, waiting to retry, done) in the terminal title;
+    /// needs a terminal on stderr and is skipped under NO_COLOR or TERM=dumb
+    #[arg(long, action = ArgAction::SetTrue)]
+    set_title: bool,
+
+    /// Print the effective settings and where each value came from, then exit
+    #[arg(long, action = ArgAction::SetTrue)]
+    print_config: bool,
+
+    /// Write the final exit code and whether it came from the child or the wrapper to a file
+    #[arg(long)]
+    reason_file: Option<PathBuf>,
+
+    /// Identifier for this run, carried by events, artifacts, the reason file, and -v logs and
+    /// exported to the child as RUSTY_CLAUDE_RUN_ID (default: a fresh UUIDv7)
+    #[arg(long, value_parser = runid::parse, value_name = "ID")]
+    run_id: Option<String>,
+
+    /// Append one JSON line per invocation to this file, shared across a pipeline's runs;
+    /// summarize it with `rusty-claude stats --sink PATH`
+    #[arg(long, value_name = "PATH")]
+    stats_sink: Option<PathBuf>,
+
+    /// When the run ends, print a summary to stderr: each attempt's exit code and duration,
+    /// time spent sleeping between attempts, and the patterns that matched
+    #[arg(long)]
+    stats: bool,
+
+    /// When the run ends, write the summary as one JSON document to this file (`-` for
+    /// stderr; never stdout)
+    #[arg(long, value_name = "PATH")]
+    stats_json: Option<PathBuf>,
+
+    /// Append one JSON line per retry decision to this file, with every input the decision
+    /// consumed (non-interactive only)
+    #[arg(long, value_name = "PATH")]
+    decision_trace: Option<PathBuf>,
+
+    /// Append one JSON line per attempt and a summary line for the run to this file (`-` for
+    /// stderr); env RUSTY_CLAUDE_LOG
+    #[arg(long, value_name = "PATH")]
+    log_file: Option<PathBuf>,
+
+    /// Run the child once per prompt in this file: one per line, or NDJSON objects with a
+    /// `prompt` field. The prompt replaces `{prompt}` in the child args, or is piped to stdin
+    #[arg(long, value_name = "FILE")]
+    batch: Option<PathBuf>,
+
+    /// With --batch, run up to this many entries at once
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..), default_value_t = 1, value_name = "N")]
+    batch_concurrency: u32,
+
+    /// With --batch, write each entry's result to DIR/entry-N.json, or as NDJSON lines to
+    /// stdout for `-`
+    #[arg(long, value_name = "DIR|-", default_value = "-", requires = "batch")]
+    batch_output: PathBuf,
 }
 
-fn backoff_ms(attempt: u32, base: u64, cap: u64) -> u64 {
-    let exp = base.saturating_mul(2u64.saturating_pow(attempt));
-    let upper = cap.min(exp).max(base);
-    let mut rng = rand::rng();
-    base + rng.random_range(0..=upper.saturating_sub(base)) + rng.random_range(0..=250)
-    // small extra jitter
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Run the retry machinery against built-in scenarios and report pass/fail
+    SelfTest(selftest::SelfTestArgs),
+    /// Measure the wrapper's overhead against running the fake child directly
+    Bench(bench::BenchArgs),
+    /// Aggregate a --stats-sink file: totals, retry rate, top patterns, slowest runs
+    Stats(stats::StatsArgs),
+    /// Replay a recorded run's attempts (--attempt-artifacts) through the retry decisions
+    /// under these settings, without running anything
+    Simulate(simulate::SimulateArgs),
+    /// Check the child command, its version, the patterns, and the effective settings a run
+    /// would use, and exit non-zero if a run could not work
+    Doctor(doctor::DoctorArgs),
+    /// Scripted stand-in for the Claude CLI used by self-test
+    #[command(name = "__fake-child", hide = true)]
+    FakeChild(Box<fake_child::FakeChildArgs>),
 }
 
-fn compile_patterns(extra: Option<String>) -> Vec<Regex> {
-    // Own the strings to avoid lifetime issues, then compile
-    let mut pats: Vec<String> = vec![
-        "(?i)overloaded".into(),
-        r"(?i)HTTP\s*500".into(),
-        r"(?i)\b5\d\d\s*(Server\s*Error|Error)\b".into(),
-        r"(?i)status\s*code\s*=\s*5\d\d".into(),
-        r"(?i)Too\s*Many\s*Requests".into(),
-        r"(?i)\b429\b".into(),
-        r"(?i)ECONNRESET".into(),
-        r"(?i)ETIMEDOUT".into(),
-        r"(?i)Gateway\s*Timeout".into(),
-        r"(?i)upstream\s*timeout".into(),
-        r"(?i)temporary\s*failure".into(),
-        r"(?i)(fetch|network)\s*error".into(),
-        r"(?i)socket\s*hang\s*up".into(),
-    ];
+/// `claude`; on Windows the `PATHEXT` search finds `claude.exe` or npm's `claude.cmd` shim.
+fn default_cmd() -> String {
+    "claude".to_string()
+}
 
-    if let Ok(env_pats) = std::env::var("CLAUDE_SUPERVISOR_PATTERNS") {
-        for s in env_pats
-            .split('|')
-            .map(|s| s.trim())
-            .filter(|s| !s.is_empty())
-        {
-            pats.push(s.to_owned());
-        }
-    }
-    if let Some(cli_pats) = extra {
-        for s in cli_pats
-            .split('|')
-            .map(|s| s.trim())
-            .filter(|s| !s.is_empty())
-        {
-            pats.push(s.to_owned());
-        }
+/// `1`/`true`/`yes`/`on` or `0`/`false`/`no`/`off`, for boolean environment knobs.
+fn parse_bool(s: &str) -> Option<bool> {
+    match s.trim().to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Some(true),
+        "0" | "false" | "no" | "off" => Some(false),
+        _ => None,
     }
-    pats.into_iter()
-        .filter_map(|p| Regex::new(&p).ok())
-        .collect()
 }
 
-fn find_retry_after_ms(text: &str) -> Option<u64> {
-    // Look for "Retry-After: N" (seconds). The Node CLI may print this if it surfaces headers.
-    if let Ok(re) = Regex::new(r"(?i)Retry-After:\s*(\d+)\b") {
-        if let Some(c) = re.captures(text) {
-            if let Some(s) = c.get(1) {
-                if let Ok(n) = s.as_str().parse::<u64>() {
-                    return Some(n * 1000);
-                }
-            }
-        }
-    }
-    None
+/// Which variables `--no-env` and `--env-only-prefix` leave the settings to read.
+fn set_env_policy(cli: &Cli) {
+    envvars::set_policy(if cli.no_env {
+        envvars::Policy::None
+    } else if let Some(prefix) = &cli.env_only_prefix {
+        envvars::Policy::OnlyPrefix(prefix.clone())
+    } else {
+        envvars::Policy::All
+    });
 }
 
-fn should_retry(
-    output: &str,
-    exit_code: Option<i32>,
-    retry_on_any: bool,
-    regexes: &[Regex],
-) -> (bool, Option<u64>) {
-    for re in regexes {
-        if re.is_match(output) {
-            return (true, find_retry_after_ms(output));
+/// The config file and the `--claude-settings` block, whichever there are.
+fn load_layers(cli: &Cli) -> Result<config::Layers, String> {
+    let located = if cli.no_config {
+        None
+    } else {
+        config::locate(cli.config.as_deref())
+    };
+    let file = match located {
+        Some((path, explicit)) => config::load(&path, explicit)?,
+        None => None,
+    };
+    let claude_settings = match cli
+        .claude_settings
+        .as_deref()
+        .and_then(claude_settings::locate)
+    {
+        Some(path) => claude_settings::load(&path)?,
+        None => None,
+    };
+    let layers = config::Layers {
+        file,
+        claude_settings,
+    };
+    if cli.strict_config {
+        if let Some(unknown) = layers.unknown().next() {
+            return Err(format!("{unknown} (refused under --strict-config)"));
         }
     }
-    if retry_on_any {
-        if let Some(code) = exit_code {
-            if code != 0 {
-                return (true, None);
-            }
-        } else {
-            return (true, None);
-        }
+    for unknown in layers.unknown() {
+        eprintln!("[rusty-claude] warning: {unknown}; ignoring it");
     }
-    (false, None)
+    Ok(layers)
 }
 
-/// Read a pipe, write through to dst (stdout/stderr), and buffer for later inspection.
-fn tee_reader(
-    mut src: impl Read + Send + 'static,
-    mut dst: impl Write + Send + 'static,
-) -> thread::JoinHandle<io::Result<Vec<u8>>> {
-    thread::spawn(move || {
-        let mut buf = Vec::new();
-        let mut tmp = [0u8; 8192];
-        loop {
-            match src.read(&mut tmp) {
-                Ok(0) => break,
-                Ok(n) => {
-                    buf.extend_from_slice(&tmp[..n]);
-                    dst.write_all(&tmp[..n])?;
-                    dst.flush()?;
-                }
-                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
-                Err(e) => return Err(e),
-            }
+/// Apply the config file's and Claude settings' values and then the environment overrides
+/// to `cli`, each only where no flag was given, and report the provenance of each knob.
+fn resolve_settings(cli: &mut Cli, matches: &ArgMatches, layers: &config::Layers) -> Vec<Setting> {
+    let flagged = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+    let flag_or_default = |id: &str| {
+        if flagged(id) {
+            Source::Flag
+        } else {
+            Source::Default
         }
-        Ok(buf)
-    })
-}
+    };
+    let mut max_retries_src = flag_or_default("max_retries");
+    let mut base_src = flag_or_default("base_delay_ms");
+    let mut cap_src = flag_or_default("max_delay_ms");
+    let mut initial_delay_src = flag_or_default("initial_delay");
+    let mut jitter_seed_src = flag_or_default("jitter_seed");
+    let mut stable_locale_src = flag_or_default("stable_locale");
+    let mut attempt_timeout_src = flag_or_default("attempt_timeout_secs");
+    let mut max_total_src = flag_or_default("max_total_ms");
+    let mut retry_on_any_error_src = flag_or_default("retry_on_any_error");
+    let mut force_tee_src = flag_or_default("force_tee");
+    let mut patterns_src = flag_or_default("patterns");
+    let mut fatal_patterns_src = flag_or_default("fatal_patterns");
+    let mut no_default_src = flag_or_default("no_default_patterns");
+    let mut no_default_fatal_src = flag_or_default("no_default_fatal_patterns");
+    let mut require_stdin_src = flag_or_default("require_stdin");
+    // `exec COMMAND` sets it too
+    let mut cmd_src = if cli.cmd.is_some() {
+        Source::Flag
+    } else {
+        Source::Default
+    };
 
-fn main() -> io::Result<()> {
-    let mut cli = Cli::parse();
+    // The config file and Claude settings, below everything else
+    let from_file = |id: &str| Some(layers).filter(|_| !flagged(id));
+    if let Some((cmd, src)) = Some(layers)
+        .filter(|_| cli.cmd.is_none())
+        .and_then(|c| c.text("cmd"))
+    {
+        cli.cmd = Some(cmd);
+        cmd_src = src;
+    }
+    let mut args_src = if cli.args.is_empty() {
+        Source::Default
+    } else {
+        Source::Flag
+    };
+    if let Some((args, src)) = Some(layers)
+        .filter(|_| cli.args.is_empty())
+        .and_then(|c| c.list("args"))
+    {
+        cli.args = args;
+        args_src = src;
+    }
+    if let Some((n, src)) = from_file("max_retries").and_then(|c| c.number("max_retries")) {
+        cli.max_retries = u32::try_from(n).unwrap_or(u32::MAX);
+        max_retries_src = src;
+    }
+    if let Some((n, src)) = from_file("base_delay_ms").and_then(|c| c.number("base_delay_ms")) {
+        cli.base_delay_ms = n;
+        base_src = src;
+    }
+    if let Some((n, src)) = from_file("max_delay_ms").and_then(|c| c.number("max_delay_ms")) {
+        cli.max_delay_ms = n;
+        cap_src = src;
+    }
+    if let Some((n, src)) = from_file("max_total_ms").and_then(|c| c.number("max_total_ms")) {
+        cli.max_total_ms = Some(n);
+        max_total_src = src;
+    }
+    if let Some((d, src)) = from_file("initial_delay").and_then(|c| c.duration("initial_delay")) {
+        cli.initial_delay = Some(d);
+        initial_delay_src = src;
+    }
+    if let Some((on, src)) = from_file("stable_locale").and_then(|c| c.flag("stable_locale")) {
+        cli.stable_locale = on;
+        stable_locale_src = src;
+    }
+    if let Some((n, src)) =
+        from_file("attempt_timeout_secs").and_then(|c| c.number("attempt_timeout_secs"))
+    {
+        cli.attempt_timeout_secs = Some(n);
+        attempt_timeout_src = src;
+    }
+    if let Some((on, src)) =
+        from_file("retry_on_any_error").and_then(|c| c.flag("retry_on_any_error"))
+    {
+        cli.retry_on_any_error = on;
+        retry_on_any_error_src = src;
+    }
+    if let Some((on, src)) = from_file("force_tee").and_then(|c| c.flag("force_tee")) {
+        cli.force_tee = on;
+        force_tee_src = src;
+    }
+    if let Some((on, src)) =
+        from_file("no_default_patterns").and_then(|c| c.flag("no_default_patterns"))
+    {
+        cli.no_default_patterns = on;
+        no_default_src = src;
+    }
+    if let Some((on, src)) =
+        from_file("no_default_fatal_patterns").and_then(|c| c.flag("no_default_fatal_patterns"))
+    {
+        cli.no_default_fatal_patterns = on;
+        no_default_fatal_src = src;
+    }
+    if let Some((n, src)) = from_file("require_stdin").and_then(|c| c.number("require_stdin")) {
+        cli.require_stdin = Some(n);
+        require_stdin_src = src;
+    }
+    // The file's lists give way to the variable as well as the flag
+    for (key, suffix, target, src) in [
+        (
+            "patterns",
+            "PATTERNS",
+            &mut cli.file_patterns,
+            &mut patterns_src,
+        ),
+        (
+            "fatal_patterns",
+            "FATAL_PATTERNS",
+            &mut cli.file_fatal_patterns,
+            &mut fatal_patterns_src,
+        ),
+    ] {
+        let listed = from_file(key)
+            .filter(|_| envvars::var(suffix).is_none())
+            .and_then(|c| c.list(key));
+        if let Some((list, file_src)) = listed {
+            *target = list
+                .into_iter()
+                .map(|p| (p, file_src.to_string()))
+                .collect();
+            *src = file_src;
+        }
+    }
 
-    // Env overrides for convenience
-    if let Ok(v) = env::var("CLAUDE_SUPERVISOR_MAX_RETRIES") {
-        if let Ok(n) = v.parse::<u32>() {
+    // Env overrides for convenience, below the flags
+    if let Some(v) = envvars::var("MAX_RETRIES").filter(|_| !flagged("max_retries")) {
+        if let Ok(n) = v.value.parse::<u32>() {
             cli.max_retries = n;
+            max_retries_src = Source::Env(v.name);
         }
     }
-    if let Ok(v) = env::var("CLAUDE_SUPERVISOR_BASE_MS") {
-        if let Ok(n) = v.parse::<u64>() {
+    if let Some(v) = envvars::var("BASE_MS").filter(|_| !flagged("base_delay_ms")) {
+        if let Ok(n) = v.value.parse::<u64>() {
             cli.base_delay_ms = n;
+            base_src = Source::Env(v.name);
         }
     }
-    if let Ok(v) = env::var("CLAUDE_SUPERVISOR_CAP_MS") {
-        if let Ok(n) = v.parse::<u64>() {
+    if let Some(v) = envvars::var("CAP_MS").filter(|_| !flagged("max_delay_ms")) {
+        if let Ok(n) = v.value.parse::<u64>() {
             cli.max_delay_ms = n;
+            cap_src = Source::Env(v.name);
         }
     }
 
-    let real_cmd = cli.cmd.clone().unwrap_or_else(default_cmd);
-    let retry_regexes = compile_patterns(cli.patterns.clone());
-
-    // If stdin is piped, capture it once to replay on retries
-    let mut stdin_buf = Vec::new();
-    let stdin_is_tty = atty::is(atty::Stream::Stdin);
-    if !stdin_is_tty {
-        io::stdin().read_to_end(&mut stdin_buf)?;
+    if let Some(v) = envvars::var("SEED").filter(|_| !flagged("jitter_seed")) {
+        if let Ok(n) = v.value.parse::<u64>() {
+            cli.jitter_seed = Some(n);
+            jitter_seed_src = Source::Env(v.name);
+        }
     }
-
-    // Decide mode:
-    // - Interactive if: stdin is TTY, no child args, and not forcing tee
-    // - Otherwise non-interactive (piped/child args present/forced tee)
-    let interactive = stdin_buf.is_empty() && stdin_is_tty && cli.args.is_empty() && !cli.force_tee;
-
-    if stdin_buf.is_empty() && cli.args.is_empty() && !stdin_is_tty {
-        eprintln!(
-            "[rusty-claude] No stdin and no child args. \
-            To run interactive mode, invoke from a TTY (no pipe). \
-            To run non-interactive mode, provide child args after `--` (e.g., -- --json)."
-        );
+    if let Some(v) = envvars::var("INITIAL_DELAY").filter(|_| !flagged("initial_delay")) {
+        if let Ok(d) = parse_duration(&v.value) {
+            cli.initial_delay = Some(d);
+            initial_delay_src = Source::Env(v.name);
+        }
+    }
+    if let Some(v) = envvars::var("STABLE_LOCALE").filter(|_| !flagged("stable_locale")) {
+        if let Some(on) = parse_bool(&v.value) {
+            cli.stable_locale = on;
+            stable_locale_src = Source::Env(v.name);
+        }
+    }
+    if let Some(v) = envvars::var("MAX_TOTAL_MS").filter(|_| !flagged("max_total_ms")) {
+        if let Ok(n) = v.value.parse::<u64>() {
+            cli.max_total_ms = Some(n);
+            max_total_src = Source::Env(v.name);
+        }
+    }
+    if let Some(v) = envvars::var("ATTEMPT_TIMEOUT").filter(|_| !flagged("attempt_timeout_secs")) {
+        if let Some(n) = v.value.parse::<u64>().ok().filter(|&n| n > 0) {
+            cli.attempt_timeout_secs = Some(n);
+            attempt_timeout_src = Source::Env(v.name);
+        }
+    }
+    let mut log_file_src = flag_or_default("log_file");
+    if let Some(v) = envvars::var("LOG").filter(|_| !flagged("log_file")) {
+        if !v.value.is_empty() {
+            cli.log_file = Some(PathBuf::from(v.value));
+            log_file_src = Source::Env(v.name);
+        }
     }
 
-    for attempt in 0..=cli.max_retries {
-        let mut cmd = Command::new(&real_cmd);
-        cmd.args(&cli.args).envs(env::vars());
-
-        if interactive {
-            cmd.stdin(Stdio::inherit())
-                .stdout(Stdio::inherit())
-                .stderr(Stdio::inherit());
-        } else {
-            cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
-            if stdin_buf.is_empty() {
-                cmd.stdin(Stdio::inherit());
+    let mut settings = vec![
+        Setting::new(
+            "config",
+            layers
+                .file
+                .as_ref()
+                .map_or_else(|| "-".to_string(), |c| c.path.display().to_string()),
+            if flagged("config") || flagged("no_config") {
+                Source::Flag
             } else {
-                cmd.stdin(Stdio::piped());
-            }
+                envvars::var("CONFIG").map_or(Source::Default, |v| Source::Env(v.name))
+            },
+        ),
+        Setting::new(
+            "claude_settings",
+            layers
+                .claude_settings
+                .as_ref()
+                .map_or_else(|| "-".to_string(), |c| c.path.display().to_string()),
+            flag_or_default("claude_settings"),
+        ),
+        Setting::new("cmd", cli.cmd.clone().unwrap_or_else(default_cmd), cmd_src),
+        Setting::new(
+            "args",
+            if cli.args.is_empty() {
+                "-".to_string()
+            } else {
+                shellquote::redacted(&cli.args)
+                    .iter()
+                    .map(|a| shellquote::posix(a))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            },
+            args_src,
+        ),
+        Setting::new("max_retries", cli.max_retries, max_retries_src),
+        Setting::new("base_delay_ms", cli.base_delay_ms, base_src),
+        Setting::new("max_delay_ms", cli.max_delay_ms, cap_src),
+        Setting::new(
+            "jitter_seed",
+            cli.jitter_seed
+                .map_or_else(|| "-".to_string(), |n| n.to_string()),
+            jitter_seed_src,
+        ),
+        Setting::new(
+            "max_total_ms",
+            cli.max_total_ms
+                .map_or_else(|| "-".to_string(), |n| n.to_string()),
+            max_total_src,
+        ),
+        Setting::new(
+            "initial_delay",
+            cli.initial_delay
+                .map_or_else(|| "-".to_string(), format_duration),
+            initial_delay_src,
+        ),
+        Setting::new("stable_locale", cli.stable_locale, stable_locale_src),
+        Setting::new(
+            "attempt_timeout_secs",
+            cli.attempt_timeout_secs
+                .map_or_else(|| "-".to_string(), |n| n.to_string()),
+            attempt_timeout_src,
+        ),
+        Setting::new(
+            "retry_on_any_error",
+            cli.retry_on_any_error,
+            retry_on_any_error_src,
+        ),
+        Setting::new("force_tee", cli.force_tee, force_tee_src),
+        Setting::new(
+            "require_stdin",
+            cli.require_stdin
+                .map_or_else(|| "-".to_string(), format_size),
+            require_stdin_src,
+        ),
+        Setting::new(
+            "patterns",
+            listed_patterns(cli.patterns.as_deref(), &cli.file_patterns),
+            patterns_src,
+        ),
+        Setting::new(
+            "fatal_patterns",
+            listed_patterns(cli.fatal_patterns.as_deref(), &cli.file_fatal_patterns),
+            fatal_patterns_src,
+        ),
+        Setting::new(
+            "retry_exit_codes",
+            cli.retry_exit_codes
+                .as_ref()
+                .map_or_else(|| "-".to_string(), CodeList::to_string),
+            flag_or_default("retry_exit_codes"),
+        ),
+        Setting::new(
+            "no_retry_exit_codes",
+            cli.no_retry_exit_codes
+                .as_ref()
+                .map_or_else(|| "-".to_string(), CodeList::to_string),
+            flag_or_default("no_retry_exit_codes"),
+        ),
+        Setting::new(
+            "assume_tz",
+            cli.assume_tz.as_deref().unwrap_or("-"),
+            flag_or_default("assume_tz"),
+        ),
+        Setting::new(
+            "patterns_file",
+            cli.patterns_file
+                .as_ref()
+                .map_or_else(|| "-".to_string(), |p| p.display().to_string()),
+            flag_or_default("patterns_file"),
+        ),
+        Setting::new(
+            "no_default_patterns",
+            cli.no_default_patterns,
+            no_default_src,
+        ),
+        Setting::new(
+            "no_default_fatal_patterns",
+            cli.no_default_fatal_patterns,
+            no_default_fatal_src,
+        ),
+        Setting::new(
+            "log_file",
+            cli.log_file
+                .as_ref()
+                .map_or_else(|| "-".to_string(), |p| p.display().to_string()),
+            log_file_src,
+        ),
+    ];
+    for (suffix, key) in [
+        ("PATTERNS", "env_patterns"),
+        ("FATAL_PATTERNS", "env_fatal_patterns"),
+    ] {
+        if let Some(v) = envvars::var(suffix) {
+            settings.push(Setting::new(key, v.value, Source::Env(v.name)));
         }
-
-        let mut child = match cmd.spawn() {
-            Ok(c) => c,
-            Err(e) => {
-                eprintln!("[rusty-claude] failed to spawn `{}`: {e}", real_cmd);
-                std::process::exit(127);
-            }
-        };
-
-        // If we captured stdin, replay it
-        if !stdin_buf.is_empty() {
-            if let Some(mut child_stdin) = child.stdin.take() {
-                child_stdin.write_all(&stdin_buf)?;
-                drop(child_stdin); // EOF
-            }
+    }
+    let flag = envvars::policy().flag();
+    for setting in &mut settings {
+        let suffix = ENV_KNOBS
+            .iter()
+            .find(|(key, _)| *key == setting.key)
+            .map(|(_, suffix)| suffix);
+        if let Some(v) = suffix.and_then(|s| envvars::ignored(s)) {
+            setting.ignored = Some((v.name, flag));
         }
-
-        if interactive {
-            // In interactive mode, just wait and return child's exit code
-            let status = child.wait()?;
-            if status.success() {
-                return Ok(());
-            }
-
-            if attempt == cli.max_retries {
-                std::process::exit(status.code().unwrap_or(1));
-            }
-            let wait = backoff_ms(attempt, cli.base_delay_ms, cli.max_delay_ms);
-            eprintln!(
-                "[rusty-claude] attempt={} interactive process failed (code={:?}); retrying in {}ms",
-                attempt + 1, status.code(), wait
-            );
-            thread::sleep(Duration::from_millis(wait));
-            continue;
+    }
+    for (suffix, key) in [
+        ("PATTERNS", "env_patterns"),
+        ("FATAL_PATTERNS", "env_fatal_patterns"),
+    ] {
+        if let Some(v) = envvars::ignored(suffix) {
+            let mut setting = Setting::new(key, "-", Source::Default);
+            setting.ignored = Some((v.name, flag));
+            settings.push(setting);
         }
+    }
+    settings
+}
 
-        // Non-interactive: tee outputs and decide to retry based on content/exit code.
-        let stdout = child.stdout.take().unwrap();
-        let stderr = child.stderr.take().unwrap();
+/// A patterns setting for display: the flag's value, else the config file's entries.
+fn listed_patterns(flag: Option<&str>, file: &[(String, String)]) -> String {
+    match flag {
+        Some(value) => value.to_string(),
+        None if !file.is_empty() => format!(
+            "[{}]",
+            file.iter()
+                .map(|(p, _)| format!("{p:?}"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        None => "-".to_string(),
+    }
+}
 
-        let stdout_handle = tee_reader(stdout, io::stdout());
-        let stderr_handle = tee_reader(stderr, io::stderr());
+/// Settings keys that an environment knob can override, with the knob's suffix.
+const ENV_KNOBS: &[(&str, &str)] = &[
+    ("max_retries", "MAX_RETRIES"),
+    ("base_delay_ms", "BASE_MS"),
+    ("max_delay_ms", "CAP_MS"),
+    ("jitter_seed", "SEED"),
+    ("max_total_ms", "MAX_TOTAL_MS"),
+    ("initial_delay", "INITIAL_DELAY"),
+    ("stable_locale", "STABLE_LOCALE"),
+    ("attempt_timeout_secs", "ATTEMPT_TIMEOUT"),
+    ("log_file", "LOG"),
+];
 
-        let status = child.wait()?;
+/// Long flags the Claude CLI shares with rusty-claude, so seeing them after the command in
+/// `exec` form is not a mistake.
+const SHARED_CHILD_FLAGS: &[&str] = &["help", "version", "verbose"];
 
-        // Join readers & collect buffers for pattern matching
-        let out_buf = stdout_handle.join().unwrap_or_else(|_| Ok(Vec::new()))?;
-        let err_buf = stderr_handle.join().unwrap_or_else(|_| Ok(Vec::new()))?;
-        let combined_text = {
-            let mut s = String::from_utf8_lossy(&out_buf).to_string();
-            s.push('\n');
-            s.push_str(&String::from_utf8_lossy(&err_buf));
-            s
-        };
+/// `Cli::command()` plus `exec`, which takes the same wrapper flags followed by the child
+/// command line instead of `--cmd` and `--`.
+fn cli_command() -> clap::Command {
+    let base = Cli::command();
+    let exec = clap::Command::new("exec")
+        .about("Run COMMAND [ARGS]... with retries; wrapper flags go between `exec` and COMMAND")
+        .args(
+            base.get_arguments()
+                .filter(|a| !a.is_positional())
+                .map(|a| {
+                    let a = a.clone();
+                    // Still defined so `Cli` can be read from these matches; the command is a positional
+                    if a.get_id() == "cmd" {
+                        a.hide(true)
+                    } else {
+                        a
+                    }
+                }),
+        )
+        .arg(
+            clap::Arg::new("args")
+                .value_name("COMMAND")
+                .help(
+                    "The command to run, then its arguments (use `--` first if it starts with `-`)",
+                )
+                .required(true)
+                .num_args(1..)
+                .trailing_var_arg(true)
+                .value_parser(clap::value_parser!(String)),
+        );
+    base.subcommand(exec)
+}
 
-        if status.success() {
-            // Success: return same exit code (0)
-            return Ok(());
-        }
+/// Turn `exec [FLAGS] COMMAND [ARGS]...` into the equivalent `--cmd COMMAND -- ARGS...`.
+fn exec_form(parent: &ArgMatches, sub: &ArgMatches) -> Cli {
+    let fail = |msg: String| -> ! {
+        let mut cmd = cli_command();
+        cmd.build();
+        let exec = cmd.find_subcommand_mut("exec").expect("exec subcommand");
+        exec.error(clap::error::ErrorKind::ArgumentConflict, msg)
+            .exit()
+    };
+    if let Some(id) = parent
+        .ids()
+        .find(|id| parent.value_source(id.as_str()) == Some(ValueSource::CommandLine))
+    {
+        fail(format!(
+            "wrapper flags go after `exec`, not before it (found `--{}`)",
+            id.as_str().replace('_', "-")
+        ));
+    }
+    let mut cli = Cli::from_arg_matches(sub).unwrap_or_else(|e| e.exit());
+    if cli.cmd.is_some() {
+        fail("`exec` takes the command as its first argument instead of --cmd".into());
+    }
+    let command = cli.args.remove(0);
+    let wrapper_flags: Vec<String> = cli_command()
+        .get_arguments()
+        .filter_map(|a| a.get_long())
+        .filter(|long| !SHARED_CHILD_FLAGS.contains(long))
+        .map(|long| format!("--{long}"))
+        .collect();
+    if let Some(flag) = cli.args.iter().find(|arg| {
+        let name = arg.split_once('=').map_or(arg.as_str(), |(name, _)| name);
+        wrapper_flags.iter().any(|f| f == name)
+    }) {
+        fail(format!(
+            "`{flag}` comes after the command, so it would be passed to `{command}`; \
+            move it before `{command}`, or use `rusty-claude --cmd {command} -- ...` \
+            if `{command}` really takes it"
+        ));
+    }
+    cli.cmd = Some(command);
+    cli
+}
 
-        let code = status.code();
-        let (retry, retry_after_ms) =
-            should_retry(&combined_text, code, cli.retry_on_any_error, &retry_regexes);
-        if !retry || attempt == cli.max_retries {
-            // Final failure: exit with the child's code
-            std::process::exit(code.unwrap_or(1));
+fn main() {
+    let matches = cli_command().get_matches();
+    let (mut cli, matches) = match matches.subcommand() {
+        Some(("exec", sub)) => (exec_form(&matches, sub), sub),
+        _ => (
+            Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit()),
+            &matches,
+        ),
+    };
+    match &cli.command {
+        Some(Commands::SelfTest(args)) => std::process::exit(selftest::run(args)),
+        Some(Commands::Bench(args)) => std::process::exit(bench::run(args)),
+        Some(Commands::Stats(args)) => std::process::exit(stats::run(args)),
+        Some(Commands::Simulate(_)) => std::process::exit(simulate::run(cli, matches)),
+        Some(Commands::Doctor(_)) => std::process::exit(doctor::run(cli, matches)),
+        Some(Commands::FakeChild(args)) => {
+            std::process::exit(fake_child::run(args).unwrap_or(exit_codes::INTERNAL_ERROR))
         }
-
-        let wait = retry_after_ms
-            .unwrap_or_else(|| backoff_ms(attempt, cli.base_delay_ms, cli.max_delay_ms));
+        None => {}
+    }
+    shutdown::install();
+    let reason_file = cli.reason_file.clone();
+    let stats_sink = cli.stats_sink.clone();
+    let stats_summary = cli.stats;
+    // `run` refuses `-` under --raw-passthrough; the refusal must not write it either
+    let stats_json = cli
+        .stats_json
+        .clone()
+        .filter(|path| !(cli.raw_passthrough && path == Path::new("-")));
+    let verbose = cli.verbose;
+    let started = Instant::now();
+    let started_at_ms = artifacts::unix_ms();
+    let run_id = cli.run_id.get_or_insert_with(runid::generate).clone();
+    let outcome = run::run(cli, matches).unwrap_or_else(|e| {
+        eprintln!("[rusty-claude] internal error: {e}");
+        Outcome::wrapper(Reason::InternalError, exit_codes::INTERNAL_ERROR, 0)
+    });
+    if let Some(path) = reason_file {
+        if let Err(e) = outcome.write_reason_file(&path, &run_id) {
+            eprintln!(
+                "[rusty-claude] warning: could not write reason file {}: {e}",
+                path.display()
+            );
+        }
+    }
+    if verbose > 1 {
         eprintln!(
-            "[rusty-claude] attempt={} failed (code={:?}); retrying in {}ms",
-            attempt + 1,
-            code,
-            wait
+            "[rusty-claude] memory: the wrapper's buffers held at most {}",
+            format_size(memory::peak())
         );
-        thread::sleep(Duration::from_millis(wait));
     }
+    log_file::summary(&outcome, started.elapsed().as_millis() as u64);
+    if stats_summary || stats_json.is_some() {
+        let doc = summary::document(&outcome, &run_id, started.elapsed().as_millis() as u64);
+        if stats_summary {
+            eprint!("{}", summary::render(&doc));
+        }
+        if let Some(path) = stats_json {
+            if let Err(e) = summary::write_json(&path, &doc) {
+                eprintln!(
+                    "[rusty-claude] warning: could not write --stats-json {}: {e}",
+                    path.display()
+                );
+            }
+        }
+    }
+    if let Some(path) = stats_sink {
+        let duration_ms = started.elapsed().as_millis() as u64;
+        let record = stats::record(&outcome, &run_id, started_at_ms, duration_ms);
+        if let Err(e) = stats::append(&path, &record) {
+            eprintln!(
+                "[rusty-claude] warning: could not append to stats sink {}: {e}",
+                path.display()
+            );
+        }
+    }
+    // Whatever the run's own drops didn't release
+    shutdown::shutdown();
+    std::process::exit(outcome.exit_code);
+}
 
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_beat_the_environment_which_beats_the_config_file() {
+        // Only this test reads the knob
+        std::env::set_var("CLAUDE_SUPERVISOR_CAP_MS", "700");
+        let config = config::Config::parse(
+            Path::new("c.toml"),
+            "max_retries = 2\nmax_delay_ms = 500\nbase_delay_ms = 50\n",
+        )
+        .unwrap();
+        let layers = config::Layers {
+            file: Some(config),
+            claude_settings: None,
+        };
+        let resolve = |args: &[&str]| {
+            let matches = Cli::command()
+                .try_get_matches_from(std::iter::once("rusty-claude").chain(args.iter().copied()))
+                .expect("valid flags");
+            let mut cli = Cli::from_arg_matches(&matches).unwrap();
+            let settings = resolve_settings(&mut cli, &matches, &layers);
+            let source = |key| {
+                let s = settings.iter().find(|s| s.key == key).unwrap();
+                (s.value.clone(), s.source.to_string())
+            };
+            [
+                source("max_retries"),
+                source("max_delay_ms"),
+                source("base_delay_ms"),
+            ]
+        };
+        let [retries, cap, base] = resolve(&[]);
+        assert_eq!(retries, ("2".into(), "config c.toml:1".into()));
+        assert_eq!(cap.0, "700");
+        assert!(cap.1.contains("CLAUDE_SUPERVISOR_CAP_MS"), "{}", cap.1);
+        assert_eq!(base, ("50".into(), "config c.toml:3".into()));
+        let [retries, cap, _] = resolve(&["--max-retries", "4", "--max-delay-ms", "900"]);
+        assert_eq!(retries, ("4".into(), "flag".into()));
+        assert_eq!(cap, ("900".into(), "flag".into()));
+        std::env::remove_var("CLAUDE_SUPERVISOR_CAP_MS");
+    }
 }
@@ -0,0 +1,294 @@
+//! Bookkeeping of the wrapper's own big buffers, for `-vv` and `--self-mem-limit`, and the
+//! `--max-capture-bytes` cap on each captured stream.
+//!
+//! Each buffer kind is a gauge set to what that buffer holds now; their sum is checked
+//! against the limit whenever a captured stream grows. A stream that would pass the limit
+//! stops growing instead: output held back for `--buffer-output` moves to a temp file, and
+//! any other capture, which only exists to be matched against, keeps just its most recent
+//! output. Replayed stdin held in memory is always kept whole, since every attempt needs all
+//! of it; input over `--stdin-spool-threshold-bytes` is replayed from disk instead (see
+//! [`spool`](crate::spool)).
+//!
+//! A captured stream that isn't held back also keeps only its last `--max-capture-bytes`,
+//! which is where a CLI prints the error it died of. Wherever a capture is cut, it keeps the
+//! rest of the line the cut falls in, up to [`OVERLAP`] bytes, so a pattern matching that
+//! line whole still matches it.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+
+use crate::shutdown::{self, Priority, Registration};
+use crate::size::format_size;
+
+/// A kind of buffer the wrapper holds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Buffer {
+    /// Captured stdin, replayed to every attempt.
+    Stdin,
+    /// The current attempt's stdout.
+    Stdout,
+    /// The current attempt's stderr.
+    Stderr,
+    /// Both streams joined for pattern matching.
+    Text,
+    /// The previous attempt's stderr, kept for `--feed-previous-error`.
+    PreviousStderr,
+}
+
+const BUFFERS: [Buffer; 5] = [
+    Buffer::Stdin,
+    Buffer::Stdout,
+    Buffer::Stderr,
+    Buffer::Text,
+    Buffer::PreviousStderr,
+];
+
+impl Buffer {
+    fn as_str(self) -> &'static str {
+        match self {
+            Buffer::Stdin => "stdin",
+            Buffer::Stdout => "stdout",
+            Buffer::Stderr => "stderr",
+            Buffer::Text => "match text",
+            Buffer::PreviousStderr => "previous stderr",
+        }
+    }
+}
+
+static HELD: [AtomicU64; 5] = [const { AtomicU64::new(0) }; 5];
+static PEAK: AtomicU64 = AtomicU64::new(0);
+static LIMIT: OnceLock<u64> = OnceLock::new();
+static SPILLS: AtomicU64 = AtomicU64::new(0);
+static CAPTURE_LIMIT: OnceLock<u64> = OnceLock::new();
+
+/// Most of a line cut at the front of a capture that is kept anyway.
+pub const OVERLAP: usize = 4096;
+
+/// Default `--max-capture-bytes`.
+pub const DEFAULT_CAPTURE_LIMIT: u64 = 4 << 20;
+
+/// Set `--self-mem-limit` for the rest of the process.
+pub fn set_limit(limit: u64) {
+    let _ = LIMIT.set(limit);
+}
+
+pub fn limit() -> Option<u64> {
+    LIMIT.get().copied()
+}
+
+/// Set `--max-capture-bytes` for the rest of the process; 0 keeps every stream whole.
+pub fn set_capture_limit(limit: u64) {
+    let _ = CAPTURE_LIMIT.set(limit);
+}
+
+fn capture_limit() -> Option<u64> {
+    CAPTURE_LIMIT.get().copied().filter(|&l| l > 0)
+}
+
+/// Record that `buffer` now holds `bytes`.
+pub fn set(buffer: Buffer, bytes: u64) {
+    HELD[buffer as usize].store(bytes, Ordering::Relaxed);
+    PEAK.fetch_max(total(), Ordering::Relaxed);
+}
+
+pub fn held(buffer: Buffer) -> u64 {
+    HELD[buffer as usize].load(Ordering::Relaxed)
+}
+
+pub fn total() -> u64 {
+    BUFFERS.iter().map(|&b| held(b)).sum()
+}
+
+/// The most all the buffers held at once.
+pub fn peak() -> u64 {
+    PEAK.load(Ordering::Relaxed)
+}
+
+/// e.g. `stdin 0B, stdout 1.05MB, ...; 1.05MB in all, peak 2.1MB`.
+pub fn render() -> String {
+    let each: Vec<String> = BUFFERS
+        .iter()
+        .map(|&b| format!("{} {}", b.as_str(), format_size(held(b))))
+        .collect();
+    let limit = limit()
+        .map(|l| format!(" of --self-mem-limit {}", format_size(l)))
+        .unwrap_or_default();
+    format!(
+        "{}; {} in all{limit}, peak {}",
+        each.join(", "),
+        format_size(total()),
+        format_size(peak())
+    )
+}
+
+/// Held-back output moved out of memory; the file is removed with the spill.
+pub struct Spill {
+    file: File,
+    _removal: Registration,
+}
+
+impl Spill {
+    fn create(buffer: Buffer) -> io::Result<Self> {
+        let path: PathBuf = std::env::temp_dir().join(format!(
+            "rusty-claude-spill-{}-{}-{}",
+            std::process::id(),
+            buffer.as_str(),
+            SPILLS.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        Ok(Spill {
+            file,
+            _removal: shutdown::register("output spill file", Priority::Normal, move || {
+                let _ = fs::remove_file(path);
+            }),
+        })
+    }
+}
+
+/// One stream's output as captured, counted against the limit.
+pub struct Capture {
+    buffer: Buffer,
+    /// Held back for `--buffer-output`, so none of it may be lost.
+    held_back: bool,
+    bytes: Vec<u8>,
+    /// Cut off the front of `bytes` to stay within the limits.
+    dropped: u64,
+    /// Some of the cut was for `--self-mem-limit`, not only `--max-capture-bytes`.
+    over_mem_limit: bool,
+    /// All of a held-back stream, once it no longer fit.
+    spill: Option<Spill>,
+    /// No spill file could be made, so a held-back stream stays in memory.
+    unspillable: bool,
+}
+
+impl Capture {
+    pub fn new(buffer: Buffer, held_back: bool) -> Self {
+        set(buffer, 0);
+        Capture {
+            buffer,
+            held_back,
+            bytes: Vec::new(),
+            dropped: 0,
+            over_mem_limit: false,
+            spill: None,
+            unspillable: false,
+        }
+    }
+
+    pub fn held_back(&self) -> bool {
+        self.held_back
+    }
+
+    /// The captured output: all of it, or its most recent part under the limit.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Everything the stream wrote, captured or not.
+    pub fn len(&self) -> u64 {
+        self.bytes.len() as u64 + self.dropped
+    }
+
+    /// How much of the output `bytes` lacks at its front.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Whether a cut was for `--self-mem-limit` rather than `--max-capture-bytes` alone.
+    pub fn over_mem_limit(&self) -> bool {
+        self.over_mem_limit
+    }
+
+    pub fn spilled(&self) -> bool {
+        self.spill.is_some()
+    }
+
+    /// Add `data` to the capture. When that would pass the limit, a held-back stream moves
+    /// to a temp file (or, when none can be made, stays in memory over the limit), and
+    /// `bytes` keeps only the most recent three quarters of what fits. A stream not held back
+    /// that has grown an eighth past `--max-capture-bytes` keeps its last that many bytes.
+    /// `Err` is a failed write to the spill file.
+    pub fn push(&mut self, data: &[u8]) -> io::Result<()> {
+        if let Some(spill) = self.spill.as_mut() {
+            spill.file.write_all(data)?;
+        }
+        self.bytes.extend_from_slice(data);
+        // Half of what's free, as every captured byte is copied into the match text once
+        // the attempt is over
+        let fits = limit().map(|limit| limit.saturating_sub(total() - held(self.buffer)) / 2);
+        if let Some(fits) = fits.filter(|&f| self.bytes.len() as u64 > f) {
+            if self.held_back && self.spill.is_none() && !self.unspillable {
+                match Spill::create(self.buffer).and_then(|mut spill| {
+                    spill.file.write_all(&self.bytes)?;
+                    Ok(spill)
+                }) {
+                    Ok(spill) => self.spill = Some(spill),
+                    Err(e) => {
+                        eprintln!(
+                            "[rusty-claude] warning: --self-mem-limit: cannot move the held-back \
+                            {} to disk ({e}); keeping it in memory",
+                            self.buffer.as_str()
+                        );
+                        self.unspillable = true;
+                    }
+                }
+            }
+            if !self.held_back || self.spill.is_some() {
+                // Cut well below the limit, so the front isn't moved again on every read
+                self.trim_front((fits / 4 * 3) as usize);
+                self.over_mem_limit = true;
+            }
+        }
+        // Past the cap by a margin, for the same reason
+        if let Some(cap) = capture_limit().filter(|_| !self.held_back) {
+            if self.bytes.len() as u64 > cap + cap / 8 {
+                self.trim_front(cap as usize);
+            }
+        }
+        set(self.buffer, self.bytes.len() as u64);
+        Ok(())
+    }
+
+    /// Drop the front of `bytes` down to about `keep`, keeping the rest of the line the cut
+    /// would fall in when it starts within `OVERLAP` bytes of it.
+    fn trim_front(&mut self, keep: usize) {
+        let cut = self.bytes.len().saturating_sub(keep);
+        if cut == 0 {
+            return;
+        }
+        let from = cut.saturating_sub(OVERLAP);
+        let cut = memchr::memrchr(b'\n', &self.bytes[from..cut]).map_or(from, |i| from + i + 1);
+        self.bytes.drain(..cut);
+        self.dropped += cut as u64;
+    }
+
+    /// Write all of the output to `dst`, from the spill file if there is one.
+    pub fn write_to(&self, dst: &mut impl Write) -> io::Result<()> {
+        match &self.spill {
+            Some(spill) => {
+                let mut file = &spill.file;
+                file.seek(SeekFrom::Start(0))?;
+                io::copy(&mut file, dst).map(drop)
+            }
+            None => dst.write_all(&self.bytes),
+        }
+    }
+
+    /// Hand the captured bytes over, no longer counted as this stream's.
+    pub fn into_bytes(mut self) -> Vec<u8> {
+        std::mem::take(&mut self.bytes)
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        set(self.buffer, 0);
+    }
+}
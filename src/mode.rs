@@ -0,0 +1,460 @@
+//! How the child is attached (piped, interactive, or through a PTY), and the warnings for
+//! settings that can never retry anything in the chosen mode.
+
+use crate::{resume, tag, Cli};
+
+/// How the child is attached, decided once per run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// Interactive session on the inherited terminal.
+    Interactive,
+    /// Interactive session on a PTY relayed by rusty-claude; with `capture` (`--force-tee`)
+    /// the relayed output is also kept for retry patterns.
+    Pty { capture: bool },
+    /// Output teed through pipes and scanned for retryable errors.
+    Piped,
+}
+
+impl Mode {
+    pub fn interactive(self) -> bool {
+        self != Mode::Piped
+    }
+
+    /// The `-v` line saying why this mode was picked.
+    pub fn explain(self, cli: &Cli) -> &'static str {
+        match self {
+            Mode::Interactive => "interactive: terminal stdin and no child args",
+            Mode::Pty { capture: true } => {
+                "interactive on a PTY with output capture: --force-tee in a terminal session"
+            }
+            Mode::Pty { capture: false } => "interactive on a PTY: --pty",
+            Mode::Piped if cli.server_mode => "piped: --server-mode",
+            Mode::Piped if cli.args.is_empty() => "piped: stdin is not a terminal",
+            Mode::Piped => "piped: child args given",
+        }
+    }
+}
+
+/// What `decide_mode` looks at.
+pub struct ModeInputs {
+    /// Stdin is a terminal and nothing was read from it.
+    pub terminal: bool,
+    pub has_args: bool,
+    pub force_tee: bool,
+    pub pty: bool,
+    pub server_mode: bool,
+    /// Whether this build can run a child on a PTY.
+    pub pty_supported: bool,
+}
+
+/// Pick the run's `Mode`. A terminal session with no child args stays interactive;
+/// `--force-tee` there captures through the PTY relay rather than pipes, which would take
+/// the terminal away from the child's UI.
+pub fn decide_mode(i: &ModeInputs) -> Result<Mode, String> {
+    if i.pty && !i.pty_supported {
+        return Err("--pty is only supported on Unix".to_string());
+    }
+    if i.server_mode || !i.terminal || i.has_args {
+        return Ok(Mode::Piped);
+    }
+    match (i.force_tee, i.pty_supported) {
+        (true, true) => Ok(Mode::Pty { capture: true }),
+        (true, false) => Err(
+            "--force-tee in an interactive terminal session needs a PTY, which is only \
+            supported on Unix; piping the session would break the child's UI, so pass child \
+            args after `--` or pipe stdin to run it non-interactively"
+                .to_string(),
+        ),
+        (false, _) if i.pty => Ok(Mode::Pty { capture: false }),
+        (false, _) => Ok(Mode::Interactive),
+    }
+}
+
+/// Child args for which a retry can never produce a different result.
+const ONE_SHOT_ARGS: &[&str] = &["-h", "--help", "-v", "--version"];
+
+/// Detect configurations where `should_retry` can never return true for the selected mode,
+/// returning one message per problem that explains which knob to set.
+pub fn config_warnings(
+    cli: &Cli,
+    mode: Mode,
+    pattern_count: usize,
+    user_patterns: bool,
+) -> Vec<String> {
+    let interactive = mode.interactive();
+    let mut warnings = Vec::new();
+    if cli.max_retries == 0 && !cli.server_mode {
+        warnings.push(
+            "--max-retries is 0, so a failed attempt is never retried; \
+            raise --max-retries (or RUSTY_CLAUDE_MAX_RETRIES)"
+                .to_string(),
+        );
+    }
+    if interactive {
+        if user_patterns && mode != (Mode::Pty { capture: true }) {
+            warnings.push(
+                "retry patterns are ignored in interactive mode (only a non-zero exit retries); \
+                pipe stdin, pass child args after `--`, or add --force-tee to enable pattern \
+                matching"
+                    .to_string(),
+            );
+        }
+    } else if cli.pty {
+        warnings.push(
+            "--pty only applies to interactive sessions (TTY stdin, no child args) and is ignored here"
+                .to_string(),
+        );
+    }
+    if !interactive
+        && pattern_count == 0
+        && !cli.retry_on_any_error
+        && cli.retry_exit_codes.is_none()
+        && !cli.server_mode
+    {
+        warnings.push(
+            "no retry patterns are active and --retry-on-any-error is off, so nothing can trigger a retry; \
+            add --patterns (or RUSTY_CLAUDE_PATTERNS) or pass --retry-on-any-error"
+                .to_string(),
+        );
+    }
+    if cli.control_file.is_some() && interactive {
+        warnings.push(
+            "--control-file only applies to non-interactive attempts and server mode and is \
+            ignored here"
+                .to_string(),
+        );
+    }
+    if cli.stream_match && (interactive || cli.server_mode) {
+        warnings.push(
+            "--stream-match only watches the output of non-interactive attempts and is ignored \
+            here"
+                .to_string(),
+        );
+    }
+    if cli.buffer_output && (interactive || cli.server_mode) {
+        warnings.push(
+            "--buffer-output only holds back the output of non-interactive attempts and is \
+            ignored here"
+                .to_string(),
+        );
+    }
+    if cli.json_errors && (interactive || cli.server_mode) {
+        warnings.push(
+            "--json-errors only reads the stdout of non-interactive attempts and is ignored here"
+                .to_string(),
+        );
+    }
+    if cli.decision_trace.is_some() && (interactive || cli.server_mode) {
+        warnings.push(
+            "--decision-trace only records non-interactive attempts and is ignored here"
+                .to_string(),
+        );
+    }
+    if cli.log_file.is_some() && (interactive || cli.server_mode) {
+        warnings.push(
+            "--log-file only records non-interactive attempts; here it gets the summary alone"
+                .to_string(),
+        );
+    }
+    if cli.attempt_artifacts.is_some() && (interactive || cli.server_mode) {
+        warnings.push(
+            "--attempt-artifacts only records non-interactive attempts and is ignored here"
+                .to_string(),
+        );
+    }
+    if cli.retry_on_success_match && (interactive || cli.server_mode) {
+        warnings.push(
+            "--retry-on-success-match needs the captured output of a non-interactive attempt \
+            and is ignored here"
+                .to_string(),
+        );
+    } else if cli.retry_on_success_match && cli.success_pattern.is_some() {
+        warnings.push(
+            "--retry-on-success-match is ignored under --success-pattern, which decides success \
+            by itself"
+                .to_string(),
+        );
+    }
+    if cli.success_pattern.is_some() && (interactive || cli.server_mode) {
+        warnings.push(
+            "--success-pattern needs the captured output of a non-interactive attempt and is \
+            ignored here"
+                .to_string(),
+        );
+    }
+    if cli.strict_utf8.is_some()
+        && cli.json_events.is_none()
+        && cli.observe_socket.is_none()
+        && cli.attempt_artifacts.is_none()
+    {
+        warnings.push(
+            "--strict-utf8 applies to output excerpts in --json-events, --observe-socket events, \
+            and --attempt-artifacts metadata, none of which is enabled, and is ignored here"
+                .to_string(),
+        );
+    }
+    if cli.attempt_timeout_secs.is_some() && cli.server_mode {
+        warnings.push(
+            "--attempt-timeout-secs does not apply to a --server-mode child and is ignored here"
+                .to_string(),
+        );
+    }
+    if cli.max_total_ms.is_some() && (interactive || cli.server_mode) {
+        warnings.push(
+            "--max-total-ms bounds the retries of non-interactive runs and is ignored here"
+                .to_string(),
+        );
+    }
+    if cli.first_output_timeout.is_some() && (interactive || cli.server_mode) {
+        warnings.push(
+            "--first-output-timeout only applies to non-interactive attempts and is ignored here"
+                .to_string(),
+        );
+    }
+    if cli.idle_timeout_secs.is_some() && (interactive || cli.server_mode) {
+        warnings.push(
+            "--idle-timeout-secs only applies to non-interactive attempts and is ignored here"
+                .to_string(),
+        );
+    }
+    if cli.timeout_warning.is_some()
+        && (cli.attempt_timeout_secs.is_none() || interactive || cli.server_mode)
+    {
+        warnings.push(
+            "--timeout-warning leads --attempt-timeout-secs, which does not apply here; it is \
+            ignored"
+                .to_string(),
+        );
+    }
+    if cli.tag_attempts == Some(tag::TagMode::Env) && cli.tag_arg != tag::DEFAULT_TEMPLATE {
+        warnings.push(
+            "--tag-arg only applies with --tag-attempts header and is ignored here".to_string(),
+        );
+    }
+    if cli.on_retry_cmd.is_some() && cli.server_mode {
+        warnings.push(
+            "--on-retry-cmd does not run for server restarts and is ignored here".to_string(),
+        );
+    }
+    if !cli.fallback_args.is_empty() && cli.server_mode {
+        warnings.push(
+            "--fallback-args does not apply to server mode, which restarts every exit; it is \
+            ignored here"
+                .to_string(),
+        );
+    }
+    if cli.on_exhausted_cmd.is_some() && cli.server_mode {
+        warnings.push(
+            "--on-exhausted-cmd does not run in server mode, which never runs out of restarts; \
+            it is ignored here"
+                .to_string(),
+        );
+    }
+    if cli.retry_exit_codes.is_some() && cli.server_mode {
+        warnings.push(
+            "--retry-exit-codes doesn't apply to server mode, which restarts every exit; it is \
+            ignored here"
+                .to_string(),
+        );
+    }
+    if cli.delay_cmd.is_some() && (interactive || cli.server_mode) {
+        warnings.push(
+            "--delay-cmd only paces retries of non-interactive runs and is ignored here"
+                .to_string(),
+        );
+    }
+    if cli.feed_previous_error.is_some() && (interactive || cli.server_mode) {
+        warnings.push(
+            "--feed-previous-error needs the captured stderr of a non-interactive attempt and is \
+            ignored here"
+                .to_string(),
+        );
+    }
+    if cli.auto_resume && (interactive || cli.server_mode) {
+        warnings.push(
+            "--auto-resume reads the session id from the captured stdout of a non-interactive \
+            attempt and is ignored here"
+                .to_string(),
+        );
+    } else if cli.auto_resume {
+        if let Some(flag) = resume::own_session_flag(&cli.args, cli.retry_extra_args.as_deref()) {
+            warnings.push(format!(
+                "--auto-resume: the child args already pass `{flag}`, so no `{}` is added",
+                resume::FLAG
+            ));
+        }
+    }
+    if cli.edit_on_retry.is_some()
+        && (interactive || cli.server_mode || atty::is(atty::Stream::Stdin))
+    {
+        warnings.push(
+            "--edit-on-retry edits the captured stdin, so it needs piped input and is ignored here"
+                .to_string(),
+        );
+    }
+    if cli.ready_pattern.is_some() && cli.initial_input.is_none() && !cli.server_mode {
+        warnings.push(
+            "--ready-pattern only applies with --initial-input or --server-mode and is ignored here"
+                .to_string(),
+        );
+    }
+    if cli.retry_on_any_error {
+        if let Some(arg) = cli
+            .args
+            .iter()
+            .find(|a| ONE_SHOT_ARGS.contains(&a.as_str()))
+        {
+            warnings.push(format!(
+                "--retry-on-any-error with child arg `{arg}` makes no sense: \
+                its result will not change on retry; drop --retry-on-any-error for this invocation"
+            ));
+        }
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use rusty_claude::patterns::DEFAULT_RETRY_PATTERNS;
+
+    /// The warnings for wrapper flags `args` in `mode`, with `pattern_count` retry patterns.
+    fn warnings(
+        args: &[&str],
+        mode: Mode,
+        pattern_count: usize,
+        user_patterns: bool,
+    ) -> Vec<String> {
+        let cli = Cli::try_parse_from(std::iter::once("rusty-claude").chain(args.iter().copied()))
+            .expect("valid flags");
+        config_warnings(&cli, mode, pattern_count, user_patterns)
+    }
+
+    fn expect_one(warnings: &[String], needle: &str) {
+        assert_eq!(warnings.len(), 1, "{warnings:?}");
+        assert!(warnings[0].contains(needle), "{warnings:?}");
+    }
+
+    #[test]
+    fn force_tee_modes() {
+        let pty_capture = Ok(Mode::Pty { capture: true });
+        // terminal, has_args, force_tee, pty, server_mode, pty_supported
+        let table = [
+            (
+                (true, false, false, false, false, true),
+                Ok(Mode::Interactive),
+            ),
+            ((true, false, true, false, false, true), pty_capture),
+            ((true, false, true, true, false, true), pty_capture),
+            (
+                (true, false, false, true, false, true),
+                Ok(Mode::Pty { capture: false }),
+            ),
+            ((true, true, true, false, false, true), Ok(Mode::Piped)),
+            ((false, false, true, false, false, true), Ok(Mode::Piped)),
+            ((true, false, true, false, true, true), Ok(Mode::Piped)),
+            ((true, false, true, false, false, false), Err("--force-tee")),
+            ((false, true, false, true, false, false), Err("--pty")),
+        ];
+        for ((terminal, has_args, force_tee, pty, server_mode, pty_supported), want) in table {
+            let got = decide_mode(&ModeInputs {
+                terminal,
+                has_args,
+                force_tee,
+                pty,
+                server_mode,
+                pty_supported,
+            });
+            let ok = match (&got, &want) {
+                (Ok(got), Ok(want)) => got == want,
+                (Err(msg), Err(flag)) => msg.starts_with(flag),
+                _ => false,
+            };
+            assert!(
+                ok,
+                "terminal={terminal} args={has_args} force_tee={force_tee} pty={pty} \
+                server={server_mode} pty_supported={pty_supported}: got {got:?}, want {want:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn default_settings_can_retry() {
+        assert!(warnings(&[], Mode::Piped, DEFAULT_RETRY_PATTERNS.len(), false).is_empty());
+    }
+
+    #[test]
+    fn zero_retries() {
+        let w = warnings(&["--max-retries", "0"], Mode::Piped, 13, false);
+        expect_one(&w, "raise --max-retries");
+        let w = warnings(
+            &["--max-retries", "0", "--server-mode"],
+            Mode::Piped,
+            13,
+            false,
+        );
+        assert!(w.is_empty(), "{w:?}");
+    }
+
+    #[test]
+    fn nothing_can_trigger_a_retry() {
+        let w = warnings(&["--no-default-patterns"], Mode::Piped, 0, false);
+        expect_one(&w, "nothing can trigger a retry");
+        for knob in [
+            &["--retry-on-any-error"][..],
+            &["--retry-exit-codes", "1"],
+            &["--server-mode"],
+        ] {
+            let w = warnings(knob, Mode::Piped, 0, false);
+            assert!(w.is_empty(), "{knob:?}: {w:?}");
+        }
+    }
+
+    #[test]
+    fn patterns_ignored_in_interactive_mode() {
+        let w = warnings(&[], Mode::Interactive, 14, true);
+        expect_one(&w, "ignored in interactive mode");
+        let w = warnings(&[], Mode::Pty { capture: false }, 14, true);
+        expect_one(&w, "--force-tee");
+        // Captured, the session is matched like a piped attempt
+        assert!(warnings(&[], Mode::Pty { capture: true }, 14, true).is_empty());
+        // Only patterns of the user's own are worth a warning
+        assert!(warnings(&[], Mode::Interactive, 13, false).is_empty());
+    }
+
+    #[test]
+    fn pty_without_a_session() {
+        let w = warnings(&["--pty"], Mode::Piped, 13, false);
+        expect_one(&w, "--pty only applies to interactive sessions");
+    }
+
+    #[test]
+    fn retry_on_any_error_with_one_shot_args() {
+        for arg in ONE_SHOT_ARGS {
+            let w = warnings(&["--retry-on-any-error", "--", arg], Mode::Piped, 13, false);
+            expect_one(&w, &format!("child arg `{arg}` makes no sense"));
+        }
+        let w = warnings(&["--", "--help"], Mode::Piped, 13, false);
+        assert!(w.is_empty(), "{w:?}");
+    }
+
+    #[test]
+    fn ignored_outside_piped_runs() {
+        for (flag, needle) in [
+            ("--stream-match", "--stream-match only watches"),
+            ("--buffer-output", "--buffer-output only holds back"),
+            ("--json-errors", "--json-errors only reads"),
+            ("--auto-resume", "--auto-resume reads the session id"),
+        ] {
+            let w = warnings(&[flag], Mode::Interactive, 13, false);
+            expect_one(&w, needle);
+            let w = warnings(&[flag, "--server-mode"], Mode::Piped, 13, false);
+            expect_one(&w, needle);
+            assert!(
+                warnings(&[flag], Mode::Piped, 13, false).is_empty(),
+                "{flag}"
+            );
+        }
+    }
+}
@@ -0,0 +1,278 @@
+//! `--observe-socket`: stream live output and supervisor events to any number of external
+//! observers over a Unix domain socket, without ever slowing down the child.
+//!
+//! Every frame is one line of JSON:
+//!
+//! - `{"type":"hello","schema":"rusty-claude/observe/1","pid":..,"version":"..","run_id":".."}`
+//!   once per connection
+//! - `{"type":"output","attempt":N,"stream":"stdout"|"stderr","data":"<base64>"}`
+//! - `{"type":"event","event":"..",...}` with the same fields as `--json-events`
+//! - `{"type":"dropped","count":N}` when a slow observer lost frames
+//!
+//! Each client has a bounded queue; when it is full the oldest frame is dropped and counted.
+//! Clients may send `{"cmd":"drain"}` or `{"cmd":"abort"}` lines back (see `control`).
+
+use std::collections::VecDeque;
+use std::io;
+#[cfg(unix)]
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+#[cfg(unix)]
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::json;
+
+use crate::shutdown::{self, Priority, Registration};
+
+/// Frames buffered per client before the oldest are dropped.
+const CLIENT_QUEUE_FRAMES: usize = 1024;
+
+#[derive(Default)]
+struct Queue {
+    frames: VecDeque<Arc<str>>,
+    dropped: u64,
+    closed: bool,
+    /// The writer thread holds frames it has taken but not yet written.
+    writing: bool,
+}
+
+#[derive(Default)]
+struct Client {
+    queue: Mutex<Queue>,
+    wake: Condvar,
+}
+
+/// Fan-out point shared by the tee readers, the event sink, and the listener.
+#[derive(Default)]
+pub struct Hub {
+    clients: Mutex<Vec<Arc<Client>>>,
+    /// Sent in each client's hello frame.
+    #[cfg_attr(not(unix), allow(dead_code))]
+    run_id: String,
+}
+
+impl Hub {
+    pub fn new(run_id: &str) -> Self {
+        Hub {
+            run_id: run_id.to_string(),
+            ..Hub::default()
+        }
+    }
+
+    /// Queue one frame (a JSON line without the newline) for every connected client.
+    pub fn publish(&self, frame: String) {
+        let Ok(mut clients) = self.clients.lock() else {
+            return;
+        };
+        if clients.is_empty() {
+            return;
+        }
+        let frame: Arc<str> = frame.into();
+        clients.retain(|client| {
+            let Ok(mut q) = client.queue.lock() else {
+                return false;
+            };
+            if q.closed {
+                return false;
+            }
+            if q.frames.len() >= CLIENT_QUEUE_FRAMES {
+                q.frames.pop_front();
+                q.dropped += 1;
+            }
+            q.frames.push_back(Arc::clone(&frame));
+            client.wake.notify_one();
+            true
+        });
+    }
+
+    pub fn output(&self, attempt: u32, stream: &str, data: &[u8]) {
+        if self.clients.lock().is_ok_and(|c| c.is_empty()) {
+            return;
+        }
+        self.publish(
+            json!({
+                "type": "output",
+                "attempt": attempt,
+                "stream": stream,
+                "data": base64(data),
+            })
+            .to_string(),
+        );
+    }
+
+    /// Whether every live client has been handed all of its frames.
+    fn drained(&self) -> bool {
+        self.clients.lock().map_or(true, |clients| {
+            clients.iter().all(|c| {
+                c.queue
+                    .lock()
+                    .map_or(true, |q| q.closed || (q.frames.is_empty() && !q.writing))
+            })
+        })
+    }
+
+    /// Start serving `conn` on its own writer thread.
+    #[cfg(unix)]
+    fn add(&self, mut conn: impl Write + Send + 'static) {
+        let client = Arc::new(Client::default());
+        let hello = json!({
+            "type": "hello",
+            "schema": crate::schema::OBSERVE,
+            "pid": std::process::id(),
+            "version": env!("CARGO_PKG_VERSION"),
+            "run_id": self.run_id,
+        });
+        if writeln!(conn, "{hello}").is_err() {
+            return;
+        }
+        if let Ok(mut clients) = self.clients.lock() {
+            clients.push(Arc::clone(&client));
+        }
+        thread::spawn(move || serve(&client, &mut conn));
+    }
+}
+
+#[cfg(unix)]
+fn serve(client: &Client, conn: &mut dyn Write) {
+    loop {
+        let (dropped, frames) = {
+            let Ok(mut q) = client.queue.lock() else {
+                return;
+            };
+            while q.frames.is_empty() {
+                q = match client.wake.wait(q) {
+                    Ok(q) => q,
+                    Err(_) => return,
+                };
+            }
+            q.writing = true;
+            (
+                std::mem::take(&mut q.dropped),
+                q.frames.drain(..).collect::<Vec<_>>(),
+            )
+        };
+        let mut write = || -> io::Result<()> {
+            if dropped > 0 {
+                writeln!(conn, "{}", json!({ "type": "dropped", "count": dropped }))?;
+            }
+            for frame in &frames {
+                writeln!(conn, "{frame}")?;
+            }
+            conn.flush()
+        };
+        let failed = write().is_err();
+        if let Ok(mut q) = client.queue.lock() {
+            q.writing = false;
+            if failed {
+                q.closed = true;
+                q.frames.clear();
+            }
+        }
+        if failed {
+            return;
+        }
+    }
+}
+
+/// How long the end of the run waits for observers to receive their queued frames.
+const DRAIN_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Gives observers a moment to receive the final frames, then removes the socket file.
+pub struct SocketGuard {
+    _removal: Registration,
+}
+
+impl SocketGuard {
+    #[cfg_attr(not(unix), allow(dead_code))]
+    fn new(path: PathBuf, hub: Arc<Hub>) -> Self {
+        let removal = shutdown::register("observer socket", Priority::Normal, move || {
+            let deadline = Instant::now() + DRAIN_TIMEOUT;
+            while Instant::now() < deadline && !hub.drained() {
+                std::thread::sleep(Duration::from_millis(5));
+            }
+            let _ = std::fs::remove_file(path);
+        });
+        SocketGuard { _removal: removal }
+    }
+}
+
+/// Pass on every control command a client sends, until it disconnects.
+#[cfg(unix)]
+fn read_commands(conn: impl io::Read) {
+    use std::io::BufRead;
+
+    for line in io::BufReader::new(conn).lines() {
+        let Ok(line) = line else {
+            return;
+        };
+        if let Some(cmd) = crate::control::parse_frame(&line) {
+            crate::control::request(cmd, "socket");
+        }
+    }
+}
+
+/// Listen on `path`, replacing a stale socket left by a crashed run.
+#[cfg(unix)]
+pub fn listen(path: &Path, hub: Arc<Hub>) -> io::Result<SocketGuard> {
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    if let Ok(meta) = std::fs::symlink_metadata(path) {
+        if !meta.file_type().is_socket() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "path exists and is not a socket",
+            ));
+        }
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                "another process is listening on it",
+            ));
+        }
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    let accepting = Arc::clone(&hub);
+    thread::spawn(move || {
+        for conn in listener.incoming().flatten() {
+            if let Ok(reader) = conn.try_clone() {
+                thread::spawn(move || read_commands(reader));
+            }
+            accepting.add(conn);
+        }
+    });
+    Ok(SocketGuard::new(path.to_path_buf(), hub))
+}
+
+#[cfg(not(unix))]
+pub fn listen(_path: &Path, _hub: Arc<Hub>) -> io::Result<SocketGuard> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "observer sockets are only supported on Unix",
+    ))
+}
+
+/// Standard base64 with padding, for raw output bytes inside JSON frames.
+pub fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = (u32::from(b[0]) << 16) | (u32::from(b[1]) << 8) | u32::from(b[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
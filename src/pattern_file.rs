@@ -0,0 +1,32 @@
+//! `--patterns-file`: retry patterns one per line, so `|` is plain alternation and needs no
+//! escaping. Blank lines and lines starting with `#` are skipped, and a CRLF file reads the
+//! same as an LF one. Unlike a pattern on the command line, one that doesn't compile is a
+//! startup error naming the file and line.
+
+use std::fs;
+use std::path::Path;
+
+use regex::Regex;
+use rusty_claude::patterns::regex_error;
+
+/// The patterns in `path`, each with its `PATH:LINE` source.
+pub fn read(path: &Path) -> Result<Vec<(String, String)>, String> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("cannot read --patterns-file {}: {e}", path.display()))?;
+    let mut patterns = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let source = format!("{}:{}", path.display(), n + 1);
+        if let Err(e) = Regex::new(line) {
+            return Err(format!(
+                "pattern `{line}` at {source} does not compile: {}",
+                regex_error(&e)
+            ));
+        }
+        patterns.push((line.to_string(), source));
+    }
+    Ok(patterns)
+}
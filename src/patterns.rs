@@ -0,0 +1,730 @@
+//! Retry and fatal patterns, and the decision a failed attempt's output leads to.
+//!
+//! The built-in retry patterns each belong to an [`ErrorClass`]; patterns of your own have
+//! none. A retry pattern may carry a minimum delay, the least wait after it matches. A fatal
+//! pattern anywhere in the output rules out a retry whatever else matches.
+//! Output is scanned from its last line back, since that's where a CLI prints the error it
+//! died of. Stdout and stderr are kept apart, so the patterns can be held to one of them
+//! ([`MatchStreams`]).
+
+use std::time::{Duration, Instant, SystemTime};
+
+use clap::ValueEnum;
+use regex::{Regex, RegexBuilder, RegexSet, SetMatches};
+
+use crate::classes::ErrorClass;
+use crate::code_list::CodeList;
+use crate::retry_after;
+
+/// Built-in retry patterns: overloads, rate limits, and network trouble, each with its
+/// class (`--no-default-patterns` drops them).
+pub const DEFAULT_RETRY_PATTERNS: &[(&str, ErrorClass)] = &[
+    ("(?i)overloaded", ErrorClass::Server),
+    (r"(?i)HTTP\s*500", ErrorClass::Server),
+    (
+        r"(?i)\b5\d\d\s*(Server\s*Error|Error)\b",
+        ErrorClass::Server,
+    ),
+    (r"(?i)status\s*code\s*=\s*5\d\d", ErrorClass::Server),
+    (r"(?i)Too\s*Many\s*Requests", ErrorClass::RateLimit),
+    (r"(?i)\b429\b", ErrorClass::RateLimit),
+    (r"(?i)ECONNRESET", ErrorClass::Network),
+    (r"(?i)ETIMEDOUT", ErrorClass::Network),
+    (r"(?i)Gateway\s*Timeout", ErrorClass::Server),
+    (r"(?i)upstream\s*timeout", ErrorClass::Server),
+    (r"(?i)temporary\s*failure", ErrorClass::Network),
+    (r"(?i)(fetch|network)\s*error", ErrorClass::Network),
+    (r"(?i)socket\s*hang\s*up", ErrorClass::Network),
+];
+
+/// Minimum delays of the built-in retry patterns, in ms: a rate limit doesn't lift in the
+/// half second the first backoff would wait.
+pub const DEFAULT_PATTERN_DELAYS: &[(&str, u64)] = &[
+    (r"(?i)Too\s*Many\s*Requests", 30_000),
+    (r"(?i)\b429\b", 30_000),
+];
+
+/// Built-in fatal patterns: failures no retry can fix (`--no-default-fatal-patterns` drops
+/// them).
+pub const DEFAULT_FATAL_PATTERNS: &[&str] = &[
+    r"(?i)\b401\b",
+    r"(?i)\b403\b",
+    r"(?i)invalid\s*api\s*key",
+    r"(?i)credit\s*balance",
+];
+
+/// The streams of an attempt the patterns are matched against (`--match-streams`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum MatchStreams {
+    /// Only stderr, where the CLI reports transport errors; the model's answer can't match
+    Stderr,
+    /// Only stdout
+    Stdout,
+    /// Both
+    #[default]
+    Both,
+}
+
+impl MatchStreams {
+    fn includes(self, stream: Stream) -> bool {
+        match self {
+            MatchStreams::Both => true,
+            MatchStreams::Stderr => stream == Stream::Stderr,
+            MatchStreams::Stdout => stream == Stream::Stdout,
+        }
+    }
+}
+
+/// One of an attempt's output streams.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl Stream {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Stream::Stdout => "stdout",
+            Stream::Stderr => "stderr",
+        }
+    }
+}
+
+/// An attempt's output as the patterns see it.
+#[derive(Clone, Copy, Debug)]
+pub enum Output<'a> {
+    /// Stdout and stderr apart, matched as [`Patterns::streams`] says.
+    Streams { stdout: &'a str, stderr: &'a str },
+    /// One stream both went to, e.g. a terminal session's; matched whatever the scope.
+    Merged(&'a str),
+}
+
+impl<'a> Output<'a> {
+    /// The text of `stream` if `streams` takes it in; a merged output is taken in whole.
+    fn text(self, stream: Option<Stream>, streams: MatchStreams) -> Option<&'a str> {
+        match (self, stream) {
+            (Output::Merged(text), None) => Some(text),
+            (Output::Streams { stdout, stderr }, Some(stream)) if streams.includes(stream) => {
+                Some(if stream == Stream::Stdout {
+                    stdout
+                } else {
+                    stderr
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The compiled retry patterns with their error classes (user patterns have none) and the
+/// fatal patterns, each with a `RegexSet` over them for one-pass line matching.
+pub struct Patterns {
+    pub regexes: Vec<Regex>,
+    pub classes: Vec<Option<ErrorClass>>,
+    /// The least wait after each retry pattern matches, in ms; `None` for most.
+    pub min_delays: Vec<Option<u64>>,
+    pub set: RegexSet,
+    pub fatal: Vec<Regex>,
+    pub fatal_set: RegexSet,
+    /// Where each user pattern came from (`--patterns`, or the variable); the rest are
+    /// built in.
+    pub sources: Vec<(String, String)>,
+    /// `--retry-exit-codes` and `--no-retry-exit-codes`.
+    pub retry_codes: CodeList,
+    pub no_retry_codes: CodeList,
+    /// `--match-streams`.
+    pub streams: MatchStreams,
+}
+
+impl Patterns {
+    pub fn new(
+        patterns: Vec<(Regex, Option<ErrorClass>)>,
+        fatal: Vec<Regex>,
+    ) -> Result<Self, String> {
+        let (regexes, classes): (Vec<_>, Vec<_>) = patterns.into_iter().unzip();
+        let set = RegexSet::new(regexes.iter().map(|re| re.as_str()))
+            .map_err(|e| format!("cannot combine retry patterns: {e}"))?;
+        let fatal_set = RegexSet::new(fatal.iter().map(|re| re.as_str()))
+            .map_err(|e| format!("cannot combine fatal patterns: {e}"))?;
+        Ok(Patterns {
+            min_delays: vec![None; regexes.len()],
+            regexes,
+            classes,
+            set,
+            fatal,
+            fatal_set,
+            sources: Vec::new(),
+            retry_codes: CodeList::default(),
+            no_retry_codes: CodeList::default(),
+            streams: MatchStreams::default(),
+        })
+    }
+
+    /// The built-in retry patterns, the built-in fatal ones, or both.
+    pub fn defaults(retry: bool, fatal: bool) -> Self {
+        let regexes = DEFAULT_RETRY_PATTERNS
+            .iter()
+            .filter(|_| retry)
+            .map(|&(p, class)| (Regex::new(p).expect("built-in pattern"), Some(class)))
+            .collect();
+        let fatal = DEFAULT_FATAL_PATTERNS
+            .iter()
+            .filter(|_| fatal)
+            .map(|p| Regex::new(p).expect("built-in pattern"))
+            .collect();
+        let mut patterns = Patterns::new(regexes, fatal).expect("built-in patterns combine");
+        patterns.default_delays();
+        patterns
+    }
+
+    /// Give the built-in retry patterns among these their minimum delays.
+    pub fn default_delays(&mut self) {
+        for &(pattern, ms) in DEFAULT_PATTERN_DELAYS {
+            self.set_min_delay(pattern, ms);
+        }
+    }
+
+    /// Wait at least `ms` after the retry pattern `pattern` matches (0 for no minimum);
+    /// false if there is no such pattern.
+    pub fn set_min_delay(&mut self, pattern: &str, ms: u64) -> bool {
+        match self.regexes.iter().position(|re| re.as_str() == pattern) {
+            Some(idx) => {
+                self.min_delays[idx] = Some(ms).filter(|&ms| ms > 0);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Where `pattern` came from: its source if it is a user pattern, else `built-in`.
+    pub fn source(&self, pattern: &str) -> &str {
+        self.sources
+            .iter()
+            .find(|(p, _)| p == pattern)
+            .map_or("built-in", |(_, source)| source)
+    }
+
+    pub fn len(&self) -> usize {
+        self.regexes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.regexes.is_empty()
+    }
+}
+
+/// Compiled-program and lazy-DFA limits for user patterns, which may come from sources the
+/// caller doesn't control. Hitting either is an error.
+pub const USER_PATTERN_SIZE_LIMIT: usize = 256 * 1024;
+pub const USER_PATTERN_DFA_LIMIT: usize = 1024 * 1024;
+
+/// A user pattern, compiled under the size limits.
+pub fn build_user_pattern(p: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(p)
+        .size_limit(USER_PATTERN_SIZE_LIMIT)
+        .dfa_size_limit(USER_PATTERN_DFA_LIMIT)
+        .build()
+}
+
+/// The one-line reason a pattern failed to compile. A syntax error renders the pattern with
+/// a caret under it; its last line says why.
+pub fn regex_error(e: &regex::Error) -> String {
+    let e = e.to_string();
+    let why = e.lines().last().unwrap_or_default().trim();
+    why.trim_start_matches("error: ").to_string()
+}
+
+/// Compile one user pattern under the size limits; `None`, with a warning in `warnings`, if
+/// it is not a valid regex.
+fn compile_user_pattern(
+    p: &str,
+    source: &str,
+    warnings: &mut Vec<String>,
+) -> Result<Option<Regex>, String> {
+    match build_user_pattern(p) {
+        Ok(re) => Ok(Some(re)),
+        Err(regex::Error::CompiledTooBig(limit)) => Err(format!(
+            "pattern `{p}` from {source} is too expensive to compile \
+            (exceeds the {limit}-byte limit for user patterns)"
+        )),
+        Err(e) => {
+            warnings.push(format!(
+                "ignoring pattern `{p}` from {source}, which does not compile: {}",
+                regex_error(&e)
+            ));
+            Ok(None)
+        }
+    }
+}
+
+/// Compile the built-in retry and fatal patterns, as `default_retry` and `default_fatal`
+/// say, followed by the user's, each given with where it came from; a pattern listed twice
+/// is kept once, where it first appears. Fails if a user pattern exceeds the size limits,
+/// naming its source. One that merely doesn't compile is left out, with a warning among
+/// those returned.
+pub fn compile_patterns(
+    user: impl IntoIterator<Item = (String, String)>,
+    user_fatal: impl IntoIterator<Item = (String, String)>,
+    default_retry: bool,
+    default_fatal: bool,
+) -> Result<(Patterns, Vec<String>), String> {
+    let mut warnings = Vec::new();
+    let mut regexes: Vec<(Regex, Option<ErrorClass>)> = DEFAULT_RETRY_PATTERNS
+        .iter()
+        .filter(|_| default_retry)
+        .filter_map(|&(p, class)| Some((Regex::new(p).ok()?, Some(class))))
+        .collect();
+    let mut sources = Vec::new();
+    for (p, source) in user {
+        if regexes.iter().any(|(re, _)| re.as_str() == p) {
+            continue;
+        }
+        if let Some(re) = compile_user_pattern(&p, &source, &mut warnings)? {
+            regexes.push((re, None));
+            sources.push((p, source));
+        }
+    }
+    let mut fatal: Vec<Regex> = DEFAULT_FATAL_PATTERNS
+        .iter()
+        .filter(|_| default_fatal)
+        .filter_map(|p| Regex::new(p).ok())
+        .collect();
+    for (p, source) in user_fatal {
+        if fatal.iter().any(|re| re.as_str() == p) {
+            continue;
+        }
+        if let Some(re) = compile_user_pattern(&p, &source, &mut warnings)? {
+            fatal.push(re);
+            sources.push((p, source));
+        }
+    }
+    let mut patterns = Patterns {
+        sources,
+        ..Patterns::new(regexes, fatal)?
+    };
+    if default_retry {
+        patterns.default_delays();
+    }
+    Ok((patterns, warnings))
+}
+
+/// Outcome of inspecting a failed attempt.
+#[derive(Debug, Default)]
+pub struct RetryDecision {
+    pub retry: bool,
+    pub retry_after_ms: Option<u64>,
+    /// The pattern that triggered the retry, if it was a pattern match.
+    pub matched: Option<String>,
+    /// The error class of the matched pattern; `None` for user patterns and exit-code retries.
+    pub class: Option<ErrorClass>,
+    /// The largest minimum delay among the retry patterns matching the matched line, if any
+    /// has one.
+    pub min_delay_ms: Option<u64>,
+    /// The matching line, counted from the end of stdout and stderr joined in that order.
+    pub matched_line: Option<usize>,
+    /// The stream the retry or fatal pattern matched in; `None` for merged output.
+    pub stream: Option<Stream>,
+    /// The pattern scan ran past `--match-timeout` and the decision used the exit code only.
+    pub scan_timed_out: bool,
+    /// The fatal pattern that ruled out any retry, if one matched.
+    pub fatal: Option<String>,
+    /// The exit code is in `--retry-exit-codes` and no pattern matched.
+    pub retry_code: bool,
+    /// The exit code is in `--no-retry-exit-codes`, ruling out any retry.
+    pub no_retry_code: bool,
+    /// The `error.type` in the attempt's JSON output (`--json-errors`), which decided.
+    pub json_error: Option<String>,
+}
+
+impl RetryDecision {
+    /// `wait_ms`, raised to the matched line's minimum delay.
+    pub fn floored(&self, wait_ms: u64) -> u64 {
+        wait_ms.max(self.min_delay_ms.unwrap_or(0))
+    }
+}
+
+/// With a match budget, the deadline is checked every this many lines.
+const SCAN_CHECK_EVERY: usize = 256;
+
+enum Scan {
+    /// The patterns matching the line, the lowest index first, and the line counted from
+    /// the end.
+    Matched(SetMatches, usize),
+    NoMatch,
+    TimedOut,
+}
+
+/// Lines of `text` from last to first. Retryable errors are printed at the end of output,
+/// so scanning backwards usually stops within the first few lines.
+fn lines_rev(text: &str) -> impl Iterator<Item = &str> {
+    let bytes = text.as_bytes();
+    let mut end = bytes.len();
+    let mut newlines = memchr::memrchr_iter(b'\n', bytes);
+    let mut done = false;
+    std::iter::from_fn(move || {
+        if done {
+            return None;
+        }
+        let start = match newlines.next() {
+            Some(i) => i + 1,
+            None => {
+                done = true;
+                0
+            }
+        };
+        // Splitting at '\n' (ASCII) always lands on a char boundary.
+        let line = &text[start..end];
+        end = start.saturating_sub(1);
+        Some(line)
+    })
+}
+
+/// Find the first line (from the end) matching any pattern of `set`, reporting the
+/// patterns matching on that line (the lowest index is the one reported). A pattern matching only an earlier line loses to it
+/// even when listed first; on a single line the result is the first pattern in list order,
+/// as a whole-buffer match of each pattern in turn would report. The deadline is checked
+/// between batches of lines.
+fn scan_patterns(output: &str, set: &RegexSet, deadline: Option<Instant>) -> Scan {
+    if set.is_empty() {
+        return Scan::NoMatch;
+    }
+    for (i, line) in lines_rev(output).enumerate() {
+        if i % SCAN_CHECK_EVERY == 0 && deadline.is_some_and(|d| Instant::now() >= d) {
+            return Scan::TimedOut;
+        }
+        let matches = set.matches(line);
+        if matches.matched_any() {
+            return Scan::Matched(matches, i);
+        }
+    }
+    Scan::NoMatch
+}
+
+/// The parts of an output in the order they are scanned: a merged output whole, or stderr
+/// before stdout, since read from its end the output is stdout then stderr.
+const SCAN_ORDER: [Option<Stream>; 3] = [None, Some(Stream::Stderr), Some(Stream::Stdout)];
+
+/// Find the first line (from the end) of the in-scope output matching a pattern of `set`,
+/// with its line counted as in [`RetryDecision::matched_line`] and the stream it is in.
+fn scan_output(
+    output: Output,
+    streams: MatchStreams,
+    set: &RegexSet,
+    deadline: Option<Instant>,
+) -> (Scan, Option<Stream>) {
+    for stream in SCAN_ORDER {
+        let Some(text) = output.text(stream, streams) else {
+            continue;
+        };
+        match scan_patterns(text, set, deadline) {
+            Scan::Matched(matches, line) => {
+                // Stdout's lines come after all of stderr's
+                let offset = match (output, stream) {
+                    (Output::Streams { stderr, .. }, Some(Stream::Stdout)) => {
+                        lines_rev(stderr).count()
+                    }
+                    _ => 0,
+                };
+                return (Scan::Matched(matches, line + offset), stream);
+            }
+            Scan::TimedOut => return (Scan::TimedOut, None),
+            Scan::NoMatch => {}
+        }
+    }
+    (Scan::NoMatch, None)
+}
+
+/// The lowest-index pattern of a line's matches.
+fn first(matches: &SetMatches) -> usize {
+    matches.iter().next().expect("a match")
+}
+
+/// The `Retry-After` hint in the stream a pattern matched in, else elsewhere in scope.
+fn find_retry_after(output: Output, streams: MatchStreams, matched: Option<Stream>) -> Option<u64> {
+    let now = SystemTime::now();
+    let others = SCAN_ORDER.into_iter().filter(|&s| s != matched);
+    [matched]
+        .into_iter()
+        .chain(others)
+        .filter_map(|stream| output.text(stream, streams))
+        .find_map(|text| retry_after::find_ms(text, now))
+}
+
+/// The decision ruling out a retry if a fatal pattern matches the in-scope output.
+fn fatal_decision(
+    output: Output,
+    patterns: &Patterns,
+    deadline: Option<Instant>,
+) -> Option<RetryDecision> {
+    match scan_output(output, patterns.streams, &patterns.fatal_set, deadline) {
+        (Scan::Matched(matches, line), stream) => Some(RetryDecision {
+            fatal: Some(patterns.fatal[first(&matches)].as_str().to_string()),
+            matched_line: Some(line),
+            stream,
+            ..RetryDecision::default()
+        }),
+        _ => None,
+    }
+}
+
+/// Check an attempt's output for a fatal pattern alone, whatever its exit code; `None`
+/// also when the scan runs past `match_timeout`.
+pub fn find_fatal(
+    output: Output,
+    patterns: &Patterns,
+    match_timeout: Option<Duration>,
+) -> Option<RetryDecision> {
+    fatal_decision(output, patterns, match_timeout.map(|b| Instant::now() + b))
+}
+
+/// Classify a failed attempt: a fatal pattern match anywhere in the output rules out a
+/// retry, as does a code in `--no-retry-exit-codes`; then a retry pattern match retries
+/// (with any Retry-After hint), a code in `--retry-exit-codes` does, and otherwise only
+/// `retry_on_any` does. Only the streams `patterns.streams` names are matched.
+pub fn should_retry(
+    output: Output,
+    code: Option<i32>,
+    retry_on_any: bool,
+    patterns: &Patterns,
+    match_timeout: Option<Duration>,
+) -> RetryDecision {
+    let deadline = match_timeout.map(|b| Instant::now() + b);
+    // Scanned in full before the retry patterns, so a fatal line wins wherever it is
+    if let Some(decision) = fatal_decision(output, patterns, deadline) {
+        return decision;
+    }
+    let streams = patterns.streams;
+    if patterns.no_retry_codes.contains(code) {
+        return RetryDecision {
+            no_retry_code: true,
+            ..RetryDecision::default()
+        };
+    }
+    let scan_timed_out = match scan_output(output, streams, &patterns.set, deadline) {
+        (Scan::Matched(matches, line), stream) => {
+            let idx = first(&matches);
+            return RetryDecision {
+                retry: true,
+                retry_after_ms: find_retry_after(output, streams, stream),
+                matched: Some(patterns.regexes[idx].as_str().to_string()),
+                class: patterns.classes[idx],
+                // Every pattern on the line counts, not just the one reported
+                min_delay_ms: matches.iter().filter_map(|i| patterns.min_delays[i]).max(),
+                matched_line: Some(line),
+                stream,
+                ..RetryDecision::default()
+            };
+        }
+        (Scan::NoMatch, _) => false,
+        (Scan::TimedOut, _) => true,
+    };
+    let retry_code = patterns.retry_codes.contains(code);
+    RetryDecision {
+        retry: retry_code || retry_on_any,
+        retry_code,
+        scan_timed_out,
+        ..RetryDecision::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The retry pattern `should_retry` reports for a failed merged `output`.
+    fn matched(output: &str) -> Option<String> {
+        let patterns = Patterns::defaults(true, true);
+        should_retry(Output::Merged(output), Some(1), false, &patterns, None).matched
+    }
+
+    #[test]
+    fn lines_from_the_end() {
+        let lines: Vec<&str> = lines_rev("one\ntwo\r\n\nthree").collect();
+        assert_eq!(lines, ["three", "", "two\r", "one"]);
+        let lines: Vec<&str> = lines_rev("tail\n").collect();
+        assert_eq!(lines, ["", "tail"]);
+        assert_eq!(lines_rev("").collect::<Vec<_>>(), [""]);
+    }
+
+    #[test]
+    fn one_line_reports_the_first_listed_pattern() {
+        // `overloaded` is listed before `429`, as a whole-buffer match in turn would find
+        let line = "API Error: 429 overloaded";
+        assert_eq!(matched(line).as_deref(), Some("(?i)overloaded"));
+        let patterns = Patterns::defaults(true, false);
+        let whole = patterns.regexes.iter().find(|re| re.is_match(line));
+        assert_eq!(whole.map(Regex::as_str), Some("(?i)overloaded"));
+    }
+
+    #[test]
+    fn the_last_matching_line_wins() {
+        assert_eq!(
+            matched("API Error: overloaded\nthen: 429\n").as_deref(),
+            Some(r"(?i)\b429\b")
+        );
+        assert_eq!(
+            matched("got 429\nanswer text\nAPI Error: Overloaded\n\n").as_deref(),
+            Some("(?i)overloaded")
+        );
+    }
+
+    #[test]
+    fn matched_line_counts_from_the_end() {
+        let patterns = Patterns::defaults(true, true);
+        let output = "API Error: overloaded\nmore\nlast\n";
+        let decision = should_retry(Output::Merged(output), Some(1), false, &patterns, None);
+        // The empty line after the final newline is line 0
+        assert_eq!(decision.matched_line, Some(3));
+    }
+
+    #[test]
+    fn a_fatal_line_anywhere_wins() {
+        let patterns = Patterns::defaults(true, true);
+        let output = "invalid api key\nAPI Error: overloaded\n";
+        let decision = should_retry(Output::Merged(output), Some(1), false, &patterns, None);
+        assert!(!decision.retry);
+        assert_eq!(decision.fatal.as_deref(), Some(r"(?i)invalid\s*api\s*key"));
+    }
+
+    #[test]
+    fn exit_code_lists_and_retry_on_any() {
+        let mut patterns = Patterns::defaults(true, true);
+        patterns.retry_codes = "75".parse().unwrap();
+        patterns.no_retry_codes = "3".parse().unwrap();
+        let quiet = Output::Merged("no pattern here\n");
+        let decision = should_retry(quiet, Some(75), false, &patterns, None);
+        assert!(decision.retry && decision.retry_code);
+        assert!(!should_retry(quiet, Some(1), false, &patterns, None).retry);
+        assert!(should_retry(quiet, Some(1), true, &patterns, None).retry);
+        // A code ruled out wins over a pattern and --retry-on-any-error
+        let busy = Output::Merged("API Error: overloaded\n");
+        let decision = should_retry(busy, Some(3), true, &patterns, None);
+        assert!(!decision.retry && decision.no_retry_code);
+    }
+
+    #[test]
+    fn streams_are_matched_as_scoped() {
+        let mut patterns = Patterns::defaults(true, true);
+        let output = Output::Streams {
+            stdout: "the answer mentions 429\n",
+            stderr: "",
+        };
+        assert!(should_retry(output, Some(1), false, &patterns, None).retry);
+        patterns.streams = MatchStreams::Stderr;
+        assert!(!should_retry(output, Some(1), false, &patterns, None).retry);
+        let output = Output::Streams {
+            stdout: "answer\n",
+            stderr: "API Error: overloaded\n",
+        };
+        let decision = should_retry(output, Some(1), false, &patterns, None);
+        assert_eq!(decision.stream, Some(Stream::Stderr));
+        assert_eq!(decision.class, Some(ErrorClass::Server));
+    }
+
+    #[test]
+    fn retry_after_and_minimum_delays() {
+        let mut patterns = Patterns::defaults(true, true);
+        patterns.default_delays();
+        let output = Output::Merged("API Error: 429 Too Many Requests\nRetry-After: 5\n");
+        let decision = should_retry(output, Some(1), false, &patterns, None);
+        assert_eq!(decision.retry_after_ms, Some(5_000));
+        assert_eq!(decision.min_delay_ms, Some(30_000));
+        assert_eq!(decision.floored(5_000), 30_000);
+        assert_eq!(decision.floored(45_000), 45_000);
+        let decision = should_retry(
+            Output::Merged("overloaded"),
+            Some(1),
+            false,
+            &patterns,
+            None,
+        );
+        assert_eq!(
+            (decision.retry_after_ms, decision.floored(500)),
+            (None, 500)
+        );
+    }
+
+    #[test]
+    fn the_largest_minimum_on_the_line_applies() {
+        let mut patterns = Patterns::defaults(true, true);
+        patterns.default_delays();
+        patterns.set_min_delay(r"(?i)\b429\b", 60_000);
+        let output = Output::Merged("API Error: 429 Too Many Requests\n");
+        let decision = should_retry(output, Some(1), false, &patterns, None);
+        assert_eq!(
+            decision.matched.as_deref(),
+            Some(r"(?i)Too\s*Many\s*Requests")
+        );
+        assert_eq!(decision.min_delay_ms, Some(60_000));
+        // A pattern on an earlier line doesn't count
+        let output = Output::Merged("HTTP 429\nAPI Error: Too Many Requests\n");
+        let decision = should_retry(output, Some(1), false, &patterns, None);
+        assert_eq!(decision.min_delay_ms, Some(30_000));
+    }
+
+    fn user(patterns: &[&str]) -> Vec<(String, String)> {
+        patterns
+            .iter()
+            .map(|p| (p.to_string(), "--patterns".to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn expensive_user_patterns_are_refused() {
+        let alternation = (0..5_000)
+            .map(|i| format!(r"\w+ {i}"))
+            .collect::<Vec<_>>()
+            .join("|");
+        for p in [r"(\w{100}){100}", "a{1000}{1000}", alternation.as_str()] {
+            let listed = [(p.to_string(), "CLAUDE_SUPERVISOR_PATTERNS".to_string())];
+            let err = compile_patterns(listed, [], true, true)
+                .err()
+                .expect("over the size limit");
+            assert!(
+                err.ends_with(
+                    "from CLAUDE_SUPERVISOR_PATTERNS is too expensive to compile \
+                    (exceeds the 262144-byte limit for user patterns)"
+                ),
+                "{err}"
+            );
+        }
+    }
+
+    #[test]
+    fn cheap_and_invalid_user_patterns() {
+        // A pattern that merely fails to compile is skipped with a warning, not refused
+        let (patterns, warnings) = compile_patterns(
+            user(&[r"(?i)\b(429|overloaded)\b", "(unclosed"]),
+            [],
+            false,
+            false,
+        )
+        .expect("compiles");
+        assert_eq!(patterns.len(), 1);
+        assert_eq!(warnings.len(), 1);
+        assert!(
+            warnings[0].starts_with(
+                "ignoring pattern `(unclosed` from --patterns, which does not compile: "
+            ),
+            "{warnings:?}"
+        );
+    }
+
+    #[test]
+    fn a_pattern_listed_twice_is_kept_where_it_first_appears() {
+        let (patterns, _) = compile_patterns(
+            user(&["(?i)overloaded", "busy", "busy"]),
+            user(&["quota", r"(?i)\b401\b"]),
+            true,
+            true,
+        )
+        .expect("compiles");
+        let busy = patterns.regexes.iter().filter(|re| re.as_str() == "busy");
+        assert_eq!(busy.count(), 1);
+        assert_eq!(patterns.source("(?i)overloaded"), "built-in");
+        assert_eq!(patterns.source("busy"), "--patterns");
+        assert_eq!(patterns.len(), DEFAULT_RETRY_PATTERNS.len() + 1);
+        assert_eq!(patterns.fatal.len(), DEFAULT_FATAL_PATTERNS.len() + 1);
+    }
+}
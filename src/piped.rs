@@ -0,0 +1,505 @@
+//! The plumbing of a piped attempt: the tee readers, the stdin replay, and the wait loop
+//! that watches the child and kills it when it runs into one of its limits.
+
+use std::io::{self, Read, Write};
+use std::process::{Child, ChildStdin, ExitStatus};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use regex::RegexSet;
+use rusty_claude::exec;
+use rusty_claude::patterns::Patterns;
+
+use crate::duration::format_duration;
+use crate::{artifacts, chaos, control, events, memory, observe, resume, signals, spool, tree};
+
+/// Last-activity tracking for one attempt, shared between the tee readers and the
+/// supervising wait loop.
+pub struct Activity {
+    pub started: Instant,
+    /// Milliseconds after `started` at which child output was last forwarded.
+    last_output_ms: AtomicU64,
+    pub bytes: AtomicU64,
+    /// Output pipes the tee readers have not yet seen EOF on.
+    open_streams: AtomicU64,
+    /// Milliseconds after `started` at which the stdin replay finished (`INPUT_PENDING`
+    /// until then); 0 when nothing is replayed.
+    input_done_ms: AtomicU64,
+    /// Set by the wait loop once it has failed the attempt (by killing the child). Output
+    /// read after that is still captured but, without `forward_late`, no longer forwarded,
+    /// so it can't land after our own messages about the failure.
+    failed: AtomicBool,
+    /// `--forward-late-output`.
+    forward_late: bool,
+    /// Bytes held back because they were read after `failed` was set.
+    pub late_bytes: AtomicU64,
+    /// `--buffer-limit`, under `--buffer-output`.
+    buffer_limit: Option<u64>,
+    /// Bytes held for `--buffer-output` so far.
+    buffered: AtomicU64,
+}
+
+const INPUT_PENDING: u64 = u64::MAX;
+
+impl Activity {
+    pub fn new(forward_late: bool, buffer_limit: Option<u64>) -> Self {
+        Activity {
+            started: Instant::now(),
+            last_output_ms: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            open_streams: AtomicU64::new(0),
+            input_done_ms: AtomicU64::new(0),
+            failed: AtomicBool::new(false),
+            forward_late,
+            late_bytes: AtomicU64::new(0),
+            buffer_limit,
+            buffered: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether `--buffer-output` holds more than `--buffer-limit`.
+    pub fn over_buffer_limit(&self) -> bool {
+        self.buffer_limit
+            .is_some_and(|limit| self.buffered.load(Ordering::Relaxed) > limit)
+    }
+
+    /// Mark the attempt failed; call before killing the child.
+    pub fn fail(&self) {
+        self.failed.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether output read now must be held back instead of forwarded.
+    pub fn holding_back(&self) -> bool {
+        !self.forward_late && self.failed.load(Ordering::SeqCst)
+    }
+
+    pub fn finish_input(&self) {
+        let now = self.started.elapsed().as_millis() as u64;
+        self.input_done_ms.store(now, Ordering::Relaxed);
+    }
+
+    /// How long the child has gone without any output since its input was replayed; `None`
+    /// once it has written something, or while the replay is still going.
+    pub fn silent_since_input(&self) -> Option<Duration> {
+        let done = self.input_done_ms.load(Ordering::Relaxed);
+        if self.bytes.load(Ordering::Relaxed) > 0 || done == INPUT_PENDING {
+            return None;
+        }
+        Some(
+            self.started
+                .elapsed()
+                .saturating_sub(Duration::from_millis(done)),
+        )
+    }
+
+    pub fn record(&self, n: usize) {
+        let now = self.started.elapsed().as_millis() as u64;
+        self.last_output_ms.store(now, Ordering::Relaxed);
+        self.bytes.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    /// Time since the last forwarded output, or since the attempt started if there was none.
+    pub fn idle(&self) -> Duration {
+        let last = Duration::from_millis(self.last_output_ms.load(Ordering::Relaxed));
+        self.started.elapsed().saturating_sub(last)
+    }
+
+    /// Whether every output pipe has closed, i.e. the child can't produce anything more.
+    pub fn drained(&self) -> bool {
+        self.open_streams.load(Ordering::Relaxed) == 0
+    }
+}
+
+/// How much of the previous chunk `--stream-match` keeps in front of the next, so a match
+/// split across two reads is still found.
+const STREAM_OVERLAP: usize = 1024;
+
+/// `--stream-match`: the retry patterns checked against output as it arrives, shared by
+/// both tee readers and the wait loop, which kills the attempt once one has matched.
+pub struct StreamMatch {
+    set: RegexSet,
+    /// Index of the first pattern that matched, in `Patterns` order.
+    hit: OnceLock<usize>,
+}
+
+impl StreamMatch {
+    pub fn new(patterns: &Patterns) -> Self {
+        StreamMatch {
+            set: patterns.set.clone(),
+            hit: OnceLock::new(),
+        }
+    }
+
+    /// Check `chunk` behind the overlap kept in `tail`, recording and returning the first
+    /// matching pattern.
+    pub fn feed(&self, tail: &mut Vec<u8>, chunk: &[u8]) -> Option<usize> {
+        tail.extend_from_slice(chunk);
+        let found = self
+            .set
+            .matches(&String::from_utf8_lossy(tail))
+            .iter()
+            .next();
+        tail.drain(..tail.len().saturating_sub(STREAM_OVERLAP));
+        let idx = found?;
+        Some(*self.hit.get_or_init(|| idx))
+    }
+
+    pub fn hit(&self) -> Option<usize> {
+        self.hit.get().copied()
+    }
+}
+
+/// Copies of one attempt's stream for `--observe-socket` clients.
+pub struct Tap {
+    pub hub: Arc<observe::Hub>,
+    pub attempt: u32,
+    pub stream: &'static str,
+}
+
+/// What a tee reader looks for in its stream as it passes: the retry patterns
+/// (`--stream-match`) and the session id (`--auto-resume`).
+#[derive(Default)]
+pub struct Lookout {
+    pub stream_match: Option<Arc<StreamMatch>>,
+    pub session: Option<Arc<resume::Watch>>,
+}
+
+/// Copy `src` to `dst`, also streaming it to `tap`, the `artifact` file, and `lookout`,
+/// and return everything read, in `capture`, for pattern matching. Once the attempt has
+/// failed, `dst` gets nothing more (see `Activity::holding_back`); the rest still goes
+/// everywhere else. A held-back stream (`--buffer-output`) is not written to `dst` at all:
+/// the caller writes the capture out once it knows the attempt is final.
+pub fn tee_reader(
+    src: impl Read + Send + 'static,
+    dst: impl Write + Send + 'static,
+    capture: memory::Capture,
+    activity: Arc<Activity>,
+    tap: Option<Tap>,
+    artifact: Option<artifacts::Stream>,
+    lookout: Lookout,
+) -> thread::JoinHandle<io::Result<memory::Capture>> {
+    activity.open_streams.fetch_add(1, Ordering::Relaxed);
+    let passing = Passing {
+        capture,
+        activity,
+        tap,
+        artifact,
+        lookout,
+        tail: Vec::new(),
+        session_tail: Vec::new(),
+    };
+    thread::spawn(move || exec::tee(src, Forward(dst), passing).map(|p| p.capture))
+}
+
+/// One attempt's stream on its way through [`tee_reader`].
+struct Passing {
+    capture: memory::Capture,
+    activity: Arc<Activity>,
+    tap: Option<Tap>,
+    artifact: Option<artifacts::Stream>,
+    lookout: Lookout,
+    tail: Vec<u8>,
+    session_tail: Vec<u8>,
+}
+
+impl exec::Sink for Passing {
+    fn chunk(&mut self, chunk: &[u8]) -> io::Result<bool> {
+        let n = chunk.len();
+        let pushed = self.capture.push(chunk);
+        let forward = if self.capture.held_back() {
+            self.activity
+                .buffered
+                .fetch_add(n as u64, Ordering::Relaxed);
+            false
+        } else if self.activity.holding_back() {
+            self.activity
+                .late_bytes
+                .fetch_add(n as u64, Ordering::Relaxed);
+            false
+        } else {
+            true
+        };
+        self.activity.record(n);
+        let lookout = &self.lookout;
+        // After the first hit there is nothing more to look for
+        if let Some(m) = lookout.stream_match.as_ref().filter(|m| m.hit().is_none()) {
+            m.feed(&mut self.tail, chunk);
+        }
+        if let Some(watch) = &lookout.session {
+            watch.feed(&mut self.session_tail, chunk);
+        }
+        if let Some(tap) = &self.tap {
+            tap.hub.output(tap.attempt, tap.stream, chunk);
+        }
+        // A full disk must not break the tee; the artifact just stops growing
+        if self
+            .artifact
+            .as_mut()
+            .is_some_and(|f| f.write(chunk).is_err())
+        {
+            self.artifact = None;
+        }
+        pushed.map(|()| forward)
+    }
+
+    fn finish(&mut self) {
+        self.activity.open_streams.fetch_sub(1, Ordering::Relaxed);
+        if let Some(Err(e)) = self.artifact.take().map(artifacts::Stream::close) {
+            eprintln!("[rusty-claude] warning: --attempt-artifacts: {e}");
+        }
+    }
+}
+
+/// A tee destination that passes each forwarded chunk through the `--chaos` checkpoint.
+struct Forward<W>(W);
+
+impl<W: Write> Write for Forward<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        chaos::tee_write(buf.len())?;
+        self.0.write_all(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Write out what `--buffer-output` (and `--buffer-stderr`) held back for the final
+/// attempt, byte for byte.
+pub fn release_buffered(out: &memory::Capture, err: &memory::Capture) -> io::Result<()> {
+    if out.held_back() {
+        let mut stdout = io::stdout().lock();
+        out.write_to(&mut stdout)?;
+        stdout.flush()?;
+    }
+    if err.held_back() {
+        let mut stderr = io::stderr().lock();
+        err.write_to(&mut stderr)?;
+        stderr.flush()?;
+    }
+    Ok(())
+}
+
+/// How long attempt teardown waits for the stdin writer after the child has exited.
+const STDIN_JOIN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Replay captured stdin into the child on its own thread, so a child that writes a lot of
+/// output before consuming its input can't deadlock against us. Dropping `dst` sends EOF,
+/// after which `activity` learns the replay is over.
+pub fn stdin_writer(
+    mut dst: ChildStdin,
+    data: spool::Input,
+    activity: Arc<Activity>,
+) -> thread::JoinHandle<io::Result<()>> {
+    activity
+        .input_done_ms
+        .store(INPUT_PENDING, Ordering::Relaxed);
+    thread::spawn(move || {
+        let result = data.write_to(&mut dst).and_then(|()| dst.flush());
+        drop(dst);
+        activity.finish_input();
+        result
+    })
+}
+
+/// Finish the stdin replay of an exited child. Its errors are never the attempt's result:
+/// a child may legitimately exit without reading all of its input.
+pub fn settle_stdin(handle: Option<thread::JoinHandle<io::Result<()>>>) {
+    let Some(handle) = handle else {
+        return;
+    };
+    match join_with_timeout(handle, STDIN_JOIN_TIMEOUT) {
+        Some(Ok(Err(e))) if e.kind() != io::ErrorKind::BrokenPipe => {
+            eprintln!("[rusty-claude] warning: stdin replay failed: {e}");
+        }
+        Some(_) => {}
+        None => eprintln!("[rusty-claude] warning: stdin replay still blocked after child exit"),
+    }
+}
+
+/// Join a thread, giving up after `timeout`. A thread still running past the deadline is
+/// left detached; for the stdin writer it unblocks with EPIPE once the child is gone.
+pub fn join_with_timeout<T>(
+    handle: thread::JoinHandle<T>,
+    timeout: Duration,
+) -> Option<thread::Result<T>> {
+    let deadline = Instant::now() + timeout;
+    while !handle.is_finished() {
+        if Instant::now() >= deadline {
+            return None;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    Some(handle.join())
+}
+
+/// How often the supervising wait loop polls the child.
+const WAIT_POLL: Duration = Duration::from_millis(50);
+
+/// `--timeout-warning` for one attempt.
+pub struct TimeoutWarning {
+    /// Time after the attempt started at which the warning fires.
+    pub at: Duration,
+    pub timeout: Duration,
+    pub signal: signals::Signal,
+    pub attempt: u32,
+}
+
+/// Why the wait loop killed an attempt.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Killed {
+    /// It ran for the whole `--attempt-timeout-secs`.
+    Timeout,
+    /// Its output had been silent this long, past `--idle-timeout-secs`.
+    Idle(Duration),
+    /// It wrote nothing within `--first-output-timeout`.
+    NoOutput,
+    /// An `abort` control command arrived.
+    Aborted,
+    /// The retry pattern with this index matched mid-stream (`--stream-match`).
+    Matched(usize),
+    /// It wrote more than `--buffer-limit` under `--buffer-output`.
+    BufferFull,
+}
+
+impl Killed {
+    /// The name the decision trace and attempt metadata give the kill.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Killed::Timeout => "attempt-timeout",
+            Killed::Idle(_) => "idle-timeout",
+            Killed::NoOutput => "first-output-timeout",
+            Killed::Aborted => "abort",
+            Killed::Matched(_) => "stream-match",
+            Killed::BufferFull => "buffer-limit",
+        }
+    }
+}
+
+/// How a supervised attempt ended.
+pub struct Waited {
+    pub status: ExitStatus,
+    /// When `--timeout-warning` fired, if it did.
+    pub warned_at: Option<Duration>,
+    pub killed: Option<Killed>,
+}
+
+/// Limits under which the wait loop kills an attempt.
+#[derive(Clone, Copy, Default)]
+pub struct Deadlines {
+    pub timeout: Option<Duration>,
+    pub idle: Option<Duration>,
+    pub first_output: Option<Duration>,
+}
+
+/// Wait for a non-interactive child, printing a keepalive line whenever it has been silent
+/// for a full `heartbeat` interval, firing `warning` once when it comes due, and killing the
+/// child when it runs into one of its `deadlines`, `stream_match` finds a retry pattern, or
+/// it overflows `--buffer-limit`.
+pub fn wait_child(
+    child: &mut Child,
+    activity: &Activity,
+    heartbeat: Option<Duration>,
+    deadlines: Deadlines,
+    warning: Option<&TimeoutWarning>,
+    stream_match: Option<&StreamMatch>,
+    events: &events::Events,
+) -> io::Result<Waited> {
+    let mut last_beat: Option<Instant> = None;
+    let mut warned_at = None;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Waited {
+                status,
+                warned_at,
+                killed: None,
+            });
+        }
+        if let Some(interval) = heartbeat {
+            // Any output resets the idle clock, so this fires once per silent interval.
+            let due = last_beat.is_none_or(|t| t.elapsed() >= interval);
+            if activity.idle() >= interval && due {
+                eprintln!(
+                    "[rusty-claude] still running, {} elapsed, 0 bytes in last {}",
+                    format_duration(activity.started.elapsed()),
+                    format_duration(interval)
+                );
+                last_beat = Some(Instant::now());
+            }
+        }
+        let elapsed = activity.started.elapsed();
+        if let Some(w) = warning.filter(|w| warned_at.is_none() && elapsed >= w.at) {
+            warned_at = Some(elapsed);
+            let sent = match signals::send(child, w.signal) {
+                Ok(sent) => sent,
+                Err(e) => {
+                    eprintln!("[rusty-claude] warning: could not send {}: {e}", w.signal);
+                    false
+                }
+            };
+            let remaining = w.timeout.saturating_sub(elapsed);
+            eprintln!(
+                "[rusty-claude] attempt {} times out in {}{}",
+                w.attempt,
+                format_duration(remaining),
+                if sent {
+                    format!("; sent {} to the child", w.signal)
+                } else {
+                    String::new()
+                }
+            );
+            events.emit(
+                "timeout_warning",
+                serde_json::json!({
+                    "attempt": w.attempt,
+                    "elapsed_ms": elapsed.as_millis() as u64,
+                    "remaining_ms": remaining.as_millis() as u64,
+                    "signal": sent.then(|| w.signal.to_string()),
+                }),
+            );
+        }
+        let idle = activity.idle();
+        let silent = activity.silent_since_input();
+        // Silence before the first byte is the first-output deadline's alone, so the two
+        // never both fire
+        let idle_applies =
+            deadlines.first_output.is_none() || activity.bytes.load(Ordering::Relaxed) > 0;
+        let killed = if control::check(events) == Some(control::Command::Abort) {
+            Some(Killed::Aborted)
+        } else if let Some(idx) = stream_match.and_then(StreamMatch::hit) {
+            Some(Killed::Matched(idx))
+        } else if activity.over_buffer_limit() {
+            Some(Killed::BufferFull)
+        } else if deadlines.timeout.is_some_and(|t| elapsed >= t) {
+            Some(Killed::Timeout)
+        } else if deadlines
+            .first_output
+            .zip(silent)
+            .is_some_and(|(t, silent)| silent >= t)
+        {
+            Some(Killed::NoOutput)
+        } else if idle_applies && deadlines.idle.is_some_and(|t| idle >= t) && !activity.drained() {
+            // Silence after both pipes closed is just a slow exit, not a stall
+            Some(Killed::Idle(idle))
+        } else {
+            None
+        };
+        if killed.is_some() {
+            // Reaped here, so the tee readers reach EOF and keep the partial output
+            activity.fail();
+            tree::kill_tree(child)?;
+            return Ok(Waited {
+                status: child.wait()?,
+                warned_at,
+                killed,
+            });
+        }
+        thread::sleep(WAIT_POLL);
+    }
+}
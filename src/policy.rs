@@ -0,0 +1,181 @@
+//! How a run's settings become its retry policy: the backoff and its jitter, and the
+//! retry and fatal patterns with their sources and `--pattern-delay` minimums.
+
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rusty_claude::backoff;
+use rusty_claude::exec::RetryPolicy;
+use rusty_claude::patterns::{compile_patterns, Patterns};
+
+use crate::duration::format_duration;
+use crate::{envvars, pattern_file, trace, Cli};
+
+pub fn backoff(cli: &Cli) -> backoff::Backoff {
+    backoff::Backoff {
+        strategy: cli.backoff_strategy,
+        base_ms: cli.base_delay_ms,
+        cap_ms: cli.max_delay_ms,
+        multiplier: cli.backoff_multiplier,
+    }
+}
+
+/// The retry settings `Supervisor` takes, judging failures by `patterns`.
+pub fn retry_policy(cli: &Cli, patterns: Patterns) -> RetryPolicy {
+    RetryPolicy {
+        max_retries: cli.max_retries,
+        backoff: backoff(cli),
+        patterns,
+        retry_on_any: cli.retry_on_any_error,
+        max_retry_after: Duration::from_millis(cli.max_retry_after_ms),
+        jitter_seed: cli.jitter_seed,
+    }
+}
+
+/// The RNG every backoff draw of a run comes from: seeded by `--jitter-seed`, so the whole
+/// delay sequence repeats, or else from the thread RNG.
+pub fn jitter_rng(cli: &Cli) -> StdRng {
+    match cli.jitter_seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_rng(&mut rand::rng()),
+    }
+}
+
+/// The `--backoff-strategy` delay after `attempt` failed, following `previous` if any.
+pub fn backoff_ms(attempt: u32, previous: Option<u64>, cli: &Cli, rng: &mut impl Rng) -> u64 {
+    backoff(cli).delay_ms(attempt, previous, rng)
+}
+
+/// The `--decision-trace` record of a matched pattern.
+pub fn provenance(patterns: &Patterns, pattern: &str) -> trace::Match {
+    trace::Match {
+        pattern: pattern.to_string(),
+        source: patterns.source(pattern).to_string(),
+    }
+}
+
+/// The `-v` listing of every compiled pattern with its class and where it came from.
+pub fn log_patterns(patterns: &Patterns) {
+    for ((re, class), min_delay) in patterns
+        .regexes
+        .iter()
+        .zip(&patterns.classes)
+        .zip(&patterns.min_delays)
+    {
+        let mut about = patterns.source(re.as_str()).to_string();
+        if let Some(class) = class {
+            about += &format!(", {}", class.as_str());
+        }
+        if let Some(ms) = min_delay {
+            about += &format!(
+                ", waits at least {}",
+                format_duration(Duration::from_millis(*ms))
+            );
+        }
+        eprintln!("[rusty-claude] retry pattern `{re}` ({about})");
+    }
+    for re in &patterns.fatal {
+        let source = patterns.source(re.as_str());
+        eprintln!("[rusty-claude] fatal pattern `{re}` ({source})");
+    }
+}
+
+/// Pipe-separated patterns from the `env` knob, then from `flag`, then the config file's
+/// (only set when neither is), each with its source.
+fn split_patterns(
+    env: &str,
+    flag: &str,
+    value: Option<&str>,
+    file: &[(String, String)],
+) -> Vec<(String, String)> {
+    let split = |s: &str| -> Vec<String> {
+        s.split('|')
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .map(str::to_owned)
+            .collect()
+    };
+    let mut user = Vec::new();
+    if let Some(v) = envvars::var(env) {
+        user.extend(split(&v.value).into_iter().map(|p| (p, v.name.clone())));
+    }
+    if let Some(value) = value {
+        user.extend(split(value).into_iter().map(|p| (p, flag.to_string())));
+    }
+    user.extend(file.iter().cloned());
+    user
+}
+
+/// Parse a `--pattern-delay REGEX=MS`; the last `=` splits, as a regex may contain one.
+pub fn parse_pattern_delay(s: &str) -> Result<(String, u64), String> {
+    let (pattern, ms) = s
+        .rsplit_once('=')
+        .ok_or("expected REGEX=MS, e.g. '(?i)\\b429\\b=30000'")?;
+    if pattern.is_empty() {
+        return Err("the pattern is empty".to_string());
+    }
+    let ms = ms
+        .trim()
+        .parse::<u64>()
+        .map_err(|_| format!("`{ms}` is not a whole number of milliseconds"))?;
+    Ok((pattern.to_string(), ms))
+}
+
+/// Set each `--pattern-delay` minimum on its pattern, over any built-in one.
+fn set_pattern_delays(patterns: &mut Patterns, cli: &Cli) {
+    for (pattern, ms) in &cli.pattern_delay {
+        patterns.set_min_delay(pattern, *ms);
+    }
+}
+
+/// Patterns, each with where it came from.
+type Listed = Vec<(String, String)>;
+
+/// The user's retry patterns in the order they are listed (`--patterns-file` entries, the
+/// `--pattern-delay` patterns, then those of the environment, `--patterns`, or the config
+/// file) and their fatal ones, each with its source.
+pub fn user_patterns(cli: &Cli, from_file: Vec<(String, String)>) -> (Listed, Listed) {
+    let delayed = cli
+        .pattern_delay
+        .iter()
+        .map(|(p, _)| (p.clone(), "--pattern-delay".to_string()));
+    let retry = from_file
+        .into_iter()
+        .chain(delayed)
+        .chain(split_patterns(
+            "PATTERNS",
+            "--patterns",
+            cli.patterns.as_deref(),
+            &cli.file_patterns,
+        ))
+        .collect();
+    let fatal = split_patterns(
+        "FATAL_PATTERNS",
+        "--fatal-patterns",
+        cli.fatal_patterns.as_deref(),
+        &cli.file_fatal_patterns,
+    );
+    (retry, fatal)
+}
+
+/// The retry and fatal patterns the settings ask for, with their `--pattern-delay`
+/// minimums. A user pattern that doesn't compile is left out with a warning.
+pub fn build_patterns(cli: &Cli) -> Result<Patterns, String> {
+    let from_file = match &cli.patterns_file {
+        Some(path) => pattern_file::read(path)?,
+        None => Vec::new(),
+    };
+    let (user, user_fatal) = user_patterns(cli, from_file);
+    let (mut patterns, warnings) = compile_patterns(
+        user,
+        user_fatal,
+        !cli.no_default_patterns,
+        !cli.no_default_fatal_patterns,
+    )?;
+    for warning in warnings {
+        eprintln!("[rusty-claude] warning: {warning}");
+    }
+    set_pattern_delays(&mut patterns, cli);
+    Ok(patterns)
+}
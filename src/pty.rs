@@ -0,0 +1,312 @@
+//! Running the interactive child on a pseudo-terminal (`--pty`, Unix only).
+//!
+//! The child gets the PTY slave as its controlling terminal, so it keeps its interactive
+//! UI, while rusty-claude sits on the master side copying bytes to and from the real
+//! terminal. Being in the middle is what makes `--initial-input` possible, and lets
+//! `--force-tee` keep a copy of what the session printed without taking the terminal away.
+//! A resized terminal window (SIGWINCH) resizes the PTY, so the child redraws to fit.
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Once};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use regex::Regex;
+
+use crate::shutdown::{self, Priority, Registration};
+
+/// Output of the child kept for ready-pattern matching before the initial input is sent.
+const READY_SCAN_LIMIT: usize = 64 * 1024;
+/// How long the child must be quiet after looking ready before the initial input is typed,
+/// so it lands in the drawn prompt rather than in the middle of a redraw.
+const SETTLE: Duration = Duration::from_millis(300);
+/// Session output kept for retry patterns under `--force-tee`; the end is where the error is.
+const CAPTURE_LIMIT: usize = 1024 * 1024;
+/// How often the stdin forwarder checks whether the session is over.
+const STDIN_POLL_MS: libc::c_int = 100;
+
+/// Text to type into the child once its UI looks ready.
+pub struct Injection {
+    pub text: String,
+    /// Output that signals readiness; without one, the first output counts.
+    pub ready: Option<Regex>,
+    /// Give up (without sending anything) if the child doesn't look ready in time.
+    pub timeout: Duration,
+}
+
+/// Set by the SIGWINCH handler, cleared by whoever copies the new size to the PTY.
+static RESIZED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_winch(_: libc::c_int) {
+    RESIZED.store(true, Ordering::Relaxed);
+}
+
+/// Catch SIGWINCH for the rest of the process; the stdin forwarder acts on it, since its
+/// `poll` is interrupted by the signal.
+fn watch_resize() {
+    static INSTALLED: Once = Once::new();
+    INSTALLED.call_once(|| {
+        // SAFETY: the handler only stores to an atomic, which is async-signal-safe.
+        // SA_RESTART keeps blocking reads elsewhere (the master relay) from failing with EINTR.
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = on_winch as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(libc::SIGWINCH, &action, std::ptr::null_mut());
+        }
+    });
+}
+
+/// Give the PTY behind `master` the real terminal's current size; the kernel then sends
+/// SIGWINCH to the child.
+fn copy_size(master: &File) {
+    // SAFETY: `winsize` is plain data; it is only passed on if TIOCGWINSZ filled it in.
+    unsafe {
+        let mut size: libc::winsize = std::mem::zeroed();
+        if libc::ioctl(libc::STDIN_FILENO, libc::TIOCGWINSZ, &mut size) == 0 {
+            libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, &size);
+        }
+    }
+}
+
+/// A PTY pair whose slave side is handed to the child by `attach`.
+pub struct Pty {
+    master: File,
+    slave: OwnedFd,
+}
+
+/// Open a PTY sized like the current terminal.
+pub fn open() -> io::Result<Pty> {
+    let mut master: libc::c_int = -1;
+    let mut slave: libc::c_int = -1;
+    // SAFETY: `winsize` is plain data and `ioctl` only writes into it; on failure it stays zeroed.
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let size_ptr = unsafe {
+        if libc::ioctl(libc::STDIN_FILENO, libc::TIOCGWINSZ, &mut size) == 0 {
+            &mut size as *mut libc::winsize
+        } else {
+            std::ptr::null_mut()
+        }
+    };
+    // SAFETY: the out-pointers are valid, and null name/termios are allowed.
+    let rc = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            size_ptr,
+        )
+    };
+    if rc != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: openpty succeeded, so both descriptors are open and owned by us.
+    unsafe {
+        Ok(Pty {
+            master: File::from_raw_fd(master),
+            slave: OwnedFd::from_raw_fd(slave),
+        })
+    }
+}
+
+impl Pty {
+    /// Connect `cmd`'s stdio to the slave and make it the child's controlling terminal.
+    pub fn attach(&self, cmd: &mut Command) -> io::Result<()> {
+        cmd.stdin(Stdio::from(self.slave.try_clone()?))
+            .stdout(Stdio::from(self.slave.try_clone()?))
+            .stderr(Stdio::from(self.slave.try_clone()?));
+        // SAFETY: only async-signal-safe calls between fork and exec.
+        unsafe {
+            cmd.pre_exec(|| {
+                if libc::setsid() == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                if libc::ioctl(libc::STDIN_FILENO, libc::TIOCSCTTY as _, 0) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+
+    /// Relay the terminal to the spawned child until it exits, typing `inject` once it is
+    /// ready, and return the last `CAPTURE_LIMIT` bytes it printed if `capture` is set. The
+    /// caller must have dropped the `Command` so only the child holds the slave.
+    pub fn run(
+        self,
+        child: &mut Child,
+        inject: Option<&Injection>,
+        capture: bool,
+    ) -> io::Result<(ExitStatus, Vec<u8>)> {
+        let Pty { master, slave } = self;
+        drop(slave);
+        let _raw = RawMode::enable();
+        watch_resize();
+        // A resize between `open` and now, or during an earlier session, is caught up on here
+        RESIZED.store(false, Ordering::Relaxed);
+        copy_size(&master);
+        let done = Arc::new(AtomicBool::new(false));
+        let started = Instant::now();
+        let last_output_ms = Arc::new(AtomicU64::new(0));
+        let (ready_tx, ready_rx) = mpsc::channel::<()>();
+
+        let output = {
+            let mut master = master.try_clone()?;
+            let ready = inject.map(|i| i.ready.clone());
+            let last_output_ms = Arc::clone(&last_output_ms);
+            thread::spawn(move || {
+                let mut out = io::stdout();
+                let mut buf = [0u8; 8192];
+                let mut seen = Vec::new();
+                let mut ready_tx = ready.as_ref().map(|_| ready_tx);
+                let mut captured = Vec::new();
+                loop {
+                    // EIO from the master means every slave descriptor is closed.
+                    let n = match master.read(&mut buf) {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => n,
+                    };
+                    let ms = started.elapsed().as_millis() as u64;
+                    last_output_ms.store(ms, Ordering::Relaxed);
+                    if out.write_all(&buf[..n]).and_then(|_| out.flush()).is_err() {
+                        break;
+                    }
+                    if capture {
+                        captured.extend_from_slice(&buf[..n]);
+                        if captured.len() > 2 * CAPTURE_LIMIT {
+                            captured.drain(..captured.len() - CAPTURE_LIMIT);
+                        }
+                    }
+                    if let (Some(tx), Some(pattern)) = (&ready_tx, &ready) {
+                        let hit = match pattern {
+                            None => true,
+                            Some(re) => {
+                                if seen.len() < READY_SCAN_LIMIT {
+                                    seen.extend_from_slice(&buf[..n]);
+                                }
+                                re.is_match(&String::from_utf8_lossy(&seen))
+                            }
+                        };
+                        if hit {
+                            let _ = tx.send(());
+                            ready_tx = None;
+                        }
+                    }
+                }
+                let start = captured.len().saturating_sub(CAPTURE_LIMIT);
+                captured.split_off(start)
+            })
+        };
+
+        {
+            let mut master = master.try_clone()?;
+            let done = Arc::clone(&done);
+            thread::spawn(move || forward_stdin(&mut master, &done));
+        }
+
+        if let Some(inject) = inject {
+            let mut master = master.try_clone()?;
+            let text = inject.text.clone();
+            let timeout = inject.timeout;
+            let last_output_ms = Arc::clone(&last_output_ms);
+            thread::spawn(move || {
+                if ready_rx.recv_timeout(timeout).is_err() {
+                    eprint!(
+                        "\r\n[rusty-claude] warning: child did not look ready within {}; \
+                        --initial-input not sent\r\n",
+                        crate::duration::format_duration(timeout)
+                    );
+                    return;
+                }
+                let deadline = Instant::now() + timeout;
+                loop {
+                    let last = Duration::from_millis(last_output_ms.load(Ordering::Relaxed));
+                    if started.elapsed().saturating_sub(last) >= SETTLE
+                        || Instant::now() >= deadline
+                    {
+                        break;
+                    }
+                    thread::sleep(Duration::from_millis(50));
+                }
+                let _ = master
+                    .write_all(text.as_bytes())
+                    .and_then(|_| master.write_all(b"\r"));
+            });
+        }
+
+        let status = child.wait();
+        done.store(true, Ordering::Relaxed);
+        // Background processes the child left behind may still hold the slave open.
+        let captured = crate::piped::join_with_timeout(output, Duration::from_millis(500))
+            .and_then(Result::ok)
+            .unwrap_or_default();
+        Ok((status?, captured))
+    }
+}
+
+/// Copy the real terminal's input to the PTY until `done` is set, polling so the thread
+/// stops between sessions instead of swallowing the next keystroke, and pass on resizes.
+fn forward_stdin(master: &mut File, done: &AtomicBool) {
+    let mut buf = [0u8; 1024];
+    let stdin = io::stdin();
+    let fd = stdin.as_raw_fd();
+    while !done.load(Ordering::Relaxed) {
+        let mut pfd = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: one valid pollfd.
+        let ready = unsafe { libc::poll(&mut pfd, 1, STDIN_POLL_MS) };
+        if RESIZED.swap(false, Ordering::Relaxed) {
+            copy_size(master);
+        }
+        if ready <= 0 {
+            continue;
+        }
+        // SAFETY: reading into a stack buffer of the stated length.
+        let n = unsafe { libc::read(fd, buf.as_mut_ptr().cast(), buf.len()) };
+        if n <= 0 || master.write_all(&buf[..n as usize]).is_err() {
+            break;
+        }
+    }
+}
+
+/// Puts the user's terminal in raw mode so keystrokes reach the child unprocessed;
+/// restores the saved settings on drop or at shutdown (see `crate::shutdown`).
+struct RawMode {
+    _restore: Option<Registration>,
+}
+
+impl RawMode {
+    fn enable() -> Self {
+        // SAFETY: termios is plain data filled by tcgetattr.
+        let saved = unsafe {
+            let mut saved: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut saved) != 0 {
+                return RawMode { _restore: None };
+            }
+            let mut raw = saved;
+            libc::cfmakeraw(&mut raw);
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw);
+            saved
+        };
+        let restore = shutdown::register("terminal mode", Priority::First, move || {
+            // SAFETY: restoring settings previously read from the same descriptor.
+            unsafe {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &saved);
+            }
+        });
+        RawMode {
+            _restore: Some(restore),
+        }
+    }
+}
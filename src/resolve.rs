@@ -0,0 +1,363 @@
+//! Locating the child command when it is not where `PATH` says it should be.
+//!
+//! Non-interactive environments (services, cron, systemd units, CI shells) often miss the
+//! directories an installer or node version manager added to `PATH` in shell init files.
+//! The probes here look in well-known install locations so the not-found diagnostic can
+//! name the concrete `--cmd` value to use, and `--auto-discover-cmd` can pick the first hit.
+
+use std::env;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Filesystem access used by the probes, injectable so layouts can be faked.
+pub trait FsView {
+    fn is_file(&self, path: &Path) -> bool;
+    /// Entries of a directory, empty if it doesn't exist or can't be read.
+    fn read_dir(&self, path: &Path) -> Vec<PathBuf>;
+}
+
+/// The real filesystem.
+pub struct RealFs;
+
+impl FsView for RealFs {
+    fn is_file(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+
+    fn read_dir(&self, path: &Path) -> Vec<PathBuf> {
+        std::fs::read_dir(path)
+            .map(|entries| entries.filter_map(|e| e.ok().map(|e| e.path())).collect())
+            .unwrap_or_default()
+    }
+}
+
+/// An installation found outside `PATH`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Candidate {
+    pub path: PathBuf,
+    /// Which installer layout produced the hit, e.g. `npm` or `scoop`.
+    pub source: &'static str,
+}
+
+/// Windows' default `PATHEXT`: the extensions a bare command name is tried with.
+pub const DEFAULT_PATHEXT: &str = ".COM;.EXE;.BAT;.CMD";
+
+/// The extensions in a `PATHEXT` value, in order and upper-cased; the default list when it
+/// is unset or empty.
+pub fn path_exts(pathext: Option<&str>) -> Vec<String> {
+    let exts: Vec<String> = pathext
+        .unwrap_or_default()
+        .split(';')
+        .map(str::trim)
+        .filter(|ext| ext.len() > 1 && ext.starts_with('.'))
+        .map(str::to_ascii_uppercase)
+        .collect();
+    if exts.is_empty() && pathext != Some(DEFAULT_PATHEXT) {
+        return path_exts(Some(DEFAULT_PATHEXT));
+    }
+    exts
+}
+
+/// The extensions a bare name is tried with here: `PATHEXT`'s on Windows, none elsewhere.
+pub fn exts() -> Vec<String> {
+    if cfg!(windows) {
+        path_exts(env::var("PATHEXT").ok().as_deref())
+    } else {
+        Vec::new()
+    }
+}
+
+/// Find `name` in the directories of a `PATH`-style list, as a shell would: in each
+/// directory in turn, `name` itself if it needs no extension (`exts` is empty, or it already
+/// ends in one of them), then `name` with each of `exts` appended. On a miss, the
+/// directories that were searched.
+pub fn search(
+    name: &str,
+    path_var: Option<&OsStr>,
+    exts: &[String],
+    fs: &dyn FsView,
+) -> Result<PathBuf, Vec<PathBuf>> {
+    let upper = name.to_ascii_uppercase();
+    let as_is = exts.is_empty() || exts.iter().any(|ext| upper.ends_with(ext.as_str()));
+    let dirs: Vec<PathBuf> = path_var
+        .map(env::split_paths)
+        .into_iter()
+        .flatten()
+        .collect();
+    for dir in &dirs {
+        let names = as_is.then(|| name.to_string()).into_iter().chain(
+            exts.iter()
+                .map(|ext| format!("{name}{}", ext.to_ascii_lowercase())),
+        );
+        if let Some(found) = names.map(|n| dir.join(n)).find(|p| fs.is_file(p)) {
+            return Ok(found);
+        }
+    }
+    Err(dirs)
+}
+
+/// Find `name` in the directories of a `PATH`-style list, trying `PATHEXT` on Windows.
+pub fn find_in_path(name: &str, path_var: Option<&OsStr>, fs: &dyn FsView) -> Option<PathBuf> {
+    search(name, path_var, &exts(), fs).ok()
+}
+
+/// Where spawning `cmd` would look: the path itself if it has a directory part, else its
+/// first hit on `PATH`.
+pub fn locate(cmd: &str) -> Option<PathBuf> {
+    if cmd.contains(['/', '\\']) {
+        Some(PathBuf::from(cmd))
+    } else {
+        find_in_path(cmd, env::var_os("PATH").as_deref(), &RealFs)
+    }
+}
+
+/// The `not found in PATH` line for a bare `cmd` that failed to spawn, naming the
+/// directories searched.
+pub fn searched(cmd: &str) -> String {
+    let path = env::var_os("PATH");
+    match search(cmd, path.as_deref(), &exts(), &RealFs) {
+        Err(dirs) if dirs.is_empty() => format!("`{cmd}` not found: PATH is empty"),
+        Err(dirs) => {
+            let dirs: Vec<String> = dirs.iter().map(|d| d.display().to_string()).collect();
+            format!("`{cmd}` not found in PATH: tried {}", dirs.join(", "))
+        }
+        Ok(found) => format!("`{cmd}` is on PATH at {}", found.display()),
+    }
+}
+
+/// Whether `path` is a batch script (`.cmd` or `.bat`), which Windows runs through `cmd`.
+pub fn is_script(path: &str) -> bool {
+    let lower = path.to_ascii_lowercase();
+    lower.ends_with(".cmd") || lower.ends_with(".bat")
+}
+
+/// Characters `cmd` treats specially outside quotes, each escaped with `^`.
+const CMD_META: &[char] = &[
+    '(', ')', '[', ']', '%', '!', '^', '"', '`', '<', '>', '&', '|', ';', ',', ' ', '*', '?',
+];
+
+fn caret_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() * 2);
+    for c in s.chars() {
+        if CMD_META.contains(&c) {
+            out.push('^');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// One argument for a batch script run through `cmd /d /s /c`: quoted the way the program
+/// the script hands it to parses its command line (backslashes before a quote doubled, the
+/// quote escaped), then caret-escaped twice, once for the `cmd` line and once for the
+/// script's own `%*`, so spaces, quotes, and `&` or `|` reach that program unchanged.
+pub fn cmd_arg(arg: &str) -> String {
+    let mut quoted = String::from("\"");
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                quoted.extend(std::iter::repeat_n('\\', backslashes * 2 + 1));
+                backslashes = 0;
+            }
+            _ => {
+                quoted.extend(std::iter::repeat_n('\\', backslashes));
+                backslashes = 0;
+            }
+        }
+        if c != '\\' {
+            quoted.push(c);
+        }
+    }
+    quoted.extend(std::iter::repeat_n('\\', backslashes * 2));
+    quoted.push('"');
+    caret_escape(&caret_escape(&quoted))
+}
+
+/// The command line after `cmd` that runs `script` with `args`: `/d /s /c "SCRIPT ARGS"`.
+pub fn cmd_line(script: &str, args: &[String]) -> String {
+    let mut line = format!("/d /s /c \"{}", caret_escape(script));
+    for arg in args {
+        line.push(' ');
+        line.push_str(&cmd_arg(arg));
+    }
+    line.push('"');
+    line
+}
+
+/// The command that runs `program` with `args`: directly, or for a batch script on
+/// Windows, through `cmd` with the arguments quoted by [`cmd_line`].
+pub fn command(program: &str, args: &[String]) -> Command {
+    #[cfg(windows)]
+    if is_script(program) {
+        use std::os::windows::process::CommandExt;
+        let mut cmd = Command::new(env::var_os("ComSpec").unwrap_or_else(|| "cmd.exe".into()));
+        cmd.raw_arg(cmd_line(program, args));
+        return cmd;
+    }
+    let mut cmd = Command::new(program);
+    cmd.args(args);
+    cmd
+}
+
+/// Probe well-known install locations for the Claude CLI, in preference order.
+pub fn discover(fs: &dyn FsView, var: &dyn Fn(&str) -> Option<String>) -> Vec<Candidate> {
+    if cfg!(windows) {
+        discover_windows(fs, var)
+    } else {
+        discover_version_managers(fs, var)
+    }
+}
+
+/// Shims and bin directories of node version managers (nvm, asdf, mise, volta), which are
+/// only on `PATH` after shell init runs. Under nvm the highest node version wins.
+///
+/// Both layouts build on any host, so the self-test can walk them over a faked filesystem.
+pub fn discover_version_managers(
+    fs: &dyn FsView,
+    var: &dyn Fn(&str) -> Option<String>,
+) -> Vec<Candidate> {
+    let home = var("HOME").map(PathBuf::from);
+    let under_home = |rel: &str| home.as_ref().map(|h| h.join(rel));
+    let mut probes: Vec<(PathBuf, &'static str)> = Vec::new();
+
+    if let Some(nvm) = var("NVM_DIR")
+        .map(PathBuf::from)
+        .or_else(|| under_home(".nvm"))
+    {
+        let mut versions: Vec<(Vec<u64>, PathBuf)> = fs
+            .read_dir(&nvm.join("versions").join("node"))
+            .into_iter()
+            .filter_map(|dir| {
+                let name = dir.file_name()?.to_str()?.to_string();
+                Some((node_version_key(&name)?, dir))
+            })
+            .collect();
+        versions.sort_by(|a, b| b.0.cmp(&a.0));
+        for (_, dir) in versions {
+            probes.push((dir.join("bin").join("claude"), "nvm"));
+        }
+    }
+    if let Some(asdf) = var("ASDF_DATA_DIR")
+        .map(PathBuf::from)
+        .or_else(|| under_home(".asdf"))
+    {
+        probes.push((asdf.join("shims").join("claude"), "asdf"));
+    }
+    let mise = var("MISE_DATA_DIR")
+        .map(PathBuf::from)
+        .or_else(|| var("XDG_DATA_HOME").map(|d| Path::new(&d).join("mise")))
+        .or_else(|| under_home(".local/share/mise"));
+    if let Some(mise) = mise {
+        probes.push((mise.join("shims").join("claude"), "mise"));
+    }
+    if let Some(volta) = var("VOLTA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| under_home(".volta"))
+    {
+        probes.push((volta.join("bin").join("claude"), "volta"));
+    }
+
+    probes
+        .into_iter()
+        .filter(|(path, _)| fs.is_file(path))
+        .map(|(path, source)| Candidate { path, source })
+        .collect()
+}
+
+/// Sort key for nvm version directory names like `v20.11.1`.
+fn node_version_key(name: &str) -> Option<Vec<u64>> {
+    name.strip_prefix('v')?
+        .split('.')
+        .map(|part| part.parse().ok())
+        .collect()
+}
+
+/// npm global prefix, scoop shims, winget links, per-user program installs, and the native
+/// installer's `~/.local/bin`.
+pub fn discover_windows(fs: &dyn FsView, var: &dyn Fn(&str) -> Option<String>) -> Vec<Candidate> {
+    let mut probes: Vec<(PathBuf, &'static str)> = Vec::new();
+    if let Some(appdata) = var("APPDATA") {
+        let npm = Path::new(&appdata).join("npm");
+        probes.push((npm.join("claude.cmd"), "npm"));
+        probes.push((npm.join("claude.exe"), "npm"));
+    }
+    let scoop_root = var("SCOOP")
+        .map(PathBuf::from)
+        .or_else(|| var("USERPROFILE").map(|home| Path::new(&home).join("scoop")));
+    if let Some(root) = scoop_root {
+        let shims = root.join("shims");
+        probes.push((shims.join("claude.exe"), "scoop"));
+        probes.push((shims.join("claude.cmd"), "scoop"));
+    }
+    if let Some(local) = var("LOCALAPPDATA") {
+        let local = Path::new(&local);
+        let winget = local.join("Microsoft").join("WinGet");
+        probes.push((winget.join("Links").join("claude.exe"), "winget"));
+        // Portable packages live in `Packages\<id>_<source>` when the links folder is missing
+        for dir in fs.read_dir(&winget.join("Packages")) {
+            let is_claude = dir
+                .file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with("Anthropic.Claude"));
+            if is_claude {
+                probes.push((dir.join("claude.exe"), "winget"));
+            }
+        }
+        probes.push((
+            local.join("Programs").join("claude").join("claude.exe"),
+            "programs",
+        ));
+        probes.push((
+            local.join("Programs").join("Claude").join("claude.exe"),
+            "programs",
+        ));
+    }
+    if let Some(home) = var("USERPROFILE") {
+        probes.push((
+            Path::new(&home)
+                .join(".local")
+                .join("bin")
+                .join("claude.exe"),
+            "native installer",
+        ));
+    }
+    probes
+        .into_iter()
+        .filter(|(path, _)| fs.is_file(path))
+        .map(|(path, source)| Candidate { path, source })
+        .collect()
+}
+
+/// Lines explaining where the command was (or wasn't) found, suggesting a `--cmd` value.
+pub fn not_found_diagnostic(cmd: &str, candidates: &[Candidate]) -> Vec<String> {
+    if candidates.is_empty() {
+        return vec![format!(
+            "`{cmd}` is not on PATH and no known installation location contains it"
+        )];
+    }
+    let mut lines = vec![format!(
+        "`{cmd}` is not on PATH, but installations were found:"
+    )];
+    for c in candidates {
+        lines.push(format!("  {} ({})", c.path.display(), c.source));
+    }
+    lines.push(format!(
+        "rerun with --cmd \"{}\" or pass --auto-discover-cmd",
+        candidates[0].path.display()
+    ));
+    lines
+}
+
+/// The path `cmd` would run if it is rusty-claude itself: named `rusty-claude`, or any name
+/// (usually a `claude` symlink) that resolves to the running executable.
+pub fn self_wrap(cmd: &str) -> Option<PathBuf> {
+    let found = locate(cmd).unwrap_or_else(|| PathBuf::from(cmd));
+    if found.file_stem() == Some(OsStr::new("rusty-claude")) {
+        return Some(found);
+    }
+    let ours = env::current_exe().ok()?.canonicalize().ok()?;
+    (found.canonicalize().ok()? == ours).then_some(found)
+}
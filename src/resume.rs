@@ -0,0 +1,231 @@
+//! `--auto-resume`: retry a failed attempt by resuming its session (`--resume <id>`) instead
+//! of starting the prompt over, keeping what the model already did.
+//!
+//! The id is the `session_id` the CLI prints in its JSON output: read from the JSON under
+//! `--json-errors`, else (or when that finds none) matched as text. Each retry resumes the
+//! session of the attempt before it; an attempt whose output names none is retried plainly.
+//! The id is also picked out of stdout as it streams past ([`Watch`]), in case the capture
+//! cut the init line that carries it.
+
+use std::sync::{LazyLock, Mutex, PoisonError};
+
+use regex::Regex;
+use serde_json::Value;
+
+/// The child flag a session is resumed with.
+pub const FLAG: &str = "--resume";
+
+/// Child flags that already pick a session, so nothing is injected next to them.
+const SESSION_FLAGS: &[&str] = &["--resume", "-r", "--continue", "-c"];
+
+/// Child flags whose value is the next arg, which is then no flag even if it reads like one.
+const VALUE_FLAGS: &[&str] = &[
+    "--model",
+    "--fallback-model",
+    "--output-format",
+    "--input-format",
+    "--system-prompt",
+    "--system-prompt-file",
+    "--append-system-prompt",
+    "--append-system-prompt-file",
+    "--permission-mode",
+    "--permission-prompt-tool",
+    "--allowedTools",
+    "--allowed-tools",
+    "--disallowedTools",
+    "--disallowed-tools",
+    "--mcp-config",
+    "--add-dir",
+    "--settings",
+    "--setting-sources",
+    "--session-id",
+    "--max-turns",
+    "--agents",
+    "--betas",
+];
+
+/// How much of the previous chunk [`Watch`] keeps in front of the next, so an id split
+/// across two reads is still found.
+const WATCH_OVERLAP: usize = 256;
+
+static SESSION_ID: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#""session_id"\s*:\s*"([a-f0-9-]+)""#).expect("session id pattern")
+});
+
+/// The args at flag positions, with their indexes: not the value of a [`VALUE_FLAGS`] flag,
+/// and none after a `--`, which ends the list.
+fn flag_positions<'a>(args: impl IntoIterator<Item = &'a str>) -> Vec<(usize, &'a str)> {
+    let mut flags = Vec::new();
+    let mut value_next = false;
+    for (i, a) in args.into_iter().enumerate() {
+        if std::mem::take(&mut value_next) {
+            continue;
+        }
+        flags.push((i, a));
+        if a == "--" {
+            break;
+        }
+        value_next = VALUE_FLAGS.contains(&a);
+    }
+    flags
+}
+
+/// The session flag the user passes themselves, in the child args or `--retry-extra-args`;
+/// a prompt or flag value that reads like one doesn't count.
+pub fn own_session_flag<'a>(
+    args: &'a [String],
+    retry_extra_args: Option<&'a str>,
+) -> Option<&'a str> {
+    let picks = |a: &str| {
+        SESSION_FLAGS
+            .iter()
+            .any(|f| a == *f || a.strip_prefix(f).is_some_and(|r| r.starts_with('=')))
+    };
+    let extra = retry_extra_args.into_iter().flat_map(str::split_whitespace);
+    flag_positions(args.iter().map(String::as_str))
+        .into_iter()
+        .chain(flag_positions(extra))
+        .map(|(_, a)| a)
+        .find(|a| picks(a))
+}
+
+/// The session id in an attempt's stdout, the last one if there are several.
+pub fn session_id(stdout: &str, json: bool) -> Option<String> {
+    if json {
+        let from_json = serde_json::from_str::<Value>(stdout.trim())
+            .ok()
+            .and_then(|doc| field(&doc))
+            .or_else(|| {
+                stdout
+                    .lines()
+                    .rev()
+                    .filter_map(|l| serde_json::from_str::<Value>(l.trim()).ok())
+                    .find_map(|doc| field(&doc))
+            });
+        if from_json.is_some() {
+            return from_json;
+        }
+    }
+    SESSION_ID
+        .captures_iter(stdout)
+        .last()
+        .map(|c| c[1].to_string())
+}
+
+/// `session_id` of a document, or of an array's last element that has one.
+fn field(doc: &Value) -> Option<String> {
+    match doc {
+        Value::Array(items) => items.iter().rev().find_map(field),
+        doc => doc["session_id"]
+            .as_str()
+            .filter(|id| !id.is_empty())
+            .map(str::to_string),
+    }
+}
+
+/// `args` resuming `session`: `--resume <id>` goes after the flags, before any `--` (past
+/// which the CLI would read it as prompt text), so the prompt args are passed as given. The
+/// args are rebuilt for every attempt, so only the latest session is ever in.
+pub fn inject(args: &mut Vec<String>, session: &str) {
+    let at = flag_positions(args.iter().map(String::as_str))
+        .into_iter()
+        .find(|&(_, a)| a == "--")
+        .map_or(args.len(), |(i, _)| i);
+    args.splice(at..at, [FLAG.to_string(), session.to_string()]);
+}
+
+/// The last session id in a stream as it passes, kept apart from the capture, which
+/// `--max-capture-bytes` can cut the init line with the id from. Shared with the tee
+/// reader, which keeps the overlap between reads.
+#[derive(Default)]
+pub struct Watch {
+    last: Mutex<Option<String>>,
+}
+
+impl Watch {
+    /// Check `chunk` behind the overlap kept in `tail`, recording the last id in it.
+    pub fn feed(&self, tail: &mut Vec<u8>, chunk: &[u8]) {
+        tail.extend_from_slice(chunk);
+        // The closing quote is part of the match, so a cut-off id is never taken
+        if let Some(c) = SESSION_ID
+            .captures_iter(&String::from_utf8_lossy(tail))
+            .last()
+        {
+            *self.last.lock().unwrap_or_else(PoisonError::into_inner) = Some(c[1].to_string());
+        }
+        tail.drain(..tail.len().saturating_sub(WATCH_OVERLAP));
+    }
+
+    /// The last id seen so far.
+    pub fn last(&self) -> Option<String> {
+        self.last
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn only_flag_positions_pick_a_session() {
+        let own =
+            |args: &[&str], extra| own_session_flag(&strings(args), extra).map(str::to_string);
+        assert_eq!(
+            own(&["-p", "--resume=abc"], None).as_deref(),
+            Some("--resume=abc")
+        );
+        assert_eq!(own(&["-p"], Some("--model x -c")).as_deref(), Some("-c"));
+        // A flag's value and the prompt after `--` read like flags but are not
+        assert_eq!(own(&["--append-system-prompt", "-c", "-p"], None), None);
+        assert_eq!(own(&["-p", "--", "-r"], None), None);
+        assert_eq!(own(&["-p", "--continuation"], None), None);
+    }
+
+    #[test]
+    fn the_resume_flag_goes_before_a_double_dash() {
+        let mut args = strings(&["-p", "--output-format", "json"]);
+        inject(&mut args, "5e55");
+        assert_eq!(
+            args,
+            strings(&["-p", "--output-format", "json", "--resume", "5e55"])
+        );
+        let mut args = strings(&["-p", "--", "fix -- the tests"]);
+        inject(&mut args, "5e55");
+        assert_eq!(
+            args,
+            strings(&["-p", "--resume", "5e55", "--", "fix -- the tests"])
+        );
+        // A `--` that is a flag's value is no separator
+        let mut args = strings(&["--append-system-prompt", "--", "-p"]);
+        inject(&mut args, "5e55");
+        assert_eq!(&args[3..], ["--resume", "5e55"]);
+    }
+
+    #[test]
+    fn the_watch_sees_ids_split_across_reads() {
+        let watch = Watch::default();
+        let mut tail = Vec::new();
+        let line = br#"{"type":"system","subtype":"init","session_id":"5e55-0001"}"#;
+        let (head, rest) = line.split_at(50);
+        watch.feed(&mut tail, head);
+        assert_eq!(watch.last(), None);
+        watch.feed(&mut tail, rest);
+        watch.feed(&mut tail, &[b'x'; 10_000]);
+        assert_eq!(watch.last().as_deref(), Some("5e55-0001"));
+    }
+
+    #[test]
+    fn session_ids_from_json_or_text() {
+        let stream = "{\"type\":\"system\",\"session_id\":\"ab-1\"}\n{\"type\":\"result\",\"session_id\":\"ab-2\"}\n";
+        assert_eq!(session_id(stream, true).as_deref(), Some("ab-2"));
+        assert_eq!(session_id(stream, false).as_deref(), Some("ab-2"));
+        assert_eq!(session_id("no session here", true), None);
+    }
+}
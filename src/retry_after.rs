@@ -0,0 +1,407 @@
+//! How long the server asked us to wait, read from a failed attempt's output:
+//!
+//! - `Retry-After: 30` or `Retry-After: 2.5` (seconds)
+//! - `Retry-After: Wed, 21 Oct 2025 07:28:00 GMT` (an HTTP date)
+//! - `retry after 3.5 seconds` in an error body
+//! - `anthropic-ratelimit-requests-reset: 2025-10-21T07:28:00Z` (and the other
+//!   `anthropic-ratelimit-*-reset` headers)
+//! - `limit will reset at 7pm (America/Los_Angeles)`, `resets 7:30am PST`, `resets tomorrow
+//!   at 19:00`: a usage limit's reset time, in the zone it names or else the assumed one
+//!   (see [`crate::tz`])
+//!
+//! Dates become a delay from now, 0 if they have passed. When several hints appear the
+//! longest wins, since waiting less than any one of them would hit the same limit again.
+
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use regex::{Captures, Regex};
+
+use crate::tz::{self, Zone};
+
+struct Patterns {
+    header: Regex,
+    prose: Regex,
+    reset: Regex,
+    reset_time: Regex,
+    seconds: Regex,
+    http_date: Regex,
+    rfc3339: Regex,
+}
+
+fn patterns() -> &'static Patterns {
+    static PATTERNS: OnceLock<Patterns> = OnceLock::new();
+    PATTERNS.get_or_init(|| Patterns {
+        header: Regex::new(r"(?im)\bretry-after:[ \t]*(.+?)[ \t]*\r?$").unwrap(),
+        prose: Regex::new(r"(?i)\bretry[ \t]+(?:after|in)[ \t]+(\d+(?:\.\d+)?)[ \t]*(?:seconds?|secs?|s)\b")
+            .unwrap(),
+        reset: Regex::new(r"(?im)\banthropic-ratelimit-[a-z-]+-reset:[ \t]*(\S+)").unwrap(),
+        reset_time: Regex::new(
+            r"(?i)\bresets?[ \t]+(?:at[ \t]+)?(?:(today|tomorrow)[ \t]+(?:at[ \t]+)?)?(\d{1,2})(?::(\d{2}))?(?:[ \t]*([ap])\.?m\b\.?)?(?:[ \t]*\(([^()\n]{1,64})\)|[ \t]+(?-i:([A-Z]{2,5}(?:[+-]\d{1,2}(?::?\d{2})?)?|[A-Za-z_]+/[A-Za-z0-9_+/-]+)\b))?",
+        )
+        .unwrap(),
+        seconds: Regex::new(r"^(\d+(?:\.\d+)?)\b").unwrap(),
+        http_date: Regex::new(
+            r"^(?:[A-Za-z]{3},[ \t]*)?(\d{1,2})[ \t]+([A-Za-z]{3})[ \t]+(\d{4})[ \t]+(\d{2}):(\d{2}):(\d{2})[ \t]*GMT\b",
+        )
+        .unwrap(),
+        rfc3339: Regex::new(
+            r"^(\d{4})-(\d{2})-(\d{2})[Tt ](\d{2}):(\d{2}):(\d{2})(?:\.(\d+))?(Z|z|[+-]\d{2}:\d{2})$",
+        )
+        .unwrap(),
+    })
+}
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+/// Unix milliseconds of a UTC date and time, if the fields are in range.
+fn unix_ms(date: (i64, i64, i64), time: (i64, i64, i64), offset_secs: i64) -> Option<i64> {
+    let (year, month, day) = date;
+    let (hour, min, sec) = time;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || min > 59 || sec > 60 {
+        return None;
+    }
+    let secs = tz::days_from_civil(year, month, day) * 86_400 + hour * 3600 + min * 60 + sec;
+    Some((secs - offset_secs) * 1000)
+}
+
+fn num(s: &str) -> i64 {
+    s.parse().unwrap_or(i64::MAX)
+}
+
+/// `Wed, 21 Oct 2025 07:28:00 GMT` as unix milliseconds.
+pub fn parse_http_date(s: &str) -> Option<i64> {
+    let c = patterns().http_date.captures(s.trim())?;
+    let month = MONTHS.iter().position(|m| c[2].eq_ignore_ascii_case(m))? as i64 + 1;
+    unix_ms(
+        (num(&c[3]), month, num(&c[1])),
+        (num(&c[4]), num(&c[5]), num(&c[6])),
+        0,
+    )
+}
+
+/// `2025-10-21T07:28:00Z` (fractions and `+HH:MM` offsets allowed) as unix milliseconds.
+pub fn parse_rfc3339(s: &str) -> Option<i64> {
+    let c = patterns().rfc3339.captures(s.trim())?;
+    let offset = match &c[8] {
+        "Z" | "z" => 0,
+        o => {
+            let sign = if o.starts_with('-') { -1 } else { 1 };
+            sign * (num(&o[1..3]) * 3600 + num(&o[4..6]) * 60)
+        }
+    };
+    let frac_ms = c.get(7).map_or(0, |f| {
+        let digits: String = f.as_str().chars().chain("00".chars()).take(3).collect();
+        num(&digits)
+    });
+    let ms = unix_ms(
+        (num(&c[1]), num(&c[2]), num(&c[3])),
+        (num(&c[4]), num(&c[5]), num(&c[6])),
+        offset,
+    )?;
+    Some(ms + frac_ms)
+}
+
+fn seconds_ms(s: &str) -> Option<u64> {
+    let secs: f64 = s.parse().ok()?;
+    secs.is_finite().then(|| (secs * 1000.0).round() as u64)
+}
+
+/// The wait until unix millisecond `at`, 0 once it has passed.
+fn until(at: i64, now_ms: i64) -> u64 {
+    at.saturating_sub(now_ms).max(0) as u64
+}
+
+/// The wait until the reset time one `reset_time` match names, with `default` standing in
+/// for a missing zone. A bare time is its next occurrence, so one that has just passed
+/// means tomorrow.
+fn reset_wait(c: &Captures, now_ms: i64, default: Option<&Zone>) -> Option<u64> {
+    // A word after the time that isn't a zone is just the next word; a parenthesized one
+    // is meant as a zone, so the time can't be placed without it
+    let zone = match (c.get(5), c.get(6).and_then(|w| tz::zone(w.as_str()))) {
+        (Some(name), _) => tz::zone(name.as_str())?,
+        (None, Some(zone)) => zone,
+        (None, None) => default?.clone(),
+    };
+    let (hour, min) = (num(&c[2]), c.get(3).map_or(0, |m| num(m.as_str())));
+    let hour = match c.get(4).map(|m| m.as_str().to_ascii_lowercase()) {
+        _ if min > 59 => return None,
+        Some(_) if !(1..=12).contains(&hour) => return None,
+        Some(m) => hour % 12 + if m == "p" { 12 } else { 0 },
+        // Without am/pm a bare number isn't a time
+        None if c.get(3).is_none() || hour > 23 => return None,
+        None => hour,
+    };
+    let now = now_ms.div_euclid(1000);
+    let today = (now + i64::from(zone.offset_at(now))).div_euclid(86_400);
+    let day = match c.get(1).map(|d| d.as_str().to_ascii_lowercase()) {
+        Some(d) if d == "tomorrow" => today + 1,
+        _ => today,
+    };
+    let local = day * 86_400 + hour * 3600 + min * 60;
+    let mut at = zone.instant_of(local);
+    if c.get(1).is_none() && at * 1000 <= now_ms {
+        at = zone.instant_of(local + 86_400);
+    }
+    Some(until(at * 1000, now_ms))
+}
+
+/// The longest wait a reset time in `text` asks for, assuming `default` for ones that name
+/// no zone.
+pub fn reset_ms(text: &str, now: SystemTime, default: Option<&Zone>) -> Option<u64> {
+    let now_ms = now
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as i64);
+    patterns()
+        .reset_time
+        .captures_iter(text)
+        .filter_map(|c| reset_wait(&c, now_ms, default))
+        .max()
+}
+
+/// The longest wait any hint in `text` asks for, in milliseconds, measuring dates from
+/// `now`.
+pub fn find_ms(text: &str, now: SystemTime) -> Option<u64> {
+    let p = patterns();
+    let now_ms = now
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as i64);
+    let headers = p.header.captures_iter(text).filter_map(|c| {
+        let value = c.get(1)?.as_str();
+        // A date first, since one without the weekday starts with digits too
+        match parse_http_date(value) {
+            Some(at) => Some(until(at, now_ms)),
+            None => seconds_ms(&p.seconds.captures(value)?[1]),
+        }
+    });
+    let prose = p
+        .prose
+        .captures_iter(text)
+        .filter_map(|c| seconds_ms(&c[1]));
+    let resets = p
+        .reset
+        .captures_iter(text)
+        .filter_map(|c| parse_rfc3339(&c[1]).map(|at| until(at, now_ms)));
+    let reset_time = reset_ms(text, now, tz::assumed());
+    headers.chain(prose).chain(resets).chain(reset_time).max()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    /// 2025-10-21T07:27:00Z, a minute before the dates below.
+    fn now() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_761_031_620)
+    }
+
+    #[test]
+    fn headers_in_seconds_or_as_dates() {
+        assert_eq!(
+            find_ms("HTTP/1.1 429\nRetry-After: 30\n", now()),
+            Some(30_000)
+        );
+        assert_eq!(find_ms("retry-after: 2.5\r\n", now()), Some(2_500));
+        let date = "Retry-After: Tue, 21 Oct 2025 07:28:00 GMT";
+        assert_eq!(find_ms(date, now()), Some(60_000));
+        assert_eq!(
+            find_ms("Retry-After: 21 Oct 2025 07:28:00 GMT", now()),
+            Some(60_000)
+        );
+        // Passed already
+        assert_eq!(
+            find_ms("Retry-After: 21 Oct 2025 07:00:00 GMT", now()),
+            Some(0)
+        );
+        assert_eq!(find_ms("Retry-After: soon", now()), None);
+    }
+
+    #[test]
+    fn prose_and_reset_headers() {
+        let body = r#"{"error":"rate limited, please retry after 3.5 seconds"}"#;
+        assert_eq!(find_ms(body, now()), Some(3_500));
+        assert_eq!(find_ms("Retry in 10s", now()), Some(10_000));
+        let reset = "anthropic-ratelimit-tokens-reset: 2025-10-21T07:28:30.5Z";
+        assert_eq!(find_ms(reset, now()), Some(90_500));
+        let offset = "anthropic-ratelimit-requests-reset: 2025-10-21T09:28:00+02:00";
+        assert_eq!(find_ms(offset, now()), Some(60_000));
+        assert_eq!(find_ms("API Error: 529 overloaded", now()), None);
+    }
+
+    #[test]
+    fn the_longest_hint_wins() {
+        let text = "Retry-After: 5\nplease retry after 20 seconds\nRetry-After: 12\n";
+        assert_eq!(find_ms(text, now()), Some(20_000));
+    }
+
+    #[test]
+    fn formats() {
+        let table: &[(&str, Option<u64>)] = &[
+            ("Retry-After: 30", Some(30_000)),
+            ("retry-after: 2.5", Some(2_500)),
+            ("Retry-After: Tue, 21 Oct 2025 07:28:00 GMT", Some(60_000)),
+            ("Retry-After: 21 Oct 2025 07:27:01 GMT", Some(1_000)),
+            ("Retry-After: Tue, 21 Oct 2025 07:00:00 GMT", Some(0)),
+            ("Error: rate limited, retry after 3.5 seconds", Some(3_500)),
+            ("please retry in 12s", Some(12_000)),
+            (
+                "anthropic-ratelimit-requests-reset: 2025-10-21T07:28:00Z",
+                Some(60_000),
+            ),
+            (
+                "anthropic-ratelimit-output-tokens-reset: 2025-10-21T09:28:30.5+02:00",
+                Some(90_500),
+            ),
+            (
+                "anthropic-ratelimit-tokens-reset: 2025-10-21T07:00:00Z",
+                Some(0),
+            ),
+            (
+                "anthropic-ratelimit-tokens-reset: 2026-01-01T00:00:00Z",
+                Some(6_193_980_000),
+            ),
+            (
+                "Retry-After: 5\nanthropic-ratelimit-requests-reset: 2025-10-21T07:28:00Z\n\
+                retry after 3 seconds",
+                Some(60_000),
+            ),
+            ("Retry-After: 2\r\nRetry-After: 7\r\n", Some(7_000)),
+            ("Retry-After: soon", None),
+            (
+                "anthropic-ratelimit-requests-reset: 2025-13-01T00:00:00Z",
+                None,
+            ),
+            ("HTTP 429 Too Many Requests", None),
+        ];
+        for &(text, want) in table {
+            assert_eq!(find_ms(text, now()), want, "{text:?}");
+        }
+    }
+
+    #[test]
+    fn reset_times() {
+        let utc = tz::zone("UTC");
+        let minutes = |m: u64| Some(m * 60_000);
+        assert_eq!(
+            reset_ms("limit will reset at 8am (UTC)", now(), None),
+            minutes(33)
+        );
+        assert_eq!(reset_ms("resets 8:00am UTC", now(), None), minutes(33));
+        // Just passed today, so tomorrow's
+        assert_eq!(
+            reset_ms("resets 7:00 UTC", now(), None),
+            minutes(23 * 60 + 33)
+        );
+        assert_eq!(
+            reset_ms("resets tomorrow at 19:00 (UTC)", now(), None),
+            minutes(35 * 60 + 33)
+        );
+        // A time without a zone needs the assumed one
+        assert_eq!(reset_ms("resets at 8am", now(), None), None);
+        assert_eq!(reset_ms("resets at 8am", now(), utc.as_ref()), minutes(33));
+        // Not times
+        assert_eq!(reset_ms("resets 8 UTC", now(), None), None);
+        assert_eq!(reset_ms("resets 13pm UTC", now(), None), None);
+        assert_eq!(
+            reset_ms("resets at 8am (Nowhere/Special)", now(), utc.as_ref()),
+            None
+        );
+    }
+
+    #[test]
+    fn reset_time_table() {
+        // (text, now, zone assumed when none is named, wait)
+        type Row = (&'static str, u64, Option<&'static str>, Option<u64>);
+        // 2025-10-21T07:27:00Z, 00:27 in Los Angeles
+        let oct = 1_761_031_620;
+        let mut table: Vec<Row> = vec![
+            (
+                "Your limit will reset at 7pm (PST8PDT).",
+                oct,
+                None,
+                Some(66_780_000),
+            ),
+            ("limit resets 7:30am PST", oct, None, Some(28_980_000)),
+            (
+                "resets tomorrow at 19:00 (UTC)",
+                oct,
+                None,
+                Some(127_980_000),
+            ),
+            ("reset at 19:00 UTC", oct, None, Some(41_580_000)),
+            ("resets at 12am UTC", oct, None, Some(59_580_000)),
+            ("resets at 12 p.m. UTC", oct, None, Some(16_380_000)),
+            // Passed today, so tomorrow; exactly now counts as passed
+            ("resets at 7am (UTC+2)", oct, None, Some(77_580_000)),
+            ("resets at 9:27am (GMT+02:00)", oct, None, Some(86_400_000)),
+            (
+                "resets at 10am (AEST-10AEDT,M10.1.0,M4.1.0/3)",
+                oct,
+                None,
+                Some(55_980_000),
+            ),
+            ("limit will reset at 8am", oct, Some("UTC"), Some(1_980_000)),
+            (
+                "limit will reset at 8am",
+                oct,
+                Some("CEST"),
+                Some(81_180_000),
+            ),
+            ("limit will reset at 8am", oct, None, None),
+            (
+                "resets at 8am ON weekdays",
+                oct,
+                Some("UTC"),
+                Some(1_980_000),
+            ),
+            // 1:30 happens twice on 2025-11-02 in Los Angeles: the later one
+            (
+                "resets at 1:30am (PST8PDT)",
+                1_762_066_800,
+                None,
+                Some(9_000_000),
+            ),
+            // 2:30 never happens on 2025-03-09: an hour past it
+            (
+                "resets at 2:30am (PT)",
+                1_741_507_200,
+                None,
+                Some(9_000_000),
+            ),
+            ("resets at 7pm (Mars/Olympus_Mons)", oct, Some("UTC"), None),
+            ("resets 7", oct, Some("UTC"), None),
+            ("resets at 13pm UTC", oct, None, None),
+            ("resets at 7:75pm UTC", oct, None, None),
+        ];
+        if tz::zone("America/Los_Angeles").is_some() {
+            table.extend([
+                (
+                    "resets at 7pm (America/Los_Angeles)",
+                    oct,
+                    None,
+                    Some(66_780_000),
+                ),
+                ("resets at 7pm Europe/Berlin", oct, None, Some(34_380_000)),
+                (
+                    "resets at 1:30am",
+                    1_762_066_800,
+                    Some("America/Los_Angeles"),
+                    Some(9_000_000),
+                ),
+            ]);
+        }
+        for (text, now, default, want) in table {
+            let now = UNIX_EPOCH + Duration::from_secs(now);
+            let default = default.map(|d| tz::zone(d).expect(d));
+            assert_eq!(
+                reset_ms(text, now, default.as_ref()),
+                want,
+                "{text:?} in {default:?}"
+            );
+        }
+    }
+}
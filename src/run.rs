@@ -0,0 +1,2407 @@
+//! One run of the child. [`setup`] turns the resolved flags into a [`Run`]; [`attempt`]
+//! starts each attempt; [`interactive_attempt`] sees an interactive one through, while
+//! [`piped_attempt`] tees and waits out a piped one and [`dispose`] judges how it went,
+//! then retries or ends the run.
+
+use std::env;
+use std::ffi::OsString;
+use std::io;
+use std::ops::ControlFlow::{self, Break, Continue};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ExitStatus, Stdio};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::parser::ValueSource;
+use clap::ArgMatches;
+use regex::Regex;
+use rusty_claude::classes::{ClassBudget, ErrorClass};
+use rusty_claude::exec::{Step, Supervisor};
+use rusty_claude::patterns::{should_retry, MatchStreams, Output, RetryDecision, Stream};
+use rusty_claude::tz;
+
+use crate::ci::{self, Annotator, CiMode};
+use crate::duration::{self, format_duration};
+use crate::exit_codes::{self, Outcome, Reason};
+use crate::mode::{config_warnings, decide_mode, ModeInputs};
+use crate::piped::{
+    release_buffered, settle_stdin, stdin_writer, tee_reader, wait_child, Activity, Deadlines,
+    Killed, Lookout, StreamMatch, Tap, TimeoutWarning, Waited,
+};
+use crate::policy::{backoff, build_patterns, jitter_rng, log_patterns, provenance, retry_policy};
+use crate::size::format_size;
+use crate::verdict::{cap_retry_after, judge, Recorded, Stops, Verdict};
+use crate::{
+    argenv, artifacts, batch, budget, chaos, child_env, control, delay_cmd, edit, events, fallback,
+    feed, guard, home, hook, integrity, interrupt, locale, log_file, memory, observe, resolve,
+    resume, runid, server, settings, shellquote, spool, summary, tag, title, trace, tree, tty,
+    utf8, version, waste,
+};
+use crate::{default_cmd, load_layers, resolve_settings, set_env_policy, Cli};
+#[cfg(unix)]
+use crate::{mode::Mode, pty};
+
+/// Minimum delay before relaunching an interactive session under `--interactive-retry`.
+const INTERACTIVE_RETRY_GRACE_MS: u64 = 5000;
+
+/// What a run sets up before its first attempt and keeps for all of them.
+struct Run {
+    cli: Cli,
+    /// Where --max-total-ms counts from, ahead of any --initial-delay
+    started: Instant,
+    run_id: String,
+    real_cmd: String,
+    pinned: Option<integrity::Pinned>,
+    /// Replayed into every piped attempt; --edit-on-retry may replace it
+    stdin_buf: spool::Input,
+    stdin_is_tty: bool,
+    interactive: bool,
+    heartbeat: Option<Duration>,
+    attempt_timeout: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    first_output_timeout: Option<Duration>,
+    guards: guard::Guards,
+    annotator: Annotator,
+    success_pattern: Option<Regex>,
+    edit_pattern: Option<Option<Regex>>,
+    events: events::Events,
+    observers: Option<Arc<observe::Hub>>,
+    #[cfg(unix)]
+    mode: Mode,
+    #[cfg(unix)]
+    injection: Option<pty::Injection>,
+    #[cfg(unix)]
+    use_pty: bool,
+    child_env: child_env::ChildEnv,
+    time_budget: Option<budget::Budget>,
+    title: title::Title,
+    decision_trace: Option<trace::Trace>,
+    artifacts: Option<artifacts::Artifacts>,
+    /// Whether each attempt resumes the session of the one before
+    auto_resume: bool,
+    /// Removed once the run is over
+    _home: Option<home::IsolatedHome>,
+    _socket: Option<observe::SocketGuard>,
+}
+
+/// What a run's attempts carry from one to the next.
+#[derive(Default)]
+struct Progress {
+    /// Output bytes across the attempts so far, for --max-total-output
+    total_output: u64,
+    class_budget: ClassBudget,
+    previous_stderr: Option<Vec<u8>>,
+    /// The session the next attempt resumes, under --auto-resume
+    resume_session: Option<String>,
+    /// For decorrelated jitter, which grows from the delay before
+    previous_wait: Option<u64>,
+    waste: waste::Waste,
+    matched: Vec<(String, Option<ErrorClass>)>,
+    /// What --on-retry-cmd set for the next attempt
+    hook_env: Vec<(OsString, OsString)>,
+    /// The argument set being tried (0 for the child args, then each --fallback-args) and
+    /// the attempt its retry cycle began with
+    arg_set: usize,
+    cycle_start: u32,
+}
+
+/// One attempt, as it was started.
+struct Launch {
+    /// Counted from 0, like the supervisor's attempts.
+    attempt: u32,
+    /// Attempts so far in this argument set's retry cycle
+    cycle_attempt: u32,
+    last_in_cycle: bool,
+    fallback_left: bool,
+    args: Vec<String>,
+    env: Vec<(OsString, OsString)>,
+    tag: Option<String>,
+    /// Removed again once this attempt is over
+    _previous_error: Option<feed::ErrorFile>,
+}
+
+impl Launch {
+    /// The shell line that reruns this attempt by hand.
+    fn repro(&self, real_cmd: &str, stdin_bytes: u64) -> String {
+        shellquote::repro_line(
+            env::current_dir().ok().as_deref(),
+            &self.env,
+            real_cmd,
+            &self.args,
+            stdin_bytes,
+        )
+    }
+}
+
+/// How a piped attempt ended, for [`dispose`] to judge.
+struct Ran {
+    status: ExitStatus,
+    warned_at: Option<Duration>,
+    killed: Option<Killed>,
+    activity: Arc<Activity>,
+    out: memory::Capture,
+    err: memory::Capture,
+    /// Both streams, stdout first, as --edit-on-retry looks at them
+    combined_text: String,
+    wall: Duration,
+    cpu: Option<Duration>,
+    session_watch: Option<Arc<resume::Watch>>,
+}
+
+/// Run the child under `cli`, retrying as it says, and return how the run ended.
+pub fn run(cli: Cli, matches: &ArgMatches) -> io::Result<Outcome> {
+    let (mut run, supervisor) = match setup(cli, matches)? {
+        Continue(ready) => ready,
+        Break(outcome) => return Ok(outcome),
+    };
+    let mut progress = Progress {
+        class_budget: run.cli.class_budget.clone().unwrap_or_default(),
+        ..Progress::default()
+    };
+    // Ends with a `Step::Stop`: every cycle is at most --max-retries + 1 attempts
+    supervisor.drive(|n| attempt(&mut run, &mut progress, &supervisor, n))
+}
+
+/// Check the flags and prepare everything the attempts share. A run that ends before its
+/// first attempt (a config error, --dry-run, --batch, --server-mode) breaks with its outcome.
+fn setup(
+    mut cli: Cli,
+    matches: &ArgMatches,
+) -> io::Result<ControlFlow<Outcome, (Run, Supervisor)>> {
+    let started = Instant::now();
+    #[cfg(feature = "chaos")]
+    chaos::install(std::mem::take(&mut cli.chaos));
+    if cli.no_tty {
+        tty::force_absent();
+    }
+    duration::set_raw(cli.raw_durations);
+    set_env_policy(&cli);
+    let layers = match load_layers(&cli) {
+        Ok(layers) => layers,
+        Err(e) => {
+            eprintln!("[rusty-claude] error: {e}");
+            return Ok(Break(Outcome::wrapper(
+                Reason::ConfigError,
+                exit_codes::CONFIG_ERROR,
+                0,
+            )));
+        }
+    };
+    let settings = resolve_settings(&mut cli, matches, &layers);
+    if cli.print_config {
+        print!("{}", settings::render(&settings));
+        return Ok(Break(Outcome::wrapper(Reason::Success, 0, 0)));
+    }
+    if cli.print_backoff {
+        let seeded = cli.jitter_seed.map(|_| jitter_rng(&cli));
+        print!("{}", backoff(&cli).schedule(cli.max_retries, seeded));
+        return Ok(Break(Outcome::wrapper(Reason::Success, 0, 0)));
+    }
+    let run_id = cli.run_id.clone().unwrap_or_default();
+    if cli.verbose > 0 {
+        eprintln!("[rusty-claude] run id {run_id}");
+    }
+    if cli.raw_passthrough {
+        // Checked before the log opens, which would write to stderr from then on
+        let to_stderr = [
+            ("--json-events", &cli.json_events),
+            ("--log-file", &cli.log_file),
+            ("--stats-json", &cli.stats_json),
+        ]
+        .into_iter()
+        .find(|(_, path)| path.as_deref() == Some(Path::new("-")));
+        if let Some((flag, _)) = to_stderr {
+            eprintln!(
+                "[rusty-claude] error: {flag} - writes to stderr, which --raw-passthrough \
+                keeps untouched"
+            );
+            return Ok(Break(Outcome::wrapper(
+                Reason::ConfigError,
+                exit_codes::CONFIG_ERROR,
+                0,
+            )));
+        }
+        cli.quiet = true;
+    }
+    if let Some(path) = &cli.log_file {
+        log_file::open(path, &run_id);
+    }
+
+    let mut real_cmd = resolve_cmd(&cli);
+    let mut pinned = None;
+    if !cli.expect_cmd_sha256.is_empty() {
+        match integrity::verify(&real_cmd, &cli.expect_cmd_sha256) {
+            Ok(pin) => {
+                // Spawn exactly the file that was hashed
+                real_cmd = pin.path.to_string_lossy().into_owned();
+                pinned = Some(pin);
+            }
+            Err(e) => return Ok(Break(integrity_failure(&e, 0))),
+        }
+    }
+    if !cli.allow_self_wrap {
+        if let Some(path) = resolve::self_wrap(&real_cmd) {
+            eprintln!(
+                "[rusty-claude] error: the child command `{real_cmd}` is rusty-claude itself ({}); \
+                every level would start another wrapper instead of the CLI. Point --cmd at the real \
+                CLI, or pass --allow-self-wrap if the nesting is intended",
+                path.display()
+            );
+            return Ok(Break(Outcome::wrapper(
+                Reason::ConfigError,
+                exit_codes::CONFIG_ERROR,
+                0,
+            )));
+        }
+    }
+    if let Some(mode) = cli.expand_arg_env {
+        match argenv::expand_all(&cli.args, mode, &|k| env::var(k).ok()) {
+            Ok(args) => cli.args = args,
+            Err(e) => {
+                eprintln!("[rusty-claude] error: --expand-arg-env: {e}");
+                return Ok(Break(Outcome::wrapper(
+                    Reason::ConfigError,
+                    exit_codes::CONFIG_ERROR,
+                    0,
+                )));
+            }
+        }
+    }
+    if cli.tag_attempts == Some(tag::TagMode::Header) {
+        if let Err(e) = tag::check_template(&cli.tag_arg) {
+            eprintln!("[rusty-claude] error: {e}");
+            return Ok(Break(Outcome::wrapper(
+                Reason::ConfigError,
+                exit_codes::CONFIG_ERROR,
+                0,
+            )));
+        }
+    }
+    if let Some(arg) = &cli.feed_previous_error {
+        if let Err(e) = feed::check(arg, &cli.args, cli.retry_extra_args.as_deref()) {
+            eprintln!("[rusty-claude] error: --feed-previous-error {arg}: {e}");
+            return Ok(Break(Outcome::wrapper(
+                Reason::ConfigError,
+                exit_codes::CONFIG_ERROR,
+                0,
+            )));
+        }
+    }
+    let argv = format!("{real_cmd:?} {:?}", cli.args);
+    if cli.dry_run {
+        println!("{argv}");
+        return Ok(Break(Outcome::wrapper(Reason::Success, 0, 0)));
+    }
+    let mut retry_regexes = match build_patterns(&cli) {
+        Ok(patterns) => patterns,
+        Err(e) => {
+            eprintln!("[rusty-claude] error: {e}");
+            return Ok(Break(Outcome::wrapper(
+                Reason::ConfigError,
+                exit_codes::CONFIG_ERROR,
+                0,
+            )));
+        }
+    };
+    if cli.verbose > 0 {
+        log_patterns(&retry_regexes);
+    }
+    // Every server exit is restarted anyway, unless its code rules that out
+    if !cli.server_mode {
+        retry_regexes.retry_codes = cli.retry_exit_codes.clone().unwrap_or_default();
+    }
+    retry_regexes.no_retry_codes = cli.no_retry_exit_codes.clone().unwrap_or_default();
+    retry_regexes.streams = cli.match_streams;
+    if cli.batch.is_some() {
+        return batch::run(&cli, &real_cmd, retry_regexes, &run_id).map(Break);
+    }
+
+    // If stdin is piped, capture it once to replay on retries
+    let mut stdin_buf = spool::Input::from(Vec::new());
+    // --stdin-file stands in for a pipe even on a terminal
+    let stdin_is_tty = atty::is(atty::Stream::Stdin) && cli.stdin_file.is_none();
+    if let Some(path) = cli.stdin_file.as_deref().filter(|p| *p != Path::new("-")) {
+        // Read up front, so a missing file never gets as far as a spawn
+        match std::fs::File::open(path)
+            .and_then(|file| spool::Input::read(file, cli.stdin_spool_threshold_bytes))
+        {
+            Ok(input) => stdin_buf = input,
+            Err(e) => {
+                eprintln!(
+                    "[rusty-claude] error: cannot read --stdin-file {}: {e}",
+                    path.display()
+                );
+                return Ok(Break(Outcome::wrapper(
+                    Reason::ConfigError,
+                    exit_codes::CONFIG_ERROR,
+                    0,
+                )));
+            }
+        }
+    // A server child reads its own stdin (often a protocol); never swallow it
+    } else if !stdin_is_tty && !cli.server_mode {
+        stdin_buf = spool::Input::read(io::stdin(), cli.stdin_spool_threshold_bytes)?;
+        if stdin_buf.spooled() && cli.verbose > 0 {
+            eprintln!(
+                "[rusty-claude] stdin is over --stdin-spool-threshold-bytes {}; streaming it \
+                through a temp file",
+                format_size(cli.stdin_spool_threshold_bytes)
+            );
+        }
+    }
+    if cli.no_process_group {
+        tree::disable();
+    }
+    memory::set_capture_limit(cli.max_capture_bytes);
+    if let Some(limit) = cli.self_mem_limit {
+        memory::set_limit(limit);
+        if stdin_buf.in_memory() > limit {
+            eprintln!(
+                "[rusty-claude] warning: the {} of stdin alone is over --self-mem-limit {}; \
+                it is kept whole, since every attempt replays it (a lower \
+                --stdin-spool-threshold-bytes keeps it on disk instead)",
+                format_size(stdin_buf.in_memory()),
+                format_size(limit)
+            );
+        }
+    }
+    memory::set(memory::Buffer::Stdin, stdin_buf.in_memory());
+    // A server child reads its own stdin, so there is nothing of ours to check
+    if let Some(min) = cli.require_stdin.filter(|_| !cli.server_mode) {
+        let source = match cli.stdin_file.as_deref().filter(|p| *p != Path::new("-")) {
+            Some(path) => format!("--stdin-file {}", path.display()),
+            None => "stdin".to_string(),
+        };
+        let missing = if stdin_is_tty {
+            Some("stdin is a terminal, not a pipe".to_string())
+        } else if stdin_buf.len() < min {
+            Some(match stdin_buf.len() {
+                0 => format!("{source} is empty"),
+                n => format!("{source} holds only {}", format_size(n)),
+            })
+        } else {
+            None
+        };
+        if let Some(missing) = missing {
+            eprintln!(
+                "[rusty-claude] error: --require-stdin {}: {missing}; not starting the child",
+                format_size(min)
+            );
+            return Ok(Break(Outcome::wrapper(
+                Reason::NoStdin,
+                exit_codes::NO_STDIN,
+                0,
+            )));
+        }
+    }
+
+    let mode = match decide_mode(&ModeInputs {
+        terminal: stdin_buf.is_empty() && stdin_is_tty,
+        has_args: !cli.args.is_empty(),
+        force_tee: cli.force_tee,
+        pty: cli.pty,
+        server_mode: cli.server_mode,
+        pty_supported: cfg!(unix),
+    }) {
+        Ok(mode) => mode,
+        Err(msg) => {
+            eprintln!("[rusty-claude] error: {msg}");
+            return Ok(Break(Outcome::wrapper(
+                Reason::ConfigError,
+                exit_codes::CONFIG_ERROR,
+                0,
+            )));
+        }
+    };
+    if cli.verbose > 0 {
+        eprintln!("[rusty-claude] mode: {}", mode.explain(&cli));
+    }
+    let interactive = mode.interactive();
+    if interactive && matches.value_source("attempt_timeout_secs") == Some(ValueSource::CommandLine)
+    {
+        eprintln!(
+            "[rusty-claude] error: --attempt-timeout-secs applies to non-interactive runs; an \
+            interactive session is never timed out"
+        );
+        return Ok(Break(Outcome::wrapper(
+            Reason::ConfigError,
+            exit_codes::CONFIG_ERROR,
+            0,
+        )));
+    }
+
+    let user_patterns = retry_regexes
+        .regexes
+        .iter()
+        .any(|re| retry_regexes.source(re.as_str()) != "built-in");
+    let warnings = config_warnings(&cli, mode, retry_regexes.len(), user_patterns);
+    for w in warnings
+        .iter()
+        .filter(|_| cli.strict_config || !cli.raw_passthrough)
+    {
+        if cli.strict_config {
+            eprintln!("[rusty-claude] error: {w}");
+        } else {
+            eprintln!("[rusty-claude] warning: {w}");
+        }
+    }
+    if cli.strict_config && !warnings.is_empty() {
+        return Ok(Break(Outcome::wrapper(
+            Reason::ConfigError,
+            exit_codes::CONFIG_ERROR,
+            0,
+        )));
+    }
+
+    let heartbeat = cli
+        .heartbeat
+        .filter(|_| !cli.quiet || cli.heartbeat_even_when_quiet);
+    // From the environment it is not an error in an interactive session, just not applied
+    let attempt_timeout = cli
+        .attempt_timeout_secs
+        .filter(|_| !interactive)
+        .map(Duration::from_secs);
+    let idle_timeout = cli
+        .idle_timeout_secs
+        .filter(|_| !interactive)
+        .map(Duration::from_secs);
+    let first_output_timeout = cli
+        .first_output_timeout
+        .filter(|d| !interactive && !d.is_zero());
+    let guards = guard::Guards {
+        min_runtime: cli.min_runtime_for_retry,
+        disabled: cli.no_retry_guard.clone(),
+    };
+
+    let ci_mode = if interactive {
+        CiMode::Off
+    } else {
+        cli.ci_annotations.resolve()
+    };
+    let annotations_to_stderr = ci_mode != CiMode::Off && ci::stdout_is_structured(&cli.args);
+    if annotations_to_stderr {
+        eprintln!(
+            "[rusty-claude] warning: child stdout is machine-readable; \
+            writing CI annotations to stderr instead"
+        );
+    }
+    let annotator = Annotator::new(ci_mode, annotations_to_stderr);
+
+    if stdin_buf.is_empty()
+        && cli.args.is_empty()
+        && !stdin_is_tty
+        && !cli.quiet
+        && !cli.server_mode
+    {
+        eprintln!(
+            "[rusty-claude] No stdin and no child args. \
+            To run interactive mode, invoke from a TTY (no pipe). \
+            To run non-interactive mode, provide child args after `--` (e.g., -- --json)."
+        );
+    }
+
+    if let Some(min) = cli.min_child_version {
+        let verdict = match version::probe(&real_cmd) {
+            Ok(found) if found >= min => None,
+            Ok(found) => Some(format!(
+                "`{real_cmd}` is version {found}, below the known-good minimum {min}; \
+                upgrade the CLI (e.g. `npm install -g @anthropic-ai/claude-code`)"
+            )),
+            Err(e) => Some(format!("cannot verify --min-child-version {min}: {e}")),
+        };
+        if let Some(msg) = verdict {
+            if cli.enforce_min_child_version {
+                eprintln!("[rusty-claude] error: {msg}");
+                return Ok(Break(Outcome::wrapper(
+                    Reason::ChildTooOld,
+                    exit_codes::CHILD_TOO_OLD,
+                    0,
+                )));
+            }
+            eprintln!("[rusty-claude] warning: {msg}");
+        }
+    }
+
+    let ready_pattern = match cli.ready_pattern.as_deref().map(Regex::new).transpose() {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("[rusty-claude] error: invalid --ready-pattern: {e}");
+            return Ok(Break(Outcome::wrapper(
+                Reason::ConfigError,
+                exit_codes::CONFIG_ERROR,
+                0,
+            )));
+        }
+    };
+    let success_pattern = match cli.success_pattern.as_deref().map(Regex::new).transpose() {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("[rusty-claude] error: invalid --success-pattern: {e}");
+            return Ok(Break(Outcome::wrapper(
+                Reason::ConfigError,
+                exit_codes::CONFIG_ERROR,
+                0,
+            )));
+        }
+    };
+    if let Some(name) = &cli.assume_tz {
+        match tz::zone(name) {
+            Some(zone) => tz::assume(zone),
+            None => {
+                eprintln!(
+                    "[rusty-claude] error: --assume-tz `{name}` is not a known time zone \
+                    (expected an IANA name like America/Los_Angeles, an abbreviation like PST, \
+                    or an offset like UTC+2)"
+                );
+                return Ok(Break(Outcome::wrapper(
+                    Reason::ConfigError,
+                    exit_codes::CONFIG_ERROR,
+                    0,
+                )));
+            }
+        }
+    }
+    let edit_pattern = match cli
+        .edit_on_retry
+        .as_ref()
+        .map(|p| p.as_deref().map(Regex::new))
+    {
+        Some(Some(Err(e))) => {
+            eprintln!("[rusty-claude] error: invalid --edit-on-retry pattern: {e}");
+            return Ok(Break(Outcome::wrapper(
+                Reason::ConfigError,
+                exit_codes::CONFIG_ERROR,
+                0,
+            )));
+        }
+        Some(pattern) => match (tty::terminal(), cli.edit_no_tty) {
+            (Ok(_), _) => pattern.transpose().ok(),
+            (Err(e), tty::Fallback::Skip) => {
+                if !cli.quiet {
+                    eprintln!(
+                        "[rusty-claude] --edit-on-retry: {e}; retrying without editing \
+                            (--edit-no-tty skip)"
+                    );
+                }
+                None
+            }
+            (Err(e), tty::Fallback::Abort) => {
+                eprintln!(
+                    "[rusty-claude] error: --edit-on-retry needs a terminal to run the editor \
+                        on: {e}; pass --edit-no-tty skip to retry without editing"
+                );
+                return Ok(Break(Outcome::wrapper(
+                    Reason::ConfigError,
+                    exit_codes::CONFIG_ERROR,
+                    0,
+                )));
+            }
+        },
+        None => None,
+    };
+    let mut events = match &cli.json_events {
+        Some(path) => match events::Events::open(path, &run_id) {
+            Ok(e) => e,
+            Err(e) => {
+                eprintln!(
+                    "[rusty-claude] error: cannot open --json-events {}: {e}",
+                    path.display()
+                );
+                return Ok(Break(Outcome::wrapper(
+                    Reason::ConfigError,
+                    exit_codes::CONFIG_ERROR,
+                    0,
+                )));
+            }
+        },
+        None => events::Events::disabled(&run_id),
+    };
+    control::install(cli.control_file.clone(), cli.quiet);
+    let mut observers = None;
+    let _socket = match &cli.observe_socket {
+        Some(path) => {
+            let hub = Arc::new(observe::Hub::new(&run_id));
+            match observe::listen(path, Arc::clone(&hub)) {
+                Ok(guard) => {
+                    events.observe(Arc::clone(&hub));
+                    observers = Some(hub);
+                    Some(guard)
+                }
+                Err(e) => {
+                    eprintln!(
+                        "[rusty-claude] error: cannot listen on --observe-socket {}: {e}",
+                        path.display()
+                    );
+                    return Ok(Break(Outcome::wrapper(
+                        Reason::ConfigError,
+                        exit_codes::CONFIG_ERROR,
+                        0,
+                    )));
+                }
+            }
+        }
+        None => None,
+    };
+
+    #[cfg(unix)]
+    let injection = cli.initial_input.as_ref().map(|text| pty::Injection {
+        text: text.clone(),
+        ready: ready_pattern.clone(),
+        timeout: cli.ready_timeout,
+    });
+    #[cfg(unix)]
+    let use_pty = matches!(mode, Mode::Pty { .. });
+
+    // The environment of every child, the wrapper's variables layered over what it inherits
+    let mut child_env = child_env::ChildEnv::new(&cli);
+    let _home = match &cli.isolated_home {
+        Some(requested) => {
+            match home::prepare(
+                requested.as_deref(),
+                cli.home_template.as_deref(),
+                cli.keep_isolated_home,
+            ) {
+                Ok(home) => {
+                    if !cli.quiet {
+                        eprintln!(
+                            "[rusty-claude] child HOME isolated at {}",
+                            home.path.display()
+                        );
+                    }
+                    child_env.vars.extend(home.env());
+                    Some(home)
+                }
+                Err(e) => {
+                    eprintln!("[rusty-claude] error: cannot prepare --isolated-home: {e}");
+                    return Ok(Break(Outcome::wrapper(
+                        Reason::ConfigError,
+                        exit_codes::CONFIG_ERROR,
+                        0,
+                    )));
+                }
+            }
+        }
+        None => None,
+    };
+    if cli.stable_locale {
+        let (vars, note) = locale::stable_env();
+        if cli.verbose > 0 {
+            eprintln!("[rusty-claude] child locale: {note}");
+        }
+        child_env.vars.extend(vars);
+    }
+    child_env.set_explicit(&cli);
+    if cli.verbose > 0 {
+        let mut set_per_attempt = vec![runid::ENV_VAR];
+        if cli.tag_attempts == Some(tag::TagMode::Env) {
+            set_per_attempt.push(tag::ENV_VAR);
+        }
+        eprintln!("[rusty-claude] {}", child_env.describe(&set_per_attempt));
+    }
+
+    let time_budget = cli
+        .max_total_ms
+        .filter(|_| !interactive && !cli.server_mode)
+        .map(|ms| budget::Budget::new(started, Duration::from_millis(ms)));
+    if let Some(delay) = cli.initial_delay.filter(|d| !d.is_zero()) {
+        // A run that can't start inside its budget would only fail later, having waited
+        if budget::check_wait(time_budget.as_ref(), delay, Instant::now()).is_err() {
+            eprintln!(
+                "[rusty-claude] error: --initial-delay {} leaves no time for an attempt inside \
+                --max-total-ms {}",
+                format_duration(delay),
+                format_duration(time_budget.map(|b| b.total()).unwrap_or_default())
+            );
+            return Ok(Break(Outcome::wrapper(
+                Reason::ConfigError,
+                exit_codes::CONFIG_ERROR,
+                0,
+            )));
+        }
+        if !cli.quiet {
+            eprintln!(
+                "[rusty-claude] waiting {} before the first attempt (--initial-delay)",
+                format_duration(delay)
+            );
+        }
+        events.emit(
+            "initial_delay",
+            serde_json::json!({ "delay_ms": delay.as_millis() as u64 }),
+        );
+        let sleeping = Instant::now();
+        let halted_sleep = control::sleep(chaos::sleep(delay), &events);
+        summary::pre_delay(sleeping.elapsed());
+        if let Some(cmd) = halted_sleep {
+            eprintln!("[rusty-claude] not starting: {cmd} requested during --initial-delay");
+            return Ok(Break(match cmd {
+                control::Command::Drain => Outcome::wrapper(Reason::Drained, 0, 0),
+                control::Command::Abort => {
+                    Outcome::wrapper(Reason::Aborted, exit_codes::INTERRUPTED, 0)
+                }
+            }));
+        }
+        if let Some(sig) = interrupt::received() {
+            return Ok(Break(Outcome::wrapper(
+                Reason::Interrupted,
+                interrupt::exit_code(sig),
+                0,
+            )));
+        }
+    }
+
+    if cli.server_mode {
+        return server::run(
+            &cli,
+            &real_cmd,
+            &retry_regexes,
+            ready_pattern,
+            pinned,
+            &child_env,
+            &events,
+        )
+        .map(Break);
+    }
+
+    // An interactive child gets Ctrl-C from the terminal it owns and decides for itself
+    if !interactive {
+        interrupt::claim();
+    }
+    // Interactive children own the terminal, title included
+    let title = title::Title::new(cli.set_title && !interactive, &real_cmd);
+    let artifacts_dir = cli.attempt_artifacts.as_deref().map(|dir| {
+        let text = dir.to_string_lossy();
+        if text.contains(runid::PLACEHOLDER) {
+            PathBuf::from(text.replace(runid::PLACEHOLDER, &run_id))
+        } else {
+            dir.to_path_buf()
+        }
+    });
+    let decision_trace = match cli.decision_trace.as_deref().filter(|_| !interactive) {
+        Some(path) => match trace::Trace::open(path, &run_id) {
+            Ok(t) => Some(t),
+            Err(e) => {
+                eprintln!(
+                    "[rusty-claude] error: cannot open --decision-trace {}: {e}",
+                    path.display()
+                );
+                return Ok(Break(Outcome::wrapper(
+                    Reason::ConfigError,
+                    exit_codes::CONFIG_ERROR,
+                    0,
+                )));
+            }
+        },
+        None => None,
+    };
+    let artifacts = match artifacts_dir.as_deref().filter(|_| !interactive) {
+        Some(dir) => match artifacts::Artifacts::open(
+            dir,
+            cli.attempt_artifacts_keep,
+            cli.compress_artifacts,
+        ) {
+            Ok(a) => Some(a),
+            Err(e) => {
+                eprintln!(
+                    "[rusty-claude] error: cannot write to --attempt-artifacts directory {}: {e}",
+                    dir.display()
+                );
+                return Ok(Break(Outcome::wrapper(
+                    Reason::ConfigError,
+                    exit_codes::CONFIG_ERROR,
+                    0,
+                )));
+            }
+        },
+        None => None,
+    };
+
+    let auto_resume = cli.auto_resume
+        && !interactive
+        && !cli.server_mode
+        && resume::own_session_flag(&cli.args, cli.retry_extra_args.as_deref()).is_none();
+    let supervisor = Supervisor::new(retry_policy(&cli, retry_regexes));
+    let run = Run {
+        cli,
+        started,
+        run_id,
+        real_cmd,
+        pinned,
+        stdin_buf,
+        stdin_is_tty,
+        interactive,
+        heartbeat,
+        attempt_timeout,
+        idle_timeout,
+        first_output_timeout,
+        guards,
+        annotator,
+        success_pattern,
+        edit_pattern,
+        events,
+        observers,
+        #[cfg(unix)]
+        mode,
+        #[cfg(unix)]
+        injection,
+        #[cfg(unix)]
+        use_pty,
+        child_env,
+        time_budget,
+        title,
+        decision_trace,
+        artifacts,
+        auto_resume,
+        _home,
+        _socket,
+    };
+    Ok(Continue((run, supervisor)))
+}
+
+/// Start attempt `attempt` and see it through, by [`interactive_attempt`] or by
+/// [`piped_attempt`] and [`dispose`].
+fn attempt(
+    run: &mut Run,
+    progress: &mut Progress,
+    supervisor: &Supervisor,
+    attempt: u32,
+) -> io::Result<Step<Outcome>> {
+    let Run {
+        ref cli,
+        ref run_id,
+        ref real_cmd,
+        ref mut pinned,
+        ref stdin_buf,
+        stdin_is_tty,
+        interactive,
+        ref child_env,
+        ..
+    } = *run;
+    if let Some(pin) = pinned.as_mut() {
+        if let Err(e) = pin.recheck(&cli.expect_cmd_sha256) {
+            return Ok(Step::Stop(integrity_failure(&e, attempt)));
+        }
+    }
+    // Attempts so far in this argument set's retry cycle
+    let cycle_attempt = attempt - progress.cycle_start;
+    let last_in_cycle = cycle_attempt == cli.max_retries;
+    let arg_set = progress.arg_set;
+    let fallback_left = arg_set < cli.fallback_args.len() && !cli.server_mode;
+    let mut args = fallback::args(&cli.args, &cli.fallback_args, arg_set, cli.fallback_mode);
+    if !cli.fallback_args.is_empty() && !cli.quiet {
+        eprintln!(
+            "[rusty-claude] attempt {} uses the {}",
+            attempt + 1,
+            fallback::describe(&cli.fallback_args, arg_set)
+        );
+    }
+    if attempt > 0 {
+        if let Some(extra) = &cli.retry_extra_args {
+            args.extend(extra.split_whitespace().map(str::to_string));
+        }
+    }
+    if let Some(session) = &progress.resume_session {
+        if !cli.quiet {
+            eprintln!(
+                "[rusty-claude] attempt {} resumes session {session} (--auto-resume)",
+                attempt + 1
+            );
+        }
+        resume::inject(&mut args, session);
+    }
+    // The hook's variables apply to this attempt only
+    let mut attempt_env = child_env.vars.clone();
+    let attempt_tag = cli
+        .tag_attempts
+        .map(|mode| (mode, tag::attempt_tag(run_id, attempt + 1)));
+    match &attempt_tag {
+        Some((tag::TagMode::Env, t)) => attempt_env.push((tag::ENV_VAR.into(), t.into())),
+        Some((tag::TagMode::Header, t)) => args.extend(tag::expand(&cli.tag_arg, t)),
+        None => {}
+    }
+    let attempt_tag = attempt_tag.map(|(_, t)| t);
+    attempt_env.append(&mut progress.hook_env);
+    let previous_error = match (&cli.feed_previous_error, &progress.previous_stderr) {
+        (Some(arg), Some(stderr)) => match feed::ErrorFile::write(stderr, attempt + 1) {
+            Ok(file) => {
+                if !cli.quiet {
+                    eprintln!(
+                        "[rusty-claude] attempt {}: passing the previous stderr ({}) as `{arg} {}`",
+                        attempt + 1,
+                        format_size(file.bytes as u64),
+                        file.path.display()
+                    );
+                }
+                args.push(arg.clone());
+                args.push(file.path.to_string_lossy().into_owned());
+                Some(file)
+            }
+            Err(e) => {
+                eprintln!(
+                    "[rusty-claude] warning: --feed-previous-error: {e}; retrying without it"
+                );
+                None
+            }
+        },
+        _ => None,
+    };
+    let launch = Launch {
+        attempt,
+        cycle_attempt,
+        last_in_cycle,
+        fallback_left,
+        args,
+        env: attempt_env,
+        tag: attempt_tag,
+        _previous_error: previous_error,
+    };
+    if cli.verbose > 0 {
+        eprintln!(
+            "[rusty-claude] attempt {} of run {run_id}: {}",
+            attempt + 1,
+            launch.repro(real_cmd, stdin_buf.len())
+        );
+    }
+    let mut cmd = resolve::command(real_cmd, &launch.args);
+    child_env.apply(&mut cmd, &launch.env);
+    cmd.env(runid::ENV_VAR, run_id);
+
+    if interactive {
+        cmd.stdin(Stdio::inherit())
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit());
+    } else {
+        cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+        if stdin_buf.is_empty() && cli.stdin_file.is_some() {
+            cmd.stdin(Stdio::null());
+        } else if stdin_buf.is_empty() {
+            cmd.stdin(Stdio::inherit());
+        } else {
+            cmd.stdin(Stdio::piped());
+        }
+    }
+    // Under --pty the slave replaces the inherited stdio
+    #[cfg(unix)]
+    let session = if run.use_pty {
+        let pty = pty::open()?;
+        pty.attach(&mut cmd)?;
+        Some(pty)
+    } else {
+        None
+    };
+
+    let cpu_before = waste::children_cpu();
+    // A child that shares our terminal stays in its foreground group
+    let isolate = !(interactive || (stdin_buf.is_empty() && stdin_is_tty));
+    let mut child = match chaos::spawn(attempt + 1).and_then(|()| tree::spawn(&mut cmd, isolate)) {
+        Ok(c) => c,
+        Err(e) => return Ok(Step::Stop(spawn_failed(real_cmd, &e, attempt + 1))),
+    };
+
+    if interactive {
+        // In interactive mode, just wait and return child's exit code
+        #[cfg(unix)]
+        let (status, captured) = match session {
+            Some(pty) => {
+                // Only the child may hold the slave, or the relay never sees it close
+                drop(cmd);
+                let capture = run.mode == (Mode::Pty { capture: true });
+                let (status, output) = pty.run(&mut child, run.injection.as_ref(), capture)?;
+                (status, capture.then_some(output))
+            }
+            None => (child.wait()?, None),
+        };
+        #[cfg(not(unix))]
+        let (status, captured) = (child.wait()?, None::<Vec<u8>>);
+        return interactive_attempt(run, progress, supervisor, launch, status, captured);
+    }
+    let ran = piped_attempt(run, progress, supervisor, &launch, child, cpu_before)?;
+    dispose(run, progress, supervisor, launch, ran)
+}
+
+/// Judge an interactive attempt that has exited, with what `captured` of its session under
+/// --force-tee, and relaunch it under --interactive-retry.
+fn interactive_attempt(
+    run: &Run,
+    progress: &mut Progress,
+    supervisor: &Supervisor,
+    launch: Launch,
+    status: ExitStatus,
+    captured: Option<Vec<u8>>,
+) -> io::Result<Step<Outcome>> {
+    let (cli, run_id, real_cmd) = (&run.cli, &run.run_id, &run.real_cmd);
+    let retry_regexes = &supervisor.policy().patterns;
+    let Launch {
+        attempt,
+        cycle_attempt,
+        last_in_cycle,
+        fallback_left,
+        ref args,
+        ..
+    } = launch;
+    let Progress {
+        waste,
+        matched,
+        hook_env,
+        arg_set,
+        cycle_start,
+        previous_wait,
+        ..
+    } = progress;
+    if status.success() {
+        return Ok(Step::Stop(child_outcome(
+            Reason::Success,
+            Some(status),
+            attempt,
+            cli,
+        )));
+    }
+
+    // Under --force-tee the captured session decides like a piped attempt would
+    let mut judged = RetryDecision::default();
+    if let Some(output) = &captured {
+        let decision = should_retry(
+            Output::Merged(&String::from_utf8_lossy(output)),
+            status.code(),
+            cli.retry_on_any_error,
+            retry_regexes,
+            cli.match_timeout,
+        );
+        if let Some(pattern) = &decision.matched {
+            matched.push((pattern.clone(), decision.class));
+        }
+        if let Some(pattern) = &decision.fatal {
+            eprintln!(
+                "[rusty-claude] interactive session output matched fatal pattern \
+                `{pattern}`; not relaunching"
+            );
+            return Ok(Step::Stop(with_tally(
+                child_outcome(Reason::Fatal, Some(status), attempt, cli),
+                waste,
+                matched,
+                cli,
+            )));
+        }
+        if !decision.retry {
+            if !cli.quiet {
+                let why = if decision.no_retry_code {
+                    "is in --no-retry-exit-codes"
+                } else {
+                    "and its output matched no retry pattern"
+                };
+                eprintln!(
+                    "[rusty-claude] interactive session exited with code {} {why}; not \
+                    relaunching",
+                    code_label(status.code())
+                );
+            }
+            return Ok(Step::Stop(with_tally(
+                child_outcome(Reason::NotRetryable, Some(status), attempt, cli),
+                waste,
+                matched,
+                cli,
+            )));
+        }
+        judged = decision;
+    }
+    let child_cmd = shellquote::command_line(real_cmd, args);
+    let hook_ctx = |attempt, next_delay_ms| hook::Context {
+        attempt,
+        run_id,
+        code: status.code(),
+        class: judged.class,
+        matched: judged.matched.as_deref(),
+        next_delay_ms,
+        child_cmd: &child_cmd,
+    };
+    if !cli.interactive_retry {
+        if !cli.quiet {
+            eprintln!(
+                "[rusty-claude] interactive session exited with code {}; not relaunching \
+                (pass --interactive-retry to start a new session)",
+                code_label(status.code())
+            );
+        }
+        return Ok(Step::Stop(with_tally(
+            child_outcome(Reason::NotRetryable, Some(status), attempt, cli),
+            waste,
+            matched,
+            cli,
+        )));
+    }
+    if last_in_cycle && !fallback_left {
+        exhausted_hook(cli, &hook_ctx(attempt + 1, None));
+        let outcome = child_outcome(Reason::Exhausted, Some(status), attempt, cli);
+        if !cli.quiet {
+            disposition(&outcome, status);
+        }
+        return Ok(Step::Stop(with_tally(outcome, waste, matched, cli)));
+    }
+    // A fallback set starts a cycle of its own, from the shortest wait
+    let backoff_attempt = if last_in_cycle {
+        fall_back(cli, arg_set);
+        *cycle_start = attempt + 1;
+        *previous_wait = None;
+        0
+    } else {
+        cycle_attempt
+    };
+    // Always leave time to read the banner and abort
+    let wait = supervisor.backoff_ms(backoff_attempt, *previous_wait);
+    *previous_wait = Some(wait);
+    let wait = wait.max(INTERACTIVE_RETRY_GRACE_MS);
+    eprintln!(
+        "[rusty-claude] previous session exited with code {}; starting a new session in {}, \
+        Ctrl-C to abort",
+        code_label(status.code()),
+        duration::format_duration(Duration::from_millis(wait))
+    );
+    // The hook's time counts toward the wait it was told about
+    let hook_started = Instant::now();
+    *hook_env = retry_hook(cli, &hook_ctx(attempt + 2, Some(wait)));
+    let wait = Duration::from_millis(wait).saturating_sub(hook_started.elapsed());
+    thread::sleep(wait);
+    summary::slept(wait);
+    Ok(Step::Retry)
+}
+
+/// Tee a piped attempt's output and wait for it to end, or for the wait loop to end it.
+fn piped_attempt(
+    run: &mut Run,
+    progress: &mut Progress,
+    supervisor: &Supervisor,
+    launch: &Launch,
+    mut child: Child,
+    cpu_before: Option<Duration>,
+) -> io::Result<Ran> {
+    let Run {
+        ref cli,
+        ref run_id,
+        ref real_cmd,
+        ref stdin_buf,
+        heartbeat,
+        attempt_timeout,
+        idle_timeout,
+        first_output_timeout,
+        ref annotator,
+        ref events,
+        ref observers,
+        ref title,
+        ref mut artifacts,
+        auto_resume,
+        ..
+    } = *run;
+    let retry_regexes = &supervisor.policy().patterns;
+    let Launch {
+        attempt,
+        last_in_cycle,
+        fallback_left,
+        ref args,
+        tag: ref attempt_tag,
+        ..
+    } = *launch;
+    let Progress {
+        ref mut total_output,
+        arg_set,
+        ..
+    } = *progress;
+    title.set(title::State::Running {
+        attempt: attempt + 1,
+    });
+    let stdout = child.stdout.take().unwrap();
+    let stderr = child.stderr.take().unwrap();
+
+    annotator.group_start(attempt + 1);
+    events.emit(
+        "attempt_start",
+        serde_json::json!({
+            "attempt": attempt + 1,
+            "pid": child.id(),
+            "tag": attempt_tag,
+            "fallback": (arg_set > 0).then_some(arg_set),
+        }),
+    );
+    let activity = Arc::new(Activity::new(
+        cli.forward_late_output,
+        cli.buffer_output.then_some(cli.buffer_limit),
+    ));
+    let tap = |stream| {
+        observers.as_ref().map(|hub| Tap {
+            hub: Arc::clone(hub),
+            attempt: attempt + 1,
+            stream,
+        })
+    };
+    let files = artifacts.as_mut().and_then(|a| {
+        let mut start = serde_json::Map::new();
+        start.insert("run_id".into(), run_id.clone().into());
+        start.insert("attempt".into(), (attempt + 1).into());
+        start.insert("cmd".into(), real_cmd.clone().into());
+        start.insert("args".into(), shellquote::redacted(args).into());
+        start.insert("pid".into(), child.id().into());
+        start.insert("tag".into(), attempt_tag.clone().into());
+        start.insert("fallback".into(), (arg_set > 0).then_some(arg_set).into());
+        start.insert("stdin_bytes".into(), stdin_buf.len().into());
+        start.insert("started_at_ms".into(), artifacts::unix_ms().into());
+        a.begin(attempt + 1, start)
+            .map_err(|e| eprintln!("[rusty-claude] warning: --attempt-artifacts: {e}"))
+            .ok()
+    });
+    let (out_file, err_file) = files.unzip();
+    // Killing the last attempt would only end it sooner, with nothing to retry into
+    let stream_match = (cli.stream_match && (!last_in_cycle || fallback_left))
+        .then(|| Arc::new(StreamMatch::new(retry_regexes)));
+    // The capture may lose the init line with the session id, so stdout is watched for it
+    let session_watch = auto_resume.then(|| Arc::new(resume::Watch::default()));
+    // The last attempt's match text is gone by now
+    memory::set(memory::Buffer::Text, 0);
+    let stdout_handle = tee_reader(
+        stdout,
+        io::stdout(),
+        memory::Capture::new(memory::Buffer::Stdout, cli.buffer_output),
+        Arc::clone(&activity),
+        tap("stdout"),
+        out_file,
+        Lookout {
+            stream_match: stream_match
+                .clone()
+                .filter(|_| cli.match_streams != MatchStreams::Stderr),
+            session: session_watch.clone(),
+        },
+    );
+    let stderr_handle = tee_reader(
+        stderr,
+        io::stderr(),
+        memory::Capture::new(memory::Buffer::Stderr, cli.buffer_stderr),
+        Arc::clone(&activity),
+        tap("stderr"),
+        err_file,
+        Lookout {
+            stream_match: stream_match
+                .clone()
+                .filter(|_| cli.match_streams != MatchStreams::Stdout),
+            ..Lookout::default()
+        },
+    );
+
+    // If we captured stdin, replay it alongside the output draining
+    let stdin_handle = child
+        .stdin
+        .take()
+        .map(|child_stdin| stdin_writer(child_stdin, stdin_buf.clone(), Arc::clone(&activity)));
+
+    let warning = attempt_timeout
+        .zip(cli.timeout_warning)
+        .map(|(timeout, lead)| TimeoutWarning {
+            at: timeout.saturating_sub(lead),
+            timeout,
+            signal: cli.timeout_warning_signal,
+            attempt: attempt + 1,
+        });
+    interrupt::watch(child.id());
+    let Waited {
+        status,
+        warned_at,
+        killed,
+    } = wait_child(
+        &mut child,
+        &activity,
+        heartbeat,
+        Deadlines {
+            timeout: attempt_timeout,
+            idle: idle_timeout,
+            first_output: first_output_timeout,
+        },
+        warning.as_ref(),
+        stream_match.as_deref(),
+        events,
+    )?;
+    interrupt::unwatch();
+    // What a failed attempt started would only get in the next one's way
+    if killed.is_none() && !status.success() {
+        let _ = tree::kill_tree(&mut child);
+    }
+    tree::release(&child);
+    let attempt_wall = activity.started.elapsed();
+    let attempt_cpu = waste::children_cpu()
+        .zip(cpu_before)
+        .map(|(after, before)| after.saturating_sub(before));
+
+    // Teardown order: settle the stdin replay, collect everything the child wrote, then
+    // classify on its exit status, so a child that quit before reading its input is
+    // reported by its own error and code rather than by our failed write
+    settle_stdin(stdin_handle);
+    let out = stdout_handle
+        .join()
+        .unwrap_or_else(|_| Ok(memory::Capture::new(memory::Buffer::Stdout, false)))?;
+    let err = stderr_handle
+        .join()
+        .unwrap_or_else(|_| Ok(memory::Capture::new(memory::Buffer::Stderr, false)))?;
+    let (out_buf, err_buf) = (out.bytes(), err.bytes());
+    annotator.group_end(attempt + 1);
+    // A child can exit before the wait loop sees it pass the limit
+    let killed = killed.or(activity.over_buffer_limit().then_some(Killed::BufferFull));
+    *total_output += activity.bytes.load(Ordering::Relaxed);
+    let combined_text = {
+        let mut s = String::from_utf8_lossy(out_buf).to_string();
+        s.push('\n');
+        s.push_str(&String::from_utf8_lossy(err_buf));
+        s
+    };
+    memory::set(memory::Buffer::Text, combined_text.len() as u64);
+    for (stream, capture) in [("stdout", &out), ("stderr", &err)] {
+        if capture.spilled() && cli.verbose > 0 {
+            eprintln!(
+                "[rusty-claude] --self-mem-limit: held back the attempt's {stream} in a \
+                temp file"
+            );
+        }
+        if capture.dropped() > 0 && !capture.over_mem_limit() {
+            if cli.verbose > 0 {
+                eprintln!(
+                    "[rusty-claude] --max-capture-bytes: matching on the last {} of the \
+                    attempt's {} {stream}",
+                    format_size(capture.bytes().len() as u64),
+                    format_size(capture.len())
+                );
+            }
+        } else if capture.dropped() > 0 {
+            eprintln!(
+                "[rusty-claude] warning: --self-mem-limit: kept only the last {} of the \
+                attempt's {} {stream} for retry matching",
+                format_size(capture.bytes().len() as u64),
+                format_size(capture.len())
+            );
+        }
+    }
+    if cli.verbose > 1 {
+        eprintln!("[rusty-claude] memory: {}", memory::render());
+    }
+
+    if let Some(killed) = killed {
+        match killed {
+            Killed::Timeout => eprintln!(
+                "[rusty-claude] attempt timed out after {}",
+                format_duration(attempt_timeout.unwrap_or_default())
+            ),
+            Killed::Idle(idle) => eprintln!(
+                "[rusty-claude] attempt stalled: no output for {} after {} of output; killed it",
+                format_duration(idle),
+                format_size(activity.bytes.load(Ordering::Relaxed))
+            ),
+            Killed::NoOutput => eprintln!(
+                "[rusty-claude] no output within {}; killed the attempt",
+                format_duration(first_output_timeout.unwrap_or_default())
+            ),
+            Killed::Aborted => eprintln!("[rusty-claude] aborting; killed the attempt"),
+            Killed::Matched(idx) => eprintln!(
+                "[rusty-claude] retry pattern `{}` matched mid-stream; killed the attempt",
+                retry_regexes.regexes[idx].as_str()
+            ),
+            Killed::BufferFull => eprintln!(
+                "[rusty-claude] error: the attempt's output passed --buffer-limit {} under \
+            --buffer-output; stopped it and discarded the output",
+                format_size(cli.buffer_limit)
+            ),
+        }
+        let late = activity.late_bytes.load(Ordering::Relaxed);
+        if late > 0 {
+            eprintln!(
+                "[rusty-claude] held back {} the child wrote after it was killed \
+            (--forward-late-output passes it through)",
+                format_size(late)
+            );
+        }
+    }
+    Ok(Ran {
+        status,
+        warned_at,
+        killed,
+        activity,
+        out,
+        err,
+        combined_text,
+        wall: attempt_wall,
+        cpu: attempt_cpu,
+        session_watch,
+    })
+}
+
+/// Judge a piped attempt that has ended: stop the run with its outcome, or wait out the
+/// backoff and retry.
+fn dispose(
+    run: &mut Run,
+    progress: &mut Progress,
+    supervisor: &Supervisor,
+    launch: Launch,
+    ran: Ran,
+) -> io::Result<Step<Outcome>> {
+    let Run {
+        ref cli,
+        started,
+        ref run_id,
+        ref real_cmd,
+        ref stdin_buf,
+        ref guards,
+        ref annotator,
+        ref success_pattern,
+        ref events,
+        time_budget,
+        ref title,
+        ref mut decision_trace,
+        ref mut artifacts,
+        ..
+    } = *run;
+    let retry_regexes = &supervisor.policy().patterns;
+    let Launch {
+        attempt,
+        cycle_attempt,
+        last_in_cycle,
+        fallback_left,
+        ref args,
+        ..
+    } = launch;
+    let Progress {
+        total_output,
+        class_budget,
+        previous_wait,
+        waste,
+        matched,
+        ..
+    } = progress;
+    let Ran {
+        status,
+        warned_at,
+        killed,
+        ref activity,
+        ref out,
+        ref err,
+        wall: attempt_wall,
+        ..
+    } = ran;
+    let code = status.code();
+    let timed_out = killed == Some(Killed::Timeout);
+    let (out_buf, err_buf) = (out.bytes(), err.bytes());
+    let recorded = Recorded {
+        out: out_buf,
+        err: err_buf,
+        code,
+        killed,
+    };
+    let verdict = match judge(&recorded, success_pattern.as_ref(), cli, retry_regexes) {
+        // The pattern that stopped the attempt is the one it is retried for
+        Verdict::Failure(decision) if decision.fatal.is_none() => match killed {
+            Some(Killed::Matched(idx)) => Verdict::Failure(RetryDecision {
+                matched: Some(retry_regexes.regexes[idx].as_str().to_string()),
+                class: retry_regexes.classes[idx],
+                min_delay_ms: retry_regexes.min_delays[idx].max(decision.min_delay_ms),
+                ..decision
+            }),
+            _ => Verdict::Failure(decision),
+        },
+        verdict => verdict,
+    };
+    let succeeded = matches!(verdict, Verdict::Success);
+    let finish_artifacts = |artifacts: &mut Option<artifacts::Artifacts>, outcome| {
+        log_file::attempt(attempt + 1, real_cmd, args, &outcome);
+        summary::attempt(attempt + 1, &outcome);
+        if let Some(Err(e)) = artifacts.as_mut().map(|a| a.finish(succeeded, outcome)) {
+            eprintln!("[rusty-claude] warning: --attempt-artifacts: {e}");
+        }
+    };
+
+    let record_decision = |trace: &mut Option<trace::Trace>, decision: trace::Decision| {
+        if let Some(Err(e)) = trace.as_mut().map(|t| t.record(&decision)) {
+            eprintln!("[rusty-claude] warning: --decision-trace: {e}");
+        }
+    };
+    let inputs = trace::Decision {
+        attempt: attempt + 1,
+        code,
+        runtime_ms: attempt_wall.as_millis() as u64,
+        output_bytes: activity.bytes.load(Ordering::Relaxed),
+        retries_left: cli.max_retries - cycle_attempt,
+        budget_left_ms: time_budget.map(|b| b.remaining(Instant::now()).as_millis() as u64),
+        total_output_bytes: *total_output,
+        retry_on_any_error: cli.retry_on_any_error,
+        ..trace::Decision::default()
+    };
+
+    let Verdict::Failure(mut decision) = verdict else {
+        record_decision(
+            decision_trace,
+            trace::Decision {
+                action: Some(trace::Action::Success),
+                heuristics: (code != Some(0))
+                    .then(|| "success-pattern".to_string())
+                    .into_iter()
+                    .collect(),
+                ..inputs
+            },
+        );
+        if code != Some(0) && !cli.quiet {
+            eprintln!(
+                "[rusty-claude] --success-pattern matched; treating exit code {} as success",
+                code_label(code)
+            );
+        }
+        events.emit(
+            "attempt_end",
+            serde_json::json!({ "attempt": attempt + 1, "code": code, "retry": false }),
+        );
+        finish_artifacts(
+            artifacts,
+            serde_json::json!({
+                "code": code,
+                "duration_ms": activity.started.elapsed().as_millis() as u64,
+                "stdout_bytes": out.len(),
+                    "stderr_bytes": err.len(),
+                "timeout_warning_ms": warned_at.map(|d| d.as_millis() as u64),
+                "retry": false,
+            }),
+        );
+        release_buffered(out, err)?;
+        // Success: exit 0, which the child's code matches unless --success-pattern decided
+        title.set(title::State::Done {
+            success: true,
+            attempts: attempt + 1,
+        });
+        return Ok(Step::Stop(with_tally(
+            child_outcome(Reason::Success, Some(status), attempt, cli),
+            waste,
+            matched,
+            cli,
+        )));
+    };
+
+    cap_retry_after(&mut decision, cli);
+    // Only a retry owed to --retry-on-any-error is vetoed, never a pattern match, a listed
+    // exit code, or a timeout
+    let guarded = (decision.retry
+        && decision.matched.is_none()
+        && decision.json_error.is_none()
+        && !decision.retry_code
+        && killed.is_none())
+    .then(|| guards.check(code, attempt_wall, &String::from_utf8_lossy(err_buf)))
+    .flatten();
+    if let Some((guard, why)) = &guarded {
+        decision.retry = false;
+        eprintln!(
+            "[rusty-claude] not retrying under --retry-on-any-error: {why} \
+            (--no-retry-guard {} retries anyway)",
+            guard.as_str()
+        );
+    }
+    if let Some(pattern) = &decision.fatal {
+        eprintln!("[rusty-claude] fatal pattern `{pattern}` matched; not retrying");
+    }
+    if let Some(error_type) = &decision.json_error {
+        let verdict = if decision.class.is_some() {
+            "retryable"
+        } else {
+            "not retryable"
+        };
+        eprintln!(
+            "[rusty-claude] JSON output reports error type `{error_type}`, which is {verdict}"
+        );
+    }
+    let success_matched = code == Some(0)
+        && cli.retry_on_success_match
+        && cli.success_pattern.is_none()
+        && decision.json_error.is_none()
+        && killed.is_none();
+    if success_matched && decision.fatal.is_none() {
+        if let Some(pattern) = &decision.matched {
+            eprintln!(
+                "[rusty-claude] attempt exited 0 but its output matched retry pattern \
+                `{pattern}`; treating it as failed (--retry-on-success-match)"
+            );
+        }
+    }
+    if decision.no_retry_code {
+        eprintln!(
+            "[rusty-claude] exit code {} is in --no-retry-exit-codes; not retrying",
+            code_label(code)
+        );
+    }
+    // So does an interrupt, whose handler has already said so, and it ends the run
+    // whatever else the attempt would have done
+    let interrupted = interrupt::received();
+    let interrupt_stopped = interrupted.is_some() && decision.retry;
+    if interrupted.is_some() {
+        decision.retry = false;
+    }
+    // A drain or abort ends the run where it would have retried
+    let halted = control::check(events).filter(|_| decision.retry);
+    if let Some(cmd) = halted {
+        decision.retry = false;
+        if killed != Some(Killed::Aborted) {
+            eprintln!("[rusty-claude] not retrying: {cmd} requested");
+        }
+    }
+
+    let failed = title::State::Done {
+        success: false,
+        attempts: attempt + 1,
+    };
+    let class_exhausted = if decision.retry && !last_in_cycle {
+        class_budget.take(decision.class).err()
+    } else {
+        None
+    };
+    // Out of retries with this argument set, on to the next one
+    let falling_back =
+        decision.retry && (last_in_cycle || class_exhausted.is_some()) && fallback_left;
+    let retry = decision.retry && ((!last_in_cycle && class_exhausted.is_none()) || falling_back);
+    if let Some(pattern) = &decision.matched {
+        matched.push((pattern.clone(), decision.class));
+    }
+    let matched_line = decision
+        .matched_line
+        .and_then(|n| utf8::line_from_end(out_buf, err_buf, n));
+    let with_excerpt = |mut fields: serde_json::Value| {
+        if let (Some(line), Some(map)) = (matched_line, fields.as_object_mut()) {
+            utf8::embed(map, "matched_line", line, cli.strict_utf8);
+        }
+        fields
+    };
+    events.emit(
+        "attempt_end",
+        with_excerpt(serde_json::json!({
+            "attempt": attempt + 1,
+            "code": code,
+            "retry": retry,
+            "timed_out": timed_out,
+            "stalled": matches!(killed, Some(Killed::Idle(_))),
+            "no_output": killed == Some(Killed::NoOutput),
+            "matched": decision.matched,
+            "matched_stream": decision.stream.map(Stream::as_str),
+            "class": decision.class.map(ErrorClass::as_str),
+            "guard": guarded.as_ref().map(|(g, _)| g.as_str()),
+            "fatal": decision.fatal,
+            "json_error": decision.json_error,
+        })),
+    );
+    // The wait, and for -v what it came from
+    let (wait, wait_from) = match (decision.retry_after_ms, killed) {
+        (Some(ms), _) => (ms, "Retry-After".to_string()),
+        // A child that never got going is retried on its own, shorter delay
+        (None, Some(Killed::NoOutput)) => (
+            cli.first_output_retry_delay.as_millis() as u64,
+            "--first-output-retry-delay".to_string(),
+        ),
+        // A fallback set starts a cycle of its own, from the shortest wait
+        (None, _) if falling_back => (
+            supervisor.backoff_ms(0, None),
+            backoff(cli).describe(0, None),
+        ),
+        (None, _) => {
+            let default_ms = supervisor.backoff_ms(cycle_attempt, *previous_wait);
+            let default_from = backoff(cli).describe(cycle_attempt, *previous_wait);
+            match cli.delay_cmd.as_deref().filter(|_| retry) {
+                Some(cmd) => {
+                    let ctx = delay_cmd::Context {
+                        attempt: attempt + 1,
+                        run_id,
+                        code,
+                        class: decision.class,
+                        matched: decision.matched.as_deref(),
+                        elapsed: started.elapsed(),
+                        budget_left: time_budget.map(|b| b.remaining(Instant::now())),
+                        default_ms,
+                    };
+                    match delay_cmd::delay_ms(cmd, &ctx, cli.delay_cmd_timeout) {
+                        Ok(ms) => (ms, "--delay-cmd".to_string()),
+                        Err(e) => {
+                            eprintln!(
+                                "[rusty-claude] warning: --delay-cmd {e}; using the \
+                                built-in backoff"
+                            );
+                            (default_ms, default_from)
+                        }
+                    }
+                }
+                None => (default_ms, default_from),
+            }
+        }
+    };
+    // The matched line's minimum delay outlasts a shorter wait, whatever it came from
+    let (wait, wait_from) = match decision.min_delay_ms.filter(|&floor| floor > wait) {
+        Some(floor) => (
+            floor,
+            format!(
+                "the minimum delay of the retry patterns matching the line of `{}` \
+                (over {wait}ms from {wait_from})",
+                decision.matched.as_deref().unwrap_or_default()
+            ),
+        ),
+        None => (wait, wait_from),
+    };
+    if cli.verbose > 0 {
+        if let Some(pattern) = &decision.matched {
+            let source = retry_regexes.source(pattern);
+            let class = decision
+                .class
+                .map_or(String::new(), |c| format!(", {}", c.as_str()));
+            let line = matched_line.map(utf8::excerpt).unwrap_or_default();
+            let stream = decision.stream.map_or("output", Stream::as_str);
+            eprintln!(
+                "[rusty-claude] attempt {} matched retry pattern `{pattern}` \
+                ({source}{class}) in its {stream}: {line}",
+                attempt + 1
+            );
+        }
+        if let Some(ms) = decision.retry_after_ms {
+            eprintln!(
+                "[rusty-claude] the output asks to retry after {}",
+                format_duration(Duration::from_millis(ms))
+            );
+        }
+        if retry {
+            eprintln!("[rusty-claude] waiting {wait}ms, from {wait_from}");
+        }
+    }
+    *previous_wait = Some(wait);
+    let over_output = cli.max_total_output.filter(|&l| *total_output > l);
+    let chosen_wait = Duration::from_millis(wait);
+    let within_budget = budget::check_wait(time_budget.as_ref(), chosen_wait, Instant::now());
+    let action = Stops {
+        fatal: decision.fatal.is_some(),
+        interrupted: interrupt_stopped,
+        halted,
+        retryable: decision.retry,
+        last_attempt: last_in_cycle && !falling_back,
+        class_exhausted: class_exhausted.is_some() && !falling_back,
+        over_output: over_output.is_some(),
+        over_budget: within_budget.is_err(),
+    }
+    .action();
+    let heuristics = [
+        killed.map(|k| k.as_str().to_string()),
+        guarded
+            .as_ref()
+            .map(|(g, _)| format!("guard:{}", g.as_str())),
+        decision.scan_timed_out.then(|| "scan-timeout".to_string()),
+        decision.retry_code.then(|| "retry-exit-code".to_string()),
+        success_matched.then(|| "success-match".to_string()),
+        decision
+            .no_retry_code
+            .then(|| "no-retry-exit-code".to_string()),
+        decision
+            .json_error
+            .as_ref()
+            .map(|t| format!("json-error:{t}")),
+    ];
+    record_decision(
+        decision_trace,
+        trace::Decision {
+            action: Some(action),
+            class: decision.class,
+            matched: decision
+                .matched
+                .as_deref()
+                .map(|p| provenance(retry_regexes, p)),
+            fatal: decision
+                .fatal
+                .as_deref()
+                .map(|p| provenance(retry_regexes, p)),
+            heuristics: heuristics.into_iter().flatten().collect(),
+            retry_after_ms: decision.retry_after_ms,
+            wait_ms: (action == trace::Action::Retry).then_some(wait),
+            ..inputs
+        },
+    );
+    finish_artifacts(
+        artifacts,
+        with_excerpt(serde_json::json!({
+            "code": code,
+            "duration_ms": activity.started.elapsed().as_millis() as u64,
+            "stdout_bytes": out.len(),
+            "stderr_bytes": err.len(),
+            "timeout_warning_ms": warned_at.map(|d| d.as_millis() as u64),
+            "timed_out": timed_out,
+            "killed": killed.map(Killed::as_str),
+            "interrupted": interrupted.map(interrupt::name),
+            "idle_ms": match killed {
+                Some(Killed::Idle(idle)) => Some(idle.as_millis() as u64),
+                _ => None,
+            },
+            "matched": decision.matched,
+            "class": decision.class.map(ErrorClass::as_str),
+            "fatal": decision.fatal,
+            "retry": retry,
+            "delay_ms": retry.then_some(wait),
+        })),
+    );
+    // Anything but a retry makes this the final attempt, whose output is the run's
+    if action != trace::Action::Retry && killed != Some(Killed::BufferFull) {
+        release_buffered(out, err)?;
+    }
+    if decision.scan_timed_out {
+        eprintln!(
+            "[rusty-claude] warning: pattern scan exceeded --match-timeout; \
+            deciding on the exit code only"
+        );
+    }
+    let child_cmd = shellquote::command_line(real_cmd, args);
+    let hook_ctx = |attempt, next_delay_ms| hook::Context {
+        attempt,
+        run_id,
+        code,
+        class: decision.class,
+        matched: decision.matched.as_deref(),
+        next_delay_ms,
+        child_cmd: &child_cmd,
+    };
+    if !decision.retry || (last_in_cycle && !falling_back) {
+        // Retryable, with no attempts left
+        if decision.retry {
+            exhausted_hook(cli, &hook_ctx(attempt + 1, None));
+        }
+        annotator.error(&format!(
+            "claude failed after {} attempt(s) (code={:?})",
+            attempt + 1,
+            code
+        ));
+        if !cli.quiet {
+            eprintln!(
+                "[rusty-claude] reproduce with: {}",
+                launch.repro(real_cmd, stdin_buf.len())
+            );
+        }
+        title.set(failed);
+        if let Some(sig) = interrupted {
+            return Ok(Step::Stop(with_tally(
+                interrupted_outcome(sig, code, attempt),
+                waste,
+                matched,
+                cli,
+            )));
+        }
+        if halted == Some(control::Command::Abort) {
+            let outcome = Outcome {
+                child_code: code,
+                ..Outcome::wrapper(Reason::Aborted, exit_codes::INTERRUPTED, attempt + 1)
+            };
+            return Ok(Step::Stop(with_tally(outcome, waste, matched, cli)));
+        }
+        let killed_outcome = match killed {
+            Some(Killed::Timeout) => Some((Reason::AttemptTimeout, cli.timeout_exit_code)),
+            Some(Killed::Idle(_)) => Some((Reason::Stalled, cli.stall_exit_code)),
+            Some(Killed::NoOutput) => Some((Reason::NoOutput, cli.stall_exit_code)),
+            Some(Killed::Aborted) => Some((Reason::Aborted, exit_codes::INTERRUPTED)),
+            Some(Killed::BufferFull) => Some((Reason::BufferLimit, exit_codes::BUFFER_LIMIT)),
+            // Killed for a pattern match, it ends like an attempt that exited with one
+            Some(Killed::Matched(_)) | None => None,
+        };
+        if let Some((reason, exit_code)) = killed_outcome {
+            return Ok(Step::Stop(with_tally(
+                Outcome::wrapper(reason, exit_code, attempt + 1),
+                waste,
+                matched,
+                cli,
+            )));
+        }
+        // Final failure: exit with the child's code
+        let reason = if halted.is_some() {
+            Reason::Drained
+        } else if decision.retry {
+            Reason::Exhausted
+        } else if decision.fatal.is_some() {
+            Reason::Fatal
+        } else {
+            Reason::NotRetryable
+        };
+        let outcome = child_outcome(reason, Some(status), attempt, cli);
+        if !cli.quiet {
+            disposition(&outcome, status);
+        }
+        return Ok(Step::Stop(with_tally(outcome, waste, matched, cli)));
+    }
+    if let (Some(limit), Some(class), false) = (class_exhausted, decision.class, falling_back) {
+        let msg = format!(
+            "{class} retry budget exhausted ({limit} retries per --class-budget) \
+            after {} attempt(s); not retrying",
+            attempt + 1
+        );
+        eprintln!("[rusty-claude] {msg}");
+        annotator.error(&msg);
+        exhausted_hook(cli, &hook_ctx(attempt + 1, None));
+        if !cli.quiet {
+            eprintln!(
+                "[rusty-claude] reproduce with: {}",
+                launch.repro(real_cmd, stdin_buf.len())
+            );
+        }
+        title.set(failed);
+        let outcome = Outcome {
+            exhausted_class: Some(class),
+            ..child_outcome(Reason::Exhausted, Some(status), attempt, cli)
+        };
+        return Ok(Step::Stop(with_tally(outcome, waste, matched, cli)));
+    }
+    if let Some(limit) = over_output {
+        let msg = format!(
+            "output budget exceeded: {} forwarded across {} attempt(s) (limit {}); not retrying",
+            format_size(*total_output),
+            attempt + 1,
+            format_size(limit)
+        );
+        eprintln!("[rusty-claude] {msg}");
+        annotator.error(&msg);
+        title.set(failed);
+        let outcome = Outcome {
+            child_code: code,
+            ..Outcome::wrapper(Reason::OutputLimit, exit_codes::OUTPUT_LIMIT, attempt + 1)
+        };
+        return Ok(Step::Stop(with_tally(outcome, waste, matched, cli)));
+    }
+    if let Err(give_up) = within_budget {
+        let msg = format!(
+            "giving up: total budget of {} exhausted after {} attempt(s); {}",
+            format_duration(time_budget.map(|b| b.total()).unwrap_or_default()),
+            attempt + 1,
+            give_up.describe(decision.retry_after_ms.is_some())
+        );
+        eprintln!("[rusty-claude] {msg}");
+        annotator.error(&msg);
+        events.emit(
+            "give_up_early",
+            serde_json::json!({
+                "attempt": attempt + 1,
+                "wait_ms": give_up.wait.as_millis() as u64,
+                "remaining_ms": give_up.remaining.as_millis() as u64,
+            }),
+        );
+        exhausted_hook(cli, &hook_ctx(attempt + 1, None));
+        if !cli.quiet {
+            eprintln!(
+                "[rusty-claude] reproduce with: {}",
+                launch.repro(real_cmd, stdin_buf.len())
+            );
+        }
+        title.set(failed);
+        let outcome = Outcome {
+            child_code: code,
+            gave_up: Some(give_up),
+            ..Outcome::wrapper(
+                Reason::TotalTimeout,
+                cli.total_timeout_exit_code,
+                attempt + 1,
+            )
+        };
+        return Ok(Step::Stop(with_tally(outcome, waste, matched, cli)));
+    }
+    back_off(run, progress, &launch, ran, &decision, wait, falling_back)
+}
+
+/// Wait out the backoff after the failed attempt `ran`, which `decision` retries after
+/// `wait_ms`, and carry what the next attempt needs over to it.
+fn back_off(
+    run: &mut Run,
+    progress: &mut Progress,
+    launch: &Launch,
+    ran: Ran,
+    decision: &RetryDecision,
+    wait_ms: u64,
+    falling_back: bool,
+) -> io::Result<Step<Outcome>> {
+    let Run {
+        ref cli,
+        ref run_id,
+        ref real_cmd,
+        ref mut stdin_buf,
+        stdin_is_tty,
+        ref annotator,
+        ref edit_pattern,
+        ref events,
+        ref title,
+        auto_resume,
+        ..
+    } = *run;
+    let Progress {
+        class_budget,
+        previous_stderr,
+        resume_session,
+        previous_wait,
+        waste,
+        matched,
+        hook_env,
+        arg_set,
+        cycle_start,
+        ..
+    } = progress;
+    let Ran {
+        status,
+        activity,
+        out,
+        err,
+        combined_text,
+        wall: attempt_wall,
+        cpu: attempt_cpu,
+        session_watch,
+        ..
+    } = ran;
+    let attempt = launch.attempt;
+    let code = status.code();
+    let chosen_wait = Duration::from_millis(wait_ms);
+    let child_cmd = shellquote::command_line(real_cmd, &launch.args);
+    let hook_ctx = |attempt, next_delay_ms| hook::Context {
+        attempt,
+        run_id,
+        code,
+        class: decision.class,
+        matched: decision.matched.as_deref(),
+        next_delay_ms,
+        child_cmd: &child_cmd,
+    };
+    waste.add(
+        attempt_wall,
+        attempt_cpu,
+        activity.bytes.load(Ordering::Relaxed),
+    );
+
+    annotator.warning(&format!(
+        "attempt {} failed (code={:?}, matched {}); retrying in {}",
+        attempt + 1,
+        code,
+        decision
+            .matched
+            .as_deref()
+            .map_or_else(|| "no pattern".to_string(), |p| format!("`{p}`")),
+        format_duration(chosen_wait)
+    ));
+    if !cli.quiet {
+        eprintln!(
+            "[rusty-claude] attempt={} failed (code={:?}); retrying in {}",
+            attempt + 1,
+            code,
+            format_duration(chosen_wait)
+        );
+    }
+    if falling_back {
+        fall_back(cli, arg_set);
+        *cycle_start = attempt + 1;
+        *previous_wait = None;
+        *class_budget = cli.class_budget.clone().unwrap_or_default();
+    }
+    if auto_resume {
+        // The end of the capture has the latest id; the watch has one the cut dropped
+        *resume_session =
+            resume::session_id(&String::from_utf8_lossy(out.bytes()), cli.json_errors)
+                .or_else(|| session_watch.as_ref().and_then(|w| w.last()));
+        if resume_session.is_none() && !cli.quiet {
+            eprintln!(
+                "[rusty-claude] --auto-resume: attempt {} printed no session id; the next \
+                attempt starts over",
+                attempt + 1
+            );
+        }
+    }
+    drop(out);
+    let kept = err.into_bytes();
+    memory::set(memory::Buffer::PreviousStderr, kept.len() as u64);
+    *previous_stderr = Some(kept);
+    let why = match (decision.class, code) {
+        (Some(class), _) => class.to_string(),
+        (None, code) => format!("exit {}", code_label(code)),
+    };
+    title.set(title::State::Waiting {
+        delay: chosen_wait,
+        why: &why,
+    });
+    let mut wait = chosen_wait;
+    if let Some(pattern) = edit_pattern.as_ref().filter(|_| !stdin_is_tty) {
+        if edit::wanted(pattern.as_ref(), &combined_text) {
+            let editing = Instant::now();
+            match stdin_buf.to_vec().and_then(|input| edit::edit(&input)) {
+                Ok(Some(edited)) => {
+                    if !cli.quiet {
+                        eprintln!(
+                            "[rusty-claude] input edited; replaying {} from now on",
+                            format_size(edited.len() as u64)
+                        );
+                    }
+                    memory::set(memory::Buffer::Stdin, edited.len() as u64);
+                    *stdin_buf = spool::Input::from(edited);
+                }
+                Ok(None) => {
+                    if !cli.quiet {
+                        eprintln!("[rusty-claude] input not changed; replaying the original");
+                    }
+                }
+                Err(e) => eprintln!(
+                    "[rusty-claude] warning: --edit-on-retry: {e}; replaying the original input"
+                ),
+            }
+            // Time spent in the editor counts toward the backoff
+            wait = wait.saturating_sub(editing.elapsed());
+        }
+    }
+    // Before the backoff, so the delay it is told is the one waited; its time counts
+    // toward it
+    let hook_started = Instant::now();
+    *hook_env = retry_hook(cli, &hook_ctx(attempt + 2, Some(wait.as_millis() as u64)));
+    wait = wait.saturating_sub(hook_started.elapsed());
+    let sleeping = Instant::now();
+    let halted_sleep = control::sleep(chaos::sleep(wait), events);
+    summary::slept(sleeping.elapsed());
+    if let Some(cmd) = halted_sleep {
+        eprintln!("[rusty-claude] not retrying: {cmd} requested during the backoff");
+        let outcome = match cmd {
+            control::Command::Drain => child_outcome(Reason::Drained, Some(status), attempt, cli),
+            control::Command::Abort => Outcome {
+                child_code: code,
+                ..Outcome::wrapper(Reason::Aborted, exit_codes::INTERRUPTED, attempt + 1)
+            },
+        };
+        return Ok(Step::Stop(with_tally(outcome, waste, matched, cli)));
+    }
+    if let Some(sig) = interrupt::received() {
+        title.set(title::State::Done {
+            success: false,
+            attempts: attempt + 1,
+        });
+        return Ok(Step::Stop(with_tally(
+            interrupted_outcome(sig, code, attempt),
+            waste,
+            matched,
+            cli,
+        )));
+    }
+    Ok(Step::Retry)
+}
+
+/// Move on to the next `--fallback-args` set once the current one's retries have run out.
+fn fall_back(cli: &Cli, arg_set: &mut usize) {
+    eprintln!(
+        "[rusty-claude] retries exhausted with the {}; falling back to the {}",
+        fallback::describe(&cli.fallback_args, *arg_set),
+        fallback::describe(&cli.fallback_args, *arg_set + 1)
+    );
+    *arg_set += 1;
+}
+
+/// Run `--on-retry-cmd` before the backoff to `ctx.attempt`, returning the variables it set
+/// for that attempt.
+fn retry_hook(cli: &Cli, ctx: &hook::Context) -> Vec<(OsString, OsString)> {
+    let Some(cmd) = cli.on_retry_cmd.as_deref() else {
+        return Vec::new();
+    };
+    match hook::run(cmd, ctx, cli.hook_timeout) {
+        Ok(result) => {
+            if let Some(failure) = result.failure {
+                eprintln!("[rusty-claude] warning: --on-retry-cmd {failure}; retrying anyway");
+            }
+            for w in &result.warnings {
+                eprintln!("[rusty-claude] warning: --on-retry-cmd env file {w}");
+            }
+            if !result.env.is_empty() && !cli.quiet {
+                let names: Vec<_> = result
+                    .env
+                    .iter()
+                    .map(|(k, _)| k.to_string_lossy())
+                    .collect();
+                eprintln!(
+                    "[rusty-claude] --on-retry-cmd set {} for attempt {}",
+                    names.join(", "),
+                    ctx.attempt
+                );
+            }
+            result.env
+        }
+        Err(e) => {
+            eprintln!("[rusty-claude] warning: --on-retry-cmd {e}; retrying anyway");
+            Vec::new()
+        }
+    }
+}
+
+/// Run `--on-exhausted-cmd` for the run's last attempt.
+fn exhausted_hook(cli: &Cli, ctx: &hook::Context) {
+    if let Some(cmd) = cli.on_exhausted_cmd.as_deref() {
+        if let Err(e) = hook::notify(cmd, ctx, cli.hook_timeout) {
+            eprintln!("[rusty-claude] warning: --on-exhausted-cmd {e}");
+        }
+    }
+}
+
+/// The command to spawn: `--cmd`, else the platform default, replaced by a discovered
+/// installation under `--auto-discover-cmd` when the default is not on PATH. On Windows a
+/// bare name is resolved through `PATH` and `PATHEXT` here, since spawning finds neither a
+/// `.cmd` shim nor a name given without its extension.
+pub fn resolve_cmd(cli: &Cli) -> String {
+    let cmd = pick_cmd(cli);
+    if cfg!(windows) && !cmd.contains(['/', '\\']) {
+        if let Some(found) = resolve::locate(&cmd) {
+            return found.to_string_lossy().into_owned();
+        }
+    }
+    cmd
+}
+
+fn pick_cmd(cli: &Cli) -> String {
+    if let Some(cmd) = &cli.cmd {
+        return cmd.clone();
+    }
+    let default = default_cmd();
+    if !cli.auto_discover_cmd
+        || resolve::find_in_path(&default, env::var_os("PATH").as_deref(), &resolve::RealFs)
+            .is_some()
+    {
+        return default;
+    }
+    match resolve::discover(&resolve::RealFs, &|k| env::var(k).ok()).first() {
+        Some(found) => {
+            if !cli.quiet {
+                eprintln!(
+                    "[rusty-claude] `{default}` is not on PATH; using {} ({})",
+                    found.path.display(),
+                    found.source
+                );
+            }
+            found.path.to_string_lossy().into_owned()
+        }
+        None => default,
+    }
+}
+
+/// Build the outcome for a run that ended with the child's exit on attempt index `attempt`.
+pub fn integrity_failure(msg: &str, attempts: u32) -> Outcome {
+    eprintln!("[rusty-claude] error: --expect-cmd-sha256: {msg}; refusing to run");
+    Outcome::wrapper(
+        Reason::IntegrityMismatch,
+        exit_codes::INTEGRITY_MISMATCH,
+        attempts,
+    )
+}
+
+/// Report a failed spawn, with install locations when the command isn't on PATH.
+pub fn spawn_failed(real_cmd: &str, e: &io::Error, attempts: u32) -> Outcome {
+    eprintln!("[rusty-claude] failed to spawn `{}`: {e}", real_cmd);
+    if e.kind() == io::ErrorKind::NotFound && !real_cmd.contains(['/', '\\']) {
+        eprintln!("[rusty-claude] {}", resolve::searched(real_cmd));
+        let candidates = resolve::discover(&resolve::RealFs, &|k| env::var(k).ok());
+        for line in resolve::not_found_diagnostic(real_cmd, &candidates) {
+            eprintln!("[rusty-claude] {line}");
+        }
+    }
+    let (reason, code) = if e.kind() == io::ErrorKind::NotFound {
+        (Reason::SpawnNotFound, exit_codes::NOT_FOUND)
+    } else {
+        (Reason::SpawnCannotExecute, exit_codes::CANNOT_EXECUTE)
+    };
+    Outcome::wrapper(reason, code, attempts)
+}
+
+/// A child exit code for messages; `None` means it was killed by a signal.
+pub fn code_label(code: Option<i32>) -> String {
+    code.map_or_else(|| "none (signal)".to_string(), |c| c.to_string())
+}
+
+/// The last line of a run that ended on a failed attempt with `status`, saying whether
+/// retrying gave up and what the run exits with.
+fn disposition(outcome: &Outcome, status: ExitStatus) {
+    let what = match outcome.reason {
+        Reason::Exhausted => format!(
+            "giving up after {} attempt(s): retries exhausted",
+            outcome.attempts
+        ),
+        Reason::NotRetryable | Reason::Fatal => {
+            format!(
+                "non-retryable failure after {} attempt(s)",
+                outcome.attempts
+            )
+        }
+        _ => return,
+    };
+    let signal = match status.code() {
+        Some(_) => String::new(),
+        None => format!(
+            ", the child was killed by signal {}",
+            exit_codes::of_status(status) - 128
+        ),
+    };
+    eprintln!(
+        "[rusty-claude] {what}; exiting with code {}{signal}",
+        outcome.exit_code
+    );
+}
+
+/// A run stopped by `sig` after `attempt`, exiting as though the wrapper died of it.
+fn interrupted_outcome(sig: i32, code: Option<i32>, attempt: u32) -> Outcome {
+    Outcome {
+        child_code: code,
+        ..Outcome::wrapper(Reason::Interrupted, interrupt::exit_code(sig), attempt + 1)
+    }
+}
+
+/// The outcome of a run that ends with the child's `status` (`None` if it never exited by
+/// itself).
+pub fn child_outcome(
+    reason: Reason,
+    status: Option<ExitStatus>,
+    attempt: u32,
+    cli: &Cli,
+) -> Outcome {
+    let code = status.and_then(|s| s.code());
+    let pattern_judged = cli.success_pattern.is_some() && !cli.server_mode;
+    let override_code = cli
+        .exhausted_exit_code
+        .filter(|_| reason == Reason::Exhausted)
+        .or_else(|| {
+            // Under --success-pattern the exit code alone no longer says how the run went
+            match reason {
+                Reason::Success if pattern_judged && code != Some(0) => Some(0),
+                // Only the JSON can fail an attempt that exited 0 without --success-pattern
+                r if cli.json_errors && r != Reason::Success && code == Some(0) => {
+                    Some(exit_codes::JSON_ERROR)
+                }
+                r if cli.retry_on_success_match
+                    && !pattern_judged
+                    && r != Reason::Success
+                    && code == Some(0) =>
+                {
+                    Some(cli.success_match_exit_code)
+                }
+                _ if pattern_judged && code == Some(0) => Some(exit_codes::NO_SUCCESS_MATCH),
+                _ => None,
+            }
+        });
+    Outcome {
+        exit_code: override_code.unwrap_or_else(|| status.map_or(1, exit_codes::of_status)),
+        reason,
+        from_wrapper: override_code.is_some(),
+        child_code: code,
+        attempts: attempt + 1,
+        exhausted_class: None,
+        waste: None,
+        matched: Vec::new(),
+        gave_up: None,
+    }
+}
+
+/// Attach the patterns the failed attempts matched and the cost of the retried ones to the
+/// final outcome, reporting the cost.
+fn with_tally(
+    outcome: Outcome,
+    waste: &waste::Waste,
+    matched: &[(String, Option<ErrorClass>)],
+    cli: &Cli,
+) -> Outcome {
+    let outcome = Outcome {
+        matched: matched.to_vec(),
+        ..outcome
+    };
+    if waste.attempts == 0 {
+        return outcome;
+    }
+    if !cli.quiet {
+        eprintln!("[rusty-claude] {}", waste.describe());
+    }
+    Outcome {
+        waste: Some(waste.clone()),
+        ..outcome
+    }
+}
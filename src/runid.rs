@@ -0,0 +1,49 @@
+//! The run id: one identifier carried by everything a run writes (JSON events, artifacts,
+//! the reason file, verbose logs) and exported to the child as `RUSTY_CLAUDE_RUN_ID`, so a
+//! grep for it pulls the whole story together.
+//!
+//! Generated ids are UUIDv7, which sort by creation time; `--run-id` supplies one from an
+//! orchestrator instead.
+
+/// Variable the child sees the run id in.
+pub const ENV_VAR: &str = "RUSTY_CLAUDE_RUN_ID";
+
+/// Placeholder replaced by the run id in output paths such as `--attempt-artifacts`.
+pub const PLACEHOLDER: &str = "{run_id}";
+
+/// A fresh UUIDv7: 48 bits of unix milliseconds, then random bits.
+pub fn generate() -> String {
+    let ms = crate::artifacts::unix_ms() as u128 & ((1 << 48) - 1);
+    let random = rand::random::<u128>();
+    let bits = (ms << 80)
+        | (0x7 << 76)
+        | (random & (0xfff << 64))
+        | (0b10 << 62)
+        | (random & ((1 << 62) - 1));
+    let hex = format!("{bits:032x}");
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// `--run-id` values end up in file names, so only a conservative character set is allowed.
+pub fn parse(s: &str) -> Result<String, String> {
+    if s.is_empty() || s.len() > 128 {
+        return Err("expected 1 to 128 characters".to_string());
+    }
+    if !s
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+    {
+        return Err("only letters, digits, `-`, `_` and `.` are allowed".to_string());
+    }
+    if s.starts_with('.') {
+        return Err("must not start with `.`".to_string());
+    }
+    Ok(s.to_string())
+}
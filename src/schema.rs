@@ -0,0 +1,197 @@
+//! The layout version of every JSON surface, in one place, and the golden records the
+//! self-test holds each surface to.
+//!
+//! Every record (or, on the observer socket, the `hello` frame) carries `schema`, e.g.
+//! `rusty-claude/events/1`. The promise to tooling: within one version, fields are only
+//! ever added. Removing or renaming a field, changing its type, or changing what it means
+//! is a new version: bump the number here and check in the new version's golden records
+//! under `src/schema/` next to the old ones, so the break is on purpose and visible in the
+//! diff. The `schema-compat` self-test fails on any record missing a golden field or
+//! holding it with another type, and on a version with no golden records.
+
+use serde_json::Value;
+
+/// `--json-events` lines, and the `event` frames of the observer socket.
+pub const EVENTS: &str = "rusty-claude/events/1";
+/// The observer socket's frames, announced by its `hello`.
+pub const OBSERVE: &str = "rusty-claude/observe/1";
+/// `--decision-trace` records.
+pub const TRACE: &str = "rusty-claude/trace/1";
+/// `--log-file` lines.
+pub const LOG: &str = "rusty-claude/log/1";
+/// `attempt-NN.meta.json` under `--attempt-artifacts`.
+pub const ATTEMPT_META: &str = "rusty-claude/attempt-meta/1";
+/// `--stats-sink` records.
+pub const STATS: &str = "rusty-claude/stats/1";
+/// `rusty-claude stats --json`.
+pub const STATS_REPORT: &str = "rusty-claude/stats-report/1";
+/// `--stats-json`.
+pub const SUMMARY: &str = "rusty-claude/summary/1";
+/// `--batch` results, as NDJSON lines or one file per entry.
+pub const BATCH: &str = "rusty-claude/batch/1";
+/// `rusty-claude doctor --json`.
+pub const DOCTOR: &str = "rusty-claude/doctor/1";
+/// `rusty-claude bench --json`.
+pub const BENCH: &str = "rusty-claude/bench/1";
+
+/// One surface's records as of one version.
+pub struct Golden {
+    pub schema: &'static str,
+    /// The field telling kinds of record apart, if the surface has several.
+    pub kind: Option<&'static str>,
+    /// JSON lines, one record of each kind.
+    pub records: &'static str,
+}
+
+pub const GOLDEN: &[Golden] = &[
+    Golden {
+        schema: EVENTS,
+        kind: Some("event"),
+        records: include_str!("schema/events.1.jsonl"),
+    },
+    Golden {
+        schema: OBSERVE,
+        kind: Some("type"),
+        records: include_str!("schema/observe.1.jsonl"),
+    },
+    Golden {
+        schema: TRACE,
+        kind: None,
+        records: include_str!("schema/trace.1.jsonl"),
+    },
+    Golden {
+        schema: LOG,
+        kind: Some("record"),
+        records: include_str!("schema/log.1.jsonl"),
+    },
+    Golden {
+        schema: ATTEMPT_META,
+        kind: None,
+        records: include_str!("schema/attempt-meta.1.jsonl"),
+    },
+    Golden {
+        schema: STATS,
+        kind: None,
+        records: include_str!("schema/stats.1.jsonl"),
+    },
+    Golden {
+        schema: STATS_REPORT,
+        kind: None,
+        records: include_str!("schema/stats-report.1.jsonl"),
+    },
+    Golden {
+        schema: SUMMARY,
+        kind: None,
+        records: include_str!("schema/summary.1.jsonl"),
+    },
+    Golden {
+        schema: DOCTOR,
+        kind: None,
+        records: include_str!("schema/doctor.1.jsonl"),
+    },
+    Golden {
+        schema: BATCH,
+        kind: None,
+        records: include_str!("schema/batch.1.jsonl"),
+    },
+];
+
+impl Golden {
+    pub fn parse(&self) -> Vec<Value> {
+        self.records
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .map(|l| serde_json::from_str(l).expect("golden records are valid JSON"))
+            .collect()
+    }
+
+    /// Hold `live` records of this surface to the golden ones: each golden kind that shows
+    /// up live must keep its `schema` and every field, with the same type wherever both are
+    /// non-null, in at least one of its live records. Returns how many golden records were
+    /// matched.
+    pub fn check(&self, live: &[Value]) -> Result<usize, String> {
+        let golden = self.parse();
+        if !golden.iter().any(|g| g["schema"] == self.schema) {
+            return Err(format!("no golden records for {}", self.schema));
+        }
+        let mut matched = 0;
+        for golden in golden {
+            let kind = self.kind.map(|k| (k, &golden[k]));
+            let mut candidates = live
+                .iter()
+                .filter(|r| kind.is_none_or(|(k, kind)| &r[k] == kind))
+                .peekable();
+            if candidates.peek().is_none() {
+                continue;
+            }
+            let mut first_error = None;
+            for record in candidates {
+                let result = if record.get("schema") == golden.get("schema") {
+                    same_shape(&golden, record, "")
+                } else {
+                    Err(format!(
+                        "schema is {}; bump the version only with new golden records",
+                        record.get("schema").unwrap_or(&Value::Null)
+                    ))
+                };
+                match result {
+                    Ok(()) => {
+                        first_error = None;
+                        break;
+                    }
+                    Err(e) => {
+                        first_error.get_or_insert(e);
+                    }
+                }
+            }
+            if let Some(e) = first_error {
+                let name = kind.and_then(|(_, kind)| kind.as_str()).unwrap_or("record");
+                return Err(format!("{} {name}: {e}", self.schema));
+            }
+            matched += 1;
+        }
+        Ok(matched)
+    }
+}
+
+/// Every field of `golden` is in `live`, recursively, with the same JSON type unless either
+/// is null.
+fn same_shape(golden: &Value, live: &Value, at: &str) -> Result<(), String> {
+    match (golden, live) {
+        (Value::Null, _) | (_, Value::Null) => Ok(()),
+        (Value::Object(want), Value::Object(got)) => {
+            for (key, value) in want {
+                let path = format!("{at}.{key}");
+                let Some(found) = got.get(key) else {
+                    return Err(format!("`{}` is gone", path.trim_start_matches('.')));
+                };
+                same_shape(value, found, &path)?;
+            }
+            Ok(())
+        }
+        (Value::Array(want), Value::Array(got)) => match (want.first(), got.first()) {
+            (Some(want), Some(got)) => same_shape(want, got, &format!("{at}[]")),
+            _ => Ok(()),
+        },
+        (Value::Number(_), Value::Number(_))
+        | (Value::String(_), Value::String(_))
+        | (Value::Bool(_), Value::Bool(_)) => Ok(()),
+        _ => Err(format!(
+            "`{}` was {}, now {}",
+            at.trim_start_matches('.'),
+            type_name(golden),
+            type_name(live)
+        )),
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
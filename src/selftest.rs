@@ -0,0 +1,5902 @@
+//! `rusty-claude self-test`: run the installed binary against the built-in fake child and
+//! report pass/fail per scenario. Signal handling, pipes, and exit-code propagation are
+//! platform-sensitive, so this is meant to be pasted into bug reports.
+
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::fake_child::{payload_bytes, Payload};
+
+/// Hard cap on a single scenario, so a wedged wrapper can't hang the suite.
+const CASE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// What one wrapper run produced.
+struct RunResult {
+    code: Option<i32>,
+    stdout: Vec<u8>,
+    stderr: String,
+    stderr_raw: Vec<u8>,
+    elapsed: Duration,
+    /// Attempt count from the wrapper's `--reason-file`.
+    attempts: Option<u32>,
+    /// Frames read from `--observe-socket`, if the case observed.
+    observed: String,
+    /// The observer socket file still existed after the wrapper exited.
+    socket_left: bool,
+    /// Scratch directory the wrapper ran in, for cases that inspect files it wrote.
+    dir: PathBuf,
+}
+
+struct Case {
+    name: &'static str,
+    wrapper_args: &'static [&'static str],
+    child_args: &'static [&'static str],
+    stdin: Option<Vec<u8>>,
+    /// Run with `--observe-socket` and capture what a client connected to it received.
+    observe: bool,
+    /// Supervisor variables set for this case; all others are scrubbed.
+    env: &'static [(&'static str, &'static str)],
+    check: fn(&RunResult, Option<&[u8]>) -> Result<(), String>,
+}
+
+/// Common flags keeping the suite fast while still exercising real backoff sleeps.
+const FAST: &[&str] = &[
+    "--max-retries",
+    "3",
+    "--base-delay-ms",
+    "10",
+    "--max-delay-ms",
+    "50",
+];
+
+fn expect_code(r: &RunResult, want: i32) -> Result<(), String> {
+    match r.code {
+        Some(c) if c == want => Ok(()),
+        other => Err(format!("expected exit {want}, got {other:?}")),
+    }
+}
+
+/// The pid a child started with `--grandchild grandchild.pid` wrote.
+fn grandchild(r: &RunResult) -> Result<i32, String> {
+    let path = r.dir.join("grandchild.pid");
+    fs::read_to_string(&path)
+        .ok()
+        .and_then(|text| text.trim().parse().ok())
+        .ok_or_else(|| format!("no pid in {}", path.display()))
+}
+
+/// Whether `pid` is still running (not just a zombie waiting for init) `after` from now,
+/// returning as soon as it's gone.
+#[cfg(unix)]
+fn alive_after(pid: i32, after: Duration) -> bool {
+    let zombie = || {
+        fs::read_to_string(format!("/proc/{pid}/stat"))
+            .ok()
+            .and_then(|stat| {
+                stat.rsplit_once(") ")
+                    .map(|(_, rest)| rest.starts_with('Z'))
+            })
+            .unwrap_or(false)
+    };
+    let deadline = Instant::now() + after;
+    loop {
+        // SAFETY: signal 0 only checks that the pid exists.
+        let exists = unsafe { libc::kill(pid, 0) } == 0;
+        if !exists || zombie() {
+            return false;
+        }
+        if Instant::now() >= deadline {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[cfg(not(unix))]
+fn alive_after(_: i32, _: Duration) -> bool {
+    false
+}
+
+fn kill_pid(pid: i32) {
+    #[cfg(unix)]
+    // SAFETY: killing the grandchild a case started.
+    unsafe {
+        libc::kill(pid, libc::SIGKILL);
+    }
+    #[cfg(not(unix))]
+    let _ = pid;
+}
+
+fn expect_attempts(r: &RunResult, want: u32) -> Result<(), String> {
+    match r.attempts {
+        Some(n) if n == want => Ok(()),
+        other => Err(format!("expected {want} attempt(s), got {other:?}")),
+    }
+}
+
+/// Case `name` was refused by `--require-stdin` without the child ever starting.
+fn expect_no_stdin(r: &RunResult, name: &str, why: &str) -> Result<(), String> {
+    expect_code(r, crate::exit_codes::NO_STDIN)?;
+    if !r.stderr.contains(why) {
+        return Err(format!("no `{why}` in: {}", r.stderr.trim()));
+    }
+    if r.dir.join(format!("{name}.state")).exists() {
+        return Err("the child was spawned".into());
+    }
+    Ok(())
+}
+
+/// The `timeout=` line case `name` left in its reason file.
+fn expect_timeout(r: &RunResult, name: &str, want: &str) -> Result<(), String> {
+    let reason = fs::read_to_string(r.dir.join(format!("{name}.reason"))).unwrap_or_default();
+    match reason.lines().find_map(|l| l.strip_prefix("timeout=")) {
+        Some(got) if got == want => Ok(()),
+        got => Err(format!(
+            "expected timeout={want} in the reason file, got {got:?}"
+        )),
+    }
+}
+
+/// Byte-for-byte comparison of both streams against what the fake child wrote.
+fn expect_raw(r: &RunResult, payload: Payload, bytes: u64) -> Result<(), String> {
+    expect_code(r, 0)?;
+    let (out, err) = payload_bytes(payload, bytes);
+    if r.stdout != out {
+        return Err(format!(
+            "stdout differs: {} bytes forwarded, {} written",
+            r.stdout.len(),
+            out.len()
+        ));
+    }
+    if r.stderr_raw != err {
+        return Err(format!(
+            "stderr differs: {} bytes forwarded, {} written",
+            r.stderr_raw.len(),
+            err.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Every cleanup in `want` ran exactly once, and nothing else did, according to the
+/// `--chaos release-log` file `log`; the isolated home `home` is gone.
+fn expect_released(r: &RunResult, log: &str, home: &str, want: &[&str]) -> Result<(), String> {
+    if r.dir.join(home).exists() {
+        return Err(format!("{home} was left behind"));
+    }
+    let text = fs::read_to_string(r.dir.join(log)).unwrap_or_default();
+    let mut released: Vec<&str> = text.lines().collect();
+    released.sort_unstable();
+    let mut want = want.to_vec();
+    want.sort_unstable();
+    if released != want {
+        return Err(format!("released {released:?}, want each of {want:?} once"));
+    }
+    Ok(())
+}
+
+/// The child saw a temporary HOME (and XDG dirs inside it) that is gone after the run.
+fn expect_isolated_home(r: &RunResult) -> Result<(), String> {
+    let stdout = String::from_utf8_lossy(&r.stdout);
+    let home = stdout
+        .lines()
+        .find_map(|l| l.strip_prefix("HOME="))
+        .ok_or("child printed no HOME")?;
+    if Some(Path::new(home)) == env::var_os("HOME").as_deref().map(Path::new) {
+        return Err("child saw the real HOME".into());
+    }
+    if let Some(config) = stdout
+        .lines()
+        .find_map(|l| l.strip_prefix("XDG_CONFIG_HOME="))
+    {
+        if !config.starts_with(home) {
+            return Err(format!("XDG_CONFIG_HOME {config} is outside {home}"));
+        }
+    }
+    if Path::new(home).exists() {
+        return Err(format!("{home} was not cleaned up"));
+    }
+    Ok(())
+}
+
+/// The wrapper noticed it was about to run itself and never started the child.
+fn expect_self_wrap_refused(r: &RunResult, _: Option<&[u8]>) -> Result<(), String> {
+    expect_code(r, 2)?;
+    if !r.stderr.contains("is rusty-claude itself") {
+        return Err(format!("self-wrap not refused: {}", r.stderr.trim()));
+    }
+    if !r.stdout.is_empty() {
+        return Err("the child ran anyway".into());
+    }
+    Ok(())
+}
+
+/// `--attempt-artifacts <subdir>` left exactly the files of `attempts`.
+/// The first `attempt_end` event in the case's `--json-events` file.
+fn attempt_end(r: &RunResult, file: &str) -> Result<serde_json::Value, String> {
+    let text = fs::read_to_string(r.dir.join(file)).map_err(|e| format!("{file}: {e}"))?;
+    text.lines()
+        .filter_map(|l| serde_json::from_str::<serde_json::Value>(l).ok())
+        .find(|e| e["event"] == "attempt_end")
+        .ok_or_else(|| format!("no attempt_end event in {file}"))
+}
+
+fn expect_artifacts(r: &RunResult, subdir: &str, attempts: &[u32]) -> Result<(), String> {
+    let mut found: Vec<String> = fs::read_dir(r.dir.join(subdir))
+        .map_err(|e| format!("no artifact directory: {e}"))?
+        .filter_map(|e| e.ok()?.file_name().into_string().ok())
+        .collect();
+    found.sort();
+    let want: Vec<String> = attempts
+        .iter()
+        .flat_map(|n| ["meta.json", "stderr", "stdout"].map(|ext| format!("attempt-{n:02}.{ext}")))
+        .collect();
+    if found != want {
+        return Err(format!("artifacts {found:?}, expected {want:?}"));
+    }
+    Ok(())
+}
+
+/// `rusty-claude [FLAGS] simulate` run on the case's artifact directory `subdir`; its stdout.
+fn simulate(r: &RunResult, flags: &[&str], subdir: &str) -> Result<String, String> {
+    let exe = env::current_exe().map_err(|e| format!("cannot find rusty-claude: {e}"))?;
+    let mut cmd = Command::new(exe);
+    cmd.arg("--no-config")
+        .args(flags)
+        .arg("simulate")
+        .arg("--transcript")
+        .arg(r.dir.join(subdir))
+        .stdin(Stdio::null());
+    scrub_env(&mut cmd);
+    let output = cmd
+        .output()
+        .map_err(|e| format!("cannot run simulate: {e}"))?;
+    if !output.status.success() {
+        return Err(format!(
+            "simulate failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The records a case wrote for the surface `schema`, from the files `schema-compat`
+/// names.
+fn live_records(r: &RunResult, schema: &str) -> Result<Vec<serde_json::Value>, String> {
+    let lines = |text: &str| -> Result<Vec<serde_json::Value>, String> {
+        text.lines()
+            .filter(|l| !l.trim().is_empty())
+            .map(|l| serde_json::from_str(l).map_err(|e| format!("{e} in {l}")))
+            .collect()
+    };
+    let file =
+        |name: &str| fs::read_to_string(r.dir.join(name)).map_err(|e| format!("{name}: {e}"));
+    match schema {
+        crate::schema::EVENTS => lines(&file("schema-events.jsonl")?),
+        crate::schema::OBSERVE => lines(&r.observed),
+        crate::schema::TRACE => lines(&file("schema-trace.jsonl")?),
+        crate::schema::LOG => lines(&file("schema-log.jsonl")?),
+        crate::schema::ATTEMPT_META => [
+            "schema-art/attempt-01.meta.json",
+            "schema-art/attempt-02.meta.json",
+        ]
+        .iter()
+        .map(|name| serde_json::from_str(&file(name)?).map_err(|e| format!("{name}: {e}")))
+        .collect(),
+        crate::schema::STATS => lines(&file("schema-stats.jsonl")?),
+        crate::schema::SUMMARY => serde_json::from_str(&file("schema-summary.json")?)
+            .map(|doc| vec![doc])
+            .map_err(|e| format!("schema-summary.json: {e}")),
+        crate::schema::STATS_REPORT => {
+            let exe = env::current_exe().map_err(|e| format!("cannot find rusty-claude: {e}"))?;
+            let mut cmd = Command::new(exe);
+            cmd.args(["--no-config", "stats", "--json", "--sink"])
+                .arg(r.dir.join("schema-stats.jsonl"))
+                .stdin(Stdio::null());
+            scrub_env(&mut cmd);
+            let output = cmd.output().map_err(|e| format!("cannot run stats: {e}"))?;
+            serde_json::from_slice(&output.stdout)
+                .map(|report| vec![report])
+                .map_err(|e| format!("stats --json: {e}"))
+        }
+        crate::schema::DOCTOR => {
+            let (_, stdout) = doctor(None, &["--patterns", "busy"])?;
+            serde_json::from_str(&stdout)
+                .map(|report| vec![report])
+                .map_err(|e| format!("doctor --json: {e}"))
+        }
+        crate::schema::BATCH => {
+            let exe = env::current_exe().map_err(|e| format!("cannot find rusty-claude: {e}"))?;
+            let mut cmd = Command::new(&exe);
+            cmd.args(["--no-config", "--quiet", "--allow-self-wrap", "--cmd"])
+                .arg(&exe)
+                .arg("--batch")
+                .arg(r.dir.join("batch-prompts.txt"))
+                .args(["--", "__fake-child", "succeed"])
+                .stdin(Stdio::null());
+            scrub_env(&mut cmd);
+            let output = cmd
+                .output()
+                .map_err(|e| format!("cannot run --batch: {e}"))?;
+            lines(&String::from_utf8_lossy(&output.stdout))
+        }
+        other => Err(format!("no live records for {other}")),
+    }
+}
+
+/// Run `rusty-claude --no-config --allow-self-wrap --cmd CMD FLAGS doctor --json`, CMD
+/// defaulting to this binary, returning its exit code and stdout.
+fn doctor(child: Option<&str>, flags: &[&str]) -> Result<(i32, String), String> {
+    let exe = env::current_exe().map_err(|e| format!("cannot find rusty-claude: {e}"))?;
+    let mut cmd = Command::new(&exe);
+    cmd.args(["--no-config", "--allow-self-wrap", "--cmd"])
+        .arg(child.map_or(exe.as_os_str(), std::ffi::OsStr::new))
+        .args(flags)
+        .args(["doctor", "--json"])
+        .stdin(Stdio::null());
+    scrub_env(&mut cmd);
+    let output = cmd
+        .output()
+        .map_err(|e| format!("cannot run doctor: {e}"))?;
+    Ok((
+        output.status.code().unwrap_or(-1),
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+    ))
+}
+
+/// Run `rusty-claude --no-config --cmd CMD -- -p hi` in `dir`, returning its exit code and
+/// stderr.
+fn run_cmd(child: &Path, dir: &Path) -> Result<(i32, String), String> {
+    let exe = env::current_exe().map_err(|e| format!("cannot find rusty-claude: {e}"))?;
+    let mut cmd = Command::new(exe);
+    cmd.args(["--no-config", "--cmd"])
+        .arg(child)
+        .args(["--", "-p", "hi"])
+        .current_dir(dir)
+        .stdin(Stdio::null());
+    scrub_env(&mut cmd);
+    let output = cmd
+        .output()
+        .map_err(|e| format!("cannot run rusty-claude: {e}"))?;
+    Ok((
+        output.status.code().unwrap_or(-1),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    ))
+}
+
+/// Write an executable `NAME.sh` in `dir` that runs this binary's fake child with `args`,
+/// for cases that need a `--cmd` taking no child args of its own.
+fn fake_child_script(dir: &Path, name: &str, args: &str) -> Result<PathBuf, String> {
+    let exe = env::current_exe().map_err(|e| format!("cannot find rusty-claude: {e}"))?;
+    let script = dir.join(format!("{name}.sh"));
+    let body = format!("#!/bin/sh\nexec '{}' __fake-child {args}\n", exe.display());
+    fs::write(&script, body).map_err(|e| e.to_string())?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755))
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(script)
+}
+
+/// Run `rusty-claude --no-config FLAGS` in `dir` on a terminal of its own (a PTY as stdin,
+/// stdout, and stderr, and no child args), as an interactive session starts; returns its exit
+/// code and everything the terminal showed.
+#[cfg(unix)]
+fn run_on_terminal(dir: &Path, flags: &[&std::ffi::OsStr]) -> Result<(i32, String), String> {
+    use std::os::fd::{FromRawFd, OwnedFd};
+    use std::os::unix::process::CommandExt;
+
+    let (mut master, mut slave): (libc::c_int, libc::c_int) = (-1, -1);
+    // SAFETY: the out-pointers are valid, and null name/termios/size are allowed.
+    let rc = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        )
+    };
+    if rc != 0 {
+        return Err(format!("openpty: {}", io::Error::last_os_error()));
+    }
+    // SAFETY: openpty succeeded, so both descriptors are open and owned by us.
+    let (mut master, slave) =
+        unsafe { (fs::File::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+    let exe = env::current_exe().map_err(|e| format!("cannot find rusty-claude: {e}"))?;
+    let mut cmd = Command::new(exe);
+    cmd.arg("--no-config").args(flags).current_dir(dir);
+    let stdio = || {
+        slave
+            .try_clone()
+            .map(Stdio::from)
+            .map_err(|e| e.to_string())
+    };
+    cmd.stdin(stdio()?).stdout(stdio()?).stderr(stdio()?);
+    scrub_env(&mut cmd);
+    // SAFETY: only async-signal-safe calls between fork and exec.
+    unsafe {
+        cmd.pre_exec(|| {
+            if libc::setsid() == -1 || libc::ioctl(0, libc::TIOCSCTTY as _, 0) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let mut child = cmd
+        .spawn()
+        .map_err(|e| format!("cannot run rusty-claude: {e}"))?;
+    drop(cmd);
+    drop(slave);
+    let (tx, rx) = std::sync::mpsc::channel();
+    thread::spawn(move || {
+        let mut shown = Vec::new();
+        // EIO once every slave descriptor is closed
+        let _ = master.read_to_end(&mut shown);
+        let _ = tx.send(shown);
+    });
+    let deadline = Instant::now() + CASE_TIMEOUT;
+    let status = loop {
+        match child.try_wait().map_err(|e| e.to_string())? {
+            Some(status) => break status,
+            None if Instant::now() < deadline => thread::sleep(Duration::from_millis(20)),
+            None => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err("the session did not end in time".into());
+            }
+        }
+    };
+    let shown = rx.recv_timeout(Duration::from_secs(5)).unwrap_or_default();
+    Ok((
+        status.code().unwrap_or(-1),
+        String::from_utf8_lossy(&shown).into_owned(),
+    ))
+}
+
+#[cfg(not(unix))]
+fn run_on_terminal(_: &Path, _: &[&std::ffi::OsStr]) -> Result<(i32, String), String> {
+    Err("needs a PTY".into())
+}
+
+/// A filesystem holding just these files (and the directories above them), for the `PATH`
+/// search and the install-location probes.
+struct FakeFs(&'static [&'static str]);
+
+impl crate::resolve::FsView for FakeFs {
+    fn is_file(&self, path: &Path) -> bool {
+        self.0.iter().any(|f| Path::new(f) == path)
+    }
+
+    fn read_dir(&self, path: &Path) -> Vec<PathBuf> {
+        let mut entries: Vec<PathBuf> = Vec::new();
+        for entry in self.0.iter().flat_map(|f| Path::new(f).ancestors()) {
+            if entry.parent() == Some(path) && !entries.iter().any(|e| e == entry) {
+                entries.push(entry.to_path_buf());
+            }
+        }
+        entries
+    }
+}
+
+/// Check probe hits against `(path, source)` pairs, in order.
+fn expect_candidates(
+    got: &[crate::resolve::Candidate],
+    want: &[(&str, &str)],
+) -> Result<(), String> {
+    let got: Vec<(&Path, &str)> = got.iter().map(|c| (c.path.as_path(), c.source)).collect();
+    let want: Vec<(&Path, &str)> = want.iter().map(|(p, s)| (Path::new(*p), *s)).collect();
+    if got != want {
+        return Err(format!("found {got:?}, expected {want:?}"));
+    }
+    Ok(())
+}
+
+/// `peak_buffer_bytes` from the summary line of the case's `--log-file`.
+fn peak_buffer_bytes(r: &RunResult, file: &str) -> Result<u64, String> {
+    let text = fs::read_to_string(r.dir.join(file)).map_err(|e| format!("{file}: {e}"))?;
+    text.lines()
+        .filter_map(|l| serde_json::from_str::<serde_json::Value>(l).ok())
+        .find(|l| l["record"] == "summary")
+        .and_then(|l| l["peak_buffer_bytes"].as_u64())
+        .ok_or_else(|| format!("no peak_buffer_bytes in {file}"))
+}
+
+/// Size of the `raw-large` payload; big enough to cross every buffer boundary many times.
+const RAW_LARGE_BYTES: u64 = 64 * 1024 * 1024;
+
+/// The delays `--jitter-seed SEED` gives the first `retries` retries under `FAST`.
+fn seeded_delays(seed: u64, retries: u32) -> Vec<u64> {
+    use rand::SeedableRng;
+    use rusty_claude::backoff::{Backoff, Strategy};
+
+    let backoff = Backoff {
+        strategy: Strategy::Exponential,
+        base_ms: 10,
+        cap_ms: 50,
+        multiplier: 2.0,
+    };
+    let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+    let mut previous = None;
+    (0..retries)
+        .map(|n| {
+            let ms = backoff.delay_ms(n, previous, &mut rng);
+            previous = Some(ms);
+            ms
+        })
+        .collect()
+}
+
+fn cases() -> Vec<Case> {
+    let stdin_payload: Vec<u8> = (0..1024 * 1024).map(|i| (i % 251) as u8).collect();
+    let mut cases = vec![
+        Case {
+            name: "fails-then-succeeds",
+            wrapper_args: FAST,
+            child_args: &["fails-then-succeeds", "--failures", "2"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                expect_attempts(r, 3)?;
+                if r.stdout != b"ok\n" {
+                    return Err("stdout was not exactly `ok`".into());
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "jitter-seed",
+            wrapper_args: &[
+                "-v",
+                "--jitter-seed",
+                "42",
+                "--max-retries",
+                "3",
+                "--base-delay-ms",
+                "10",
+                "--max-delay-ms",
+                "50",
+            ],
+            child_args: &["fails-then-succeeds", "--failures", "2"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                let waits: Vec<u64> = r
+                    .stderr
+                    .lines()
+                    .filter_map(|l| l.strip_prefix("[rusty-claude] waiting ")?.split_once("ms"))
+                    .filter_map(|(ms, _)| ms.parse().ok())
+                    .collect();
+                let want = seeded_delays(42, 2);
+                if waits != want {
+                    return Err(format!("waited {waits:?}, seed 42 gives {want:?}"));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "print-backoff",
+            wrapper_args: &[
+                "--print-backoff",
+                "--max-retries",
+                "3",
+                "--base-delay-ms",
+                "10",
+                "--max-delay-ms",
+                "50",
+            ],
+            child_args: &["always-fatal"],
+            stdin: None,
+            observe: false,
+            // The legacy spelling of RUSTY_CLAUDE_SEED
+            env: &[("CLAUDE_SUPERVISOR_SEED", "42")],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                let delays = seeded_delays(42, 3);
+                let mut want: String = delays
+                    .iter()
+                    .enumerate()
+                    .map(|(n, ms)| format!("retry {} (after attempt {}): {ms}ms\n", n + 1, n + 1))
+                    .collect();
+                want += &format!("total: {}ms over 3 retries\n", delays.iter().sum::<u64>());
+                let stdout = String::from_utf8_lossy(&r.stdout);
+                if stdout != want {
+                    return Err(format!("printed {stdout:?}, expected {want:?}"));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "always-fatal",
+            wrapper_args: FAST,
+            child_args: &["always-fatal"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 2)?;
+                expect_attempts(r, 1)
+            },
+        },
+        Case {
+            name: "exit-code-passthrough",
+            wrapper_args: FAST,
+            child_args: &["always-fatal", "--exit-code", "42"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| expect_code(r, 42),
+        },
+        Case {
+            name: "emits-retry-after",
+            wrapper_args: FAST,
+            child_args: &["emits-retry-after"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                expect_attempts(r, 2)?;
+                if r.elapsed < Duration::from_secs(1) {
+                    return Err(format!(
+                        "Retry-After: 1 not honored (finished in {:?})",
+                        r.elapsed
+                    ));
+                }
+                if !r.stderr.contains("retrying in 1s") {
+                    return Err(format!("wait not shown as `1s` in: {}", r.stderr.trim()));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "retry-after-over-cap",
+            wrapper_args: &[
+                "--max-retry-after-ms",
+                "500",
+                "--max-retries",
+                "3",
+                "--base-delay-ms",
+                "10",
+                "--max-delay-ms",
+                "50",
+            ],
+            child_args: &["emits-retry-after"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                expect_attempts(r, 2)?;
+                if r.elapsed >= Duration::from_secs(1) {
+                    return Err(format!(
+                        "Retry-After: 1 honored past the cap ({:?})",
+                        r.elapsed
+                    ));
+                }
+                if !r.stderr.contains("ignoring a requested wait of 1s") {
+                    return Err(format!("no cap warning in: {}", r.stderr.trim()));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "pattern-delay",
+            wrapper_args: &[
+                "-v",
+                "--max-retries",
+                "1",
+                "--base-delay-ms",
+                "10",
+                "--pattern-delay",
+                "(?i)overloaded=1500",
+            ],
+            child_args: &["emits-retry-after"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                expect_attempts(r, 2)?;
+                if r.elapsed < Duration::from_millis(1500) {
+                    return Err(format!("the minimum delay wasn't kept ({:?})", r.elapsed));
+                }
+                let line = "waiting 1500ms, from the minimum delay of the retry patterns \
+                    matching the line of `(?i)overloaded` (over 1000ms from Retry-After)";
+                if !r.stderr.contains(line) {
+                    return Err(format!(
+                        "no floor in the wait's origin: {}",
+                        r.stderr.trim()
+                    ));
+                }
+                // The built-in rate-limit patterns ship with a floor; the rest have none
+                let patterns = rusty_claude::patterns::compile_patterns([], [], true, true)?.0;
+                for (text, want) in [
+                    ("API Error: 429 rate limited", Some(30_000)),
+                    ("Too Many Requests", Some(30_000)),
+                    ("read ECONNRESET", None),
+                ] {
+                    let decision = rusty_claude::patterns::should_retry(
+                        rusty_claude::Output::Merged(text),
+                        Some(1),
+                        false,
+                        &patterns,
+                        None,
+                    );
+                    if !decision.retry || decision.min_delay_ms != want {
+                        return Err(format!("{text:?}: minimum {:?}", decision.min_delay_ms));
+                    }
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "pattern-delay-largest",
+            wrapper_args: &[
+                "-v",
+                "--max-retries",
+                "1",
+                "--base-delay-ms",
+                "10",
+                "--pattern-delay",
+                r"(?i)Too\s*Many\s*Requests=0",
+                "--pattern-delay",
+                "API Error=1200",
+            ],
+            child_args: &["sequence", "--steps", "ratelimit,ok"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                expect_attempts(r, 2)?;
+                // The built-in pattern is the match, but the line's other pattern sets the wait
+                if r.elapsed < Duration::from_millis(1200) {
+                    return Err(format!("the larger minimum wasn't kept ({:?})", r.elapsed));
+                }
+                if !r.stderr.contains(
+                    "waiting 1200ms, from the minimum delay of the retry patterns matching \
+                    the line of `(?i)Too\\s*Many\\s*Requests`",
+                ) {
+                    return Err(format!(
+                        "no floor in the wait's origin: {}",
+                        r.stderr.trim()
+                    ));
+                }
+                let user = [("rate.?limit".to_string(), "--pattern-delay".to_string())];
+                let mut patterns =
+                    rusty_claude::patterns::compile_patterns(user, [], true, true)?.0;
+                patterns.set_min_delay("rate.?limit", 60_000);
+                patterns.set_min_delay(r"(?i)\b429\b", 0);
+                for (text, matched, want) in [
+                    ("429 rate limit", r"(?i)\b429\b", Some(60_000)),
+                    // `=0` clears only the identical built-in; its neighbour still floors
+                    (
+                        "429 Too Many Requests",
+                        r"(?i)Too\s*Many\s*Requests",
+                        Some(30_000),
+                    ),
+                    ("error 429", r"(?i)\b429\b", None),
+                ] {
+                    let decision = rusty_claude::patterns::should_retry(
+                        rusty_claude::Output::Merged(text),
+                        Some(1),
+                        false,
+                        &patterns,
+                        None,
+                    );
+                    if decision.matched.as_deref() != Some(matched) || decision.min_delay_ms != want
+                    {
+                        return Err(format!(
+                            "{text:?}: matched {:?}, minimum {:?}",
+                            decision.matched, decision.min_delay_ms
+                        ));
+                    }
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "assume-tz-unknown",
+            wrapper_args: &["--assume-tz", "Mars/Olympus_Mons"],
+            child_args: &["succeed"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 2)?;
+                if !r
+                    .stderr
+                    .contains("--assume-tz `Mars/Olympus_Mons` is not a known time zone")
+                {
+                    return Err(format!("unknown zone not reported: {}", r.stderr.trim()));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "delay-cmd-not-a-duration",
+            wrapper_args: &[
+                "--max-retries",
+                "1",
+                "--base-delay-ms",
+                "10",
+                "--delay-cmd",
+                "echo soon",
+            ],
+            child_args: &["fails-then-succeeds", "--failures", "1"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                expect_attempts(r, 2)?;
+                if !r.stderr.contains(
+                    "--delay-cmd printed `soon`, not a duration; using the built-in backoff",
+                ) {
+                    return Err("unparseable delay was not reported".into());
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "delay-cmd-failed",
+            wrapper_args: &[
+                "--max-retries",
+                "1",
+                "--base-delay-ms",
+                "10",
+                "--delay-cmd",
+                "exit 3",
+            ],
+            child_args: &["fails-then-succeeds", "--failures", "1"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                expect_attempts(r, 2)?;
+                if !r
+                    .stderr
+                    .contains("--delay-cmd exited with code 3; using the built-in backoff")
+                {
+                    return Err("failed delay command was not reported".into());
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "delay-cmd-output",
+            wrapper_args: &[],
+            child_args: &["succeed"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                let table: &[(&str, Option<u64>)] = &[
+                    ("750\n", Some(750)),
+                    ("750ms", Some(750)),
+                    (" 2.5s \r\n", Some(2_500)),
+                    ("1m30s", Some(90_000)),
+                    ("0", Some(0)),
+                    ("soon", None),
+                    ("", None),
+                    ("-5", None),
+                ];
+                for &(text, want) in table {
+                    let got = crate::delay_cmd::parse_output(text);
+                    if got != want {
+                        return Err(format!("{text:?}: got {got:?}, want {want:?}"));
+                    }
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "max-total-backoff",
+            wrapper_args: &[
+                "--max-total-ms",
+                "1500",
+                "--base-delay-ms",
+                "1000",
+                "--max-delay-ms",
+                "4000",
+            ],
+            child_args: &["fails-then-succeeds", "--failures", "5"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                // As soon as the next backoff no longer fits
+                expect_code(r, 122)?;
+                expect_timeout(r, "max-total-backoff", "total")?;
+                if r.elapsed >= Duration::from_millis(1500) {
+                    return Err(format!("slept past the budget ({:?})", r.elapsed));
+                }
+                if !r
+                    .stderr
+                    .contains("giving up: total budget of 1.5s exhausted after")
+                {
+                    return Err(format!("no give-up line in: {}", r.stderr.trim()));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "max-total-retry-after",
+            wrapper_args: &["--max-total-ms", "800"],
+            child_args: &["emits-retry-after"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 122)?;
+                expect_attempts(r, 1)?;
+                if r.elapsed >= Duration::from_millis(800) {
+                    return Err(format!("waited out Retry-After ({:?})", r.elapsed));
+                }
+                if !r.stderr.contains("server asked for 1s") {
+                    return Err(format!("no Retry-After give-up in: {}", r.stderr.trim()));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "raw-durations",
+            wrapper_args: &["--raw-durations", "--max-retries", "1"],
+            child_args: &["emits-retry-after"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                if !r.stderr.contains("retrying in 1000ms") {
+                    return Err(format!(
+                        "wait not shown as `1000ms` in: {}",
+                        r.stderr.trim()
+                    ));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "huge-output",
+            wrapper_args: FAST,
+            child_args: &["huge-output", "--bytes", "8388608"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                if r.stdout.len() != 8 * 1024 * 1024 || r.stdout.iter().any(|&b| b != b'x') {
+                    return Err(format!(
+                        "stdout altered: {} bytes forwarded",
+                        r.stdout.len()
+                    ));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "stalls",
+            wrapper_args: &["--heartbeat", "300ms"],
+            child_args: &["stalls", "--secs", "1"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                if !r.stderr.contains("still running") {
+                    return Err("no heartbeat during the silent stretch".into());
+                }
+                if r.stdout != b"start\nend\n" {
+                    return Err("stdout was not `start`/`end`".into());
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "attempt-timeout",
+            wrapper_args: &[
+                "--attempt-timeout-secs",
+                "1",
+                "--timeout-warning",
+                "500ms",
+                "--timeout-warning-signal",
+                "none",
+                "--max-retries",
+                "1",
+                "--base-delay-ms",
+                "10",
+            ],
+            child_args: &["stalls", "--secs", "30"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 124)?;
+                expect_attempts(r, 2)?;
+                expect_timeout(r, "attempt-timeout", "attempt")?;
+                if r.elapsed > Duration::from_secs(10) {
+                    return Err(format!("hung attempts were not killed ({:?})", r.elapsed));
+                }
+                // The partial output of both attempts is still forwarded
+                if r.stdout != b"start\nstart\n" {
+                    return Err(format!("stdout {:?}", String::from_utf8_lossy(&r.stdout)));
+                }
+                // One warning per attempt, each ahead of that attempt's timeout
+                let lines: Vec<&str> = r
+                    .stderr
+                    .lines()
+                    .filter(|l| l.contains("times out in") || l.contains("timed out after"))
+                    .collect();
+                let timed_out = |l: &&str| l.contains("attempt timed out after 1s");
+                if lines.len() != 4 || !timed_out(&lines[1]) || !timed_out(&lines[3]) {
+                    return Err(format!("warning/timeout lines out of order: {lines:?}"));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "schema-compat",
+            wrapper_args: &[
+                "--max-retries",
+                "3",
+                "--base-delay-ms",
+                "10",
+                "--max-delay-ms",
+                "50",
+                "--json-events",
+                "schema-events.jsonl",
+                "--decision-trace",
+                "schema-trace.jsonl",
+                "--log-file",
+                "schema-log.jsonl",
+                "--attempt-artifacts",
+                "schema-art",
+                "--stats-sink",
+                "schema-stats.jsonl",
+                "--stats-json",
+                "schema-summary.json",
+            ],
+            child_args: &["fails-then-succeeds", "--failures", "1"],
+            stdin: None,
+            observe: true,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                // Today's records still have every field of the golden ones
+                for golden in crate::schema::GOLDEN {
+                    let live = live_records(r, golden.schema)?;
+                    if golden.check(&live)? == 0 {
+                        return Err(format!("no live record to check {} by", golden.schema));
+                    }
+                }
+                // And what reads them back still reads the golden ones
+                let stats = crate::schema::GOLDEN
+                    .iter()
+                    .find(|g| g.schema == crate::schema::STATS)
+                    .map(|g| crate::stats::aggregate(g.records))
+                    .unwrap_or_default();
+                if stats.runs != 1 || stats.skipped_lines != 0 {
+                    return Err(format!("golden stats records read as {stats:?}"));
+                }
+                let meta = crate::schema::GOLDEN
+                    .iter()
+                    .find(|g| g.schema == crate::schema::ATTEMPT_META)
+                    .map_or("", |g| g.records);
+                let transcript = r.dir.join("schema-golden");
+                fs::create_dir_all(&transcript)
+                    .and_then(|()| fs::write(transcript.join("attempt-01.meta.json"), meta))
+                    .map_err(|e| format!("cannot write the golden transcript: {e}"))?;
+                let replay = simulate(r, &[], "schema-golden")?;
+                if !replay.contains("attempt 1: exit 1") {
+                    return Err(format!("golden attempt metadata replayed as: {replay}"));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "initial-delay-reported",
+            wrapper_args: &[
+                "--initial-delay",
+                "300ms",
+                "--log-file",
+                "initial-delay.jsonl",
+                "--stats-json",
+                "initial-delay-summary.json",
+            ],
+            child_args: &["succeed"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                let read = |file: &str| {
+                    fs::read_to_string(r.dir.join(file)).map_err(|e| format!("{file}: {e}"))
+                };
+                let log = read("initial-delay.jsonl")?;
+                let logged = log
+                    .lines()
+                    .filter_map(|l| serde_json::from_str::<serde_json::Value>(l).ok())
+                    .find(|l| l["record"] == "summary")
+                    .unwrap_or_default();
+                let summary: serde_json::Value =
+                    serde_json::from_str(&read("initial-delay-summary.json")?)
+                        .map_err(|e| format!("summary: {e}"))?;
+                for (what, record) in [("log file", &logged), ("summary", &summary)] {
+                    let ms = record["pre_delay_ms"].as_u64();
+                    if !ms.is_some_and(|ms| (300..2000).contains(&ms)) {
+                        return Err(format!("{what} pre_delay_ms: {ms:?}"));
+                    }
+                }
+                if summary["sleep_ms"].as_u64() != Some(0) {
+                    return Err("the pre-delay was counted as a backoff".into());
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "initial-delay-abort",
+            wrapper_args: &["--initial-delay", "30s", "--control-file", "control-abort"],
+            child_args: &["succeed"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, crate::exit_codes::INTERRUPTED)?;
+                expect_attempts(r, 0)?;
+                if !r
+                    .stderr
+                    .contains("not starting: abort requested during --initial-delay")
+                {
+                    return Err(format!("no abort message: {}", r.stderr.trim()));
+                }
+                if !r.stdout.is_empty() || r.elapsed > Duration::from_secs(10) {
+                    return Err("the first attempt ran anyway".into());
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "initial-delay-over-budget",
+            wrapper_args: &["--initial-delay", "10s", "--max-total-ms", "5000"],
+            child_args: &["succeed"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, crate::exit_codes::CONFIG_ERROR)?;
+                if !r.stderr.contains(
+                    "error: --initial-delay 10s leaves no time for an attempt inside \
+                    --max-total-ms 5s",
+                ) {
+                    return Err(format!("not refused: {}", r.stderr.trim()));
+                }
+                if r.elapsed > Duration::from_secs(5) {
+                    return Err(format!("refused only after {:?}", r.elapsed));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "expand-arg-env",
+            wrapper_args: &["--expand-arg-env"],
+            child_args: &["always-fatal", "--exit-code", "${SELFTEST_CODE}"],
+            stdin: None,
+            observe: false,
+            env: &[("SELFTEST_CODE", "7")],
+            check: |r, _| expect_code(r, 7),
+        },
+        Case {
+            name: "expand-arg-env-dry-run",
+            wrapper_args: &["--expand-arg-env", "--dry-run"],
+            child_args: &[
+                "succeed",
+                "$SELFTEST_DIR/mcp.json",
+                "$$SELFTEST_DIR",
+                "cost: $5",
+            ],
+            stdin: None,
+            observe: false,
+            env: &[("SELFTEST_DIR", "/etc/mcp")],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                let shown = String::from_utf8_lossy(&r.stdout);
+                if !shown.contains(r#""/etc/mcp/mcp.json", "$SELFTEST_DIR", "cost: $5""#) {
+                    return Err(format!("dry run showed {}", shown.trim()));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "expand-arg-env-off",
+            wrapper_args: &["--dry-run"],
+            child_args: &["succeed", "$SELFTEST_DIR/mcp.json"],
+            stdin: None,
+            observe: false,
+            env: &[("SELFTEST_DIR", "/etc/mcp")],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                let shown = String::from_utf8_lossy(&r.stdout);
+                if !shown.contains(r#""$SELFTEST_DIR/mcp.json""#) {
+                    return Err(format!("expanded without the flag: {}", shown.trim()));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "expand-arg-env-unset",
+            wrapper_args: &["--expand-arg-env"],
+            child_args: &["always-fatal", "--exit-code", "$SELFTEST_UNSET"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, crate::exit_codes::CONFIG_ERROR)?;
+                if !r.stderr.contains(
+                    "error: --expand-arg-env: `$SELFTEST_UNSET` in `$SELFTEST_UNSET` is not set",
+                ) {
+                    return Err(format!("not refused: {}", r.stderr.trim()));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "expand-arg-env-lossy",
+            wrapper_args: &["--expand-arg-env=lossy"],
+            child_args: &["always-fatal", "--exit-code", "${SELFTEST_UNSET}5"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| expect_code(r, 5),
+        },
+        Case {
+            name: "late-output-held-back",
+            wrapper_args: &[
+                "--attempt-timeout-secs",
+                "1",
+                "--max-retries",
+                "0",
+                "--no-process-group",
+            ],
+            child_args: &["chatty", "--orphan", "--secs", "2"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                // The orphan keeps printing for a second after the kill
+                expect_code(r, 124)?;
+                let stdout = String::from_utf8_lossy(&r.stdout);
+                if !stdout.starts_with("tick 0\n") || stdout.contains("chatty done") {
+                    return Err(format!("forwarded: {:?}", stdout.lines().last()));
+                }
+                if !r.stderr.contains("the child wrote after it was killed") {
+                    return Err("held-back output was not reported".into());
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "forward-late-output",
+            wrapper_args: &[
+                "--attempt-timeout-secs",
+                "1",
+                "--max-retries",
+                "0",
+                "--forward-late-output",
+                "--no-process-group",
+            ],
+            child_args: &["chatty", "--orphan", "--secs", "2"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 124)?;
+                let stdout = String::from_utf8_lossy(&r.stdout);
+                let ticks = stdout.lines().filter(|l| l.starts_with("tick ")).count();
+                if ticks != 200 || !stdout.ends_with("chatty done\n") {
+                    return Err(format!(
+                        "got {ticks} ticks, ending {:?}",
+                        stdout.lines().last()
+                    ));
+                }
+                if r.stderr.contains("after it was killed") {
+                    return Err("output was reported as held back".into());
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "stream-match",
+            wrapper_args: &[
+                "--stream-match",
+                "--max-retries",
+                "1",
+                "--base-delay-ms",
+                "10",
+            ],
+            child_args: &["overloaded-then-hangs", "--secs", "30"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                // Without --stream-match the first attempt would hang for 30s
+                expect_code(r, 0)?;
+                expect_attempts(r, 2)?;
+                if r.elapsed > Duration::from_secs(10) {
+                    return Err(format!(
+                        "the match did not cut the attempt short ({:?})",
+                        r.elapsed
+                    ));
+                }
+                if !r.stderr.contains(
+                    "retry pattern `(?i)overloaded` matched mid-stream; killed the attempt",
+                ) {
+                    return Err("mid-stream match was not reported".into());
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "library-supervisor",
+            wrapper_args: &[],
+            child_args: &["succeed"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                use rusty_claude::{RetryPolicy, Supervisor};
+                let exe =
+                    env::current_exe().map_err(|e| format!("cannot find rusty-claude: {e}"))?;
+                let mut cmd = Command::new(exe);
+                cmd.args([
+                    "__fake-child",
+                    "fails-then-succeeds",
+                    "--failures",
+                    "1",
+                    "--state",
+                ])
+                .arg(r.dir.join("library-supervisor-lib.state"));
+                let mut policy = RetryPolicy::default();
+                policy.backoff.base_ms = 10;
+                let report = Supervisor::new(policy)
+                    .run(cmd, None)
+                    .map_err(|e| format!("run: {e}"))?;
+                let first = &report.attempts[0];
+                let matched = first.decision.as_ref().and_then(|d| d.matched.as_deref());
+                if report.attempts.len() != 2 || !report.success() {
+                    return Err(format!(
+                        "{} attempt(s), success {}",
+                        report.attempts.len(),
+                        report.success()
+                    ));
+                }
+                if matched != Some("(?i)overloaded") || first.delay.is_none() {
+                    return Err(format!(
+                        "first attempt matched {matched:?}, delay {:?}",
+                        first.delay
+                    ));
+                }
+                if report.last().stdout != b"ok\n" {
+                    return Err(format!("last stdout {:?}", report.last().stdout));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "doctor",
+            wrapper_args: &[],
+            child_args: &["succeed"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                let (code, stdout) = doctor(None, &["--max-retries", "3"])?;
+                let report: serde_json::Value =
+                    serde_json::from_str(&stdout).map_err(|e| format!("doctor --json: {e}"))?;
+                let retries = report["settings"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .find(|s| s["key"] == "max_retries");
+                if code != 0
+                    || report["executable"] != true
+                    || report["version"] != env!("CARGO_PKG_VERSION")
+                    || retries.is_none_or(|s| s["value"] != "3" || s["source"] != "flag")
+                {
+                    return Err(format!("healthy doctor exited {code}: {stdout}"));
+                }
+                let (code, stdout) = doctor(Some("no-such-cli"), &["--patterns", "ok|foo("])?;
+                let expected = [
+                    "`no-such-cli` was not found",
+                    "retry pattern `foo(` from --patterns does not compile: unclosed group",
+                ];
+                if code != crate::exit_codes::NOT_FOUND
+                    || !expected.iter().all(|e| stdout.contains(e))
+                {
+                    return Err(format!("broken doctor exited {code}: {stdout}"));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "batch-stdin",
+            wrapper_args: &[
+                "--max-retries",
+                "3",
+                "--base-delay-ms",
+                "10",
+                "--max-delay-ms",
+                "50",
+                "--batch",
+                "batch-prompts.txt",
+                "--batch-concurrency",
+                "2",
+            ],
+            child_args: &["echo-stdin", "--failures", "1"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                let mut records = Vec::new();
+                for line in String::from_utf8_lossy(&r.stdout).lines() {
+                    let record: serde_json::Value =
+                        serde_json::from_str(line).map_err(|e| format!("{e} in {line}"))?;
+                    records.push(record);
+                }
+                records.sort_by_key(|r| r["index"].as_u64());
+                let got: Vec<_> = records
+                    .iter()
+                    .map(|r| {
+                        (
+                            r["index"].as_u64().unwrap_or(0),
+                            r["stdout"].as_str().unwrap_or_default(),
+                        )
+                    })
+                    .collect();
+                let want = [
+                    (1, "first prompt\n"),
+                    (3, "second prompt\n"),
+                    (4, "third\n"),
+                ];
+                if got != want {
+                    return Err(format!("records {got:?}"));
+                }
+                // Concurrent entries may both read the shared run counter before either
+                // bumps it, and then both fail
+                let attempts: u64 = records.iter().filter_map(|r| r["attempts"].as_u64()).sum();
+                if attempts < 4 || !r.stderr.contains("attempt 1 failed (exit 1, matched") {
+                    return Err(format!("{attempts} attempts in all; stderr: {}", r.stderr));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "stdin-file",
+            wrapper_args: &[
+                "--max-retries",
+                "2",
+                "--base-delay-ms",
+                "10",
+                "--buffer-output",
+                "--stdin-file",
+                "stdin-file.json",
+            ],
+            child_args: &["echo-stdin", "--failures", "1"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                expect_attempts(r, 2)?;
+                if r.stdout != b"{\"prompt\": \"replayed\"}\n" {
+                    return Err(format!(
+                        "the retry got {:?}",
+                        String::from_utf8_lossy(&r.stdout)
+                    ));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "stdin-file-missing",
+            wrapper_args: &["--stdin-file", "no-such-stdin-file"],
+            child_args: &["succeed"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, crate::exit_codes::CONFIG_ERROR)?;
+                if !r
+                    .stderr
+                    .contains("cannot read --stdin-file no-such-stdin-file")
+                {
+                    return Err(format!("no error about the file: {}", r.stderr.trim()));
+                }
+                if !r.stdout.is_empty() {
+                    return Err("the child ran".to_string());
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "batch-failed",
+            wrapper_args: &[
+                "--max-retries",
+                "0",
+                "--batch",
+                "batch-prompts.txt",
+                "--batch-output",
+                "batch-out",
+            ],
+            child_args: &["print-env", "--var", "{prompt}", "--status", "3"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, crate::exit_codes::BATCH_FAILED)?;
+                let path = r.dir.join("batch-out/entry-3.json");
+                let text = fs::read_to_string(&path).map_err(|e| format!("entry-3.json: {e}"))?;
+                let record: serde_json::Value =
+                    serde_json::from_str(&text).map_err(|e| format!("entry-3.json: {e}"))?;
+                if record["stdout"] != "second prompt=\n" || record["exit_code"] != 3 {
+                    return Err(format!("entry-3.json: {text}"));
+                }
+                if !r
+                    .stderr
+                    .contains("3 of 3 entries failed; failed entries (by line): 1,3,4")
+                {
+                    return Err(format!("no failure summary: {}", r.stderr));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "pathext-search",
+            wrapper_args: &[],
+            child_args: &["succeed"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                use crate::resolve::{path_exts, search};
+                expect_code(r, 0)?;
+                let exts = path_exts(None);
+                if exts != [".COM", ".EXE", ".BAT", ".CMD"] {
+                    return Err(format!("default PATHEXT {exts:?}"));
+                }
+                let custom = path_exts(Some(".exe; .cmd;;ps1;.Ps1"));
+                if custom != [".EXE", ".CMD", ".PS1"] {
+                    return Err(format!("custom PATHEXT {custom:?}"));
+                }
+                // npm's first dir has only shims; a later one has a real .exe
+                let fs = FakeFs(&["npm/claude.cmd", "npm/claude.bat", "bin/claude.exe"]);
+                let path = env::join_paths(["npm", "bin"]).map_err(|e| e.to_string())?;
+                let found = |name: &str, exts: &[String]| {
+                    search(name, Some(&path), exts, &fs).map_err(|tried| tried.len())
+                };
+                let want = |p: &str| Ok(PathBuf::from(p));
+                let checks = [
+                    (found("claude", &exts), want("npm/claude.bat")),
+                    (found("claude", &custom), want("npm/claude.cmd")),
+                    (found("claude.exe", &exts), want("bin/claude.exe")),
+                    (found("claude", &[]), Err(2)),
+                    (found("node", &exts), Err(2)),
+                ];
+                for (n, (got, want)) in checks.iter().enumerate() {
+                    if got != want {
+                        return Err(format!("search {n}: got {got:?}, expected {want:?}"));
+                    }
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "discover-windows-layout",
+            wrapper_args: &[],
+            child_args: &["succeed"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                use crate::resolve::discover_windows;
+                expect_code(r, 0)?;
+                let var = |k: &str| {
+                    let v = match k {
+                        "APPDATA" => "/roaming",
+                        "SCOOP" => "/scoop",
+                        "LOCALAPPDATA" => "/local",
+                        "USERPROFILE" => "/profile",
+                        _ => return None,
+                    };
+                    Some(v.to_string())
+                };
+                // No winget links folder: the portable package is found under Packages,
+                // and another vendor's package with a claude.exe is not
+                let fs = FakeFs(&[
+                    "/roaming/npm/claude.cmd",
+                    "/scoop/shims/claude.exe",
+                    "/local/Microsoft/WinGet/Packages/Other.Tool_winget/claude.exe",
+                    "/local/Microsoft/WinGet/Packages/Anthropic.ClaudeCode_winget/claude.exe",
+                    "/profile/.local/bin/claude.exe",
+                ]);
+                expect_candidates(
+                    &discover_windows(&fs, &var),
+                    &[
+                        ("/roaming/npm/claude.cmd", "npm"),
+                        ("/scoop/shims/claude.exe", "scoop"),
+                        (
+                            "/local/Microsoft/WinGet/Packages/Anthropic.ClaudeCode_winget/claude.exe",
+                            "winget",
+                        ),
+                        ("/profile/.local/bin/claude.exe", "native installer"),
+                    ],
+                )?;
+                // Scoop defaults to the profile, and winget's links come before its packages
+                let var = |k: &str| {
+                    let v = match k {
+                        "LOCALAPPDATA" => "/local",
+                        "USERPROFILE" => "/profile",
+                        _ => return None,
+                    };
+                    Some(v.to_string())
+                };
+                let fs = FakeFs(&[
+                    "/profile/scoop/shims/claude.cmd",
+                    "/local/Microsoft/WinGet/Packages/Anthropic.ClaudeCode_winget/claude.exe",
+                    "/local/Microsoft/WinGet/Links/claude.exe",
+                ]);
+                expect_candidates(
+                    &discover_windows(&fs, &var),
+                    &[
+                        ("/profile/scoop/shims/claude.cmd", "scoop"),
+                        ("/local/Microsoft/WinGet/Links/claude.exe", "winget"),
+                        (
+                            "/local/Microsoft/WinGet/Packages/Anthropic.ClaudeCode_winget/claude.exe",
+                            "winget",
+                        ),
+                    ],
+                )
+            },
+        },
+        Case {
+            name: "discover-nvm-highest",
+            wrapper_args: &[],
+            child_args: &["succeed"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                use crate::resolve::discover_version_managers;
+                expect_code(r, 0)?;
+                // Versions compare as numbers, v21 has no claude, and `system` is no version
+                let fs = FakeFs(&[
+                    "/nvm/versions/node/v9.11.2/bin/claude",
+                    "/nvm/versions/node/v20.11.1/bin/claude",
+                    "/nvm/versions/node/v18.19.0/bin/claude",
+                    "/nvm/versions/node/v21.0.0/bin/node",
+                    "/nvm/versions/node/system/bin/claude",
+                ]);
+                let var = |k: &str| (k == "NVM_DIR").then(|| "/nvm".to_string());
+                expect_candidates(
+                    &discover_version_managers(&fs, &var),
+                    &[
+                        ("/nvm/versions/node/v20.11.1/bin/claude", "nvm"),
+                        ("/nvm/versions/node/v18.19.0/bin/claude", "nvm"),
+                        ("/nvm/versions/node/v9.11.2/bin/claude", "nvm"),
+                    ],
+                )?;
+                // Without NVM_DIR, ~/.nvm
+                let fs = FakeFs(&["/home/u/.nvm/versions/node/v22.1.0/bin/claude"]);
+                let var = |k: &str| (k == "HOME").then(|| "/home/u".to_string());
+                expect_candidates(
+                    &discover_version_managers(&fs, &var),
+                    &[("/home/u/.nvm/versions/node/v22.1.0/bin/claude", "nvm")],
+                )
+            },
+        },
+        Case {
+            name: "discover-version-manager-shims",
+            wrapper_args: &[],
+            child_args: &["succeed"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                use crate::resolve::discover_version_managers;
+                expect_code(r, 0)?;
+                let fs = FakeFs(&[
+                    "/home/u/.asdf/shims/claude",
+                    "/home/u/.local/share/mise/shims/claude",
+                    "/home/u/.volta/bin/claude",
+                    "/asdf/shims/claude",
+                    "/xdg/mise/shims/claude",
+                    "/mise/shims/claude",
+                    "/volta/bin/claude",
+                ]);
+                let home = |k: &str| (k == "HOME").then(|| "/home/u".to_string());
+                expect_candidates(
+                    &discover_version_managers(&fs, &home),
+                    &[
+                        ("/home/u/.asdf/shims/claude", "asdf"),
+                        ("/home/u/.local/share/mise/shims/claude", "mise"),
+                        ("/home/u/.volta/bin/claude", "volta"),
+                    ],
+                )?;
+                // Each manager's own variable beats HOME; MISE_DATA_DIR beats XDG_DATA_HOME
+                let dirs = |xdg_only: bool| {
+                    move |k: &str| {
+                        let v = match k {
+                            "HOME" => "/home/u",
+                            "ASDF_DATA_DIR" => "/asdf",
+                            "XDG_DATA_HOME" => "/xdg",
+                            "MISE_DATA_DIR" if !xdg_only => "/mise",
+                            "VOLTA_HOME" => "/volta",
+                            _ => return None,
+                        };
+                        Some(v.to_string())
+                    }
+                };
+                expect_candidates(
+                    &discover_version_managers(&fs, &dirs(false)),
+                    &[
+                        ("/asdf/shims/claude", "asdf"),
+                        ("/mise/shims/claude", "mise"),
+                        ("/volta/bin/claude", "volta"),
+                    ],
+                )?;
+                expect_candidates(
+                    &discover_version_managers(&fs, &dirs(true)),
+                    &[
+                        ("/asdf/shims/claude", "asdf"),
+                        ("/xdg/mise/shims/claude", "mise"),
+                        ("/volta/bin/claude", "volta"),
+                    ],
+                )
+            },
+        },
+        Case {
+            name: "cmd-shim-quoting",
+            wrapper_args: &[],
+            child_args: &["succeed"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                use crate::resolve::{cmd_line, is_script};
+                expect_code(r, 0)?;
+                if !is_script(r"C:\npm\claude.CMD") || is_script(r"C:\bin\claude.exe") {
+                    return Err("only .cmd and .bat are run through cmd".into());
+                }
+                let args = ["-p", r#"say "hi" & bye"#, r"C:\dir\"].map(String::from);
+                let line = cmd_line(r"C:\Program Files\npm\claude.cmd", &args);
+                let want = r#"/d /s /c "C:\Program^ Files\npm\claude.cmd ^^^"-p^^^" ^^^"say^^^ \^^^"hi\^^^"^^^ ^^^&^^^ bye^^^" ^^^"C:\dir\\^^^"""#;
+                if line != want {
+                    return Err(format!("cmd line\n  {line}\nexpected\n  {want}"));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "exhausted-disposition",
+            wrapper_args: &[
+                "--exhausted-exit-code",
+                "75",
+                "--max-retries",
+                "1",
+                "--base-delay-ms",
+                "10",
+            ],
+            child_args: &["fails-then-succeeds", "--failures", "9"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 75)?;
+                let line = "giving up after 2 attempt(s): retries exhausted; exiting with code 75";
+                if !r.stderr.contains(line) {
+                    return Err(format!("stderr lacks `{line}`"));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "success-match-retried",
+            wrapper_args: &[
+                "--retry-on-success-match",
+                "--max-retries",
+                "3",
+                "--base-delay-ms",
+                "10",
+            ],
+            child_args: &["overloaded-exits-zero", "--failures", "1"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                expect_attempts(r, 2)?;
+                let line =
+                    "attempt exited 0 but its output matched retry pattern `(?i)overloaded`; \
+                    treating it as failed (--retry-on-success-match)";
+                if !r.stderr.contains(line) {
+                    return Err(format!("stderr lacks `{line}`"));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "success-match-exhausted",
+            wrapper_args: &[
+                "--retry-on-success-match",
+                "--success-match-exit-code",
+                "9",
+                "--max-retries",
+                "1",
+                "--base-delay-ms",
+                "10",
+            ],
+            child_args: &["overloaded-exits-zero", "--failures", "9"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 9)?;
+                expect_attempts(r, 2)
+            },
+        },
+        Case {
+            name: "success-match-off",
+            wrapper_args: &["--max-retries", "3", "--base-delay-ms", "10"],
+            child_args: &["overloaded-exits-zero", "--failures", "9"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                expect_attempts(r, 1)
+            },
+        },
+        Case {
+            name: "stdout-quote-exit-zero",
+            wrapper_args: FAST,
+            child_args: &["raw-bytes", "--payload", "quotes-errors"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                expect_attempts(r, 1)
+            },
+        },
+        Case {
+            name: "match-streams-stderr",
+            wrapper_args: &[
+                "--match-streams",
+                "stderr",
+                "--retry-on-success-match",
+                "--max-retries",
+                "3",
+            ],
+            child_args: &["raw-bytes", "--payload", "quotes-errors", "--status", "1"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                // The quoted 429 is on stdout, out of scope; nothing else retries exit 1
+                expect_code(r, 1)?;
+                expect_attempts(r, 1)
+            },
+        },
+        Case {
+            name: "match-streams-stderr-retries",
+            wrapper_args: &["--match-streams", "stderr", "--base-delay-ms", "10", "-v"],
+            child_args: &["fails-then-succeeds", "--failures", "1"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                expect_attempts(r, 2)?;
+                if !r.stderr.contains("(built-in, server) in its stderr:") {
+                    return Err("-v did not say the match was on stderr".into());
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "patterns-file",
+            wrapper_args: &[],
+            child_args: &["succeed"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                use crate::pattern_file;
+                use rusty_claude::patterns::compile_patterns;
+                let path = r.dir.join("retry-patterns.txt");
+                let text = "# curated\r\n\r\nbusy|try again\r\n  # indented comment\n\
+                    (?i)overloaded\nbusy|try again\n";
+                fs::write(&path, text).map_err(|e| e.to_string())?;
+                let listed = pattern_file::read(&path)?;
+                let want = [
+                    ("busy|try again", 3),
+                    ("(?i)overloaded", 5),
+                    ("busy|try again", 6),
+                ];
+                let got: Vec<_> = listed.iter().map(|(p, _)| p.as_str()).collect();
+                if got != want.map(|(p, _)| p) {
+                    return Err(format!("read {got:?}"));
+                }
+                let source = format!("{}:{}", path.display(), want[0].1);
+                if listed[0].1 != source {
+                    return Err(format!("source {}, want {source}", listed[0].1));
+                }
+                // The same pattern from --patterns, or one that's built in, is kept once
+                let flag = ("(?i)overloaded".to_string(), "--patterns".to_string());
+                let user = || listed.iter().cloned().chain([flag.clone()]);
+                let with_defaults = compile_patterns(user(), [], true, true)?.0;
+                let own = compile_patterns(user(), [], false, true)?.0;
+                let patterns: Vec<_> = own.regexes.iter().map(|re| re.as_str()).collect();
+                if patterns != ["busy|try again", "(?i)overloaded"] {
+                    return Err(format!("without the defaults: {patterns:?}"));
+                }
+                if with_defaults.len() != 14 {
+                    return Err(format!(
+                        "with the defaults: {} patterns",
+                        with_defaults.len()
+                    ));
+                }
+                fs::write(&path, "ok\n# fine\nbroken(\n").map_err(|e| e.to_string())?;
+                match pattern_file::read(&path) {
+                    Err(e)
+                        if e.contains(&format!(
+                            "{}:3 does not compile: unclosed group",
+                            path.display()
+                        )) =>
+                    {
+                        Ok(())
+                    }
+                    other => Err(format!("a broken pattern gave {other:?}")),
+                }
+            },
+        },
+        Case {
+            name: "no-default-patterns",
+            wrapper_args: &[
+                "--no-default-patterns",
+                "--max-retries",
+                "2",
+                "--patterns",
+                "never-printed",
+            ],
+            child_args: &["fails-then-succeeds", "--failures", "9"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| expect_attempts(r, 1),
+        },
+        Case {
+            name: "nothing-can-retry-warning",
+            wrapper_args: &["--no-default-patterns"],
+            child_args: &["succeed"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                if !r.stderr.contains("warning: no retry patterns are active") {
+                    return Err(format!("no warning: {}", r.stderr.trim()));
+                }
+                if r.stdout != b"ok\n" {
+                    return Err("a warning alone stopped the child".into());
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "strict-config",
+            wrapper_args: &["--strict-config", "--no-default-patterns"],
+            child_args: &["succeed"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, crate::exit_codes::CONFIG_ERROR)?;
+                if !r.stderr.contains("error: no retry patterns are active") {
+                    return Err(format!("not refused: {}", r.stderr.trim()));
+                }
+                if !r.stdout.is_empty() {
+                    return Err("the child ran anyway".into());
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "max-total-output",
+            wrapper_args: &["--max-total-output", "1000", "--max-retries", "5"],
+            child_args: &[
+                "raw-bytes",
+                "--payload",
+                "noisy-overload",
+                "--bytes",
+                "5000",
+                "--status",
+                "1",
+            ],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, crate::exit_codes::OUTPUT_LIMIT)?;
+                expect_attempts(r, 1)?;
+                if !r.stderr.contains("output budget exceeded") {
+                    return Err(format!("no output budget message: {}", r.stderr.trim()));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "max-total-output-across-attempts",
+            wrapper_args: &[
+                "--max-total-output",
+                "1MB",
+                "--max-retries",
+                "6",
+                "--base-delay-ms",
+                "10",
+            ],
+            child_args: &[
+                "raw-bytes",
+                "--payload",
+                "noisy-overload",
+                "--bytes",
+                "400000",
+                "--status",
+                "1",
+            ],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                // Each ~400KB attempt is under the limit; the third one takes the total over
+                expect_code(r, crate::exit_codes::OUTPUT_LIMIT)?;
+                expect_attempts(r, 3)?;
+                if !r
+                    .stderr
+                    .contains("across 3 attempt(s) (limit 1.0MB); not retrying")
+                {
+                    return Err(format!("no output budget message: {}", r.stderr.trim()));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "expensive-env-pattern",
+            wrapper_args: &[],
+            child_args: &["succeed"],
+            stdin: None,
+            observe: false,
+            env: &[("CLAUDE_SUPERVISOR_PATTERNS", "overloaded|a{1000}{1000}")],
+            check: |r, _| {
+                expect_code(r, crate::exit_codes::CONFIG_ERROR)?;
+                if !r.stderr.contains(
+                    "pattern `a{1000}{1000}` from CLAUDE_SUPERVISOR_PATTERNS is too expensive",
+                ) {
+                    return Err(format!("not refused: {}", r.stderr.trim()));
+                }
+                if !r.stdout.is_empty() {
+                    return Err("the child ran anyway".into());
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "match-timeout",
+            wrapper_args: &[
+                "--match-timeout",
+                "1ms",
+                "--retry-exit-codes",
+                "1",
+                "--max-retries",
+                "1",
+                "--base-delay-ms",
+                "10",
+            ],
+            child_args: &[
+                "raw-bytes",
+                "--payload",
+                "large",
+                "--bytes",
+                "8000000",
+                "--status",
+                "1",
+            ],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                // 32k lines take well over 1ms to scan; the exit code alone retries once
+                expect_code(r, 1)?;
+                expect_attempts(r, 2)?;
+                let warned = r
+                    .stderr
+                    .matches(
+                        "pattern scan exceeded --match-timeout; deciding on the exit code only",
+                    )
+                    .count();
+                if warned != 2 {
+                    return Err(format!("{warned} scan timeout warning(s), expected 2"));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "stream-match-chunks",
+            wrapper_args: &[],
+            child_args: &["succeed"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                use crate::piped::StreamMatch;
+                use rusty_claude::patterns::compile_patterns;
+                let patterns = compile_patterns([], [], true, true)?.0;
+                let pattern = |idx: Option<usize>| idx.map(|i| patterns.regexes[i].as_str());
+                let filler = "x".repeat(3000);
+                // chunks as read, the pattern the stream should match
+                let table: &[(&[&str], Option<&str>)] = &[
+                    (&["API Error: 529 Overloaded\n"], Some("(?i)overloaded")),
+                    (&["API Error: 529 Over", "loaded\n"], Some("(?i)overloaded")),
+                    (&["HTTP 42", "9", " Too Many"], Some(r"(?i)\b429\b")),
+                    (&["all ", "good\n", "done\n"], None),
+                    (&["over", &filler, "loaded"], None),
+                    (&[&filler, "ECONN", "RESET"], Some("(?i)ECONNRESET")),
+                ];
+                for &(chunks, want) in table {
+                    let m = StreamMatch::new(&patterns);
+                    let mut tail = Vec::new();
+                    for chunk in chunks {
+                        m.feed(&mut tail, chunk.as_bytes());
+                    }
+                    if pattern(m.hit()) != want {
+                        return Err(format!(
+                            "{chunks:?}: got {:?}, want {want:?}",
+                            pattern(m.hit())
+                        ));
+                    }
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "idle-timeout",
+            wrapper_args: &[
+                "--idle-timeout-secs",
+                "1",
+                "--max-retries",
+                "1",
+                "--base-delay-ms",
+                "10",
+            ],
+            child_args: &["stalls", "--secs", "30"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 123)?;
+                expect_attempts(r, 2)?;
+                expect_timeout(r, "idle-timeout", "idle")?;
+                if r.elapsed > Duration::from_secs(10) {
+                    return Err(format!(
+                        "stalled attempts were not killed ({:?})",
+                        r.elapsed
+                    ));
+                }
+                if r.stderr.matches("attempt stalled: no output for 1").count() != 2 {
+                    return Err(format!(
+                        "no stall report per attempt in: {}",
+                        r.stderr.trim()
+                    ));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "first-output-timeout",
+            wrapper_args: &[
+                "--first-output-timeout",
+                "1s",
+                "--first-output-retry-delay",
+                "100ms",
+                "--idle-timeout-secs",
+                "1",
+                "--max-retries",
+                "1",
+            ],
+            child_args: &["server", "--startup", "30"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 123)?;
+                expect_attempts(r, 2)?;
+                expect_timeout(r, "first-output-timeout", "first-output")?;
+                if r.elapsed > Duration::from_secs(10) {
+                    return Err(format!("silent attempts were not killed ({:?})", r.elapsed));
+                }
+                // One kill per attempt, by the first-output deadline rather than the stall one
+                if r.stderr
+                    .matches("no output within 1s; killed the attempt")
+                    .count()
+                    != 2
+                    || r.stderr.contains("attempt stalled")
+                {
+                    return Err(format!("unexpected kill reports in: {}", r.stderr.trim()));
+                }
+                if !r.stderr.contains("retrying in 100ms") {
+                    return Err("the first-output retry delay was not used".into());
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "timeout-exit-code",
+            wrapper_args: &[
+                "--attempt-timeout-secs",
+                "1",
+                "--max-retries",
+                "0",
+                "--timeout-exit-code",
+                "64",
+            ],
+            child_args: &["stalls", "--secs", "30"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 64)?;
+                expect_timeout(r, "timeout-exit-code", "attempt")
+            },
+        },
+        Case {
+            name: "stall-exit-code",
+            wrapper_args: &[
+                "--idle-timeout-secs",
+                "1",
+                "--max-retries",
+                "0",
+                "--stall-exit-code",
+                "65",
+            ],
+            child_args: &["stalls", "--secs", "30"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 65)?;
+                expect_timeout(r, "stall-exit-code", "idle")
+            },
+        },
+        Case {
+            name: "stall-exit-code-first-output",
+            wrapper_args: &[
+                "--first-output-timeout",
+                "1s",
+                "--max-retries",
+                "0",
+                "--stall-exit-code",
+                "65",
+            ],
+            child_args: &["server", "--startup", "30"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 65)?;
+                expect_timeout(r, "stall-exit-code-first-output", "first-output")
+            },
+        },
+        Case {
+            name: "total-timeout-exit-code",
+            wrapper_args: &[
+                "--max-total-ms",
+                "500",
+                "--base-delay-ms",
+                "1000",
+                "--total-timeout-exit-code",
+                "66",
+            ],
+            child_args: &["fails-then-succeeds", "--failures", "5"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 66)?;
+                expect_attempts(r, 1)?;
+                expect_timeout(r, "total-timeout-exit-code", "total")?;
+                let reason = fs::read_to_string(r.dir.join("total-timeout-exit-code.reason"))
+                    .unwrap_or_default();
+                for line in [
+                    "origin=wrapper",
+                    "reason=total-timeout",
+                    "child_exit_code=1",
+                ] {
+                    if !reason.lines().any(|l| l == line) {
+                        return Err(format!("no `{line}` in the reason file: {reason}"));
+                    }
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "first-output-silent-then-fast",
+            wrapper_args: &["--first-output-timeout", "1s"],
+            child_args: &[
+                "server",
+                "--startup",
+                "0.3",
+                "--secs",
+                "0",
+                "--exit-code",
+                "0",
+            ],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                expect_attempts(r, 1)
+            },
+        },
+        Case {
+            name: "first-output-chatty",
+            wrapper_args: &["--first-output-timeout", "1s"],
+            child_args: &["stalls", "--secs", "2"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                // The first byte cancels the deadline, however long the silence after it
+                expect_code(r, 0)?;
+                expect_attempts(r, 1)
+            },
+        },
+        Case {
+            name: "stdin-replay",
+            wrapper_args: FAST,
+            child_args: &["echo-stdin", "--failures", "1"],
+            stdin: Some(stdin_payload.clone()),
+            observe: false,
+            env: &[],
+            check: |r, input| {
+                expect_code(r, 0)?;
+                expect_attempts(r, 2)?;
+                if Some(r.stdout.as_slice()) != input {
+                    return Err("replayed stdin differs from the original".into());
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "require-stdin-empty",
+            wrapper_args: &["--require-stdin"],
+            child_args: &["succeed"],
+            stdin: Some(Vec::new()),
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_no_stdin(
+                    r,
+                    "require-stdin-empty",
+                    "--require-stdin 1B: stdin is empty; not starting the child",
+                )?;
+                let reason = fs::read_to_string(r.dir.join("require-stdin-empty.reason"))
+                    .unwrap_or_default();
+                if !reason.lines().any(|l| l == "reason=no-stdin") {
+                    return Err(format!("reason file: {reason}"));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "require-stdin-short",
+            wrapper_args: &["--require-stdin", "100"],
+            child_args: &["succeed"],
+            stdin: Some(b"short prompt\n".to_vec()),
+            observe: false,
+            env: &[],
+            check: |r, _| expect_no_stdin(r, "require-stdin-short", "stdin holds only 13B"),
+        },
+        Case {
+            name: "require-stdin-met",
+            wrapper_args: &["--require-stdin", "13"],
+            child_args: &["succeed"],
+            stdin: Some(b"short prompt\n".to_vec()),
+            observe: false,
+            env: &[],
+            check: |r, _| expect_code(r, 0),
+        },
+        Case {
+            name: "require-stdin-file",
+            wrapper_args: &["--require-stdin", "1KB", "--stdin-file", "stdin-file.json"],
+            child_args: &["succeed"],
+            stdin: Some(vec![b'x'; 2000]),
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_no_stdin(
+                    r,
+                    "require-stdin-file",
+                    "--require-stdin 1.0KB: --stdin-file stdin-file.json holds only 23B",
+                )
+            },
+        },
+        Case {
+            name: "require-stdin-config",
+            wrapper_args: &["--config", "require-stdin.toml"],
+            child_args: &["succeed"],
+            stdin: Some(b"hi\n".to_vec()),
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_no_stdin(
+                    r,
+                    "require-stdin-config",
+                    "--require-stdin 100B: stdin holds only 3B",
+                )
+            },
+        },
+        Case {
+            name: "stdin-spool-replay",
+            wrapper_args: &[
+                "--max-retries",
+                "3",
+                "--base-delay-ms",
+                "10",
+                "--max-delay-ms",
+                "50",
+                "--stdin-spool-threshold-bytes",
+                "64KiB",
+                "-v",
+            ],
+            child_args: &["echo-stdin", "--failures", "1"],
+            stdin: Some(stdin_payload),
+            observe: false,
+            env: &[],
+            check: |r, input| {
+                expect_code(r, 0)?;
+                expect_attempts(r, 2)?;
+                if !r.stderr.contains("streaming it through a temp file") {
+                    return Err("stdin over the threshold was not spooled".into());
+                }
+                if Some(r.stdout.as_slice()) != input {
+                    return Err("stdin replayed from the spool differs from the original".into());
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "verbose-decision",
+            wrapper_args: &[
+                "-v",
+                "--max-retries",
+                "1",
+                "--base-delay-ms",
+                "10",
+                "--backoff-strategy",
+                "constant",
+                "--patterns",
+                "ok(|never-printed",
+            ],
+            child_args: &["fails-then-succeeds", "--failures", "9"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                for line in [
+                    "warning: ignoring pattern `ok(` from --patterns, which does not compile: \
+                    unclosed group",
+                    "retry pattern `never-printed` (--patterns)",
+                    "retry pattern `(?i)overloaded` (built-in, server)",
+                    "attempt 1 matched retry pattern `(?i)overloaded` (built-in, server) in its \
+                    stderr: API Error: 529",
+                    "waiting 10ms, from constant backoff for attempt 1 (base 10ms, cap ",
+                ] {
+                    if !r.stderr.contains(line) {
+                        return Err(format!("stderr lacks `{line}`"));
+                    }
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "ci-annotations-github",
+            wrapper_args: &[
+                "--ci-annotations",
+                "github",
+                "--backoff-strategy",
+                "constant",
+                "--base-delay-ms",
+                "10",
+            ],
+            child_args: &["fails-then-succeeds", "--failures", "1"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                let want = "::group::attempt 1\n\
+                    ::endgroup::\n\
+                    ::warning::attempt 1 failed (code=Some(1), matched `(?i)overloaded`); \
+                    retrying in 10ms\n\
+                    ::group::attempt 2\n\
+                    ok\n\
+                    ::endgroup::\n";
+                let stdout = String::from_utf8_lossy(&r.stdout);
+                if stdout != want {
+                    return Err(format!("stdout {stdout:?}, expected {want:?}"));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "ci-annotations-error",
+            wrapper_args: &[
+                "--ci-annotations",
+                "auto",
+                "--backoff-strategy",
+                "constant",
+                "--base-delay-ms",
+                "10",
+                "--max-retries",
+                "1",
+            ],
+            child_args: &["fails-then-succeeds", "--failures", "9"],
+            stdin: None,
+            observe: false,
+            env: &[("GITHUB_ACTIONS", "true")],
+            check: |r, _| {
+                expect_code(r, 1)?;
+                let want = "::group::attempt 1\n\
+                    ::endgroup::\n\
+                    ::warning::attempt 1 failed (code=Some(1), matched `(?i)overloaded`); \
+                    retrying in 10ms\n\
+                    ::group::attempt 2\n\
+                    ::endgroup::\n\
+                    ::error::claude failed after 2 attempt(s) (code=Some(1))\n";
+                let stdout = String::from_utf8_lossy(&r.stdout);
+                if stdout != want {
+                    return Err(format!("stdout {stdout:?}, expected {want:?}"));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "run-summary",
+            wrapper_args: &[
+                "--max-retries",
+                "1",
+                "--base-delay-ms",
+                "10",
+                "--stats",
+                "--stats-json",
+                "-",
+            ],
+            child_args: &["fails-then-succeeds", "--failures", "9"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_attempts(r, 2)?;
+                for line in [
+                    "after 2 attempt(s) in",
+                    "attempt 1: exit 1 after",
+                    "retried after",
+                    "attempt 2: exit 1 after",
+                    "sleeping between attempts",
+                    "patterns matched: `(?i)overloaded` x2",
+                    "\"schema\": \"rusty-claude/summary/1\"",
+                ] {
+                    if !r.stderr.contains(line) {
+                        return Err(format!("stderr lacks `{line}`"));
+                    }
+                }
+                // The JSON goes to stderr for `-`, never into the child's stdout
+                if String::from_utf8_lossy(&r.stdout).contains("rusty-claude/summary") {
+                    return Err("summary JSON on stdout".into());
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "fallback-args",
+            wrapper_args: &[
+                "--max-retries",
+                "1",
+                "--base-delay-ms",
+                "10",
+                "--fallback-mode",
+                "replace",
+                "--fallback-args",
+                "__fake-child succeed",
+            ],
+            child_args: &["fails-then-succeeds", "--failures", "9"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                expect_attempts(r, 3)?;
+                for line in [
+                    "attempt 2 uses the primary args",
+                    "retries exhausted with the primary args; falling back to the fallback 1 of 1 \
+                    (__fake-child succeed)",
+                    "attempt 3 uses the fallback 1 of 1 (__fake-child succeed)",
+                ] {
+                    if !r.stderr.contains(line) {
+                        return Err(format!("stderr lacks `{line}`"));
+                    }
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "fallback-not-for-fatal",
+            wrapper_args: &[
+                "--max-retries",
+                "1",
+                "--base-delay-ms",
+                "10",
+                "--fallback-args",
+                "--model claude-sonnet-4",
+            ],
+            child_args: &["always-fatal"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_attempts(r, 1)?;
+                if r.code == Some(0) || r.stderr.contains("falling back") {
+                    return Err("a non-retryable failure fell back".into());
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "raw-adversarial",
+            wrapper_args: &["--raw-passthrough"],
+            child_args: &["raw-bytes", "--payload", "adversarial"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| expect_raw(r, Payload::Adversarial, 0),
+        },
+        Case {
+            name: "raw-empty",
+            wrapper_args: &["--raw-passthrough"],
+            child_args: &["raw-bytes", "--payload", "empty"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| expect_raw(r, Payload::Empty, 0),
+        },
+        Case {
+            name: "raw-large",
+            wrapper_args: &["--raw-passthrough"],
+            child_args: &["raw-bytes", "--payload", "large", "--bytes", "67108864"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| expect_raw(r, Payload::Large, RAW_LARGE_BYTES),
+        },
+        Case {
+            name: "raw-log-file-stderr",
+            wrapper_args: &["--raw-passthrough", "--log-file", "-"],
+            child_args: &["succeed"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, crate::exit_codes::CONFIG_ERROR)?;
+                if !r.stdout.is_empty() {
+                    return Err("the child ran".into());
+                }
+                if r.stderr.trim()
+                    != "[rusty-claude] error: --log-file - writes to stderr, which \
+                    --raw-passthrough keeps untouched"
+                {
+                    return Err(format!("unexpected stderr: {}", r.stderr.trim()));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "raw-stats",
+            wrapper_args: &["--raw-passthrough", "--stats"],
+            child_args: &["succeed"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 2)?;
+                if !r.stdout.is_empty() {
+                    return Err("the child ran".into());
+                }
+                if !r
+                    .stderr
+                    .contains("'--raw-passthrough' cannot be used with '--stats'")
+                {
+                    return Err(format!("no conflict reported: {}", r.stderr.trim()));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "raw-stats-json-stderr",
+            wrapper_args: &["--raw-passthrough", "--stats-json", "-"],
+            child_args: &["succeed"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, crate::exit_codes::CONFIG_ERROR)?;
+                if !r.stdout.is_empty() {
+                    return Err("the child ran".into());
+                }
+                if r.stderr.trim()
+                    != "[rusty-claude] error: --stats-json - writes to stderr, which \
+                    --raw-passthrough keeps untouched"
+                {
+                    return Err(format!("unexpected stderr: {}", r.stderr.trim()));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "buffer-output",
+            wrapper_args: &[
+                "--buffer-output",
+                "--json-errors",
+                "--max-retries",
+                "2",
+                "--base-delay-ms",
+                "10",
+            ],
+            child_args: &["json-error", "--failures", "2"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                expect_attempts(r, 3)?;
+                let stdout = String::from_utf8_lossy(&r.stdout);
+                if stdout.lines().count() != 2 || stdout.contains("is_error\":true") {
+                    return Err(format!("failed attempts reached stdout: {stdout:?}"));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "buffer-output-exhausted",
+            wrapper_args: &[
+                "--buffer-output",
+                "--json-errors",
+                "--max-retries",
+                "1",
+                "--base-delay-ms",
+                "10",
+            ],
+            child_args: &["json-error", "--failures", "5"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, crate::exit_codes::JSON_ERROR)?;
+                expect_attempts(r, 2)?;
+                // The last attempt is the final one, failed or not
+                let stdout = String::from_utf8_lossy(&r.stdout);
+                if stdout.lines().count() != 2 || !stdout.contains("overloaded_error") {
+                    return Err(format!(
+                        "expected only the last attempt's output: {stdout:?}"
+                    ));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "buffer-adversarial",
+            wrapper_args: &["--buffer-output", "--buffer-stderr"],
+            child_args: &["raw-bytes", "--payload", "adversarial"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| expect_raw(r, Payload::Adversarial, 0),
+        },
+        Case {
+            name: "buffer-limit",
+            wrapper_args: &["--buffer-output", "--buffer-limit", "1KiB"],
+            child_args: &["huge-output", "--bytes", "1048576"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, crate::exit_codes::BUFFER_LIMIT)?;
+                expect_attempts(r, 1)?;
+                if !r.stdout.is_empty() {
+                    return Err(format!(
+                        "{} bytes of an overflowed buffer written",
+                        r.stdout.len()
+                    ));
+                }
+                if !r
+                    .stderr
+                    .contains("passed --buffer-limit 1.0KB under --buffer-output")
+                {
+                    return Err(format!("overflow not reported: {}", r.stderr.trim()));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "integrity-mismatch",
+            wrapper_args: &[
+                "--expect-cmd-sha256",
+                "0000000000000000000000000000000000000000000000000000000000000000",
+            ],
+            child_args: &["succeed"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, crate::exit_codes::INTEGRITY_MISMATCH)?;
+                if !r.stdout.is_empty() {
+                    return Err("the unverified child ran anyway".into());
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "isolated-home",
+            wrapper_args: &["--isolated-home"],
+            child_args: &["print-env", "--var", "HOME", "--var", "XDG_CONFIG_HOME"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                expect_isolated_home(r)
+            },
+        },
+        Case {
+            name: "isolated-home-failure",
+            wrapper_args: &["--isolated-home"],
+            child_args: &["print-env", "--var", "HOME", "--status", "3"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 3)?;
+                expect_isolated_home(r)
+            },
+        },
+        Case {
+            name: "stable-locale",
+            wrapper_args: &["--stable-locale"],
+            child_args: &["print-env", "--var", "LC_ALL", "--var", "LANG"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                let stdout = String::from_utf8_lossy(&r.stdout);
+                for name in ["LC_ALL", "LANG"] {
+                    let value = stdout
+                        .lines()
+                        .find_map(|l| l.strip_prefix(name)?.strip_prefix('='))
+                        .unwrap_or_default();
+                    if value != "C.UTF-8" && value != "C" {
+                        return Err(format!("child saw {name}={value:?}"));
+                    }
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "child-env",
+            wrapper_args: &[
+                "-v",
+                "--unset-env",
+                "SELFTEST_SECRET_*",
+                "--env",
+                "SELFTEST_KEPT=from-env",
+                "--env",
+                "SELFTEST_SECRET_B=set-again",
+            ],
+            child_args: &[
+                "print-env",
+                "--var",
+                "SELFTEST_SECRET_A",
+                "--var",
+                "SELFTEST_SECRET_B",
+                "--var",
+                "SELFTEST_KEPT",
+                "--var",
+                "SELFTEST_OTHER",
+            ],
+            stdin: None,
+            observe: false,
+            env: &[
+                ("SELFTEST_SECRET_A", "inherited-a"),
+                ("SELFTEST_SECRET_B", "inherited-b"),
+                ("SELFTEST_KEPT", "inherited"),
+                ("SELFTEST_OTHER", "inherited-other"),
+            ],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                let stdout = String::from_utf8_lossy(&r.stdout);
+                for want in [
+                    "SELFTEST_SECRET_A=\n",
+                    "SELFTEST_SECRET_B=set-again",
+                    "SELFTEST_KEPT=from-env",
+                    "SELFTEST_OTHER=inherited-other",
+                ] {
+                    if !stdout.contains(want) {
+                        return Err(format!("child output lacks {want:?}: {stdout:?}"));
+                    }
+                }
+                let trace = r
+                    .stderr
+                    .lines()
+                    .find(|l| l.contains("child environment:"))
+                    .ok_or("no child environment line under -v")?;
+                let names: Vec<&str> = trace.split_whitespace().collect();
+                if !names.contains(&"SELFTEST_KEPT") || names.contains(&"SELFTEST_SECRET_A") {
+                    return Err(format!("unexpected names: {trace}"));
+                }
+                if trace.contains("from-env") || trace.contains("inherited-other") {
+                    return Err(format!("values in the trace: {trace}"));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "no-inherit-env",
+            wrapper_args: &["--no-inherit-env", "--env", "SELFTEST_SET=1"],
+            child_args: &[
+                "print-env",
+                "--var",
+                "SELFTEST_DROPPED",
+                "--var",
+                "SELFTEST_SET",
+                "--var",
+                "PATH",
+            ],
+            stdin: None,
+            observe: false,
+            env: &[("SELFTEST_DROPPED", "inherited")],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                let stdout = String::from_utf8_lossy(&r.stdout);
+                let path = env::var("PATH").unwrap_or_default();
+                for want in [
+                    "SELFTEST_DROPPED=\n".to_string(),
+                    "SELFTEST_SET=1\n".to_string(),
+                    format!("PATH={path}\n"),
+                ] {
+                    if !stdout.contains(&want) {
+                        return Err(format!("child output lacks {want:?}: {stdout:?}"));
+                    }
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "self-wrap",
+            wrapper_args: &["self-wrap"],
+            child_args: &["succeed"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: expect_self_wrap_refused,
+        },
+        Case {
+            name: "run-id",
+            wrapper_args: &[
+                "-v",
+                "--run-id",
+                "selftest-run-id",
+                "--json-events",
+                "run-id.events",
+                "--attempt-artifacts",
+                "run-id-{run_id}",
+            ],
+            child_args: &["print-env", "--var", "RUSTY_CLAUDE_RUN_ID"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                let id = "selftest-run-id";
+                let read = |name: &str| fs::read_to_string(r.dir.join(name)).unwrap_or_default();
+                let surfaces = [
+                    ("child env", String::from_utf8_lossy(&r.stdout).into_owned()),
+                    ("-v log", r.stderr.clone()),
+                    ("events", read("run-id.events")),
+                    ("reason file", read("run-id.reason")),
+                    (
+                        "artifacts",
+                        read("run-id-selftest-run-id/attempt-01.meta.json"),
+                    ),
+                ];
+                for (surface, text) in surfaces {
+                    if !text.contains(id) {
+                        return Err(format!("run id missing from the {surface}"));
+                    }
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "feed-previous-error",
+            wrapper_args: &[
+                "--base-delay-ms",
+                "10",
+                "--feed-previous-error",
+                "--previous-error",
+            ],
+            child_args: &["fails-then-succeeds", "--failures", "1"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                expect_attempts(r, 2)?;
+                let stdout = String::from_utf8_lossy(&r.stdout);
+                let fed: Vec<&str> = stdout
+                    .lines()
+                    .filter_map(|l| l.strip_prefix("previous error from "))
+                    .collect();
+                let [path] = fed[..] else {
+                    return Err(format!(
+                        "expected one fed error file, child saw {}",
+                        fed.len()
+                    ));
+                };
+                if !stdout.contains("overloaded_error") {
+                    return Err("fed file lacked the previous stderr".into());
+                }
+                if Path::new(path.trim_end_matches(':')).exists() {
+                    return Err(format!("{path} was not removed after the attempt"));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "auto-resume",
+            wrapper_args: &["--base-delay-ms", "10", "--auto-resume"],
+            child_args: &["session", "--failures", "2"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                expect_attempts(r, 3)?;
+                // A second `--resume` would have been refused by the child
+                let stdout = String::from_utf8_lossy(&r.stdout);
+                if !stdout.contains("resuming 5e55-0002\n") {
+                    return Err(format!("last attempt did not resume 5e55-0002: {stdout}"));
+                }
+                for logged in [
+                    "attempt 2 resumes session 5e55-0001",
+                    "attempt 3 resumes session 5e55-0002",
+                ] {
+                    if !r.stderr.contains(logged) {
+                        return Err(format!("stderr lacks `{logged}`"));
+                    }
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "auto-resume-capture-cut",
+            wrapper_args: &[
+                "--base-delay-ms",
+                "10",
+                "--auto-resume",
+                "--max-capture-bytes",
+                "1KiB",
+            ],
+            child_args: &["session", "--failures", "1", "--filler", "65536"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                expect_attempts(r, 2)?;
+                // The capture kept only the filler, so the id came from the stream
+                if !r.stderr.contains("attempt 2 resumes session 5e55-0001") {
+                    return Err(format!("attempt 2 did not resume: {}", r.stderr.trim()));
+                }
+                let stdout = String::from_utf8_lossy(&r.stdout);
+                if !stdout.contains("resuming 5e55-0001\n") {
+                    return Err("the child was not passed the session".to_string());
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "success-pattern-overrides-exit",
+            wrapper_args: &["--success-pattern", "unknown option"],
+            child_args: &["always-fatal", "--exit-code", "3"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                expect_attempts(r, 1)
+            },
+        },
+        Case {
+            name: "success-pattern-missing",
+            wrapper_args: &[
+                "--success-pattern",
+                "^DONE$",
+                "--retry-on-any-error",
+                "--min-runtime-for-retry",
+                "0",
+                "--max-retries",
+                "1",
+                "--base-delay-ms",
+                "10",
+            ],
+            child_args: &["succeed"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 117)?;
+                expect_attempts(r, 2)
+            },
+        },
+        Case {
+            name: "waste",
+            wrapper_args: &[
+                "--base-delay-ms",
+                "10",
+                "--attempt-artifacts",
+                "waste-artifacts",
+            ],
+            child_args: &["fails-then-succeeds", "--failures", "2"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                expect_attempts(r, 3)?;
+                let reason = fs::read_to_string(r.dir.join("waste.reason")).unwrap_or_default();
+                let field = |key: &str| {
+                    reason
+                        .lines()
+                        .find_map(|l| l.strip_prefix(key)?.strip_prefix('='))
+                        .and_then(|v| v.parse::<u64>().ok())
+                };
+                if field("wasted_attempts") != Some(2) {
+                    return Err(format!("expected 2 wasted attempts in:\n{reason}"));
+                }
+                // The two failed attempts' output, as recorded in their artifacts
+                let mut discarded = 0;
+                for n in 1..=2 {
+                    let meta = fs::read_to_string(
+                        r.dir
+                            .join(format!("waste-artifacts/attempt-{n:02}.meta.json")),
+                    )
+                    .map_err(|e| format!("attempt {n} metadata: {e}"))?;
+                    let meta: serde_json::Value =
+                        serde_json::from_str(&meta).map_err(|e| e.to_string())?;
+                    discarded += meta["stdout_bytes"].as_u64().unwrap_or(0)
+                        + meta["stderr_bytes"].as_u64().unwrap_or(0);
+                }
+                if field("wasted_output_bytes") != Some(discarded) {
+                    return Err(format!(
+                        "wasted_output_bytes {:?}, the failed attempts wrote {discarded}",
+                        field("wasted_output_bytes")
+                    ));
+                }
+                if !r.stderr.contains("across 2 failed attempts") {
+                    return Err("no waste epilogue".into());
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "stats-sink",
+            wrapper_args: &["--base-delay-ms", "10", "--stats-sink", "stats.jsonl"],
+            child_args: &["fails-then-succeeds", "--failures", "1"],
+            stdin: None,
+            observe: false,
+            env: &[("RUSTY_CLAUDE_PIPELINE_ID", "selftest")],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                let sink = r.dir.join("stats.jsonl");
+                let text = fs::read_to_string(&sink).map_err(|e| format!("stats sink: {e}"))?;
+                let line = text.lines().next().unwrap_or_default().to_string();
+                let record: serde_json::Value =
+                    serde_json::from_str(&line).map_err(|e| format!("{e}: {line}"))?;
+                if record["attempts"] != 2 || record["pipeline_id"] != "selftest" {
+                    return Err(format!("unexpected record {line}"));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "force-tee-modes",
+            wrapper_args: &["--force-tee", "-v"],
+            child_args: &["succeed"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                if !r.stderr.contains("mode: piped: child args given") {
+                    return Err(format!("no mode explanation in: {}", r.stderr.trim()));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "retry-guard-usage-error",
+            wrapper_args: &["--retry-on-any-error", "--base-delay-ms", "10"],
+            child_args: &["always-fatal", "--exit-code", "2"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 2)?;
+                expect_attempts(r, 1)?;
+                if !r
+                    .stderr
+                    .contains("not retrying under --retry-on-any-error: exit code 2")
+                {
+                    return Err(format!("no guard explanation in: {}", r.stderr.trim()));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "retry-guard-pattern-wins",
+            wrapper_args: &[
+                "--retry-on-any-error",
+                "--max-retries",
+                "3",
+                "--base-delay-ms",
+                "10",
+            ],
+            child_args: &["fails-then-succeeds", "--failures", "1"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                // Fast, but the overload pattern matched, so no guard applies
+                expect_code(r, 0)?;
+                expect_attempts(r, 2)
+            },
+        },
+        Case {
+            name: "retry-exit-codes",
+            wrapper_args: &[
+                "--retry-exit-codes",
+                "1,70-78",
+                "--max-retries",
+                "1",
+                "--base-delay-ms",
+                "10",
+            ],
+            child_args: &["always-fatal", "--exit-code", "75"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                // Matches no pattern and looks like a usage error, but the code is listed
+                expect_code(r, 75)?;
+                expect_attempts(r, 2)
+            },
+        },
+        Case {
+            name: "no-retry-exit-codes",
+            wrapper_args: &[
+                "--no-retry-exit-codes",
+                "1",
+                "--retry-exit-codes",
+                "1",
+                "--max-retries",
+                "3",
+                "--base-delay-ms",
+                "10",
+            ],
+            child_args: &["fails-then-succeeds", "--failures", "1"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                // The overload line matches a retry pattern; the no-retry code wins
+                expect_code(r, 1)?;
+                expect_attempts(r, 1)?;
+                if !r
+                    .stderr
+                    .contains("exit code 1 is in --no-retry-exit-codes; not retrying")
+                {
+                    return Err(format!("no explanation in: {}", r.stderr.trim()));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "json-errors",
+            wrapper_args: &[
+                "--json-errors",
+                "--max-retries",
+                "2",
+                "--base-delay-ms",
+                "10",
+            ],
+            child_args: &["json-error", "--failures", "1"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                // The overload came with exit 0 and is retried all the same
+                expect_code(r, 0)?;
+                expect_attempts(r, 2)?;
+                if !r.stderr.contains(
+                    "JSON output reports error type `overloaded_error`, which is retryable",
+                ) {
+                    return Err("JSON error was not reported".into());
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "json-errors-clean",
+            wrapper_args: &[
+                "--json-errors",
+                "--max-retries",
+                "2",
+                "--base-delay-ms",
+                "10",
+            ],
+            child_args: &["json-error", "--failures", "0", "--status", "1"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                // "429" in the model's text is not an error
+                expect_code(r, 1)?;
+                expect_attempts(r, 1)
+            },
+        },
+        Case {
+            name: "fatal-pattern-beats-retry",
+            wrapper_args: &[
+                "--fatal-patterns",
+                "(?i)overloaded",
+                "--max-retries",
+                "3",
+                "--base-delay-ms",
+                "10",
+            ],
+            child_args: &["fails-then-succeeds", "--failures", "1"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                // The overload line matches a retry pattern too; the fatal one wins
+                expect_code(r, 1)?;
+                expect_attempts(r, 1)?;
+                if !r
+                    .stderr
+                    .contains("fatal pattern `(?i)overloaded` matched; not retrying")
+                {
+                    return Err(format!("no fatal explanation in: {}", r.stderr.trim()));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "fatal-patterns",
+            wrapper_args: &[],
+            child_args: &["succeed"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                use rusty_claude::patterns::{compile_patterns, should_retry, Output};
+                // output, --retry-on-any-error, --fatal-patterns, default fatal patterns,
+                // expected retry, expected fatal pattern
+                type Row = (
+                    &'static str,
+                    bool,
+                    Option<&'static str>,
+                    bool,
+                    bool,
+                    Option<&'static str>,
+                );
+                let table: &[Row] = &[
+                    ("API Error: 529 overloaded", false, None, true, true, None),
+                    (
+                        "Error: 401 Unauthorized",
+                        true,
+                        None,
+                        true,
+                        false,
+                        Some(r"(?i)\b401\b"),
+                    ),
+                    (
+                        "HTTP 403 overloaded",
+                        false,
+                        None,
+                        true,
+                        false,
+                        Some(r"(?i)\b403\b"),
+                    ),
+                    (
+                        "overloaded\nInvalid API key",
+                        false,
+                        None,
+                        true,
+                        false,
+                        Some(r"(?i)invalid\s*api\s*key"),
+                    ),
+                    (
+                        "Invalid API key\noverloaded",
+                        false,
+                        None,
+                        true,
+                        false,
+                        Some(r"(?i)invalid\s*api\s*key"),
+                    ),
+                    (
+                        "Your credit balance is too low",
+                        true,
+                        None,
+                        true,
+                        false,
+                        Some(r"(?i)credit\s*balance"),
+                    ),
+                    ("Error: 4011 overloaded", false, None, true, true, None),
+                    ("Error: 401 overloaded", false, None, false, true, None),
+                    ("Error: 401 Unauthorized", true, None, false, true, None),
+                    (
+                        "quota exceeded",
+                        true,
+                        Some("quota"),
+                        true,
+                        false,
+                        Some("quota"),
+                    ),
+                    (
+                        "quota exceeded",
+                        true,
+                        Some("quota"),
+                        false,
+                        false,
+                        Some("quota"),
+                    ),
+                ];
+                for &(output, retry_on_any, fatal, default_fatal, want_retry, want_fatal) in table {
+                    let user_fatal = fatal.map(|p| (p.to_string(), "--fatal-patterns".to_string()));
+                    let patterns = compile_patterns([], user_fatal, true, default_fatal)?.0;
+                    let decision =
+                        should_retry(Output::Merged(output), None, retry_on_any, &patterns, None);
+                    if decision.retry != want_retry || decision.fatal.as_deref() != want_fatal {
+                        return Err(format!(
+                            "{output:?} retry_on_any={retry_on_any} fatal={fatal:?} \
+                            default_fatal={default_fatal}: got retry={} fatal={:?}, \
+                            want retry={want_retry} fatal={want_fatal:?}",
+                            decision.retry, decision.fatal
+                        ));
+                    }
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "decision-trace",
+            wrapper_args: &[
+                "--decision-trace",
+                "trace.jsonl",
+                "--run-id",
+                "tracerun",
+                "--max-retries",
+                "3",
+                "--backoff-strategy",
+                "constant",
+                "--base-delay-ms",
+                "10",
+            ],
+            child_args: &["fails-then-succeeds", "--failures", "1"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                let text = fs::read_to_string(r.dir.join("trace.jsonl")).unwrap_or_default();
+                let records: Vec<serde_json::Value> = text
+                    .lines()
+                    .filter_map(|l| serde_json::from_str(l).ok())
+                    .collect();
+                let [retry, success] = records.as_slice() else {
+                    return Err(format!("expected 2 records, got: {text}"));
+                };
+                let want = [
+                    ("schema", serde_json::json!(crate::schema::TRACE)),
+                    ("run_id", "tracerun".into()),
+                    ("attempt", 1.into()),
+                    ("action", "retry".into()),
+                    ("code", 1.into()),
+                    ("retries_left", 3.into()),
+                    ("class", "server".into()),
+                    (
+                        "matched",
+                        serde_json::json!({ "pattern": "(?i)overloaded", "source": "built-in" }),
+                    ),
+                    ("fatal", serde_json::Value::Null),
+                    ("wait_ms", 10.into()),
+                ];
+                for (key, value) in want {
+                    if retry[key] != value {
+                        return Err(format!("first record {key}={}, want {value}", retry[key]));
+                    }
+                }
+                if success["action"] != "success" || success["attempt"] != 2 {
+                    return Err(format!("second record: {success}"));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "control-file-abort",
+            wrapper_args: &["--control-file", "abort.ctl", "--max-retries", "3"],
+            child_args: &["stalls", "--secs", "30", "--write-file", "abort.ctl=abort"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, crate::exit_codes::INTERRUPTED)?;
+                expect_attempts(r, 1)?;
+                if r.elapsed >= Duration::from_secs(10) {
+                    return Err(format!("the child was not killed: took {:?}", r.elapsed));
+                }
+                let reason =
+                    fs::read_to_string(r.dir.join("control-file-abort.reason")).unwrap_or_default();
+                if !reason.contains("reason=aborted") {
+                    return Err(format!("reason file: {reason}"));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "tag-attempts-env",
+            wrapper_args: &[
+                "--tag-attempts",
+                "env",
+                "--run-id",
+                "tagrun",
+                "--retry-on-any-error",
+                "--min-runtime-for-retry",
+                "0",
+                "--max-retries",
+                "1",
+                "--base-delay-ms",
+                "10",
+                "--json-events",
+                "events.jsonl",
+            ],
+            child_args: &[
+                "print-env",
+                "--var",
+                "RUSTY_CLAUDE_ATTEMPT_TAG",
+                "--status",
+                "1",
+            ],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_attempts(r, 2)?;
+                let stdout = String::from_utf8_lossy(&r.stdout);
+                let seen: Vec<&str> = stdout.lines().collect();
+                if seen
+                    != [
+                        "RUSTY_CLAUDE_ATTEMPT_TAG=tagrun-1",
+                        "RUSTY_CLAUDE_ATTEMPT_TAG=tagrun-2",
+                    ]
+                {
+                    return Err(format!("child saw {seen:?}"));
+                }
+                let events = fs::read_to_string(r.dir.join("events.jsonl")).unwrap_or_default();
+                let tags: Vec<_> = events
+                    .lines()
+                    .filter_map(|l| serde_json::from_str::<serde_json::Value>(l).ok())
+                    .filter(|e| e["event"] == "attempt_start")
+                    .map(|e| e["tag"].as_str().unwrap_or_default().to_string())
+                    .collect();
+                if tags != ["tagrun-1", "tagrun-2"] {
+                    return Err(format!("attempt_start tags {tags:?}"));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "tag-attempts-header",
+            wrapper_args: &[
+                "--tag-attempts",
+                "header",
+                "--tag-arg",
+                "--var={tag}",
+                "--run-id",
+                "tagrun",
+            ],
+            child_args: &["print-env"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                // The injected `--var=tagrun-1` makes the child print that (unset) name
+                if r.stdout != b"tagrun-1=\n" {
+                    return Err(format!("stdout {:?}", String::from_utf8_lossy(&r.stdout)));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "strict-utf8-encode",
+            wrapper_args: &[
+                "--max-retries",
+                "0",
+                "--strict-utf8",
+                "--json-events",
+                "utf8-encode.jsonl",
+            ],
+            // Writes of 28 bytes split the 4-byte character between reads
+            child_args: &[
+                "raw-bytes",
+                "--payload",
+                "malformed",
+                "--chunk",
+                "28",
+                "--status",
+                "1",
+            ],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                let event = attempt_end(r, "utf8-encode.jsonl")?;
+                let (_, err) = payload_bytes(Payload::Malformed, 0);
+                let line = &err[..err.len() - 1];
+                if !event["matched_line"].is_null()
+                    || event["matched_line_base64"] != crate::observe::base64(line)
+                {
+                    return Err(format!("unexpected excerpt in {event}"));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "strict-utf8-omit",
+            wrapper_args: &[
+                "--max-retries",
+                "0",
+                "--strict-utf8=omit",
+                "--json-events",
+                "utf8-omit.jsonl",
+            ],
+            child_args: &[
+                "raw-bytes",
+                "--payload",
+                "malformed",
+                "--chunk",
+                "28",
+                "--status",
+                "1",
+            ],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                let event = attempt_end(r, "utf8-omit.jsonl")?;
+                // 0xff 0xfe at 31..33, the truncated 3-byte sequence at 42..44
+                if event["matched_line"] != crate::utf8::OMITTED
+                    || event["matched_line_invalid"] != serde_json::json!([[31, 33], [42, 44]])
+                {
+                    return Err(format!("unexpected excerpt in {event}"));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "strict-utf8-excerpt-cut",
+            wrapper_args: &[
+                "--max-retries",
+                "0",
+                "--strict-utf8",
+                "--json-events",
+                "utf8-cut.jsonl",
+            ],
+            child_args: &[
+                "raw-bytes",
+                "--payload",
+                "long-line",
+                "--chunk",
+                "511",
+                "--status",
+                "1",
+            ],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                let event = attempt_end(r, "utf8-cut.jsonl")?;
+                let (_, err) = payload_bytes(Payload::LongLine, 0);
+                // The character straddling the limit is dropped, not reported as invalid
+                let want = std::str::from_utf8(&err[..crate::utf8::EXCERPT_BYTES - 2])
+                    .map_err(|e| e.to_string())?;
+                if event["matched_line"] != want || event.get("matched_line_base64").is_some() {
+                    return Err(format!("unexpected excerpt in {event}"));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "fast-exit-during-stdin-replay",
+            wrapper_args: FAST,
+            child_args: &["always-fatal", "--exit-code", "2"],
+            stdin: Some((0..10 * 1024 * 1024).map(|i| (i % 251) as u8).collect()),
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                // The child's own complaint and code, not a wrapper I/O error
+                expect_code(r, 2)?;
+                expect_attempts(r, 1)?;
+                if !r.stderr.contains("unknown option '--bogus'")
+                    || r.stderr.contains("internal error")
+                    || r.stderr.contains("stdin replay")
+                {
+                    return Err(format!("unexpected stderr: {}", r.stderr.trim()));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "no-tty-edit-abort",
+            wrapper_args: &["--no-tty", "--edit-on-retry"],
+            child_args: &["succeed"],
+            stdin: Some(b"prompt".to_vec()),
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 2)?;
+                if !r.stderr.contains("--edit-no-tty skip") || !r.stdout.is_empty() {
+                    return Err(format!("missing terminal not refused: {}", r.stderr.trim()));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "no-tty-edit-skip",
+            wrapper_args: &[
+                "--no-tty",
+                "--edit-on-retry",
+                "--edit-no-tty",
+                "skip",
+                "--base-delay-ms",
+                "10",
+            ],
+            child_args: &["echo-stdin", "--failures", "1"],
+            stdin: Some(b"prompt".to_vec()),
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                expect_attempts(r, 2)?;
+                if r.stdout != b"prompt" {
+                    return Err("the original input was not replayed".into());
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "exec-form",
+            wrapper_args: &["exec", "--base-delay-ms", "10"],
+            child_args: &["fails-then-succeeds", "--failures", "1"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                expect_attempts(r, 2)
+            },
+        },
+        Case {
+            name: "exec-flag-after-command",
+            wrapper_args: &["exec"],
+            child_args: &["succeed", "--max-retries=3"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 2)?;
+                if !r.stderr.contains("comes after the command") {
+                    return Err(format!("unexpected error: {}", r.stderr.trim()));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "repro-line",
+            wrapper_args: &["-v"],
+            child_args: &["print-env", "--var", "it's", "--var", "API_TOKEN=x"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                let want = if cfg!(windows) {
+                    "__fake-child print-env --var 'it''s' --var 'API_TOKEN=<redacted>'"
+                } else {
+                    r"__fake-child print-env --var 'it'\''s' --var 'API_TOKEN=<redacted>'"
+                };
+                if !r.stderr.contains(want) {
+                    return Err(format!("no `{want}` in: {}", r.stderr.trim()));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "artifacts-all",
+            wrapper_args: &[
+                "--base-delay-ms",
+                "10",
+                "--attempt-artifacts",
+                "artifacts-all",
+                "--attempt-artifacts-keep",
+                "all",
+            ],
+            child_args: &["fails-then-succeeds", "--failures", "2"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                expect_artifacts(r, "artifacts-all", &[1, 2, 3])
+            },
+        },
+        Case {
+            name: "artifacts-failed",
+            wrapper_args: &[
+                "--base-delay-ms",
+                "10",
+                "--attempt-artifacts",
+                "artifacts-failed",
+                "--attempt-artifacts-keep",
+                "failed",
+            ],
+            child_args: &["fails-then-succeeds", "--failures", "2"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                expect_artifacts(r, "artifacts-failed", &[1, 2])
+            },
+        },
+        Case {
+            name: "artifacts-last",
+            wrapper_args: &[
+                "--base-delay-ms",
+                "10",
+                "--attempt-artifacts",
+                "artifacts-last",
+                "--attempt-artifacts-keep",
+                "last",
+            ],
+            child_args: &["fails-then-succeeds", "--failures", "2"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                expect_artifacts(r, "artifacts-last", &[3])
+            },
+        },
+        Case {
+            name: "artifacts-gzip",
+            wrapper_args: &[
+                "--attempt-artifacts",
+                "artifacts-gzip",
+                "--compress-artifacts",
+            ],
+            child_args: &["huge-output", "--bytes", "1048576"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                let dir = r.dir.join("artifacts-gzip");
+                let file = fs::File::open(dir.join("attempt-01.stdout.gz"))
+                    .map_err(|e| format!("no compressed stdout: {e}"))?;
+                let mut unpacked = Vec::new();
+                flate2::read::GzDecoder::new(file)
+                    .read_to_end(&mut unpacked)
+                    .map_err(|e| format!("corrupt gzip stream: {e}"))?;
+                if unpacked != r.stdout {
+                    return Err(format!(
+                        "decompressed {} bytes, the child wrote {}",
+                        unpacked.len(),
+                        r.stdout.len()
+                    ));
+                }
+                let meta = fs::read_to_string(dir.join("attempt-01.meta.json")).unwrap_or_default();
+                if !meta.contains("\"stdout_compressed_bytes\"") {
+                    return Err("meta.json lacks stdout_compressed_bytes".into());
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "env-override",
+            wrapper_args: &["--base-delay-ms", "10"],
+            child_args: &["fails-then-succeeds", "--failures", "1"],
+            stdin: None,
+            observe: false,
+            env: &[("RUSTY_CLAUDE_MAX_RETRIES", "0")],
+            check: |r, _| {
+                expect_code(r, 1)?;
+                expect_attempts(r, 1)
+            },
+        },
+        Case {
+            name: "no-env",
+            wrapper_args: &["--base-delay-ms", "10", "--no-env"],
+            child_args: &["fails-then-succeeds", "--failures", "1"],
+            stdin: None,
+            observe: false,
+            env: &[("RUSTY_CLAUDE_MAX_RETRIES", "0")],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                expect_attempts(r, 2)
+            },
+        },
+        Case {
+            name: "env-only-prefix",
+            wrapper_args: &[
+                "--base-delay-ms",
+                "10",
+                "--env-only-prefix",
+                "RUSTY_CLAUDE_",
+            ],
+            child_args: &["fails-then-succeeds", "--failures", "1"],
+            stdin: None,
+            observe: false,
+            env: &[("CLAUDE_SUPERVISOR_MAX_RETRIES", "0")],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                expect_attempts(r, 2)
+            },
+        },
+        Case {
+            name: "no-env-print-config",
+            wrapper_args: &["--no-env", "--print-config"],
+            child_args: &["succeed"],
+            stdin: None,
+            observe: false,
+            env: &[("RUSTY_CLAUDE_MAX_RETRIES", "9")],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                let stdout = String::from_utf8_lossy(&r.stdout);
+                let line = stdout
+                    .lines()
+                    .find(|l| l.starts_with("max_retries"))
+                    .unwrap_or_default();
+                if !line.contains(" 6 ")
+                    || !line.contains("RUSTY_CLAUDE_MAX_RETRIES ignored (--no-env)")
+                {
+                    return Err(format!("unexpected max_retries line: {line:?}"));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "config-file",
+            wrapper_args: &["--config", "config-fields.toml"],
+            child_args: &["always-fatal", "--exit-code", "3"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 3)?;
+                expect_attempts(r, 3)?;
+                if !r
+                    .stderr
+                    .contains("config-fields.toml:9: unknown key `retry_budget`")
+                {
+                    return Err("no warning about the unknown key".into());
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "config-unknown-key-strict",
+            wrapper_args: &["--config", "config-fields.toml", "--strict-config"],
+            child_args: &["succeed"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, crate::exit_codes::CONFIG_ERROR)?;
+                if !r.stderr.contains(
+                    "config-fields.toml:9: unknown key `retry_budget` in the config file \
+                    (refused under --strict-config)",
+                ) {
+                    return Err(format!("unknown key not refused: {}", r.stderr.trim()));
+                }
+                if !r.stdout.is_empty() {
+                    return Err("the child ran anyway".into());
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "config-precedence",
+            wrapper_args: &["--max-retries", "5", "--print-config"],
+            child_args: &["succeed"],
+            stdin: None,
+            observe: false,
+            env: &[
+                ("RUSTY_CLAUDE_CONFIG", "config-fields.toml"),
+                ("RUSTY_CLAUDE_BASE_MS", "15"),
+                ("RUSTY_CLAUDE_MAX_RETRIES", "9"),
+            ],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                let stdout = String::from_utf8_lossy(&r.stdout);
+                for (key, value, source) in [
+                    ("config", "config-fields.toml", "(env RUSTY_CLAUDE_CONFIG)"),
+                    ("max_retries", "5", "(flag)"),
+                    ("base_delay_ms", "15", "(env RUSTY_CLAUDE_BASE_MS)"),
+                    ("max_delay_ms", "20", "(config config-fields.toml:4)"),
+                    (
+                        "patterns",
+                        r#"["unknown option '--(bogus|other)'", "no such \\w+"]"#,
+                        "(config config-fields.toml:5)",
+                    ),
+                ] {
+                    let line = stdout
+                        .lines()
+                        .find(|l| l.split_whitespace().next() == Some(key))
+                        .unwrap_or_default();
+                    if !line.contains(&format!(" {value} ")) || !line.ends_with(source) {
+                        return Err(format!("unexpected {key} line: {line:?}"));
+                    }
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "config-malformed",
+            wrapper_args: &["--config", "config-malformed.toml"],
+            child_args: &["succeed"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, crate::exit_codes::CONFIG_ERROR)?;
+                if !r.stderr.contains("error: config-malformed.toml:3: ") {
+                    return Err(format!("unexpected error: {}", r.stderr.trim()));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "claude-settings-auto",
+            wrapper_args: &["--claude-settings", "auto"],
+            child_args: &["always-fatal", "--exit-code", "3"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 3)?;
+                expect_attempts(r, 5)?;
+                if !r
+                    .stderr
+                    .contains("unknown key `rustyClaude.retryBudget` in the Claude settings block")
+                {
+                    return Err("no warning about the unknown key".into());
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "claude-settings-precedence",
+            wrapper_args: &[
+                "--config",
+                "config-fields.toml",
+                "--claude-settings",
+                "auto",
+                "--print-config",
+            ],
+            child_args: &["succeed"],
+            stdin: None,
+            observe: false,
+            env: &[("RUSTY_CLAUDE_CAP_MS", "40")],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                let stdout = String::from_utf8_lossy(&r.stdout);
+                for (key, value, source) in [
+                    ("max_retries", "2", "(config config-fields.toml:2)"),
+                    ("max_delay_ms", "40", "(env RUSTY_CLAUDE_CAP_MS)"),
+                    (
+                        "attempt_timeout_secs",
+                        "30",
+                        "settings.json rustyClaude.attemptTimeoutSecs)",
+                    ),
+                ] {
+                    let line = stdout
+                        .lines()
+                        .find(|l| l.split_whitespace().next() == Some(key))
+                        .unwrap_or_default();
+                    if !line.contains(&format!(" {value} ")) || !line.ends_with(source) {
+                        return Err(format!("unexpected {key} line: {line:?}"));
+                    }
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "claude-settings-broken-elsewhere",
+            wrapper_args: &[
+                "--claude-settings",
+                "broken-settings.json",
+                "--print-config",
+            ],
+            child_args: &["succeed"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                let stdout = String::from_utf8_lossy(&r.stdout);
+                for (key, value) in [("max_retries", "7"), ("force_tee", "true")] {
+                    let line = stdout
+                        .lines()
+                        .find(|l| l.split_whitespace().next() == Some(key))
+                        .unwrap_or_default();
+                    if !line.contains(&format!(" {value} "))
+                        || !line.contains("(claude settings broken-settings.json rustyClaude.")
+                    {
+                        return Err(format!("unexpected {key} line: {line:?}"));
+                    }
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "claude-settings-bad-key",
+            wrapper_args: &["--claude-settings", "bad-settings.json"],
+            child_args: &["succeed"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, crate::exit_codes::CONFIG_ERROR)?;
+                if !r
+                    .stderr
+                    .contains("bad-settings.json: `rustyClaude.maxRetries` must be")
+                {
+                    return Err(format!("unexpected error: {}", r.stderr.trim()));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "simulate-transcript",
+            wrapper_args: &["simulate", "--transcript", "transcript"],
+            child_args: &[],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                let out = String::from_utf8_lossy(&r.stdout);
+                for want in [
+                    "attempt 1: exit 1 after 4.2s, 91B of output",
+                    "matched  `(?i)overloaded` (server)",
+                    "would    retry in",
+                    "did      retry in 1.2s",
+                    "attempt 2: exit 0",
+                    "would    success",
+                    "same decisions as the recorded run",
+                ] {
+                    if !out.contains(want) {
+                        return Err(format!("no `{want}` in:\n{out}"));
+                    }
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "simulate-candidate",
+            wrapper_args: &[
+                "simulate",
+                "--transcript",
+                "transcript",
+                "--settings",
+                "candidate.toml",
+            ],
+            child_args: &[],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                let out = String::from_utf8_lossy(&r.stdout);
+                for want in [
+                    "would    stop (exhausted)",
+                    "did      retry in 1.2s  <- differs",
+                    "these settings stop at attempt 1; the run went on to attempt 2",
+                    "decisions differ from the recorded run at attempt 1",
+                ] {
+                    if !out.contains(want) {
+                        return Err(format!("no `{want}` in:\n{out}"));
+                    }
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "simulate-round-trip",
+            wrapper_args: &[
+                "--max-retries",
+                "3",
+                "--base-delay-ms",
+                "10",
+                "--max-delay-ms",
+                "50",
+                "--attempt-artifacts",
+                "simulate-round-trip",
+                "--compress-artifacts",
+            ],
+            child_args: &["fails-then-succeeds", "--failures", "2"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                expect_attempts(r, 3)?;
+                let same = simulate(r, FAST, "simulate-round-trip")?;
+                if !same.contains("same decisions as the recorded run")
+                    || same.matches("would    retry").count() != 2
+                {
+                    return Err(format!("replay under the same flags:\n{same}"));
+                }
+                let fatal = simulate(
+                    r,
+                    &["--fatal-patterns", "overloaded"],
+                    "simulate-round-trip",
+                )?;
+                if !fatal.contains("would    stop (fatal pattern `overloaded`)")
+                    || !fatal.contains("differ from the recorded run at attempt 1")
+                {
+                    return Err(format!("replay with a fatal pattern:\n{fatal}"));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "log-file",
+            wrapper_args: FAST,
+            child_args: &["fails-then-succeeds", "--failures", "1"],
+            stdin: None,
+            observe: false,
+            env: &[("CLAUDE_SUPERVISOR_LOG", "log-file.jsonl")],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                let text = fs::read_to_string(r.dir.join("log-file.jsonl"))
+                    .map_err(|e| format!("no log file: {e}"))?;
+                let records: Vec<serde_json::Value> = text
+                    .lines()
+                    .map(serde_json::from_str)
+                    .collect::<Result<_, _>>()
+                    .map_err(|e| format!("unparseable log line: {e}"))?;
+                let [first, second, summary] = &records[..] else {
+                    return Err(format!("expected 2 attempts and a summary, got:\n{text}"));
+                };
+                if first["record"] != "attempt"
+                    || first["attempt"] != 1
+                    || first["code"] != 1
+                    || first["retry"] != true
+                    || first["matched"] != "(?i)overloaded"
+                    || !first["delay_ms"].is_u64()
+                    || first["stderr_bytes"] == 0
+                    || first["args"][0] != "__fake-child"
+                {
+                    return Err(format!("first attempt record: {first}"));
+                }
+                if second["attempt"] != 2 || second["retry"] != false || second["stdout_bytes"] != 3
+                {
+                    return Err(format!("second attempt record: {second}"));
+                }
+                if summary["record"] != "summary"
+                    || summary["attempts"] != 2
+                    || summary["reason"] != "success"
+                    || summary["run_id"] != first["run_id"]
+                {
+                    return Err(format!("summary record: {summary}"));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "self-mem-accounting",
+            wrapper_args: &["-vv", "--log-file", "self-mem-accounting.jsonl"],
+            child_args: &["huge-output", "--bytes", "1048576"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                if !r
+                    .stderr
+                    .contains("memory: stdin 0B, stdout 1.0MB, stderr 0B")
+                {
+                    return Err(format!("no buffer report in: {}", r.stderr.trim()));
+                }
+                // The stdout capture plus the match text: stdout, a newline, and no stderr
+                let peak = peak_buffer_bytes(r, "self-mem-accounting.jsonl")?;
+                if peak != 2 * 1048576 + 1 {
+                    return Err(format!(
+                        "peak_buffer_bytes {peak}, expected {}",
+                        2 * 1048576 + 1
+                    ));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "self-mem-limit-spill",
+            wrapper_args: &[
+                "--buffer-output",
+                "--self-mem-limit",
+                "256KiB",
+                "--log-file",
+                "self-mem-limit-spill.jsonl",
+            ],
+            child_args: &["huge-output", "--bytes", "1048576"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                if r.stdout.len() != 1048576 {
+                    return Err(format!(
+                        "released {} of the 1048576 held-back bytes",
+                        r.stdout.len()
+                    ));
+                }
+                if !r.stderr.contains("kept only the last") {
+                    return Err(format!("no truncation note in: {}", r.stderr.trim()));
+                }
+                let peak = peak_buffer_bytes(r, "self-mem-limit-spill.jsonl")?;
+                if peak > 256 * 1024 {
+                    return Err(format!("buffers peaked at {peak} bytes, over the limit"));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "max-capture-bytes",
+            wrapper_args: &[
+                "-v",
+                "--max-retries",
+                "1",
+                "--base-delay-ms",
+                "10",
+                "--max-capture-bytes",
+                "1KiB",
+            ],
+            child_args: &[
+                "raw-bytes",
+                "--payload",
+                "noisy-overload",
+                "--bytes",
+                "60000",
+                "--status",
+                "1",
+            ],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 1)?;
+                // The overload line is longer than the cap, so only the overlap keeps it whole
+                if !r
+                    .stderr
+                    .contains("giving up after 2 attempt(s): retries exhausted")
+                {
+                    return Err(format!(
+                        "the overload in the tail wasn't retried: {}",
+                        r.stderr
+                    ));
+                }
+                if !r
+                    .stderr
+                    .contains("--max-capture-bytes: matching on the last 3.0KB")
+                {
+                    return Err("no truncation note under -v".to_string());
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "server-restart",
+            wrapper_args: &[
+                "--server-mode",
+                "--ready-pattern",
+                "listening",
+                "--max-restarts-per-hour",
+                "1",
+                "--base-delay-ms",
+                "10",
+            ],
+            child_args: &[
+                "server",
+                "--startup",
+                "0.1",
+                "--secs",
+                "0.2",
+                "--exit-code",
+                "3",
+            ],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 3)?;
+                expect_attempts(r, 2)?;
+                if !r.stderr.contains("server ready") {
+                    return Err("readiness was never reported".into());
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "class-budget-exhausted",
+            wrapper_args: &[
+                "--class-budget",
+                "server=2",
+                "--max-retries",
+                "5",
+                "--base-delay-ms",
+                "10",
+            ],
+            child_args: &["sequence", "--steps", "server"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 1)?;
+                expect_attempts(r, 3)?;
+                if !r.stderr.contains(
+                    "server retry budget exhausted (2 retries per --class-budget) \
+                     after 3 attempt(s); not retrying",
+                ) {
+                    return Err(format!("no class budget message: {}", r.stderr.trim()));
+                }
+                let reason = fs::read_to_string(r.dir.join("class-budget-exhausted.reason"))
+                    .unwrap_or_default();
+                if !reason.lines().any(|l| l == "exhausted_class=server") {
+                    return Err(format!("reason file lacks the class: {reason}"));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "class-budget-mixed",
+            wrapper_args: &[
+                "--class-budget",
+                "server=1,network=1",
+                "--max-retries",
+                "9",
+                "--base-delay-ms",
+                "10",
+                "--max-delay-ms",
+                "50",
+                "--pattern-delay",
+                r"(?i)Too\s*Many\s*Requests=0",
+            ],
+            child_args: &[
+                "sequence",
+                "--steps",
+                "server,ratelimit,network,ratelimit,server",
+            ],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 1)?;
+                expect_attempts(r, 5)?;
+                if !r
+                    .stderr
+                    .contains("server retry budget exhausted (1 retries")
+                {
+                    return Err(format!(
+                        "not stopped by the server budget: {}",
+                        r.stderr.trim()
+                    ));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "class-budget-under-global",
+            wrapper_args: &[
+                "--class-budget",
+                "server=5",
+                "--max-retries",
+                "2",
+                "--base-delay-ms",
+                "10",
+            ],
+            child_args: &["sequence", "--steps", "server,server,server,ok"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 1)?;
+                expect_attempts(r, 3)?;
+                if r.stderr.contains("retry budget exhausted") {
+                    return Err("a class budget was blamed for --max-retries".into());
+                }
+                let reason = fs::read_to_string(r.dir.join("class-budget-under-global.reason"))
+                    .unwrap_or_default();
+                if reason.contains("exhausted_class=") {
+                    return Err(format!("reason file names a class: {reason}"));
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "server-no-retry-code",
+            wrapper_args: &[
+                "--server-mode",
+                "--no-retry-exit-codes",
+                "3",
+                "--base-delay-ms",
+                "10",
+            ],
+            child_args: &["server", "--secs", "0.1", "--exit-code", "3"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 3)?;
+                expect_attempts(r, 1)?;
+                if !r
+                    .stderr
+                    .contains("(code=3), which is in --no-retry-exit-codes; not restarting")
+                {
+                    return Err(format!("restarted or silent: {}", r.stderr.trim()));
+                }
+                if r.stderr.contains("ignored here") {
+                    return Err("warned that the list is ignored".into());
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "server-never-ready",
+            wrapper_args: &[
+                "--server-mode",
+                "--ready-pattern",
+                "listening",
+                "--ready-timeout",
+                "300ms",
+                "--max-restarts-per-hour",
+                "1",
+                "--base-delay-ms",
+                "10",
+            ],
+            child_args: &["server", "--startup", "30"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_attempts(r, 2)?;
+                if !r.stderr.contains("did not become ready") {
+                    return Err("ready timeout was not reported as a failed start".into());
+                }
+                if r.elapsed > Duration::from_secs(10) {
+                    return Err(format!("ready timeout not enforced ({:?})", r.elapsed));
+                }
+                Ok(())
+            },
+        },
+    ];
+    // Faults that can't be provoked from outside; the flag exists in `chaos` builds only
+    if cfg!(feature = "chaos") {
+        // Each way a run ends releases what it registered exactly once, including the
+        // process tree of every attempt
+        cases.push(Case {
+            name: "chaos-shutdown-success",
+            wrapper_args: &[
+                "--chaos",
+                "release-log:path=released-success.log",
+                "--isolated-home=home-success",
+                "--feed-previous-error",
+                "--previous-error",
+                "--base-delay-ms",
+                "10",
+            ],
+            child_args: &["fails-then-succeeds", "--failures", "1"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                expect_released(
+                    r,
+                    "released-success.log",
+                    "home-success",
+                    &[
+                        "child process tree",
+                        "child process tree",
+                        "isolated home",
+                        "previous-error file",
+                    ],
+                )
+            },
+        });
+        cases.push(Case {
+            name: "chaos-shutdown-exhausted",
+            wrapper_args: &[
+                "--chaos",
+                "release-log:path=released-exhausted.log",
+                "--isolated-home=home-exhausted",
+                "--feed-previous-error",
+                "--previous-error",
+                "--max-retries",
+                "2",
+                "--base-delay-ms",
+                "10",
+            ],
+            child_args: &["fails-then-succeeds", "--failures", "9"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 1)?;
+                expect_released(
+                    r,
+                    "released-exhausted.log",
+                    "home-exhausted",
+                    &[
+                        "child process tree",
+                        "child process tree",
+                        "child process tree",
+                        "isolated home",
+                        "previous-error file",
+                        "previous-error file",
+                    ],
+                )
+            },
+        });
+        cases.push(Case {
+            name: "chaos-shutdown-fatal",
+            wrapper_args: &[
+                "--chaos",
+                "release-log:path=released-fatal.log",
+                "--isolated-home=home-fatal",
+            ],
+            child_args: &["always-fatal", "--exit-code", "3"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 3)?;
+                expect_released(
+                    r,
+                    "released-fatal.log",
+                    "home-fatal",
+                    &["child process tree", "isolated home"],
+                )
+            },
+        });
+        cases.push(Case {
+            name: "chaos-shutdown-panic",
+            wrapper_args: &[
+                "--chaos",
+                "release-log:path=released-panic.log",
+                "--chaos",
+                "panic:attempt=2",
+                "--isolated-home=home-panic",
+                "--feed-previous-error",
+                "--previous-error",
+                "--base-delay-ms",
+                "10",
+            ],
+            child_args: &["fails-then-succeeds", "--failures", "1"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 101)?;
+                if !r.stderr.contains("chaos: injected panic before attempt 2") {
+                    return Err(format!("no injected panic: {}", r.stderr.trim()));
+                }
+                expect_released(
+                    r,
+                    "released-panic.log",
+                    "home-panic",
+                    &["child process tree", "isolated home", "previous-error file"],
+                )
+            },
+        });
+        if cfg!(unix) {
+            cases.push(Case {
+                name: "chaos-shutdown-signal",
+                wrapper_args: &[
+                    "--chaos",
+                    "release-log:path=released-signal.log",
+                    "--isolated-home=home-signal",
+                ],
+                child_args: &["stalls", "--secs", "0.5", "--signal-parent"],
+                stdin: None,
+                observe: true,
+                env: &[],
+                check: |r, _| {
+                    // 128 + SIGTERM, the signal passed on to the attempt
+                    expect_code(r, 143)?;
+                    if r.socket_left {
+                        return Err("socket file was left behind".into());
+                    }
+                    expect_released(
+                        r,
+                        "released-signal.log",
+                        "home-signal",
+                        &["child process tree", "isolated home", "observer socket"],
+                    )
+                },
+            });
+        }
+        cases.push(Case {
+            name: "chaos-tee-write-error",
+            wrapper_args: &["--chaos", "tee-write-error:after=1MiB"],
+            child_args: &["huge-output", "--bytes", "8388608"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                // The child must not stay blocked on a pipe nobody drains any more
+                expect_code(r, 125)?;
+                if !r.stderr.contains("injected tee write error") {
+                    return Err(format!("write error not reported: {}", r.stderr.trim()));
+                }
+                let forwarded = r.stdout.len();
+                if !(1 << 20..(1 << 20) + 8192).contains(&forwarded) {
+                    return Err(format!("{forwarded} bytes forwarded, expected about 1MiB"));
+                }
+                Ok(())
+            },
+        });
+        cases.push(Case {
+            name: "chaos-slow-consumer",
+            wrapper_args: &["--chaos", "slow-consumer:delay=20ms"],
+            child_args: &["huge-output", "--bytes", "65536"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                if r.stdout.len() != 65536 || r.elapsed < Duration::from_millis(20) {
+                    return Err(format!(
+                        "{} bytes in {:?} through a slow consumer",
+                        r.stdout.len(),
+                        r.elapsed
+                    ));
+                }
+                Ok(())
+            },
+        });
+        cases.push(Case {
+            name: "chaos-spawn-fail",
+            wrapper_args: &[
+                "--chaos",
+                "spawn-fail:attempt=2:kind=notfound",
+                "--base-delay-ms",
+                "10",
+            ],
+            child_args: &["fails-then-succeeds", "--failures", "1"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                // A spawn failure mid-run ends it at once; it is not retried
+                expect_code(r, 127)?;
+                expect_attempts(r, 2)
+            },
+        });
+        cases.push(Case {
+            name: "chaos-sleep-skew-ahead",
+            wrapper_args: &[
+                "--chaos",
+                "sleep-skew:-1h",
+                "--base-delay-ms",
+                "30000",
+                "--max-delay-ms",
+                "30000",
+            ],
+            child_args: &["fails-then-succeeds", "--failures", "1"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                expect_attempts(r, 2)?;
+                if r.elapsed > Duration::from_secs(10) {
+                    return Err(format!("backoff not cut short: {:?}", r.elapsed));
+                }
+                Ok(())
+            },
+        });
+        cases.push(Case {
+            name: "chaos-sleep-skew-behind",
+            wrapper_args: &["--chaos", "sleep-skew:+1s", "--base-delay-ms", "10"],
+            child_args: &["fails-then-succeeds", "--failures", "1"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                expect_attempts(r, 2)?;
+                if r.elapsed < Duration::from_secs(1) {
+                    return Err(format!("backoff not stretched: {:?}", r.elapsed));
+                }
+                Ok(())
+            },
+        });
+    }
+    if cfg!(unix) {
+        cases.push(Case {
+            name: "initial-input-pty",
+            wrapper_args: &[],
+            child_args: &["succeed"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                use std::ffi::OsStr;
+                let script = fake_child_script(&r.dir, "prompt", "prompt")?;
+                let (code, shown) = run_on_terminal(
+                    &r.dir,
+                    &[
+                        OsStr::new("--pty"),
+                        OsStr::new("--initial-input"),
+                        OsStr::new("continue where we left off"),
+                        OsStr::new("--cmd"),
+                        script.as_os_str(),
+                    ],
+                )?;
+                if code != 0 {
+                    return Err(format!("exit {code}: {shown}"));
+                }
+                if !shown.contains("got: continue where we left off") {
+                    return Err(format!("the prompt did not get the input: {shown:?}"));
+                }
+                Ok(())
+            },
+        });
+        cases.push(Case {
+            name: "require-stdin-tty",
+            wrapper_args: &[],
+            child_args: &["succeed"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                use std::ffi::OsStr;
+                let state = r.dir.join("require-stdin-tty.session");
+                let args = format!("succeed --state '{}'", state.display());
+                let script = fake_child_script(&r.dir, "session", &args)?;
+                let (code, shown) = run_on_terminal(
+                    &r.dir,
+                    &[
+                        OsStr::new("--require-stdin"),
+                        OsStr::new("--cmd"),
+                        script.as_os_str(),
+                    ],
+                )?;
+                if code != crate::exit_codes::NO_STDIN {
+                    return Err(format!("exit {code}: {shown}"));
+                }
+                if !shown.contains("stdin is a terminal, not a pipe; not starting the child") {
+                    return Err(format!("no refusal: {shown:?}"));
+                }
+                if state.exists() {
+                    return Err("the session was started".into());
+                }
+                Ok(())
+            },
+        });
+        cases.push(Case {
+            name: "interactive-no-relaunch",
+            wrapper_args: &[],
+            child_args: &["succeed"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                use std::ffi::OsStr;
+                let state = r.dir.join("interactive-no-relaunch.session");
+                let args = format!("fails-then-succeeds --state '{}'", state.display());
+                let script = fake_child_script(&r.dir, "session", &args)?;
+                let (code, shown) =
+                    run_on_terminal(&r.dir, &[OsStr::new("--cmd"), script.as_os_str()])?;
+                if code != 1 {
+                    return Err(format!("exit {code}: {shown}"));
+                }
+                if !shown.contains(
+                    "interactive session exited with code 1; not relaunching \
+                    (pass --interactive-retry to start a new session)",
+                ) {
+                    return Err(format!("no refusal to relaunch: {shown:?}"));
+                }
+                let runs = fs::read_to_string(&state).map_err(|e| e.to_string())?;
+                if runs.trim() != "1" {
+                    return Err(format!("{} sessions ran", runs.trim()));
+                }
+                Ok(())
+            },
+        });
+        cases.push(Case {
+            name: "interactive-retry-banner",
+            wrapper_args: &[],
+            child_args: &["succeed"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                use std::ffi::OsStr;
+                let state = r.dir.join("interactive-retry-banner.session");
+                let args = format!("fails-then-succeeds --state '{}'", state.display());
+                let script = fake_child_script(&r.dir, "session", &args)?;
+                let started = Instant::now();
+                let (code, shown) = run_on_terminal(
+                    &r.dir,
+                    &[
+                        OsStr::new("--interactive-retry"),
+                        OsStr::new("--base-delay-ms"),
+                        OsStr::new("10"),
+                        OsStr::new("--cmd"),
+                        script.as_os_str(),
+                    ],
+                )?;
+                if code != 0 {
+                    return Err(format!("exit {code}: {shown}"));
+                }
+                let banner = shown
+                    .find(
+                        "previous session exited with code 1; starting a new session in 5s, \
+                        Ctrl-C to abort",
+                    )
+                    .ok_or_else(|| format!("no relaunch banner: {shown:?}"))?;
+                if !shown[banner..].contains("ok") {
+                    return Err(format!("the new session did not run after it: {shown:?}"));
+                }
+                // The banner stays up for the grace period, however short the backoff
+                if started.elapsed() < Duration::from_secs(5) {
+                    return Err(format!("relaunched after {:?}", started.elapsed()));
+                }
+                Ok(())
+            },
+        });
+        cases.push(Case {
+            name: "version-probe-stderr",
+            wrapper_args: &[],
+            child_args: &["succeed"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                // More stderr than a pipe holds, before the banner
+                let script = r.dir.join("chatty-version.sh");
+                fs::write(
+                    &script,
+                    "#!/bin/sh\nhead -c 262144 /dev/zero >&2\necho '1.0.44 (Claude Code)'\n",
+                )
+                .map_err(|e| e.to_string())?;
+                #[cfg(unix)]
+                {
+                    use std::os::unix::fs::PermissionsExt;
+                    fs::set_permissions(&script, fs::Permissions::from_mode(0o755))
+                        .map_err(|e| e.to_string())?;
+                }
+                let started = Instant::now();
+                let found = crate::version::probe(&script.to_string_lossy())?;
+                if found.to_string() != "1.0.44" {
+                    return Err(format!("probed {found}"));
+                }
+                if started.elapsed() > Duration::from_secs(5) {
+                    return Err(format!("the probe took {:?}", started.elapsed()));
+                }
+                Ok(())
+            },
+        });
+        cases.push(Case {
+            name: "cmd-not-executable",
+            wrapper_args: &[],
+            child_args: &["succeed"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                let script = r.dir.join("not-executable.sh");
+                fs::write(&script, "#!/bin/sh\necho ran\n").map_err(|e| e.to_string())?;
+                let (code, stderr) = run_cmd(&script, &r.dir)?;
+                if code != crate::exit_codes::CANNOT_EXECUTE {
+                    return Err(format!("expected exit 126, got {code}: {}", stderr.trim()));
+                }
+                if !stderr.contains("failed to spawn") {
+                    return Err(format!("no spawn error: {}", stderr.trim()));
+                }
+                Ok(())
+            },
+        });
+        cases.push(Case {
+            name: "observe-socket",
+            wrapper_args: &[],
+            child_args: &["stalls", "--secs", "1"],
+            stdin: None,
+            observe: true,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                if !r.observed.contains(r#""type":"hello""#) {
+                    return Err("observer got no hello frame".into());
+                }
+                // base64 of "end\n", written after the observer connected
+                if !r
+                    .observed
+                    .contains(r#""stream":"stdout","data":"ZW5kCg==""#)
+                {
+                    return Err("observer missed the live stdout chunk".into());
+                }
+                if !r.observed.contains(r#""event":"attempt_end""#) {
+                    return Err("observer missed the attempt_end event".into());
+                }
+                if r.socket_left {
+                    return Err("socket file was not removed on exit".into());
+                }
+                Ok(())
+            },
+        });
+        cases.push(Case {
+            name: "control-socket-drain",
+            wrapper_args: &["--retry-on-any-error", "--max-retries", "3"],
+            child_args: &[
+                "stalls",
+                "--secs",
+                "1",
+                "--status",
+                "1",
+                "--send-socket",
+                r#"control-socket-drain.sock={"cmd":"drain"}"#,
+            ],
+            stdin: None,
+            observe: true,
+            env: &[],
+            check: |r, _| {
+                // The attempt in flight finishes with its own code, then nothing is retried
+                expect_code(r, 1)?;
+                expect_attempts(r, 1)?;
+                if !String::from_utf8_lossy(&r.stdout).contains("end") {
+                    return Err("the attempt was cut short".into());
+                }
+                if !r.stderr.contains("not retrying: drain requested") {
+                    return Err(format!("no drain explanation in: {}", r.stderr.trim()));
+                }
+                if !r.observed.contains(r#""event":"control","#) {
+                    return Err("observer missed the control event".into());
+                }
+                let reason = fs::read_to_string(r.dir.join("control-socket-drain.reason"))
+                    .unwrap_or_default();
+                if !reason.contains("reason=drained") {
+                    return Err(format!("reason file: {reason}"));
+                }
+                Ok(())
+            },
+        });
+        cases.push(Case {
+            name: "on-retry-env-file",
+            wrapper_args: &[
+                "--retry-on-any-error",
+                "--min-runtime-for-retry",
+                "0",
+                "--max-retries",
+                "1",
+                "--base-delay-ms",
+                "10",
+                "--on-retry-cmd",
+                r#"echo "env file $RUSTY_CLAUDE_ENV_FILE" >&2; printf 'TOKEN_X=fresh\nnot an assignment\n' > "$RUSTY_CLAUDE_ENV_FILE""#,
+            ],
+            child_args: &["print-env", "--var", "TOKEN_X", "--status", "1"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_attempts(r, 2)?;
+                let stdout = String::from_utf8_lossy(&r.stdout);
+                let seen: Vec<&str> = stdout.lines().collect();
+                if seen != ["TOKEN_X=", "TOKEN_X=fresh"] {
+                    return Err(format!("child saw {seen:?}"));
+                }
+                if !r.stderr.contains("line 2: expected KEY=VALUE") {
+                    return Err("malformed env file line was not reported".into());
+                }
+                let file = r
+                    .stderr
+                    .lines()
+                    .find_map(|l| l.strip_prefix("env file "))
+                    .ok_or("hook did not run")?;
+                if Path::new(file).exists() {
+                    return Err(format!("{file} was not removed"));
+                }
+                Ok(())
+            },
+        });
+        cases.push(Case {
+            name: "hook-context",
+            wrapper_args: &[
+                "--max-retries",
+                "1",
+                "--base-delay-ms",
+                "10",
+                "--max-delay-ms",
+                "50",
+                "--on-retry-cmd",
+                "env | grep ^RUSTY_CLAUDE_ > hook-context-retry.env",
+                "--on-exhausted-cmd",
+                "env | grep ^RUSTY_CLAUDE_ > hook-context-exhausted.env; exit 4",
+            ],
+            child_args: &["fails-then-succeeds", "--failures", "5"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 1)?;
+                expect_attempts(r, 2)?;
+                let read = |file: &str| {
+                    let text =
+                        fs::read_to_string(r.dir.join(file)).map_err(|e| format!("{file}: {e}"))?;
+                    let vars: Vec<(String, String)> = text
+                        .lines()
+                        .filter_map(|l| l.split_once('='))
+                        .map(|(k, v)| (k.to_string(), v.to_string()))
+                        .collect();
+                    Ok::<_, String>(vars)
+                };
+                let get = |vars: &[(String, String)], key: &str| {
+                    vars.iter()
+                        .find(|(k, _)| k == key)
+                        .map(|(_, v)| v.clone())
+                        .ok_or(format!("{key} not set"))
+                };
+                let retry = read("hook-context-retry.env")?;
+                let exhausted = read("hook-context-exhausted.env")?;
+                let want = [
+                    ("RUSTY_CLAUDE_ATTEMPT", "2", "2"),
+                    ("RUSTY_CLAUDE_EXIT_CODE", "1", "1"),
+                    ("RUSTY_CLAUDE_CLASS", "server", "server"),
+                ];
+                for (key, at_retry, at_exhausted) in want {
+                    let (a, b) = (get(&retry, key)?, get(&exhausted, key)?);
+                    if a != at_retry || b != at_exhausted {
+                        return Err(format!("{key}: {a:?} before the retry, {b:?} at the end"));
+                    }
+                }
+                if get(&retry, "RUSTY_CLAUDE_MATCHED")?.is_empty() {
+                    return Err("the retry hook did not see the matched pattern".into());
+                }
+                let delay = get(&retry, "RUSTY_CLAUDE_NEXT_DELAY_MS")?;
+                if delay.parse::<u64>().map_or(true, |ms| ms > 50) {
+                    return Err(format!("RUSTY_CLAUDE_NEXT_DELAY_MS was {delay:?}"));
+                }
+                if !get(&exhausted, "RUSTY_CLAUDE_NEXT_DELAY_MS")?.is_empty() {
+                    return Err("the exhausted hook was told of a next delay".into());
+                }
+                if !get(&retry, "RUSTY_CLAUDE_CHILD_CMD")?.contains("fails-then-succeeds") {
+                    return Err("RUSTY_CLAUDE_CHILD_CMD lacks the child's args".into());
+                }
+                if !r.stderr.contains("--on-exhausted-cmd exited with code 4") {
+                    return Err("the failing exhausted hook was not reported".into());
+                }
+                Ok(())
+            },
+        });
+        cases.push(Case {
+            name: "hook-timeout",
+            wrapper_args: &[
+                "--max-retries",
+                "1",
+                "--base-delay-ms",
+                "10",
+                "--hook-timeout",
+                "200ms",
+                "--on-retry-cmd",
+                "sleep 5",
+            ],
+            child_args: &["fails-then-succeeds", "--failures", "1"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                expect_attempts(r, 2)?;
+                if !r
+                    .stderr
+                    .contains("--on-retry-cmd timed out after 200ms; retrying anyway")
+                {
+                    return Err("the hung hook was not reported".into());
+                }
+                if r.elapsed > Duration::from_secs(3) {
+                    return Err(format!("run took {:?}; the hook was not killed", r.elapsed));
+                }
+                Ok(())
+            },
+        });
+        cases.push(Case {
+            name: "delay-cmd",
+            wrapper_args: &[
+                "--max-retries",
+                "1",
+                "--base-delay-ms",
+                "10",
+                "--delay-cmd",
+                r#"echo "context $RUSTY_CLAUDE_ATTEMPT $RUSTY_CLAUDE_EXIT_CODE $RUSTY_CLAUDE_CLASS" >&2; echo 300ms"#,
+            ],
+            child_args: &["fails-then-succeeds", "--failures", "1"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                expect_attempts(r, 2)?;
+                if !r.stderr.contains("context 1 1 server") {
+                    return Err("delay command did not see the decision context".into());
+                }
+                if !r.stderr.contains("retrying in 300ms") {
+                    return Err("the printed delay was not used".into());
+                }
+                Ok(())
+            },
+        });
+        cases.push(Case {
+            name: "delay-cmd-timeout",
+            wrapper_args: &[
+                "--max-retries",
+                "1",
+                "--base-delay-ms",
+                "10",
+                "--delay-cmd-timeout",
+                "200ms",
+                "--delay-cmd",
+                "sleep 5; echo 1",
+            ],
+            child_args: &["fails-then-succeeds", "--failures", "1"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 0)?;
+                expect_attempts(r, 2)?;
+                if !r
+                    .stderr
+                    .contains("--delay-cmd timed out after 200ms; using the built-in backoff")
+                {
+                    return Err("slow delay command was not reported".into());
+                }
+                if r.elapsed >= Duration::from_secs(3) {
+                    return Err(format!("waited {:?} on the delay command", r.elapsed));
+                }
+                Ok(())
+            },
+        });
+        cases.push(Case {
+            name: "self-wrap-symlink",
+            wrapper_args: &["self-wrap-symlink"],
+            child_args: &["succeed"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: expect_self_wrap_refused,
+        });
+        cases.push(Case {
+            name: "idle-after-eof",
+            wrapper_args: &["--idle-timeout-secs", "1"],
+            child_args: &["stalls", "--secs", "2", "--close-output"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                // Silent for longer than the limit, but only after closing both pipes
+                expect_code(r, 0)?;
+                expect_attempts(r, 1)
+            },
+        });
+        cases.push(Case {
+            name: "wrapper-signal-cleanup",
+            wrapper_args: &["--isolated-home=home-sigterm"],
+            child_args: &["stalls", "--secs", "0.5", "--signal-parent"],
+            stdin: None,
+            observe: true,
+            env: &[],
+            check: |r, _| {
+                // SIGTERM ends the run with 128 + the signal, but only after cleaning up
+                expect_code(r, 143)?;
+                if r.dir.join("home-sigterm").exists() {
+                    return Err("isolated home was left behind".into());
+                }
+                if r.socket_left {
+                    return Err("socket file was left behind".into());
+                }
+                Ok(())
+            },
+        });
+        cases.push(Case {
+            name: "process-tree-killed",
+            wrapper_args: FAST,
+            child_args: &["always-fatal", "--grandchild", "grandchild.pid"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 2)?;
+                let pid = grandchild(r)?;
+                if alive_after(pid, Duration::from_secs(2)) {
+                    kill_pid(pid);
+                    return Err(format!("grandchild {pid} outlived the failed run"));
+                }
+                Ok(())
+            },
+        });
+        cases.push(Case {
+            name: "no-process-group",
+            wrapper_args: &["--no-process-group", "--max-retries", "0"],
+            child_args: &["always-fatal", "--grandchild", "grandchild.pid"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 2)?;
+                let pid = grandchild(r)?;
+                let alive = alive_after(pid, Duration::from_millis(300));
+                kill_pid(pid);
+                if !alive {
+                    return Err(format!("grandchild {pid} was killed with the child"));
+                }
+                Ok(())
+            },
+        });
+        cases.push(Case {
+            name: "interrupt-not-retried",
+            wrapper_args: &[
+                "--retry-on-any-error",
+                "--no-retry-guard",
+                "runtime",
+                "--max-retries",
+                "3",
+                "--base-delay-ms",
+                "10",
+            ],
+            child_args: &["stalls", "--secs", "5", "--signal-parent"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                // The forwarded SIGTERM kills the child, and that death is not retried
+                expect_code(r, 143)?;
+                expect_attempts(r, 1)?;
+                if r.elapsed > Duration::from_secs(3) {
+                    return Err(format!("took {:?}; the child wasn't signalled", r.elapsed));
+                }
+                if !r
+                    .stderr
+                    .contains("SIGTERM received; passed it on to the attempt")
+                {
+                    return Err(format!("no forwarding note in stderr: {}", r.stderr));
+                }
+                Ok(())
+            },
+        });
+        cases.push(Case {
+            name: "interrupt-twice-kills",
+            wrapper_args: &["--retry-on-any-error", "--max-retries", "3"],
+            child_args: &[
+                "stalls",
+                "--secs",
+                "10",
+                "--ignore-sigterm",
+                "--signal-parent",
+                "2",
+            ],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                expect_code(r, 143)?;
+                expect_attempts(r, 1)?;
+                if r.elapsed > Duration::from_secs(5) {
+                    return Err(format!("took {:?}; the child wasn't killed", r.elapsed));
+                }
+                if !r.stderr.contains("received again; killed the attempt") {
+                    return Err(format!("no kill note in stderr: {}", r.stderr));
+                }
+                Ok(())
+            },
+        });
+        cases.push(Case {
+            name: "signal-death",
+            wrapper_args: FAST,
+            child_args: &["signal-death"],
+            stdin: None,
+            observe: false,
+            env: &[],
+            check: |r, _| {
+                // 128 + SIGTERM, as a shell reports a child it killed
+                expect_code(r, 143)?;
+                expect_attempts(r, 1)?;
+                let line = "non-retryable failure after 1 attempt(s); exiting with code 143, the \
+                    child was killed by signal 15";
+                if !r.stderr.contains(line) {
+                    return Err(format!("stderr lacks `{line}`"));
+                }
+                Ok(())
+            },
+        });
+    }
+    cases
+}
+
+/// Files written to the scratch directory before the cases run, for those that read one.
+const FIXTURES: &[(&str, &str)] = &[
+    ("control-abort", "abort\n"),
+    ("require-stdin.toml", "require_stdin = 100\n"),
+    ("stdin-file.json", "{\"prompt\": \"replayed\"}\n"),
+    (
+        "batch-prompts.txt",
+        "first prompt\n\n{\"prompt\": \"second prompt\"}\nthird\n",
+    ),
+    (
+        "config-fields.toml",
+        "# Shared by every script\n\
+        max_retries = 2\n\
+        base_delay_ms = 10\n\
+        max_delay_ms = 20 # capped low for the suite\n\
+        patterns = [\n\
+        \x20   \"unknown option '--(bogus|other)'\",\n\
+        \x20   'no such \\w+',\n\
+        ]\n\
+        retry_budget = 3\n",
+    ),
+    (
+        "config-malformed.toml",
+        "max_retries = 3\n\
+        \n\
+        base_delay_ms = = 10\n",
+    ),
+    (
+        ".claude/settings.json",
+        r#"{
+  "$schema": "https://json.schemastore.org/claude-code-settings.json",
+  "model": "claude-sonnet-4-5",
+  "permissions": {
+    "allow": ["Bash(npm run lint)", "Bash(npm run test:*)", "Read(~/.zshrc)"],
+    "deny": ["Bash(curl:*)", "Read(./.env)", "Read(./secrets/**)"]
+  },
+  "env": { "CLAUDE_CODE_ENABLE_TELEMETRY": "1", "OTEL_METRICS_EXPORTER": "otlp" },
+  "hooks": {
+    "PostToolUse": [
+      { "matcher": "Edit|Write", "hooks": [{ "type": "command", "command": "npx prettier --write" }] }
+    ]
+  },
+  "rustyClaude": {
+    "maxRetries": 4,
+    "baseDelayMs": 10,
+    "maxDelayMs": 20,
+    "attemptTimeoutSecs": 30,
+    "patterns": ["unknown option '--bogus'"],
+    "retryBudget": 2
+  }
+}
+"#,
+    ),
+    (
+        "broken-settings.json",
+        r#"{
+  "permissions": {
+    "allow": ["Bash(git diff:*)",],
+  },
+  // written by hand
+  "rustyClaude": { "maxRetries": 7, "forceTee": true },
+}
+"#,
+    ),
+    (
+        "transcript/attempt-01.meta.json",
+        r#"{
+  "run_id": "01a0f3c2-7d41-7a9e-9b1c-5e2d8f6a4b10",
+  "attempt": 1,
+  "cmd": "claude",
+  "args": ["-p", "summarize the changelog"],
+  "pid": 48213,
+  "tag": null,
+  "stdin_bytes": 0,
+  "started_at_ms": 1760000000000,
+  "finished_at_ms": 1760000004210,
+  "code": 1,
+  "duration_ms": 4210,
+  "stdout_bytes": 0,
+  "stderr_bytes": 91,
+  "timeout_warning_ms": null,
+  "timed_out": false,
+  "killed": null,
+  "idle_ms": null,
+  "matched": "(?i)overloaded",
+  "class": "server",
+  "fatal": null,
+  "retry": true,
+  "delay_ms": 1200
+}
+"#,
+    ),
+    ("transcript/attempt-01.stdout", ""),
+    (
+        "transcript/attempt-01.stderr",
+        "API Error: 529 {\"type\":\"error\",\"error\":{\"type\":\"overloaded_error\",\"message\":\"Overloaded\"}}\n",
+    ),
+    (
+        "transcript/attempt-02.meta.json",
+        r#"{
+  "run_id": "01a0f3c2-7d41-7a9e-9b1c-5e2d8f6a4b10",
+  "attempt": 2,
+  "cmd": "claude",
+  "args": ["-p", "summarize the changelog"],
+  "pid": 48240,
+  "tag": null,
+  "stdin_bytes": 0,
+  "started_at_ms": 1760000005412,
+  "finished_at_ms": 1760000017930,
+  "code": 0,
+  "duration_ms": 12518,
+  "stdout_bytes": 38,
+  "stderr_bytes": 0,
+  "timeout_warning_ms": null,
+  "retry": false
+}
+"#,
+    ),
+    (
+        "transcript/attempt-02.stdout",
+        "The changelog lists three fixes.\nDone\n",
+    ),
+    ("transcript/attempt-02.stderr", ""),
+    ("candidate.toml", "max_retries = 0\n"),
+    (
+        "bad-settings.json",
+        r#"{
+  "permissions": { "allow": [] },
+  "rustyClaude": { "maxRetries": "four" }
+}
+"#,
+    ),
+];
+
+/// Keep the user's own supervisor settings from leaking into a built-in scenario.
+pub fn scrub_env(cmd: &mut Command) {
+    for (key, _) in env::vars_os() {
+        let key_str = key.to_string_lossy();
+        if key_str.starts_with(crate::envvars::PREFIX)
+            || key_str.starts_with(crate::envvars::LEGACY_PREFIX)
+        {
+            cmd.env_remove(&key);
+        }
+    }
+}
+
+fn run_case(exe: &Path, dir: &Path, case: &Case) -> io::Result<RunResult> {
+    let reason = dir.join(format!("{}.reason", case.name));
+    let state = dir.join(format!("{}.state", case.name));
+    // Cases whose wrapper args start with `exec` use `exec [FLAGS] COMMAND` form; those
+    // starting with `self-wrap` or `self-wrap-symlink` keep the recursion guard armed
+    let (mode, wrapper_args) = match case.wrapper_args.split_first() {
+        Some((&first @ ("exec" | "self-wrap" | "self-wrap-symlink"), rest)) => (first, rest),
+        _ => ("", case.wrapper_args),
+    };
+    let exec = mode == "exec";
+    // Cases naming `simulate` replay a fixture transcript, with no child at all
+    let simulate = wrapper_args.contains(&"simulate");
+    let mut cmd = Command::new(exe);
+    if exec {
+        cmd.arg("exec");
+    }
+    cmd.arg("--reason-file").arg(&reason);
+    match mode {
+        "exec" => {
+            cmd.arg("--allow-self-wrap");
+        }
+        "self-wrap" => {
+            cmd.arg("--cmd").arg(exe);
+        }
+        "self-wrap-symlink" => {
+            // A differently named link on PATH, the way a `claude` alias would be installed
+            let bin = dir.join(format!("{}.bin", case.name));
+            fs::create_dir_all(&bin)?;
+            link_to(exe, &bin.join("claude"))?;
+            let path = env::join_paths(
+                std::iter::once(bin)
+                    .chain(env::split_paths(&env::var_os("PATH").unwrap_or_default())),
+            )
+            .map_err(io::Error::other)?;
+            cmd.env("PATH", path).arg("--cmd").arg("claude");
+        }
+        _ if simulate => {}
+        _ => {
+            cmd.arg("--cmd").arg(exe).arg("--allow-self-wrap");
+        }
+    }
+    // The user's own config file stays out of it too, unless the case names one
+    let names_config = wrapper_args
+        .iter()
+        .any(|&a| a == "--config" || a == "--settings")
+        || case.env.iter().any(|(k, _)| k.ends_with("_CONFIG"));
+    if !names_config {
+        cmd.arg("--no-config");
+    }
+    cmd.args(wrapper_args);
+    let socket = dir.join(format!("{}.sock", case.name));
+    if case.observe {
+        cmd.arg("--observe-socket").arg(&socket);
+    }
+    if !simulate {
+        if exec {
+            cmd.arg(exe);
+        } else {
+            cmd.arg("--");
+        }
+        cmd.arg("__fake-child")
+            .args(case.child_args)
+            .arg("--state")
+            .arg(&state);
+    }
+    cmd.stdin(if case.stdin.is_some() {
+        Stdio::piped()
+    } else {
+        Stdio::null()
+    })
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .current_dir(dir);
+    scrub_env(&mut cmd);
+    cmd.envs(case.env.iter().copied());
+
+    let started = Instant::now();
+    let mut child = cmd.spawn()?;
+    let observer = case.observe.then(|| observe_client(socket.clone()));
+    let input = case.stdin.clone();
+    let mut child_stdin = child.stdin.take();
+    let writer = thread::spawn(move || {
+        if let (Some(stdin), Some(data)) = (child_stdin.as_mut(), input) {
+            let _ = stdin.write_all(&data);
+        }
+    });
+    let mut out = child.stdout.take().expect("piped stdout");
+    let mut err = child.stderr.take().expect("piped stderr");
+    let out_reader = thread::spawn(move || {
+        let mut buf = Vec::new();
+        out.read_to_end(&mut buf).map(|_| buf)
+    });
+    let err_reader = thread::spawn(move || {
+        let mut buf = Vec::new();
+        err.read_to_end(&mut buf).map(|_| buf)
+    });
+
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if started.elapsed() > CASE_TIMEOUT {
+            let _ = child.kill();
+            break child.wait()?;
+        }
+        thread::sleep(Duration::from_millis(20));
+    };
+    let elapsed = started.elapsed();
+    let _ = writer.join();
+    let stdout = out_reader.join().unwrap_or_else(|_| Ok(Vec::new()))?;
+    let stderr = err_reader.join().unwrap_or_else(|_| Ok(Vec::new()))?;
+    let attempts = fs::read_to_string(&reason).ok().and_then(|text| {
+        text.lines()
+            .find_map(|l| l.strip_prefix("attempts="))
+            .and_then(|n| n.parse().ok())
+    });
+    Ok(RunResult {
+        code: status.code(),
+        stdout,
+        stderr: String::from_utf8_lossy(&stderr).into_owned(),
+        stderr_raw: stderr,
+        elapsed,
+        attempts,
+        observed: observer.and_then(|h| h.join().ok()).unwrap_or_default(),
+        socket_left: case.observe && socket.exists(),
+        dir: dir.to_path_buf(),
+    })
+}
+
+#[cfg(unix)]
+fn link_to(target: &Path, link: &Path) -> io::Result<()> {
+    let _ = fs::remove_file(link);
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(not(unix))]
+fn link_to(_: &Path, _: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "symlinked self-wrap needs unix",
+    ))
+}
+
+/// Connect to the wrapper's observer socket as soon as it appears and read until it exits.
+#[cfg(unix)]
+fn observe_client(socket: PathBuf) -> thread::JoinHandle<String> {
+    thread::spawn(move || {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            match std::os::unix::net::UnixStream::connect(&socket) {
+                Ok(mut conn) => {
+                    let mut text = String::new();
+                    let _ = conn.read_to_string(&mut text);
+                    return text;
+                }
+                Err(_) if Instant::now() < deadline => thread::sleep(Duration::from_millis(5)),
+                Err(_) => return String::new(),
+            }
+        }
+    })
+}
+
+#[cfg(not(unix))]
+fn observe_client(_socket: PathBuf) -> thread::JoinHandle<String> {
+    thread::spawn(String::new)
+}
+
+fn scratch_dir() -> io::Result<PathBuf> {
+    let dir = env::temp_dir().join(format!("rusty-claude-self-test-{}", std::process::id()));
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+#[derive(clap::Args, Debug)]
+pub struct SelfTestArgs {
+    /// Run only these scenarios (all of them if none are named)
+    #[arg(value_name = "CASE")]
+    cases: Vec<String>,
+
+    /// Print the scenario names, one per line, and run nothing
+    #[arg(long)]
+    list: bool,
+}
+
+/// Run the scenarios `args` names and print a report; returns the process exit code.
+pub fn run(args: &SelfTestArgs) -> i32 {
+    let mut cases = cases();
+    if args.list {
+        let mut stdout = io::stdout().lock();
+        // A closed pipe (`| head`) just ends the list
+        let _ = cases
+            .iter()
+            .try_for_each(|case| writeln!(stdout, "{}", case.name));
+        return 0;
+    }
+    if let Some(unknown) = args
+        .cases
+        .iter()
+        .find(|name| !cases.iter().any(|c| c.name == name.as_str()))
+    {
+        eprintln!("[rusty-claude] self-test: no scenario named `{unknown}` (see --list)");
+        return crate::exit_codes::CONFIG_ERROR;
+    }
+    if !args.cases.is_empty() {
+        cases.retain(|c| args.cases.iter().any(|name| name == c.name));
+    }
+    let exe = match env::current_exe() {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("[rusty-claude] self-test: cannot locate own executable: {e}");
+            return crate::exit_codes::INTERNAL_ERROR;
+        }
+    };
+    let dir = match scratch_dir() {
+        Ok(d) => d,
+        Err(e) => {
+            eprintln!("[rusty-claude] self-test: cannot create scratch directory: {e}");
+            return crate::exit_codes::INTERNAL_ERROR;
+        }
+    };
+    for (name, contents) in FIXTURES {
+        let path = dir.join(name);
+        let written = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&path, contents));
+        if let Err(e) = written {
+            eprintln!("[rusty-claude] self-test: cannot write {name}: {e}");
+            return crate::exit_codes::INTERNAL_ERROR;
+        }
+    }
+
+    println!(
+        "rusty-claude {} self-test ({}/{})",
+        env!("CARGO_PKG_VERSION"),
+        env::consts::OS,
+        env::consts::ARCH
+    );
+    let mut failed = 0;
+    let width = cases.iter().map(|c| c.name.len()).max().unwrap_or(0);
+    for case in &cases {
+        let verdict = run_case(&exe, &dir, case)
+            .map_err(|e| format!("could not run: {e}"))
+            .and_then(|r| {
+                let elapsed = r.elapsed;
+                (case.check)(&r, case.stdin.as_deref()).map(|_| elapsed)
+            });
+        match verdict {
+            Ok(elapsed) => println!(
+                "  PASS  {:width$}  {:.2}s",
+                case.name,
+                elapsed.as_secs_f64()
+            ),
+            Err(why) => {
+                failed += 1;
+                println!("  FAIL  {:width$}  {why}", case.name);
+            }
+        }
+    }
+    let _ = fs::remove_dir_all(&dir);
+    println!("{} passed, {} failed", cases.len() - failed, failed);
+    if failed == 0 {
+        0
+    } else {
+        1
+    }
+}
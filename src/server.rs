@@ -0,0 +1,418 @@
+//! `--server-mode`: keep a long-running child such as `claude mcp serve` alive.
+//!
+//! The child is expected to run indefinitely, so every exit is a failure and is restarted.
+//! stdin and stdout are inherited untouched because they usually carry a protocol; only
+//! stderr is teed, keeping a bounded tail for Retry-After hints. The backoff counter resets
+//! once the child has stayed up for `--stable-after`, and `--max-restarts-per-hour` stops a
+//! crash loop.
+//!
+//! With `--ready-pattern` (matched on stderr) or `--ready-tcp`, a fresh child is "starting"
+//! until readiness is confirmed within `--ready-timeout`; exiting or timing out before then
+//! is a failed start, reported separately from a crash of a healthy child.
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process::{Child, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use regex::Regex;
+use rusty_claude::patterns::{should_retry, Output, Patterns};
+use serde_json::json;
+
+use crate::child_env::ChildEnv;
+use crate::control;
+use crate::duration::format_duration;
+use crate::events::Events;
+use crate::exit_codes::{self, Outcome, Reason};
+use crate::integrity::Pinned;
+use crate::policy::{backoff_ms, jitter_rng};
+use crate::run::{child_outcome, code_label, integrity_failure, spawn_failed};
+use crate::shellquote::repro_line;
+use crate::tree;
+use crate::verdict::cap_retry_after;
+use crate::Cli;
+
+/// Bytes of the child's stderr kept for pattern matching after it exits.
+const STDERR_TAIL: usize = 64 * 1024;
+/// How long a stopping child gets between SIGTERM and SIGKILL.
+#[cfg(unix)]
+const STOP_GRACE: Duration = Duration::from_secs(10);
+const POLL: Duration = Duration::from_millis(100);
+const HOUR: Duration = Duration::from_secs(3600);
+/// Early stderr kept for `--ready-pattern`; a server that logs more before it is ready
+/// should use a more specific pattern or `--ready-tcp`.
+const READY_SCAN_LIMIT: usize = 64 * 1024;
+const TCP_PROBE_EVERY: Duration = Duration::from_millis(250);
+const TCP_CONNECT_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Set by SIGTERM/SIGINT; the supervision loop stops the child and returns.
+static STOP: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn on_stop_signal(_: libc::c_int) {
+    STOP.store(true, Ordering::SeqCst);
+}
+
+#[cfg(unix)]
+fn install_stop_handler() {
+    // SAFETY: the handler only stores to an atomic, which is async-signal-safe.
+    unsafe {
+        libc::signal(
+            libc::SIGTERM,
+            on_stop_signal as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+        libc::signal(
+            libc::SIGINT,
+            on_stop_signal as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+}
+
+#[cfg(not(unix))]
+fn install_stop_handler() {}
+
+/// Ask the child to exit, escalating to a kill after `STOP_GRACE`.
+/// Everything it started gets the same signal and, after the grace, the kill too.
+fn stop_child(child: &mut Child) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        tree::signal(child.id(), libc::SIGTERM);
+        let deadline = Instant::now() + STOP_GRACE;
+        while Instant::now() < deadline && child.try_wait()?.is_none() {
+            thread::sleep(POLL);
+        }
+    }
+    let _ = tree::kill_tree(child);
+    child.wait()?;
+    tree::release(child);
+    Ok(())
+}
+
+/// Sleep for `total`, returning early (with `false`) if a stop or a control command was
+/// requested.
+fn interruptible_sleep(total: Duration, events: &Events) -> bool {
+    let deadline = Instant::now() + total;
+    while Instant::now() < deadline {
+        if STOP.load(Ordering::SeqCst) || control::check(events).is_some() {
+            return false;
+        }
+        thread::sleep(POLL.min(deadline.saturating_duration_since(Instant::now())));
+    }
+    !STOP.load(Ordering::SeqCst) && control::check(events).is_none()
+}
+
+/// How a `drain` or `abort` ends supervision: `status` is the last child's.
+fn halted(cmd: control::Command, status: Option<ExitStatus>, starts: u32, cli: &Cli) -> Outcome {
+    let code = status.and_then(|s| s.code());
+    match cmd {
+        control::Command::Drain => child_outcome(Reason::Drained, status, starts - 1, cli),
+        control::Command::Abort => Outcome {
+            child_code: code,
+            ..Outcome::wrapper(Reason::Aborted, exit_codes::INTERRUPTED, starts)
+        },
+    }
+}
+
+/// Forward the child's stderr, keeping roughly the last `STDERR_TAIL` bytes and setting
+/// `ready` once the early output matches `pattern`.
+fn tee_stderr_tail(
+    mut src: impl Read + Send + 'static,
+    pattern: Option<Regex>,
+    ready: Arc<AtomicBool>,
+) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut tail = Vec::new();
+        let mut early = Vec::new();
+        let mut pattern = pattern;
+        let mut tmp = [0u8; 8192];
+        let mut dst = io::stderr();
+        loop {
+            match src.read(&mut tmp) {
+                Ok(0) => break,
+                Ok(n) => {
+                    let _ = dst.write_all(&tmp[..n]);
+                    tail.extend_from_slice(&tmp[..n]);
+                    if tail.len() > 2 * STDERR_TAIL {
+                        tail.drain(..tail.len() - STDERR_TAIL);
+                    }
+                    if let Some(re) = &pattern {
+                        early.extend_from_slice(&tmp[..n]);
+                        if re.is_match(&String::from_utf8_lossy(&early)) {
+                            ready.store(true, Ordering::SeqCst);
+                            pattern = None;
+                        } else if early.len() > READY_SCAN_LIMIT {
+                            pattern = None;
+                        }
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            }
+        }
+        tail
+    })
+}
+
+fn tcp_ready(addr: &str) -> bool {
+    addr.to_socket_addrs().is_ok_and(|mut addrs| {
+        addrs.any(|a| TcpStream::connect_timeout(&a, TCP_CONNECT_TIMEOUT).is_ok())
+    })
+}
+
+/// How one run of the server child ended.
+enum Ending {
+    /// Exited before readiness was confirmed.
+    FailedStart(ExitStatus),
+    /// Not ready within `--ready-timeout`; the child has been stopped.
+    ReadyTimeout,
+    /// Exited after having been healthy.
+    Crashed(ExitStatus),
+}
+
+pub fn run(
+    cli: &Cli,
+    real_cmd: &str,
+    patterns: &Patterns,
+    ready_pattern: Option<Regex>,
+    mut pinned: Option<Pinned>,
+    child_env: &ChildEnv,
+    events: &Events,
+) -> io::Result<Outcome> {
+    install_stop_handler();
+    let mut restarts: VecDeque<Instant> = VecDeque::new();
+    let mut failures: u32 = 0;
+    let mut previous_wait: Option<u64> = None;
+    let mut rng = jitter_rng(cli);
+    let mut starts: u32 = 0;
+    let gated = ready_pattern.is_some() || cli.ready_tcp.is_some();
+
+    loop {
+        if let Some(pin) = pinned.as_mut() {
+            if let Err(e) = pin.recheck(&cli.expect_cmd_sha256) {
+                return Ok(integrity_failure(&e, starts));
+            }
+        }
+        let mut args = cli.args.clone();
+        if starts > 0 {
+            if let Some(extra) = &cli.retry_extra_args {
+                args.extend(extra.split_whitespace().map(str::to_string));
+            }
+        }
+        let run_id = cli.run_id.as_deref().unwrap_or_default();
+        if cli.verbose > 0 {
+            eprintln!(
+                "[rusty-claude] start {} of run {run_id}: {}",
+                starts + 1,
+                repro_line(
+                    std::env::current_dir().ok().as_deref(),
+                    &child_env.vars,
+                    real_cmd,
+                    &args,
+                    0
+                )
+            );
+        }
+        let mut cmd = crate::resolve::command(real_cmd, &args);
+        child_env.apply(&mut cmd, &child_env.vars);
+        cmd.env(crate::runid::ENV_VAR, run_id)
+            .stdin(Stdio::inherit())
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::piped());
+        // A child reading our terminal must stay in its foreground group
+        let mut child = match tree::spawn(&mut cmd, !atty::is(atty::Stream::Stdin)) {
+            Ok(c) => c,
+            Err(e) => return Ok(spawn_failed(real_cmd, &e, starts + 1)),
+        };
+        starts += 1;
+        let started = Instant::now();
+        events.emit("starting", json!({ "start": starts, "pid": child.id() }));
+        let ready = Arc::new(AtomicBool::new(!gated));
+        let stderr = tee_stderr_tail(
+            child.stderr.take().expect("piped stderr"),
+            ready_pattern.clone(),
+            Arc::clone(&ready),
+        );
+        if !gated {
+            events.emit("ready", json!({ "start": starts, "gated": false }));
+        }
+
+        let mut announced = !gated;
+        let mut last_probe: Option<Instant> = None;
+        let ending = loop {
+            if control::check(events) == Some(control::Command::Abort) {
+                if !cli.quiet {
+                    eprintln!("[rusty-claude] aborting; terminating the server child");
+                }
+                stop_child(&mut child)?;
+                let _ = stderr.join();
+                return Ok(halted(control::Command::Abort, None, starts, cli));
+            }
+            if STOP.load(Ordering::SeqCst) {
+                if !cli.quiet {
+                    eprintln!("[rusty-claude] stop requested; terminating the server child");
+                }
+                stop_child(&mut child)?;
+                let _ = stderr.join();
+                events.emit("stopped", json!({ "start": starts }));
+                return Ok(Outcome::wrapper(Reason::Stopped, 0, starts));
+            }
+            if let Some(status) = child.try_wait()? {
+                // Helpers it left would hold on to what the next start needs
+                let _ = tree::kill_tree(&mut child);
+                tree::release(&child);
+                break if announced {
+                    Ending::Crashed(status)
+                } else {
+                    Ending::FailedStart(status)
+                };
+            }
+            if !announced {
+                if let Some(addr) = &cli.ready_tcp {
+                    if last_probe.is_none_or(|t| t.elapsed() >= TCP_PROBE_EVERY) {
+                        last_probe = Some(Instant::now());
+                        if tcp_ready(addr) {
+                            ready.store(true, Ordering::SeqCst);
+                        }
+                    }
+                }
+                if ready.load(Ordering::SeqCst) {
+                    announced = true;
+                    let took = started.elapsed();
+                    if !cli.quiet {
+                        eprintln!(
+                            "[rusty-claude] server ready after {}",
+                            format_duration(took)
+                        );
+                    }
+                    events.emit(
+                        "ready",
+                        json!({ "start": starts, "gated": true, "after_ms": took.as_millis() as u64 }),
+                    );
+                } else if started.elapsed() >= cli.ready_timeout {
+                    stop_child(&mut child)?;
+                    break Ending::ReadyTimeout;
+                }
+            }
+            thread::sleep(POLL);
+        };
+        let uptime = started.elapsed();
+        let tail = stderr.join().unwrap_or_default();
+        let status = match ending {
+            Ending::FailedStart(status) | Ending::Crashed(status) => Some(status),
+            Ending::ReadyTimeout => None,
+        };
+        let code = status.and_then(|s| s.code());
+        if let Some(cmd) = control::check(events) {
+            eprintln!(
+                "[rusty-claude] server exited (code={}); not restarting: {cmd} requested",
+                code_label(code)
+            );
+            return Ok(halted(cmd, status, starts, cli));
+        }
+        // Only a child that was actually healthy for a while earns a fresh backoff
+        if matches!(ending, Ending::Crashed(_)) && uptime >= cli.stable_after {
+            failures = 0;
+            previous_wait = None;
+        }
+        let (event, what) = match ending {
+            Ending::FailedStart(_) => ("failed_start", "server failed to start"),
+            Ending::ReadyTimeout => ("failed_start", "server did not become ready in time"),
+            Ending::Crashed(_) => ("crashed", "server exited"),
+        };
+        events.emit(
+            event,
+            json!({
+                "start": starts,
+                "code": code,
+                "uptime_ms": uptime.as_millis() as u64,
+                "ready_timeout": matches!(ending, Ending::ReadyTimeout),
+            }),
+        );
+
+        let now = Instant::now();
+        while restarts
+            .front()
+            .is_some_and(|t| now.duration_since(*t) > HOUR)
+        {
+            restarts.pop_front();
+        }
+        if restarts.len() as u32 >= cli.max_restarts_per_hour {
+            eprintln!(
+                "[rusty-claude] {what} (code={}) and has already restarted {} times in the \
+                last hour (--max-restarts-per-hour); giving up",
+                code_label(code),
+                restarts.len()
+            );
+            events.emit("gave_up", json!({ "start": starts, "code": code }));
+            return Ok(child_outcome(Reason::Exhausted, status, starts - 1, cli));
+        }
+        restarts.push_back(now);
+
+        // Only stderr is teed here; stdout goes straight through
+        let mut decision = should_retry(
+            Output::Streams {
+                stdout: "",
+                stderr: &String::from_utf8_lossy(&tail),
+            },
+            code,
+            true,
+            patterns,
+            cli.match_timeout,
+        );
+        cap_retry_after(&mut decision, cli);
+        if let Some(pattern) = &decision.fatal {
+            eprintln!(
+                "[rusty-claude] {what} (code={}) with output matching fatal pattern \
+                `{pattern}`; not restarting",
+                code_label(code)
+            );
+            events.emit(
+                "gave_up",
+                json!({ "start": starts, "code": code, "fatal": pattern }),
+            );
+            return Ok(child_outcome(Reason::Fatal, status, starts - 1, cli));
+        }
+        // Every exit restarts, so only an exit code in --no-retry-exit-codes gets here
+        if !decision.retry {
+            eprintln!(
+                "[rusty-claude] {what} (code={}), which is in --no-retry-exit-codes; not \
+                restarting",
+                code_label(code)
+            );
+            events.emit(
+                "gave_up",
+                json!({ "start": starts, "code": code, "no_retry_code": true }),
+            );
+            return Ok(child_outcome(Reason::NotRetryable, status, starts - 1, cli));
+        }
+        let wait = decision.floored(
+            decision
+                .retry_after_ms
+                .unwrap_or_else(|| backoff_ms(failures, previous_wait, cli, &mut rng)),
+        );
+        previous_wait = Some(wait);
+        failures = failures.saturating_add(1);
+        if !cli.quiet {
+            eprintln!(
+                "[rusty-claude] {what} (code={}) after {} uptime; restart #{} in {}",
+                code_label(code),
+                format_duration(uptime),
+                starts,
+                format_duration(Duration::from_millis(wait))
+            );
+        }
+        events.emit("restarting", json!({ "restart": starts, "delay_ms": wait }));
+        if !interruptible_sleep(Duration::from_millis(wait), events) {
+            if let Some(cmd) = control::check(events) {
+                eprintln!("[rusty-claude] not restarting: {cmd} requested during the backoff");
+                return Ok(halted(cmd, status, starts, cli));
+            }
+            events.emit("stopped", json!({ "start": starts }));
+            return Ok(Outcome::wrapper(Reason::Stopped, 0, starts));
+        }
+    }
+}
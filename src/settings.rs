@@ -0,0 +1,72 @@
+//! Provenance of effective settings, reported by `--print-config`.
+
+use std::fmt;
+
+/// Where an effective setting value came from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Source {
+    Default,
+    Flag,
+    /// Supplied by the named environment variable.
+    Env(String),
+    /// Set in the config file, at `PATH:LINE`.
+    File(String),
+    /// Set in a Claude settings file's block, at `PATH rustyClaude.KEY`.
+    ClaudeSettings(String),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::Flag => write!(f, "flag"),
+            Source::Env(name) if name.starts_with(crate::envvars::LEGACY_PREFIX) => {
+                write!(f, "env {name}, deprecated")
+            }
+            Source::Env(name) => write!(f, "env {name}"),
+            Source::File(at) => write!(f, "config {at}"),
+            Source::ClaudeSettings(at) => write!(f, "claude settings {at}"),
+        }
+    }
+}
+
+/// One effective setting with its value rendered for display.
+#[derive(Clone, Debug)]
+pub struct Setting {
+    pub key: &'static str,
+    pub value: String,
+    pub source: Source,
+    /// An environment variable that would have set this but was ignored, and why.
+    pub ignored: Option<(String, &'static str)>,
+}
+
+impl Setting {
+    pub fn new(key: &'static str, value: impl fmt::Display, source: Source) -> Self {
+        Setting {
+            key,
+            value: value.to_string(),
+            source,
+            ignored: None,
+        }
+    }
+}
+
+/// Render settings as aligned `key  value  (source)` lines.
+pub fn render(settings: &[Setting]) -> String {
+    let key_width = settings.iter().map(|s| s.key.len()).max().unwrap_or(0);
+    let value_width = settings.iter().map(|s| s.value.len()).max().unwrap_or(0);
+    settings
+        .iter()
+        .map(|s| {
+            let ignored = s
+                .ignored
+                .as_ref()
+                .map(|(name, flag)| format!("; env {name} ignored ({flag})"))
+                .unwrap_or_default();
+            format!(
+                "{:key_width$}  {:value_width$}  ({}{ignored})\n",
+                s.key, s.value, s.source
+            )
+        })
+        .collect()
+}
@@ -0,0 +1,304 @@
+//! Copy-pasteable reproduction lines for a spawn: the working directory, environment
+//! overrides, and command line, quoted for the platform's shell.
+//!
+//! POSIX shells get single quotes with `'\''` for embedded quotes (newlines are literal inside
+//! them); PowerShell gets single quotes with doubled `''`. Secrets are redacted and very long
+//! arguments, usually prompts, are elided, so the line is safe to paste into a bug report.
+
+use std::borrow::Cow;
+use std::ffi::OsString;
+use std::path::Path;
+use std::sync::LazyLock;
+
+use regex::Regex;
+
+/// Arguments longer than this are replaced by a note with their length.
+const MAX_ARG_BYTES: usize = 200;
+
+/// Words marking an environment variable or flag as carrying a secret, matched whole
+/// between `-` and `_`: `--api-key` and `ANTHROPIC_AUTH_TOKEN` are secrets, `--max-tokens`
+/// and `--author` are not.
+const SECRET_HINTS: &[&str] = &[
+    "KEY",
+    "APIKEY",
+    "TOKEN",
+    "SECRET",
+    "PASSWORD",
+    "PASSWD",
+    "CREDENTIAL",
+    "CREDENTIALS",
+    "AUTH",
+];
+
+fn is_safe(s: &str, extra: &[char]) -> bool {
+    !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-./:=".contains(c) || extra.contains(&c))
+}
+
+/// Quote `s` as one word for a POSIX shell.
+pub fn posix(s: &str) -> Cow<'_, str> {
+    if is_safe(s, &['@', '%', '+', ',']) {
+        Cow::Borrowed(s)
+    } else {
+        Cow::Owned(format!("'{}'", s.replace('\'', r"'\''")))
+    }
+}
+
+/// Quote `s` as one argument for PowerShell.
+pub fn powershell(s: &str) -> Cow<'_, str> {
+    if is_safe(s, &['\\']) {
+        Cow::Borrowed(s)
+    } else {
+        Cow::Owned(format!("'{}'", s.replace('\'', "''")))
+    }
+}
+
+fn looks_secret(name: &str) -> bool {
+    name.split(['-', '_']).any(|word| {
+        SECRET_HINTS
+            .iter()
+            .any(|hint| word.eq_ignore_ascii_case(hint))
+    })
+}
+
+/// Redact or elide one argument; `after_secret_flag` is set for the value of e.g. `--api-key`.
+fn sanitize(arg: &str, after_secret_flag: bool) -> Cow<'_, str> {
+    if after_secret_flag || arg.starts_with("sk-ant-") {
+        return Cow::Borrowed("<redacted>");
+    }
+    // `--api-key=...` and `NAME=value` settings alike
+    if let Some((name, _)) = arg.split_once('=') {
+        if looks_secret(name) {
+            return Cow::Owned(format!("{name}=<redacted>"));
+        }
+    }
+    if arg.len() > MAX_ARG_BYTES {
+        return Cow::Owned(format!("<{}-byte argument elided>", arg.len()));
+    }
+    Cow::Borrowed(arg)
+}
+
+/// `args` with secrets redacted and over-long arguments elided, for logs and artifacts.
+pub fn redacted(args: &[String]) -> Vec<String> {
+    let mut secret_next = false;
+    args.iter()
+        .map(|arg| {
+            let clean = sanitize(arg, secret_next).into_owned();
+            secret_next = arg.starts_with("--") && !arg.contains('=') && looks_secret(arg);
+            clean
+        })
+        .collect()
+}
+
+/// Credentials as they show up in free-form output: API keys, bearer tokens, and `NAME=value`,
+/// `NAME: value` or `"name":"value"` with a secret-looking name, its hint a whole word.
+static TEXT_SECRETS: LazyLock<Regex> = LazyLock::new(|| {
+    let hints = SECRET_HINTS.join("|");
+    Regex::new(&format!(
+        r#"sk-ant-[\w-]+|(?i)(bearer\s+)\S+|\b((?:[a-z0-9]+[-_])*(?:{hints})(?:[-_][a-z0-9]+)*)\b("?\s*[=:]\s*"?)(?:bearer\s+)?[^\s,;"]+"#
+    ))
+    .expect("valid secret pattern")
+});
+
+/// `text` with credentials replaced by `<redacted>`, for passing output along.
+pub fn redact_text(text: &str) -> String {
+    TEXT_SECRETS
+        .replace_all(text, |caps: &regex::Captures| {
+            match (caps.get(1), caps.get(2)) {
+                (Some(bearer), _) => format!("{}<redacted>", bearer.as_str()),
+                (None, Some(name)) => format!("{}{}<redacted>", name.as_str(), &caps[3]),
+                (None, None) => "<redacted>".to_string(),
+            }
+        })
+        .into_owned()
+}
+
+/// `cmd args` quoted for the platform's shell, with `redacted` args.
+pub fn command_line(cmd: &str, args: &[String]) -> String {
+    let quote = if cfg!(windows) { powershell } else { posix };
+    std::iter::once(quote(cmd).into_owned())
+        .chain(redacted(args).iter().map(|a| quote(a).into_owned()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The whole reproduction line for running `cmd args` in `cwd` with `env` layered on top of
+/// the inherited environment, noting `stdin_bytes` of piped input.
+pub fn repro_line(
+    cwd: Option<&Path>,
+    env: &[(OsString, OsString)],
+    cmd: &str,
+    args: &[String],
+    stdin_bytes: u64,
+) -> String {
+    let command = command_line(cmd, args);
+
+    let mut line = String::new();
+    let env = env.iter().map(|(k, v)| {
+        let (k, v) = (k.to_string_lossy(), v.to_string_lossy());
+        let v = if looks_secret(&k) {
+            Cow::Borrowed("<redacted>")
+        } else {
+            v
+        };
+        (k, v)
+    });
+    if cfg!(windows) {
+        if let Some(cwd) = cwd {
+            line += &format!("Set-Location {}; ", powershell(&cwd.to_string_lossy()));
+        }
+        for (k, v) in env {
+            line += &format!("$env:{k} = {}; ", powershell(&v));
+        }
+        line += &format!("& {command}");
+    } else {
+        if let Some(cwd) = cwd {
+            line += &format!("cd {} && ", posix(&cwd.to_string_lossy()));
+        }
+        for (k, v) in env {
+            line += &format!("{k}={} ", posix(&v));
+        }
+        line += &command;
+    }
+    if stdin_bytes > 0 {
+        line += &format!("  # plus {stdin_bytes} bytes on stdin");
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn posix_quoting() {
+        for (arg, want) in [
+            ("plain-word_1.txt", "plain-word_1.txt"),
+            ("", "''"),
+            ("it's", r"'it'\''s'"),
+            (r#"say "hi""#, r#"'say "hi"'"#),
+            ("two\nlines", "'two\nlines'"),
+            ("h\u{e9}llo \u{1f600}", "'h\u{e9}llo \u{1f600}'"),
+            ("$HOME `x`", "'$HOME `x`'"),
+        ] {
+            assert_eq!(posix(arg), want, "{arg:?}");
+        }
+    }
+
+    #[test]
+    fn powershell_quoting() {
+        for (arg, want) in [
+            (r"C:\Users\me", r"C:\Users\me"),
+            ("", "''"),
+            ("it's", "'it''s'"),
+            (r#"say "hi""#, r#"'say "hi"'"#),
+            ("$env:PATH", "'$env:PATH'"),
+            ("two\nlines", "'two\nlines'"),
+        ] {
+            assert_eq!(powershell(arg), want, "{arg:?}");
+        }
+    }
+
+    #[test]
+    fn secrets_are_redacted() {
+        let args = strings(&[
+            "--api-key",
+            "abc",
+            "--auth-token=xyz",
+            "sk-ant-api03-zzz",
+            "ANTHROPIC_API_KEY=k",
+            "-p",
+        ]);
+        assert_eq!(
+            redacted(&args),
+            [
+                "--api-key",
+                "<redacted>",
+                "--auth-token=<redacted>",
+                "<redacted>",
+                "ANTHROPIC_API_KEY=<redacted>",
+                "-p",
+            ]
+        );
+        assert_eq!(
+            sanitize(&"x".repeat(201), false),
+            "<201-byte argument elided>"
+        );
+        assert_eq!(sanitize(&"x".repeat(200), false).len(), 200);
+    }
+
+    #[test]
+    fn flags_that_only_contain_a_hint_are_not_secrets() {
+        let args = strings(&[
+            "--max-tokens",
+            "4096",
+            "--keyboard",
+            "dvorak",
+            "--author",
+            "me",
+            "MONKEY=1",
+            "--tokens=9",
+        ]);
+        assert_eq!(redacted(&args), args);
+        for name in [
+            "GITHUB_TOKEN",
+            "db-password",
+            "AWS_SECRET_ACCESS_KEY",
+            "x-apikey",
+        ] {
+            assert!(looks_secret(name), "{name}");
+        }
+    }
+
+    #[test]
+    fn secrets_in_text() {
+        for (text, want) in [
+            ("key sk-ant-abc-123 leaked", "key <redacted> leaked"),
+            (
+                "Authorization: Bearer abc.def",
+                "Authorization: Bearer <redacted>",
+            ),
+            (
+                r#"{"api_key":"abc","n":1}"#,
+                r#"{"api_key":"<redacted>","n":1}"#,
+            ),
+            ("GITHUB_TOKEN=ghp_1, next", "GITHUB_TOKEN=<redacted>, next"),
+            ("max_tokens: 4096, author=me", "max_tokens: 4096, author=me"),
+            ("keyboard=dvorak", "keyboard=dvorak"),
+        ] {
+            assert_eq!(redact_text(text), want, "{text:?}");
+        }
+    }
+
+    #[test]
+    fn repro_lines() {
+        let env = [
+            (OsString::from("LC_ALL"), OsString::from("C.UTF-8")),
+            (OsString::from("MY_TOKEN"), OsString::from("t")),
+        ];
+        let line = repro_line(
+            Some(Path::new("/my repo")),
+            &env,
+            "claude",
+            &strings(&["-p", "it's"]),
+            12,
+        );
+        if cfg!(windows) {
+            assert_eq!(
+                line,
+                "Set-Location '/my repo'; $env:LC_ALL = C.UTF-8; $env:MY_TOKEN = '<redacted>'; \
+                & claude -p 'it''s'  # plus 12 bytes on stdin"
+            );
+        } else {
+            assert_eq!(
+                line,
+                r"cd '/my repo' && LC_ALL=C.UTF-8 MY_TOKEN='<redacted>' claude -p 'it'\''s'  # plus 12 bytes on stdin"
+            );
+        }
+    }
+}
@@ -0,0 +1,198 @@
+//! One cleanup path for every way a run ends. Whatever would otherwise be left behind
+//! (temp files, the isolated home, the observer socket, the terminal's raw mode and title)
+//! registers its cleanup here when it is created. The first of these runs it, and nothing
+//! runs it twice:
+//!
+//! - dropping the [`Registration`], on every normal exit and when a panic unwinds the main
+//!   thread
+//! - the panic hook, for a panic on the main thread
+//! - SIGINT, SIGTERM, or SIGHUP, after which the signal takes its default action; server
+//!   mode handles SIGINT and SIGTERM itself, stopping its child before its drops clean up,
+//!   and the retry loop takes them over through [`interrupt`](crate::interrupt)
+//!
+//! [`shutdown`] runs whatever is still registered, by priority and newest first within
+//! one. Every cleanup gets its own thread and [`ITEM_TIMEOUT`], so one that hangs (an
+//! unlink on a dead network mount) is abandoned instead of holding up the exit.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use crate::chaos;
+use crate::duration::format_duration;
+
+/// How long one cleanup may take before the rest go ahead without it.
+const ITEM_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// When a cleanup runs relative to the others.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Before anything that might write to the terminal.
+    #[cfg_attr(not(unix), allow(dead_code))]
+    First,
+    Normal,
+    /// After everything else.
+    Last,
+}
+
+struct Item {
+    name: &'static str,
+    priority: Priority,
+    cleanup: Box<dyn FnOnce() + Send>,
+}
+
+static ITEMS: Mutex<BTreeMap<u64, Item>> = Mutex::new(BTreeMap::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+fn items() -> MutexGuard<'static, BTreeMap<u64, Item>> {
+    ITEMS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// A registered cleanup; dropping it runs the cleanup unless something already has.
+#[must_use = "dropping the registration runs the cleanup at once"]
+pub struct Registration(u64);
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let item = items().remove(&self.0);
+        if let Some(item) = item {
+            run(item);
+        }
+    }
+}
+
+/// Register `cleanup` under `name` (as shown when it times out).
+pub fn register(
+    name: &'static str,
+    priority: Priority,
+    cleanup: impl FnOnce() + Send + 'static,
+) -> Registration {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    items().insert(
+        id,
+        Item {
+            name,
+            priority,
+            cleanup: Box::new(cleanup),
+        },
+    );
+    Registration(id)
+}
+
+fn run(item: Item) {
+    chaos::released(item.name);
+    let Item { name, cleanup, .. } = item;
+    let (done, finished) = mpsc::channel();
+    let spawned = thread::Builder::new()
+        .name(format!("cleanup: {name}"))
+        .spawn(move || {
+            cleanup();
+            let _ = done.send(());
+        });
+    // A cleanup that panicked drops `done`, which ends the wait as well
+    if spawned.is_ok()
+        && finished.recv_timeout(ITEM_TIMEOUT) == Err(mpsc::RecvTimeoutError::Timeout)
+    {
+        eprintln!(
+            "[rusty-claude] warning: cleanup of the {name} did not finish within {}; \
+            leaving it",
+            format_duration(ITEM_TIMEOUT)
+        );
+    }
+}
+
+/// Run every cleanup still registered.
+pub fn shutdown() {
+    let mut pending: Vec<Item> = std::mem::take(&mut *items()).into_values().rev().collect();
+    pending.sort_by_key(|item| item.priority);
+    for item in pending {
+        run(item);
+    }
+}
+
+/// Route panics on the main thread and SIGINT/SIGTERM/SIGHUP through [`shutdown`]. Called
+/// once, before anything registers.
+pub fn install() {
+    let default = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default(info);
+        // A panic on another thread is caught where it is joined, and the run goes on
+        if thread::current().name() == Some("main") {
+            shutdown();
+        }
+    }));
+    #[cfg(unix)]
+    install_signal_handlers();
+}
+
+/// Write end of the pipe the signal handler wakes the cleanup thread through.
+#[cfg(unix)]
+static SIGNAL_PIPE: std::sync::atomic::AtomicI32 = std::sync::atomic::AtomicI32::new(-1);
+
+#[cfg(unix)]
+extern "C" fn on_signal(sig: libc::c_int) {
+    let byte = sig as u8;
+    // SAFETY: write(2) from a local buffer is async-signal-safe.
+    unsafe {
+        libc::write(
+            SIGNAL_PIPE.load(Ordering::Relaxed),
+            (&byte as *const u8).cast(),
+            1,
+        );
+    }
+}
+
+/// Cleanups can't run in a signal handler, so the handler only wakes a thread that runs
+/// them and then ends the process with the same signal.
+#[cfg(unix)]
+fn install_signal_handlers() {
+    let mut fds = [0; 2];
+    // SAFETY: pipe(2) fills in the two descriptors.
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return;
+    }
+    let [read_end, write_end] = fds;
+    SIGNAL_PIPE.store(write_end, Ordering::Relaxed);
+    let watcher = thread::Builder::new()
+        .name("shutdown-signals".into())
+        .spawn(move || {
+            let mut byte = 0u8;
+            let sig = loop {
+                // SAFETY: reading one byte from our own pipe into a local.
+                let n = unsafe { libc::read(read_end, (&mut byte as *mut u8).cast(), 1) };
+                if n == 1 {
+                    let sig = libc::c_int::from(byte);
+                    if crate::interrupt::handle(sig) {
+                        continue;
+                    }
+                    break sig;
+                }
+                if n == 0
+                    || std::io::Error::last_os_error().kind() != std::io::ErrorKind::Interrupted
+                {
+                    return;
+                }
+            };
+            shutdown();
+            // SAFETY: restoring the default action and re-raising ends the process as the
+            // signal would have.
+            unsafe {
+                libc::signal(sig, libc::SIG_DFL);
+                libc::raise(sig);
+            }
+        });
+    if watcher.is_err() {
+        return;
+    }
+    for sig in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+        // SAFETY: the handler only calls write(2), which is async-signal-safe.
+        unsafe {
+            libc::signal(
+                sig,
+                on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t,
+            );
+        }
+    }
+}
@@ -0,0 +1,73 @@
+//! Signals sent to a running child by name (`--timeout-warning-signal`). Windows has no
+//! signals to send, so there a name is accepted and nothing is delivered.
+
+use std::io;
+use std::process::Child;
+
+/// Names accepted without the `SIG` prefix, e.g. `USR1`.
+const NAMES: &[&str] = &["HUP", "INT", "QUIT", "USR1", "USR2", "TERM"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Signal {
+    /// Send nothing.
+    None,
+    Named(&'static str),
+}
+
+impl std::fmt::Display for Signal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Signal::None => f.write_str("none"),
+            Signal::Named(name) => write!(f, "SIG{name}"),
+        }
+    }
+}
+
+/// `USR1`, `SIGUSR1`, `usr1`, or `none`.
+pub fn parse(s: &str) -> Result<Signal, String> {
+    if s.eq_ignore_ascii_case("none") {
+        return Ok(Signal::None);
+    }
+    let upper = s.to_ascii_uppercase();
+    let bare = upper.strip_prefix("SIG").unwrap_or(&upper);
+    NAMES
+        .iter()
+        .find(|n| **n == bare)
+        .map(|n| Signal::Named(n))
+        .ok_or_else(|| {
+            format!(
+                "unknown signal `{s}` (expected one of {}, or none)",
+                NAMES.join(", ")
+            )
+        })
+}
+
+#[cfg(unix)]
+fn number(name: &str) -> libc::c_int {
+    match name {
+        "HUP" => libc::SIGHUP,
+        "INT" => libc::SIGINT,
+        "QUIT" => libc::SIGQUIT,
+        "USR2" => libc::SIGUSR2,
+        "TERM" => libc::SIGTERM,
+        _ => libc::SIGUSR1,
+    }
+}
+
+/// Deliver `signal` to `child`; `Ok(false)` when there was nothing to send.
+#[cfg(unix)]
+pub fn send(child: &Child, signal: Signal) -> io::Result<bool> {
+    let Signal::Named(name) = signal else {
+        return Ok(false);
+    };
+    // SAFETY: signalling our own child by pid.
+    if unsafe { libc::kill(child.id() as libc::pid_t, number(name)) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(true)
+}
+
+#[cfg(not(unix))]
+pub fn send(_: &Child, _: Signal) -> io::Result<bool> {
+    Ok(false)
+}
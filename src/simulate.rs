@@ -0,0 +1,426 @@
+//! `rusty-claude simulate --transcript DIR`: replay the attempts an `--attempt-artifacts`
+//! run recorded through the retry decisions, under the settings given now instead of the
+//! ones it ran with. Nothing is spawned and nothing sleeps; each attempt's recorded output,
+//! exit code, and running time are judged as the retry loop would, and the decision is shown
+//! next to the one the run made.
+//!
+//! The clock is simulated: the budget is spent by the recorded running times and the
+//! delays chosen here. Backoff jitter is drawn afresh and `--delay-cmd` is not run, so only
+//! the kind of decision is compared, not the length of the wait.
+
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use clap::{ArgMatches, Args};
+use flate2::read::GzDecoder;
+use regex::Regex;
+use rusty_claude::patterns::Patterns;
+use serde_json::Value;
+
+use crate::duration::format_duration;
+use crate::exit_codes;
+use crate::piped::Killed;
+use crate::policy::{backoff_ms, build_patterns, jitter_rng};
+use crate::size::format_size;
+use crate::trace::Action;
+use crate::verdict::{cap_retry_after, judge, Recorded, Stops, Verdict};
+use crate::{budget, control, guard, tz};
+use crate::{Cli, Commands};
+
+#[derive(Args, Debug)]
+pub struct SimulateArgs {
+    /// An --attempt-artifacts directory, or one attempt-NN.meta.json in it
+    #[arg(long, value_name = "FILE|DIR")]
+    transcript: PathBuf,
+
+    /// A candidate config file to read instead of the usual one
+    #[arg(long, value_name = "PATH")]
+    settings: Option<PathBuf>,
+}
+
+/// One recorded attempt.
+struct Attempt {
+    number: u32,
+    meta: Value,
+    out: Vec<u8>,
+    err: Vec<u8>,
+}
+
+/// The recorded attempts under `transcript`, by number.
+fn load(transcript: &Path) -> Result<Vec<Attempt>, String> {
+    if let Err(e) = fs::metadata(transcript) {
+        return Err(format!("cannot read {}: {e}", transcript.display()));
+    }
+    let (dir, numbers) = if transcript.is_dir() {
+        let entries = fs::read_dir(transcript)
+            .map_err(|e| format!("cannot read {}: {e}", transcript.display()))?;
+        let mut numbers: Vec<u32> = entries
+            .filter_map(|entry| meta_number(&entry.ok()?.file_name().to_string_lossy()))
+            .collect();
+        numbers.sort_unstable();
+        (transcript.to_path_buf(), numbers)
+    } else {
+        let number = transcript
+            .file_name()
+            .and_then(|name| meta_number(&name.to_string_lossy()))
+            .ok_or_else(|| {
+                format!(
+                    "{} is neither an --attempt-artifacts directory nor an attempt-NN.meta.json",
+                    transcript.display()
+                )
+            })?;
+        let dir = transcript.parent().unwrap_or(Path::new("")).to_path_buf();
+        (dir, vec![number])
+    };
+    if numbers.is_empty() {
+        return Err(format!(
+            "{} holds no attempt-NN.meta.json files",
+            transcript.display()
+        ));
+    }
+    numbers
+        .into_iter()
+        .map(|number| {
+            let path = dir.join(format!("attempt-{number:02}.meta.json"));
+            let text = fs::read_to_string(&path)
+                .map_err(|e| format!("cannot read {}: {e}", path.display()))?;
+            let meta = serde_json::from_str(&text)
+                .map_err(|e| format!("cannot parse {}: {e}", path.display()))?;
+            Ok(Attempt {
+                number,
+                meta,
+                out: output(&dir, number, "stdout"),
+                err: output(&dir, number, "stderr"),
+            })
+        })
+        .collect()
+}
+
+/// `attempt-07.meta.json` -> 7.
+fn meta_number(name: &str) -> Option<u32> {
+    name.strip_prefix("attempt-")?
+        .strip_suffix(".meta.json")?
+        .parse()
+        .ok()
+}
+
+/// One output stream of an attempt, plain or compressed; missing reads as empty.
+fn output(dir: &Path, number: u32, stream: &str) -> Vec<u8> {
+    let path = dir.join(format!("attempt-{number:02}.{stream}"));
+    if let Ok(bytes) = fs::read(&path) {
+        return bytes;
+    }
+    let mut out = Vec::new();
+    if let Ok(file) = File::open(dir.join(format!("attempt-{number:02}.{stream}.gz"))) {
+        // A run killed mid-attempt leaves the stream without its trailer; keep what
+        // decompressed
+        let _ = GzDecoder::new(file).read_to_end(&mut out);
+    }
+    out
+}
+
+/// The kill recorded in `meta`, if it changes how the attempt is judged.
+fn killed(meta: &Value) -> Option<Killed> {
+    let idle = || Killed::Idle(Duration::from_millis(meta["idle_ms"].as_u64().unwrap_or(0)));
+    match meta["killed"].as_str() {
+        Some("attempt-timeout") => Some(Killed::Timeout),
+        Some("idle-timeout") => Some(idle()),
+        Some("first-output-timeout") => Some(Killed::NoOutput),
+        Some("abort") => Some(Killed::Aborted),
+        Some("buffer-limit") => Some(Killed::BufferFull),
+        // A mid-stream match is found again in the recorded output
+        Some(_) => None,
+        // Transcripts from before `killed` was recorded
+        None if meta["idle_ms"].is_u64() => Some(idle()),
+        None if meta["timed_out"] == true => Some(Killed::Timeout),
+        None => None,
+    }
+}
+
+/// A decision, as far as the candidate and the original are compared.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    Success,
+    Retry,
+    Fatal,
+    Stop,
+}
+
+/// What the recorded run decided for `meta`'s attempt; `None` when it never finished.
+fn original(meta: &Value) -> Option<(Kind, String)> {
+    let retry = meta["retry"].as_bool()?;
+    Some(if retry {
+        let delay = meta["delay_ms"]
+            .as_u64()
+            .map(|ms| format!(" in {}", format_duration(Duration::from_millis(ms))))
+            .unwrap_or_default();
+        (Kind::Retry, format!("retry{delay}"))
+    } else if meta.get("fatal").is_none() {
+        (Kind::Success, "success".to_string())
+    } else if let Some(pattern) = meta["fatal"].as_str() {
+        (Kind::Fatal, format!("stop (fatal pattern `{pattern}`)"))
+    } else {
+        (Kind::Stop, "stop".to_string())
+    })
+}
+
+/// Everything `judge` needs, compiled from the resolved settings.
+fn prepare(cli: &Cli) -> Result<(Patterns, Option<Regex>), String> {
+    let mut patterns = build_patterns(cli)?;
+    if !cli.server_mode {
+        patterns.retry_codes = cli.retry_exit_codes.clone().unwrap_or_default();
+        patterns.no_retry_codes = cli.no_retry_exit_codes.clone().unwrap_or_default();
+    }
+    patterns.streams = cli.match_streams;
+    let success_pattern = cli
+        .success_pattern
+        .as_deref()
+        .map(Regex::new)
+        .transpose()
+        .map_err(|e| format!("invalid --success-pattern: {e}"))?;
+    if let Some(name) = &cli.assume_tz {
+        tz::assume(
+            tz::zone(name)
+                .ok_or_else(|| format!("--assume-tz `{name}` is not a known time zone"))?,
+        );
+    }
+    Ok((patterns, success_pattern))
+}
+
+/// `rusty-claude simulate`; returns the process exit code.
+pub fn run(mut cli: Cli, matches: &ArgMatches) -> i32 {
+    let Some(Commands::Simulate(args)) = cli.command.take() else {
+        unreachable!("dispatched on the simulate subcommand");
+    };
+    if let Some(path) = args.settings {
+        cli.config = Some(path);
+        cli.no_config = false;
+    }
+    crate::set_env_policy(&cli);
+    let prepared = crate::load_layers(&cli).and_then(|layers| {
+        crate::resolve_settings(&mut cli, matches, &layers);
+        prepare(&cli)
+    });
+    let (patterns, success_pattern) = match prepared {
+        Ok(prepared) => prepared,
+        Err(e) => {
+            eprintln!("[rusty-claude] error: {e}");
+            return exit_codes::CONFIG_ERROR;
+        }
+    };
+    let attempts = match load(&args.transcript) {
+        Ok(attempts) => attempts,
+        Err(e) => {
+            eprintln!("[rusty-claude] simulate: {e}");
+            return exit_codes::CONFIG_ERROR;
+        }
+    };
+    if cli.delay_cmd.is_some() {
+        eprintln!("[rusty-claude] simulate: --delay-cmd is not run; using the built-in backoff");
+    }
+
+    let guards = guard::Guards {
+        min_runtime: cli.min_runtime_for_retry,
+        disabled: cli.no_retry_guard.clone(),
+    };
+    let mut class_budget = cli.class_budget.clone().unwrap_or_default();
+    let start = Instant::now();
+    let time_budget = cli
+        .max_total_ms
+        .map(|ms| budget::Budget::new(start, Duration::from_millis(ms)));
+    let mut elapsed = cli.initial_delay.unwrap_or_default();
+    let mut previous_wait = None;
+    let mut rng = jitter_rng(&cli);
+    let mut total_output = 0;
+    let mut differs = Vec::new();
+    // One attempt's file replays that attempt alone
+    let mut expected = if args.transcript.is_dir() {
+        1
+    } else {
+        attempts[0].number
+    };
+    let mut last = None;
+
+    for attempt in &attempts {
+        match attempt.number - expected {
+            0 => {}
+            1 => println!("(attempt {expected} is missing from the transcript)"),
+            _ => println!(
+                "(attempts {expected} to {} are missing from the transcript)",
+                attempt.number - 1
+            ),
+        }
+        expected = attempt.number + 1;
+        let meta = &attempt.meta;
+        let Some(code_field) = meta.get("code") else {
+            println!(
+                "attempt {}: never finished (the run itself was cut short)",
+                attempt.number
+            );
+            break;
+        };
+        let code = code_field.as_i64().map(|c| c as i32);
+        let runtime = Duration::from_millis(meta["duration_ms"].as_u64().unwrap_or(0));
+        let killed = killed(meta);
+        let recorded = Recorded {
+            out: &attempt.out,
+            err: &attempt.err,
+            code,
+            killed,
+        };
+        let verdict = judge(&recorded, success_pattern.as_ref(), &cli, &patterns);
+        elapsed += runtime;
+        let bytes = meta["stdout_bytes"]
+            .as_u64()
+            .unwrap_or(attempt.out.len() as u64)
+            + meta["stderr_bytes"]
+                .as_u64()
+                .unwrap_or(attempt.err.len() as u64);
+        total_output += bytes;
+        println!(
+            "attempt {}: exit {} after {}, {} of output{}",
+            attempt.number,
+            crate::run::code_label(code),
+            format_duration(runtime),
+            format_size(bytes),
+            killed
+                .map(|k| format!(", killed ({})", k.as_str()))
+                .unwrap_or_default()
+        );
+
+        let index = attempt.number - 1;
+        let (kind, would) = match verdict {
+            Verdict::Success => (Kind::Success, "success".to_string()),
+            Verdict::Failure(mut decision) => {
+                cap_retry_after(&mut decision, &cli);
+                let guarded = (decision.retry
+                    && decision.matched.is_none()
+                    && decision.json_error.is_none()
+                    && !decision.retry_code
+                    && killed.is_none())
+                .then(|| guards.check(code, runtime, &String::from_utf8_lossy(&attempt.err)))
+                .flatten();
+                if guarded.is_some() {
+                    decision.retry = false;
+                }
+                let halted = (killed == Some(Killed::Aborted))
+                    .then_some(control::Command::Abort)
+                    .filter(|_| decision.retry);
+                let class_exhausted = decision.retry
+                    && index < cli.max_retries
+                    && class_budget.take(decision.class).is_err();
+                let wait = match (decision.retry_after_ms, killed) {
+                    (Some(ms), _) => ms,
+                    (None, Some(Killed::NoOutput)) => {
+                        cli.first_output_retry_delay.as_millis() as u64
+                    }
+                    (None, _) => backoff_ms(index, previous_wait, &cli, &mut rng),
+                };
+                let wait = decision.floored(wait);
+                previous_wait = Some(wait);
+                let now = start + elapsed;
+                let wait = Duration::from_millis(wait);
+                let action = Stops {
+                    fatal: decision.fatal.is_some(),
+                    // The recorded run was interrupted here, whatever the settings
+                    interrupted: decision.retry && meta["interrupted"].is_string(),
+                    halted,
+                    retryable: decision.retry,
+                    last_attempt: index >= cli.max_retries,
+                    class_exhausted,
+                    over_output: cli.max_total_output.is_some_and(|l| total_output > l),
+                    over_budget: budget::check_wait(time_budget.as_ref(), wait, now).is_err(),
+                }
+                .action();
+
+                if let Some(pattern) = &decision.matched {
+                    let class = decision
+                        .class
+                        .map(|c| format!(" ({})", c.as_str()))
+                        .unwrap_or_default();
+                    println!("  matched  `{pattern}`{class}");
+                }
+                if let Some(error_type) = &decision.json_error {
+                    println!("  json     error type `{error_type}`");
+                }
+                match action {
+                    Action::Retry => {
+                        elapsed += wait;
+                        let budget_left = time_budget
+                            .map(|b| {
+                                format!(
+                                    " and {} of budget",
+                                    format_duration(b.remaining(start + elapsed))
+                                )
+                            })
+                            .unwrap_or_default();
+                        let retries = match cli.max_retries - index - 1 {
+                            1 => "1 retry".to_string(),
+                            n => format!("{n} retries"),
+                        };
+                        (
+                            Kind::Retry,
+                            format!(
+                                "retry in {}, with {retries}{budget_left} left",
+                                format_duration(wait)
+                            ),
+                        )
+                    }
+                    Action::Fatal => (
+                        Kind::Fatal,
+                        format!(
+                            "stop (fatal pattern `{}`)",
+                            decision.fatal.unwrap_or_default()
+                        ),
+                    ),
+                    action => {
+                        let why = guarded
+                            .map(|(_, why)| format!(": {why}"))
+                            .unwrap_or_default();
+                        (Kind::Stop, format!("stop ({}{why})", action.as_str()))
+                    }
+                }
+            }
+        };
+        println!("  would    {would}");
+        match original(meta) {
+            Some((did, text)) => {
+                let mark = if did == kind {
+                    ""
+                } else {
+                    differs.push(attempt.number);
+                    "  <- differs"
+                };
+                println!("  did      {text}{mark}");
+            }
+            None => println!("  did      (not recorded)"),
+        }
+        last = Some((attempt.number, kind));
+        if kind != Kind::Retry {
+            break;
+        }
+    }
+
+    let recorded_last = attempts.last().map_or(0, |a| a.number);
+    match last {
+        Some((number, Kind::Retry)) => {
+            println!("the transcript ends at attempt {number}, where these settings would retry")
+        }
+        Some((number, _)) if number < recorded_last => println!(
+            "these settings stop at attempt {number}; the run went on to attempt {recorded_last}"
+        ),
+        _ => {}
+    }
+    if differs.is_empty() {
+        println!("same decisions as the recorded run");
+    } else {
+        let list: Vec<String> = differs.iter().map(u32::to_string).collect();
+        println!(
+            "decisions differ from the recorded run at attempt {}",
+            list.join(", ")
+        );
+    }
+    0
+}
@@ -0,0 +1,43 @@
+//! Parsing of byte sizes such as `512K`, `10MB`, or `2GiB` used by the size-limit flags.
+
+/// Parse a byte count with an optional decimal (`K`, `M`, `G`) or binary (`KiB`, `MiB`,
+/// `GiB`) suffix. A trailing `B` is optional and suffixes are case-insensitive.
+pub fn parse_size(input: &str) -> Result<u64, String> {
+    let s = input.trim();
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (num, unit) = s.split_at(split);
+    let value: f64 = num
+        .parse()
+        .map_err(|_| format!("invalid size `{input}`: expected a number"))?;
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1_000,
+        "m" | "mb" => 1_000_000,
+        "g" | "gb" => 1_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        other => return Err(format!("invalid size `{input}`: unknown unit `{other}`")),
+    };
+    Ok((value * multiplier as f64).round() as u64)
+}
+
+/// Compact display of a byte count, e.g. `512B`, `3.4KB`, `2.1GB`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1_000 {
+        return format!("{bytes}B");
+    }
+    let mut value = bytes as f64;
+    let mut unit = "B";
+    for u in UNITS {
+        if value < 1_000.0 {
+            break;
+        }
+        value /= 1_000.0;
+        unit = u;
+    }
+    format!("{value:.1}{unit}")
+}
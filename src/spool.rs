@@ -0,0 +1,216 @@
+//! Piped stdin, read once and replayed to every attempt.
+//!
+//! Input up to `--stdin-spool-threshold-bytes` is read to the end and kept in memory, as it
+//! always was. Past that, reading stops waiting for the end: what has arrived goes to a
+//! temp file (private to the user) and the rest is pumped there on a thread of its own,
+//! while the attempt replays the file as it grows. A 2GB dump then starts the child at once
+//! and costs no memory, and a retry replays from the file. The file is removed with
+//! everything else at [`shutdown`](crate::shutdown).
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread;
+
+use crate::shutdown::{self, Priority, Registration};
+
+/// Default for `--stdin-spool-threshold-bytes`.
+pub const DEFAULT_THRESHOLD: u64 = 8 * 1024 * 1024;
+/// How much is read from stdin, or the spool file, at a time.
+const CHUNK: usize = 64 * 1024;
+
+/// The captured input; cloning it is cheap.
+#[derive(Clone)]
+pub enum Input {
+    Memory(Arc<Vec<u8>>),
+    Spooled(Arc<Spool>),
+}
+
+/// Input on its way to, or already in, a temp file.
+pub struct Spool {
+    path: PathBuf,
+    state: Mutex<State>,
+    grew: Condvar,
+    _removal: Registration,
+}
+
+#[derive(Default)]
+struct State {
+    /// Bytes in the file so far.
+    written: u64,
+    done: bool,
+    /// Why the pump stopped before the end of stdin.
+    error: Option<String>,
+}
+
+impl Input {
+    /// Read `src` up to `threshold`, keeping it in memory if it ends there and spooling it
+    /// otherwise.
+    pub fn read(mut src: impl Read + Send + 'static, threshold: u64) -> io::Result<Input> {
+        let mut head = Vec::new();
+        src.by_ref()
+            .take(threshold.saturating_add(1))
+            .read_to_end(&mut head)?;
+        if head.len() as u64 <= threshold {
+            return Ok(Input::from(head));
+        }
+        let spool = Arc::new(Spool::create()?);
+        let mut file = OpenOptions::new().append(true).open(&spool.path)?;
+        file.write_all(&head)?;
+        spool.state().written = head.len() as u64;
+        drop(head);
+        let pumped = Arc::clone(&spool);
+        thread::Builder::new()
+            .name("stdin-spool".into())
+            .spawn(move || pumped.pump(&mut src, &mut file))?;
+        Ok(Input::Spooled(spool))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Input::Memory(data) => data.is_empty(),
+            Input::Spooled(_) => false,
+        }
+    }
+
+    /// Bytes of input so far; a spool still filling up may get more.
+    pub fn len(&self) -> u64 {
+        match self {
+            Input::Memory(data) => data.len() as u64,
+            Input::Spooled(spool) => spool.state().written,
+        }
+    }
+
+    /// Bytes held in memory.
+    pub fn in_memory(&self) -> u64 {
+        match self {
+            Input::Memory(data) => data.len() as u64,
+            Input::Spooled(_) => 0,
+        }
+    }
+
+    pub fn spooled(&self) -> bool {
+        matches!(self, Input::Spooled(_))
+    }
+
+    /// All of the input, once stdin has ended.
+    pub fn to_vec(&self) -> io::Result<Vec<u8>> {
+        match self {
+            Input::Memory(data) => Ok(data.to_vec()),
+            Input::Spooled(spool) => {
+                spool.wait_done()?;
+                fs::read(&spool.path)
+            }
+        }
+    }
+
+    /// Write the whole input to `dst` a chunk at a time, following a spool as it fills.
+    pub fn write_to(&self, dst: &mut impl Write) -> io::Result<()> {
+        match self {
+            Input::Memory(data) => data
+                .chunks(CHUNK)
+                .try_for_each(|chunk| dst.write_all(chunk)),
+            Input::Spooled(spool) => spool.write_to(dst),
+        }
+    }
+}
+
+impl From<Vec<u8>> for Input {
+    fn from(data: Vec<u8>) -> Self {
+        Input::Memory(Arc::new(data))
+    }
+}
+
+impl Spool {
+    fn create() -> io::Result<Self> {
+        let path = std::env::temp_dir().join(format!(
+            "rusty-claude-stdin-{}-{:08x}",
+            std::process::id(),
+            rand::random::<u32>()
+        ));
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options.open(&path)?;
+        let removed = path.clone();
+        Ok(Spool {
+            path,
+            state: Mutex::new(State::default()),
+            grew: Condvar::new(),
+            _removal: shutdown::register("stdin spool file", Priority::Normal, move || {
+                let _ = fs::remove_file(removed);
+            }),
+        })
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Move the rest of `src` into the file, waking replays as it grows.
+    fn pump(&self, src: &mut impl Read, file: &mut File) {
+        let mut buf = vec![0u8; CHUNK];
+        let error = loop {
+            let n = match src.read(&mut buf) {
+                Ok(0) => break None,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => break Some(format!("reading stdin: {e}")),
+            };
+            if let Err(e) = file.write_all(&buf[..n]) {
+                break Some(format!("writing {}: {e}", self.path.display()));
+            }
+            self.state().written += n as u64;
+            self.grew.notify_all();
+        };
+        let mut state = self.state();
+        state.done = true;
+        state.error = error;
+        drop(state);
+        self.grew.notify_all();
+    }
+
+    fn wait_done(&self) -> io::Result<()> {
+        let mut state = self.state();
+        while !state.done {
+            state = self
+                .grew
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        match &state.error {
+            Some(e) => Err(io::Error::other(e.clone())),
+            None => Ok(()),
+        }
+    }
+
+    fn write_to(&self, dst: &mut impl Write) -> io::Result<()> {
+        let mut file = File::open(&self.path)?;
+        let mut buf = vec![0u8; CHUNK];
+        let mut sent = 0u64;
+        loop {
+            let available = {
+                let mut state = self.state();
+                while state.written == sent && !state.done {
+                    state = self
+                        .grew
+                        .wait(state)
+                        .unwrap_or_else(PoisonError::into_inner);
+                }
+                if state.written == sent {
+                    return match &state.error {
+                        Some(e) => Err(io::Error::other(e.clone())),
+                        None => Ok(()),
+                    };
+                }
+                state.written - sent
+            };
+            let n = available.min(CHUNK as u64) as usize;
+            file.read_exact(&mut buf[..n])?;
+            dst.write_all(&buf[..n])?;
+            sent += n as u64;
+        }
+    }
+}
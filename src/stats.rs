@@ -0,0 +1,315 @@
+//! `--stats-sink PATH`: one compact JSON line per invocation appended to a file shared by a
+//! whole pipeline, and `rusty-claude stats --sink PATH` to aggregate it.
+//!
+//! Each line is written with a single `write` on an `O_APPEND` file, so concurrent
+//! invocations never interleave. Lines that don't parse (a writer killed mid-line, a
+//! truncated copy) are skipped and counted rather than failing the report.
+
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use serde_json::{json, Value};
+
+use crate::duration::format_duration;
+use crate::envvars;
+use crate::exit_codes::{self, Outcome};
+use crate::schema;
+
+/// CI variables naming the pipeline run, used when `RUSTY_CLAUDE_PIPELINE_ID` is unset.
+const PIPELINE_VARS: &[&str] = &[
+    "GITHUB_RUN_ID",
+    "CI_PIPELINE_ID",
+    "BUILD_BUILDID",
+    "BUILDKITE_BUILD_ID",
+];
+
+/// How many patterns and runs the report lists.
+const TOP: usize = 5;
+
+fn pipeline_id() -> Option<String> {
+    envvars::var("PIPELINE_ID").map(|v| v.value).or_else(|| {
+        PIPELINE_VARS
+            .iter()
+            .find_map(|k| std::env::var(k).ok().filter(|v| !v.is_empty()))
+    })
+}
+
+/// The sink record for one finished invocation.
+pub fn record(outcome: &Outcome, run_id: &str, started_at_ms: u64, duration_ms: u64) -> Value {
+    json!({
+        "schema": schema::STATS,
+        "run_id": run_id,
+        "pipeline_id": pipeline_id(),
+        "started_at_ms": started_at_ms,
+        "duration_ms": duration_ms,
+        "attempts": outcome.attempts,
+        "reason": outcome.reason.as_str(),
+        "exit_code": outcome.exit_code,
+        "child_exit_code": outcome.child_code,
+        "matched": outcome.matched.iter().map(|(pattern, class)| json!({
+            "pattern": pattern,
+            "class": class.map(|c| c.as_str()),
+        })).collect::<Vec<_>>(),
+        "wasted_wall_ms": outcome.waste.as_ref().map(|w| w.wall.as_millis() as u64),
+    })
+}
+
+/// Append `record` as one line with a single write.
+pub fn append(path: &Path, record: &Value) -> io::Result<()> {
+    let mut line = record.to_string();
+    line.push('\n');
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(line.as_bytes())
+}
+
+#[derive(Args, Debug)]
+pub struct StatsArgs {
+    /// The sink file written by --stats-sink
+    #[arg(long, value_name = "PATH")]
+    sink: PathBuf,
+
+    /// Print the aggregate as JSON instead of a table
+    #[arg(long)]
+    json: bool,
+}
+
+/// Totals over a sink's records.
+#[derive(Debug, Default, PartialEq)]
+pub struct Aggregate {
+    pub runs: u64,
+    pub succeeded: u64,
+    pub attempts: u64,
+    /// Runs that needed more than one attempt.
+    pub retried: u64,
+    pub skipped_lines: u64,
+    pub pipelines: Vec<String>,
+    pub reasons: Vec<(String, u64)>,
+    /// Most-matched patterns first.
+    pub top_patterns: Vec<(String, u64)>,
+    /// Slowest runs first: run id, duration, reason.
+    pub slowest: Vec<(String, u64, String)>,
+}
+
+impl Aggregate {
+    pub fn retry_rate(&self) -> f64 {
+        if self.runs == 0 {
+            0.0
+        } else {
+            self.retried as f64 / self.runs as f64
+        }
+    }
+}
+
+fn counted(map: HashMap<String, u64>) -> Vec<(String, u64)> {
+    let mut v: Vec<_> = map.into_iter().collect();
+    v.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    v
+}
+
+/// Aggregate the sink's lines; anything that isn't a record is skipped and counted.
+pub fn aggregate(text: &str) -> Aggregate {
+    let mut agg = Aggregate::default();
+    let mut pipelines = Vec::new();
+    let mut reasons = HashMap::new();
+    let mut patterns = HashMap::new();
+    let mut durations = Vec::new();
+    for line in text.lines().filter(|l| !l.trim().is_empty()) {
+        let Some(rec) = serde_json::from_str::<Value>(line)
+            .ok()
+            .filter(|r| r["run_id"].is_string() && r["attempts"].is_u64())
+        else {
+            agg.skipped_lines += 1;
+            continue;
+        };
+        let attempts = rec["attempts"].as_u64().unwrap_or(0);
+        let reason = rec["reason"].as_str().unwrap_or("unknown").to_string();
+        agg.runs += 1;
+        agg.attempts += attempts;
+        agg.retried += u64::from(attempts > 1);
+        agg.succeeded += u64::from(reason == "success");
+        if let Some(p) = rec["pipeline_id"].as_str() {
+            if !pipelines.iter().any(|q| q == p) {
+                pipelines.push(p.to_string());
+            }
+        }
+        for m in rec["matched"].as_array().into_iter().flatten() {
+            if let Some(p) = m["pattern"].as_str() {
+                *patterns.entry(p.to_string()).or_insert(0) += 1;
+            }
+        }
+        durations.push((
+            rec["run_id"].as_str().unwrap_or_default().to_string(),
+            rec["duration_ms"].as_u64().unwrap_or(0),
+            reason.clone(),
+        ));
+        *reasons.entry(reason).or_insert(0) += 1;
+    }
+    durations.sort_by_key(|d| std::cmp::Reverse(d.1));
+    durations.truncate(TOP);
+    let mut top_patterns = counted(patterns);
+    top_patterns.truncate(TOP);
+    Aggregate {
+        pipelines,
+        reasons: counted(reasons),
+        top_patterns,
+        slowest: durations,
+        ..agg
+    }
+}
+
+/// `rusty-claude stats`; returns the process exit code.
+pub fn run(args: &StatsArgs) -> i32 {
+    let text = match fs::read(&args.sink) {
+        Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+        Err(e) => {
+            eprintln!(
+                "[rusty-claude] stats: cannot read {}: {e}",
+                args.sink.display()
+            );
+            return exit_codes::CONFIG_ERROR;
+        }
+    };
+    let agg = aggregate(&text);
+    if args.json {
+        let pairs = |v: &[(String, u64)], key: &str| {
+            v.iter()
+                .map(|(name, n)| json!({ key: name, "count": n }))
+                .collect::<Vec<_>>()
+        };
+        let report = json!({
+            "schema": schema::STATS_REPORT,
+            "runs": agg.runs,
+            "succeeded": agg.succeeded,
+            "attempts": agg.attempts,
+            "retried_runs": agg.retried,
+            "retry_rate": agg.retry_rate(),
+            "skipped_lines": agg.skipped_lines,
+            "pipelines": agg.pipelines,
+            "reasons": pairs(&agg.reasons, "reason"),
+            "top_patterns": pairs(&agg.top_patterns, "pattern"),
+            "slowest": agg.slowest.iter().map(|(id, ms, reason)| json!({
+                "run_id": id, "duration_ms": ms, "reason": reason,
+            })).collect::<Vec<_>>(),
+        });
+        println!("{report:#}");
+        return 0;
+    }
+
+    println!(
+        "{} runs across {} pipeline(s): {} succeeded, {} attempts, {:.0}% retried",
+        agg.runs,
+        agg.pipelines.len(),
+        agg.succeeded,
+        agg.attempts,
+        agg.retry_rate() * 100.0
+    );
+    if agg.skipped_lines > 0 {
+        println!("  ({} unreadable line(s) skipped)", agg.skipped_lines);
+    }
+    if !agg.reasons.is_empty() {
+        println!("outcomes:");
+        for (reason, n) in &agg.reasons {
+            println!("  {n:>6}  {reason}");
+        }
+    }
+    if !agg.top_patterns.is_empty() {
+        println!("top patterns:");
+        for (pattern, n) in &agg.top_patterns {
+            println!("  {n:>6}  {pattern}");
+        }
+    }
+    if !agg.slowest.is_empty() {
+        println!("slowest runs:");
+        for (id, ms, reason) in &agg.slowest {
+            println!(
+                "  {:>8}  {id} ({reason})",
+                format_duration(std::time::Duration::from_millis(*ms))
+            );
+        }
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn run_record(run_id: &str, attempts: u64, reason: &str, duration_ms: u64) -> Value {
+        json!({
+            "schema": schema::STATS,
+            "run_id": run_id,
+            "pipeline_id": "ci-1",
+            "duration_ms": duration_ms,
+            "attempts": attempts,
+            "reason": reason,
+            "matched": if attempts > 1 {
+                json!([{"pattern": "(?i)overloaded", "class": "overloaded"}])
+            } else {
+                json!([])
+            },
+        })
+    }
+
+    #[test]
+    fn concurrent_appends_stay_whole_lines() {
+        let sink = std::env::temp_dir().join(format!(
+            "rusty-claude-stats-test-{}.jsonl",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&sink);
+        let record = run_record("r", 2, "success", 10);
+        let writers: Vec<_> = (0..4)
+            .map(|_| {
+                let (sink, record) = (sink.clone(), record.clone());
+                thread::spawn(move || (0..25).try_for_each(|_| append(&sink, &record)))
+            })
+            .collect();
+        for w in writers {
+            w.join().expect("writer panicked").expect("append");
+        }
+        // A writer that died mid-line
+        let line = record.to_string();
+        OpenOptions::new()
+            .append(true)
+            .open(&sink)
+            .and_then(|mut f| f.write_all(&line.as_bytes()[..line.len() / 2]))
+            .unwrap();
+        let text = fs::read_to_string(&sink).unwrap();
+        let _ = fs::remove_file(&sink);
+        let agg = aggregate(&text);
+        assert_eq!((agg.runs, agg.attempts, agg.skipped_lines), (100, 200, 1));
+        assert_eq!(agg.top_patterns, [("(?i)overloaded".to_string(), 100)]);
+    }
+
+    #[test]
+    fn aggregate_totals() {
+        let text = [
+            run_record("a", 1, "success", 300).to_string(),
+            run_record("b", 3, "success", 9_000).to_string(),
+            String::new(),
+            "not json".to_string(),
+            r#"{"run_id": "no attempts"}"#.to_string(),
+            run_record("c", 2, "exhausted", 1_200).to_string(),
+        ]
+        .join("\n");
+        let agg = aggregate(&text);
+        assert_eq!(
+            (agg.runs, agg.succeeded, agg.attempts, agg.retried),
+            (3, 2, 6, 2)
+        );
+        assert_eq!(agg.skipped_lines, 2);
+        assert!((agg.retry_rate() - 2.0 / 3.0).abs() < 1e-9);
+        assert_eq!(agg.pipelines, ["ci-1"]);
+        assert_eq!(
+            agg.reasons,
+            [("success".to_string(), 2), ("exhausted".to_string(), 1)]
+        );
+        let slowest: Vec<_> = agg.slowest.iter().map(|s| s.0.as_str()).collect();
+        assert_eq!(slowest, ["b", "c", "a"]);
+        assert_eq!(aggregate("").retry_rate(), 0.0);
+    }
+}
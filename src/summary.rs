@@ -0,0 +1,175 @@
+//! `--stats` and `--stats-json`: once the run is over, where its time went. Every finished
+//! attempt is recorded here as it ends, whatever path the run then leaves by, and `main`
+//! reports them after the last one: how long each attempt ran and how it ended, how long was
+//! spent sleeping between them, and which patterns sent it round again.
+//!
+//! The JSON is one document, to a file or `-` for stderr; never stdout, which carries the
+//! child's output.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+use serde_json::{json, Map, Value};
+
+use crate::duration::format_duration;
+use crate::exit_codes::Outcome;
+use crate::schema;
+
+/// The attempt's outcome fields the summary carries over from its metadata.
+const ATTEMPT_FIELDS: &[&str] = &[
+    "code",
+    "duration_ms",
+    "retry",
+    "delay_ms",
+    "matched",
+    "class",
+    "killed",
+];
+
+static ATTEMPTS: Mutex<Vec<Value>> = Mutex::new(Vec::new());
+static SLEPT_MS: AtomicU64 = AtomicU64::new(0);
+static PRE_DELAY_MS: AtomicU64 = AtomicU64::new(0);
+
+/// Record a finished attempt (1-based), from the `outcome` its metadata records.
+pub fn attempt(attempt: u32, outcome: &Value) {
+    let mut record = Map::new();
+    record.insert("attempt".into(), attempt.into());
+    for &field in ATTEMPT_FIELDS {
+        record.insert(
+            field.into(),
+            outcome.get(field).cloned().unwrap_or(Value::Null),
+        );
+    }
+    ATTEMPTS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .push(Value::Object(record));
+}
+
+/// Record time spent waiting between attempts.
+pub fn slept(time: Duration) {
+    SLEPT_MS.fetch_add(time.as_millis() as u64, Ordering::Relaxed);
+}
+
+/// Record the `--initial-delay` wait before the first attempt.
+pub fn pre_delay(time: Duration) {
+    PRE_DELAY_MS.store(time.as_millis() as u64, Ordering::Relaxed);
+}
+
+/// The `--initial-delay` wait, in ms, 0 without one.
+pub fn pre_delay_ms() -> u64 {
+    PRE_DELAY_MS.load(Ordering::Relaxed)
+}
+
+/// The run as one JSON document.
+pub fn document(outcome: &Outcome, run_id: &str, duration_ms: u64) -> Value {
+    let attempts = ATTEMPTS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone();
+    let child_ms: u64 = attempts
+        .iter()
+        .filter_map(|a| a["duration_ms"].as_u64())
+        .sum();
+    json!({
+        "schema": schema::SUMMARY,
+        "run_id": run_id,
+        "reason": outcome.reason.as_str(),
+        "exit_code": outcome.exit_code,
+        "child_exit_code": outcome.child_code,
+        "attempts": outcome.attempts,
+        "duration_ms": duration_ms,
+        "child_ms": child_ms,
+        "pre_delay_ms": pre_delay_ms(),
+        "sleep_ms": SLEPT_MS.load(Ordering::Relaxed),
+        "matched": outcome.matched.iter().map(|(pattern, class)| json!({
+            "pattern": pattern,
+            "class": class.map(|c| c.as_str()),
+        })).collect::<Vec<_>>(),
+        "attempt_records": attempts,
+    })
+}
+
+fn ms(value: &Value) -> String {
+    format_duration(Duration::from_millis(value.as_u64().unwrap_or(0)))
+}
+
+/// The `--stats` lines for `doc`.
+pub fn render(doc: &Value) -> String {
+    let mut text = format!(
+        "[rusty-claude] run summary: {}, exit {} after {} attempt(s) in {}\n",
+        doc["reason"].as_str().unwrap_or_default(),
+        doc["exit_code"],
+        doc["attempts"],
+        ms(&doc["duration_ms"])
+    );
+    for a in doc["attempt_records"].as_array().into_iter().flatten() {
+        let code = match &a["code"] {
+            Value::Null => "killed by a signal".to_string(),
+            code => format!("exit {code}"),
+        };
+        let mut line = format!(
+            "[rusty-claude]   attempt {}: {code} after {}",
+            a["attempt"],
+            ms(&a["duration_ms"])
+        );
+        if let Some(killed) = a["killed"].as_str() {
+            line += &format!(", killed ({killed})");
+        }
+        if let Some(pattern) = a["matched"].as_str() {
+            line += &format!(", matched `{pattern}`");
+            if let Some(class) = a["class"].as_str() {
+                line += &format!(" ({class})");
+            }
+        }
+        if a["retry"] == true {
+            line += &format!("; retried after {}", ms(&a["delay_ms"]));
+        }
+        text += &line;
+        text.push('\n');
+    }
+    let pre_delay = match doc["pre_delay_ms"].as_u64() {
+        Some(0) | None => String::new(),
+        Some(_) => format!(
+            ", waiting {} before the first attempt",
+            ms(&doc["pre_delay_ms"])
+        ),
+    };
+    text += &format!(
+        "[rusty-claude]   child running {}{pre_delay}, sleeping between attempts {}\n",
+        ms(&doc["child_ms"]),
+        ms(&doc["sleep_ms"])
+    );
+    let mut counts: Vec<(&str, u64)> = Vec::new();
+    for m in doc["matched"].as_array().into_iter().flatten() {
+        let pattern = m["pattern"].as_str().unwrap_or_default();
+        match counts.iter_mut().find(|(p, _)| *p == pattern) {
+            Some((_, n)) => *n += 1,
+            None => counts.push((pattern, 1)),
+        }
+    }
+    if !counts.is_empty() {
+        let list: Vec<String> = counts
+            .iter()
+            .map(|(pattern, n)| format!("`{pattern}` x{n}"))
+            .collect();
+        text += &format!("[rusty-claude]   patterns matched: {}\n", list.join(", "));
+    }
+    text
+}
+
+/// Write `doc` to `path`, or to stderr when it is `-`.
+pub fn write_json(path: &Path, doc: &Value) -> io::Result<()> {
+    let text = format!("{doc:#}\n");
+    if path == Path::new("-") {
+        let mut stderr = io::stderr().lock();
+        stderr.write_all(text.as_bytes())?;
+        stderr.flush()
+    } else {
+        fs::write(path, text)
+    }
+}
@@ -0,0 +1,80 @@
+//! `--tag-attempts`: a tag naming one attempt of one run, `<run_id>-<attempt>`, handed to
+//! the child so whatever it talks to (a logging proxy, the API) can record it. The same tag
+//! appears in events and attempt metadata, so both sides of the correlation exist.
+
+use clap::ValueEnum;
+
+/// Variable the child sees the tag in, in `env` mode.
+pub const ENV_VAR: &str = "RUSTY_CLAUDE_ATTEMPT_TAG";
+
+/// Placeholder replaced by the tag in `--tag-arg`.
+pub const PLACEHOLDER: &str = "{tag}";
+
+/// `--tag-arg` unless given: an extra request header, for children that take one.
+pub const DEFAULT_TEMPLATE: &str = "--extra-header X-Client-Tag:{tag}";
+
+/// How the child is handed the tag.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum TagMode {
+    /// Export it as RUSTY_CLAUDE_ATTEMPT_TAG
+    Env,
+    /// Append the --tag-arg arguments to the child args
+    Header,
+}
+
+pub fn attempt_tag(run_id: &str, attempt: u32) -> String {
+    format!("{run_id}-{attempt}")
+}
+
+/// Reject a `--tag-arg` template that would never carry the tag.
+pub fn check_template(template: &str) -> Result<(), String> {
+    if template.split_whitespace().next().is_none() {
+        return Err("--tag-arg is empty".to_string());
+    }
+    if !template.contains(PLACEHOLDER) {
+        return Err(format!(
+            "--tag-arg `{template}` has no {PLACEHOLDER} placeholder"
+        ));
+    }
+    Ok(())
+}
+
+/// The child args for `template`: whitespace-separated words with `{tag}` replaced, so
+/// `--flag {tag}` gives two args and `--flag={tag}` one.
+pub fn expand(template: &str, tag: &str) -> Vec<String> {
+    template
+        .split_whitespace()
+        .map(|word| word.replace(PLACEHOLDER, tag))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn templates_expand_word_by_word() {
+        let table: [(&str, &[&str]); 4] = [
+            (DEFAULT_TEMPLATE, &["--extra-header", "X-Client-Tag:r-2"]),
+            (
+                "--extra-header=X-Client-Tag:{tag}",
+                &["--extra-header=X-Client-Tag:r-2"],
+            ),
+            ("-H  X-Tag:{tag}  --trace", &["-H", "X-Tag:r-2", "--trace"]),
+            ("--id {tag}-{tag}", &["--id", "r-2-r-2"]),
+        ];
+        for (template, want) in table {
+            assert_eq!(check_template(template), Ok(()));
+            assert_eq!(expand(template, &attempt_tag("r", 2)), want, "`{template}`");
+        }
+    }
+
+    #[test]
+    fn templates_without_the_tag_are_refused() {
+        assert_eq!(
+            check_template("--extra-header X-Client-Tag"),
+            Err("--tag-arg `--extra-header X-Client-Tag` has no {tag} placeholder".to_string())
+        );
+        assert_eq!(check_template(" "), Err("--tag-arg is empty".to_string()));
+    }
+}
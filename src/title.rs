@@ -0,0 +1,147 @@
+//! `--set-title`: show the wrapper's state in the terminal title, so several terminals running
+//! long jobs can be told apart at a glance.
+//!
+//! The original title is saved on the terminal's title stack (`CSI 22;2 t`) and popped again
+//! at shutdown (see `crate::shutdown`), including when a signal ends the run; terminals
+//! without a stack just keep ours.
+
+use std::io::{self, Write};
+use std::time::Duration;
+
+use crate::duration::format_duration;
+use crate::shutdown::{self, Priority, Registration};
+
+const PUSH_TITLE: &str = "\x1b[22;2t";
+const POP_TITLE: &str = "\x1b[23;2t";
+
+/// What the wrapper is doing right now.
+pub enum State<'a> {
+    Running { attempt: u32 },
+    Waiting { delay: Duration, why: &'a str },
+    Done { success: bool, attempts: u32 },
+}
+
+/// The title text for `state`, prefixed with the child's name.
+pub fn render(name: &str, state: &State) -> String {
+    match state {
+        State::Running { attempt } => format!("{name}: attempt {attempt} running"),
+        State::Waiting { delay, why } => {
+            format!("{name}: retry in {} ({why})", format_duration(*delay))
+        }
+        State::Done { success, attempts } => {
+            let plural = if *attempts == 1 { "" } else { "s" };
+            if *success {
+                format!("{name}: done \u{2713} ({attempts} attempt{plural})")
+            } else {
+                format!("{name}: failed \u{2717} ({attempts} attempt{plural})")
+            }
+        }
+    }
+}
+
+/// Strip control characters so a strange command name can't end the sequence early.
+fn sanitize(title: &str) -> String {
+    title.chars().filter(|c| !c.is_control()).collect()
+}
+
+/// The child's name as the title shows it: the command's file stem, sanitized.
+fn display_name(cmd: &str) -> String {
+    let name = std::path::Path::new(cmd)
+        .file_stem()
+        .map_or_else(|| cmd.to_string(), |s| s.to_string_lossy().into_owned());
+    sanitize(&name)
+}
+
+/// Owner of the terminal title for the run; restores the original on drop.
+pub struct Title {
+    name: String,
+    enabled: bool,
+    _restore: Option<Registration>,
+}
+
+impl Title {
+    /// Titles are only written when asked for, stderr is a terminal, and neither `NO_COLOR`
+    /// nor `TERM=dumb` opts out of escape sequences.
+    pub fn new(requested: bool, cmd: &str) -> Self {
+        let opted_out = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty())
+            || std::env::var("TERM").is_ok_and(|t| t == "dumb");
+        let enabled = requested && !opted_out && atty::is(atty::Stream::Stderr);
+        let restore = enabled.then(|| {
+            let _ = write!(io::stderr(), "{PUSH_TITLE}");
+            shutdown::register("terminal title", Priority::Last, || {
+                let mut err = io::stderr();
+                let _ = write!(err, "{POP_TITLE}");
+                let _ = err.flush();
+            })
+        });
+        Title {
+            name: display_name(cmd),
+            enabled,
+            _restore: restore,
+        }
+    }
+
+    pub fn set(&self, state: State) {
+        if self.enabled {
+            let text = sanitize(&render(&self.name, &state));
+            let mut err = io::stderr();
+            let _ = write!(err, "\x1b]2;{text}\x07");
+            let _ = err.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn state_strings() {
+        for (state, want) in [
+            (State::Running { attempt: 2 }, "claude: attempt 2 running"),
+            (
+                State::Waiting {
+                    delay: Duration::from_millis(1_500),
+                    why: "overloaded",
+                },
+                "claude: retry in 1.5s (overloaded)",
+            ),
+            (
+                State::Done {
+                    success: true,
+                    attempts: 1,
+                },
+                "claude: done \u{2713} (1 attempt)",
+            ),
+            (
+                State::Done {
+                    success: false,
+                    attempts: 3,
+                },
+                "claude: failed \u{2717} (3 attempts)",
+            ),
+        ] {
+            assert_eq!(render("claude", &state), want);
+        }
+    }
+
+    #[test]
+    fn control_characters_are_stripped_from_the_name() {
+        assert_eq!(display_name("/usr/local/bin/claude"), "claude");
+        assert_eq!(
+            display_name(r"C:\tools\claude.cmd"),
+            if cfg!(windows) {
+                "claude"
+            } else {
+                r"C:\tools\claude"
+            }
+        );
+        // BEL would end the title sequence early and ESC start another
+        assert_eq!(
+            display_name("/tmp/evil\x07\x1b]2;pwned\x1b\\"),
+            "evil]2;pwned\\"
+        );
+        assert_eq!(display_name("two\nlines\r\tname"), "twolinesname");
+        assert_eq!(sanitize("caf\u{e9} \u{9b}x"), "caf\u{e9} x");
+    }
+}
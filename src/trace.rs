@@ -0,0 +1,182 @@
+//! `--decision-trace PATH`: one JSON line per decision the retry loop makes about a
+//! finished attempt, holding every input that decision consumed. Events say what happened;
+//! the trace says why, for post-mortems of runs that behaved oddly.
+//!
+//! Records carry a `schema` version ([`schema::TRACE`]), bumped whenever a field changes
+//! meaning or goes away, so tooling written against one layout can refuse another.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+use serde_json::{json, Value};
+
+use crate::classes::ErrorClass;
+use crate::schema;
+
+/// What the loop decided to do after an attempt.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Success,
+    Retry,
+    /// A fatal pattern matched.
+    Fatal,
+    /// Neither a pattern nor `--retry-on-any-error` made the failure retryable, or a guard
+    /// vetoed the retry.
+    NotRetryable,
+    /// `--max-retries` ran out.
+    Exhausted,
+    /// The matched class ran out of `--class-budget` retries.
+    ClassBudget,
+    /// `--max-total-output` was exceeded.
+    OutputLimit,
+    /// The wait would outlast `--max-total-ms`.
+    TimeBudget,
+    /// A `drain` control command stopped the retry.
+    Drained,
+    /// An `abort` control command stopped the run.
+    Aborted,
+    /// SIGINT or SIGTERM stopped the run.
+    Interrupted,
+}
+
+impl Action {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Action::Success => "success",
+            Action::Retry => "retry",
+            Action::Fatal => "fatal",
+            Action::NotRetryable => "not-retryable",
+            Action::Exhausted => "exhausted",
+            Action::ClassBudget => "class-budget",
+            Action::OutputLimit => "output-limit",
+            Action::TimeBudget => "time-budget",
+            Action::Drained => "drained",
+            Action::Aborted => "aborted",
+            Action::Interrupted => "interrupted",
+        }
+    }
+}
+
+/// A pattern that matched, and where it was configured (`built-in`, `--patterns`, or the
+/// variable that supplied it).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Match {
+    pub pattern: String,
+    pub source: String,
+}
+
+/// The inputs of one decision, and the decision itself.
+#[derive(Clone, Debug, Default)]
+pub struct Decision {
+    /// 1-based.
+    pub attempt: u32,
+    pub code: Option<i32>,
+    pub runtime_ms: u64,
+    pub output_bytes: u64,
+    pub action: Option<Action>,
+    /// `--max-retries` minus the retries already made.
+    pub retries_left: u32,
+    /// Left of `--max-total-ms`, when it is set.
+    pub budget_left_ms: Option<u64>,
+    /// Forwarded across all attempts so far, against `--max-total-output`.
+    pub total_output_bytes: u64,
+    pub retry_on_any_error: bool,
+    pub class: Option<ErrorClass>,
+    pub matched: Option<Match>,
+    pub fatal: Option<Match>,
+    /// Heuristics that fired, e.g. `guard:runtime`, `attempt-timeout`, `scan-timeout`.
+    pub heuristics: Vec<String>,
+    pub retry_after_ms: Option<u64>,
+    /// The wait before the next attempt, when there is one.
+    pub wait_ms: Option<u64>,
+}
+
+impl Decision {
+    pub fn to_json(&self, run_id: &str) -> Value {
+        let matched = |m: &Option<Match>| {
+            m.as_ref()
+                .map(|m| json!({ "pattern": m.pattern, "source": m.source }))
+        };
+        json!({
+            "schema": schema::TRACE,
+            "run_id": run_id,
+            "attempt": self.attempt,
+            "action": self.action.map(Action::as_str),
+            "code": self.code,
+            "runtime_ms": self.runtime_ms,
+            "output_bytes": self.output_bytes,
+            "retries_left": self.retries_left,
+            "budget_left_ms": self.budget_left_ms,
+            "total_output_bytes": self.total_output_bytes,
+            "retry_on_any_error": self.retry_on_any_error,
+            "class": self.class.map(ErrorClass::as_str),
+            "matched": matched(&self.matched),
+            "fatal": matched(&self.fatal),
+            "heuristics": self.heuristics,
+            "retry_after_ms": self.retry_after_ms,
+            "wait_ms": self.wait_ms,
+        })
+    }
+}
+
+/// The open trace file; records are appended one line per write.
+pub struct Trace {
+    file: File,
+    run_id: String,
+}
+
+impl Trace {
+    pub fn open(path: &Path, run_id: &str) -> io::Result<Self> {
+        Ok(Trace {
+            file: OpenOptions::new().create(true).append(true).open(path)?,
+            run_id: run_id.to_string(),
+        })
+    }
+
+    pub fn record(&mut self, decision: &Decision) -> io::Result<()> {
+        let mut line = decision.to_json(&self.run_id).to_string();
+        line.push('\n');
+        self.file.write_all(line.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schema_one_fields() {
+        // Pins the field names, order, and types of schema 1
+        let decision = Decision {
+            attempt: 2,
+            code: Some(1),
+            runtime_ms: 1500,
+            output_bytes: 42,
+            action: Some(Action::TimeBudget),
+            retries_left: 1,
+            budget_left_ms: Some(900),
+            total_output_bytes: 84,
+            retry_on_any_error: true,
+            class: Some(ErrorClass::RateLimit),
+            matched: Some(Match {
+                pattern: "busy".to_string(),
+                source: "--patterns".to_string(),
+            }),
+            fatal: None,
+            heuristics: vec!["guard:runtime".to_string()],
+            retry_after_ms: Some(2000),
+            wait_ms: None,
+        };
+        assert_eq!(
+            decision.to_json("run").to_string(),
+            concat!(
+                r#"{"schema":"rusty-claude/trace/1","run_id":"run","attempt":2,"action":"time-budget","code":1,"#,
+                r#""runtime_ms":1500,"output_bytes":42,"retries_left":1,"budget_left_ms":900,"#,
+                r#""total_output_bytes":84,"retry_on_any_error":true,"class":"ratelimit","#,
+                r#""matched":{"pattern":"busy","source":"--patterns"},"fatal":null,"#,
+                r#""heuristics":["guard:runtime"],"retry_after_ms":2000,"wait_ms":null}"#,
+            )
+        );
+    }
+}
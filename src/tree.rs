@@ -0,0 +1,177 @@
+//! The child's process tree. The claude CLI starts helper node processes and MCP servers,
+//! which outlive a child that is killed or gives up and keep holding ports and lockfiles.
+//! So a non-interactive child is [`spawn`]ed into a tree of its own, a process group on Unix
+//! and a job object on Windows, and [`kill_tree`] kills the lot instead of the direct child
+//! only. A tree still alive when the wrapper ends abnormally (a panic, SIGHUP) is killed by
+//! [`shutdown`](crate::shutdown).
+//!
+//! `--no-process-group` leaves the child in ours, for where a new group breaks terminal job
+//! control; then only the direct child is ever killed.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::process::{Child, Command};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use crate::shutdown::{self, Priority, Registration};
+
+static DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// A spawned child's tree, by the child's pid.
+struct Tree {
+    #[cfg(windows)]
+    job: windows::Job,
+    _kill_on_exit: Registration,
+}
+
+static TREES: Mutex<BTreeMap<u32, Tree>> = Mutex::new(BTreeMap::new());
+
+fn trees() -> MutexGuard<'static, BTreeMap<u32, Tree>> {
+    TREES.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// `--no-process-group`, for the rest of the process.
+pub fn disable() {
+    DISABLED.store(true, Ordering::Relaxed);
+}
+
+/// Spawn `cmd`, in a tree of its own when `isolate` and not `--no-process-group`.
+pub fn spawn(cmd: &mut Command, isolate: bool) -> io::Result<Child> {
+    let isolate = isolate && !DISABLED.load(Ordering::Relaxed);
+    #[cfg(unix)]
+    if isolate {
+        std::os::unix::process::CommandExt::process_group(cmd, 0);
+    }
+    let child = cmd.spawn()?;
+    if isolate {
+        let pid = child.id();
+        #[cfg(windows)]
+        let Some(job) = windows::Job::adopt(&child) else {
+            return Ok(child);
+        };
+        let kill_on_exit = shutdown::register("child process tree", Priority::Normal, move || {
+            if let Some(tree) = trees().get(&pid) {
+                tree.kill(pid);
+            }
+        });
+        trees().insert(
+            pid,
+            Tree {
+                #[cfg(windows)]
+                job,
+                _kill_on_exit: kill_on_exit,
+            },
+        );
+    }
+    Ok(child)
+}
+
+impl Tree {
+    #[cfg_attr(windows, allow(unused_variables))]
+    fn kill(&self, pid: u32) {
+        #[cfg(unix)]
+        // SAFETY: signalling the process group made for our own child.
+        unsafe {
+            libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
+        }
+        #[cfg(windows)]
+        self.job.terminate();
+    }
+}
+
+/// Kill `child` and, if it has a tree of its own, everything in it. After the child has been
+/// waited for, this kills what it left behind.
+pub fn kill_tree(child: &mut Child) -> io::Result<()> {
+    let pid = child.id();
+    if let Some(tree) = trees().get(&pid) {
+        tree.kill(pid);
+    }
+    child.kill()
+}
+
+/// Send `sig` to the tree of the child `pid`, or to the child alone when it has none.
+#[cfg(unix)]
+pub fn signal(pid: u32, sig: libc::c_int) {
+    let target = if trees().contains_key(&pid) {
+        -(pid as libc::pid_t)
+    } else {
+        pid as libc::pid_t
+    };
+    // SAFETY: signalling our own child, or the process group made for it.
+    unsafe {
+        libc::kill(target, sig);
+    }
+}
+
+/// Kill the tree of the child `pid`, returning whether it had one.
+#[cfg(windows)]
+pub fn terminate(pid: u32) -> bool {
+    match trees().get(&pid) {
+        Some(tree) => {
+            tree.kill(pid);
+            true
+        }
+        None => false,
+    }
+}
+
+/// `child` has been waited for, and whatever is left of its tree may stay.
+pub fn release(child: &Child) {
+    // Out of the map first, so dropping the registration finds nothing to kill
+    let tree = trees().remove(&child.id());
+    drop(tree);
+}
+
+#[cfg(windows)]
+mod windows {
+    use std::ffi::c_void;
+    use std::os::windows::io::AsRawHandle;
+    use std::process::Child;
+
+    extern "system" {
+        fn CreateJobObjectW(attributes: *mut c_void, name: *const u16) -> *mut c_void;
+        fn AssignProcessToJobObject(job: *mut c_void, process: *mut c_void) -> i32;
+        fn TerminateJobObject(job: *mut c_void, code: u32) -> i32;
+        fn CloseHandle(handle: *mut c_void) -> i32;
+    }
+
+    /// A job object holding one child and whatever it starts.
+    pub struct Job(*mut c_void);
+
+    // SAFETY: a job handle may be used and closed from any thread.
+    unsafe impl Send for Job {}
+
+    impl Job {
+        /// `None` when no job can be made or the child can't join one (it may already be in
+        /// a job that forbids it).
+        pub fn adopt(child: &Child) -> Option<Job> {
+            // SAFETY: an anonymous job with default security; the handle is checked.
+            let job = unsafe { CreateJobObjectW(std::ptr::null_mut(), std::ptr::null()) };
+            if job.is_null() {
+                return None;
+            }
+            let job = Job(job);
+            // SAFETY: both handles are live for the call.
+            let assigned = unsafe { AssignProcessToJobObject(job.0, child.as_raw_handle()) };
+            (assigned != 0).then_some(job)
+        }
+
+        pub fn terminate(&self) {
+            // SAFETY: the handle is live until dropped.
+            unsafe {
+                TerminateJobObject(self.0, 1);
+            }
+        }
+    }
+
+    impl Drop for Job {
+        fn drop(&mut self) {
+            // Closing the handle leaves the processes running
+            // SAFETY: the handle is closed once, here.
+            unsafe {
+                CloseHandle(self.0);
+            }
+        }
+    }
+}
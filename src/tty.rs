@@ -0,0 +1,66 @@
+//! The controlling terminal, for features that talk to the user while stdin and stdout are
+//! pipes (`--edit-on-retry`).
+//!
+//! rusty-claude often runs under setsid, in containers, or from systemd, where `/dev/tty`
+//! (`CONIN$`/`CONOUT$` on Windows) doesn't open. It is opened once and cached; without it
+//! each feature applies its documented `Fallback` instead of hanging on a read. `--no-tty`
+//! forces that path even when a terminal exists.
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+use clap::ValueEnum;
+
+#[cfg(not(windows))]
+const DEVICES: (&str, &str) = ("/dev/tty", "/dev/tty");
+#[cfg(windows)]
+const DEVICES: (&str, &str) = ("CONIN$", "CONOUT$");
+
+/// What a feature does when it needs the terminal and there is none.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Fallback {
+    /// Carry on as if the feature were off for this step
+    Skip,
+    /// Refuse to run
+    Abort,
+}
+
+pub struct Terminal {
+    input: File,
+    output: File,
+}
+
+impl Terminal {
+    /// Fresh handles for a subprocess (such as an editor) to use as its stdio.
+    pub fn handles(&self) -> io::Result<(File, File)> {
+        Ok((self.input.try_clone()?, self.output.try_clone()?))
+    }
+}
+
+static FORCED_ABSENT: AtomicBool = AtomicBool::new(false);
+static TERMINAL: OnceLock<Result<Terminal, String>> = OnceLock::new();
+
+/// `--no-tty`: behave as if there were no controlling terminal.
+pub fn force_absent() {
+    FORCED_ABSENT.store(true, Ordering::SeqCst);
+}
+
+fn open() -> io::Result<Terminal> {
+    Ok(Terminal {
+        input: OpenOptions::new().read(true).open(DEVICES.0)?,
+        output: OpenOptions::new().write(true).open(DEVICES.1)?,
+    })
+}
+
+/// The controlling terminal, or why there is none.
+pub fn terminal() -> Result<&'static Terminal, &'static str> {
+    if FORCED_ABSENT.load(Ordering::SeqCst) {
+        return Err("no controlling terminal (--no-tty)");
+    }
+    TERMINAL
+        .get_or_init(|| open().map_err(|e| format!("no controlling terminal: {e}")))
+        .as_ref()
+        .map_err(String::as_str)
+}
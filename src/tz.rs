@@ -0,0 +1,562 @@
+//! Time zones for the reset times usage limits name ("resets at 7pm (America/Los_Angeles)"):
+//!
+//! - IANA names, read from the system's zoneinfo (`$TZDIR`, else /usr/share/zoneinfo)
+//! - common abbreviations (`PST`, `CEST`, `JST`; `PT` and `ET` mean the zone that observes
+//!   daylight saving)
+//! - fixed offsets (`UTC`, `UTC+2`, `GMT-03:30`, `+05:30`)
+//! - POSIX `TZ` strings (`EST5EDT,M3.2.0,M11.1.0`)
+//!
+//! A phrase without a zone means the account's zone, which the host can't know:
+//! `--assume-tz` names it, and otherwise the host's own zone (`$TZ`, then /etc/localtime)
+//! stands in. When neither is known such a phrase is not used.
+
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+const DAY: i64 = 86_400;
+
+/// Days from 1970-01-01 to the given civil date (proleptic Gregorian).
+pub fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// The civil date (year, month, day) `days` after 1970-01-01.
+pub fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+fn is_leap(year: i64) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+/// The day a POSIX rule names within a year.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RuleDay {
+    /// `Mm.w.d`: weekday `d` (0 = Sunday) of week `w` (5 = last) of month `m`.
+    Month { month: i64, week: i64, weekday: i64 },
+    /// `Jn`: day 1-365, never counting February 29.
+    Julian(i64),
+    /// `n`: day 0-365, counting February 29.
+    Ordinal(i64),
+}
+
+impl RuleDay {
+    /// Days since the epoch of this day in `year`.
+    fn in_year(self, year: i64) -> i64 {
+        let jan1 = days_from_civil(year, 1, 1);
+        match self {
+            RuleDay::Month {
+                month,
+                week,
+                weekday,
+            } => {
+                let first = days_from_civil(year, month, 1);
+                let next = if month == 12 {
+                    days_from_civil(year + 1, 1, 1)
+                } else {
+                    days_from_civil(year, month + 1, 1)
+                };
+                // 1970-01-01 was a Thursday
+                let first_weekday = (first + 4).rem_euclid(7);
+                let mut day = first + (weekday - first_weekday).rem_euclid(7) + (week - 1) * 7;
+                while day >= next {
+                    day -= 7;
+                }
+                day
+            }
+            RuleDay::Julian(n) => jan1 + n - 1 + i64::from(is_leap(year) && n >= 60),
+            RuleDay::Ordinal(n) => jan1 + n,
+        }
+    }
+}
+
+/// Daylight saving time under a POSIX rule.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Dst {
+    offset: i32,
+    /// When it starts, as a day and the local (standard) time of day in seconds.
+    start: (RuleDay, i64),
+    /// When it ends, as a day and the local (daylight) time of day in seconds.
+    end: (RuleDay, i64),
+}
+
+/// A POSIX `TZ` rule: a standard offset and, optionally, yearly daylight saving.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Rule {
+    std: i32,
+    dst: Option<Dst>,
+}
+
+impl Rule {
+    fn offset_at(&self, t: i64) -> i32 {
+        let Some(dst) = self.dst else {
+            return self.std;
+        };
+        let (year, _, _) = civil_from_days((t + i64::from(self.std)).div_euclid(DAY));
+        let start = dst.start.0.in_year(year) * DAY + dst.start.1 - i64::from(self.std);
+        let end = dst.end.0.in_year(year) * DAY + dst.end.1 - i64::from(dst.offset);
+        let in_dst = if start < end {
+            (start..end).contains(&t)
+        } else {
+            // Southern hemisphere: daylight saving spans the new year
+            t >= start || t < end
+        };
+        if in_dst {
+            dst.offset
+        } else {
+            self.std
+        }
+    }
+}
+
+/// Parse a POSIX zone name (`PST`, `<+0530>`), returning the rest.
+fn posix_name(s: &str) -> Option<&str> {
+    if let Some(rest) = s.strip_prefix('<') {
+        let end = rest.find('>')?;
+        return Some(&rest[end + 1..]);
+    }
+    let len = s
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(s.len());
+    (len >= 3).then(|| &s[len..])
+}
+
+/// Parse `[+-]hh[:mm[:ss]]` as seconds, returning the rest.
+fn posix_time(s: &str) -> Option<(i64, &str)> {
+    let (sign, s) = match s.as_bytes().first()? {
+        b'-' => (-1, &s[1..]),
+        b'+' => (1, &s[1..]),
+        _ => (1, s),
+    };
+    let len = s
+        .find(|c: char| !c.is_ascii_digit() && c != ':')
+        .unwrap_or(s.len());
+    let mut secs = 0;
+    let mut parts = 0;
+    for (i, part) in s[..len].split(':').enumerate() {
+        let n: i64 = part.parse().ok()?;
+        let limit = if i == 0 { 167 } else { 59 };
+        if i > 2 || n > limit || part.is_empty() {
+            return None;
+        }
+        secs += n * [3600, 60, 1][i];
+        parts += 1;
+    }
+    (parts > 0).then_some((sign * secs, &s[len..]))
+}
+
+/// Parse a rule date with its optional `/time`, returning the rest.
+fn posix_day(s: &str) -> Option<((RuleDay, i64), &str)> {
+    let len = s.find([',', '/']).unwrap_or(s.len());
+    let (spec, mut rest) = s.split_at(len);
+    let day = if let Some(m) = spec.strip_prefix('M') {
+        let mut fields = m.split('.').map(|f| f.parse::<i64>().ok());
+        let (month, week, weekday) = (fields.next()??, fields.next()??, fields.next()??);
+        if fields.next().is_some()
+            || !(1..=12).contains(&month)
+            || !(1..=5).contains(&week)
+            || !(0..=6).contains(&weekday)
+        {
+            return None;
+        }
+        RuleDay::Month {
+            month,
+            week,
+            weekday,
+        }
+    } else if let Some(n) = spec.strip_prefix('J') {
+        RuleDay::Julian(n.parse().ok().filter(|n| (1..=365).contains(n))?)
+    } else {
+        RuleDay::Ordinal(spec.parse().ok().filter(|n| (0..=365).contains(n))?)
+    };
+    let mut time = 2 * 3600;
+    if let Some(t) = rest.strip_prefix('/') {
+        (time, rest) = posix_time(t)?;
+    }
+    Some(((day, time), rest))
+}
+
+/// Parse a POSIX `TZ` string such as `CET-1CEST,M3.5.0,M10.5.0/3`.
+fn parse_posix(s: &str) -> Option<Rule> {
+    let rest = posix_name(s)?;
+    let (west, rest) = posix_time(rest)?;
+    let std = i32::try_from(-west).ok()?;
+    if rest.is_empty() {
+        return Some(Rule { std, dst: None });
+    }
+    let rest = posix_name(rest)?;
+    let (offset, rest) = match rest.as_bytes().first() {
+        Some(b'+' | b'-' | b'0'..=b'9') => {
+            let (west, rest) = posix_time(rest)?;
+            (i32::try_from(-west).ok()?, rest)
+        }
+        _ => (std + 3600, rest),
+    };
+    // Without dates, the US rules (as most implementations assume)
+    let rest = if rest.is_empty() {
+        ",M3.2.0,M11.1.0"
+    } else {
+        rest
+    };
+    let (start, rest) = posix_day(rest.strip_prefix(',')?)?;
+    let (end, rest) = posix_day(rest.strip_prefix(',')?)?;
+    rest.is_empty().then_some(Rule {
+        std,
+        dst: Some(Dst { offset, start, end }),
+    })
+}
+
+/// UTC offsets over time.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Zone {
+    /// (unix seconds, offset in effect from then), ascending.
+    transitions: Vec<(i64, i32)>,
+    /// The offset before the first transition.
+    initial: i32,
+    /// Offsets after the last transition (or always, without transitions).
+    rule: Option<Rule>,
+}
+
+impl Zone {
+    fn fixed(offset: i32) -> Self {
+        Zone {
+            transitions: Vec::new(),
+            initial: offset,
+            rule: None,
+        }
+    }
+
+    /// The UTC offset in seconds at unix time `t`.
+    pub fn offset_at(&self, t: i64) -> i32 {
+        match self.transitions.iter().rposition(|&(at, _)| at <= t) {
+            Some(i) if i + 1 == self.transitions.len() && self.rule.is_some() => {
+                self.rule.map_or(self.transitions[i].1, |r| r.offset_at(t))
+            }
+            Some(i) => self.transitions[i].1,
+            None if self.transitions.is_empty() => {
+                self.rule.map_or(self.initial, |r| r.offset_at(t))
+            }
+            None => self.initial,
+        }
+    }
+
+    /// The unix time at which this zone's clocks read `local` (seconds since the epoch as
+    /// if local time were UTC). A time that occurs twice when clocks go back resolves to the
+    /// later instant, and one skipped when they go forward to the later of its two readings
+    /// (2:30 to 3:30), so a wait computed from it is never too short.
+    pub fn instant_of(&self, local: i64) -> i64 {
+        // Offsets change at most once within a day or so of any time
+        let offsets = [
+            self.offset_at(local - DAY),
+            self.offset_at(local),
+            self.offset_at(local + DAY),
+        ];
+        let candidates = offsets.map(|o| local - i64::from(o));
+        let valid = candidates
+            .iter()
+            .zip(offsets)
+            .filter(|&(&t, o)| self.offset_at(t) == o)
+            .map(|(&t, _)| t)
+            .max();
+        valid.unwrap_or_else(|| candidates.into_iter().max().unwrap_or(local))
+    }
+}
+
+/// Fixed offsets of common abbreviations, in hours east of UTC.
+const ABBREVIATIONS: &[(&str, f64)] = &[
+    ("UTC", 0.0),
+    ("GMT", 0.0),
+    ("UT", 0.0),
+    ("Z", 0.0),
+    ("WET", 0.0),
+    ("WEST", 1.0),
+    ("BST", 1.0),
+    ("CET", 1.0),
+    ("CEST", 2.0),
+    ("EET", 2.0),
+    ("EEST", 3.0),
+    ("MSK", 3.0),
+    ("IST", 5.5),
+    ("SGT", 8.0),
+    ("HKT", 8.0),
+    ("AWST", 8.0),
+    ("JST", 9.0),
+    ("KST", 9.0),
+    ("ACST", 9.5),
+    ("ACDT", 10.5),
+    ("AEST", 10.0),
+    ("AEDT", 11.0),
+    ("NZST", 12.0),
+    ("NZDT", 13.0),
+    ("HST", -10.0),
+    ("AKST", -9.0),
+    ("AKDT", -8.0),
+    ("PST", -8.0),
+    ("PDT", -7.0),
+    ("MST", -7.0),
+    ("MDT", -6.0),
+    ("CST", -6.0),
+    ("CDT", -5.0),
+    ("EST", -5.0),
+    ("EDT", -4.0),
+    ("AST", -4.0),
+    ("ADT", -3.0),
+    ("NST", -3.5),
+    ("NDT", -2.5),
+];
+
+/// Generic abbreviations for zones that switch between two of the ones above.
+const GENERIC: &[(&str, &str)] = &[
+    ("PT", "PST8PDT,M3.2.0,M11.1.0"),
+    ("MT", "MST7MDT,M3.2.0,M11.1.0"),
+    ("CT", "CST6CDT,M3.2.0,M11.1.0"),
+    ("ET", "EST5EDT,M3.2.0,M11.1.0"),
+];
+
+/// `+05:30`, `-3`, `+0200` as seconds east of UTC.
+fn parse_offset(s: &str) -> Option<i32> {
+    let (sign, digits) = match s.as_bytes().first()? {
+        b'+' => (1, &s[1..]),
+        b'-' => (-1, &s[1..]),
+        _ => return None,
+    };
+    let (h, m) = match digits.split_once(':') {
+        Some((h, m)) => (h, m),
+        None if digits.len() == 4 => digits.split_at(2),
+        None => (digits, "0"),
+    };
+    let (h, m): (i32, i32) = (h.parse().ok()?, m.parse().ok()?);
+    (h <= 14 && m < 60 && !digits.is_empty()).then_some(sign * (h * 3600 + m * 60))
+}
+
+/// Where IANA zone files live.
+fn zoneinfo_dirs() -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = std::env::var_os("TZDIR")
+        .map(PathBuf::from)
+        .into_iter()
+        .collect();
+    dirs.extend(
+        [
+            "/usr/share/zoneinfo",
+            "/usr/lib/zoneinfo",
+            "/usr/share/lib/zoneinfo",
+        ]
+        .iter()
+        .map(PathBuf::from),
+    );
+    dirs
+}
+
+fn be_i32(b: &[u8]) -> i32 {
+    i32::from_be_bytes([b[0], b[1], b[2], b[3]])
+}
+
+/// Parse a TZif file (RFC 8536), preferring its 64-bit data and using its footer rule past
+/// the last transition.
+fn parse_tzif(data: &[u8]) -> Option<Zone> {
+    let header = |at: usize| -> Option<[usize; 6]> {
+        let h = data.get(at..at + 44)?;
+        if &h[..4] != b"TZif" {
+            return None;
+        }
+        let mut counts = [0; 6];
+        for (i, c) in counts.iter_mut().enumerate() {
+            *c = usize::try_from(be_i32(&h[20 + i * 4..])).ok()?;
+        }
+        Some(counts)
+    };
+    let block_len = |c: [usize; 6], time: usize| {
+        let [isut, isstd, leap, times, types, chars] = c;
+        times * time + times + types * 6 + chars + leap * (time + 4) + isstd + isut
+    };
+    let v1 = header(0)?;
+    let (start, counts, time) = if data[4] >= b'2' {
+        let at = 44 + block_len(v1, 4);
+        (at + 44, header(at)?, 8)
+    } else {
+        (44, v1, 4)
+    };
+    let [_, _, _, times, types, _] = counts;
+    let body = data.get(start..start + block_len(counts, time))?;
+    let (stamps, rest) = body.split_at(times * time);
+    let (indices, rest) = rest.split_at(times);
+    let offsets: Vec<i32> = rest[..types * 6].chunks(6).map(be_i32).collect();
+    let transitions = stamps
+        .chunks(time)
+        .zip(indices)
+        .map(|(t, &i)| {
+            let at = if time == 8 {
+                i64::from_be_bytes(t.try_into().ok()?)
+            } else {
+                i64::from(be_i32(t))
+            };
+            Some((at, *offsets.get(usize::from(i))?))
+        })
+        .collect::<Option<Vec<_>>>()?;
+    let footer = data
+        .get(start + block_len(counts, time)..)
+        .unwrap_or_default();
+    let rule = std::str::from_utf8(footer)
+        .ok()
+        .and_then(|f| f.trim_matches('\n').lines().next())
+        .filter(|f| !f.is_empty())
+        .and_then(parse_posix);
+    Some(Zone {
+        transitions,
+        initial: *offsets.first()?,
+        rule,
+    })
+}
+
+fn read_tzif(path: &Path) -> Option<Zone> {
+    parse_tzif(&std::fs::read(path).ok()?)
+}
+
+/// Look up a zone by IANA name, abbreviation, offset, or POSIX `TZ` string.
+pub fn zone(name: &str) -> Option<Zone> {
+    let name = name.trim();
+    if let Some(&(_, hours)) = ABBREVIATIONS
+        .iter()
+        .find(|(a, _)| a.eq_ignore_ascii_case(name))
+    {
+        return Some(Zone::fixed((hours * 3600.0) as i32));
+    }
+    if let Some(&(_, rule)) = GENERIC.iter().find(|(a, _)| a.eq_ignore_ascii_case(name)) {
+        return parse_posix(rule).map(|rule| Zone {
+            rule: Some(rule),
+            ..Zone::fixed(rule.std)
+        });
+    }
+    let upper = name.to_ascii_uppercase();
+    if let Some(offset) = ["UTC", "GMT", ""]
+        .iter()
+        .find_map(|p| upper.strip_prefix(p).and_then(parse_offset))
+    {
+        return Some(Zone::fixed(offset));
+    }
+    let iana = !name.is_empty()
+        && !name
+            .split('/')
+            .any(|part| part.is_empty() || part == "." || part == "..")
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_+-/".contains(c));
+    if iana {
+        if let Some(zone) = zoneinfo_dirs()
+            .iter()
+            .find_map(|dir| read_tzif(&dir.join(name)))
+        {
+            return Some(zone);
+        }
+    }
+    parse_posix(name).map(|rule| Zone {
+        rule: Some(rule),
+        ..Zone::fixed(rule.std)
+    })
+}
+
+/// The host's zone: `$TZ` (a name, a POSIX string, or `:path`), then /etc/localtime.
+fn host() -> Option<Zone> {
+    match std::env::var("TZ") {
+        Ok(tz) if !tz.is_empty() => {
+            let tz = tz.strip_prefix(':').unwrap_or(&tz);
+            if Path::new(tz).is_absolute() {
+                read_tzif(Path::new(tz))
+            } else {
+                zone(tz)
+            }
+        }
+        _ => read_tzif(Path::new("/etc/localtime")),
+    }
+}
+
+static ASSUMED: OnceLock<Option<Zone>> = OnceLock::new();
+
+/// Use `zone` (`--assume-tz`) for reset times that name none.
+pub fn assume(zone: Zone) {
+    let _ = ASSUMED.set(Some(zone));
+}
+
+/// The zone of reset times that name none: `--assume-tz`, else the host's.
+pub fn assumed() -> Option<&'static Zone> {
+    ASSUMED.get_or_init(host).as_ref()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: i64 = 3_600;
+
+    /// Unix time of `hour`:`min` UTC on the given day.
+    fn at(year: i64, month: i64, day: i64, hour: i64, min: i64) -> i64 {
+        days_from_civil(year, month, day) * DAY + hour * HOUR + min * 60
+    }
+
+    #[test]
+    fn civil_dates_round_trip() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2025, 10, 21), 20_382);
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        for days in [-719_468, -1, 0, 59, 60, 11_016, 20_382, 2_932_896] {
+            let (y, m, d) = civil_from_days(days);
+            assert_eq!(days_from_civil(y, m, d), days, "{y}-{m}-{d}");
+        }
+    }
+
+    #[test]
+    fn names_offsets_and_abbreviations() {
+        let offset = |name| zone(name).map(|z| z.offset_at(0));
+        assert_eq!(offset("UTC"), Some(0));
+        assert_eq!(offset("utc+2"), Some(2 * 3_600));
+        assert_eq!(offset("GMT-03:30"), Some(-(3 * 3_600 + 1_800)));
+        assert_eq!(offset("+05:30"), Some(5 * 3_600 + 1_800));
+        assert_eq!(offset("CEST"), Some(2 * 3_600));
+        assert_eq!(offset("jst"), Some(9 * 3_600));
+        assert_eq!(offset("Mars/Olympus_Mons"), None);
+        assert_eq!(offset("../etc/passwd"), None);
+    }
+
+    #[test]
+    fn daylight_saving_rules() {
+        let ny = zone("EST5EDT,M3.2.0,M11.1.0").unwrap();
+        assert_eq!(ny.offset_at(at(2025, 1, 15, 12, 0)), -5 * 3_600);
+        assert_eq!(ny.offset_at(at(2025, 7, 15, 12, 0)), -4 * 3_600);
+        // `PT` observes daylight saving, `PST` doesn't
+        let pt = zone("PT").unwrap();
+        assert_eq!(pt.offset_at(at(2025, 7, 15, 12, 0)), -7 * 3_600);
+        assert_eq!(
+            zone("PST").unwrap().offset_at(at(2025, 7, 15, 12, 0)),
+            -8 * 3_600
+        );
+    }
+
+    #[test]
+    fn local_times_around_the_changes() {
+        let ny = zone("EST5EDT,M3.2.0,M11.1.0").unwrap();
+        // An ordinary time
+        assert_eq!(ny.instant_of(at(2025, 1, 15, 9, 0)), at(2025, 1, 15, 14, 0));
+        // 2:30 is skipped on 2025-03-09: read as 3:30 EDT
+        assert_eq!(ny.instant_of(at(2025, 3, 9, 2, 30)), at(2025, 3, 9, 7, 30));
+        // 1:30 happens twice on 2025-11-02: the later, EST one
+        assert_eq!(
+            ny.instant_of(at(2025, 11, 2, 1, 30)),
+            at(2025, 11, 2, 6, 30)
+        );
+    }
+}
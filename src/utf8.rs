@@ -0,0 +1,125 @@
+//! Child text embedded in JSON (`--json-events`, attempt metadata), such as the line a retry
+//! pattern matched.
+//!
+//! By default invalid UTF-8 in such an excerpt becomes U+FFFD, which some log pipelines
+//! reject. `--strict-utf8` validates the excerpt first and, when it isn't UTF-8, either ships
+//! the raw bytes base64-encoded in a `<key>_base64` field (`encode`) or replaces the text
+//! with a marker and lists the invalid byte ranges in `<key>_invalid` (`omit`). The forwarded
+//! output is never touched either way.
+
+use clap::ValueEnum;
+use serde_json::{Map, Value};
+
+use crate::observe::base64;
+use crate::shellquote::redact_text;
+
+/// What `--strict-utf8` does with an excerpt that is not valid UTF-8.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum StrictUtf8 {
+    /// Put the raw bytes, base64-encoded, in a separate field
+    Encode,
+    /// Replace the excerpt with a marker and the invalid byte ranges
+    Omit,
+}
+
+/// Longest excerpt embedded, in bytes.
+pub const EXCERPT_BYTES: usize = 512;
+
+/// Stands in for an excerpt dropped under `--strict-utf8=omit`.
+pub const OMITTED: &str = "[invalid UTF-8 omitted]";
+
+/// Line `n` counted from the end of stdout followed by stderr, the order the pattern scan
+/// sees them in.
+pub fn line_from_end<'a>(stdout: &'a [u8], stderr: &'a [u8], n: usize) -> Option<&'a [u8]> {
+    let err_lines = memchr::memchr_iter(b'\n', stderr).count() + 1;
+    if n < err_lines {
+        stderr.rsplit(|&b| b == b'\n').nth(n)
+    } else {
+        stdout.rsplit(|&b| b == b'\n').nth(n - err_lines)
+    }
+}
+
+/// A short, redacted excerpt of a child line for the wrapper's own messages.
+pub fn excerpt(raw: &[u8]) -> String {
+    redact_text(String::from_utf8_lossy(truncate(raw, 160)).trim())
+}
+
+/// At most `limit` bytes of `raw`. A character split by the cut is dropped whole, so the
+/// cut itself never makes an excerpt invalid.
+pub fn truncate(raw: &[u8], limit: usize) -> &[u8] {
+    if raw.len() <= limit {
+        return raw;
+    }
+    let cut = &raw[..limit];
+    let Some(last) = cut.utf8_chunks().last() else {
+        return cut;
+    };
+    if last.invalid().is_empty() {
+        return cut;
+    }
+    let tail = limit - last.invalid().len();
+    // A split character decodes once the bytes after the cut are put back
+    let rest = &raw[tail..raw.len().min(tail + 4)];
+    let split = match std::str::from_utf8(rest) {
+        Ok(_) => true,
+        Err(e) => e.valid_up_to() > 0,
+    };
+    if split {
+        &raw[..tail]
+    } else {
+        cut
+    }
+}
+
+/// Byte ranges of the invalid sequences in `raw`, adjacent ones merged.
+pub fn invalid_ranges(raw: &[u8]) -> Vec<(usize, usize)> {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    let mut offset = 0;
+    for chunk in raw.utf8_chunks() {
+        offset += chunk.valid().len();
+        let len = chunk.invalid().len();
+        if len > 0 {
+            match ranges.last_mut() {
+                Some(last) if last.1 == offset => last.1 += len,
+                _ => ranges.push((offset, offset + len)),
+            }
+        }
+        offset += len;
+    }
+    ranges
+}
+
+/// `raw` with secrets in its valid stretches redacted; invalid bytes are kept as they are.
+fn redact_bytes(raw: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(raw.len());
+    for chunk in raw.utf8_chunks() {
+        out.extend_from_slice(redact_text(chunk.valid()).as_bytes());
+        out.extend_from_slice(chunk.invalid());
+    }
+    out
+}
+
+/// Insert the excerpt of `raw` under `key` (redacted, at most `EXCERPT_BYTES`), following
+/// `strict` when it is not UTF-8.
+pub fn embed(fields: &mut Map<String, Value>, key: &str, raw: &[u8], strict: Option<StrictUtf8>) {
+    let raw = truncate(raw, EXCERPT_BYTES);
+    let text = match (std::str::from_utf8(raw), strict) {
+        (Ok(text), _) => redact_text(text),
+        (Err(_), None) => redact_text(&String::from_utf8_lossy(raw)),
+        (Err(_), Some(StrictUtf8::Encode)) => {
+            fields.insert(key.into(), Value::Null);
+            fields.insert(format!("{key}_base64"), base64(&redact_bytes(raw)).into());
+            return;
+        }
+        (Err(_), Some(StrictUtf8::Omit)) => {
+            let ranges = invalid_ranges(raw)
+                .into_iter()
+                .map(|(start, end)| serde_json::json!([start, end]))
+                .collect::<Vec<_>>();
+            fields.insert(key.into(), OMITTED.into());
+            fields.insert(format!("{key}_invalid"), ranges.into());
+            return;
+        }
+    };
+    fields.insert(key.into(), text.into());
+}
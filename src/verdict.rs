@@ -0,0 +1,321 @@
+//! Judging a finished attempt, live or from a transcript: whether it succeeded, and what
+//! rules out retrying it if not.
+
+use std::time::Duration;
+
+use regex::Regex;
+use rusty_claude::patterns::{find_fatal, should_retry, Output, Patterns, RetryDecision};
+
+use crate::duration::format_duration;
+use crate::piped::Killed;
+use crate::{control, json_errors, trace, Cli};
+
+/// Drop a server-requested wait longer than `--max-retry-after-ms`, leaving the wait to
+/// the backoff.
+pub fn cap_retry_after(decision: &mut RetryDecision, cli: &Cli) {
+    let cap = cli.max_retry_after_ms;
+    if let Some(ms) = decision.retry_after_ms.filter(|&ms| ms > cap) {
+        if decision.retry {
+            eprintln!(
+                "[rusty-claude] warning: ignoring a requested wait of {} (over \
+                --max-retry-after-ms {}); using the backoff",
+                format_duration(Duration::from_millis(ms)),
+                format_duration(Duration::from_millis(cap))
+            );
+        }
+        decision.retry_after_ms = None;
+    }
+}
+
+/// Verdict on a finished attempt.
+pub enum Verdict {
+    Success,
+    Failure(RetryDecision),
+}
+
+/// Whatever rules out retrying a failed attempt, for the decision trace.
+pub struct Stops {
+    pub fatal: bool,
+    /// SIGINT or SIGTERM arrived where it would have retried.
+    pub interrupted: bool,
+    /// A `drain` or `abort` control command arrived.
+    pub halted: Option<control::Command>,
+    pub retryable: bool,
+    /// No `--max-retries` left.
+    pub last_attempt: bool,
+    pub class_exhausted: bool,
+    pub over_output: bool,
+    /// The wait would outlast `--max-total-ms`.
+    pub over_budget: bool,
+}
+
+impl Stops {
+    /// The first that applies, in the order the retry loop checks them.
+    pub fn action(&self) -> trace::Action {
+        if self.fatal {
+            trace::Action::Fatal
+        } else if self.interrupted {
+            trace::Action::Interrupted
+        } else if let Some(cmd) = self.halted {
+            match cmd {
+                control::Command::Drain => trace::Action::Drained,
+                control::Command::Abort => trace::Action::Aborted,
+            }
+        } else if !self.retryable {
+            trace::Action::NotRetryable
+        } else if self.last_attempt {
+            trace::Action::Exhausted
+        } else if self.class_exhausted {
+            trace::Action::ClassBudget
+        } else if self.over_output {
+            trace::Action::OutputLimit
+        } else if self.over_budget {
+            trace::Action::TimeBudget
+        } else {
+            trace::Action::Retry
+        }
+    }
+}
+
+/// What a finished attempt left to judge it by, live or from a transcript.
+pub struct Recorded<'a> {
+    pub out: &'a [u8],
+    pub err: &'a [u8],
+    pub code: Option<i32>,
+    pub killed: Option<Killed>,
+}
+
+/// Judge a finished attempt the way the retry loop does. One we killed is retried
+/// whatever its partial output says, unless it was fatal or overflowed the buffer; its exit
+/// code is our kill's, so the exit-code lists don't apply. Otherwise `--json-errors` output
+/// decides when it reports an error, and `evaluate` when it doesn't.
+pub fn judge(
+    attempt: &Recorded,
+    success_pattern: Option<&Regex>,
+    cli: &Cli,
+    patterns: &Patterns,
+) -> Verdict {
+    let (out, err) = (
+        String::from_utf8_lossy(attempt.out),
+        String::from_utf8_lossy(attempt.err),
+    );
+    let output = Output::Streams {
+        stdout: &out,
+        stderr: &err,
+    };
+    if let Some(killed) = attempt.killed {
+        let decision = should_retry(
+            output,
+            attempt.code,
+            cli.retry_on_any_error,
+            patterns,
+            cli.match_timeout,
+        );
+        return Verdict::Failure(RetryDecision {
+            // A retry would only overflow the buffer again
+            retry: decision.fatal.is_none() && killed != Killed::BufferFull,
+            retry_code: false,
+            no_retry_code: false,
+            ..decision
+        });
+    }
+    let json = cli.json_errors.then(|| json_errors::inspect(&out));
+    match json {
+        Some(json_errors::Found::Error(error)) => {
+            Verdict::Failure(json_decision(error, attempt.code, patterns))
+        }
+        // A clean result is the model's own text, which the patterns must not sniff
+        Some(json_errors::Found::Clean) => evaluate(
+            Output::Streams {
+                stdout: "",
+                stderr: &err,
+            },
+            attempt.code,
+            success_pattern,
+            cli.retry_on_any_error,
+            cli.retry_on_success_match,
+            patterns,
+            cli.match_timeout,
+        ),
+        _ => evaluate(
+            output,
+            attempt.code,
+            success_pattern,
+            cli.retry_on_any_error,
+            cli.retry_on_success_match,
+            patterns,
+            cli.match_timeout,
+        ),
+    }
+}
+
+/// Judge a finished attempt, in precedence order: a fatal pattern match fails it; then
+/// with `--success-pattern`, a match alone decides success whatever the exit code; then
+/// under `--retry-on-success-match` a retry pattern fails a zero exit; then the exit code
+/// does. A plain zero exit is a success without reading the output at all, since a good
+/// answer can quote "401". A failure is then classified by `should_retry`.
+fn evaluate(
+    output: Output,
+    exit_code: Option<i32>,
+    success_pattern: Option<&Regex>,
+    retry_on_any: bool,
+    retry_on_success_match: bool,
+    patterns: &Patterns,
+    match_timeout: Option<Duration>,
+) -> Verdict {
+    if success_pattern.is_none() && exit_code == Some(0) && !retry_on_success_match {
+        return Verdict::Success;
+    }
+    if let Some(decision) = find_fatal(output, patterns, match_timeout) {
+        return Verdict::Failure(decision);
+    }
+    let success = match success_pattern {
+        Some(re) => match output {
+            Output::Streams { stdout, stderr } => re.is_match(stdout) || re.is_match(stderr),
+            Output::Merged(text) => re.is_match(text),
+        },
+        None => exit_code == Some(0),
+    };
+    if !success {
+        return Verdict::Failure(should_retry(
+            output,
+            exit_code,
+            retry_on_any,
+            patterns,
+            match_timeout,
+        ));
+    }
+    if success_pattern.is_none() {
+        // A zero exit under --retry-on-success-match: only the retry patterns can still
+        // fail it; the exit-code lists and --retry-on-any-error are about failed ones
+        let decision = should_retry(output, exit_code, false, patterns, match_timeout);
+        if decision.matched.is_some() {
+            return Verdict::Failure(RetryDecision {
+                retry_code: false,
+                no_retry_code: false,
+                ..decision
+            });
+        }
+    }
+    Verdict::Success
+}
+
+/// Classify an attempt by the error in its JSON output: retryable error types are retried
+/// unless `--no-retry-exit-codes` lists the code; the patterns don't take part.
+fn json_decision(
+    error: json_errors::JsonError,
+    code: Option<i32>,
+    patterns: &Patterns,
+) -> RetryDecision {
+    if patterns.no_retry_codes.contains(code) {
+        return RetryDecision {
+            no_retry_code: true,
+            json_error: Some(error.error_type),
+            ..RetryDecision::default()
+        };
+    }
+    let class = error.class();
+    RetryDecision {
+        retry: class.is_some(),
+        retry_after_ms: error.retry_after_ms.filter(|_| class.is_some()),
+        class,
+        json_error: Some(error.error_type),
+        ..RetryDecision::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What `evaluate` made of an attempt, for the table below.
+    #[derive(Debug, PartialEq)]
+    enum Judged {
+        Pass,
+        Fatal,
+        Retry,
+        Fail,
+    }
+
+    #[test]
+    fn fatal_beats_success_pattern_beats_retry_pattern_beats_exit_code() {
+        use Judged::*;
+        let patterns = Patterns::new(
+            vec![(Regex::new("RETRY").unwrap(), None)],
+            vec![Regex::new("FATAL").unwrap()],
+        )
+        .unwrap();
+        let success = Regex::new("DONE").unwrap();
+        // output, exit code, --success-pattern, --retry-on-success-match, verdict
+        for (output, code, success_pattern, retry_on_success_match, want) in [
+            ("answer\n", 0, false, false, Pass),
+            ("answer\n", 0, false, true, Pass),
+            ("answer\n", 0, true, false, Fail),
+            ("answer\n", 0, true, true, Fail),
+            ("answer\n", 1, false, false, Fail),
+            ("answer\n", 1, false, true, Fail),
+            ("answer\n", 1, true, false, Fail),
+            ("answer\n", 1, true, true, Fail),
+            ("DONE\n", 0, false, false, Pass),
+            ("DONE\n", 0, false, true, Pass),
+            ("DONE\n", 0, true, false, Pass),
+            ("DONE\n", 0, true, true, Pass),
+            ("DONE\n", 1, false, false, Fail),
+            ("DONE\n", 1, false, true, Fail),
+            ("DONE\n", 1, true, false, Pass),
+            ("DONE\n", 1, true, true, Pass),
+            ("RETRY\n", 0, false, false, Pass),
+            ("RETRY\n", 0, false, true, Retry),
+            ("RETRY\n", 0, true, false, Retry),
+            ("RETRY\n", 0, true, true, Retry),
+            ("RETRY\n", 1, false, false, Retry),
+            ("RETRY\n", 1, false, true, Retry),
+            ("RETRY\n", 1, true, false, Retry),
+            ("RETRY\n", 1, true, true, Retry),
+            ("FATAL\n", 0, false, false, Pass),
+            ("FATAL\n", 0, false, true, Fatal),
+            ("FATAL\n", 0, true, false, Fatal),
+            ("FATAL\n", 0, true, true, Fatal),
+            ("FATAL\n", 1, false, false, Fatal),
+            ("FATAL\n", 1, false, true, Fatal),
+            ("FATAL\n", 1, true, false, Fatal),
+            ("FATAL\n", 1, true, true, Fatal),
+            ("DONE\nRETRY\n", 0, false, false, Pass),
+            ("DONE\nRETRY\n", 0, false, true, Retry),
+            ("DONE\nRETRY\n", 0, true, false, Pass),
+            ("DONE\nRETRY\n", 0, true, true, Pass),
+            ("DONE\nRETRY\n", 1, false, false, Retry),
+            ("DONE\nRETRY\n", 1, false, true, Retry),
+            ("DONE\nRETRY\n", 1, true, false, Pass),
+            ("DONE\nRETRY\n", 1, true, true, Pass),
+            ("FATAL\nDONE\n", 0, false, false, Pass),
+            ("FATAL\nDONE\n", 0, false, true, Fatal),
+            ("FATAL\nDONE\n", 0, true, false, Fatal),
+            ("FATAL\nDONE\n", 0, true, true, Fatal),
+            ("FATAL\nDONE\n", 1, false, false, Fatal),
+            ("FATAL\nDONE\n", 1, false, true, Fatal),
+            ("FATAL\nDONE\n", 1, true, false, Fatal),
+            ("FATAL\nDONE\n", 1, true, true, Fatal),
+        ] {
+            let verdict = evaluate(
+                Output::Merged(output),
+                Some(code),
+                success_pattern.then_some(&success),
+                false,
+                retry_on_success_match,
+                &patterns,
+                None,
+            );
+            let judged = match verdict {
+                Verdict::Success => Pass,
+                Verdict::Failure(d) if d.fatal.is_some() => Fatal,
+                Verdict::Failure(d) if d.retry => Retry,
+                Verdict::Failure(_) => Fail,
+            };
+            assert_eq!(
+                judged, want,
+                "{output:?} exit {code} success_pattern={success_pattern} retry_on_success_match={retry_on_success_match}"
+            );
+        }
+    }
+}
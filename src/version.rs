@@ -0,0 +1,150 @@
+//! Detecting the wrapped CLI's version and comparing it with `--min-child-version`.
+
+use std::fmt;
+use std::io::Read;
+use std::process::Stdio;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long the `<cmd> --version` probe may take before it is abandoned.
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A `major.minor.patch` version; pre-release and build suffixes are ignored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl FromStr for Version {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let core = s.strip_prefix('v').unwrap_or(s);
+        let core = core.split(['-', '+']).next().unwrap_or(core);
+        let mut parts = core.split('.');
+        let mut next = |required: bool| -> Result<u64, String> {
+            match parts.next() {
+                Some(p) => p.parse().map_err(|_| format!("invalid version `{s}`")),
+                None if required => Err(format!("invalid version `{s}`")),
+                None => Ok(0),
+            }
+        };
+        let version = Version {
+            major: next(true)?,
+            minor: next(false)?,
+            patch: next(false)?,
+        };
+        if parts.next().is_some() {
+            return Err(format!("invalid version `{s}`"));
+        }
+        Ok(version)
+    }
+}
+
+/// Find the first `N.N[.N]` version in CLI output such as `1.0.44 (Claude Code)`,
+/// `claude-code/1.2.3 linux-x64`, or `v2.0.1`.
+pub fn parse_banner(text: &str) -> Option<Version> {
+    text.split(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .filter(|token| token.contains('.'))
+        .find_map(|token| token.trim_matches('.').parse().ok())
+}
+
+/// Run `<cmd> --version` with a timeout and parse its output.
+pub fn probe(cmd: &str) -> Result<Version, String> {
+    let output = banner(cmd, PROBE_TIMEOUT)?;
+    parse_banner(&output).ok_or_else(|| {
+        format!(
+            "could not find a version in `{cmd} --version` output: {:?}",
+            output.trim()
+        )
+    })
+}
+
+/// What `<cmd> --version` prints on stdout, abandoned after `timeout`.
+pub fn banner(cmd: &str, timeout: Duration) -> Result<String, String> {
+    let mut child = crate::resolve::command(cmd, &["--version".to_string()])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        // Never read, so a chatty stderr could fill the pipe and hang the probe
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("cannot run `{cmd} --version`: {e}"))?;
+    let mut stdout = child.stdout.take().expect("piped stdout");
+    let reader = thread::spawn(move || {
+        let mut buf = String::new();
+        let _ = stdout.read_to_string(&mut buf);
+        buf
+    });
+    let started = Instant::now();
+    loop {
+        match child.try_wait() {
+            Ok(Some(_)) => break,
+            Ok(None) if started.elapsed() < timeout => {
+                thread::sleep(Duration::from_millis(20));
+            }
+            _ => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!(
+                    "`{cmd} --version` did not finish within {}s",
+                    timeout.as_secs()
+                ));
+            }
+        }
+    }
+    Ok(reader.join().unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(major: u64, minor: u64, patch: u64) -> Option<Version> {
+        Some(Version {
+            major,
+            minor,
+            patch,
+        })
+    }
+
+    #[test]
+    fn banners() {
+        for (banner, want) in [
+            ("1.0.44 (Claude Code)\n", v(1, 0, 44)),
+            ("2.0.14 (Claude Code)", v(2, 0, 14)),
+            ("claude-code/1.2.3 linux-x64 node-v20.11.1", v(1, 2, 3)),
+            ("v2.0.1\r\n", v(2, 0, 1)),
+            ("Claude Code v1.0.3-beta.2 (build 7f3c)", v(1, 0, 3)),
+            ("@anthropic-ai/claude-code@1.0.17", v(1, 0, 17)),
+            ("version 3.1", v(3, 1, 0)),
+            ("\n\n  0.2.125 (Claude Code)  \n", v(0, 2, 125)),
+            ("Claude Code", None),
+            ("", None),
+            ("error: unknown option '--version'", None),
+        ] {
+            assert_eq!(parse_banner(banner), want, "{banner:?}");
+        }
+    }
+
+    #[test]
+    fn versions() {
+        assert_eq!("1.2".parse().ok(), v(1, 2, 0));
+        assert_eq!("v1".parse().ok(), v(1, 0, 0));
+        assert_eq!(" 1.2.3-rc.1+b5 ".parse().ok(), v(1, 2, 3));
+        for bad in ["", "1.2.3.4", "x.1", "1..2", "v"] {
+            assert!(bad.parse::<Version>().is_err(), "{bad:?}");
+        }
+        assert!(v(1, 10, 0) > v(1, 9, 99));
+        assert_eq!(v(0, 2, 9).map(|v| v.to_string()).as_deref(), Some("0.2.9"));
+    }
+}
@@ -0,0 +1,141 @@
+//! What the attempts that were thrown away cost: child wall and CPU time and forwarded
+//! output, so tuning a prompt can be weighed against retrying harder.
+//!
+//! An attempt counts as wasted once it is retried. CPU time is the growth of the reaped
+//! children's rusage across the attempt, which also covers the child's own descendants;
+//! platforms without it report wall time only.
+
+use std::time::Duration;
+
+use crate::duration::format_duration;
+use crate::size::format_size;
+
+#[derive(Clone, Debug, Default)]
+pub struct Waste {
+    pub attempts: u32,
+    pub wall: Duration,
+    /// `None` when the platform doesn't report child CPU time.
+    pub cpu: Option<Duration>,
+    pub output_bytes: u64,
+}
+
+impl Waste {
+    /// Count one discarded attempt.
+    pub fn add(&mut self, wall: Duration, cpu: Option<Duration>, output_bytes: u64) {
+        self.cpu = match (self.attempts, self.cpu, cpu) {
+            (0, _, cpu) => cpu,
+            (_, Some(total), Some(cpu)) => Some(total + cpu),
+            _ => None,
+        };
+        self.attempts += 1;
+        self.wall += wall;
+        self.output_bytes += output_bytes;
+    }
+
+    /// The epilogue line, e.g. `wasted 3m 12s of child time (41s CPU) and 18.0KB of
+    /// output across 2 failed attempts`.
+    pub fn describe(&self) -> String {
+        let cpu = match self.cpu {
+            Some(cpu) => format!("{} CPU", format_duration(cpu)),
+            None => "CPU time not tracked on this platform".to_string(),
+        };
+        format!(
+            "wasted {} of child time ({cpu}) and {} of output across {} failed attempt{}",
+            format_duration(self.wall),
+            format_size(self.output_bytes),
+            self.attempts,
+            if self.attempts == 1 { "" } else { "s" }
+        )
+    }
+
+    /// `key=value` lines for `--reason-file`.
+    pub fn render(&self) -> String {
+        let mut text = format!(
+            "wasted_attempts={}\nwasted_wall_ms={}\n",
+            self.attempts,
+            self.wall.as_millis()
+        );
+        if let Some(cpu) = self.cpu {
+            text.push_str(&format!("wasted_cpu_ms={}\n", cpu.as_millis()));
+        }
+        text.push_str(&format!("wasted_output_bytes={}\n", self.output_bytes));
+        text
+    }
+}
+
+/// User plus system CPU time of all reaped children so far.
+#[cfg(unix)]
+pub fn children_cpu() -> Option<Duration> {
+    // SAFETY: rusage is plain data filled by getrusage.
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    // SAFETY: valid out-pointer for our own children's usage.
+    if unsafe { libc::getrusage(libc::RUSAGE_CHILDREN, &mut usage) } != 0 {
+        return None;
+    }
+    let tv = |t: libc::timeval| {
+        Duration::from_secs(t.tv_sec as u64) + Duration::from_micros(t.tv_usec as u64)
+    };
+    Some(tv(usage.ru_utime) + tv(usage.ru_stime))
+}
+
+#[cfg(not(unix))]
+pub fn children_cpu() -> Option<Duration> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEC: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn attempts_accumulate() {
+        let mut waste = Waste::default();
+        waste.add(100 * SEC, Some(30 * SEC), 12_000);
+        waste.add(92 * SEC, Some(11 * SEC), 6_000);
+        assert_eq!(waste.attempts, 2);
+        assert_eq!(waste.wall, 192 * SEC);
+        assert_eq!(waste.cpu, Some(41 * SEC));
+        assert_eq!(waste.output_bytes, 18_000);
+        assert_eq!(
+            waste.describe(),
+            "wasted 3m 12s of child time (41s CPU) and 18.0KB of output across 2 failed attempts"
+        );
+        assert_eq!(
+            waste.render(),
+            "wasted_attempts=2\nwasted_wall_ms=192000\nwasted_cpu_ms=41000\nwasted_output_bytes=18000\n"
+        );
+    }
+
+    #[test]
+    fn one_untracked_attempt_loses_the_cpu_total() {
+        for cpus in [[None, Some(SEC)], [Some(SEC), None]] {
+            let mut waste = Waste::default();
+            for cpu in cpus {
+                waste.add(SEC, cpu, 0);
+            }
+            assert_eq!(waste.cpu, None, "{cpus:?}");
+        }
+        // and a later tracked attempt doesn't bring back a partial total
+        let mut waste = Waste::default();
+        waste.add(SEC, Some(SEC), 0);
+        waste.add(SEC, None, 0);
+        waste.add(SEC, Some(SEC), 0);
+        assert_eq!(waste.cpu, None);
+    }
+
+    #[test]
+    fn untracked_cpu_is_said_so() {
+        let mut waste = Waste::default();
+        waste.add(Duration::from_millis(1_500), None, 512);
+        assert_eq!(
+            waste.describe(),
+            "wasted 1.5s of child time (CPU time not tracked on this platform) and 512B of output across 1 failed attempt"
+        );
+        assert_eq!(
+            waste.render(),
+            "wasted_attempts=1\nwasted_wall_ms=1500\nwasted_output_bytes=512\n"
+        );
+    }
+}
@@ -0,0 +1,173 @@
+//! The library's `Supervisor` and the binary, each run against the binary's scripted
+//! stand-in for the CLI (`rusty-claude __fake-child SCENARIO`).
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::time::Duration;
+
+use rusty_claude::backoff::{Backoff, Strategy};
+use rusty_claude::{RetryPolicy, Supervisor};
+
+const EXE: &str = env!("CARGO_BIN_EXE_rusty-claude");
+
+/// A fresh directory for one test's state files.
+fn scratch(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("rusty-claude-test-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// The fake child acting out `args`, counting its runs in `dir`.
+fn fake_child(dir: &Path, args: &[&str]) -> Command {
+    let mut cmd = Command::new(EXE);
+    cmd.arg("__fake-child")
+        .args(args)
+        .arg("--state")
+        .arg(dir.join("runs"));
+    cmd
+}
+
+fn fast() -> RetryPolicy {
+    RetryPolicy {
+        max_retries: 3,
+        backoff: Backoff {
+            strategy: Strategy::Constant,
+            base_ms: 10,
+            cap_ms: 10,
+            multiplier: 2.0,
+        },
+        jitter_seed: Some(1),
+        ..RetryPolicy::default()
+    }
+}
+
+/// The binary supervising the fake child acting out `child_args`, with no ambient settings.
+fn wrapper(dir: &Path, flags: &[&str], child_args: &[&str]) -> Output {
+    let mut cmd = Command::new(EXE);
+    cmd.args(["--no-config", "--cmd", EXE, "--allow-self-wrap"])
+        .args(flags)
+        .args(["--", "__fake-child"])
+        .args(child_args)
+        .arg("--state")
+        .arg(dir.join("runs"))
+        .current_dir(dir)
+        .stdin(Stdio::null());
+    for (key, _) in env::vars_os() {
+        let key_str = key.to_string_lossy();
+        if key_str.starts_with("RUSTY_CLAUDE_") || key_str.starts_with("CLAUDE_SUPERVISOR_") {
+            cmd.env_remove(&key);
+        }
+    }
+    cmd.output().unwrap()
+}
+
+#[test]
+fn supervisor_retries_overloads_until_success() {
+    let dir = scratch("supervisor-retries");
+    let cmd = fake_child(&dir, &["fails-then-succeeds", "--failures", "2"]);
+    let report = Supervisor::new(fast()).run(cmd, None).unwrap();
+    assert!(report.success());
+    assert_eq!(report.attempts.len(), 3);
+    assert_eq!(report.last().stdout, b"ok\n");
+    for failed in &report.attempts[..2] {
+        let decision = failed.decision.as_ref().unwrap();
+        assert_eq!(decision.matched.as_deref(), Some("(?i)overloaded"));
+        assert_eq!(failed.delay, Some(Duration::from_millis(10)));
+    }
+    assert!(report.last().decision.is_none());
+}
+
+#[test]
+fn supervisor_stops_when_retries_run_out_or_nothing_matches() {
+    let dir = scratch("supervisor-stops");
+    let cmd = fake_child(&dir, &["fails-then-succeeds", "--failures", "9"]);
+    let report = Supervisor::new(fast()).run(cmd, None).unwrap();
+    assert_eq!(
+        (report.attempts.len(), report.status().code()),
+        (4, Some(1))
+    );
+    assert_eq!(report.last().delay, None);
+
+    let dir = scratch("supervisor-not-retryable");
+    let cmd = fake_child(&dir, &["always-fatal", "--exit-code", "3"]);
+    let report = Supervisor::new(fast()).run(cmd, None).unwrap();
+    assert_eq!(
+        (report.attempts.len(), report.status().code()),
+        (1, Some(3))
+    );
+    assert!(!report.last().decision.as_ref().unwrap().retry);
+}
+
+#[test]
+fn supervisor_replays_input_to_every_attempt() {
+    let dir = scratch("supervisor-input");
+    let cmd = fake_child(&dir, &["echo-stdin", "--failures", "1"]);
+    let mut retried = 0;
+    let report = Supervisor::new(fast())
+        .run_with(cmd, Some(b"the prompt\n"), |_| retried += 1)
+        .unwrap();
+    assert!(report.success());
+    assert_eq!(retried, 1);
+    assert_eq!(report.last().stdout, b"the prompt\n");
+}
+
+#[test]
+fn binary_retries_and_passes_the_output_on() {
+    let dir = scratch("binary-retries");
+    let out = wrapper(
+        &dir,
+        &["--base-delay-ms", "10", "--max-delay-ms", "20"],
+        &["fails-then-succeeds", "--failures", "2"],
+    );
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert_eq!(out.status.code(), Some(0), "{stderr}");
+    assert_eq!(out.stdout, b"ok\n");
+    assert!(
+        stderr.contains("attempt=1 failed (code=Some(1)); retrying in"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("attempt=2 failed"), "{stderr}");
+    assert_eq!(fs::read_to_string(dir.join("runs")).unwrap().trim(), "3");
+}
+
+#[test]
+fn binary_reports_exhaustion() {
+    let dir = scratch("binary-exhausted");
+    let out = wrapper(
+        &dir,
+        &[
+            "--max-retries",
+            "2",
+            "--base-delay-ms",
+            "10",
+            "--reason-file",
+            "reason",
+        ],
+        &["fails-then-succeeds", "--failures", "9"],
+    );
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert_eq!(out.status.code(), Some(1), "{stderr}");
+    assert!(
+        stderr.contains("giving up after 3 attempt(s): retries exhausted; exiting with code 1"),
+        "{stderr}"
+    );
+    let reason = fs::read_to_string(dir.join("reason")).unwrap();
+    for line in ["reason=exhausted", "origin=child", "attempts=3"] {
+        assert!(reason.lines().any(|l| l == line), "{line} in {reason}");
+    }
+}
+
+#[test]
+fn binary_does_not_retry_a_fatal_failure() {
+    let dir = scratch("binary-fatal");
+    let out = wrapper(
+        &dir,
+        &["--base-delay-ms", "10"],
+        &["always-fatal", "--exit-code", "3"],
+    );
+    assert_eq!(out.status.code(), Some(3));
+    assert_eq!(fs::read_to_string(dir.join("runs")).unwrap().trim(), "1");
+}
@@ -0,0 +1,44 @@
+//! `rusty-claude self-test`, one scenario per run, so a failure names its scenario.
+
+use std::process::{Command, Stdio};
+
+const EXE: &str = env!("CARGO_BIN_EXE_rusty-claude");
+
+#[test]
+fn every_self_test_scenario_passes() {
+    let list = Command::new(EXE)
+        .args(["self-test", "--list"])
+        .output()
+        .expect("list the scenarios");
+    assert!(list.status.success(), "self-test --list: {:?}", list.status);
+    let names: Vec<String> = String::from_utf8_lossy(&list.stdout)
+        .lines()
+        .map(str::to_string)
+        .collect();
+    assert!(!names.is_empty(), "no scenarios listed");
+    let failures: Vec<String> = names
+        .iter()
+        .filter_map(|name| {
+            let run = Command::new(EXE)
+                .args(["self-test", name])
+                .stdin(Stdio::null())
+                .output()
+                .expect("run a scenario");
+            let report = String::from_utf8_lossy(&run.stdout);
+            (run.status.code() != Some(0)).then(|| {
+                let why = report
+                    .lines()
+                    .find(|l| l.trim_start().starts_with("FAIL"))
+                    .map_or_else(|| format!("exit {:?}", run.status.code()), str::to_string);
+                format!("{name}: {}", why.trim())
+            })
+        })
+        .collect();
+    assert!(
+        failures.is_empty(),
+        "{} of {} scenarios failed:\n{}",
+        failures.len(),
+        names.len(),
+        failures.join("\n")
+    );
+}